@@ -11,6 +11,9 @@ cd "$(dirname "$0")"
 echo "Building ScottOS..."
 cargo build || exit 1
 
+echo "Writing the symbol map..."
+python3 tools/ksyms.py target/x86_64-scottos/debug/scottos || exit 1
+
 echo "Creating bootable image..."
 cargo bootimage || exit 1
 
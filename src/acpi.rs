@@ -0,0 +1,471 @@
+use crate::{klog, memory};
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use x86_64::PhysAddr;
+
+/// Signature the RSDP starts with, on a 16-byte boundary
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Physical address of the BIOS data area word holding the EBDA segment
+const EBDA_POINTER: u64 = 0x40e;
+/// The BIOS read-only area searched for the RSDP after the EBDA
+const BIOS_AREA: core::ops::Range<u64> = 0xe0000..0x100000;
+
+/// Length of the header every system description table starts with
+const HEADER_LEN: usize = 36;
+
+/// MADT entry types
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_SOURCE_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_ADDRESS: u8 = 5;
+/// Generic address structure spaces for memory-mapped and port registers
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+const ADDRESS_SPACE_IO: u8 = 1;
+/// Local APIC flag marking a processor as usable
+const LOCAL_APIC_ENABLED: u32 = 1;
+
+/// FADT field offsets
+const FADT_DSDT: usize = 40;
+const FADT_SCI_INTERRUPT: usize = 46;
+const FADT_SMI_COMMAND: usize = 48;
+const FADT_ACPI_ENABLE: usize = 52;
+const FADT_ACPI_DISABLE: usize = 53;
+const FADT_PM1A_CONTROL: usize = 64;
+const FADT_PM1B_CONTROL: usize = 68;
+const FADT_PM_TIMER: usize = 76;
+const FADT_CENTURY: usize = 108;
+const FADT_FLAGS: usize = 112;
+const FADT_RESET_REGISTER: usize = 116;
+const FADT_RESET_VALUE: usize = 128;
+const FADT_X_DSDT: usize = 140;
+const FADT_X_PM1A_CONTROL: usize = 172;
+const FADT_X_PM1B_CONTROL: usize = 184;
+const FADT_X_PM_TIMER: usize = 208;
+/// FADT flags: the PM timer has 32 bits rather than 24, and the reset
+/// register works
+const FADT_TIMER_32BIT: u32 = 1 << 8;
+const FADT_RESET_SUPPORTED: u32 = 1 << 10;
+
+/// AML opcodes and prefixes met on the way to a sleep state's values
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+const AML_WORD_PREFIX: u8 = 0x0b;
+const AML_DWORD_PREFIX: u8 = 0x0c;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ROOT_PREFIX: u8 = 0x5c;
+
+/// The FADT, found once since the RTC and power code both want it
+static FADT: OnceCell<Option<Fadt>> = OnceCell::uninit();
+
+/// The root table: the RSDT's or XSDT's address, and whether it is the XSDT
+/// with 64-bit entries
+static ROOT: OnceCell<Option<(u64, bool)>> = OnceCell::uninit();
+
+/// Whether bytes sum to zero, as every ACPI structure's do
+fn checksum_ok(bytes: &[u8]) -> bool {
+	bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+	Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+	Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Option<u64> {
+	Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// `len` bytes of physical memory, mapping them if the bootloader did not
+fn physical(addr: u64, len: usize) -> Option<&'static [u8]> {
+	let virt = memory::map_mmio(PhysAddr::new(addr), len as u64)?;
+	Some(unsafe { core::slice::from_raw_parts(virt.as_ptr(), len) })
+}
+
+/// Search a physical range for a valid RSDP
+fn scan_rsdp(range: core::ops::Range<u64>) -> Option<(u64, bool)> {
+	let area = physical(range.start, (range.end - range.start) as usize)?;
+	(0..area.len()).step_by(16).find_map(|at| {
+		let rsdp = &area[at..];
+		if !rsdp.starts_with(RSDP_SIGNATURE) || !checksum_ok(rsdp.get(..20)?) {
+			return None;
+		}
+		// Revision 2 and later add the XSDT, covered by an extended checksum
+		if rsdp[15] >= 2 {
+			let len = read_u32(rsdp, 20)? as usize;
+			if let Some(xsdt) = rsdp.get(..len).filter(|bytes| checksum_ok(bytes)).and_then(|_| read_u64(rsdp, 24)) {
+				return Some((xsdt, true));
+			}
+		}
+		Some((read_u32(rsdp, 16)? as u64, false))
+	})
+}
+
+/// Find the root table through the RSDP in the EBDA or the BIOS area; the
+/// bootloader does not pass one on
+fn root() -> Option<(u64, bool)> {
+	*ROOT.get_or_init(|| {
+		let ebda = (read_u16(physical(EBDA_POINTER, 2)?, 0)? as u64) << 4;
+		let in_ebda = (ebda != 0).then(|| scan_rsdp(ebda..ebda + 1024)).flatten();
+		in_ebda.or_else(|| scan_rsdp(BIOS_AREA))
+	})
+}
+
+/// A whole table at a physical address, if its checksum is good
+fn table_at(addr: u64) -> Option<&'static [u8]> {
+	let len = read_u32(physical(addr, HEADER_LEN)?, 4)? as usize;
+	let table = physical(addr, len.max(HEADER_LEN))?;
+	checksum_ok(table).then_some(table)
+}
+
+/// Physical addresses of the tables the root table lists
+fn table_addresses() -> impl Iterator<Item = u64> {
+	let (entries, width): (&[u8], usize) = match root().and_then(|(addr, extended)| Some((table_at(addr)?, extended))) {
+		Some((root, extended)) => (&root[HEADER_LEN..], if extended { 8 } else { 4 }),
+		None => (&[], 4),
+	};
+	entries.chunks_exact(width).filter_map(move |entry| match width {
+		8 => read_u64(entry, 0),
+		_ => read_u32(entry, 0).map(u64::from),
+	})
+}
+
+/// The firmware table with a signature such as `APIC`, header included
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+	table_addresses().filter_map(table_at).find(|table| table.starts_with(signature))
+}
+
+/// What a table's header says about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableInfo {
+	pub signature: [u8; 4],
+	pub address: u64,
+	pub length: u32,
+	pub revision: u8,
+	pub oem_id: [u8; 6],
+}
+
+impl TableInfo {
+	/// The signature as text, such as `FACP`
+	pub fn name(&self) -> &str {
+		core::str::from_utf8(&self.signature).unwrap_or("????")
+	}
+
+	/// The OEM ID as text, without its padding
+	pub fn oem(&self) -> &str {
+		core::str::from_utf8(&self.oem_id).unwrap_or("").trim_end_matches([' ', '\0'])
+	}
+}
+
+/// Every table the root table lists with a good checksum, in its order
+pub fn tables() -> Vec<TableInfo> {
+	table_addresses()
+		.filter_map(|address| {
+			let table = table_at(address)?;
+			Some(TableInfo {
+				signature: table[..4].try_into().ok()?,
+				address,
+				length: table.len() as u32,
+				revision: table[8],
+				oem_id: table[10..16].try_into().ok()?,
+			})
+		})
+		.collect()
+}
+
+/// Find the firmware's tables and log what they are
+pub fn init() {
+	let Some((root, extended)) = root() else {
+		klog!(Info, "ACPI: no RSDP found");
+		return;
+	};
+	klog!(Info, "ACPI: {} at {:#x}", if extended { "XSDT" } else { "RSDT" }, root);
+	for table in tables() {
+		klog!(Info, "ACPI: {} at {:#x}, {} bytes (v{} {})", table.name(), table.address, table.length, table.revision, table.oem());
+	}
+	// Found now so the reboot path, which may run from a panic, need not
+	// map anything
+	if let Some(fadt) = Fadt::find() {
+		klog!(Info, "ACPI: SCI on IRQ {}, PM1a control at {:#x?}", fadt.sci_interrupt, fadt.pm1a_control);
+	}
+}
+
+/// An I/O APIC and the first global system interrupt it handles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+	pub id: u8,
+	pub address: u64,
+	pub gsi_base: u32,
+}
+
+/// An ISA IRQ the firmware wired to a different interrupt, or with a
+/// different polarity or trigger mode, than the 8259 would
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceOverride {
+	pub irq: u8,
+	pub gsi: u32,
+	/// MPS INTI flags: polarity in bits 0-1, trigger mode in bits 2-3
+	pub flags: u16,
+}
+
+/// The interrupt controllers described by the MADT
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Madt {
+	pub local_apic: u64,
+	/// Local APIC IDs of the usable processors
+	pub processors: Vec<u8>,
+	pub io_apics: Vec<IoApic>,
+	pub overrides: Vec<SourceOverride>,
+}
+
+impl Madt {
+	/// The firmware's MADT, if it has one
+	pub fn find() -> Option<Madt> {
+		Madt::parse(find_table(b"APIC")?)
+	}
+
+	/// Decode a MADT, header included
+	fn parse(table: &[u8]) -> Option<Madt> {
+		let mut madt = Madt { local_apic: read_u32(table, HEADER_LEN)? as u64, ..Madt::default() };
+		let mut at = HEADER_LEN + 8;
+		while let (Some(&kind), Some(&len)) = (table.get(at), table.get(at + 1)) {
+			let entry = table.get(at..at + len as usize)?;
+			match kind {
+				MADT_LOCAL_APIC if read_u32(entry, 4)? & LOCAL_APIC_ENABLED != 0 => madt.processors.push(entry[3]),
+				MADT_IO_APIC => madt.io_apics.push(IoApic {
+					id: entry[2],
+					address: read_u32(entry, 4)? as u64,
+					gsi_base: read_u32(entry, 8)?,
+				}),
+				MADT_SOURCE_OVERRIDE => madt.overrides.push(SourceOverride {
+					irq: entry[3],
+					gsi: read_u32(entry, 4)?,
+					flags: read_u16(entry, 8)?,
+				}),
+				MADT_LOCAL_APIC_ADDRESS => madt.local_apic = read_u64(entry, 4)?,
+				_ => {}
+			}
+			at += (len as usize).max(2);
+		}
+		Some(madt)
+	}
+
+	/// The override for an ISA IRQ, or the identity mapping the 8259 uses
+	pub fn isa_irq(&self, irq: u8) -> SourceOverride {
+		self.overrides.iter().copied().find(|o| o.irq == irq).unwrap_or(SourceOverride { irq, gsi: irq as u32, flags: 0 })
+	}
+}
+
+/// The HPET table: where the event timer block's registers are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HpetTable {
+	pub address: u64,
+	/// Minimum periodic tick the firmware promises, in counter ticks
+	pub minimum_tick: u16,
+}
+
+impl HpetTable {
+	/// The firmware's HPET table, if it has one
+	pub fn find() -> Option<HpetTable> {
+		let table = find_table(b"HPET")?;
+		// The registers are in a generic address structure; only memory works
+		if *table.get(HEADER_LEN + 4)? != ADDRESS_SPACE_MEMORY {
+			return None;
+		}
+		Some(HpetTable { address: read_u64(table, HEADER_LEN + 8)?, minimum_tick: read_u16(table, HEADER_LEN + 17)? })
+	}
+}
+
+/// A register the firmware describes with a generic address structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+	/// 0 for memory, 1 for I/O ports
+	pub space: u8,
+	pub bit_width: u8,
+	pub bit_offset: u8,
+	pub address: u64,
+}
+
+impl GenericAddress {
+	fn parse(bytes: &[u8], at: usize) -> Option<GenericAddress> {
+		let gas = bytes.get(at..at + 12)?;
+		Some(GenericAddress { space: gas[0], bit_width: gas[1], bit_offset: gas[2], address: read_u64(gas, 4)? })
+	}
+
+	/// The I/O port, if the register is one
+	pub fn port(&self) -> Option<u16> {
+		(self.space == ADDRESS_SPACE_IO && self.address != 0).then_some(self.address as u16)
+	}
+}
+
+/// The FADT: the fixed power management hardware and where the DSDT is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+	pub dsdt: u64,
+	/// The ISA IRQ the SCI arrives on
+	pub sci_interrupt: u16,
+	/// Port that takes `acpi_enable` and `acpi_disable`, or 0 on hardware
+	/// that is always in ACPI mode
+	pub smi_command: u16,
+	pub acpi_enable: u8,
+	pub acpi_disable: u8,
+	/// PM1 control register ports; sleep states are entered through these
+	pub pm1a_control: Option<u16>,
+	pub pm1b_control: Option<u16>,
+	/// The ACPI PM timer port, counting at 3.579545 MHz
+	pub pm_timer: Option<u16>,
+	/// Whether the PM timer has 32 bits rather than 24
+	pub pm_timer_32bit: bool,
+	/// CMOS index of the RTC's century register, or 0 if there is none
+	pub century: u8,
+	/// Register that resets the machine when `reset_value` is written to it
+	pub reset_register: Option<GenericAddress>,
+	pub reset_value: u8,
+}
+
+impl Fadt {
+	/// The firmware's FADT, if it has one
+	pub fn find() -> Option<&'static Fadt> {
+		FADT.get_or_init(|| Fadt::parse(find_table(b"FACP")?)).as_ref()
+	}
+
+	/// Decode a FADT, header included; revision 1 tables end before the
+	/// 64-bit fields and the reset register
+	fn parse(table: &[u8]) -> Option<Fadt> {
+		let flags = read_u32(table, FADT_FLAGS).unwrap_or(0);
+		// The legacy port fields, or the extended ones if those are empty
+		let port = |legacy: usize, extended: usize| match read_u32(table, legacy) {
+			Some(port @ 1..) => Some(port as u16),
+			_ => GenericAddress::parse(table, extended)?.port(),
+		};
+		let dsdt = match read_u64(table, FADT_X_DSDT) {
+			Some(dsdt @ 1..) => dsdt,
+			_ => read_u32(table, FADT_DSDT)? as u64,
+		};
+		Some(Fadt {
+			dsdt,
+			sci_interrupt: read_u16(table, FADT_SCI_INTERRUPT)?,
+			smi_command: read_u32(table, FADT_SMI_COMMAND)? as u16,
+			acpi_enable: *table.get(FADT_ACPI_ENABLE)?,
+			acpi_disable: *table.get(FADT_ACPI_DISABLE)?,
+			pm1a_control: port(FADT_PM1A_CONTROL, FADT_X_PM1A_CONTROL),
+			pm1b_control: port(FADT_PM1B_CONTROL, FADT_X_PM1B_CONTROL),
+			pm_timer: port(FADT_PM_TIMER, FADT_X_PM_TIMER),
+			pm_timer_32bit: flags & FADT_TIMER_32BIT != 0,
+			century: table.get(FADT_CENTURY).copied().unwrap_or(0),
+			reset_register: GenericAddress::parse(table, FADT_RESET_REGISTER)
+				.filter(|reg| flags & FADT_RESET_SUPPORTED != 0 && reg.address != 0),
+			reset_value: table.get(FADT_RESET_VALUE).copied().unwrap_or(0),
+		})
+	}
+}
+
+/// An integer constant at the start of some AML, and what follows it
+fn aml_integer(aml: &[u8]) -> Option<(u64, &[u8])> {
+	match *aml.first()? {
+		AML_ZERO_OP => Some((0, &aml[1..])),
+		AML_ONE_OP => Some((1, &aml[1..])),
+		AML_BYTE_PREFIX => Some((*aml.get(1)? as u64, aml.get(2..)?)),
+		AML_WORD_PREFIX => Some((read_u16(aml, 1)? as u64, aml.get(3..)?)),
+		AML_DWORD_PREFIX => Some((read_u32(aml, 1)? as u64, aml.get(5..)?)),
+		_ => None,
+	}
+}
+
+/// The PM1a and PM1b SLP_TYP values in a `\_Sx` package defined in `aml`
+///
+/// This looks for `Name (_Sx, Package () { a, b, ... })` rather than
+/// interpreting the AML, which is how the tables every emulator and most
+/// firmware ship spell it.
+fn parse_sleep_type(aml: &[u8], state: u8) -> Option<(u16, u16)> {
+	let name = [b'_', b'S', b'0' + state, b'_'];
+	aml.windows(4).enumerate().filter(|&(_, window)| window == name).find_map(|(at, _)| {
+		let defined = matches!(&aml[..at], [.., AML_NAME_OP] | [.., AML_NAME_OP, AML_ROOT_PREFIX]);
+		let package = aml.get(at + 4..).filter(|rest| defined && rest.first() == Some(&AML_PACKAGE_OP))?;
+		// The top two bits of the package length's lead byte count the
+		// bytes after it; the element count follows
+		let length_bytes = (*package.get(1)? >> 6) as usize;
+		let (pm1a, rest) = aml_integer(package.get(3 + length_bytes..)?)?;
+		let (pm1b, _) = aml_integer(rest)?;
+		Some((pm1a as u16, pm1b as u16))
+	})
+}
+
+/// The PM1a and PM1b SLP_TYP values that enter sleep state `state`, 5
+/// being soft off, from the DSDT
+pub fn sleep_type(state: u8) -> Option<(u16, u16)> {
+	let dsdt = table_at(Fadt::find()?.dsdt)?;
+	parse_sleep_type(&dsdt[HEADER_LEN..], state)
+}
+
+/// Test decoding a MADT like QEMU's
+#[test_case]
+fn test_parse_madt() {
+	let mut table = alloc::vec![0u8; HEADER_LEN];
+	table[..4].copy_from_slice(b"APIC");
+	table.extend_from_slice(&0xfee0_0000u32.to_le_bytes());
+	table.extend_from_slice(&1u32.to_le_bytes());
+	// Processor 0, enabled; processor 1, disabled
+	table.extend_from_slice(&[MADT_LOCAL_APIC, 8, 0, 0, 1, 0, 0, 0]);
+	table.extend_from_slice(&[MADT_LOCAL_APIC, 8, 1, 1, 0, 0, 0, 0]);
+	table.extend_from_slice(&[MADT_IO_APIC, 12, 0, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0]);
+	// IRQ 0 on GSI 2, active high and edge-triggered
+	table.extend_from_slice(&[MADT_SOURCE_OVERRIDE, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+	let len = table.len() as u32;
+	table[4..8].copy_from_slice(&len.to_le_bytes());
+
+	let madt = Madt::parse(&table).unwrap();
+	assert_eq!(madt.local_apic, 0xfee0_0000);
+	assert_eq!(madt.processors, [0]);
+	assert_eq!(madt.io_apics, [IoApic { id: 0, address: 0xfec0_0000, gsi_base: 0 }]);
+	assert_eq!(madt.isa_irq(0).gsi, 2);
+	assert_eq!(madt.isa_irq(1), SourceOverride { irq: 1, gsi: 1, flags: 0 });
+	assert!(Madt::parse(&table[..HEADER_LEN]).is_none());
+}
+
+/// Test decoding a revision 1 FADT like QEMU's i440fx one
+#[test_case]
+fn test_parse_fadt() {
+	let mut table = alloc::vec![0u8; FADT_RESET_REGISTER];
+	table[..4].copy_from_slice(b"FACP");
+	table[FADT_DSDT..FADT_DSDT + 4].copy_from_slice(&0x7fe0_0040u32.to_le_bytes());
+	table[FADT_SCI_INTERRUPT] = 9;
+	table[FADT_SMI_COMMAND] = 0xb2;
+	table[FADT_ACPI_ENABLE] = 0xf1;
+	table[FADT_PM1A_CONTROL..FADT_PM1A_CONTROL + 2].copy_from_slice(&0x604u16.to_le_bytes());
+	table[FADT_PM_TIMER..FADT_PM_TIMER + 2].copy_from_slice(&0x608u16.to_le_bytes());
+	table[FADT_CENTURY] = 0x32;
+	table[FADT_FLAGS] = 0xa5;
+
+	let fadt = Fadt::parse(&table).unwrap();
+	assert_eq!(fadt.dsdt, 0x7fe0_0040);
+	assert_eq!((fadt.sci_interrupt, fadt.smi_command, fadt.acpi_enable), (9, 0xb2, 0xf1));
+	assert_eq!((fadt.pm1a_control, fadt.pm1b_control), (Some(0x604), None));
+	assert_eq!(fadt.pm_timer, Some(0x608));
+	assert!(!fadt.pm_timer_32bit);
+	assert_eq!(fadt.century, 0x32);
+	assert_eq!(fadt.reset_register, None);
+	assert!(Fadt::parse(&table[..FADT_SMI_COMMAND]).is_none());
+}
+
+/// Test finding sleep state values in AML
+#[test_case]
+fn test_parse_sleep_type() {
+	// A reference to \_S5 ahead of its definition, then QEMU's q35 package
+	// and a Bochs-style one using a byte prefix
+	let aml = [
+		&[0x70, b'_', b'S', b'5', b'_', 0x60][..],
+		&[AML_NAME_OP, b'_', b'S', b'5', b'_', AML_PACKAGE_OP, 0x06, 0x04, 0x00, 0x00, 0x00, 0x00],
+		&[AML_NAME_OP, AML_ROOT_PREFIX, b'_', b'S', b'3', b'_', AML_PACKAGE_OP, 0x08, 0x04],
+		&[AML_BYTE_PREFIX, 0x05, AML_BYTE_PREFIX, 0x07, 0x00, 0x00],
+	]
+	.concat();
+	assert_eq!(parse_sleep_type(&aml, 5), Some((0, 0)));
+	assert_eq!(parse_sleep_type(&aml, 3), Some((5, 7)));
+	assert_eq!(parse_sleep_type(&aml, 4), None);
+	assert_eq!(aml_integer(&[AML_ONE_OP, 0xff]), Some((1, &[0xff][..])));
+}
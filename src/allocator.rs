@@ -1,11 +1,19 @@
+use core::alloc::{GlobalAlloc, Layout};
+#[cfg(debug_assertions)]
+use core::fmt;
 use linked_list_allocator::LockedHeap;
 
+/// The heap, checking in debug builds that blocks are freed whole and once
+struct Allocator {
+	heap: LockedHeap,
+}
+
 /// Global heap allocator instance
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: Allocator = Allocator { heap: LockedHeap::empty() };
 
-/// Heap size in bytes (100 KB - enough for shell and basic operations)
-pub const HEAP_SIZE: usize = 100 * 1024;
+/// Heap size in bytes (1 MB - shells, files, and the consoles' screens and scrollback)
+pub const HEAP_SIZE: usize = 1024 * 1024;
 
 /// Static heap buffer in the BSS section
 static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
@@ -14,14 +22,215 @@ static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
 pub fn init_heap() -> Result<(), &'static str> {
 	unsafe {
 		let heap_start = HEAP.as_ptr() as usize;
-		ALLOCATOR.lock().init(heap_start, HEAP_SIZE);
+		ALLOCATOR.heap.lock().init(heap_start, HEAP_SIZE);
 	}
 
 	Ok(())
 }
 
+/// Whether an address is in the heap
+pub fn contains(addr: usize) -> bool {
+	let start = &raw const HEAP as usize;
+	(start..start + HEAP_SIZE).contains(&addr)
+}
+
+/// Free and total heap bytes
+pub fn heap_usage() -> (usize, usize) {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let heap = ALLOCATOR.heap.lock();
+		(heap.free(), heap.size())
+	})
+}
+
+unsafe impl GlobalAlloc for Allocator {
+	#[cfg(not(debug_assertions))]
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		self.heap.alloc(layout)
+	}
+
+	#[cfg(not(debug_assertions))]
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		self.heap.dealloc(ptr, layout)
+	}
+
+	/// Allocate a block with red zones either side of what was asked for
+	#[cfg(debug_assertions)]
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		let Some(block) = Block::layout(layout) else {
+			return core::ptr::null_mut();
+		};
+		let start = self.heap.alloc(block);
+		if start.is_null() {
+			return start;
+		}
+		let ptr = start.add(Block::front(layout));
+		Block::guard(ptr, layout);
+		ptr
+	}
+
+	/// Check the red zones and that the block is still allocated, then
+	/// poison it so stale reads show up as `0x6b6b...`
+	#[cfg(debug_assertions)]
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		if let Err(corruption) = Block::check(ptr, layout) {
+			panic!("heap corruption: {}", corruption);
+		}
+		Block::poison(ptr, layout);
+		self.heap.dealloc(ptr.sub(Block::front(layout)), Block::layout(layout).unwrap());
+	}
+}
+
+/// How a debug build lays out a block around an allocation: a red zone, the
+/// size asked for, a state word, the allocation, and another red zone
+///
+/// The state word sits right before the allocation, clear of the free-list
+/// node written over a block's first 16 bytes once it is freed, so a second
+/// free still finds it marked freed.
+#[cfg(debug_assertions)]
+struct Block;
+
+/// Bytes of red zone at least either side of an allocation
+#[cfg(debug_assertions)]
+const RED_ZONE: usize = 8;
+/// The size and state words
+#[cfg(debug_assertions)]
+const HEADER: usize = 16;
+/// What the red zones are filled with
+#[cfg(debug_assertions)]
+const CANARY: u8 = 0xfd;
+/// What freed memory is filled with
+#[cfg(debug_assertions)]
+const POISON: u8 = 0x6b;
+/// The state word of an allocated block, and of a freed one
+#[cfg(debug_assertions)]
+const LIVE: u64 = 0x4c49_5645_4c49_5645;
+#[cfg(debug_assertions)]
+const FREED: u64 = 0x4652_4545_4652_4545;
+
+/// Something wrong with a block being freed
+#[cfg(debug_assertions)]
+#[derive(Debug, PartialEq, Eq)]
+enum Corruption {
+	DoubleFree(usize),
+	/// Not a block from this heap, or its header was overwritten
+	NotAllocated(usize),
+	/// Freed with a different size than it was allocated with
+	WrongSize { addr: usize, allocated: usize, freed: usize },
+	/// A write before the start of the allocation
+	Underrun(usize),
+	/// A write past the end of the allocation
+	Overrun { addr: usize, size: usize },
+}
+
+#[cfg(debug_assertions)]
+impl fmt::Display for Corruption {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Corruption::DoubleFree(addr) => write!(f, "{:#x} freed twice", addr),
+			Corruption::NotAllocated(addr) => write!(f, "{:#x} freed but not allocated, or its header was overwritten", addr),
+			Corruption::WrongSize { addr, allocated, freed } => {
+				write!(f, "{:#x} allocated with {} bytes but freed with {}", addr, allocated, freed)
+			}
+			Corruption::Underrun(addr) => write!(f, "write before the start of {:#x}", addr),
+			Corruption::Overrun { addr, size } => write!(f, "write past the {} bytes at {:#x}", size, addr),
+		}
+	}
+}
+
+#[cfg(debug_assertions)]
+impl Block {
+	/// Bytes before an allocation, keeping it aligned
+	fn front(layout: Layout) -> usize {
+		(RED_ZONE + HEADER).next_multiple_of(layout.align())
+	}
+
+	/// The whole block for an allocation
+	fn layout(layout: Layout) -> Option<Layout> {
+		let size = Block::front(layout).checked_add(layout.size())?.checked_add(RED_ZONE)?;
+		Layout::from_size_align(size, layout.align()).ok()
+	}
+
+	/// Fill the red zones around a new allocation and mark it allocated
+	unsafe fn guard(ptr: *mut u8, layout: Layout) {
+		let front = Block::front(layout);
+		ptr.sub(front).write_bytes(CANARY, front - HEADER);
+		ptr.sub(HEADER).cast::<u64>().write_unaligned(layout.size() as u64);
+		ptr.sub(8).cast::<u64>().write_unaligned(LIVE);
+		ptr.add(layout.size()).write_bytes(CANARY, RED_ZONE);
+	}
+
+	/// Check an allocation about to be freed
+	unsafe fn check(ptr: *mut u8, layout: Layout) -> Result<(), Corruption> {
+		let addr = ptr as usize;
+		match ptr.sub(8).cast::<u64>().read_unaligned() {
+			LIVE => {}
+			FREED => return Err(Corruption::DoubleFree(addr)),
+			_ => return Err(Corruption::NotAllocated(addr)),
+		}
+		let allocated = ptr.sub(HEADER).cast::<u64>().read_unaligned() as usize;
+		if allocated != layout.size() {
+			return Err(Corruption::WrongSize { addr, allocated, freed: layout.size() });
+		}
+		let front = Block::front(layout);
+		let before = core::slice::from_raw_parts(ptr.sub(front), front - HEADER);
+		if before.iter().any(|&byte| byte != CANARY) {
+			return Err(Corruption::Underrun(addr));
+		}
+		let after = core::slice::from_raw_parts(ptr.add(layout.size()), RED_ZONE);
+		if after.iter().any(|&byte| byte != CANARY) {
+			return Err(Corruption::Overrun { addr, size: layout.size() });
+		}
+		Ok(())
+	}
+
+	/// Mark an allocation freed and fill it with poison
+	unsafe fn poison(ptr: *mut u8, layout: Layout) {
+		ptr.sub(8).cast::<u64>().write_unaligned(FREED);
+		ptr.write_bytes(POISON, layout.size());
+	}
+}
+
 /// Allocation error handler
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
 	panic!("allocation error: {:?}", layout)
-} 
\ No newline at end of file
+} 
+
+/// Test that red zones catch writes either side of a block, and that a
+/// freed block is poisoned and caught being freed again
+#[cfg(debug_assertions)]
+#[test_case]
+fn test_red_zones() {
+	let layout = Layout::from_size_align(24, 8).unwrap();
+	x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+		let ptr = ALLOCATOR.alloc(layout);
+		assert!(!ptr.is_null());
+		assert_eq!(Block::check(ptr, layout), Ok(()));
+		let addr = ptr as usize;
+		ptr.add(24).write(0);
+		assert_eq!(Block::check(ptr, layout), Err(Corruption::Overrun { addr, size: 24 }));
+		ptr.add(24).write(CANARY);
+		ptr.sub(HEADER + 1).write(0);
+		assert_eq!(Block::check(ptr, layout), Err(Corruption::Underrun(addr)));
+		ptr.sub(HEADER + 1).write(CANARY);
+		let smaller = Layout::from_size_align(16, 8).unwrap();
+		assert_eq!(Block::check(ptr, smaller), Err(Corruption::WrongSize { addr, allocated: 24, freed: 16 }));
+		ALLOCATOR.dealloc(ptr, layout);
+		assert_eq!(ptr.read(), POISON);
+		assert_eq!(Block::check(ptr, layout), Err(Corruption::DoubleFree(addr)));
+	});
+}
+
+/// Test that freeing a block twice panics rather than corrupting the heap
+#[cfg(all(test, debug_assertions))]
+fn test_double_free() {
+	let layout = Layout::from_size_align(16, 8).unwrap();
+	unsafe {
+		let ptr = ALLOCATOR.alloc(layout);
+		ALLOCATOR.dealloc(ptr, layout);
+		ALLOCATOR.dealloc(ptr, layout);
+	}
+}
+
+#[cfg(debug_assertions)]
+crate::should_panic!(TEST_DOUBLE_FREE, test_double_free);
@@ -0,0 +1,344 @@
+use crate::acpi::{Madt, SourceOverride};
+use crate::interrupts::{InterruptIndex, PIC_1_OFFSET, PICS};
+use crate::cpu::{self, Feature};
+use crate::{cmdline, klog, memory, time};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::Mutex;
+use x86_64::registers::model_specific::Msr;
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Model-specific register holding the local APIC's base and enable bit
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// Local APIC registers, as offsets from its base
+const LAPIC_ID: usize = 0x20;
+const LAPIC_TASK_PRIORITY: usize = 0x80;
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SPURIOUS: usize = 0xf0;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+const LAPIC_TIMER: usize = 0x320;
+const LAPIC_TIMER_INITIAL: usize = 0x380;
+const LAPIC_TIMER_CURRENT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+/// Spurious interrupt vector register bit that turns the local APIC on
+const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+
+/// Interrupt command register bits: the delivery modes used to start
+/// processors, the level INIT needs, and the busy bit
+pub(crate) const ICR_INIT: u32 = 0b101 << 8;
+pub(crate) const ICR_STARTUP: u32 = 0b110 << 8;
+pub(crate) const ICR_ASSERT: u32 = 1 << 14;
+const ICR_PENDING: u32 = 1 << 12;
+
+/// Timer local vector table bits
+const TIMER_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+/// Divide configuration for dividing the bus clock by 16
+const TIMER_DIVIDE_16: u32 = 0b0011;
+/// How long the timer is measured against the PIT
+const CALIBRATION_MS: u64 = 10;
+
+/// Vector the local APIC raises for spurious interrupts, which need no EOI
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// I/O APIC register window: select a register, then read or write it
+const IOAPIC_SELECT: usize = 0x00;
+const IOAPIC_WINDOW: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+/// First redirection table register; each entry takes two
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+/// Redirection entry bits
+const REDIRECT_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECT_LEVEL: u64 = 1 << 15;
+const REDIRECT_MASKED: u64 = 1 << 16;
+
+/// MPS INTI flag values for an override's polarity and trigger mode
+const POLARITY_MASK: u16 = 0b11;
+const POLARITY_LOW: u16 = 0b11;
+const TRIGGER_MASK: u16 = 0b11 << 2;
+const TRIGGER_LEVEL: u16 = 0b11 << 2;
+
+/// Where the local APIC's registers are mapped, or 0 while the 8259s are used
+static LAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+/// Local APIC timer counts per second after the divider, once calibrated
+static TIMER_CLOCK_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// The I/O APICs and where the ISA IRQs reach them, kept for routing IRQs
+/// that are enabled later
+static ROUTING: Mutex<Option<Routing>> = Mutex::new(None);
+
+/// Legacy IRQs routed through the I/O APIC, to the vectors the PIC used; the
+/// PIT's IRQ 0 is only routed if the local APIC timer is not the tick source
+const ROUTED: [InterruptIndex; 2] = [InterruptIndex::Keyboard, InterruptIndex::Serial];
+
+/// Whether interrupts are delivered by the APICs instead of the 8259s
+pub fn enabled() -> bool {
+	LAPIC_BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Acknowledge the interrupt being handled to the local APIC
+pub fn end_of_interrupt() {
+	let base = LAPIC_BASE.load(Ordering::Relaxed);
+	if base != 0 {
+		unsafe { write_lapic(base, LAPIC_EOI, 0) };
+	}
+}
+
+unsafe fn read_lapic(base: u64, register: usize) -> u32 {
+	unsafe { core::ptr::read_volatile((base as usize + register) as *const u32) }
+}
+
+unsafe fn write_lapic(base: u64, register: usize, value: u32) {
+	unsafe { core::ptr::write_volatile((base as usize + register) as *mut u32, value) };
+}
+
+/// This processor's local APIC ID, or `None` while the 8259s are used
+pub fn local_id() -> Option<u8> {
+	let base = LAPIC_BASE.load(Ordering::Relaxed);
+	(base != 0).then(|| (unsafe { read_lapic(base, LAPIC_ID) } >> 24) as u8)
+}
+
+/// Local APIC IDs of the processors the MADT lists, the boot processor's
+/// included
+pub fn processors() -> Vec<u8> {
+	ROUTING.lock().as_ref().map(|routing| routing.madt.processors.clone()).unwrap_or_default()
+}
+
+/// Send an inter-processor interrupt to the local APIC `destination`,
+/// waiting until it has been delivered; `command` is the delivery mode and
+/// vector
+pub(crate) fn send_ipi(destination: u8, command: u32) {
+	let base = LAPIC_BASE.load(Ordering::Relaxed);
+	if base == 0 {
+		return;
+	}
+	x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+		write_lapic(base, LAPIC_ICR_HIGH, (destination as u32) << 24);
+		write_lapic(base, LAPIC_ICR_LOW, command);
+		while read_lapic(base, LAPIC_ICR_LOW) & ICR_PENDING != 0 {
+			core::hint::spin_loop();
+		}
+	});
+}
+
+/// Turn on the local APIC of a processor started after `init`
+pub(crate) fn init_ap() {
+	let base = LAPIC_BASE.load(Ordering::Relaxed);
+	if base == 0 {
+		return;
+	}
+	unsafe { enable_lapic(base) };
+}
+
+/// Enable a local APIC, accepting every interrupt priority
+unsafe fn enable_lapic(base: u64) {
+	unsafe {
+		let mut apic_base = Msr::new(IA32_APIC_BASE);
+		apic_base.write(apic_base.read() | APIC_BASE_ENABLE);
+		write_lapic(base, LAPIC_TASK_PRIORITY, 0);
+		write_lapic(base, LAPIC_SPURIOUS, LAPIC_SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+	}
+}
+
+/// An I/O APIC's mapped register window and the interrupts it handles
+struct IoApic {
+	base: VirtAddr,
+	gsi_base: u32,
+}
+
+impl IoApic {
+	fn read(&self, register: u32) -> u32 {
+		unsafe {
+			core::ptr::write_volatile((self.base + IOAPIC_SELECT as u64).as_mut_ptr::<u32>(), register);
+			core::ptr::read_volatile((self.base + IOAPIC_WINDOW as u64).as_ptr::<u32>())
+		}
+	}
+
+	fn write(&self, register: u32, value: u32) {
+		unsafe {
+			core::ptr::write_volatile((self.base + IOAPIC_SELECT as u64).as_mut_ptr::<u32>(), register);
+			core::ptr::write_volatile((self.base + IOAPIC_WINDOW as u64).as_mut_ptr::<u32>(), value);
+		}
+	}
+
+	/// Number of interrupt inputs
+	fn inputs(&self) -> u32 {
+		(self.read(IOAPIC_VERSION) >> 16 & 0xff) + 1
+	}
+
+	fn set_redirection(&self, input: u32, entry: u64) {
+		let register = IOAPIC_REDIRECTION + input * 2;
+		// Mask the low half first so a half-written entry never fires
+		self.write(register, REDIRECT_MASKED as u32);
+		self.write(register + 1, (entry >> 32) as u32);
+		self.write(register, entry as u32);
+	}
+}
+
+/// The I/O APICs, the MADT's ISA IRQ overrides, and the local APIC
+/// interrupts are sent to
+struct Routing {
+	io_apics: Vec<IoApic>,
+	madt: Madt,
+	destination: u8,
+}
+
+impl Routing {
+	/// Deliver an ISA IRQ to its vector, or mask it, returning false if no
+	/// I/O APIC has it
+	fn route(&self, irq: u8, masked: bool) -> bool {
+		let route = self.madt.isa_irq(irq);
+		let owner = self.io_apics.iter().find(|io_apic| {
+			route.gsi >= io_apic.gsi_base && route.gsi < io_apic.gsi_base + io_apic.inputs()
+		});
+		match owner {
+			Some(io_apic) => {
+				let mut entry = redirection(route, PIC_1_OFFSET + irq, self.destination);
+				if masked {
+					entry |= REDIRECT_MASKED;
+				}
+				io_apic.set_redirection(route.gsi - io_apic.gsi_base, entry);
+				true
+			}
+			None => {
+				klog!(Warn, "APIC: no I/O APIC handles GSI {}", route.gsi);
+				false
+			}
+		}
+	}
+}
+
+/// Measure the local APIC timer's rate against PIT channel 2, in counts
+/// per second
+unsafe fn calibrate_timer(base: u64) -> u64 {
+	unsafe {
+		write_lapic(base, LAPIC_TIMER, TIMER_MASKED);
+		write_lapic(base, LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+		write_lapic(base, LAPIC_TIMER_INITIAL, u32::MAX);
+		time::pit_wait_ms(CALIBRATION_MS);
+		let remaining = read_lapic(base, LAPIC_TIMER_CURRENT);
+		write_lapic(base, LAPIC_TIMER_INITIAL, 0);
+		(u32::MAX - remaining) as u64 * (1000 / CALIBRATION_MS)
+	}
+}
+
+/// The timer's initial count for interrupts at `hz`: at least 1, at most
+/// what the 32-bit counter holds
+fn timer_count(clock_hz: u64, hz: u64) -> u32 {
+	(clock_hz / hz.max(1)).clamp(1, u32::MAX as u64) as u32
+}
+
+/// Run the scheduler tick from the local APIC timer at `hz` interrupts per
+/// second, returning false if the APIC or its calibration is missing
+pub fn set_timer_hz(hz: u64) -> bool {
+	let base = LAPIC_BASE.load(Ordering::Relaxed);
+	let clock_hz = TIMER_CLOCK_HZ.load(Ordering::Relaxed);
+	if base == 0 || clock_hz == 0 || !time::TICK_HZ_RANGE.contains(&hz) {
+		return false;
+	}
+	x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+		write_lapic(base, LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+		write_lapic(base, LAPIC_TIMER, TIMER_PERIODIC | InterruptIndex::Timer as u32);
+		write_lapic(base, LAPIC_TIMER_INITIAL, timer_count(clock_hz, hz));
+		time::set_tick_rate(hz);
+	});
+	true
+}
+
+/// The redirection entry sending an ISA IRQ to a vector on a local APIC,
+/// with the polarity and trigger mode the firmware gave it
+fn redirection(route: SourceOverride, vector: u8, destination: u8) -> u64 {
+	let mut entry = vector as u64 | (destination as u64) << 56;
+	// ISA interrupts conform to the bus: active high and edge-triggered
+	if route.flags & POLARITY_MASK == POLARITY_LOW {
+		entry |= REDIRECT_ACTIVE_LOW;
+	}
+	if route.flags & TRIGGER_MASK == TRIGGER_LEVEL {
+		entry |= REDIRECT_LEVEL;
+	}
+	entry
+}
+
+/// Switch interrupt delivery from the 8259s to the local APIC and I/O APIC
+/// described by the MADT, unless booted with `noapic`; interrupts must be
+/// disabled and memory initialized
+pub fn init() {
+	if cmdline::param("noapic").is_some() {
+		klog!(Info, "APIC: disabled by noapic, using the 8259 PIC");
+		return;
+	}
+	if !cpu::has(Feature::Apic) {
+		klog!(Info, "APIC: no local APIC, using the 8259 PIC");
+		return;
+	}
+	let Some(madt) = Madt::find() else {
+		klog!(Info, "APIC: no MADT, using the 8259 PIC");
+		return;
+	};
+	let Some(lapic) = memory::map_mmio(PhysAddr::new(madt.local_apic), 4096) else {
+		klog!(Warn, "APIC: cannot map the local APIC at {:#x}", madt.local_apic);
+		return;
+	};
+	let io_apics = madt.io_apics.iter().filter_map(|io_apic| {
+		let base = memory::map_mmio(PhysAddr::new(io_apic.address), 4096)?;
+		Some(IoApic { base, gsi_base: io_apic.gsi_base })
+	});
+	let io_apics: Vec<IoApic> = io_apics.collect();
+	if io_apics.is_empty() {
+		klog!(Warn, "APIC: no usable I/O APIC, using the 8259 PIC");
+		return;
+	}
+
+	// Mask every 8259 line; they stay remapped so a stray interrupt is harmless
+	unsafe { PICS.lock().write_masks(0xff, 0xff) };
+
+	let base = lapic.as_u64();
+	unsafe { enable_lapic(base) };
+	let destination = (unsafe { read_lapic(base, LAPIC_ID) } >> 24) as u8;
+	let timer_clock_hz = unsafe { calibrate_timer(base) };
+
+	for io_apic in &io_apics {
+		for input in 0..io_apic.inputs() {
+			io_apic.set_redirection(input, REDIRECT_MASKED);
+		}
+	}
+	let routing = Routing { io_apics, madt, destination };
+	for index in ROUTED {
+		routing.route(index.irq(), false);
+	}
+	klog!(
+		Info,
+		"APIC: local APIC {} at {:#x}, {} I/O APIC(s), {} processor(s)",
+		destination, routing.madt.local_apic, routing.io_apics.len(), routing.madt.processors.len()
+	);
+	*ROUTING.lock() = Some(routing);
+	LAPIC_BASE.store(base, Ordering::Relaxed);
+	TIMER_CLOCK_HZ.store(timer_clock_hz, Ordering::Relaxed);
+}
+
+/// Deliver another ISA IRQ through the I/O APIC, such as the PIT's (or the
+/// HPET's legacy) IRQ 0 when something other than the local APIC timer
+/// ticks, or mask it there
+pub fn set_isa_irq(irq: u8, masked: bool) -> bool {
+	ROUTING.lock().as_ref().is_some_and(|routing| routing.route(irq, masked))
+}
+
+/// Test building redirection entries and timer counts
+#[test_case]
+fn test_redirection() {
+	assert_eq!(timer_count(62_500_000, 100), 625_000);
+	assert_eq!(timer_count(62_500_000, 0), 62_500_000);
+	assert_eq!(timer_count(u64::MAX, 1), u32::MAX);
+	assert_eq!(timer_count(50, 100), 1);
+
+	let isa = SourceOverride { irq: 1, gsi: 1, flags: 0 };
+	assert_eq!(redirection(isa, 33, 0), 33);
+	let level_low = SourceOverride { irq: 9, gsi: 9, flags: 0b1111 };
+	assert_eq!(redirection(level_low, 41, 2), 41 | REDIRECT_ACTIVE_LOW | REDIRECT_LEVEL | 2 << 56);
+	let high_edge = SourceOverride { irq: 0, gsi: 2, flags: 0b0101 };
+	assert_eq!(redirection(high_edge, 32, 0), 32);
+}
@@ -0,0 +1,133 @@
+use crate::klog::MESSAGE_LEN;
+use crate::process::{self, Credentials, Process};
+use crate::ring::{FixedText, Ring};
+use crate::sync::Mutex;
+use crate::time;
+use core::fmt::{self, Write};
+
+/// Number of records the audit log keeps before overwriting the oldest
+pub const AUDIT_CAPACITY: usize = 128;
+
+/// What kind of event a record is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+	/// A permission check failed, or a password was wrong
+	Denied,
+	/// A process sent a signal
+	Signal,
+	/// A process was created
+	Spawn,
+	/// A process exited or was killed
+	Exit,
+	/// A filesystem was mounted
+	Mount,
+}
+
+impl Kind {
+	/// Every kind, as `auditlog` lists them
+	pub const ALL: [Kind; 5] = [Kind::Denied, Kind::Signal, Kind::Spawn, Kind::Exit, Kind::Mount];
+
+	/// Short name as shown and filtered on by `auditlog`
+	pub fn name(self) -> &'static str {
+		match self {
+			Kind::Denied => "denied",
+			Kind::Signal => "signal",
+			Kind::Spawn => "spawn",
+			Kind::Exit => "exit",
+			Kind::Mount => "mount",
+		}
+	}
+
+	/// Look up a kind by name
+	pub fn from_name(name: &str) -> Option<Kind> {
+		Kind::ALL.into_iter().find(|kind| kind.name() == name)
+	}
+}
+
+/// Who an event was done by: a process, or the kernel when `pid` is `None`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Actor {
+	pub pid: Option<usize>,
+	pub uid: u32,
+	pub gid: u32,
+}
+
+impl Actor {
+	/// The kernel acting on its own
+	pub const KERNEL: Actor = Actor { pid: None, uid: 0, gid: 0 };
+
+	/// The current process; the kernel when there is none, or when the
+	/// scheduler is busy, as it is while a caller holds it
+	pub fn current() -> Actor {
+		process::try_with_current_process(|p| Actor::of(p)).unwrap_or(Actor::KERNEL)
+	}
+
+	/// `process`, for callers already holding the scheduler
+	pub fn of(process: &Process) -> Actor {
+		Actor { pid: Some(process.pid.0), uid: process.credentials.uid, gid: process.credentials.gid }
+	}
+
+	/// The current process acting as `who`, as permission checks are asked
+	pub fn with(who: &Credentials) -> Actor {
+		Actor { uid: who.uid, gid: who.gid, ..Actor::current() }
+	}
+}
+
+/// One audited event
+#[derive(Clone, Copy)]
+pub struct Record {
+	/// Milliseconds since boot when it happened
+	pub timestamp_ms: u64,
+	pub kind: Kind,
+	pub actor: Actor,
+	text: FixedText<MESSAGE_LEN>,
+}
+
+impl Record {
+	const EMPTY: Record = Record { timestamp_ms: 0, kind: Kind::Denied, actor: Actor::KERNEL, text: FixedText::EMPTY };
+
+	/// What happened
+	pub fn message(&self) -> &str {
+		self.text.as_str()
+	}
+}
+
+/// Fixed-size ring of audit records, numbered by a running sequence
+type AuditBuffer = Ring<Record, AUDIT_CAPACITY>;
+
+/// Kept apart from the kernel log, which anyone can read and `dmesg -C`
+/// clears; only root reads this one, and nothing clears it
+static AUDIT: Mutex<AuditBuffer> = Mutex::new(AuditBuffer::new(Record::EMPTY));
+
+/// Record an event done by `actor`
+///
+/// Does not allocate or take the scheduler, so it is safe from exception
+/// handlers and from code holding either.
+pub fn record(kind: Kind, actor: Actor, args: fmt::Arguments) {
+	let mut record = Record { timestamp_ms: time::uptime_ms(), kind, actor, ..Record::EMPTY };
+	let _ = record.text.write_fmt(args);
+	x86_64::instructions::interrupts::without_interrupts(|| AUDIT.lock().push(record));
+}
+
+/// The oldest record still held with sequence number `seq` or later
+pub fn read_from(seq: u64) -> Option<(u64, Record)> {
+	x86_64::instructions::interrupts::without_interrupts(|| AUDIT.lock().read_from(seq))
+}
+
+/// Test that records keep their actor and the ring keeps the newest
+#[test_case]
+fn test_audit_buffer() {
+	let mut buffer = AuditBuffer::new(Record::EMPTY);
+	let actor = Actor { pid: Some(7), uid: 1000, gid: 100 };
+	for i in 0..AUDIT_CAPACITY + 2 {
+		let mut record = Record { kind: Kind::Spawn, actor, ..Record::EMPTY };
+		let _ = write!(record.text, "process {}", i);
+		buffer.push(record);
+	}
+	let (seq, oldest) = buffer.read_from(0).unwrap();
+	assert_eq!((seq, oldest.message()), (2, "process 2"));
+	assert_eq!(oldest.actor, actor);
+	assert!(buffer.read_from(AUDIT_CAPACITY as u64 + 2).is_none());
+	assert_eq!(Kind::from_name("exit"), Some(Kind::Exit));
+	assert_eq!(Kind::from_name("bogus"), None);
+}
@@ -0,0 +1,34 @@
+/// The kernel command line, as space-separated `name=value` parameters
+///
+/// The bootloader has no way to pass one, so it is fixed when the kernel is
+/// built, from the `SCOTTOS_CMDLINE` environment variable.
+pub const CMDLINE: &str = match option_env!("SCOTTOS_CMDLINE") {
+	Some(cmdline) => cmdline,
+	None => "",
+};
+
+/// The value of parameter `name` in a command line; a bare `name` has an
+/// empty value, and the last occurrence wins
+fn find_param<'a>(cmdline: &'a str, name: &str) -> Option<&'a str> {
+	cmdline.split_whitespace()
+		.filter_map(|param| match param.split_once('=') {
+			Some((key, value)) => (key == name).then_some(value),
+			None => (param == name).then_some(""),
+		})
+		.next_back()
+}
+
+/// The value of a kernel command line parameter (`theme=ocean`)
+pub fn param(name: &str) -> Option<&'static str> {
+	find_param(CMDLINE, name)
+}
+
+/// Test parameter lookup
+#[test_case]
+fn test_find_param() {
+	let cmdline = "theme=ocean quiet loglevel=4 theme=light";
+	assert_eq!(find_param(cmdline, "theme"), Some("light"));
+	assert_eq!(find_param(cmdline, "quiet"), Some(""));
+	assert_eq!(find_param(cmdline, "loglevel"), Some("4"));
+	assert_eq!(find_param(cmdline, "log"), None);
+}
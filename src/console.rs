@@ -0,0 +1,120 @@
+use crate::serial::SerialConsole;
+use crate::vga_buffer::{self, Color, VgaText};
+use crate::{cmdline, klog};
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::ops::Range;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// A screen of character cells that console text is drawn on, such as VGA
+/// text mode, a framebuffer with a bitmap font, or a serial terminal
+///
+/// The terminal itself (escape sequences, scrollback, virtual consoles) is
+/// `vga_buffer::Writer`, which keeps every console's cells and draws them
+/// here while that console is the one displayed.
+pub trait Console: Send {
+	/// Rows and columns of character cells
+	fn size(&self) -> (usize, usize);
+
+	/// Draw a code page 437 character in a cell
+	fn draw(&mut self, row: usize, col: usize, byte: u8, foreground: Color, background: Color);
+
+	/// Move the rows in `rows` up one, dropping the first; the last row of
+	/// the range is redrawn afterwards
+	fn scroll_up(&mut self, rows: Range<usize>);
+
+	/// Show the cursor at a cell, or hide it
+	fn set_cursor(&mut self, cursor: Option<(usize, usize)>);
+}
+
+/// Output set bits: the screen (VGA text or framebuffer) and the serial port
+const OUTPUT_SCREEN: u8 = 1;
+const OUTPUT_SERIAL: u8 = 2;
+
+/// Where the console is drawn, chosen with `console=` on the command line
+static OUTPUTS: AtomicU8 = AtomicU8::new(OUTPUT_SCREEN);
+
+/// The outputs named in a `console=` value such as `vga,serial`, or `None`
+/// if it names none or an unknown one
+fn parse_outputs(value: &str) -> Option<u8> {
+	let mut outputs = 0;
+	for name in value.split(',') {
+		outputs |= match name {
+			"vga" | "screen" | "tty0" => OUTPUT_SCREEN,
+			"serial" | "ttyS0" => OUTPUT_SERIAL,
+			_ => return None,
+		};
+	}
+	(outputs != 0).then_some(outputs)
+}
+
+/// Pick the console outputs from the `console=` parameter, drawing the
+/// console on the serial port straight away if it is one of them
+pub fn init() {
+	let Some(value) = cmdline::param("console") else {
+		return;
+	};
+	match parse_outputs(value) {
+		Some(outputs) => {
+			OUTPUTS.store(outputs, Ordering::Relaxed);
+			vga_buffer::set_display(Box::new(VgaText));
+		}
+		None => klog!(Warn, "console: unknown output in '{}'", value),
+	}
+}
+
+/// Whether the console is drawn on the serial port, which then carries
+/// nothing else
+pub fn on_serial() -> bool {
+	OUTPUTS.load(Ordering::Relaxed) & OUTPUT_SERIAL != 0
+}
+
+/// The display console output is drawn on, given the screen: the screen,
+/// the serial port, or both
+pub fn outputs(screen: Box<dyn Console>) -> Box<dyn Console> {
+	let outputs = OUTPUTS.load(Ordering::Relaxed);
+	match (outputs & OUTPUT_SCREEN != 0, outputs & OUTPUT_SERIAL != 0) {
+		(true, true) => {
+			let serial = Box::new(SerialConsole::new(screen.size()));
+			Box::new(Broadcast(vec![screen, serial]))
+		}
+		(false, true) => Box::new(SerialConsole::new(screen.size())),
+		_ => screen,
+	}
+}
+
+/// Several displays showing the same cells, sized by the first
+pub struct Broadcast(pub Vec<Box<dyn Console>>);
+
+impl Console for Broadcast {
+	fn size(&self) -> (usize, usize) {
+		self.0.first().map_or((0, 0), |display| display.size())
+	}
+
+	fn draw(&mut self, row: usize, col: usize, byte: u8, foreground: Color, background: Color) {
+		for display in &mut self.0 {
+			display.draw(row, col, byte, foreground, background);
+		}
+	}
+
+	fn scroll_up(&mut self, rows: Range<usize>) {
+		for display in &mut self.0 {
+			display.scroll_up(rows.clone());
+		}
+	}
+
+	fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+		for display in &mut self.0 {
+			display.set_cursor(cursor);
+		}
+	}
+}
+
+/// Test parsing the console outputs
+#[test_case]
+fn test_parse_outputs() {
+	assert_eq!(parse_outputs("vga"), Some(OUTPUT_SCREEN));
+	assert_eq!(parse_outputs("ttyS0"), Some(OUTPUT_SERIAL));
+	assert_eq!(parse_outputs("tty0,serial"), Some(OUTPUT_SCREEN | OUTPUT_SERIAL));
+	assert_eq!(parse_outputs("lp0"), None);
+	assert_eq!(parse_outputs(""), None);
+}
@@ -0,0 +1,89 @@
+/// The characters of code page 437, the VGA font's character set, from 0x80 up
+const UPPER_HALF: [char; 128] = [
+	'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+	'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+	'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+	'░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+	'└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+	'╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+	'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+	'≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Shown for characters code page 437 has no glyph or stand-in for
+pub const UNKNOWN: u8 = 0xfe;
+
+/// Characters without a glyph of their own, drawn with a close one
+fn stand_in(c: char) -> Option<char> {
+	Some(match c {
+		'À' | 'Á' | 'Â' | 'Ã' => 'A',
+		'È' | 'Ê' | 'Ë' => 'E',
+		'Ì' | 'Í' | 'Î' | 'Ï' => 'I',
+		'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ø' => 'O',
+		'Ù' | 'Ú' | 'Û' => 'U',
+		'Ý' => 'Y',
+		'ã' => 'a',
+		'õ' | 'ø' => 'o',
+		'ý' => 'y',
+		'×' => 'x',
+		'‘' | '’' | '′' => '\'',
+		'“' | '”' | '″' => '"',
+		'‐' | '–' | '—' | '−' => '-',
+		'β' => 'ß',
+		'μ' => 'µ',
+		'∑' => 'Σ',
+		'•' => '∙',
+		// Heavy and rounded box drawing, drawn light
+		'━' => '─',
+		'┃' => '│',
+		'┏' | '╭' => '┌',
+		'┓' | '╮' => '┐',
+		'┗' | '╰' => '└',
+		'┛' | '╯' => '┘',
+		_ => return None,
+	})
+}
+
+/// The code page 437 byte that draws `c`, or `UNKNOWN`
+///
+/// ASCII maps to itself, control characters included.
+pub fn encode(c: char) -> u8 {
+	if c.is_ascii() {
+		return c as u8;
+	}
+	let c = stand_in(c).unwrap_or(c);
+	if c.is_ascii() {
+		return c as u8;
+	}
+	match UPPER_HALF.iter().position(|&glyph| glyph == c) {
+		Some(index) => 0x80 + index as u8,
+		None => UNKNOWN,
+	}
+}
+
+/// The character code page 437 draws for `byte`; control characters,
+/// which the VGA font draws as symbols, come back as spaces
+pub fn decode(byte: u8) -> char {
+	match byte {
+		0x20..=0x7e => byte as char,
+		0x80..=0xff => UPPER_HALF[byte as usize - 0x80],
+		_ => ' ',
+	}
+}
+
+/// Test mapping box drawing, Latin-1, and unknown characters
+#[test_case]
+fn test_encode() {
+	assert_eq!(encode('A'), b'A');
+	assert_eq!(encode('\n'), b'\n');
+	assert_eq!(encode('╔'), 0xc9);
+	assert_eq!(encode('═'), 0xcd);
+	assert_eq!(encode('║'), 0xba);
+	assert_eq!(encode('é'), 0x82);
+	assert_eq!(encode('\u{a0}'), 0xff);
+	assert_eq!(encode('Á'), b'A');
+	assert_eq!(encode('╭'), 0xda);
+	assert_eq!(encode('日'), UNKNOWN);
+	assert_eq!(decode(0xc9), '╔');
+	assert_eq!(decode(encode('ü')), 'ü');
+}
@@ -0,0 +1,249 @@
+use alloc::string::String;
+use core::arch::x86_64::{CpuidResult, __cpuid, __cpuid_count};
+use core::fmt::Write;
+use lazy_static::lazy_static;
+
+/// CPUID leaves
+const LEAF_VENDOR: u32 = 0x0000_0000;
+const LEAF_FEATURES: u32 = 0x0000_0001;
+const LEAF_EXTENDED_FEATURES: u32 = 0x0000_0007;
+const LEAF_EXTENDED_MAX: u32 = 0x8000_0000;
+const LEAF_EXTENDED_INFO: u32 = 0x8000_0001;
+const LEAF_BRAND: u32 = 0x8000_0002;
+const LEAF_POWER_MANAGEMENT: u32 = 0x8000_0007;
+
+/// Times RDRAND is retried when the generator has nothing ready
+const RDRAND_RETRIES: usize = 10;
+
+/// A processor feature CPUID reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+	Fpu,
+	Tsc,
+	Msr,
+	Pae,
+	Mce,
+	Apic,
+	Mca,
+	Sse,
+	Sse2,
+	Sse3,
+	Ssse3,
+	Sse41,
+	Sse42,
+	X2apic,
+	Popcnt,
+	Aes,
+	Xsave,
+	Avx,
+	Rdrand,
+	Hypervisor,
+	Avx2,
+	Rdseed,
+	Nx,
+	Pdpe1gb,
+	Rdtscp,
+	LongMode,
+	/// A time stamp counter that runs at a constant rate through frequency
+	/// and power state changes
+	InvariantTsc,
+}
+
+/// A CPUID output register
+#[derive(Clone, Copy)]
+enum Register {
+	Ebx,
+	Ecx,
+	Edx,
+}
+
+/// Where CPUID reports each feature, and its name in `/proc/cpuinfo`
+const FEATURES: [(Feature, &str, u32, Register, u32); 27] = [
+	(Feature::Fpu, "fpu", LEAF_FEATURES, Register::Edx, 0),
+	(Feature::Tsc, "tsc", LEAF_FEATURES, Register::Edx, 4),
+	(Feature::Msr, "msr", LEAF_FEATURES, Register::Edx, 5),
+	(Feature::Pae, "pae", LEAF_FEATURES, Register::Edx, 6),
+	(Feature::Mce, "mce", LEAF_FEATURES, Register::Edx, 7),
+	(Feature::Apic, "apic", LEAF_FEATURES, Register::Edx, 9),
+	(Feature::Mca, "mca", LEAF_FEATURES, Register::Edx, 14),
+	(Feature::Sse, "sse", LEAF_FEATURES, Register::Edx, 25),
+	(Feature::Sse2, "sse2", LEAF_FEATURES, Register::Edx, 26),
+	(Feature::Sse3, "sse3", LEAF_FEATURES, Register::Ecx, 0),
+	(Feature::Ssse3, "ssse3", LEAF_FEATURES, Register::Ecx, 9),
+	(Feature::Sse41, "sse4_1", LEAF_FEATURES, Register::Ecx, 19),
+	(Feature::Sse42, "sse4_2", LEAF_FEATURES, Register::Ecx, 20),
+	(Feature::X2apic, "x2apic", LEAF_FEATURES, Register::Ecx, 21),
+	(Feature::Popcnt, "popcnt", LEAF_FEATURES, Register::Ecx, 23),
+	(Feature::Aes, "aes", LEAF_FEATURES, Register::Ecx, 25),
+	(Feature::Xsave, "xsave", LEAF_FEATURES, Register::Ecx, 26),
+	(Feature::Avx, "avx", LEAF_FEATURES, Register::Ecx, 28),
+	(Feature::Rdrand, "rdrand", LEAF_FEATURES, Register::Ecx, 30),
+	(Feature::Hypervisor, "hypervisor", LEAF_FEATURES, Register::Ecx, 31),
+	(Feature::Avx2, "avx2", LEAF_EXTENDED_FEATURES, Register::Ebx, 5),
+	(Feature::Rdseed, "rdseed", LEAF_EXTENDED_FEATURES, Register::Ebx, 18),
+	(Feature::Nx, "nx", LEAF_EXTENDED_INFO, Register::Edx, 20),
+	(Feature::Pdpe1gb, "pdpe1gb", LEAF_EXTENDED_INFO, Register::Edx, 26),
+	(Feature::Rdtscp, "rdtscp", LEAF_EXTENDED_INFO, Register::Edx, 27),
+	(Feature::LongMode, "lm", LEAF_EXTENDED_INFO, Register::Edx, 29),
+	(Feature::InvariantTsc, "invariant_tsc", LEAF_POWER_MANAGEMENT, Register::Edx, 8),
+];
+
+/// What CPUID says about the boot processor
+pub struct CpuInfo {
+	vendor: [u8; 12],
+	brand: [u8; 48],
+	pub family: u32,
+	pub model: u32,
+	pub stepping: u32,
+	/// A bit per entry of `FEATURES`
+	features: u64,
+}
+
+lazy_static! {
+	/// Read once, before the heap exists if need be
+	static ref INFO: CpuInfo = CpuInfo::detect();
+}
+
+/// CPUID output for a leaf, or zeros if the processor does not have it
+fn cpuid(leaf: u32, max_basic: u32, max_extended: u32) -> CpuidResult {
+	let max = if leaf >= LEAF_EXTENDED_MAX { max_extended } else { max_basic };
+	if leaf > max {
+		return CpuidResult { eax: 0, ebx: 0, ecx: 0, edx: 0 };
+	}
+	unsafe { __cpuid_count(leaf, 0) }
+}
+
+/// Family, model, and stepping from CPUID leaf 1 EAX, with the extended
+/// fields folded in the way both vendors document
+fn signature(eax: u32) -> (u32, u32, u32) {
+	let base_family = eax >> 8 & 0xf;
+	let family = match base_family {
+		0xf => base_family + (eax >> 20 & 0xff),
+		_ => base_family,
+	};
+	let model = match base_family {
+		0x6 | 0xf => (eax >> 4 & 0xf) | (eax >> 16 & 0xf) << 4,
+		_ => eax >> 4 & 0xf,
+	};
+	(family, model, eax & 0xf)
+}
+
+impl CpuInfo {
+	fn detect() -> CpuInfo {
+		let vendor_leaf = unsafe { __cpuid(LEAF_VENDOR) };
+		let max_basic = vendor_leaf.eax;
+		let max_extended = unsafe { __cpuid(LEAF_EXTENDED_MAX) }.eax;
+
+		let mut vendor = [0; 12];
+		for (chunk, register) in vendor.chunks_mut(4).zip([vendor_leaf.ebx, vendor_leaf.edx, vendor_leaf.ecx]) {
+			chunk.copy_from_slice(&register.to_le_bytes());
+		}
+		let mut brand = [0; 48];
+		for (index, chunk) in brand.chunks_mut(16).enumerate() {
+			let leaf = cpuid(LEAF_BRAND + index as u32, max_basic, max_extended);
+			for (bytes, register) in chunk.chunks_mut(4).zip([leaf.eax, leaf.ebx, leaf.ecx, leaf.edx]) {
+				bytes.copy_from_slice(&register.to_le_bytes());
+			}
+		}
+
+		let mut features = 0;
+		for (index, &(_, _, leaf, register, bit)) in FEATURES.iter().enumerate() {
+			let output = cpuid(leaf, max_basic, max_extended);
+			let value = match register {
+				Register::Ebx => output.ebx,
+				Register::Ecx => output.ecx,
+				Register::Edx => output.edx,
+			};
+			if value & 1 << bit != 0 {
+				features |= 1 << index;
+			}
+		}
+		let (family, model, stepping) = signature(cpuid(LEAF_FEATURES, max_basic, max_extended).eax);
+		CpuInfo { vendor, brand, family, model, stepping, features }
+	}
+
+	/// The vendor string, such as `GenuineIntel`
+	pub fn vendor(&self) -> &str {
+		core::str::from_utf8(&self.vendor).unwrap_or("unknown")
+	}
+
+	/// The brand string, or `None` on processors without one
+	pub fn brand(&self) -> Option<&str> {
+		let end = self.brand.iter().position(|&byte| byte == 0).unwrap_or(self.brand.len());
+		let brand = core::str::from_utf8(&self.brand[..end]).ok()?.trim();
+		(!brand.is_empty()).then_some(brand)
+	}
+
+	/// Whether the processor has a feature
+	pub fn has(&self, feature: Feature) -> bool {
+		FEATURES.iter().position(|&(known, ..)| known == feature).is_some_and(|index| self.features & 1 << index != 0)
+	}
+
+	/// Names of the features the processor has, in CPUID order
+	pub fn flags(&self) -> impl Iterator<Item = &'static str> + '_ {
+		FEATURES.iter().enumerate().filter(|&(index, _)| self.features & 1 << index != 0).map(|(_, &(_, name, ..))| name)
+	}
+}
+
+/// What CPUID says about the boot processor
+pub fn info() -> &'static CpuInfo {
+	&INFO
+}
+
+/// Whether the boot processor has a feature
+pub fn has(feature: Feature) -> bool {
+	INFO.has(feature)
+}
+
+/// A random number from the processor's generator, or `None` without one
+pub fn rdrand() -> Option<u64> {
+	if !has(Feature::Rdrand) {
+		return None;
+	}
+	for _ in 0..RDRAND_RETRIES {
+		let mut value = 0;
+		if unsafe { core::arch::x86_64::_rdrand64_step(&mut value) } == 1 {
+			return Some(value);
+		}
+	}
+	None
+}
+
+/// The contents of `/proc/cpuinfo`: a stanza per online processor, all with
+/// the boot processor's identity
+pub fn cpuinfo() -> String {
+	let info = info();
+	let mut flags = String::new();
+	for flag in info.flags() {
+		if !flags.is_empty() {
+			flags.push(' ');
+		}
+		flags.push_str(flag);
+	}
+	let mut text = String::new();
+	for processor in 0..crate::smp::cpu_count() {
+		let _ = writeln!(text, "processor\t: {}", processor);
+		let _ = writeln!(text, "vendor_id\t: {}", info.vendor());
+		let _ = writeln!(text, "cpu family\t: {}", info.family);
+		let _ = writeln!(text, "model\t\t: {}", info.model);
+		let _ = writeln!(text, "model name\t: {}", info.brand().unwrap_or("unknown"));
+		let _ = writeln!(text, "stepping\t: {}", info.stepping);
+		if let Some(hz) = crate::time::tsc_hz() {
+			let _ = writeln!(text, "cpu MHz\t\t: {}.{:03}", hz / 1_000_000, hz / 1000 % 1000);
+		}
+		let _ = writeln!(text, "flags\t\t: {}", flags);
+		let _ = writeln!(text);
+	}
+	text
+}
+
+/// Test decoding processor signatures
+#[test_case]
+fn test_signature() {
+	// An Intel Skylake and an AMD Zen 2
+	assert_eq!(signature(0x0005_06e3), (6, 94, 3));
+	assert_eq!(signature(0x0083_0f10), (23, 49, 0));
+	assert_eq!(signature(0x0000_0633), (6, 3, 3));
+	assert!(has(Feature::LongMode));
+	assert!(info().flags().any(|flag| flag == "sse2"));
+}
@@ -0,0 +1,339 @@
+use crate::cpu::{self, Feature};
+use crate::process::{self, Signal};
+use crate::{gdt, klog, println};
+use core::fmt;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::{Cr2, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+/// RFLAGS trap flag, which raises a debug exception after every instruction
+const TRAP_FLAG: u64 = 1 << 8;
+
+/// System control port B, whose high bits say why the chipset raised an NMI
+const SYSTEM_CONTROL_B: u16 = 0x61;
+const NMI_PARITY_ERROR: u8 = 1 << 7;
+const NMI_CHANNEL_CHECK: u8 = 1 << 6;
+
+/// Machine check MSRs; bank i has its control, status, address, and misc
+/// registers at `IA32_MC0_CTL + 4 * i`
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17a;
+const IA32_MC0_CTL: u32 = 0x400;
+const MCG_BANK_COUNT: u64 = 0xff;
+/// Global status bits: the interrupted code can be resumed, and a machine
+/// check is in progress
+const MCG_RIPV: u64 = 1 << 0;
+const MCG_MCIP: u64 = 1 << 2;
+
+/// Bank status bits
+const MCI_VALID: u64 = 1 << 63;
+const MCI_OVERFLOW: u64 = 1 << 62;
+const MCI_UNCORRECTED: u64 = 1 << 61;
+const MCI_MISC_VALID: u64 = 1 << 59;
+const MCI_ADDR_VALID: u64 = 1 << 58;
+const MCI_CONTEXT_CORRUPT: u64 = 1 << 57;
+
+/// A CPU exception, and the signal the process running when it hit gets
+struct Exception {
+	mnemonic: &'static str,
+	name: &'static str,
+	signal: Signal,
+}
+
+const DIVIDE_ERROR: Exception = Exception { mnemonic: "DE", name: "DIVIDE ERROR", signal: Signal::Fpe };
+const OVERFLOW: Exception = Exception { mnemonic: "OF", name: "OVERFLOW", signal: Signal::Segv };
+const BOUND_RANGE: Exception = Exception { mnemonic: "BR", name: "BOUND RANGE EXCEEDED", signal: Signal::Segv };
+const INVALID_OPCODE: Exception = Exception { mnemonic: "UD", name: "INVALID OPCODE", signal: Signal::Ill };
+const DEVICE_NOT_AVAILABLE: Exception = Exception { mnemonic: "NM", name: "DEVICE NOT AVAILABLE", signal: Signal::Fpe };
+const INVALID_TSS: Exception = Exception { mnemonic: "TS", name: "INVALID TSS", signal: Signal::Segv };
+const SEGMENT_NOT_PRESENT: Exception = Exception { mnemonic: "NP", name: "SEGMENT NOT PRESENT", signal: Signal::Bus };
+const STACK_SEGMENT: Exception = Exception { mnemonic: "SS", name: "STACK-SEGMENT FAULT", signal: Signal::Bus };
+const GENERAL_PROTECTION: Exception = Exception { mnemonic: "GP", name: "GENERAL PROTECTION FAULT", signal: Signal::Segv };
+const PAGE_FAULT: Exception = Exception { mnemonic: "PF", name: "PAGE FAULT", signal: Signal::Segv };
+const X87_FLOATING_POINT: Exception = Exception { mnemonic: "MF", name: "X87 FLOATING-POINT ERROR", signal: Signal::Fpe };
+const ALIGNMENT_CHECK: Exception = Exception { mnemonic: "AC", name: "ALIGNMENT CHECK", signal: Signal::Bus };
+const MACHINE_CHECK: Exception = Exception { mnemonic: "MC", name: "MACHINE CHECK", signal: Signal::Bus };
+const SIMD_FLOATING_POINT: Exception = Exception { mnemonic: "XM", name: "SIMD FLOATING-POINT EXCEPTION", signal: Signal::Fpe };
+const VIRTUALIZATION: Exception = Exception { mnemonic: "VE", name: "VIRTUALIZATION EXCEPTION", signal: Signal::Segv };
+const CONTROL_PROTECTION: Exception = Exception { mnemonic: "CP", name: "CONTROL PROTECTION EXCEPTION", signal: Signal::Segv };
+const HV_INJECTION: Exception = Exception { mnemonic: "HV", name: "HYPERVISOR INJECTION EXCEPTION", signal: Signal::Segv };
+const VMM_COMMUNICATION: Exception = Exception { mnemonic: "VC", name: "VMM COMMUNICATION EXCEPTION", signal: Signal::Segv };
+const SECURITY: Exception = Exception { mnemonic: "SX", name: "SECURITY EXCEPTION", signal: Signal::Segv };
+
+/// Install handlers for every CPU exception vector the IDT can hold; the
+/// debug and breakpoint vectors are left to the caller when the GDB stub
+/// takes them
+pub(crate) fn install(idt: &mut InterruptDescriptorTable, debugger: bool) {
+	if !debugger {
+		idt.debug.set_handler_fn(debug_handler);
+		idt.breakpoint.set_handler_fn(breakpoint_handler);
+	}
+	idt.divide_error.set_handler_fn(divide_error_handler);
+	idt.non_maskable_interrupt.set_handler_fn(nmi_handler);
+	idt.overflow.set_handler_fn(overflow_handler);
+	idt.bound_range_exceeded.set_handler_fn(bound_range_handler);
+	idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+	idt.device_not_available.set_handler_fn(device_not_available_handler);
+	unsafe {
+		idt.double_fault.set_handler_fn(double_fault_handler)
+			.set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+	}
+	idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+	idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+	idt.stack_segment_fault.set_handler_fn(stack_segment_handler);
+	idt.general_protection_fault.set_handler_fn(general_protection_handler);
+	idt.page_fault.set_handler_fn(page_fault_handler);
+	idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+	idt.alignment_check.set_handler_fn(alignment_check_handler);
+	// A corrected error can be resumed from, which the diverging handler
+	// type the crate gives this vector would rule out
+	unsafe { idt.machine_check.set_handler_addr(VirtAddr::new(machine_check_handler as usize as u64)) };
+	idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+	idt.virtualization.set_handler_fn(virtualization_handler);
+	idt.cp_protection_exception.set_handler_fn(control_protection_handler);
+	idt.hv_injection_exception.set_handler_fn(hv_injection_handler);
+	idt.vmm_communication_exception.set_handler_fn(vmm_communication_handler);
+	idt.security_exception.set_handler_fn(security_handler);
+}
+
+/// The process that was running when an exception hit
+struct Culprit;
+
+impl fmt::Display for Culprit {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// The scheduler lock may be held by the code that faulted
+		process::try_with_current_process(|process| write!(f, "pid {} ({})", process.pid.0, process.name))
+			.unwrap_or_else(|| f.write_str("no process"))
+	}
+}
+
+/// A selector error code, as pushed by #TS, #NP, #SS, and #GP
+struct SelectorError(u64);
+
+impl fmt::Display for SelectorError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let code = self.0;
+		if code == 0 {
+			return f.write_str("error code 0");
+		}
+		let table = match code >> 1 & 0b11 {
+			0b00 => "GDT",
+			0b10 => "LDT",
+			_ => "IDT",
+		};
+		write!(f, "error code {:#x}: {} entry {}", code, table, code >> 3 & 0x1fff)?;
+		if code & 1 != 0 {
+			f.write_str(", external")?;
+		}
+		Ok(())
+	}
+}
+
+/// Report an exception that the interrupted code cannot continue from: mark
+/// the running process killed by the exception's signal, then panic, since
+/// everything runs in the kernel and there is nothing to return to
+fn fault(exception: &Exception, stack_frame: &InterruptStackFrame, detail: fmt::Arguments) -> ! {
+	process::try_with_current_process(|process| process.deliver(exception.signal));
+	panic!(
+		"EXCEPTION: {} (#{}){}\nRunning {}, killed by SIG{}\n{:#?}",
+		exception.name, exception.mnemonic, detail, Culprit, exception.signal.name(), stack_frame
+	);
+}
+
+/// Generate a handler that reports a fatal exception, with its selector
+/// error code if the CPU pushes one
+macro_rules! fault_handler {
+	($name:ident, $exception:expr) => {
+		extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
+			fault(&$exception, &stack_frame, format_args!(""));
+		}
+	};
+	($name:ident, $exception:expr, error_code) => {
+		extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame, error_code: u64) {
+			fault(&$exception, &stack_frame, format_args!(", {}", SelectorError(error_code)));
+		}
+	};
+}
+
+fault_handler!(divide_error_handler, DIVIDE_ERROR);
+fault_handler!(overflow_handler, OVERFLOW);
+fault_handler!(bound_range_handler, BOUND_RANGE);
+fault_handler!(invalid_opcode_handler, INVALID_OPCODE);
+fault_handler!(device_not_available_handler, DEVICE_NOT_AVAILABLE);
+fault_handler!(invalid_tss_handler, INVALID_TSS, error_code);
+fault_handler!(segment_not_present_handler, SEGMENT_NOT_PRESENT, error_code);
+fault_handler!(stack_segment_handler, STACK_SEGMENT, error_code);
+fault_handler!(general_protection_handler, GENERAL_PROTECTION, error_code);
+fault_handler!(x87_floating_point_handler, X87_FLOATING_POINT);
+fault_handler!(alignment_check_handler, ALIGNMENT_CHECK, error_code);
+fault_handler!(simd_floating_point_handler, SIMD_FLOATING_POINT);
+fault_handler!(virtualization_handler, VIRTUALIZATION);
+fault_handler!(control_protection_handler, CONTROL_PROTECTION, error_code);
+fault_handler!(hv_injection_handler, HV_INJECTION);
+fault_handler!(vmm_communication_handler, VMM_COMMUNICATION, error_code);
+fault_handler!(security_handler, SECURITY, error_code);
+
+/// Breakpoint exception handler, which enters the debugger when booted with `kdb`
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+	if crate::kdb::on_breakpoint() {
+		crate::kdb::enter(crate::kdb::Entry::Breakpoint, Some(&stack_frame));
+		return;
+	}
+	println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+/// Debug exceptions without a debugger attached: a stray single step or
+/// hardware breakpoint, which is logged and ignored
+extern "x86-interrupt" fn debug_handler(mut stack_frame: InterruptStackFrame) {
+	klog!(Warn, "debug exception at {:?}", stack_frame.instruction_pointer);
+	// Stop single-stepping, or this would repeat after every instruction
+	unsafe { stack_frame.as_mut().update(|frame| frame.cpu_flags &= !TRAP_FLAG) };
+}
+
+/// Non-maskable interrupts: a memory parity or I/O channel error reported by
+/// the chipset is fatal, while any other NMI is logged and the machine
+/// carries on
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+	let reason = unsafe { Port::<u8>::new(SYSTEM_CONTROL_B).read() };
+	let error = match reason {
+		reason if reason & NMI_PARITY_ERROR != 0 => "memory parity or system error (SERR#)",
+		reason if reason & NMI_CHANNEL_CHECK != 0 => "I/O channel check (IOCHK#)",
+		_ => {
+			klog!(Crit, "non-maskable interrupt at {:?}, no chipset error reported", stack_frame.instruction_pointer);
+			return;
+		}
+	};
+	panic!("HARDWARE ERROR: NMI, {} (port 0x61 = {:#04x})\n{:#?}", error, reason, stack_frame);
+}
+
+/// Double fault exception handler - critical system error
+extern "x86-interrupt" fn double_fault_handler(
+	stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
+	panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+}
+
+/// Whether the processor has the machine check architecture's banks
+fn machine_check_architecture() -> bool {
+	cpu::has(Feature::Mce) && cpu::has(Feature::Mca)
+}
+
+/// Number of machine check banks
+fn machine_check_banks() -> u32 {
+	(unsafe { Msr::new(IA32_MCG_CAP).read() } & MCG_BANK_COUNT) as u32
+}
+
+/// Turn on machine check exceptions, with every error reported by every
+/// bank, after clearing what the firmware left in them
+pub fn init_machine_check() {
+	if !machine_check_architecture() {
+		return;
+	}
+	for bank in 0..machine_check_banks() {
+		unsafe {
+			// Bank 0's control register is left to the firmware on older CPUs
+			if bank > 0 {
+				Msr::new(IA32_MC0_CTL + 4 * bank).write(u64::MAX);
+			}
+			Msr::new(IA32_MC0_CTL + 4 * bank + 1).write(0);
+		}
+	}
+	unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION)) };
+}
+
+/// An error logged in a machine check bank's status register
+struct BankStatus(u64);
+
+impl BankStatus {
+	fn valid(&self) -> bool {
+		self.0 & MCI_VALID != 0
+	}
+
+	/// Whether the error leaves the processor unable to carry on
+	fn fatal(&self) -> bool {
+		self.0 & (MCI_UNCORRECTED | MCI_CONTEXT_CORRUPT) != 0
+	}
+}
+
+impl fmt::Display for BankStatus {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let status = self.0;
+		write!(f, "MCA code {:#06x}, model code {:#06x}", status & 0xffff, status >> 16 & 0xffff)?;
+		for (bit, name) in [
+			(MCI_UNCORRECTED, "uncorrected"),
+			(MCI_CONTEXT_CORRUPT, "context corrupt"),
+			(MCI_OVERFLOW, "overflow"),
+		] {
+			if status & bit != 0 {
+				write!(f, ", {}", name)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Machine check handler: log every bank with an error, and carry on if all
+/// were corrected and the interrupted code can resume, or else panic with
+/// the hardware error report
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) {
+	if !machine_check_architecture() {
+		fault(&MACHINE_CHECK, &stack_frame, format_args!(", no error banks to report from"));
+	}
+	let global = unsafe { Msr::new(IA32_MCG_STATUS).read() };
+	let mut fatal = global & MCG_RIPV == 0;
+	for bank in 0..machine_check_banks() {
+		let status = BankStatus(unsafe { Msr::new(IA32_MC0_CTL + 4 * bank + 1).read() });
+		if !status.valid() {
+			continue;
+		}
+		fatal |= status.fatal();
+		klog!(Emerg, "machine check: bank {}: {}", bank, status);
+		if status.0 & MCI_ADDR_VALID != 0 {
+			klog!(Emerg, "machine check: bank {}: address {:#x}", bank, unsafe { Msr::new(IA32_MC0_CTL + 4 * bank + 2).read() });
+		}
+		if status.0 & MCI_MISC_VALID != 0 {
+			klog!(Emerg, "machine check: bank {}: misc {:#x}", bank, unsafe { Msr::new(IA32_MC0_CTL + 4 * bank + 3).read() });
+		}
+		if !fatal {
+			unsafe { Msr::new(IA32_MC0_CTL + 4 * bank + 1).write(0) };
+		}
+	}
+	if fatal {
+		fault(&MACHINE_CHECK, &stack_frame, format_args!(", global status {:#x}, see the log for the banks", global));
+	}
+	// Another machine check while this one is in progress shuts the CPU down
+	unsafe { Msr::new(IA32_MCG_STATUS).write(global & !MCG_MCIP) };
+	klog!(Warn, "machine check at {:?} was corrected, continuing", stack_frame.instruction_pointer);
+}
+
+/// Page fault exception handler
+extern "x86-interrupt" fn page_fault_handler(
+	stack_frame: InterruptStackFrame,
+	error_code: PageFaultErrorCode,
+) {
+	fault(&PAGE_FAULT, &stack_frame, format_args!(" accessing {:?}, {:?}", Cr2::read(), error_code));
+}
+
+/// Test describing machine check bank status
+#[test_case]
+fn test_bank_status() {
+	use alloc::format;
+
+	let corrected = BankStatus(MCI_VALID | 0x0003_0136);
+	assert!(corrected.valid() && !corrected.fatal());
+	assert_eq!(format!("{}", corrected), "MCA code 0x0136, model code 0x0003");
+	let uncorrected = BankStatus(MCI_VALID | MCI_UNCORRECTED | MCI_OVERFLOW | 0x0150);
+	assert!(uncorrected.fatal());
+	assert_eq!(format!("{}", uncorrected), "MCA code 0x0150, model code 0x0000, uncorrected, overflow");
+}
+
+/// Test describing selector error codes
+#[test_case]
+fn test_selector_error() {
+	use alloc::format;
+
+	assert_eq!(format!("{}", SelectorError(0)), "error code 0");
+	assert_eq!(format!("{}", SelectorError(0x18)), "error code 0x18: GDT entry 3");
+	assert_eq!(format!("{}", SelectorError(0x6b)), "error code 0x6b: IDT entry 13, external");
+}
@@ -0,0 +1,552 @@
+use crate::console::Console;
+use crate::memory;
+use crate::pci::{self, Bar};
+use crate::vga_buffer::{self, Color};
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::sync::Mutex;
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+/// Bochs/QEMU display interface (BGA) index and data ports
+const BGA_INDEX: u16 = 0x1ce;
+const BGA_DATA: u16 = 0x1cf;
+/// BGA registers
+const BGA_ID: u16 = 0;
+const BGA_XRES: u16 = 1;
+const BGA_YRES: u16 = 2;
+const BGA_BPP: u16 = 3;
+const BGA_ENABLE: u16 = 4;
+/// BGA versions with 32-bit pixels and a linear framebuffer
+const BGA_VERSIONS: core::ops::RangeInclusive<u16> = 0xb0c2..=0xb0c5;
+/// Enable register bits
+const BGA_ENABLED: u16 = 0x01;
+const BGA_LFB_ENABLED: u16 = 0x40;
+/// Bits per pixel the console draws with
+const BGA_BPP_32: u16 = 32;
+
+/// PCI vendor and device IDs of adapters with the BGA interface: the
+/// QEMU/Bochs standard VGA and VirtualBox's adapter
+const BGA_DEVICES: [(u16, u16); 2] = [(0x1234, 0x1111), (0x80ee, 0xbeef)];
+
+/// Resolution used when the kernel command line does not pick one
+pub const DEFAULT_RESOLUTION: (usize, usize) = (1024, 768);
+/// Largest resolution the consoles can fill with 8x16 glyphs
+const MAX_RESOLUTION: (usize, usize) = (vga_buffer::MAX_COLUMNS * 8, vga_buffer::MAX_ROWS * 16);
+
+/// VGA sequencer and graphics controller index ports; data is at index + 1
+const SEQ_INDEX: u16 = 0x3c4;
+const GC_INDEX: u16 = 0x3ce;
+/// Physical address of VGA memory while plane 2 is mapped for reading
+const VGA_PLANE_MEMORY: u64 = 0xa0000;
+/// Bytes of plane 2 reserved for each glyph
+const VGA_GLYPH_STRIDE: usize = 32;
+/// Glyph height of the 80x25 text mode font
+const VGA_FONT_HEIGHT: usize = 16;
+
+/// PSF1 and PSF2 magic numbers
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+/// PSF1 mode bit for a font with 512 glyphs
+const PSF1_MODE512: u8 = 0x01;
+
+/// Scanlines at the bottom of a cell covered by the cursor
+const CURSOR_HEIGHT: usize = 2;
+
+/// RGB values of the 16 VGA colors
+const PALETTE: [u32; 16] = [
+	0x000000, 0x0000aa, 0x00aa00, 0x00aaaa, 0xaa0000, 0xaa00aa, 0xaa5500, 0xaaaaaa,
+	0x555555, 0x5555ff, 0x55ff55, 0x55ffff, 0xff5555, 0xff55ff, 0xffff55, 0xffffff,
+];
+
+/// A VGA color as a `0x00RRGGBB` pixel
+pub fn rgb(color: Color) -> u32 {
+	PALETTE[color as usize]
+}
+
+/// A linear framebuffer of 32-bit `0x00RRGGBB` pixels
+pub struct Framebuffer {
+	pixels: *mut u32,
+	width: usize,
+	height: usize,
+	/// Pixels from the start of one row to the start of the next
+	stride: usize,
+}
+
+// Only ever reached through the writer's lock
+unsafe impl Send for Framebuffer {}
+
+impl Framebuffer {
+	/// Wrap framebuffer memory
+	///
+	/// # Safety
+	/// `pixels` must be valid for `stride * height` pixels for as long as
+	/// the framebuffer is in use.
+	pub unsafe fn new(pixels: *mut u32, width: usize, height: usize, stride: usize) -> Framebuffer {
+		Framebuffer { pixels, width, height, stride }
+	}
+
+	/// Width and height in pixels
+	pub fn size(&self) -> (usize, usize) {
+		(self.width, self.height)
+	}
+
+	/// Set one pixel, ignoring positions off the edge
+	pub fn put_pixel(&mut self, x: usize, y: usize, color: u32) {
+		if x < self.width && y < self.height {
+			unsafe { self.pixels.add(y * self.stride + x).write_volatile(color) };
+		}
+	}
+
+	/// Fill a rectangle, clipped to the framebuffer
+	pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+		for y in y..(y + height).min(self.height) {
+			for x in x..(x + width).min(self.width) {
+				unsafe { self.pixels.add(y * self.stride + x).write_volatile(color) };
+			}
+		}
+	}
+
+	/// Copy rows of pixels from another framebuffer of the same width
+	pub fn copy_rows_from(&mut self, source: &Framebuffer, rows: Range<usize>) {
+		let width = self.width.min(source.width);
+		for y in rows.start..rows.end.min(self.height).min(source.height) {
+			unsafe {
+				core::ptr::copy_nonoverlapping(source.pixels.add(y * source.stride), self.pixels.add(y * self.stride), width);
+			}
+		}
+	}
+
+	/// Copy `count` rows of pixels starting at row `from` to start at row `to`
+	pub fn copy_rows(&mut self, from: usize, to: usize, count: usize) {
+		let count = count.min(self.height.saturating_sub(from.max(to)));
+		unsafe {
+			core::ptr::copy(self.pixels.add(from * self.stride), self.pixels.add(to * self.stride), count * self.stride);
+		}
+	}
+}
+
+/// A bitmap font in PC Screen Font format (PSF1 or PSF2), with glyphs in
+/// code page 437 order
+pub struct Font {
+	data: &'static [u8],
+	/// Offset of the first glyph
+	offset: usize,
+	glyph_count: usize,
+	/// Bytes per glyph
+	glyph_size: usize,
+	width: usize,
+	height: usize,
+}
+
+impl Font {
+	/// Parse a PSF1 or PSF2 font, returning `None` if it is malformed
+	pub fn parse(data: &'static [u8]) -> Option<Font> {
+		let font = if data.starts_with(&PSF1_MAGIC) {
+			let mode = *data.get(2)?;
+			let height = *data.get(3)? as usize;
+			let glyph_count = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+			Font { data, offset: 4, glyph_count, glyph_size: height, width: 8, height }
+		} else if data.starts_with(&PSF2_MAGIC) {
+			// magic, version, header size, flags, glyph count, glyph size, height, width
+			let field = |index: usize| {
+				let bytes = data.get(index * 4..index * 4 + 4)?;
+				Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+			};
+			Font {
+				data,
+				offset: field(2)?,
+				glyph_count: field(4)?,
+				glyph_size: field(5)?,
+				height: field(6)?,
+				width: field(7)?,
+			}
+		} else {
+			return None;
+		};
+		let end = font.offset.checked_add(font.glyph_count.checked_mul(font.glyph_size)?)?;
+		let fits = font.glyph_size >= font.height * font.width.div_ceil(8);
+		(font.width > 0 && font.height > 0 && font.glyph_count > 0 && fits && end <= data.len()).then_some(font)
+	}
+
+	/// Width and height of a glyph in pixels
+	pub fn size(&self) -> (usize, usize) {
+		(self.width, self.height)
+	}
+
+	/// Whether pixel `(x, y)` of the glyph for `code` is set; codes past the
+	/// end of the font are blank
+	fn pixel(&self, code: u8, x: usize, y: usize) -> bool {
+		if code as usize >= self.glyph_count {
+			return false;
+		}
+		let row = self.offset + code as usize * self.glyph_size + y * self.width.div_ceil(8);
+		self.data[row + x / 8] & (0x80 >> (x % 8)) != 0
+	}
+}
+
+/// Read a VGA sequencer or graphics controller register
+fn vga_register_read(index_port: u16, index: u8) -> u8 {
+	unsafe {
+		Port::<u8>::new(index_port).write(index);
+		Port::<u8>::new(index_port + 1).read()
+	}
+}
+
+/// Write a VGA sequencer or graphics controller register
+fn vga_register_write(index_port: u16, index: u8, value: u8) {
+	unsafe {
+		Port::<u8>::new(index_port).write(index);
+		Port::<u8>::new(index_port + 1).write(value);
+	}
+}
+
+/// Copy the VGA card's own text mode font out of plane 2 into a PSF1 font
+///
+/// Only works while the card is still in text mode. Needs the heap.
+fn vga_font() -> Option<Font> {
+	let plane = memory::phys_to_virt(PhysAddr::new(VGA_PLANE_MEMORY))?.as_ptr::<u8>();
+	let mut image = vec![PSF1_MAGIC[0], PSF1_MAGIC[1], 0, VGA_FONT_HEIGHT as u8];
+	image.reserve(256 * VGA_FONT_HEIGHT);
+	// Map plane 2 alone at 0xa0000, without odd/even addressing
+	let registers = [(SEQ_INDEX, 2, 0x04), (SEQ_INDEX, 4, 0x07), (GC_INDEX, 4, 0x02), (GC_INDEX, 5, 0x00), (GC_INDEX, 6, 0x04)];
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let saved = registers.map(|(port, index, _)| vga_register_read(port, index));
+		for (port, index, value) in registers {
+			vga_register_write(port, index, value);
+		}
+		for glyph in 0..256 {
+			for line in 0..VGA_FONT_HEIGHT {
+				image.push(unsafe { plane.add(glyph * VGA_GLYPH_STRIDE + line).read_volatile() });
+			}
+		}
+		for ((port, index, _), value) in registers.into_iter().zip(saved).rev() {
+			vga_register_write(port, index, value);
+		}
+	});
+	Font::parse(Box::leak(image.into_boxed_slice()))
+}
+
+/// The framebuffer console's pixels
+///
+/// Everything is drawn into an off-screen copy, and the rows that changed
+/// are copied to the framebuffer on the next timer tick, so a burst of
+/// output or fast scrolling reaches the screen as whole frames instead of
+/// tearing and flickering.
+pub struct Surface {
+	/// The framebuffer shown on screen
+	front: Framebuffer,
+	/// The off-screen copy everything is drawn into
+	back: Framebuffer,
+	/// Pixel rows of `back` changed since they were last presented
+	dirty: Option<Range<usize>>,
+}
+
+impl Surface {
+	/// Double-buffer `front`, drawing into `back` of the same size
+	fn new(front: Framebuffer, back: Framebuffer) -> Surface {
+		let height = front.height;
+		Surface { front, back, dirty: Some(0..height) }
+	}
+
+	/// Note pixel rows that need presenting
+	fn touch(&mut self, rows: Range<usize>) {
+		self.dirty = Some(match self.dirty.take() {
+			Some(dirty) => dirty.start.min(rows.start)..dirty.end.max(rows.end),
+			None => rows,
+		});
+	}
+
+	/// Copy the changed rows to the screen
+	fn present(&mut self) {
+		if let Some(rows) = self.dirty.take() {
+			self.front.copy_rows_from(&self.back, rows);
+		}
+	}
+}
+
+/// Pixels of the framebuffer console; `None` in text mode
+static SURFACE: Mutex<Option<Surface>> = Mutex::new(None);
+/// Whether a full-screen program has the surface to itself
+static EXCLUSIVE: AtomicBool = AtomicBool::new(false);
+
+/// Copy what changed off-screen to the framebuffer
+///
+/// Called on every timer tick; programs holding the surface can call it
+/// to show a frame straight away.
+pub fn present() {
+	if let Some(mut surface) = SURFACE.try_lock() {
+		if let Some(surface) = surface.as_mut() {
+			surface.present();
+		}
+	}
+}
+
+/// A full-screen program's hold on the framebuffer: the console stops
+/// drawing while it exists and repaints itself once it is dropped
+pub struct ExclusiveSurface(());
+
+impl ExclusiveSurface {
+	/// Draw on the off-screen pixels, which are shown on the next tick
+	pub fn draw<R>(&mut self, f: impl FnOnce(&mut Framebuffer) -> R) -> R {
+		x86_64::instructions::interrupts::without_interrupts(|| {
+			let mut surface = SURFACE.lock();
+			let surface = surface.as_mut().expect("exclusive surface exists");
+			let result = f(&mut surface.back);
+			surface.touch(0..surface.back.height);
+			result
+		})
+	}
+}
+
+impl Drop for ExclusiveSurface {
+	fn drop(&mut self) {
+		EXCLUSIVE.store(false, Ordering::Release);
+		vga_buffer::redraw();
+	}
+}
+
+/// Take the screen's pixels for a full-screen program, or `None` in text
+/// mode or while another program has them
+pub fn acquire_surface() -> Option<ExclusiveSurface> {
+	let available = x86_64::instructions::interrupts::without_interrupts(|| SURFACE.lock().is_some());
+	if !available || EXCLUSIVE.swap(true, Ordering::Acquire) {
+		return None;
+	}
+	Some(ExclusiveSurface(()))
+}
+
+/// Give the surface back to the console for the panic screen, whatever
+/// held it
+///
+/// # Safety
+///
+/// This breaks the surface lock, so nothing else may run afterwards.
+pub unsafe fn take_over() {
+	SURFACE.force_unlock();
+	EXCLUSIVE.store(false, Ordering::Release);
+}
+
+/// A text console drawn on the framebuffer surface with a bitmap font
+pub struct FramebufferConsole {
+	font: Font,
+	rows: usize,
+	cols: usize,
+	/// Character and colors of every cell, for redrawing around the cursor
+	cells: Vec<(u8, Color, Color)>,
+	cursor: Option<(usize, usize)>,
+}
+
+impl FramebufferConsole {
+	/// A console filling a `width` by `height` surface with as many cells as
+	/// the font allows
+	pub fn new(font: Font, width: usize, height: usize) -> FramebufferConsole {
+		let (rows, cols) = (height / font.height, width / font.width);
+		with_surface(|surface| {
+			surface.back.fill_rect(0, 0, width, height, rgb(Color::Black));
+			surface.touch(0..height);
+		});
+		FramebufferConsole {
+			font,
+			rows,
+			cols,
+			cells: vec![(b' ', Color::LightGray, Color::Black); rows * cols],
+			cursor: None,
+		}
+	}
+
+	/// Paint a cell's glyph, with the cursor underneath it if `cursor`
+	fn render(&self, row: usize, col: usize, cursor: bool) {
+		let (byte, foreground, background) = self.cells[row * self.cols + col];
+		let (foreground, background) = (rgb(foreground), rgb(background));
+		let (left, top) = (col * self.font.width, row * self.font.height);
+		with_surface(|surface| {
+			for y in 0..self.font.height {
+				let underline = cursor && y + CURSOR_HEIGHT >= self.font.height;
+				for x in 0..self.font.width {
+					let lit = underline || self.font.pixel(byte, x, y);
+					surface.back.put_pixel(left + x, top + y, if lit { foreground } else { background });
+				}
+			}
+			surface.touch(top..top + self.font.height);
+		});
+	}
+}
+
+/// Run `f` on the surface unless there is none or a program has it
+fn with_surface(f: impl FnOnce(&mut Surface)) {
+	if EXCLUSIVE.load(Ordering::Acquire) {
+		return;
+	}
+	if let Some(surface) = SURFACE.lock().as_mut() {
+		f(surface);
+	}
+}
+
+impl Console for FramebufferConsole {
+	fn size(&self) -> (usize, usize) {
+		(self.rows, self.cols)
+	}
+
+	fn draw(&mut self, row: usize, col: usize, byte: u8, foreground: Color, background: Color) {
+		if row < self.rows && col < self.cols {
+			self.cells[row * self.cols + col] = (byte, foreground, background);
+			self.render(row, col, self.cursor == Some((row, col)));
+		}
+	}
+
+	fn scroll_up(&mut self, rows: Range<usize>) {
+		let rows = rows.start..rows.end.min(self.rows);
+		if rows.len() < 2 {
+			return;
+		}
+		// The cursor would move up with the pixels
+		self.set_cursor(None);
+		self.cells.copy_within((rows.start + 1) * self.cols..rows.end * self.cols, rows.start * self.cols);
+		let height = self.font.height;
+		with_surface(|surface| {
+			surface.back.copy_rows((rows.start + 1) * height, rows.start * height, (rows.len() - 1) * height);
+			surface.touch(rows.start * height..rows.end * height);
+		});
+	}
+
+	fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+		let cursor = cursor.filter(|&(row, col)| row < self.rows && col < self.cols);
+		if cursor == self.cursor {
+			return;
+		}
+		if let Some((row, col)) = self.cursor.take() {
+			self.render(row, col, false);
+		}
+		if let Some((row, col)) = cursor {
+			self.render(row, col, true);
+		}
+		self.cursor = cursor;
+	}
+}
+
+/// Read a BGA register
+fn bga_read(register: u16) -> u16 {
+	unsafe {
+		Port::<u16>::new(BGA_INDEX).write(register);
+		Port::<u16>::new(BGA_DATA).read()
+	}
+}
+
+/// Write a BGA register
+fn bga_write(register: u16, value: u16) {
+	unsafe {
+		Port::<u16>::new(BGA_INDEX).write(register);
+		Port::<u16>::new(BGA_DATA).write(value);
+	}
+}
+
+/// Parse a `WIDTHxHEIGHT` resolution such as `1280x1024`
+pub fn parse_resolution(text: &str) -> Option<(usize, usize)> {
+	let (width, height) = text.split_once('x')?;
+	let resolution = (width.parse().ok()?, height.parse().ok()?);
+	(resolution.0 > 0 && resolution.1 > 0).then_some(resolution)
+}
+
+/// Switch the console to a linear framebuffer at `resolution` (or the
+/// default), returning the resolution set, or `None` if there is no
+/// adapter with the BGA interface and the screen stays in text mode
+///
+/// Needs the heap and PCI enumeration.
+pub fn init(resolution: Option<(usize, usize)>) -> Option<(usize, usize)> {
+	let (width, height) = resolution.unwrap_or(DEFAULT_RESOLUTION);
+	let (width, height) = (width.min(MAX_RESOLUTION.0), height.min(MAX_RESOLUTION.1));
+	let size = (width * height * 4) as u64;
+	let address = pci::devices().iter()
+		.filter(|device| BGA_DEVICES.contains(&(device.vendor_id, device.device_id)))
+		.find_map(|device| match device.bars.first() {
+			Some(&Bar::Memory { address, size: bar_size, .. }) if bar_size >= size => Some(address),
+			_ => None,
+		})?;
+	if !BGA_VERSIONS.contains(&bga_read(BGA_ID)) {
+		return None;
+	}
+	let font = vga_font()?;
+	let pixels = memory::map_mmio(PhysAddr::new(address), size)?;
+	let back = memory::map_memory(size)?;
+
+	bga_write(BGA_ENABLE, 0);
+	bga_write(BGA_XRES, width as u16);
+	bga_write(BGA_YRES, height as u16);
+	bga_write(BGA_BPP, BGA_BPP_32);
+	bga_write(BGA_ENABLE, BGA_ENABLED | BGA_LFB_ENABLED);
+	// The adapter may have settled on a smaller mode
+	let (width, height) = (width.min(bga_read(BGA_XRES) as usize), height.min(bga_read(BGA_YRES) as usize));
+
+	let front = unsafe { Framebuffer::new(pixels.as_mut_ptr(), width, height, width) };
+	let back = unsafe { Framebuffer::new(back.as_mut_ptr(), width, height, width) };
+	x86_64::instructions::interrupts::without_interrupts(|| *SURFACE.lock() = Some(Surface::new(front, back)));
+	vga_buffer::set_display(Box::new(FramebufferConsole::new(font, width, height)));
+	Some((width, height))
+}
+
+/// Test PSF parsing, drawing glyphs, the cursor, and scrolling off-screen,
+/// presenting, and exclusive access
+#[test_case]
+fn test_framebuffer_console() {
+	// A PSF2 font of two 8x4 glyphs: 0 is blank and 1 has two corner pixels
+	let mut psf2 = Vec::new();
+	for field in [0, 32, 0, 2, 4, 4, 8] {
+		psf2.extend_from_slice(&u32::to_le_bytes(field));
+	}
+	let mut image = Vec::from(PSF2_MAGIC);
+	image.extend(psf2);
+	image.extend([0, 0, 0, 0, 0x80, 0, 0, 0x01]);
+	let font = Font::parse(Box::leak(image.into_boxed_slice())).expect("valid PSF2 font");
+	assert_eq!(font.size(), (8, 4));
+	assert!(Font::parse(&[0x36, 0x04, 0, 16, 0]).is_none());
+	assert!(Font::parse(b"not a font").is_none());
+	assert_eq!(parse_resolution("1280x1024"), Some((1280, 1024)));
+	assert_eq!(parse_resolution("0x600"), None);
+
+	// Two rows of four cells
+	let front: &'static mut [u32] = Box::leak(vec![0xdead; 32 * 8].into_boxed_slice());
+	let back: &'static mut [u32] = Box::leak(vec![0; 32 * 8].into_boxed_slice());
+	let (front, back) = (front.as_mut_ptr(), back.as_mut_ptr());
+	let pixel = |buffer: *mut u32, x: usize, y: usize| unsafe { *buffer.add(y * 32 + x) };
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let surface = unsafe { Surface::new(Framebuffer::new(front, 32, 8, 32), Framebuffer::new(back, 32, 8, 32)) };
+		*SURFACE.lock() = Some(surface);
+		let mut console = FramebufferConsole::new(font, 32, 8);
+		assert_eq!(console.size(), (2, 4));
+		assert_eq!(pixel(back, 31, 7), rgb(Color::Black));
+		assert_eq!(pixel(front, 31, 7), 0xdead);
+
+		console.draw(1, 1, 1, Color::White, Color::Blue);
+		assert_eq!(pixel(back, 8, 4), rgb(Color::White));
+		assert_eq!(pixel(back, 9, 4), rgb(Color::Blue));
+		assert_eq!(pixel(back, 15, 7), rgb(Color::White));
+		console.draw(1, 2, 9, Color::White, Color::Blue);
+		assert_eq!(pixel(back, 16, 4), rgb(Color::Blue));
+
+		console.set_cursor(Some((0, 0)));
+		assert_eq!(pixel(back, 3, 3), rgb(Color::LightGray));
+		console.scroll_up(0..2);
+		assert_eq!(pixel(back, 3, 3), rgb(Color::Black));
+		assert_eq!(pixel(back, 8, 0), rgb(Color::White));
+		assert_eq!(console.cells[1], (1, Color::White, Color::Blue));
+		present();
+		assert_eq!(pixel(front, 8, 0), rgb(Color::White));
+		assert_eq!(pixel(front, 31, 7), rgb(Color::Black));
+
+		// The console leaves the pixels alone while a program holds them
+		let mut exclusive = acquire_surface().expect("surface is free");
+		assert!(acquire_surface().is_none());
+		exclusive.draw(|framebuffer| framebuffer.fill_rect(0, 0, 32, 8, rgb(Color::Red)));
+		console.draw(0, 0, 1, Color::White, Color::Blue);
+		assert_eq!(pixel(back, 0, 0), rgb(Color::Red));
+		assert_eq!(console.cells[0], (1, Color::White, Color::Blue));
+		present();
+		assert_eq!(pixel(front, 0, 0), rgb(Color::Red));
+		drop(exclusive);
+		console.draw(0, 0, 1, Color::White, Color::Blue);
+		assert_eq!(pixel(back, 0, 0), rgb(Color::White));
+		*SURFACE.lock() = None;
+	});
+}
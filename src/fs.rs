@@ -1,17 +1,23 @@
 use alloc::{collections::BTreeMap, string::String, vec::Vec, format};
 use alloc::string::ToString;
-use spin::Mutex;
+use crate::audit;
+use crate::process::Credentials;
+use crate::task::sync::Mutex;
 
 /// File system error types
 #[derive(Debug, Clone, Copy)]
 pub enum FsError {
 	NotFound,
+	/// The permission bits do not allow it
 	PermissionDenied,
+	/// Only the owner or root may do it
+	NotPermitted,
 	AlreadyExists,
 	IsDirectory,
 	NotDirectory,
 	InvalidPath,
 	IoError,
+	DirectoryNotEmpty,
 }
 
 /// File types
@@ -23,36 +29,179 @@ pub enum FileType {
 	Device,
 }
 
+/// Permission bits asked for by `FileSystem::access`, as in the `rwx` of a mode
+pub const MAY_READ: u32 = 0o4;
+pub const MAY_WRITE: u32 = 0o2;
+pub const MAY_EXEC: u32 = 0o1;
+
+/// Directory bit that lets only an entry's owner, or the directory's, remove it
+pub const S_ISVTX: u32 = 0o1000;
+
 /// File metadata
 #[derive(Debug, Clone)]
 pub struct FileMetadata {
 	pub file_type: FileType,
 	pub size: usize,
 	pub permissions: u32,
+	/// User and group the file belongs to
+	pub owner: u32,
+	pub group: u32,
 	pub created: u64,
 	pub modified: u64,
 	pub accessed: u64,
 }
 
+impl FileMetadata {
+	/// Whether `who` may do `wanted`, some of `MAY_READ`, `MAY_WRITE` and
+	/// `MAY_EXEC`: the owner's bits apply to the owner, the group's to its
+	/// members, and the rest to everyone else. Root may do anything but
+	/// execute a file no one can.
+	pub fn permits(&self, who: &Credentials, wanted: u32) -> bool {
+		if who.is_root() {
+			let executable = self.file_type == FileType::Directory || self.permissions & 0o111 != 0;
+			return wanted & MAY_EXEC == 0 || executable;
+		}
+		let shift = if who.uid == self.owner {
+			6
+		} else if who.in_group(self.group) {
+			3
+		} else {
+			0
+		};
+		(self.permissions >> shift) & wanted == wanted
+	}
+}
+
+/// Reads a character device: fills `buffer` from the stream at `position`,
+/// which it advances, returning the bytes read
+pub type DeviceRead = fn(position: &mut usize, buffer: &mut [u8]) -> usize;
+
 /// In-memory file representation
 #[derive(Debug, Clone)]
 pub struct File {
 	pub metadata: FileMetadata,
 	pub data: Vec<u8>,
+	/// How a device file is read, in place of `data`
+	pub device: Option<DeviceRead>,
 }
 
+/// Normalize `path` against `cwd`, resolving `.`, `..`, and repeated slashes
+pub fn normalize_path(cwd: &str, path: &str) -> String {
+	let mut components: Vec<&str> = Vec::new();
+	let joined = if path.starts_with('/') { [path, ""] } else { [cwd, path] };
+	for part in joined.iter().flat_map(|p| p.split('/')) {
+		match part {
+			"" | "." => {}
+			".." => {
+				components.pop();
+			}
+			part => components.push(part),
+		}
+	}
+	let mut result = String::new();
+	for component in &components {
+		result.push('/');
+		result.push_str(component);
+	}
+	if result.is_empty() {
+		result.push('/');
+	}
+	result
+}
+
+/// Parent directory of a normalized absolute path (`None` for the root)
+pub fn parent_path(path: &str) -> Option<&str> {
+	if path == "/" {
+		return None;
+	}
+	match path.rfind('/') {
+		Some(0) => Some("/"),
+		Some(i) => Some(&path[..i]),
+		None => None,
+	}
+}
+
+/// Where `path`, normalized and absolute, is for a process whose root
+/// directory is `root`
+pub fn under_root(root: &str, path: &str) -> String {
+	match (root, path) {
+		("/", path) => path.to_string(),
+		(root, "/") => root.to_string(),
+		(root, path) => format!("{}{}", root, path),
+	}
+}
+
+/// Pass on the result of a permission check, auditing a refusal of `who`
+/// trying to `action` `path`
+fn audited(result: Result<(), FsError>, who: &Credentials, action: &str, path: &str) -> Result<(), FsError> {
+	if let Err(err @ (FsError::PermissionDenied | FsError::NotPermitted)) = result {
+		audit::record(audit::Kind::Denied, audit::Actor::with(who), format_args!("{} {}: {:?}", action, path, err));
+	}
+	result
+}
+
+/// Final component of a path
+pub fn file_name(path: &str) -> &str {
+	path.trim_end_matches('/').rsplit('/').next().unwrap_or(path)
+}
+
+/// Open for reading only
+pub const O_RDONLY: u32 = 0o0;
+/// Open for writing only
+pub const O_WRONLY: u32 = 0o1;
+/// Open for reading and writing
+pub const O_RDWR: u32 = 0o2;
+/// Mask for the access mode bits
+pub const O_ACCMODE: u32 = 0o3;
+/// Create the file if it does not exist
+pub const O_CREAT: u32 = 0o100;
+/// Truncate the file to zero length on open
+pub const O_TRUNC: u32 = 0o1000;
+/// Every write goes to the end of the file
+pub const O_APPEND: u32 = 0o2000;
+
+/// Accounts there are at first boot: `name:x:uid:gid:comment:home:shell`,
+/// with their passwords in `/etc/shadow`
+const DEFAULT_PASSWD: &[u8] = b"root:x:0:0:root:/root:/bin/sh\n\
+guest:x:1000:1000:Guest:/home/guest:/bin/sh\n";
+
+/// Groups there are at first boot: `name:x:gid:member,...`, beyond each
+/// account's own group in `/etc/passwd`
+const DEFAULT_GROUP: &[u8] = b"root:x:0:\n\
+wheel:x:10:root\n\
+users:x:100:guest\n\
+guest:x:1000:\n";
+
+/// Startup script the shell sources for interactive settings such as aliases
+const DEFAULT_SHELLRC: &[u8] = b"# Sourced by the shell at startup\n\
+alias ll='ls -l'\n\
+alias la='ls -la'\n\
+alias ..='cd ..'\n";
+
 /// File descriptor
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct FileDescriptor(pub usize);
 
-/// File handle with position tracking
+/// Open file description with position tracking
 #[derive(Debug)]
 pub struct FileHandle {
-	pub file: File,
+	pub path: String,
 	pub position: usize,
 	pub flags: u32,
 }
 
+impl FileHandle {
+	/// Whether the handle was opened with read access
+	pub fn readable(&self) -> bool {
+		self.flags & O_ACCMODE != O_WRONLY
+	}
+
+	/// Whether the handle was opened with write access
+	pub fn writable(&self) -> bool {
+		self.flags & O_ACCMODE != O_RDONLY
+	}
+}
+
 /// Simple in-memory file system
 pub struct FileSystem {
 	files: BTreeMap<String, File>,
@@ -76,33 +225,50 @@ impl FileSystem {
 		fs.create_directory("/bin".to_string()).unwrap();
 		fs.create_directory("/etc".to_string()).unwrap();
 		fs.create_directory("/home".to_string()).unwrap();
+		fs.create_directory("/root".to_string()).unwrap();
 		fs.create_directory("/tmp".to_string()).unwrap();
 		fs.create_directory("/usr".to_string()).unwrap();
 		fs.create_directory("/var".to_string()).unwrap();
 
-		// Create /etc/passwd file
-		let passwd_content = b"root:x:0:0:root:/root:/bin/sh\n";
-		fs.create_file("/etc/passwd".to_string(), passwd_content.to_vec()).unwrap();
+		fs.create_file("/etc/passwd".to_string(), DEFAULT_PASSWD.to_vec()).unwrap();
+		fs.create_file("/etc/group".to_string(), DEFAULT_GROUP.to_vec()).unwrap();
+		fs.create_file("/etc/shellrc".to_string(), DEFAULT_SHELLRC.to_vec()).unwrap();
 
 		fs
 	}
 
+	/// Check that the parent of `path` exists and is a directory
+	fn check_parent(&self, path: &str) -> Result<(), FsError> {
+		match parent_path(path) {
+			None => Ok(()),
+			Some(parent) => match self.files.get(parent) {
+				Some(dir) if dir.metadata.file_type == FileType::Directory => Ok(()),
+				Some(_) => Err(FsError::NotDirectory),
+				None => Err(FsError::NotFound),
+			},
+		}
+	}
+
 	/// Create a new file
 	pub fn create_file(&mut self, path: String, data: Vec<u8>) -> Result<(), FsError> {
 		if self.files.contains_key(&path) {
 			return Err(FsError::AlreadyExists);
 		}
+		self.check_parent(&path)?;
 
 		let file = File {
 			metadata: FileMetadata {
 				file_type: FileType::Regular,
 				size: data.len(),
 				permissions: 0o644,
+				owner: 0,
+				group: 0,
 				created: 0, // TODO: Add real timestamp
 				modified: 0,
 				accessed: 0,
 			},
 			data,
+			device: None,
 		};
 
 		self.files.insert(path, file);
@@ -114,32 +280,81 @@ impl FileSystem {
 		if self.files.contains_key(&path) {
 			return Err(FsError::AlreadyExists);
 		}
+		self.check_parent(&path)?;
 
 		let file = File {
 			metadata: FileMetadata {
 				file_type: FileType::Directory,
 				size: 0,
 				permissions: 0o755,
+				owner: 0,
+				group: 0,
+				created: 0,
+				modified: 0,
+				accessed: 0,
+			},
+			data: Vec::new(),
+			device: None,
+		};
+
+		self.files.insert(path, file);
+		Ok(())
+	}
+
+	/// Create a read-only character device whose reads go to `read`
+	pub fn create_device(&mut self, path: String, read: DeviceRead) -> Result<(), FsError> {
+		if self.files.contains_key(&path) {
+			return Err(FsError::AlreadyExists);
+		}
+		self.check_parent(&path)?;
+
+		let file = File {
+			metadata: FileMetadata {
+				file_type: FileType::Device,
+				size: 0,
+				permissions: 0o444,
+				owner: 0,
+				group: 0,
 				created: 0,
 				modified: 0,
 				accessed: 0,
 			},
 			data: Vec::new(),
+			device: Some(read),
 		};
 
 		self.files.insert(path, file);
 		Ok(())
 	}
 
-	/// Open a file
+	/// Open a file, honoring `O_CREAT` and `O_TRUNC`
 	pub fn open(&mut self, path: &str, flags: u32) -> Result<FileDescriptor, FsError> {
-		let file = self.files.get(path).ok_or(FsError::NotFound)?.clone();
+		crate::trace_event!(Fs, "open {} flags {:#x}", path, flags);
+		if !self.files.contains_key(path) {
+			if flags & O_CREAT == 0 {
+				return Err(FsError::NotFound);
+			}
+			self.create_file(path.to_string(), Vec::new())?;
+		}
+
+		let file = self.files.get_mut(path).ok_or(FsError::NotFound)?;
+		let writable = flags & O_ACCMODE != O_RDONLY;
+		if file.metadata.file_type == FileType::Directory && writable {
+			return Err(FsError::IsDirectory);
+		}
+		if file.device.is_some() && writable {
+			return Err(FsError::PermissionDenied);
+		}
+		if flags & O_TRUNC != 0 && writable {
+			file.data.clear();
+			file.metadata.size = 0;
+		}
 
 		let fd = FileDescriptor(self.next_fd);
 		self.next_fd += 1;
 
 		let handle = FileHandle {
-			file,
+			path: path.to_string(),
 			position: 0,
 			flags,
 		};
@@ -150,20 +365,32 @@ impl FileSystem {
 
 	/// Close a file
 	pub fn close(&mut self, fd: FileDescriptor) -> Result<(), FsError> {
+		crate::trace_event!(Fs, "close fd {}", fd.0);
 		self.open_files.remove(&fd).ok_or(FsError::NotFound)?;
 		Ok(())
 	}
 
 	/// Read from a file
 	pub fn read(&mut self, fd: FileDescriptor, buffer: &mut [u8]) -> Result<usize, FsError> {
+		crate::trace_event!(Fs, "read fd {} len {}", fd.0, buffer.len());
 		let handle = self.open_files.get_mut(&fd).ok_or(FsError::NotFound)?;
+		if !handle.readable() {
+			return Err(FsError::PermissionDenied);
+		}
+		let file = self.files.get(&handle.path).ok_or(FsError::NotFound)?;
+		if file.metadata.file_type == FileType::Directory {
+			return Err(FsError::IsDirectory);
+		}
+		if let Some(read) = file.device {
+			return Ok(read(&mut handle.position, buffer));
+		}
 		
-		let available = handle.file.data.len().saturating_sub(handle.position);
+		let available = file.data.len().saturating_sub(handle.position);
 		let to_read = buffer.len().min(available);
 		
 		if to_read > 0 {
 			buffer[..to_read].copy_from_slice(
-				&handle.file.data[handle.position..handle.position + to_read]
+				&file.data[handle.position..handle.position + to_read]
 			);
 			handle.position += to_read;
 		}
@@ -171,17 +398,175 @@ impl FileSystem {
 		Ok(to_read)
 	}
 
-	/// Write to a file
+	/// Write to a file at the handle position (or the end with `O_APPEND`)
 	pub fn write(&mut self, fd: FileDescriptor, buffer: &[u8]) -> Result<usize, FsError> {
+		crate::trace_event!(Fs, "write fd {} len {}", fd.0, buffer.len());
 		let handle = self.open_files.get_mut(&fd).ok_or(FsError::NotFound)?;
-		
-		// For simplicity, append to the end of the file
-		handle.file.data.extend_from_slice(buffer);
-		handle.file.metadata.size = handle.file.data.len();
+		if !handle.writable() {
+			return Err(FsError::PermissionDenied);
+		}
+		let file = self.files.get_mut(&handle.path).ok_or(FsError::NotFound)?;
+		if file.device.is_some() {
+			return Err(FsError::PermissionDenied);
+		}
+
+		if handle.flags & O_APPEND != 0 {
+			handle.position = file.data.len();
+		}
+		let end = handle.position + buffer.len();
+		if file.data.len() < end {
+			file.data.resize(end, 0);
+		}
+		file.data[handle.position..end].copy_from_slice(buffer);
+		handle.position = end;
+		file.metadata.size = file.data.len();
 		
 		Ok(buffer.len())
 	}
 
+	/// Remove a file or an empty directory
+	pub fn remove(&mut self, path: &str) -> Result<(), FsError> {
+		crate::trace_event!(Fs, "remove {}", path);
+		let file = self.files.get(path).ok_or(FsError::NotFound)?;
+		if path == "/" {
+			return Err(FsError::PermissionDenied);
+		}
+		if file.metadata.file_type == FileType::Directory && !self.list_directory(path)?.is_empty() {
+			return Err(FsError::DirectoryNotEmpty);
+		}
+		self.files.remove(path);
+		Ok(())
+	}
+
+	/// Rename a file or directory (moving a directory's whole subtree)
+	pub fn rename(&mut self, from: &str, to: &str) -> Result<(), FsError> {
+		crate::trace_event!(Fs, "rename {} to {}", from, to);
+		let source_type = self.files.get(from).ok_or(FsError::NotFound)?.metadata.file_type;
+		if from == to {
+			return Ok(());
+		}
+		if to.starts_with(from) && to.as_bytes().get(from.len()) == Some(&b'/') {
+			return Err(FsError::InvalidPath);
+		}
+		self.check_parent(to)?;
+		if let Some(existing) = self.files.get(to) {
+			match (source_type, existing.metadata.file_type) {
+				(FileType::Directory, FileType::Directory) if self.list_directory(to)?.is_empty() => {}
+				(FileType::Directory, _) => return Err(FsError::AlreadyExists),
+				(_, FileType::Directory) => return Err(FsError::IsDirectory),
+				_ => {}
+			}
+		}
+
+		let prefix = format!("{}/", from);
+		let moved: Vec<String> = self.files.keys()
+			.filter(|key| key.as_str() == from || key.starts_with(&prefix))
+			.cloned()
+			.collect();
+		for old in moved {
+			let file = self.files.remove(&old).unwrap();
+			let new = format!("{}{}", to, &old[from.len()..]);
+			self.files.insert(new, file);
+		}
+		for handle in self.open_files.values_mut() {
+			if handle.path == from || handle.path.starts_with(&prefix) {
+				handle.path = format!("{}{}", to, &handle.path[from.len()..]);
+			}
+		}
+		Ok(())
+	}
+
+	/// Change the permission bits of a file or directory
+	pub fn set_permissions(&mut self, path: &str, permissions: u32) -> Result<(), FsError> {
+		let file = self.files.get_mut(path).ok_or(FsError::NotFound)?;
+		file.metadata.permissions = permissions & 0o7777;
+		Ok(())
+	}
+
+	/// Give a file or directory to another user and group
+	pub fn set_owner(&mut self, path: &str, owner: u32, group: u32) -> Result<(), FsError> {
+		let file = self.files.get_mut(path).ok_or(FsError::NotFound)?;
+		file.metadata.owner = owner;
+		file.metadata.group = group;
+		Ok(())
+	}
+
+	/// Check that `who` can reach `path`, which needs search permission on
+	/// every directory above it
+	fn search(&self, path: &str, who: &Credentials) -> Result<(), FsError> {
+		let mut dir = parent_path(path);
+		while let Some(path) = dir {
+			let metadata = &self.files.get(path).ok_or(FsError::NotFound)?.metadata;
+			if metadata.file_type != FileType::Directory {
+				return Err(FsError::NotDirectory);
+			}
+			if !metadata.permits(who, MAY_EXEC) {
+				return Err(FsError::PermissionDenied);
+			}
+			dir = parent_path(path);
+		}
+		Ok(())
+	}
+
+	/// Check that `path` exists and `who` may do `wanted` to it, some of
+	/// `MAY_READ`, `MAY_WRITE` and `MAY_EXEC`, or none to only ask whether it
+	/// can be reached
+	///
+	/// The methods here trust their caller, as the kernel's own setup does;
+	/// system calls check with this first on behalf of a process. Refusals
+	/// go in the audit log.
+	pub fn access(&self, path: &str, who: &Credentials, wanted: u32) -> Result<(), FsError> {
+		audited(self.check_access(path, who, wanted), who, "access", path)
+	}
+
+	fn check_access(&self, path: &str, who: &Credentials, wanted: u32) -> Result<(), FsError> {
+		self.search(path, who)?;
+		let file = self.files.get(path).ok_or(FsError::NotFound)?;
+		if !file.metadata.permits(who, wanted) {
+			return Err(FsError::PermissionDenied);
+		}
+		Ok(())
+	}
+
+	/// Check that `who` may add `path` to its directory or, if it exists,
+	/// take it out: that takes write and search permission on the directory,
+	/// and with its sticky bit set, owning the entry or the directory
+	pub fn access_entry(&self, path: &str, who: &Credentials) -> Result<(), FsError> {
+		audited(self.check_entry(path, who), who, "change entry", path)
+	}
+
+	fn check_entry(&self, path: &str, who: &Credentials) -> Result<(), FsError> {
+		let parent = parent_path(path).ok_or(FsError::PermissionDenied)?;
+		self.check_access(parent, who, MAY_WRITE | MAY_EXEC)?;
+		let dir = &self.files[parent].metadata;
+		if let Some(entry) = self.files.get(path) {
+			let owns = who.is_root() || who.uid == entry.metadata.owner || who.uid == dir.owner;
+			if dir.permissions & S_ISVTX != 0 && !owns {
+				return Err(FsError::PermissionDenied);
+			}
+		}
+		Ok(())
+	}
+
+	/// Check that `who` may change `path`'s mode or owner, as only its owner
+	/// and root may
+	pub fn access_owner(&self, path: &str, who: &Credentials) -> Result<(), FsError> {
+		let result = self.check_access(path, who, 0).and_then(|()| {
+			let owns = who.is_root() || who.uid == self.files[path].metadata.owner;
+			if owns { Ok(()) } else { Err(FsError::NotPermitted) }
+		});
+		audited(result, who, "change owner or mode of", path)
+	}
+
+	/// The whole of a regular file, without opening it
+	pub fn contents(&self, path: &str) -> Result<&[u8], FsError> {
+		let file = self.files.get(path).ok_or(FsError::NotFound)?;
+		if file.metadata.file_type == FileType::Directory {
+			return Err(FsError::IsDirectory);
+		}
+		Ok(&file.data)
+	}
+
 	/// Get file metadata
 	pub fn stat(&self, path: &str) -> Result<FileMetadata, FsError> {
 		let file = self.files.get(path).ok_or(FsError::NotFound)?;
@@ -217,7 +602,8 @@ impl FileSystem {
 	}
 }
 
-/// Global file system instance
+/// Global file system instance; an async lock so tasks can wait for it
+/// without spinning
 static FILE_SYSTEM: Mutex<FileSystem> = Mutex::new(FileSystem { 
 	files: BTreeMap::new(),
 	open_files: BTreeMap::new(),
@@ -234,14 +620,27 @@ pub fn init_filesystem() {
 		fs.create_directory("/bin".to_string()).unwrap();
 		fs.create_directory("/etc".to_string()).unwrap();
 		fs.create_directory("/home".to_string()).unwrap();
+		fs.create_directory("/root".to_string()).unwrap();
 		fs.create_directory("/tmp".to_string()).unwrap();
 		fs.create_directory("/usr".to_string()).unwrap();
 		fs.create_directory("/var".to_string()).unwrap();
 		
-		// Create a basic passwd file
-		let passwd_content = b"root:x:0:0:root:/root:/bin/sh\n";
-		fs.create_file("/etc/passwd".to_string(), passwd_content.to_vec()).unwrap();
+		fs.create_directory("/home/guest".to_string()).unwrap();
+		fs.set_owner("/home/guest", 1000, 1000).unwrap();
+		// Root's files are its own, and anyone may leave files in /tmp
+		fs.set_permissions("/root", 0o700).unwrap();
+		fs.set_permissions("/tmp", 0o1777).unwrap();
+
+		// The accounts and groups; `users::init` adds their passwords
+		fs.create_file("/etc/passwd".to_string(), DEFAULT_PASSWD.to_vec()).unwrap();
+		fs.create_file("/etc/group".to_string(), DEFAULT_GROUP.to_vec()).unwrap();
+		fs.create_file("/etc/shellrc".to_string(), DEFAULT_SHELLRC.to_vec()).unwrap();
+
+		// What the processor is, as CPUID reports it
+		fs.create_directory("/proc".to_string()).unwrap();
+		fs.create_file("/proc/cpuinfo".to_string(), crate::cpu::cpuinfo().into_bytes()).unwrap();
 	});
+	audit::record(audit::Kind::Mount, audit::Actor::KERNEL, format_args!("mounted the RAM filesystem on /"));
 }
 
 /// Execute a function with access to the global file system
@@ -249,5 +648,58 @@ pub fn with_filesystem<F, R>(f: F) -> R
 where
 	F: FnOnce(&mut FileSystem) -> R,
 {
-	f(&mut FILE_SYSTEM.lock())
-} 
\ No newline at end of file
+	f(&mut FILE_SYSTEM.lock_blocking())
+}
+
+/// Execute a function with access to the global file system from a task,
+/// yielding while something else has it
+pub async fn with_filesystem_async<F, R>(f: F) -> R
+where
+	F: FnOnce(&mut FileSystem) -> R,
+{
+	f(&mut *FILE_SYSTEM.lock().await)
+}
+
+/// Test path normalization against a working directory
+#[test_case]
+fn test_normalize_path() {
+	assert_eq!(normalize_path("/home", "user/../x/./y/"), "/home/x/y");
+	assert_eq!(normalize_path("/home", "/etc//passwd"), "/etc/passwd");
+	assert_eq!(normalize_path("/", ".."), "/");
+	assert_eq!(parent_path("/etc/passwd"), Some("/etc"));
+	assert_eq!(parent_path("/etc"), Some("/"));
+	assert_eq!(file_name("/etc/passwd"), "passwd");
+	assert_eq!(under_root("/srv/jail", &normalize_path("/", "../../etc")), "/srv/jail/etc");
+	assert_eq!(under_root("/srv/jail", "/"), "/srv/jail");
+	assert_eq!(under_root("/", "/etc"), "/etc");
+}
+
+/// Test permission checks for owners, groups, others, and root
+#[test_case]
+fn test_permissions() {
+	let mut fs = FileSystem::new();
+	let root = Credentials::root();
+	let guest = Credentials { uid: 1000, gid: 1000, groups: alloc::vec![100] };
+	fs.create_file("/tmp/notes".to_string(), Vec::new()).unwrap();
+	fs.set_permissions("/tmp/notes", 0o640).unwrap();
+	assert!(matches!(fs.access("/tmp/notes", &guest, MAY_READ), Err(FsError::PermissionDenied)));
+	fs.set_owner("/tmp/notes", 0, 100).unwrap();
+	assert!(fs.access("/tmp/notes", &guest, MAY_READ).is_ok());
+	assert!(fs.access("/tmp/notes", &guest, MAY_WRITE).is_err());
+	assert!(fs.access("/tmp/notes", &root, MAY_READ | MAY_WRITE).is_ok());
+	assert!(fs.access("/tmp/notes", &root, MAY_EXEC).is_err());
+	assert!(matches!(fs.access_owner("/tmp/notes", &guest), Err(FsError::NotPermitted)));
+
+	// Creating and removing entries takes a writable directory, and a
+	// sticky one keeps others' entries
+	assert!(fs.access_entry("/tmp/mine", &guest).is_err());
+	fs.set_permissions("/tmp", 0o1777).unwrap();
+	assert!(fs.access_entry("/tmp/mine", &guest).is_ok());
+	assert!(fs.access_entry("/tmp/notes", &guest).is_err());
+
+	// A directory without search permission hides what is in it
+	fs.create_file("/root/secret".to_string(), Vec::new()).unwrap();
+	fs.set_permissions("/root", 0o700).unwrap();
+	assert!(fs.access("/root/secret", &guest, 0).is_err());
+	assert!(fs.access("/root/secret", &root, MAY_READ).is_ok());
+}
@@ -0,0 +1,437 @@
+use crate::{cmdline, klog, memory};
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::sync::Mutex;
+use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::VirtAddr;
+
+/// I/O base of the second serial port, which the stub talks to GDB over
+const COM2: u16 = 0x2f8;
+/// COM2's interrupt enable register; the stub polls instead
+const COM2_INTERRUPT_ENABLE: u16 = COM2 + 1;
+
+/// Largest packet the stub takes or sends, as told to GDB in `qSupported`
+const PACKET_SIZE: usize = 4096;
+/// Software breakpoints GDB can set at once
+const MAX_BREAKPOINTS: usize = 32;
+/// The `int3` instruction a software breakpoint is patched with
+const INT3: u8 = 0xcc;
+/// RFLAGS trap flag, which raises a debug exception after one instruction
+const TRAP_FLAG: u64 = 1 << 8;
+/// Stop reply for a trap (SIGTRAP)
+const STOP_TRAP: &[u8] = b"S05";
+
+/// Interrupt vectors the stub handles
+const VECTOR_DEBUG: u64 = 1;
+const VECTOR_BREAKPOINT: u64 = 3;
+
+/// The interrupted code's registers, as the entry stubs push them
+#[repr(C)]
+#[derive(Debug, Clone, Default)]
+pub struct TrapFrame {
+	r15: u64,
+	r14: u64,
+	r13: u64,
+	r12: u64,
+	r11: u64,
+	r10: u64,
+	r9: u64,
+	r8: u64,
+	rbp: u64,
+	rdi: u64,
+	rsi: u64,
+	rdx: u64,
+	rcx: u64,
+	rbx: u64,
+	rax: u64,
+	// Pushed by the CPU
+	rip: u64,
+	cs: u64,
+	rflags: u64,
+	rsp: u64,
+	ss: u64,
+}
+
+impl TrapFrame {
+	/// The 64-bit registers in GDB's amd64 order: rax, rbx, rcx, rdx, rsi,
+	/// rdi, rbp, rsp, r8-r15, rip
+	fn registers_mut(&mut self) -> [&mut u64; 17] {
+		[
+			&mut self.rax, &mut self.rbx, &mut self.rcx, &mut self.rdx,
+			&mut self.rsi, &mut self.rdi, &mut self.rbp, &mut self.rsp,
+			&mut self.r8, &mut self.r9, &mut self.r10, &mut self.r11,
+			&mut self.r12, &mut self.r13, &mut self.r14, &mut self.r15,
+			&mut self.rip,
+		]
+	}
+}
+
+/// COM2, set up the first time GDB is talked to
+static PORT: Mutex<Option<SerialPort>> = Mutex::new(None);
+/// Addresses patched with `int3`, and the bytes they held
+static BREAKPOINTS: Mutex<[Option<(u64, u8)>; MAX_BREAKPOINTS]> = Mutex::new([None; MAX_BREAKPOINTS]);
+/// Set when GDB resumed the kernel, so the next trap is reported to it
+static RESUMED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the kernel was booted with `gdb`, handing breakpoints to the stub
+pub fn enabled() -> bool {
+	cmdline::param("gdb").is_some()
+}
+
+/// Announce the stub, and with `kgdbwait` stop at a breakpoint until GDB
+/// attaches and continues
+pub fn init() {
+	if !enabled() {
+		return;
+	}
+	klog!(Info, "gdb: waiting for a debugger on COM2");
+	if cmdline::param("kgdbwait").is_some() {
+		x86_64::instructions::interrupts::int3();
+	}
+}
+
+/// Generate an entry stub that saves every register, calls `handle_trap`
+/// with them and the vector, and returns to the possibly changed registers
+macro_rules! trap_entry {
+	($name:ident, $vector:expr) => {
+		#[unsafe(naked)]
+		pub extern "C" fn $name() {
+			core::arch::naked_asm!(
+				"push rax", "push rbx", "push rcx", "push rdx", "push rsi", "push rdi", "push rbp",
+				"push r8", "push r9", "push r10", "push r11", "push r12", "push r13", "push r14", "push r15",
+				// The CPU's five pushes and these fifteen leave the stack 16-byte aligned
+				"mov rdi, rsp",
+				"mov esi, {vector}",
+				"call {handler}",
+				"pop r15", "pop r14", "pop r13", "pop r12", "pop r11", "pop r10", "pop r9", "pop r8",
+				"pop rbp", "pop rdi", "pop rsi", "pop rdx", "pop rcx", "pop rbx", "pop rax",
+				"iretq",
+				vector = const $vector,
+				handler = sym handle_trap,
+			)
+		}
+	};
+}
+
+trap_entry!(debug_entry, VECTOR_DEBUG);
+trap_entry!(breakpoint_entry, VECTOR_BREAKPOINT);
+
+/// Talk to GDB until it resumes the kernel
+extern "C" fn handle_trap(frame: &mut TrapFrame, _vector: u64) {
+	frame.rflags &= !TRAP_FLAG;
+	let mut port = PORT.lock();
+	let port = port.get_or_insert_with(|| {
+		let mut port = unsafe { SerialPort::new(COM2) };
+		port.init();
+		unsafe { Port::<u8>::new(COM2_INTERRUPT_ENABLE).write(0) };
+		port
+	});
+	let mut link = Link { port };
+	if RESUMED.swap(false, Ordering::Relaxed) {
+		link.send(STOP_TRAP);
+	}
+
+	let mut packet = [0u8; PACKET_SIZE];
+	let mut reply = [0u8; PACKET_SIZE];
+	loop {
+		let len = link.receive(&mut packet);
+		match command(frame, &packet[..len], &mut reply) {
+			Action::Reply(len) => link.send(&reply[..len]),
+			Action::Resume => {
+				RESUMED.store(true, Ordering::Relaxed);
+				return;
+			}
+			Action::Detach => {
+				link.send(b"OK");
+				return;
+			}
+		}
+	}
+}
+
+/// The serial line to GDB, carrying `$packet#checksum` frames
+struct Link<'a> {
+	port: &'a mut SerialPort,
+}
+
+impl Link<'_> {
+	/// Wait for a packet with a good checksum, acknowledging it, and return
+	/// its length
+	fn receive(&mut self, packet: &mut [u8]) -> usize {
+		loop {
+			while self.port.receive() != b'$' {}
+			let mut len = 0;
+			loop {
+				match self.port.receive() {
+					b'#' => break,
+					byte if len < packet.len() => {
+						packet[len] = byte;
+						len += 1;
+					}
+					_ => {}
+				}
+			}
+			let check = [self.port.receive(), self.port.receive()];
+			if parse_hex(&check) == Some(checksum(&packet[..len]) as u64) {
+				self.port.send_raw(b'+');
+				return len;
+			}
+			self.port.send_raw(b'-');
+		}
+	}
+
+	/// Send a packet, again until GDB acknowledges it
+	fn send(&mut self, data: &[u8]) {
+		let sum = checksum(data);
+		loop {
+			self.port.send_raw(b'$');
+			for &byte in data {
+				self.port.send_raw(byte);
+			}
+			self.port.send_raw(b'#');
+			self.port.send_raw(HEX[(sum >> 4) as usize]);
+			self.port.send_raw(HEX[(sum & 0xf) as usize]);
+			if self.port.receive() != b'-' {
+				return;
+			}
+		}
+	}
+}
+
+/// What to do after a packet
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+	/// Send the first `len` bytes of the reply buffer
+	Reply(usize),
+	/// Return to the kernel, which GDB hears about at the next trap
+	Resume,
+	/// Return to the kernel and forget GDB
+	Detach,
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// Sum of a packet's bytes, modulo 256
+fn checksum(data: &[u8]) -> u8 {
+	data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// A big-endian hex number
+fn parse_hex(text: &[u8]) -> Option<u64> {
+	if text.is_empty() || text.len() > 16 {
+		return None;
+	}
+	text.iter().try_fold(0u64, |value, &digit| Some(value << 4 | (digit as char).to_digit(16)? as u64))
+}
+
+/// Parse `ADDR,LEN` as used by memory and breakpoint packets
+fn parse_range(text: &[u8]) -> Option<(u64, usize)> {
+	let comma = text.iter().position(|&byte| byte == b',')?;
+	Some((parse_hex(&text[..comma])?, parse_hex(&text[comma + 1..])? as usize))
+}
+
+/// Append bytes as hex to `out` at `len`, returning the new length
+fn put_hex(out: &mut [u8], mut len: usize, bytes: &[u8]) -> usize {
+	for &byte in bytes {
+		out[len] = HEX[(byte >> 4) as usize];
+		out[len + 1] = HEX[(byte & 0xf) as usize];
+		len += 2;
+	}
+	len
+}
+
+/// Decode hex pairs into bytes, or `None` if any pair is not hex
+fn decode_hex(text: &[u8], mut out: impl FnMut(usize, u8)) -> Option<()> {
+	for (i, pair) in text.chunks(2).enumerate() {
+		if pair.len() != 2 {
+			return None;
+		}
+		out(i, parse_hex(pair)? as u8);
+	}
+	Some(())
+}
+
+/// Copy a fixed reply into `out`
+fn reply(out: &mut [u8], text: &[u8]) -> Action {
+	out[..text.len()].copy_from_slice(text);
+	Action::Reply(text.len())
+}
+
+/// Write kernel memory even where it is mapped read-only, such as code
+fn poke(addr: u64, value: u8) {
+	let flags = Cr0::read();
+	unsafe {
+		Cr0::write(flags - Cr0Flags::WRITE_PROTECT);
+		core::ptr::write_volatile(addr as *mut u8, value);
+		Cr0::write(flags);
+	}
+}
+
+/// Insert or remove a software breakpoint
+fn set_breakpoint(addr: u64, insert: bool) -> bool {
+	let mut breakpoints = BREAKPOINTS.lock();
+	let existing = breakpoints.iter().position(|slot| slot.is_some_and(|(at, _)| at == addr));
+	match (insert, existing) {
+		(true, Some(_)) => true,
+		(true, None) => {
+			let Some(slot) = breakpoints.iter_mut().find(|slot| slot.is_none()) else {
+				return false;
+			};
+			if !memory::is_range_mapped(addr, 1) {
+				return false;
+			}
+			*slot = Some((addr, unsafe { core::ptr::read_volatile(addr as *const u8) }));
+			poke(addr, INT3);
+			true
+		}
+		(false, Some(index)) => {
+			if let Some((at, original)) = breakpoints[index].take() {
+				poke(at, original);
+			}
+			true
+		}
+		(false, None) => true,
+	}
+}
+
+/// Handle one packet, filling `out` with the reply
+fn command(frame: &mut TrapFrame, packet: &[u8], out: &mut [u8]) -> Action {
+	let Some((&kind, args)) = packet.split_first() else {
+		return Action::Reply(0);
+	};
+	match kind {
+		b'?' => reply(out, STOP_TRAP),
+		b'g' => {
+			let mut len = 0;
+			for register in frame.registers_mut() {
+				len = put_hex(out, len, &register.to_le_bytes());
+			}
+			// eflags, cs, ss, then ds, es, fs, gs, which are all null in long mode
+			for value in [frame.rflags, frame.cs, frame.ss, 0, 0, 0, 0] {
+				len = put_hex(out, len, &(value as u32).to_le_bytes());
+			}
+			Action::Reply(len)
+		}
+		b'G' => {
+			let mut values = [0u8; 17 * 8 + 4];
+			if args.len() < values.len() * 2 || decode_hex(&args[..values.len() * 2], |i, byte| values[i] = byte).is_none() {
+				return reply(out, b"E01");
+			}
+			for (register, bytes) in frame.registers_mut().into_iter().zip(values.chunks(8)) {
+				*register = u64::from_le_bytes(bytes.try_into().unwrap());
+			}
+			// Segment registers stay as they are
+			frame.rflags = u32::from_le_bytes(values[136..140].try_into().unwrap()) as u64;
+			reply(out, b"OK")
+		}
+		b'm' => {
+			let Some((addr, len)) = parse_range(args).filter(|&(_, len)| len * 2 <= out.len()) else {
+				return reply(out, b"E01");
+			};
+			if !memory::is_range_mapped(addr, len) {
+				return reply(out, b"E14");
+			}
+			let bytes = if len == 0 { &[][..] } else { unsafe { core::slice::from_raw_parts(addr as *const u8, len) } };
+			Action::Reply(put_hex(out, 0, bytes))
+		}
+		b'M' => {
+			let Some(colon) = args.iter().position(|&byte| byte == b':') else {
+				return reply(out, b"E01");
+			};
+			let Some((addr, len)) = parse_range(&args[..colon]) else {
+				return reply(out, b"E01");
+			};
+			let data = &args[colon + 1..];
+			if data.len() != len * 2 {
+				return reply(out, b"E01");
+			}
+			if !memory::is_range_mapped(addr, len) {
+				return reply(out, b"E14");
+			}
+			match decode_hex(data, |i, byte| poke(addr + i as u64, byte)) {
+				Some(()) => reply(out, b"OK"),
+				None => reply(out, b"E01"),
+			}
+		}
+		b'c' | b's' => {
+			if let Some(addr) = parse_hex(args) {
+				frame.rip = addr;
+			}
+			if kind == b's' {
+				frame.rflags |= TRAP_FLAG;
+			}
+			Action::Resume
+		}
+		b'Z' | b'z' if args.starts_with(b"0,") => {
+			let Some(addr) = args[2..].split(|&byte| byte == b',').next().and_then(parse_hex) else {
+				return reply(out, b"E01");
+			};
+			if set_breakpoint(addr, kind == b'Z') {
+				reply(out, b"OK")
+			} else {
+				reply(out, b"E0e")
+			}
+		}
+		b'D' => {
+			for slot in BREAKPOINTS.lock().iter_mut() {
+				if let Some((addr, original)) = slot.take() {
+					poke(addr, original);
+				}
+			}
+			Action::Detach
+		}
+		b'H' => reply(out, b"OK"),
+		b'q' if args.starts_with(b"Supported") => {
+			// PACKET_SIZE, in hex
+			reply(out, b"PacketSize=1000")
+		}
+		b'q' if args == b"Attached" => reply(out, b"1"),
+		b'q' if args == b"C" => reply(out, b"QC1"),
+		b'q' if args == b"fThreadInfo" => reply(out, b"m1"),
+		b'q' if args == b"sThreadInfo" => reply(out, b"l"),
+		// Anything else is unsupported, which an empty reply tells GDB
+		_ => Action::Reply(0),
+	}
+}
+
+/// Test packet checksums, register replies, and memory reads
+#[test_case]
+fn test_gdb_packets() {
+	assert_eq!(checksum(b"OK"), 0x9a);
+	assert_eq!(parse_hex(b"1f"), Some(0x1f));
+	assert_eq!(parse_hex(b"x"), None);
+	assert_eq!(parse_range(b"ffff8000,10"), Some((0xffff_8000, 16)));
+
+	let mut frame = TrapFrame { rax: 0x1122, rip: 0xdead_beef, rflags: 0x246, ..TrapFrame::default() };
+	let mut out = [0u8; PACKET_SIZE];
+	let Action::Reply(len) = command(&mut frame, b"g", &mut out) else {
+		panic!("g did not reply");
+	};
+	assert_eq!(len, (17 * 8 + 7 * 4) * 2);
+	assert_eq!(&out[..16], b"2211000000000000");
+	assert_eq!(&out[16 * 16..17 * 16], b"efbeadde00000000");
+	assert_eq!(&out[17 * 16..17 * 16 + 8], b"46020000");
+
+	let mut registers = [0u8; PACKET_SIZE];
+	registers[0] = b'G';
+	registers[1..=len].copy_from_slice(&out[..len]);
+	registers[1..3].copy_from_slice(b"ff");
+	assert_eq!(command(&mut frame, &registers[..=len], &mut out), Action::Reply(2));
+	assert_eq!(frame.rax, 0x11ff);
+
+	let value: u32 = 0x0403_0201;
+	let mut packet = [0u8; 32];
+	let text = alloc::format!("m{:x},4", &value as *const u32 as u64);
+	packet[..text.len()].copy_from_slice(text.as_bytes());
+	let read = command(&mut frame, &packet[..text.len()], &mut out);
+	// Memory translation needs the physical memory offset set at boot
+	if memory::translate(VirtAddr::new(&value as *const u32 as u64)).is_some() {
+		assert_eq!(read, Action::Reply(8));
+		assert_eq!(&out[..8], b"01020304");
+	} else {
+		assert_eq!(read, Action::Reply(3));
+	}
+	assert_eq!(command(&mut frame, b"s", &mut out), Action::Resume);
+	assert_ne!(frame.rflags & TRAP_FLAG, 0);
+}
@@ -2,16 +2,18 @@ use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector};
 use lazy_static::lazy_static;
+use alloc::boxed::Box;
 
 /// Double fault stack index in the TSS
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// Size of each processor's double fault stack
+const STACK_SIZE: usize = 4096 * 5;
 
 lazy_static! {
 	/// Task State Segment for handling interrupts
 	static ref TSS: TaskStateSegment = {
 		let mut tss = TaskStateSegment::new();
 		tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-			const STACK_SIZE: usize = 4096 * 5;
 			static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
 			let stack_start = VirtAddr::from_ptr(&raw const STACK);
@@ -24,12 +26,15 @@ lazy_static! {
 
 lazy_static! {
 	/// Global Descriptor Table with kernel code segment and TSS
-	static ref GDT: (GlobalDescriptorTable, Selectors) = {
-		let mut gdt = GlobalDescriptorTable::new();
-		let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-		let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-		(gdt, Selectors { code_selector, tss_selector })
-	};
+	static ref GDT: (GlobalDescriptorTable, Selectors) = build(&TSS);
+}
+
+/// A GDT with a kernel code segment and the given TSS
+fn build(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+	let mut gdt = GlobalDescriptorTable::new();
+	let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+	let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+	(gdt, Selectors { code_selector, tss_selector })
 }
 
 /// Segment selectors for GDT entries
@@ -48,4 +53,23 @@ pub fn init() {
 		CS::set_reg(GDT.1.code_selector);
 		load_tss(GDT.1.tss_selector);
 	}
+}
+
+/// Give an application processor a GDT and TSS of its own, with its own
+/// double fault stack; a TSS is marked busy once loaded, so processors
+/// cannot share one. Needs the heap.
+pub fn init_ap() {
+	use x86_64::instructions::segmentation::{CS, Segment};
+	use x86_64::instructions::tables::load_tss;
+
+	let stack: &'static mut [u8] = alloc::vec![0; STACK_SIZE].leak();
+	let mut tss = TaskStateSegment::new();
+	tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = VirtAddr::from_ptr(stack.as_ptr()) + STACK_SIZE;
+	let (gdt, selectors) = build(Box::leak(Box::new(tss)));
+	let gdt: &'static GlobalDescriptorTable = Box::leak(Box::new(gdt));
+	gdt.load();
+	unsafe {
+		CS::set_reg(selectors.code_selector);
+		load_tss(selectors.tss_selector);
+	}
 } 
\ No newline at end of file
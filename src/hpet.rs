@@ -0,0 +1,139 @@
+use crate::acpi::HpetTable;
+use crate::{klog, memory, time};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::PhysAddr;
+
+/// Event timer block registers, as offsets from its base
+const CAPABILITIES: usize = 0x000;
+const CONFIG: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0f0;
+/// Timer 0's configuration and comparator; the ones after are 0x20 apart
+const TIMER0_CONFIG: usize = 0x100;
+const TIMER0_COMPARATOR: usize = 0x108;
+
+/// General capability bits
+const LEGACY_ROUTE_CAPABLE: u64 = 1 << 15;
+/// General configuration bits: run the counter, and send timer 0 to IRQ 0
+/// in place of the PIT
+const ENABLE: u64 = 1 << 0;
+const LEGACY_ROUTE: u64 = 1 << 1;
+
+/// Timer configuration bits
+const TIMER_INTERRUPT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+/// Lets the next comparator write set a periodic timer's accumulator
+const TIMER_SET_VALUE: u64 = 1 << 6;
+
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+/// The specification's upper bound on the counter period, 100 ns
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+/// Where the registers are mapped, or 0 without an HPET
+static BASE: AtomicU64 = AtomicU64::new(0);
+/// Counter period in femtoseconds
+static PERIOD_FS: AtomicU64 = AtomicU64::new(0);
+
+/// How timer 0 interrupts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+	/// `hz` times a second
+	Periodic(u64),
+	/// Once, after a delay
+	OneShot(Duration),
+}
+
+fn read(register: usize) -> u64 {
+	let base = BASE.load(Ordering::Relaxed);
+	unsafe { core::ptr::read_volatile((base as usize + register) as *const u64) }
+}
+
+fn write(register: usize, value: u64) {
+	let base = BASE.load(Ordering::Relaxed);
+	unsafe { core::ptr::write_volatile((base as usize + register) as *mut u64, value) };
+}
+
+/// Counter ticks in a duration, at least one
+fn ticks_for(period_fs: u64, duration: Duration) -> u64 {
+	(duration.as_nanos() * FEMTOS_PER_NANO as u128 / period_fs.max(1) as u128).clamp(1, u64::MAX as u128) as u64
+}
+
+/// Find the HPET in the ACPI tables, map its registers, and start its
+/// counter; returns false if there is none
+pub fn init() -> bool {
+	let Some(table) = HpetTable::find() else {
+		return false;
+	};
+	let Some(base) = memory::map_mmio(PhysAddr::new(table.address), 1024) else {
+		klog!(Warn, "HPET: cannot map registers at {:#x}", table.address);
+		return false;
+	};
+	BASE.store(base.as_u64(), Ordering::Relaxed);
+	let capabilities = read(CAPABILITIES);
+	let period_fs = capabilities >> 32;
+	if period_fs == 0 || period_fs > MAX_PERIOD_FS || capabilities & LEGACY_ROUTE_CAPABLE == 0 {
+		klog!(Warn, "HPET: unusable, capabilities {:#x}", capabilities);
+		BASE.store(0, Ordering::Relaxed);
+		return false;
+	}
+	PERIOD_FS.store(period_fs, Ordering::Relaxed);
+	write(CONFIG, read(CONFIG) | ENABLE);
+	klog!(Info, "HPET: at {:#x}, {} MHz, {} timers", table.address, 1_000_000_000 / period_fs, (capabilities >> 8 & 0x1f) + 1);
+	true
+}
+
+/// Whether an HPET was found and started
+pub fn present() -> bool {
+	BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Time since the counter started, or `None` without an HPET
+pub fn elapsed() -> Option<Duration> {
+	if !present() {
+		return None;
+	}
+	let femtos = read(MAIN_COUNTER) as u128 * PERIOD_FS.load(Ordering::Relaxed) as u128;
+	Some(Duration::from_nanos((femtos / FEMTOS_PER_NANO as u128) as u64))
+}
+
+/// Program timer 0, which interrupts on IRQ 0 in place of the PIT; returns
+/// false without an HPET or if the timer cannot be periodic
+pub fn set_timer(mode: Mode) -> bool {
+	if !present() {
+		return false;
+	}
+	let period_fs = PERIOD_FS.load(Ordering::Relaxed);
+	let capable = read(TIMER0_CONFIG) & TIMER_PERIODIC_CAPABLE != 0;
+	x86_64::instructions::interrupts::without_interrupts(|| match mode {
+		Mode::Periodic(hz) if capable && time::TICK_HZ_RANGE.contains(&hz) => {
+			let ticks = ticks_for(period_fs, Duration::from_secs(1) / hz as u32);
+			// Stop the counter so the first period starts from zero
+			write(CONFIG, read(CONFIG) & !ENABLE);
+			write(MAIN_COUNTER, 0);
+			write(TIMER0_CONFIG, TIMER_INTERRUPT_ENABLE | TIMER_PERIODIC | TIMER_SET_VALUE);
+			write(TIMER0_COMPARATOR, ticks);
+			write(TIMER0_COMPARATOR, ticks);
+			write(CONFIG, read(CONFIG) | ENABLE | LEGACY_ROUTE);
+			time::set_tick_rate(hz);
+			true
+		}
+		Mode::Periodic(_) => false,
+		Mode::OneShot(delay) => {
+			write(TIMER0_CONFIG, TIMER_INTERRUPT_ENABLE);
+			write(TIMER0_COMPARATOR, read(MAIN_COUNTER).wrapping_add(ticks_for(period_fs, delay)));
+			write(CONFIG, read(CONFIG) | ENABLE | LEGACY_ROUTE);
+			true
+		}
+	})
+}
+
+/// Test converting durations to counter ticks
+#[test_case]
+fn test_ticks_for() {
+	// QEMU's HPET runs at 100 MHz
+	assert_eq!(ticks_for(10_000_000, Duration::from_millis(10)), 1_000_000);
+	assert_eq!(ticks_for(69_841_279, Duration::from_secs(1)), 14_318_179);
+	assert_eq!(ticks_for(10_000_000, Duration::ZERO), 1);
+	assert_eq!(ticks_for(0, Duration::from_nanos(5)), 5_000_000);
+}
@@ -0,0 +1,220 @@
+use crate::{klog, time, trace};
+use bootloader::BootInfo;
+use conquer_once::spin::OnceCell;
+
+/// Phases of boot, run in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Stage {
+	/// Logging and tracing, before anything else is done
+	Early = 0,
+	/// Descriptor tables and interrupt controllers
+	Arch = 1,
+	/// Page tables and the heap
+	Memory = 2,
+	/// Clocks, timers, the console, and the other processors
+	Drivers = 3,
+	/// The filesystem, processes, and the devices found on buses
+	Fs = 4,
+	/// The screen and the shell
+	Late = 5,
+}
+
+impl Stage {
+	/// Every stage, in the order they run
+	pub const ALL: [Stage; 6] = [Stage::Early, Stage::Arch, Stage::Memory, Stage::Drivers, Stage::Fs, Stage::Late];
+
+	/// Short name as shown in the boot-time summary
+	pub fn name(self) -> &'static str {
+		match self {
+			Stage::Early => "early",
+			Stage::Arch => "arch",
+			Stage::Memory => "memory",
+			Stage::Drivers => "drivers",
+			Stage::Fs => "fs",
+			Stage::Late => "late",
+		}
+	}
+}
+
+/// One step of boot: what it is called, the stage it belongs to, and what it runs
+pub struct InitCall {
+	pub name: &'static str,
+	pub stage: Stage,
+	pub run: fn(),
+}
+
+impl InitCall {
+	const fn new(name: &'static str, stage: Stage, run: fn()) -> InitCall {
+		InitCall { name, stage, run }
+	}
+}
+
+/// What the bootloader handed over, for the calls that need it
+static BOOT_INFO: OnceCell<&'static BootInfo> = OnceCell::uninit();
+
+fn boot_info() -> &'static BootInfo {
+	BOOT_INFO.get().expect("init calls run before boot info is kept")
+}
+
+/// Everything the kernel sets up before its executor runs, in order; each
+/// stage's calls may rely on every earlier stage's
+pub const INIT_CALLS: &[InitCall] = &[
+	// Apply log= and loglevel= before anything is logged
+	InitCall::new("klog", Stage::Early, klog::init),
+	InitCall::new("trace", Stage::Early, trace::init),
+	InitCall::new("gdt", Stage::Arch, || {
+		crate::gdt::init();
+		crate::percpu::init(0);
+	}),
+	InitCall::new("idt", Stage::Arch, crate::interrupts::init_idt),
+	InitCall::new("pic", Stage::Arch, || {
+		unsafe { crate::interrupts::PICS.lock().initialize() };
+		crate::interrupts::enable_serial_input();
+	}),
+	InitCall::new("memory", Stage::Memory, || crate::memory::init(boot_info())),
+	// Memory reads from GDB need the page tables, so it can attach from here
+	InitCall::new("gdbstub", Stage::Memory, crate::gdbstub::init),
+	InitCall::new("heap", Stage::Memory, || crate::allocator::init_heap().expect("heap initialization failed")),
+	// Reading the kernel's program headers takes the heap
+	InitCall::new("protect", Stage::Memory, crate::memory::protect_kernel_image),
+	InitCall::new("canary", Stage::Memory, crate::task::canary::arm_boot_stack),
+	// Interrupt handlers can queue work for later once it has a queue
+	InitCall::new("deferred", Stage::Memory, crate::task::deferred::init),
+	// Calibrate the TSC for the monotonic clock, find the ACPI tables, hand
+	// the keyboard and serial IRQs from the 8259s to the APICs, and start
+	// the timer tick
+	InitCall::new("time", Stage::Drivers, time::init),
+	InitCall::new("acpi", Stage::Drivers, crate::acpi::init),
+	InitCall::new("apic", Stage::Drivers, crate::apic::init),
+	InitCall::new("hpet", Stage::Drivers, || {
+		crate::hpet::init();
+	}),
+	InitCall::new("tick", Stage::Drivers, time::start_tick),
+	InitCall::new("rtc", Stage::Drivers, crate::rtc::init),
+	InitCall::new("vga", Stage::Drivers, || {
+		x86_64::instructions::interrupts::without_interrupts(|| {
+			crate::vga_buffer::WRITER.lock().enable_scrollback();
+		});
+		if let Some(theme) = crate::cmdline::param("theme") {
+			if !crate::vga_buffer::set_theme(theme) {
+				klog!(Warn, "Unknown theme '{}'", theme);
+			}
+		}
+	}),
+	// Draw the console on the serial port too if booted with console=serial
+	InitCall::new("console", Stage::Drivers, crate::console::init),
+	InitCall::new("keymap", Stage::Drivers, crate::keymap::init),
+	InitCall::new("interrupts", Stage::Drivers, || {
+		x86_64::instructions::interrupts::enable();
+		// Light Num Lock, which the decoder starts with on
+		crate::keyboard::update_leds();
+	}),
+	// Start the other processors, each running an executor of its own
+	InitCall::new("smp", Stage::Drivers, crate::smp::init),
+	// The banner is only for the screen; the log already has the version
+	InitCall::new("banner", Stage::Drivers, || {
+		crate::println!("\n╔══════════════════════════════════════════════════════════════════════════════╗");
+		crate::println!("║                                ScottOS v0.1.0                                ║");
+		crate::println!("║                      A Minimalist POSIX-Compliant OS                        ║");
+		crate::println!("╚══════════════════════════════════════════════════════════════════════════════╝");
+		crate::println!();
+	}),
+	InitCall::new("fs", Stage::Fs, crate::fs::init_filesystem),
+	// Seed the generator from RDSEED and create /dev/random and /dev/urandom,
+	// ahead of the password salts drawn from it
+	InitCall::new("random", Stage::Fs, crate::random::init),
+	// Passwords for the accounts the filesystem starts with
+	InitCall::new("users", Stage::Fs, crate::users::init),
+	InitCall::new("process", Stage::Fs, crate::process::init),
+	// Nodes in /dev/input for the keyboard's events
+	InitCall::new("input", Stage::Fs, crate::input::init),
+	InitCall::new("pci", Stage::Fs, || {
+		let functions = crate::pci::init();
+		klog!(Info, "PCI: found {} functions", functions);
+	}),
+	// Network drivers claim their devices from the scan
+	InitCall::new("net", Stage::Fs, crate::net::init),
+	// Move the console to a framebuffer unless booted with video=text
+	InitCall::new("framebuffer", Stage::Late, || {
+		let video = crate::cmdline::param("video");
+		if video != Some("text") {
+			let resolution = video.and_then(crate::framebuffer::parse_resolution);
+			if let Some((width, height)) = crate::framebuffer::init(resolution) {
+				klog!(Info, "Framebuffer console at {}x{}", width, height);
+			}
+		}
+	}),
+	// Turn on the hardware cursor, which the writer keeps at the insertion point
+	InitCall::new("cursor", Stage::Late, crate::vga_buffer::show_cursor),
+	InitCall::new("shell", Stage::Late, crate::shell::init_shell),
+];
+
+/// Whether calls are listed stage by stage; checked when the kernel is
+/// built, so a call cannot be put ahead of a stage it needs
+const fn in_stage_order(calls: &[InitCall]) -> bool {
+	let mut i = 1;
+	while i < calls.len() {
+		if (calls[i].stage as u8) < calls[i - 1].stage as u8 {
+			return false;
+		}
+		i += 1;
+	}
+	true
+}
+
+const _: () = assert!(in_stage_order(INIT_CALLS), "INIT_CALLS must be listed stage by stage");
+
+/// Cycles as microseconds, or `None` with no calibrated TSC
+fn micros(cycles: u64) -> Option<u64> {
+	time::tsc_hz().map(|hz| (cycles as u128 * 1_000_000 / hz as u128) as u64)
+}
+
+/// A span of cycles for the summary, in milliseconds when the TSC's rate is known
+struct Span(u64);
+
+impl core::fmt::Display for Span {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match micros(self.0) {
+			Some(micros) => write!(f, "{}.{:03} ms", micros / 1000, micros % 1000),
+			None => write!(f, "{} kcycles", self.0 / 1000),
+		}
+	}
+}
+
+/// Run every init call in order, timing each, then log how long boot took
+pub fn run(boot_info: &'static BootInfo) {
+	BOOT_INFO.init_once(|| boot_info);
+	let mut cycles = [0u64; INIT_CALLS.len()];
+	let boot_start = trace::timestamp();
+	for (call, spent) in INIT_CALLS.iter().zip(cycles.iter_mut()) {
+		let start = trace::timestamp();
+		(call.run)();
+		*spent = trace::timestamp() - start;
+	}
+	let total = trace::timestamp() - boot_start;
+
+	for (call, &spent) in INIT_CALLS.iter().zip(&cycles) {
+		klog!(Debug, "init: {} ({}) took {}", call.name, call.stage.name(), Span(spent));
+	}
+	for stage in Stage::ALL {
+		let spent: u64 = INIT_CALLS.iter().zip(&cycles).filter(|(call, _)| call.stage == stage).map(|(_, &spent)| spent).sum();
+		klog!(Info, "init: stage {:<7} {}", stage.name(), Span(spent));
+	}
+	klog!(Info, "init: boot took {}", Span(total));
+}
+
+/// Test that calls are listed stage by stage and that their names are unique
+#[test_case]
+fn test_init_calls() {
+	assert!(in_stage_order(INIT_CALLS));
+	let misordered = [InitCall::new("b", Stage::Late, || {}), InitCall::new("a", Stage::Early, || {})];
+	assert!(!in_stage_order(&misordered));
+	for (i, call) in INIT_CALLS.iter().enumerate() {
+		assert!(INIT_CALLS[i + 1..].iter().all(|other| other.name != call.name), "{} listed twice", call.name);
+	}
+	// What the rest of the kernel assumes is set up
+	for name in ["heap", "fs", "process", "shell"] {
+		assert!(INIT_CALLS.iter().any(|call| call.name == name), "no {} init call", name);
+	}
+}
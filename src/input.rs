@@ -0,0 +1,251 @@
+use crate::fs;
+use crate::ring::Ring;
+use crate::sync::SpinLockIrqSave;
+use crate::time;
+use alloc::format;
+use alloc::string::ToString;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+
+/// Events each device holds for readers that fall behind
+const RING_CAPACITY: usize = 256;
+
+/// Size of an event as `/dev/input` reads it: Linux's `struct input_event`,
+/// a `timeval` then type, code, and value
+pub const EVENT_SIZE: usize = 24;
+
+/// Set 1 scancode prefixes
+const SCANCODE_EXTENDED: u8 = 0xe0;
+const SCANCODE_PAUSE: u8 = 0xe1;
+const SCANCODE_RELEASE: u8 = 0x80;
+/// Bytes after the Pause prefix; Pause has no break code, so they are
+/// dropped rather than decoded as Ctrl and NumLock
+const PAUSE_BYTES: u8 = 5;
+
+/// Codes of `Sync` and `Rel` events, as Linux numbers them
+pub const SYN_REPORT: u16 = 0;
+pub const REL_X: u16 = 0;
+pub const REL_Y: u16 = 1;
+pub const REL_WHEEL: u16 = 8;
+/// Codes of mouse buttons, which are `Key` events
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+
+/// Values of `Key` events
+pub const KEY_UP: i32 = 0;
+pub const KEY_DOWN: i32 = 1;
+pub const KEY_REPEAT: i32 = 2;
+
+/// What an event reports, numbered as Linux's `EV_*`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum EventType {
+	/// The events since the last one form a single report
+	Sync = 0,
+	/// A key or button went down, repeated, or came up
+	Key = 1,
+	/// Relative motion, such as a mouse moving
+	Rel = 2,
+}
+
+/// One timestamped event from an input device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+	/// Time since boot
+	pub time: Duration,
+	pub kind: EventType,
+	pub code: u16,
+	pub value: i32,
+}
+
+impl InputEvent {
+	const EMPTY: InputEvent = InputEvent { time: Duration::ZERO, kind: EventType::Sync, code: 0, value: 0 };
+
+	/// The event as `/dev/input` reads it
+	pub fn to_bytes(&self) -> [u8; EVENT_SIZE] {
+		let mut bytes = [0; EVENT_SIZE];
+		bytes[0..8].copy_from_slice(&self.time.as_secs().to_le_bytes());
+		bytes[8..16].copy_from_slice(&u64::from(self.time.subsec_micros()).to_le_bytes());
+		bytes[16..18].copy_from_slice(&(self.kind as u16).to_le_bytes());
+		bytes[18..20].copy_from_slice(&self.code.to_le_bytes());
+		bytes[20..24].copy_from_slice(&self.value.to_le_bytes());
+		bytes
+	}
+}
+
+/// A source of input events, each with a node in `/dev/input`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+	Keyboard,
+	/// Has no driver yet, so reports nothing
+	Mouse,
+}
+
+impl Device {
+	pub const ALL: [Device; 2] = [Device::Keyboard, Device::Mouse];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Device::Keyboard => "AT keyboard",
+			Device::Mouse => "PS/2 mouse",
+		}
+	}
+
+	/// The device's node, such as `/dev/input/event0`
+	pub fn path(self) -> alloc::string::String {
+		format!("/dev/input/event{}", self as usize)
+	}
+}
+
+/// Each device's recent events; interrupt handlers push to them
+static RINGS: [SpinLockIrqSave<Ring<InputEvent, RING_CAPACITY>>; Device::ALL.len()] =
+	[const { SpinLockIrqSave::new(Ring::new(InputEvent::EMPTY)) }; Device::ALL.len()];
+
+/// Queue an event from `device`, stamped with the time since boot
+///
+/// Does not allocate, so drivers can report from interrupt handlers.
+pub fn report(device: Device, kind: EventType, code: u16, value: i32) {
+	let event = InputEvent { time: time::uptime(), kind, code, value };
+	RINGS[device as usize].lock().push(event);
+}
+
+/// End a report from `device`: what it queued since the last one happened
+/// at once
+pub fn sync(device: Device) {
+	report(device, EventType::Sync, SYN_REPORT, 0);
+}
+
+/// Sequence number the next event from `device` will get
+pub fn next_seq(device: Device) -> u64 {
+	RINGS[device as usize].lock().next_seq()
+}
+
+/// Copy whole events from `device` into `buffer`, starting with event
+/// number `*position` and moving it past the last one copied
+///
+/// Events a reader fell too far behind for are skipped, so a fresh reader
+/// starts with the oldest still held. Returns the bytes copied, 0 if no
+/// event has arrived since.
+pub fn read(device: Device, position: &mut usize, buffer: &mut [u8]) -> usize {
+	let ring = RINGS[device as usize].lock();
+	let mut copied = 0;
+	for chunk in buffer.chunks_exact_mut(EVENT_SIZE) {
+		let Some((seq, event)) = ring.read_from(*position as u64) else {
+			break;
+		};
+		chunk.copy_from_slice(&event.to_bytes());
+		*position = seq as usize + 1;
+		copied += EVENT_SIZE;
+	}
+	copied
+}
+
+/// Whether the last keyboard scancode was the extended-key prefix
+static EXTENDED: AtomicBool = AtomicBool::new(false);
+/// Bytes of a Pause sequence still to drop
+static PAUSE_LEFT: AtomicU8 = AtomicU8::new(0);
+/// A bit per key code held down, for telling repeats from presses
+static HELD: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+/// The key code of an extended (`0xe0`-prefixed) set 1 make code
+fn extended_key(make: u8) -> Option<u16> {
+	Some(match make {
+		0x1c => 96,  // keypad Enter
+		0x1d => 97,  // right Ctrl
+		0x35 => 98,  // keypad /
+		0x38 => 100, // right Alt
+		0x47 => 102, // Home
+		0x48 => 103, // Up
+		0x49 => 104, // Page Up
+		0x4b => 105, // Left
+		0x4d => 106, // Right
+		0x4f => 107, // End
+		0x50 => 108, // Down
+		0x51 => 109, // Page Down
+		0x52 => 110, // Insert
+		0x53 => 111, // Delete
+		0x5b => 125, // left Windows
+		0x5c => 126, // right Windows
+		0x5d => 127, // Menu
+		// Includes the fake Shifts some keyboards send around Print Screen
+		_ => return None,
+	})
+}
+
+/// Report a keyboard scancode (set 1) as key events
+///
+/// Called by the keyboard interrupt handler. Outside the extended keys,
+/// set 1 make codes are the key codes Linux uses.
+pub(crate) fn keyboard_scancode(scancode: u8) {
+	if PAUSE_LEFT.load(Ordering::Relaxed) > 0 {
+		PAUSE_LEFT.fetch_sub(1, Ordering::Relaxed);
+		return;
+	}
+	match scancode {
+		SCANCODE_EXTENDED => return EXTENDED.store(true, Ordering::Relaxed),
+		SCANCODE_PAUSE => return PAUSE_LEFT.store(PAUSE_BYTES, Ordering::Relaxed),
+		_ => {}
+	}
+	let make = scancode & !SCANCODE_RELEASE;
+	let code = match EXTENDED.swap(false, Ordering::Relaxed) {
+		true => extended_key(make),
+		false => (make != 0).then_some(u16::from(make)),
+	};
+	let Some(code) = code else {
+		return;
+	};
+
+	let (word, bit) = (&HELD[usize::from(code / 64)], 1 << (code % 64));
+	let value = if scancode & SCANCODE_RELEASE != 0 {
+		word.fetch_and(!bit, Ordering::Relaxed);
+		KEY_UP
+	} else if word.fetch_or(bit, Ordering::Relaxed) & bit != 0 {
+		KEY_REPEAT
+	} else {
+		KEY_DOWN
+	};
+	report(Device::Keyboard, EventType::Key, code, value);
+	sync(Device::Keyboard);
+}
+
+/// Create `/dev/input` with a node per device
+pub fn init() {
+	fs::with_filesystem(|fs| {
+		let _ = fs.create_directory("/dev".to_string());
+		let _ = fs.create_directory("/dev/input".to_string());
+		for device in Device::ALL {
+			let read: fs::DeviceRead = match device {
+				Device::Keyboard => |position, buffer| read(Device::Keyboard, position, buffer),
+				Device::Mouse => |position, buffer| read(Device::Mouse, position, buffer),
+			};
+			let _ = fs.create_device(device.path(), read);
+		}
+	});
+}
+
+/// Test turning scancodes into key events and reading them back as bytes
+#[test_case]
+fn test_keyboard_events() {
+	let start = next_seq(Device::Keyboard);
+	// A down, A repeat, Up arrow down, A up, then Pause
+	for &scancode in &[0x1e, 0x1e, 0xe0, 0x48, 0x9e, 0xe1, 0x1d, 0x45, 0xe1, 0x9d, 0xc5] {
+		keyboard_scancode(scancode);
+	}
+	let mut position = start as usize;
+	let mut buffer = [0; EVENT_SIZE * 10];
+	assert_eq!(read(Device::Keyboard, &mut position, &mut buffer), EVENT_SIZE * 8);
+	assert_eq!(position as u64, start + 8);
+	assert_eq!(read(Device::Keyboard, &mut position, &mut buffer[..EVENT_SIZE]), 0);
+
+	let event = |index: usize| {
+		let bytes = &buffer[index * EVENT_SIZE..][..EVENT_SIZE];
+		let field = |range: core::ops::Range<usize>| bytes[range].iter().rev().fold(0, |value, &byte| value << 8 | u64::from(byte));
+		(field(16..18) as u16, field(18..20) as u16, field(20..24) as i32)
+	};
+	let key = EventType::Key as u16;
+	assert_eq!([event(0), event(2), event(4), event(6)], [(key, 30, KEY_DOWN), (key, 30, KEY_REPEAT), (key, 103, KEY_DOWN), (key, 30, KEY_UP)]);
+	assert_eq!(event(7), (EventType::Sync as u16, SYN_REPORT, 0));
+	keyboard_scancode(0xe0);
+	keyboard_scancode(0xc8);
+}
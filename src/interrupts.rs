@@ -1,16 +1,16 @@
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
-use crate::{println, gdt, hlt_loop};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 use lazy_static::lazy_static;
+use x86_64::VirtAddr;
 use pic8259::ChainedPics;
-use spin;
+use core::cell::Cell;
 
 /// Offset for PIC interrupts
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
 /// Chained PICs for handling hardware interrupts
-pub static PICS: spin::Mutex<ChainedPics> =
-	spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+pub static PICS: crate::sync::Mutex<ChainedPics> =
+	crate::sync::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
 /// Hardware interrupt numbers
 #[derive(Debug, Clone, Copy)]
@@ -18,15 +18,20 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub enum InterruptIndex {
 	Timer = PIC_1_OFFSET,
 	Keyboard,
+	/// COM1, on IRQ 4
+	Serial = PIC_1_OFFSET + 4,
+	/// The CMOS real-time clock, on IRQ 8
+	Rtc = PIC_2_OFFSET,
 }
 
 impl InterruptIndex {
-	fn as_u8(self) -> u8 {
+	const fn as_u8(self) -> u8 {
 		self as u8
 	}
 
-	fn as_usize(self) -> usize {
-		usize::from(self.as_u8())
+	/// The ISA IRQ line this interrupt arrives on
+	pub(crate) const fn irq(self) -> u8 {
+		self.as_u8() - PIC_1_OFFSET
 	}
 }
 
@@ -36,79 +41,352 @@ lazy_static! {
 		let mut idt = InterruptDescriptorTable::new();
 		
 		// CPU Exception handlers
-		idt.breakpoint.set_handler_fn(breakpoint_handler);
-		unsafe {
-			idt.double_fault.set_handler_fn(double_fault_handler)
-				.set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+		let debugger = crate::gdbstub::enabled();
+		if debugger {
+			// The stub needs every register, so it has its own entry code
+			unsafe {
+				idt.debug.set_handler_addr(VirtAddr::new(crate::gdbstub::debug_entry as usize as u64));
+				idt.breakpoint.set_handler_addr(VirtAddr::new(crate::gdbstub::breakpoint_entry as usize as u64));
+			}
 		}
-		idt.page_fault.set_handler_fn(page_fault_handler);
+		crate::exceptions::install(&mut idt, debugger);
 		
-		// Hardware interrupt handlers
-		idt[InterruptIndex::Timer.as_usize()]
-			.set_handler_fn(timer_interrupt_handler);
-		idt[InterruptIndex::Keyboard.as_usize()]
-			.set_handler_fn(keyboard_interrupt_handler);
+		// Hardware interrupt handlers, which run whatever is registered
+		for (irq, handler) in IRQ_ENTRIES.into_iter().enumerate() {
+			idt[PIC_1_OFFSET as usize + irq].set_handler_fn(handler);
+		}
+		for (index, handler) in VECTOR_ENTRIES.into_iter().enumerate() {
+			idt[FIRST_DYNAMIC_VECTOR as usize + index].set_handler_fn(handler);
+		}
+		idt[crate::apic::SPURIOUS_VECTOR as usize]
+			.set_handler_fn(spurious_interrupt_handler);
+		idt[crate::smp::RESCHEDULE_VECTOR as usize]
+			.set_handler_fn(reschedule_interrupt_handler);
+		idt[crate::smp::CALL_FUNCTION_VECTOR as usize]
+			.set_handler_fn(request_interrupt_handler);
+		idt[crate::smp::TLB_FLUSH_VECTOR as usize]
+			.set_handler_fn(request_interrupt_handler);
 		
 		idt
 	};
 }
 
-/// Initialize the Interrupt Descriptor Table
-pub fn init_idt() {
-	IDT.load();
+/// ISA IRQ lines, each with its own vector from `PIC_1_OFFSET`
+pub const IRQ_COUNT: u8 = 16;
+/// Handlers that can share one IRQ line
+const HANDLERS_PER_IRQ: usize = 4;
+
+/// A driver's interrupt handler, run with interrupts disabled; on a shared
+/// line it must check that its own device raised the interrupt
+pub type IrqHandler = fn();
+
+/// Why an IRQ handler could not be registered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+	/// The IRQ is not an ISA line
+	InvalidIrq,
+	/// The line already has as many handlers as it can share
+	Full,
+}
+
+/// The handlers of every IRQ line, in the order they run; the kernel's own
+/// devices are there from the start
+static HANDLERS: crate::sync::Mutex<[[Option<IrqHandler>; HANDLERS_PER_IRQ]; IRQ_COUNT as usize]> = {
+	let mut handlers = [[None; HANDLERS_PER_IRQ]; IRQ_COUNT as usize];
+	handlers[InterruptIndex::Timer.irq() as usize][0] = Some(timer_interrupt as IrqHandler);
+	handlers[InterruptIndex::Keyboard.irq() as usize][0] = Some(keyboard_interrupt as IrqHandler);
+	handlers[InterruptIndex::Serial.irq() as usize][0] = Some(serial_interrupt as IrqHandler);
+	handlers[InterruptIndex::Rtc.irq() as usize][0] = Some(crate::rtc::handle_interrupt as IrqHandler);
+	crate::sync::Mutex::new(handlers)
+};
+
+/// Add a handler to an IRQ line, after any already there
+///
+/// The line is not unmasked; call `enable_irq` once the device is ready.
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+	let line = usize::from(irq);
+	if irq >= IRQ_COUNT {
+		return Err(IrqError::InvalidIrq);
+	}
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut handlers = HANDLERS.lock();
+		let slot = handlers[line].iter_mut().find(|slot| slot.is_none()).ok_or(IrqError::Full)?;
+		*slot = Some(handler);
+		Ok(())
+	})
+}
+
+/// Remove a handler from an IRQ line, returning whether it was there
+pub fn unregister_irq(irq: u8, handler: IrqHandler) -> bool {
+	let Some(line) = (irq < IRQ_COUNT).then_some(usize::from(irq)) else {
+		return false;
+	};
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut handlers = HANDLERS.lock();
+		let chain = &mut handlers[line];
+		let Some(index) = chain.iter().position(|slot| slot.is_some_and(|h| core::ptr::fn_addr_eq(h, handler))) else {
+			return false;
+		};
+		// Keep the chain in order with no gaps
+		chain[index..].rotate_left(1);
+		chain[HANDLERS_PER_IRQ - 1] = None;
+		true
+	})
+}
+
+crate::per_cpu! {
+	/// IRQ handlers running on each processor, nested or not
+	static IRQ_DEPTH: Cell<usize> = Cell::new(0);
+}
+
+/// Whether this processor is running an IRQ handler
+pub fn in_interrupt() -> bool {
+	IRQ_DEPTH.with(|depth| depth.get() > 0)
 }
 
-/// Breakpoint exception handler
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-	println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+/// Run the handlers of an IRQ line and acknowledge it
+fn dispatch(irq: u8) {
+	// Copy the chain so handlers can register others
+	let chain = HANDLERS.lock()[usize::from(irq)];
+	IRQ_DEPTH.with(|depth| depth.set(depth.get() + 1));
+	let started = crate::trace::enabled(crate::trace::Subsystem::Irq).then(crate::trace::timestamp);
+	for handler in chain.into_iter().flatten() {
+		handler();
+	}
+	if let Some(started) = started {
+		crate::trace_event!(Irq, "irq {} handled in {} cycles", irq, crate::trace::timestamp() - started);
+	}
+	IRQ_DEPTH.with(|depth| depth.set(depth.get() - 1));
+	end_of_interrupt(irq);
 }
 
-/// Double fault exception handler - critical system error
-extern "x86-interrupt" fn double_fault_handler(
-	stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
-	panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+/// Generate the interrupt entry of each IRQ line, which dispatches to its
+/// registered handlers
+macro_rules! irq_entries {
+	($($irq:literal => $name:ident),* $(,)?) => {
+		$(
+			extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
+				if $irq == InterruptIndex::Timer.irq() {
+					crate::profile::sample(&stack_frame);
+					crate::watchdog::check(&stack_frame);
+				}
+				dispatch($irq);
+			}
+		)*
+
+		const IRQ_ENTRIES: [extern "x86-interrupt" fn(InterruptStackFrame); IRQ_COUNT as usize] = [$($name),*];
+	};
 }
 
-/// Page fault exception handler
-extern "x86-interrupt" fn page_fault_handler(
-	stack_frame: InterruptStackFrame,
-	error_code: PageFaultErrorCode,
-) {
-	use x86_64::registers::control::Cr2;
+irq_entries! {
+	0 => irq0_entry, 1 => irq1_entry, 2 => irq2_entry, 3 => irq3_entry,
+	4 => irq4_entry, 5 => irq5_entry, 6 => irq6_entry, 7 => irq7_entry,
+	8 => irq8_entry, 9 => irq9_entry, 10 => irq10_entry, 11 => irq11_entry,
+	12 => irq12_entry, 13 => irq13_entry, 14 => irq14_entry, 15 => irq15_entry,
+}
 
-	println!("EXCEPTION: PAGE FAULT");
-	println!("Accessed Address: {:?}", Cr2::read());
-	println!("Error Code: {:?}", error_code);
-	println!("{:#?}", stack_frame);
-	hlt_loop();
+/// First of the vectors handed out to devices that interrupt by message,
+/// such as PCI devices with MSI, rather than on an ISA line
+pub const FIRST_DYNAMIC_VECTOR: u8 = 0x50;
+/// Vectors that can be handed out
+const DYNAMIC_VECTOR_COUNT: usize = 32;
+
+/// The handler of each vector handed out, from `FIRST_DYNAMIC_VECTOR`
+static VECTOR_HANDLERS: crate::sync::Mutex<[Option<IrqHandler>; DYNAMIC_VECTOR_COUNT]> =
+	crate::sync::Mutex::new([None; DYNAMIC_VECTOR_COUNT]);
+
+/// Hand out a vector of its own to `handler`, or `None` if all are taken;
+/// the handler runs on whichever local APIC the interrupt is sent to
+pub fn allocate_vector(handler: IrqHandler) -> Option<u8> {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut handlers = VECTOR_HANDLERS.lock();
+		let index = handlers.iter().position(Option::is_none)?;
+		handlers[index] = Some(handler);
+		Some(FIRST_DYNAMIC_VECTOR + index as u8)
+	})
+}
+
+/// Give back a vector from `allocate_vector`
+pub fn free_vector(vector: u8) {
+	let Some(index) = vector.checked_sub(FIRST_DYNAMIC_VECTOR).map(usize::from) else {
+		return;
+	};
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		if let Some(handler) = VECTOR_HANDLERS.lock().get_mut(index) {
+			*handler = None;
+		}
+	});
+}
+
+/// Run the handler of a vector handed out; message interrupts always come
+/// through the local APIC
+fn dispatch_vector(index: usize) {
+	let handler = VECTOR_HANDLERS.lock()[index];
+	IRQ_DEPTH.with(|depth| depth.set(depth.get() + 1));
+	if let Some(handler) = handler {
+		handler();
+	}
+	IRQ_DEPTH.with(|depth| depth.set(depth.get() - 1));
+	crate::apic::end_of_interrupt();
+}
+
+/// Generate the interrupt entry of each vector that can be handed out
+macro_rules! vector_entries {
+	($($index:literal => $name:ident),* $(,)?) => {
+		$(
+			extern "x86-interrupt" fn $name(_stack_frame: InterruptStackFrame) {
+				dispatch_vector($index);
+			}
+		)*
+
+		const VECTOR_ENTRIES: [extern "x86-interrupt" fn(InterruptStackFrame); DYNAMIC_VECTOR_COUNT] = [$($name),*];
+	};
+}
+
+vector_entries! {
+	0 => vector0_entry, 1 => vector1_entry, 2 => vector2_entry, 3 => vector3_entry,
+	4 => vector4_entry, 5 => vector5_entry, 6 => vector6_entry, 7 => vector7_entry,
+	8 => vector8_entry, 9 => vector9_entry, 10 => vector10_entry, 11 => vector11_entry,
+	12 => vector12_entry, 13 => vector13_entry, 14 => vector14_entry, 15 => vector15_entry,
+	16 => vector16_entry, 17 => vector17_entry, 18 => vector18_entry, 19 => vector19_entry,
+	20 => vector20_entry, 21 => vector21_entry, 22 => vector22_entry, 23 => vector23_entry,
+	24 => vector24_entry, 25 => vector25_entry, 26 => vector26_entry, 27 => vector27_entry,
+	28 => vector28_entry, 29 => vector29_entry, 30 => vector30_entry, 31 => vector31_entry,
+}
+
+/// Unmask the serial port's IRQ so received bytes raise an interrupt; the
+/// port itself is set up to raise one when `SERIAL1` is first used
+pub fn enable_serial_input() {
+	lazy_static::initialize(&crate::serial::SERIAL1);
+	enable_irq(InterruptIndex::Serial.irq());
+}
+
+/// Let an ISA IRQ through to its handlers, at the I/O APIC if it delivers
+/// interrupts or else at the 8259s
+pub fn enable_irq(irq: u8) {
+	set_irq_masked(irq, false);
+}
+
+/// Stop an ISA IRQ at the interrupt controller
+pub fn mask_irq(irq: u8) {
+	set_irq_masked(irq, true);
+}
+
+fn set_irq_masked(irq: u8, masked: bool) {
+	if irq >= IRQ_COUNT {
+		return;
+	}
+	if crate::apic::enabled() {
+		crate::apic::set_isa_irq(irq, masked);
+		return;
+	}
+	x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+		let mut pics = PICS.lock();
+		let [mut primary, mut secondary] = pics.read_masks();
+		let (mask, bit) = if irq < 8 { (&mut primary, irq) } else { (&mut secondary, irq - 8) };
+		if masked {
+			*mask |= 1 << bit;
+		} else {
+			*mask &= !(1 << bit);
+			// The secondary PIC reaches the CPU through IRQ 2
+			if irq >= 8 {
+				primary &= !(1 << 2);
+			}
+		}
+		pics.write_masks(primary, secondary);
+	});
+}
+
+/// Acknowledge a hardware interrupt to whichever controller delivered it
+fn end_of_interrupt(irq: u8) {
+	if crate::apic::enabled() {
+		crate::apic::end_of_interrupt();
+	} else {
+		unsafe { PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + irq) };
+	}
+}
+
+/// Initialize the Interrupt Descriptor Table, and turn on machine checks
+/// now that there is a handler for them
+pub fn init_idt() {
+	IDT.load();
+	crate::exceptions::init_machine_check();
 }
 
 /// Timer interrupt handler for preemptive multitasking
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+fn timer_interrupt() {
+	crate::time::tick();
+	crate::task::timer::tick();
+	crate::random::add_timer_tick();
+	crate::task::executor::watchdog();
+	crate::check_test_timeout();
+	// Show what the framebuffer console drew since the last tick
+	crate::framebuffer::present();
 	// TODO: Implement process scheduling here
-	unsafe {
-		PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-	}
 }
 
 /// Keyboard interrupt handler
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+fn keyboard_interrupt() {
 	use x86_64::instructions::port::Port;
 
 	let mut port = Port::new(0x60);
 	let scancode: u8 = unsafe { port.read() };
+	crate::random::add_keyboard_timing(scancode);
 	
 	// Add scancode to async processing queue
 	crate::task::keyboard::add_scancode(scancode);
+}
 
-	unsafe {
-		PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+/// Serial port interrupt handler
+fn serial_interrupt() {
+	// Drain the receive FIFO, which may hold several bytes per interrupt
+	while let Some(byte) = crate::serial::read_byte() {
+		crate::task::serial::add_byte(byte);
 	}
 }
 
+/// Spurious interrupts from the local APIC, which must not be acknowledged
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+/// Another processor woke this one to run its tasks; returning from the
+/// interrupt is all it takes
+extern "x86-interrupt" fn reschedule_interrupt_handler(_stack_frame: InterruptStackFrame) {
+	crate::apic::end_of_interrupt();
+}
+
+/// Another processor asked this one to run a function or flush its TLB
+extern "x86-interrupt" fn request_interrupt_handler(_stack_frame: InterruptStackFrame) {
+	crate::smp::handle_requests();
+	crate::apic::end_of_interrupt();
+}
+
 /// Test for breakpoint exception
 #[test_case]
 fn test_breakpoint_exception() {
 	// Invoke a breakpoint exception to test the handler
 	x86_64::instructions::interrupts::int3();
+}
+
+/// Test sharing an IRQ line between registered handlers
+#[test_case]
+fn test_register_irq() {
+	fn first() {}
+	fn second() {}
+
+	let line = IRQ_COUNT - 1;
+	assert_eq!(register_irq(IRQ_COUNT, first), Err(IrqError::InvalidIrq));
+	assert_eq!(register_irq(line, first), Ok(()));
+	assert_eq!(register_irq(line, second), Ok(()));
+	assert!(unregister_irq(line, first));
+	assert!(!unregister_irq(line, first));
+	let chain = HANDLERS.lock()[usize::from(line)];
+	assert!(chain[0].is_some_and(|handler| core::ptr::fn_addr_eq(handler, second as IrqHandler)));
+	assert!(chain[1].is_none());
+	assert!(unregister_irq(line, second));
+
+	let vector = allocate_vector(first).unwrap();
+	assert!(vector >= FIRST_DYNAMIC_VECTOR);
+	assert_ne!(allocate_vector(second), Some(vector));
+	free_vector(vector);
+	assert_eq!(allocate_vector(first), Some(vector));
 } 
\ No newline at end of file
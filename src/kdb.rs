@@ -0,0 +1,336 @@
+use crate::panic::{self, Registers};
+use crate::{cmdline, ksyms, memory, power, process, serial, task};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use uart_16550::SerialPort;
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// Longest command line taken
+const LINE_SIZE: usize = 80;
+/// Most bytes `x` shows at once, and how many without a length
+const MAX_EXAMINE: usize = 256;
+const DEFAULT_EXAMINE: usize = 64;
+const BYTES_PER_LINE: usize = 16;
+
+/// Control characters the line editor understands
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+const CTRL_U: u8 = 0x15;
+
+/// Set while the debugger runs, so a fault inside it goes to the panic
+/// screen rather than back in
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// How the debugger was entered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry {
+	Panic,
+	/// An `int3`, with the kernel booted with `kdb`
+	Breakpoint,
+	/// Alt+SysRq on the keyboard
+	Hotkey,
+}
+
+impl Entry {
+	fn name(self) -> &'static str {
+		match self {
+			Entry::Panic => "panic",
+			Entry::Breakpoint => "breakpoint",
+			Entry::Hotkey => "Alt+SysRq",
+		}
+	}
+}
+
+/// Whether a panic or Alt+SysRq enters the debugger; `kdb=off` turns it off
+pub fn enabled() -> bool {
+	cmdline::param("kdb") != Some("off")
+}
+
+/// Whether an `int3` enters the debugger, which needs the kernel booted with
+/// `kdb`; otherwise a breakpoint is only reported
+pub fn on_breakpoint() -> bool {
+	cmdline::param("kdb").is_some_and(|value| value != "off")
+}
+
+/// A command typed at the `kdb>` prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+	Help,
+	Registers,
+	/// Dump memory: address and length
+	Examine(u64, usize),
+	/// Walk frame pointers from the given `rbp`, or the debugger's own
+	Backtrace(Option<u64>),
+	Tasks,
+	Processes,
+	Symbol(u64),
+	Continue,
+	Reboot,
+}
+
+/// A hexadecimal number, with or without `0x`
+fn parse_hex(text: &str) -> Option<u64> {
+	let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+	u64::from_str_radix(digits, 16).ok()
+}
+
+/// Parse a command line; `Ok(None)` for an empty one
+fn parse(line: &str) -> Result<Option<Command>, &'static str> {
+	let mut words = line.split_whitespace();
+	let Some(name) = words.next() else {
+		return Ok(None);
+	};
+	let args: [Option<&str>; 2] = [words.next(), words.next()];
+	if words.next().is_some() {
+		return Err("too many arguments");
+	}
+	let address = |arg: Option<&str>| arg.and_then(parse_hex).ok_or("expected a hex address");
+	let command = match (name, args) {
+		("help" | "?", [None, None]) => Command::Help,
+		("regs" | "r", [None, None]) => Command::Registers,
+		("x", [addr, None]) => Command::Examine(address(addr)?, DEFAULT_EXAMINE),
+		("x", [addr, Some(len)]) => {
+			let len = len.parse().ok().filter(|&len| (1..=MAX_EXAMINE).contains(&len)).ok_or("length must be 1 to 256")?;
+			Command::Examine(address(addr)?, len)
+		}
+		("bt", [None, None]) => Command::Backtrace(None),
+		("bt", [rbp, None]) => Command::Backtrace(Some(address(rbp)?)),
+		("tasks", [None, None]) => Command::Tasks,
+		("ps", [None, None]) => Command::Processes,
+		("sym", [addr, None]) => Command::Symbol(address(addr)?),
+		("c" | "continue", [None, None]) => Command::Continue,
+		("reboot", [None, None]) => Command::Reboot,
+		("help" | "?" | "regs" | "r" | "bt" | "tasks" | "ps" | "sym" | "c" | "continue" | "reboot", _) => {
+			return Err("wrong arguments; try help");
+		}
+		_ => return Err("unknown command; try help"),
+	};
+	Ok(Some(command))
+}
+
+/// COM1, driven directly: whatever holds `SERIAL1` may never let it go
+struct Console {
+	port: SerialPort,
+}
+
+impl Console {
+	fn new() -> Console {
+		// Already set up at boot; only its registers are used
+		Console { port: unsafe { SerialPort::new(serial::COM1) } }
+	}
+
+	/// Wait for a byte, keeping the panic screen's reboot key working
+	fn receive(&mut self, entry: Entry) -> u8 {
+		loop {
+			if let Some(byte) = serial::read_byte() {
+				return byte;
+			}
+			if entry == Entry::Panic {
+				panic::poll_reboot_key();
+			}
+			core::hint::spin_loop();
+		}
+	}
+
+	/// Read a line, echoing it, into `line`; returns its length
+	fn read_line(&mut self, entry: Entry, line: &mut [u8; LINE_SIZE]) -> usize {
+		let mut len = 0;
+		loop {
+			match self.receive(entry) {
+				b'\r' | b'\n' => {
+					let _ = self.write_str("\n");
+					return len;
+				}
+				BACKSPACE | DELETE if len > 0 => {
+					len -= 1;
+					let _ = self.write_str("\x08 \x08");
+				}
+				CTRL_U => {
+					for _ in 0..len {
+						let _ = self.write_str("\x08 \x08");
+					}
+					len = 0;
+				}
+				byte @ 0x20..=0x7e if len < LINE_SIZE => {
+					line[len] = byte;
+					len += 1;
+					self.port.send_raw(byte);
+				}
+				_ => {}
+			}
+		}
+	}
+}
+
+impl Write for Console {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		for byte in s.bytes() {
+			if byte == b'\n' {
+				self.port.send_raw(b'\r');
+			}
+			self.port.send_raw(byte);
+		}
+		Ok(())
+	}
+}
+
+/// Write an address, with the function it is in if the symbol map knows
+fn write_address(out: &mut Console, addr: u64) {
+	let _ = match ksyms::resolve(addr) {
+		Some(symbol) => writeln!(out, "{:#018x} {}", addr, symbol),
+		None => writeln!(out, "{:#018x}", addr),
+	};
+}
+
+/// Dump memory a line at a time, hex then ASCII
+fn examine(out: &mut Console, addr: u64, len: usize) {
+	if !memory::is_range_mapped(addr, len) {
+		let _ = writeln!(out, "{:#x}: not mapped", addr);
+		return;
+	}
+	let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+	for (i, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+		let _ = write!(out, "{:016x} ", addr + (i * BYTES_PER_LINE) as u64);
+		for slot in 0..BYTES_PER_LINE {
+			let _ = match chunk.get(slot) {
+				Some(byte) => write!(out, " {:02x}", byte),
+				None => write!(out, "   "),
+			};
+		}
+		let _ = write!(out, "  |");
+		for &byte in chunk {
+			let _ = out.write_char(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' });
+		}
+		let _ = writeln!(out, "|");
+	}
+}
+
+/// List kernel tasks, unless the task table is held by what was interrupted
+fn list_tasks(out: &mut Console) {
+	let _ = writeln!(out, "{:>4} {:>3} {:<5} {:>8} NAME", "ID", "CPU", "STATE", "POLLS");
+	let listed = task::stats::try_for_each(|id, name, cpu, state, polls| {
+		let _ = writeln!(out, "{:>4} {:>3} {:<5} {:>8} {}", id, cpu, state.name(), polls, name.unwrap_or("-"));
+	});
+	if !listed {
+		let _ = writeln!(out, "(task table locked)");
+	}
+}
+
+/// List processes, unless the scheduler is held by what was interrupted
+fn list_processes(out: &mut Console) {
+	let _ = writeln!(out, "{:>5} {:>5} {:<16} STATE", "PID", "PPID", "NAME");
+	let listed = process::try_with_scheduler(|scheduler| {
+		for process in scheduler.processes() {
+			let parent = process.parent_pid.map_or(0, |pid| pid.0);
+			let _ = writeln!(out, "{:>5} {:>5} {:<16} {:?}", process.pid.0, parent, process.name, process.state);
+		}
+	});
+	if listed.is_none() {
+		let _ = writeln!(out, "(scheduler locked)");
+	}
+}
+
+/// List the commands
+fn help(out: &mut Console) {
+	let _ = writeln!(out, "  regs           registers at entry");
+	let _ = writeln!(out, "  x ADDR [LEN]   dump memory (hex address, up to 256 bytes)");
+	let _ = writeln!(out, "  bt [RBP]       backtrace from here or from a frame pointer");
+	let _ = writeln!(out, "  tasks          kernel tasks");
+	let _ = writeln!(out, "  ps             processes");
+	let _ = writeln!(out, "  sym ADDR       name the function an address is in");
+	let _ = writeln!(out, "  c              continue (not after a panic)");
+	let _ = writeln!(out, "  reboot         restart the machine");
+}
+
+/// Run the debugger on the serial port until told to continue, polling with
+/// interrupts off so it works however wedged the executor is; the other
+/// processors carry on
+///
+/// `frame` is what the CPU saved, when entered from an exception. After a
+/// panic there is nothing to continue to, and this only returns if the
+/// debugger was already running.
+pub fn enter(entry: Entry, frame: Option<&InterruptStackFrame>) {
+	if ACTIVE.swap(true, Ordering::Acquire) {
+		return;
+	}
+	let interrupts_were_enabled = x86_64::instructions::interrupts::are_enabled();
+	x86_64::instructions::interrupts::disable();
+	let registers = Registers::capture();
+
+	let mut out = Console::new();
+	let _ = write!(out, "\nkdb: entered on {} (cpu {}", entry.name(), crate::smp::current_cpu());
+	if let Some(task) = task::executor::current_task() {
+		let _ = write!(out, ", task {}", task);
+	}
+	let _ = writeln!(out, "); help lists commands");
+	if let Some(frame) = frame {
+		let _ = write!(out, "at ");
+		write_address(&mut out, frame.instruction_pointer.as_u64());
+	}
+	let mut line = [0u8; LINE_SIZE];
+	loop {
+		let _ = write!(out, "kdb> ");
+		let len = out.read_line(entry, &mut line);
+		// Only printable ASCII is ever stored
+		let text = core::str::from_utf8(&line[..len]).unwrap_or("");
+		match parse(text) {
+			Ok(None) => {}
+			Ok(Some(Command::Help)) => help(&mut out),
+			Ok(Some(Command::Registers)) => {
+				if let Some(frame) = frame {
+					let _ = writeln!(out, "  RIP {:016x}  RSP {:016x}  RFLAGS {:016x}", frame.instruction_pointer.as_u64(), frame.stack_pointer.as_u64(), frame.cpu_flags);
+					let _ = writeln!(out, "  CS {:04x}  SS {:04x}", frame.code_segment, frame.stack_segment);
+				}
+				let _ = write!(out, "{}", registers);
+			}
+			Ok(Some(Command::Examine(addr, len))) => examine(&mut out, addr, len),
+			Ok(Some(Command::Backtrace(rbp))) => {
+				for (number, address) in panic::backtrace(rbp.unwrap_or(registers.rbp), panic::is_mapped).enumerate() {
+					let _ = write!(out, "  #{:<2} ", number);
+					write_address(&mut out, address);
+				}
+			}
+			Ok(Some(Command::Tasks)) => list_tasks(&mut out),
+			Ok(Some(Command::Processes)) => list_processes(&mut out),
+			Ok(Some(Command::Symbol(addr))) => match ksyms::resolve(addr) {
+				Some(symbol) => {
+					let _ = writeln!(out, "{}", symbol);
+				}
+				None => {
+					let _ = writeln!(out, "no symbol");
+				}
+			},
+			Ok(Some(Command::Continue)) if entry == Entry::Panic => {
+				let _ = writeln!(out, "cannot continue after a panic; reboot instead");
+			}
+			Ok(Some(Command::Continue)) => break,
+			Ok(Some(Command::Reboot)) => power::reboot(),
+			Err(message) => {
+				let _ = writeln!(out, "{}", message);
+			}
+		}
+	}
+	let _ = writeln!(out, "kdb: continuing");
+	// Time stood still here, as far as the kernel is concerned
+	crate::watchdog::resume();
+	ACTIVE.store(false, Ordering::Release);
+	if interrupts_were_enabled {
+		x86_64::instructions::interrupts::enable();
+	}
+}
+
+/// Test command parsing
+#[test_case]
+fn test_kdb_commands() {
+	assert_eq!(parse("   "), Ok(None));
+	assert_eq!(parse("regs"), Ok(Some(Command::Registers)));
+	assert_eq!(parse("x ffff8000"), Ok(Some(Command::Examine(0xffff_8000, DEFAULT_EXAMINE))));
+	assert_eq!(parse("x 0x1000 16"), Ok(Some(Command::Examine(0x1000, 16))));
+	assert!(parse("x 0x1000 4096").is_err());
+	assert_eq!(parse("bt"), Ok(Some(Command::Backtrace(None))));
+	assert_eq!(parse("bt 0x7fff00"), Ok(Some(Command::Backtrace(Some(0x7f_ff00)))));
+	assert_eq!(parse("c"), Ok(Some(Command::Continue)));
+	assert!(parse("sym").is_err());
+	assert!(parse("regs now").is_err());
+	assert!(parse("step").is_err());
+}
@@ -1,2 +1,157 @@
+use crate::time::Instant;
+use core::sync::atomic::{AtomicU16, AtomicU8, Ordering};
+use core::time::Duration;
+use pc_keyboard::KeyCode;
+use x86_64::instructions::port::Port;
+
 /// Re-export the async keyboard functionality
-pub use crate::task::keyboard::print_keypresses; 
\ No newline at end of file
+pub use crate::task::keyboard::print_keypresses;
+
+/// PS/2 controller ports
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+/// Status bit set while the controller has not taken the last byte written
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Status reads before giving up on the controller taking a byte
+const WRITE_TIMEOUT: usize = 100_000;
+
+/// Keyboard commands taking the byte after them as their argument
+const COMMAND_SET_LEDS: u8 = 0xed;
+const COMMAND_SET_TYPEMATIC: u8 = 0xf3;
+/// What the keyboard replies to a command byte
+const REPLY_ACK: u8 = 0xfa;
+const REPLY_RESEND: u8 = 0xfe;
+
+/// Lock keys, as bits of the LED command's argument
+pub const SCROLL_LOCK: u8 = 1 << 0;
+pub const NUM_LOCK: u8 = 1 << 1;
+pub const CAPS_LOCK: u8 = 1 << 2;
+
+/// Lock keys turned on; Num Lock starts on, as the decoder expects
+static LOCKS: AtomicU8 = AtomicU8::new(NUM_LOCK);
+/// The argument of the command waiting for the keyboard to acknowledge
+/// it, or `NO_ARGUMENT`
+static ARGUMENT_PENDING: AtomicU16 = AtomicU16::new(NO_ARGUMENT);
+const NO_ARGUMENT: u16 = u16::MAX;
+/// How long a command waits for the one before it to be acknowledged
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Typematic delays the keyboard can wait before repeating a held key
+pub const TYPEMATIC_DELAYS_MS: [u32; 4] = [250, 500, 750, 1000];
+
+/// The lock keys turned on, as `*_LOCK` bits
+pub fn locks() -> u8 {
+	LOCKS.load(Ordering::Relaxed)
+}
+
+/// The lock a key toggles, if it is a lock key
+pub fn lock_key(code: KeyCode) -> Option<u8> {
+	match code {
+		KeyCode::CapsLock => Some(CAPS_LOCK),
+		KeyCode::NumpadLock => Some(NUM_LOCK),
+		KeyCode::ScrollLock => Some(SCROLL_LOCK),
+		_ => None,
+	}
+}
+
+/// Turn a lock on or off and show it on the keyboard's LEDs
+pub fn toggle_lock(lock: u8) {
+	LOCKS.fetch_xor(lock, Ordering::Relaxed);
+	update_leds();
+}
+
+/// Write a byte to the keyboard once the controller can take it
+fn write_data(byte: u8) {
+	let mut status: Port<u8> = Port::new(STATUS_PORT);
+	for _ in 0..WRITE_TIMEOUT {
+		if unsafe { status.read() } & STATUS_INPUT_FULL == 0 {
+			unsafe { Port::new(DATA_PORT).write(byte) };
+			return;
+		}
+		core::hint::spin_loop();
+	}
+}
+
+/// Send a command with an argument, which follows once the keyboard
+/// acknowledges the command
+fn send_command(command: u8, argument: u8) {
+	// With interrupts on, the acknowledgement of the last one clears it
+	let start = Instant::now();
+	while ARGUMENT_PENDING.load(Ordering::Relaxed) != NO_ARGUMENT && start.elapsed() < COMMAND_TIMEOUT {
+		core::hint::spin_loop();
+	}
+	ARGUMENT_PENDING.store(u16::from(argument), Ordering::Relaxed);
+	write_data(command);
+}
+
+/// Ask the keyboard to light the LEDs of the locks that are on
+pub fn update_leds() {
+	send_command(COMMAND_SET_LEDS, locks());
+}
+
+/// Tenths of a repeat a second for each typematic rate setting, fastest
+/// first: a period of `(8 + low 3 bits) << high 2 bits` times 4.17 ms
+fn typematic_rate(setting: u8) -> u32 {
+	let period = (8 + u32::from(setting & 7)) << (setting >> 3 & 3);
+	2398 / period
+}
+
+/// The typematic argument closest to a delay and a rate (in tenths of a
+/// repeat a second), with the delay and rate it gives
+pub fn typematic_setting(delay_ms: u32, rate: u32) -> (u8, u32, u32) {
+	let delay = (0..TYPEMATIC_DELAYS_MS.len()).min_by_key(|&index| TYPEMATIC_DELAYS_MS[index].abs_diff(delay_ms)).unwrap_or(0);
+	let setting = (0..32).min_by_key(|&setting| typematic_rate(setting).abs_diff(rate)).unwrap_or(0);
+	((delay as u8) << 5 | setting, TYPEMATIC_DELAYS_MS[delay], typematic_rate(setting))
+}
+
+/// Set how long a held key waits before repeating and how fast it then
+/// repeats, returning the nearest delay and rate the keyboard has
+pub fn set_typematic(delay_ms: u32, rate: u32) -> (u32, u32) {
+	let (argument, delay_ms, rate) = typematic_setting(delay_ms, rate);
+	send_command(COMMAND_SET_TYPEMATIC, argument);
+	(delay_ms, rate)
+}
+
+/// Handle a byte from the keyboard that replies to a command rather than
+/// reporting a key, returning whether it was one
+///
+/// Called by the keyboard interrupt handler before decoding.
+pub(crate) fn command_reply(byte: u8) -> bool {
+	match byte {
+		REPLY_ACK => {
+			let argument = ARGUMENT_PENDING.swap(NO_ARGUMENT, Ordering::Relaxed);
+			if argument != NO_ARGUMENT {
+				write_data(argument as u8);
+			}
+			true
+		}
+		// The command is dropped; LEDs, say, keep their old state until the
+		// next lock key
+		REPLY_RESEND => {
+			ARGUMENT_PENDING.store(NO_ARGUMENT, Ordering::Relaxed);
+			true
+		}
+		_ => false,
+	}
+}
+
+/// Test lock toggling, typematic encoding, and that command replies are
+/// kept from the decoder
+#[test_case]
+fn test_locks() {
+	let before = locks();
+	toggle_lock(CAPS_LOCK);
+	assert_eq!(locks(), before ^ CAPS_LOCK);
+	assert_eq!(ARGUMENT_PENDING.load(Ordering::Relaxed), u16::from(locks()));
+	assert!(command_reply(REPLY_ACK));
+	assert_eq!(ARGUMENT_PENDING.load(Ordering::Relaxed), NO_ARGUMENT);
+	toggle_lock(CAPS_LOCK);
+	assert!(command_reply(REPLY_RESEND));
+	assert_eq!(locks(), before);
+	assert_eq!(lock_key(KeyCode::NumpadLock), Some(NUM_LOCK));
+	assert!(!command_reply(0x1e));
+
+	assert_eq!(typematic_setting(250, 300), (0x00, 250, 299));
+	assert_eq!(typematic_setting(500, 109), (0x2b, 500, 109));
+	assert_eq!(typematic_setting(5000, 0), (0x7f, 1000, 19));
+}
@@ -0,0 +1,106 @@
+use crate::{cmdline, keyboard, klog};
+use core::sync::atomic::{AtomicU8, Ordering};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, KeyboardLayout, Modifiers, ScancodeSet1};
+
+/// A keyboard layout the decoder can map keys with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+	Us,
+	Uk,
+	Dvorak,
+	Azerty,
+	Jis,
+}
+
+impl Layout {
+	/// Every layout, in the order `keymap` lists them
+	pub const ALL: [Layout; 5] = [Layout::Us, Layout::Uk, Layout::Dvorak, Layout::Azerty, Layout::Jis];
+
+	/// The name `keymap` and the `keymap=` boot parameter take
+	pub fn name(self) -> &'static str {
+		match self {
+			Layout::Us => "us",
+			Layout::Uk => "uk",
+			Layout::Dvorak => "dvorak",
+			Layout::Azerty => "azerty",
+			Layout::Jis => "jis",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Layout> {
+		Layout::ALL.into_iter().find(|layout| layout.name() == name)
+	}
+}
+
+/// Index into `Layout::ALL` of the layout keys are mapped with
+static CURRENT: AtomicU8 = AtomicU8::new(Layout::Us as u8);
+
+/// The layout keys are mapped with
+pub fn current() -> Layout {
+	Layout::ALL[CURRENT.load(Ordering::Relaxed) as usize]
+}
+
+/// Map keys with another layout from the next keypress on
+pub fn set(layout: Layout) {
+	CURRENT.store(layout as u8, Ordering::Relaxed);
+}
+
+/// Choose the layout the `keymap=` boot parameter names, if any
+pub fn init() {
+	let Some(name) = cmdline::param("keymap") else {
+		return;
+	};
+	match Layout::from_name(name) {
+		Some(layout) => set(layout),
+		None => klog!(Warn, "keymap: unknown layout '{}'", name),
+	}
+}
+
+/// The layout `set` last chose, looked up on every key so decoders built
+/// before a switch follow it, with the keyboard driver's lock keys
+pub struct Configured;
+
+impl KeyboardLayout for Configured {
+	fn map_keycode(keycode: KeyCode, modifiers: &Modifiers, handle_ctrl: HandleControl) -> DecodedKey {
+		// The keyboard driver's lock state, which the LEDs show, rather than
+		// each decoder's own
+		let locks = keyboard::locks();
+		let modifiers = &Modifiers {
+			numlock: locks & keyboard::NUM_LOCK != 0,
+			capslock: locks & keyboard::CAPS_LOCK != 0,
+			..*modifiers
+		};
+		match current() {
+			Layout::Us => layouts::Us104Key::map_keycode(keycode, modifiers, handle_ctrl),
+			Layout::Uk => layouts::Uk105Key::map_keycode(keycode, modifiers, handle_ctrl),
+			Layout::Dvorak => layouts::Dvorak104Key::map_keycode(keycode, modifiers, handle_ctrl),
+			Layout::Azerty => layouts::Azerty::map_keycode(keycode, modifiers, handle_ctrl),
+			Layout::Jis => layouts::Jis109Key::map_keycode(keycode, modifiers, handle_ctrl),
+		}
+	}
+}
+
+/// A scancode decoder that maps keys with the configured layout, and
+/// Ctrl+letter to control characters (Ctrl-S as '\x13')
+pub fn decoder() -> Keyboard<Configured, ScancodeSet1> {
+	Keyboard::new(Configured, ScancodeSet1, HandleControl::MapLettersToUnicode)
+}
+
+/// Test that names round-trip and the decoder follows the chosen layout
+#[test_case]
+fn test_layouts() {
+	use pc_keyboard::{KeyEvent, KeyState};
+
+	assert!(Layout::ALL.iter().all(|&layout| Layout::from_name(layout.name()) == Some(layout)));
+	assert_eq!(Layout::from_name("klingon"), None);
+	let before = current();
+	let mut keyboard = decoder();
+	let mut press = |code| keyboard.process_keyevent(KeyEvent::new(code, KeyState::Down));
+	set(Layout::Us);
+	assert_eq!(press(KeyCode::Q), Some(DecodedKey::Unicode('q')));
+	set(Layout::Azerty);
+	assert_eq!(press(KeyCode::Q), Some(DecodedKey::Unicode('a')));
+	set(Layout::Dvorak);
+	assert_eq!(press(KeyCode::Q), Some(DecodedKey::Unicode('\'')));
+	set(before);
+}
@@ -0,0 +1,257 @@
+use crate::{cmdline, console, serial_println, time, vga_buffer};
+use crate::ring::{FixedText, Ring};
+use conquer_once::spin::OnceCell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+use crate::sync::Mutex;
+
+/// Number of records the kernel log keeps before overwriting the oldest
+pub const LOG_CAPACITY: usize = 256;
+/// Longest message stored per record; longer messages are truncated
+pub const MESSAGE_LEN: usize = 120;
+
+/// Message severity, most severe first (syslog numbering, plus `Trace`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+	Emerg = 0,
+	Alert = 1,
+	Crit = 2,
+	Err = 3,
+	Warn = 4,
+	Notice = 5,
+	Info = 6,
+	Debug = 7,
+	/// Finer than debug, for messages too frequent to keep by default
+	Trace = 8,
+}
+
+impl Level {
+	/// Every level, most severe first
+	pub const ALL: [Level; 9] = [
+		Level::Emerg, Level::Alert, Level::Crit, Level::Err,
+		Level::Warn, Level::Notice, Level::Info, Level::Debug, Level::Trace,
+	];
+
+	/// Short name as used by `dmesg -l`
+	pub fn name(self) -> &'static str {
+		match self {
+			Level::Emerg => "emerg",
+			Level::Alert => "alert",
+			Level::Crit => "crit",
+			Level::Err => "err",
+			Level::Warn => "warn",
+			Level::Notice => "notice",
+			Level::Info => "info",
+			Level::Debug => "debug",
+			Level::Trace => "trace",
+		}
+	}
+
+	/// Look up a level by name or number
+	pub fn from_name(name: &str) -> Option<Level> {
+		match name.parse::<usize>() {
+			Ok(n) => Level::ALL.get(n).copied(),
+			Err(_) => Level::ALL.into_iter().find(|level| level.name() == name),
+		}
+	}
+}
+
+/// One kernel log message
+#[derive(Clone, Copy)]
+pub struct Record {
+	/// Milliseconds since boot when the message was logged
+	pub timestamp_ms: u64,
+	pub level: Level,
+	/// Module that logged the message, e.g. `scottos::pci`
+	pub target: &'static str,
+	text: FixedText<MESSAGE_LEN>,
+}
+
+impl Record {
+	const EMPTY: Record = Record { timestamp_ms: 0, level: Level::Info, target: "", text: FixedText::EMPTY };
+
+	/// The message text
+	pub fn message(&self) -> &str {
+		self.text.as_str()
+	}
+}
+
+/// Fixed-size ring of log records, numbered by a running sequence
+type LogBuffer = Ring<Record, LOG_CAPACITY>;
+
+static LOG: Mutex<LogBuffer> = Mutex::new(LogBuffer::new(Record::EMPTY));
+
+/// Messages at this level or more severe are also printed on the console
+static CONSOLE_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Least severe level logged by modules the filter does not name
+const DEFAULT_LEVEL: Level = Level::Debug;
+
+/// Which modules log at which levels, e.g. `info,scottos::pci=trace`:
+/// the `log=` boot parameter
+static FILTER: OnceCell<&'static str> = OnceCell::uninit();
+
+/// The least severe level `target` logs at under `filter`: the level of the
+/// longest module path matching it, else the bare level, else `DEFAULT_LEVEL`
+fn filter_level(filter: &str, target: &str) -> Level {
+	let mut level = DEFAULT_LEVEL;
+	let mut matched = None;
+	for directive in filter.split(',') {
+		let (path, name) = match directive.split_once('=') {
+			Some((path, name)) => (Some(path), name),
+			None => (None, directive),
+		};
+		let Some(directive_level) = Level::from_name(name) else {
+			continue;
+		};
+		let length = match path {
+			None => 0,
+			Some(path) if target == path || target.strip_prefix(path).is_some_and(|rest| rest.starts_with("::")) => path.len() + 1,
+			Some(_) => continue,
+		};
+		if matched.is_none_or(|longest| length >= longest) {
+			matched = Some(length);
+			level = directive_level;
+		}
+	}
+	level
+}
+
+/// Whether a message from module `target` at `level` is logged
+pub fn enabled(level: Level, target: &str) -> bool {
+	let filter = FILTER.try_get().map_or("", |filter| *filter);
+	level <= filter_level(filter, target)
+}
+
+/// Apply the `log=` filter and `loglevel=` console level from the kernel
+/// command line
+pub fn init() {
+	if let Some(filter) = cmdline::param("log") {
+		let _ = FILTER.try_init_once(|| filter);
+	}
+	if let Some(name) = cmdline::param("loglevel") {
+		match Level::from_name(name) {
+			Some(level) => set_console_level(level),
+			None => log(Level::Warn, module_path!(), format_args!("loglevel: unknown level '{}'", name)),
+		}
+	}
+}
+
+/// Record a message from module `target` in the kernel log, echoing it to
+/// the serial port and, depending on its level, the console
+///
+/// Does not allocate, so it is safe to use before the heap exists and from
+/// interrupt handlers.
+pub fn log(level: Level, target: &'static str, args: fmt::Arguments) {
+	if !enabled(level, target) {
+		return;
+	}
+	let mut record = Record { timestamp_ms: time::uptime_ms(), level, target, ..Record::EMPTY };
+	let _ = record.text.write_fmt(args);
+	x86_64::instructions::interrupts::without_interrupts(|| LOG.lock().push(record));
+
+	let secs = record.timestamp_ms / 1000;
+	let millis = record.timestamp_ms % 1000;
+	// A serial console shows the message below instead
+	if !console::on_serial() {
+		serial_println!("[{:5}.{:03}] {}", secs, millis, record.message());
+	}
+	if level as u8 <= CONSOLE_LEVEL.load(Ordering::Relaxed) {
+		let theme = vga_buffer::theme();
+		let color = match level {
+			Level::Emerg | Level::Alert | Level::Crit | Level::Err => theme.error,
+			Level::Warn => theme.warning,
+			_ => theme.foreground,
+		};
+		vga_buffer::print_in_color(color, format_args!("{}\n", record.message()));
+	}
+}
+
+/// The oldest record still held with sequence number `seq` or later
+pub fn read_from(seq: u64) -> Option<(u64, Record)> {
+	x86_64::instructions::interrupts::without_interrupts(|| LOG.lock().read_from(seq))
+}
+
+/// Discard every held record
+pub fn clear() {
+	x86_64::instructions::interrupts::without_interrupts(|| LOG.lock().clear());
+}
+
+/// Set the least severe level printed on the console
+pub fn set_console_level(level: Level) {
+	CONSOLE_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Log a formatted message at a level: `klog!(Warn, "disk {} missing", n)`
+#[macro_export]
+macro_rules! klog {
+	($level:ident, $($arg:tt)*) => {
+		$crate::klog::log($crate::klog::Level::$level, module_path!(), format_args!($($arg)*))
+	};
+}
+
+/// Log an error: `error!("read failed: {}", err)`
+#[macro_export]
+macro_rules! error {
+	($($arg:tt)*) => ($crate::klog!(Err, $($arg)*));
+}
+
+/// Log a warning
+#[macro_export]
+macro_rules! warn {
+	($($arg:tt)*) => ($crate::klog!(Warn, $($arg)*));
+}
+
+/// Log an informational message
+#[macro_export]
+macro_rules! info {
+	($($arg:tt)*) => ($crate::klog!(Info, $($arg)*));
+}
+
+/// Log a debugging message
+#[macro_export]
+macro_rules! debug {
+	($($arg:tt)*) => ($crate::klog!(Debug, $($arg)*));
+}
+
+/// Log a trace message, which is dropped unless `log=` asks for it
+#[macro_export]
+macro_rules! trace {
+	($($arg:tt)*) => ($crate::klog!(Trace, $($arg)*));
+}
+
+/// Test ring wrap-around, sequence reads, and message truncation
+#[test_case]
+fn test_log_buffer() {
+	let mut buffer = LogBuffer::new(Record::EMPTY);
+	for i in 0..LOG_CAPACITY + 3 {
+		let mut record = Record { timestamp_ms: i as u64, ..Record::EMPTY };
+		let _ = write!(record.text, "message {}", i);
+		buffer.push(record);
+	}
+	let (seq, oldest) = buffer.read_from(0).unwrap();
+	assert_eq!(seq, 3);
+	assert_eq!(oldest.message(), "message 3");
+	assert_eq!(buffer.read_from(LOG_CAPACITY as u64 + 2).unwrap().1.timestamp_ms, LOG_CAPACITY as u64 + 2);
+	assert!(buffer.read_from(LOG_CAPACITY as u64 + 3).is_none());
+
+	let mut record = Record::EMPTY;
+	let _ = write!(record.text, "{:é<200}", "");
+	assert_eq!(record.message().chars().count(), MESSAGE_LEN / 2);
+	assert_eq!(Level::from_name("warn"), Some(Level::Warn));
+	assert_eq!(Level::from_name("7"), Some(Level::Debug));
+}
+
+/// Test per-module level filtering
+#[test_case]
+fn test_filter_level() {
+	let filter = "warn,scottos::pci=trace,scottos::fs=err,scottos::fs::ramfs=info";
+	assert_eq!(filter_level(filter, "scottos"), Level::Warn);
+	assert_eq!(filter_level(filter, "scottos::pci"), Level::Trace);
+	assert_eq!(filter_level(filter, "scottos::pcix"), Level::Warn);
+	assert_eq!(filter_level(filter, "scottos::fs::inode"), Level::Err);
+	assert_eq!(filter_level(filter, "scottos::fs::ramfs"), Level::Info);
+	assert_eq!(filter_level("", "scottos"), DEFAULT_LEVEL);
+	assert_eq!(filter_level("bogus,debug", "scottos"), Level::Debug);
+}
@@ -0,0 +1,116 @@
+/// Bytes kept for the symbol map; tools/ksyms.py leaves out whatever does not fit
+const CAPACITY: usize = 1024 * 1024;
+/// Leads the map, so the placeholder can be told from a filled-in one
+const MAGIC: &[u8; 4] = b"KSYM";
+/// The header: magic, number of entries, offset of the names
+const HEADER_SIZE: usize = 16;
+/// An entry: start address, offset and length of its name
+const ENTRY_SIZE: usize = 16;
+
+/// The symbol map, written into the linked kernel by tools/ksyms.py; until
+/// then it has no entries
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; CAPACITY] = placeholder();
+
+/// A map with the magic and no entries
+const fn placeholder() -> [u8; CAPACITY] {
+	let mut map = [0; CAPACITY];
+	let mut i = 0;
+	while i < MAGIC.len() {
+		map[i] = MAGIC[i];
+		i += 1;
+	}
+	map
+}
+
+/// A function the map names, and how far into it an address is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol<'a> {
+	pub name: &'a str,
+	pub offset: u64,
+}
+
+impl core::fmt::Display for Symbol<'_> {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "{}+{:#x}", self.name, self.offset)
+	}
+}
+
+fn read_u32(map: &[u8], at: usize) -> Option<usize> {
+	Some(u32::from_le_bytes(map.get(at..at + 4)?.try_into().ok()?) as usize)
+}
+
+fn read_u64(map: &[u8], at: usize) -> Option<u64> {
+	Some(u64::from_le_bytes(map.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// The symbol in `map` an address falls in: the last one starting at or
+/// before it
+fn lookup_in(map: &[u8], addr: u64) -> Option<Symbol<'_>> {
+	if map.get(..4)? != MAGIC {
+		return None;
+	}
+	let count = read_u32(map, 4)?;
+	let names = read_u32(map, 8)?;
+	let start = |i: usize| read_u64(map, HEADER_SIZE + i * ENTRY_SIZE);
+	// The entries are sorted by address
+	let (mut low, mut high) = (0, count);
+	while low < high {
+		let middle = (low + high) / 2;
+		if start(middle)? <= addr {
+			low = middle + 1;
+		} else {
+			high = middle;
+		}
+	}
+	let entry = HEADER_SIZE + low.checked_sub(1)? * ENTRY_SIZE;
+	let (name, length) = (names + read_u32(map, entry + 8)?, read_u32(map, entry + 12)?);
+	let name = core::str::from_utf8(map.get(name..name + length)?).ok()?;
+	Some(Symbol { name, offset: addr - read_u64(map, entry)? })
+}
+
+/// The map as written into the kernel; filled in after linking, so its
+/// contents must not be assumed
+fn map() -> &'static [u8] {
+	core::hint::black_box(&KSYMS)
+}
+
+/// Symbols in the map; 0 if tools/ksyms.py was never run on this kernel
+pub fn count() -> usize {
+	let map = map();
+	if &map[..4] != MAGIC {
+		return 0;
+	}
+	read_u32(map, 4).unwrap_or(0)
+}
+
+/// The kernel function an address is in, if the symbol map was written
+pub fn resolve(addr: u64) -> Option<Symbol<'static>> {
+	lookup_in(map(), addr)
+}
+
+/// Test finding symbols in a small map
+#[test_case]
+fn test_symbols() {
+	use alloc::vec::Vec;
+	let mut map = Vec::from(&MAGIC[..]);
+	map.extend_from_slice(&2u32.to_le_bytes());
+	map.extend_from_slice(&((HEADER_SIZE + 2 * ENTRY_SIZE) as u32).to_le_bytes());
+	map.extend_from_slice(&[0; 4]);
+	for (start, name, length) in [(0x1000u64, 0u32, 4u32), (0x1100, 4, 5)] {
+		map.extend_from_slice(&start.to_le_bytes());
+		map.extend_from_slice(&name.to_le_bytes());
+		map.extend_from_slice(&length.to_le_bytes());
+	}
+	map.extend_from_slice(b"initpanic");
+
+	assert_eq!(lookup_in(&map, 0xfff), None);
+	assert_eq!(lookup_in(&map, 0x1000), Some(Symbol { name: "init", offset: 0 }));
+	let symbol = lookup_in(&map, 0x1123).unwrap();
+	assert_eq!(symbol, Symbol { name: "panic", offset: 0x23 });
+	assert_eq!(alloc::format!("{}", symbol), "panic+0x23");
+	// Only the header was ever written here
+	assert_eq!(lookup_in(&KSYMS[..HEADER_SIZE], 0x1000), None);
+	assert_eq!(resolve(0x1000), None);
+}
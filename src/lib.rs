@@ -9,23 +9,64 @@
 extern crate alloc;
 
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
 
 pub mod serial;
+pub mod cmdline;
+pub mod klog;
+pub mod ring;
+pub mod console;
+pub mod cp437;
 pub mod vga_buffer;
+pub mod framebuffer;
 pub mod interrupts;
+pub mod exceptions;
+pub mod cpu;
+pub mod acpi;
+pub mod apic;
+pub mod hpet;
+pub mod smp;
+pub mod percpu;
+pub mod sync;
+#[cfg(debug_assertions)]
+pub mod lockdep;
+pub mod gdbstub;
 pub mod gdt;
 pub mod memory;
 pub mod allocator;
+pub mod audit;
 pub mod task;
 pub mod keyboard;
+pub mod kdb;
+pub mod keymap;
+pub mod ksyms;
 pub mod syscall;
 pub mod fs;
+pub mod mount;
+pub mod init;
+pub mod input;
+pub mod net;
+pub mod pci;
+pub mod pipe;
+pub mod panic;
+pub mod power;
 pub mod process;
+pub mod profile;
+pub mod random;
+pub mod rtc;
+pub mod sha256;
+pub mod time;
+pub mod trace;
+pub mod tty;
+pub mod users;
+pub mod virtio;
+pub mod watchdog;
 pub mod shell;
 
 /// Initialize the kernel
 pub fn init() {
 	gdt::init();
+	percpu::init(0);
 	interrupts::init_idt();
 	unsafe { interrupts::PICS.lock().initialize() };
 	x86_64::instructions::interrupts::enable();
@@ -66,22 +107,113 @@ where
 {
 	fn run(&self) {
 		serial_print!("{}...\t", core::any::type_name::<T>());
+		start_test(false);
 		self();
+		finish_test();
 		serial_println!("[ok]");
 	}
 }
 
+/// A test that passes by panicking; register one with `should_panic!`
+pub struct ShouldPanic {
+	pub name: &'static str,
+	pub test: fn(),
+}
+
+impl Testable for ShouldPanic {
+	fn run(&self) {
+		serial_print!("{}...\t", self.name);
+		start_test(true);
+		(self.test)();
+		finish_test();
+		serial_println!("[failed]\n");
+		serial_println!("Error: returned, but should have panicked\n");
+		exit_qemu(QemuExitCode::Failed);
+		hlt_loop();
+	}
+}
+
+/// Register a test that passes only if it panics: `should_panic!(TEST_NAME, test_fn);`
+#[macro_export]
+macro_rules! should_panic {
+	($name:ident, $test:path) => {
+		#[test_case]
+		static $name: $crate::ShouldPanic = $crate::ShouldPanic {
+			name: concat!(module_path!(), "::", stringify!($test)),
+			test: $test,
+		};
+	};
+}
+
+/// How long one test may run before the timer fails it as hung
+const TEST_TIMEOUT_MS: u64 = 60_000;
+
+/// The tests being run, kept so the panic handler can go on past a test
+/// that panicked as it should
+static TESTS: AtomicPtr<&'static dyn Testable> = AtomicPtr::new(core::ptr::null_mut());
+static TEST_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// The test after the one running
+static NEXT_TEST: AtomicUsize = AtomicUsize::new(0);
+/// Set while the running test should panic
+static EXPECT_PANIC: AtomicBool = AtomicBool::new(false);
+/// Uptime in milliseconds at which the running test times out; 0 between tests
+static TEST_DEADLINE_MS: AtomicU64 = AtomicU64::new(0);
+
+fn start_test(should_panic: bool) {
+	EXPECT_PANIC.store(should_panic, Ordering::Relaxed);
+	TEST_DEADLINE_MS.store(time::uptime_ms() + TEST_TIMEOUT_MS, Ordering::Relaxed);
+}
+
+fn finish_test() {
+	TEST_DEADLINE_MS.store(0, Ordering::Relaxed);
+	EXPECT_PANIC.store(false, Ordering::Relaxed);
+}
+
+/// Fail the running test if it has run too long; called at every timer
+/// tick, so it catches a test hung with interrupts on
+pub(crate) fn check_test_timeout() {
+	let deadline = TEST_DEADLINE_MS.load(Ordering::Relaxed);
+	if deadline != 0 && time::uptime_ms() >= deadline {
+		serial_println!("[timeout]\n");
+		serial_println!("Error: still running after {} s\n", TEST_TIMEOUT_MS / 1000);
+		exit_qemu(QemuExitCode::Failed);
+		hlt_loop();
+	}
+}
+
 /// Test runner function
 pub fn test_runner(tests: &[&dyn Testable]) {
 	serial_println!("Running {} tests", tests.len());
-	for test in tests {
+	// The harness's list of tests is a static, so it outlives any panic
+	TESTS.store(tests.as_ptr() as *mut &'static dyn Testable, Ordering::Relaxed);
+	TEST_COUNT.store(tests.len(), Ordering::Relaxed);
+	run_tests(0);
+}
+
+/// Run the tests from `first` on, then exit QEMU
+fn run_tests(first: usize) -> ! {
+	let tests = match TESTS.load(Ordering::Relaxed) {
+		tests if tests.is_null() => &[][..],
+		tests => unsafe { core::slice::from_raw_parts(tests, TEST_COUNT.load(Ordering::Relaxed)) },
+	};
+	for (index, test) in tests.iter().enumerate().skip(first) {
+		NEXT_TEST.store(index + 1, Ordering::Relaxed);
 		test.run();
 	}
 	exit_qemu(QemuExitCode::Success);
+	hlt_loop();
 }
 
-/// Test panic handler
+/// Test panic handler; passes a test that should panic and goes on to the
+/// next, on what is left of the panicked stack since nothing unwinds
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
+	TEST_DEADLINE_MS.store(0, Ordering::Relaxed);
+	if EXPECT_PANIC.swap(false, Ordering::Relaxed) {
+		serial_println!("[ok]");
+		// The panic may have come from an exception handler
+		x86_64::instructions::interrupts::enable();
+		run_tests(NEXT_TEST.load(Ordering::Relaxed));
+	}
 	serial_println!("[failed]\n");
 	serial_println!("Error: {}\n", info);
 	exit_qemu(QemuExitCode::Failed);
@@ -93,6 +225,7 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
 	init();
+	allocator::init_heap().expect("heap initialization failed");
 	test_main();
 	hlt_loop();
 }
@@ -0,0 +1,282 @@
+use crate::{allocator, ksyms, panic};
+use core::cell::RefCell;
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+/// Return addresses kept from where a lock was taken
+const HOLDER_FRAMES: usize = 6;
+/// Locks a processor can be seen to hold at once; deeper nesting goes unchecked
+const MAX_HELD: usize = 16;
+/// Distinct "taken while holding" pairs remembered
+const MAX_EDGES: usize = 256;
+/// Locks visited looking for the other order before giving up
+const MAX_SEARCH: usize = 64;
+/// The task field of a lock taken outside any task
+const NO_TASK: u64 = u64::MAX;
+
+extern "C" {
+	/// Bounds of the loaded kernel image, from the linker
+	static __ehdr_start: u8;
+	static _end: u8;
+}
+
+/// Cleared once the kernel panics, so the panic screen takes whatever locks
+/// it needs without being stopped again
+static CHECKING: AtomicBool = AtomicBool::new(true);
+
+/// Stop checking locks, for the panic path
+pub fn stop() {
+	CHECKING.store(false, Ordering::Relaxed);
+}
+
+/// Who holds a lock, as recorded when it was taken
+pub struct Holder {
+	/// Processor holding it, plus one; 0 while it is free
+	cpu: AtomicUsize,
+	task: AtomicU64,
+	site: AtomicPtr<Location<'static>>,
+	frames: [AtomicU64; HOLDER_FRAMES],
+}
+
+impl Holder {
+	pub const fn new() -> Holder {
+		Holder {
+			cpu: AtomicUsize::new(0),
+			task: AtomicU64::new(NO_TASK),
+			site: AtomicPtr::new(core::ptr::null_mut()),
+			frames: [const { AtomicU64::new(0) }; HOLDER_FRAMES],
+		}
+	}
+
+	fn site(&self) -> Option<&'static Location<'static>> {
+		unsafe { self.site.load(Ordering::Relaxed).as_ref() }
+	}
+}
+
+impl Default for Holder {
+	fn default() -> Holder {
+		Holder::new()
+	}
+}
+
+/// A holder's task and backtrace, for a panic message
+struct HolderReport<'a>(&'a Holder);
+
+impl fmt::Display for HolderReport<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let holder = self.0;
+		match holder.site() {
+			Some(site) => write!(f, "taken at {}", site)?,
+			None => write!(f, "taken at an unknown site")?,
+		}
+		match holder.task.load(Ordering::Relaxed) {
+			NO_TASK => writeln!(f, " outside any task, from:")?,
+			task => writeln!(f, " by task {}, from:", task)?,
+		}
+		for frame in holder.frames.iter().map(|frame| frame.load(Ordering::Relaxed)).take_while(|&frame| frame != 0) {
+			match ksyms::resolve(frame) {
+				Some(symbol) => writeln!(f, "    {:#018x} {}", frame, symbol)?,
+				None => writeln!(f, "    {:#018x}", frame)?,
+			}
+		}
+		Ok(())
+	}
+}
+
+/// The locks this processor holds, oldest first
+struct Held {
+	locks: [usize; MAX_HELD],
+	len: usize,
+}
+
+crate::per_cpu! {
+	/// What each processor holds
+	static HELD: RefCell<Held> = RefCell::new(Held { locks: [0; MAX_HELD], len: 0 });
+}
+
+/// Pairs of locks seen taken in order: the second while holding the first
+struct Edges {
+	from: [usize; MAX_EDGES],
+	to: [usize; MAX_EDGES],
+	site: [Option<&'static Location<'static>>; MAX_EDGES],
+	len: usize,
+}
+
+impl Edges {
+	fn contains(&self, from: usize, to: usize) -> bool {
+		(0..self.len).any(|i| self.from[i] == from && self.to[i] == to)
+	}
+
+	/// The first step of a chain of orders leading from `start` to `goal`,
+	/// with where it was taken
+	fn path(&self, start: usize, goal: usize) -> Option<(usize, &'static Location<'static>)> {
+		let mut stack = [(0usize, 0usize); MAX_SEARCH];
+		let mut visited = [0usize; MAX_SEARCH];
+		let (mut depth, mut seen) = (0, 0);
+		for i in (0..self.len).filter(|&i| self.from[i] == start) {
+			if depth < MAX_SEARCH {
+				stack[depth] = (self.to[i], i);
+				depth += 1;
+			}
+		}
+		while depth > 0 {
+			depth -= 1;
+			let (lock, first) = stack[depth];
+			if lock == goal {
+				return Some((self.to[first], self.site[first]?));
+			}
+			if visited[..seen].contains(&lock) || seen == MAX_SEARCH {
+				continue;
+			}
+			visited[seen] = lock;
+			seen += 1;
+			for i in (0..self.len).filter(|&i| self.from[i] == lock) {
+				if depth < MAX_SEARCH {
+					stack[depth] = (self.to[i], first);
+					depth += 1;
+				}
+			}
+		}
+		None
+	}
+
+	fn add(&mut self, from: usize, to: usize, site: &'static Location<'static>) {
+		if self.len < MAX_EDGES {
+			self.from[self.len] = from;
+			self.to[self.len] = to;
+			self.site[self.len] = Some(site);
+			self.len += 1;
+		}
+	}
+}
+
+/// A plain spinlock: checking it would need checking itself
+static EDGES: spin::Mutex<Edges> = spin::Mutex::new(Edges {
+	from: [0; MAX_EDGES],
+	to: [0; MAX_EDGES],
+	site: [None; MAX_EDGES],
+	len: 0,
+});
+
+/// Whether a lock is a static in the kernel image, and so the same lock
+/// every time its address is seen; heap and stack locks come and go
+fn is_static(lock: usize) -> bool {
+	let (start, end) = (&raw const __ehdr_start as usize, &raw const _end as usize);
+	(start..end).contains(&lock) && !allocator::contains(lock)
+}
+
+/// Before waiting for `lock`, check that taking it while holding what this
+/// processor holds could never deadlock with a processor taking them the
+/// other way round
+#[track_caller]
+pub(crate) fn before_lock(lock: usize, holder: &Holder) {
+	if !CHECKING.load(Ordering::Relaxed) || !is_static(lock) {
+		return;
+	}
+	let site = Location::caller();
+	let mut held = [0usize; MAX_HELD];
+	let len = HELD.with(|cell| {
+		let current = cell.borrow();
+		held[..current.len].copy_from_slice(&current.locks[..current.len]);
+		current.len
+	});
+	let mut inversion = None;
+	{
+		let mut edges = EDGES.lock();
+		for &earlier in held[..len].iter().filter(|&&earlier| earlier != lock && is_static(earlier)) {
+			if edges.contains(earlier, lock) {
+				continue;
+			}
+			if let Some(found) = edges.path(lock, earlier) {
+				inversion = Some((earlier, found));
+				break;
+			}
+			edges.add(earlier, lock, site);
+		}
+	}
+	if let Some((earlier, (next, other_site))) = inversion {
+		stop();
+		panic!(
+			"lock order: {:#x} taken at {} while holding {:#x}, but {:#x} was taken while holding {:#x} at {}; this processor's lock was {}",
+			lock, site, earlier, next, lock, other_site, HolderReport(holder)
+		);
+	}
+}
+
+/// While `lock` is busy: panic if this processor is the one holding it,
+/// since it would wait for itself forever
+#[track_caller]
+pub(crate) fn contended(lock: usize, holder: &Holder) {
+	if !CHECKING.load(Ordering::Relaxed) {
+		return;
+	}
+	let cpu = crate::smp::current_cpu();
+	if holder.cpu.load(Ordering::Acquire) == cpu + 1 {
+		stop();
+		panic!(
+			"deadlock: lock {:#x} taken again on CPU {} at {}, which already holds it; it was {}",
+			lock, cpu, Location::caller(), HolderReport(holder)
+		);
+	}
+}
+
+/// Record who took `lock`, just after taking it
+#[track_caller]
+pub(crate) fn acquired(lock: usize, holder: &Holder) {
+	if !CHECKING.load(Ordering::Relaxed) {
+		return;
+	}
+	holder.site.store(Location::caller() as *const Location as *mut Location, Ordering::Relaxed);
+	holder.task.store(crate::task::executor::current_task().unwrap_or(NO_TASK), Ordering::Relaxed);
+	let mut frames = holder.frames.iter();
+	// The first return is only into the lock's own code
+	for address in panic::backtrace(panic::frame_pointer(), panic::cached_is_mapped()).skip(1) {
+		match frames.next() {
+			Some(frame) => frame.store(address, Ordering::Relaxed),
+			None => break,
+		}
+	}
+	for frame in frames {
+		frame.store(0, Ordering::Relaxed);
+	}
+	holder.cpu.store(crate::smp::current_cpu() + 1, Ordering::Release);
+	HELD.with(|cell| {
+		let mut held = cell.borrow_mut();
+		if held.len < MAX_HELD {
+			let len = held.len;
+			held.locks[len] = lock;
+			held.len += 1;
+		}
+	});
+}
+
+/// Forget who held `lock`, just before letting it go
+pub(crate) fn released(lock: usize, holder: &Holder) {
+	holder.cpu.store(0, Ordering::Release);
+	HELD.with(|cell| {
+		let mut held = cell.borrow_mut();
+		// Usually the newest, but guards can be dropped in any order
+		if let Some(index) = held.locks[..held.len].iter().rposition(|&at| at == lock) {
+			let len = held.len;
+			held.locks.copy_within(index + 1..len, index);
+			held.len -= 1;
+		}
+	});
+}
+
+/// Test that both orders of a pair of locks are caught, through a third as well
+#[test_case]
+fn test_lock_order() {
+	let mut edges = Edges { from: [0; MAX_EDGES], to: [0; MAX_EDGES], site: [None; MAX_EDGES], len: 0 };
+	let site = Location::caller();
+	edges.add(1, 2, site);
+	edges.add(2, 3, site);
+	assert!(edges.contains(1, 2) && !edges.contains(2, 1));
+	assert_eq!(edges.path(3, 1), None);
+	assert_eq!(edges.path(1, 3), Some((2, site)));
+	assert_eq!(edges.path(2, 3), Some((3, site)));
+	assert!(is_static(&raw const EDGES as usize));
+	let lock = spin::Mutex::new(0);
+	assert!(!is_static(&lock as *const _ as usize));
+}
@@ -1,17 +1,35 @@
 use x86_64::{
+	registers::control::{Cr0, Cr0Flags},
 	structures::paging::{
-		FrameAllocator, PhysFrame, Size4KiB,
+		mapper::{MappedFrame, TranslateResult},
+		FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate,
 	},
-	PhysAddr,
+	PhysAddr, VirtAddr,
 };
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 use bootloader::BootInfo;
+use crate::klog;
+use alloc::vec::Vec;
 
 /// Global frame allocator
 pub static mut FRAME_ALLOCATOR: Option<BootInfoFrameAllocator> = None;
 
+/// Size of a page and a frame
+const PAGE_SIZE: u64 = 4096;
+
+/// Start of the kernel address range `map_memory` hands out
+const MAPPINGS_START: u64 = 0x4444_4444_0000;
+/// Next unused address in that range
+static NEXT_MAPPING: AtomicU64 = AtomicU64::new(MAPPINGS_START);
+
+/// Virtual address at which the bootloader mapped all of physical memory
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
 /// Initialize the memory management system from BootInfo (simplified)
 pub fn init(boot_info: &'static BootInfo) {
+	PHYSICAL_MEMORY_OFFSET.init_once(|| VirtAddr::new(boot_info.physical_memory_offset));
 	let frame_allocator = unsafe {
 		BootInfoFrameAllocator::init(&boot_info.memory_map)
 	};
@@ -22,6 +40,263 @@ pub fn init(boot_info: &'static BootInfo) {
 	}
 }
 
+/// The virtual address through which a physical address can be accessed
+pub fn phys_to_virt(addr: PhysAddr) -> Option<VirtAddr> {
+	let offset = PHYSICAL_MEMORY_OFFSET.get()?;
+	Some(*offset + addr.as_u64())
+}
+
+/// Translate a virtual address through the active page tables, returning
+/// `None` if it is not mapped
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+	active_page_table()?.translate_addr(addr)
+}
+
+/// Whether all `len` bytes from `addr` are mapped, none of them past the
+/// end of the address space or in the non-canonical hole; true for none
+pub fn is_range_mapped(addr: u64, len: usize) -> bool {
+	if len == 0 {
+		return true;
+	}
+	let Some(end) = addr.checked_add(len as u64 - 1) else {
+		return false;
+	};
+	(addr / PAGE_SIZE..=end / PAGE_SIZE).all(|page| VirtAddr::try_new(page * PAGE_SIZE).ok().and_then(translate).is_some())
+}
+
+/// The active page tables, reached through the physical memory offset
+fn active_page_table() -> Option<OffsetPageTable<'static>> {
+	let offset = *PHYSICAL_MEMORY_OFFSET.get()?;
+	let (level_4_frame, _) = x86_64::registers::control::Cr3::read();
+	let table = offset + level_4_frame.start_address().as_u64();
+	// The bootloader maps all physical memory at `offset`
+	Some(unsafe { OffsetPageTable::new(&mut *table.as_mut_ptr::<PageTable>(), offset) })
+}
+
+/// The flag keeping code from running in data pages, if the processor has
+/// it and it is turned on
+fn no_execute() -> PageTableFlags {
+	use x86_64::registers::model_specific::{Efer, EferFlags};
+
+	if crate::cpu::has(crate::cpu::Feature::Nx) && Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+		PageTableFlags::NO_EXECUTE
+	} else {
+		PageTableFlags::empty()
+	}
+}
+
+/// Make device memory such as a framebuffer reachable through the physical
+/// memory offset, mapping uncached any pages the bootloader left out
+pub fn map_mmio(start: PhysAddr, size: u64) -> Option<VirtAddr> {
+	let offset = *PHYSICAL_MEMORY_OFFSET.get()?;
+	let mut mapper = active_page_table()?;
+	let frame_allocator = unsafe { (*core::ptr::addr_of_mut!(FRAME_ALLOCATOR)).as_mut()? };
+	let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | no_execute();
+
+	let first = PhysFrame::<Size4KiB>::containing_address(start);
+	let last = PhysFrame::containing_address(start + size.max(1) - 1u64);
+	for frame in PhysFrame::range_inclusive(first, last) {
+		let page = Page::containing_address(offset + frame.start_address().as_u64());
+		if mapper.translate_addr(page.start_address()).is_some() {
+			continue;
+		}
+		unsafe { mapper.map_to(page, frame, flags, frame_allocator).ok()?.flush() };
+	}
+	Some(offset + start.as_u64())
+}
+
+/// Real-mode code can only reach the first megabyte
+const REAL_MODE_LIMIT: u64 = 0x10_0000;
+
+/// Allocate a frame below 1 MiB, where a processor starting in real mode can
+/// run code, and map it at its own address so the code keeps running once
+/// paging is on, returning whether this added the mapping; `None` if the
+/// next free frame is above 1 MiB
+pub fn identity_map_low_frame() -> Option<(PhysFrame, bool)> {
+	let mut mapper = active_page_table()?;
+	let frame_allocator = unsafe { (*core::ptr::addr_of_mut!(FRAME_ALLOCATOR)).as_mut()? };
+	let frame = frame_allocator.allocate_frame()?;
+	if frame.start_address().as_u64() >= REAL_MODE_LIMIT {
+		return None;
+	}
+	let page = Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+	match mapper.translate_addr(page.start_address()) {
+		Some(addr) if addr == frame.start_address() => Some((frame, false)),
+		Some(_) => None,
+		None => {
+			let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+			unsafe { mapper.map_to(page, frame, flags, frame_allocator).ok()?.flush() };
+			Some((frame, true))
+		}
+	}
+}
+
+/// Remove the mappings of `size` bytes from `start`, on every processor;
+/// the frames behind them are not reused
+pub fn unmap_memory(start: VirtAddr, size: u64) {
+	let Some(mut mapper) = active_page_table() else {
+		return;
+	};
+	let pages = size.max(1).div_ceil(PAGE_SIZE);
+	let first = Page::<Size4KiB>::containing_address(start);
+	for page in Page::range(first, first + pages) {
+		if let Ok((_, flush)) = mapper.unmap(page) {
+			flush.flush();
+		}
+	}
+	// Other processors may still have the old translations cached
+	crate::smp::flush_tlb(first.start_address(), pages);
+}
+
+/// ELF program header types and flags, as the kernel's image describes itself
+const PT_LOAD: u32 = 1;
+const PT_GNU_RELRO: u32 = 0x6474_e552;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+extern "C" {
+	/// The kernel's ELF header, which the linker defines at the start of
+	/// the first segment loaded, along with the program headers after it
+	static __ehdr_start: u8;
+}
+
+/// A range of the kernel image, with the `PF_` flags it was linked with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+	start: u64,
+	end: u64,
+	flags: u32,
+}
+
+/// The kernel's loaded segments, and the range only written while it is
+/// relocated, which a kernel linked at its final address never is
+fn image_segments() -> (Vec<Segment>, Option<Segment>) {
+	let header = &raw const __ehdr_start;
+	let read = |at: usize, len: usize| unsafe { core::slice::from_raw_parts(header.add(at), len) };
+	let mut loads = Vec::new();
+	let mut relro = None;
+	if read(0, 4) != b"\x7fELF" {
+		return (loads, relro);
+	}
+	let phoff = u64::from_le_bytes(read(0x20, 8).try_into().unwrap()) as usize;
+	let phentsize = u16::from_le_bytes(read(0x36, 2).try_into().unwrap()) as usize;
+	let phnum = u16::from_le_bytes(read(0x38, 2).try_into().unwrap()) as usize;
+	for entry in (0..phnum).map(|i| read(phoff + i * phentsize, phentsize.min(56))) {
+		let word = |at: usize| u32::from_le_bytes(entry[at..at + 4].try_into().unwrap());
+		let quad = |at: usize| u64::from_le_bytes(entry[at..at + 8].try_into().unwrap());
+		let segment = Segment { start: quad(16), end: quad(16) + quad(40), flags: word(4) };
+		match word(0) {
+			PT_LOAD => loads.push(segment),
+			PT_GNU_RELRO => relro = Some(segment),
+			_ => {}
+		}
+	}
+	(loads, relro)
+}
+
+/// Whether the page at `page` has to stay writable and executable: pages
+/// the linker shares between segments take the needs of both
+fn page_access(page: u64, loads: &[Segment], relro: Option<Segment>) -> (bool, bool) {
+	let (mut writable, mut executable) = (false, false);
+	for segment in loads.iter().filter(|segment| segment.start < page + PAGE_SIZE && page < segment.end) {
+		let (start, end) = (segment.start.max(page), segment.end.min(page + PAGE_SIZE));
+		let outside_relro = relro.is_none_or(|relro| start < relro.start.min(end) || relro.end.max(start) < end);
+		writable |= segment.flags & PF_W != 0 && outside_relro;
+		executable |= segment.flags & PF_X != 0;
+	}
+	(writable, executable)
+}
+
+/// Map the kernel's code and read-only data, its `.text` and `.rodata`,
+/// read-only and its data not executable, so a stray write into code
+/// faults at once instead of changing what runs
+///
+/// The bootloader maps each segment as linked, but leaves pages shared
+/// between segments, and the relocation-read-only data, writable. The
+/// GDB stub turns `CR0.WP` off to write its breakpoints.
+pub fn protect_kernel_image() {
+	let Some(mut mapper) = active_page_table() else {
+		return;
+	};
+	let (loads, relro) = image_segments();
+	let (Some(first), Some(last)) = (loads.iter().map(|s| s.start).min(), loads.iter().map(|s| s.end).max()) else {
+		klog!(Warn, "memory: no program headers at the start of the kernel image; leaving it as mapped");
+		return;
+	};
+	let mut read_only = 0;
+	for page in Page::<Size4KiB>::range(Page::containing_address(VirtAddr::new(first)), Page::containing_address(VirtAddr::new(last - 1)) + 1) {
+		let TranslateResult::Mapped { frame: MappedFrame::Size4KiB(_), flags, .. } = mapper.translate(page.start_address()) else {
+			continue;
+		};
+		let (writable, executable) = page_access(page.start_address().as_u64(), &loads, relro);
+		let mut wanted = flags;
+		if !writable {
+			wanted.remove(PageTableFlags::WRITABLE);
+			read_only += 1;
+		}
+		if !executable {
+			wanted.insert(no_execute());
+		}
+		if wanted != flags {
+			if let Ok(flush) = unsafe { mapper.update_flags(page, wanted) } {
+				flush.flush();
+			}
+		}
+	}
+	// Without this, the kernel could write to read-only pages anyway
+	unsafe { Cr0::update(|cr0| cr0.insert(Cr0Flags::WRITE_PROTECT)) };
+	klog!(Info, "memory: {} pages of kernel code and read-only data mapped read-only", read_only);
+}
+
+/// Physical frames left to allocate, or `None` before memory is initialized
+pub fn free_frames() -> Option<usize> {
+	unsafe { (*core::ptr::addr_of!(FRAME_ALLOCATOR)).as_ref().map(BootInfoFrameAllocator::free_frames) }
+}
+
+/// Map `size` bytes of fresh, zeroed memory at an unused kernel address,
+/// for buffers too big for the heap
+pub fn map_memory(size: u64) -> Option<VirtAddr> {
+	let size = size.max(1).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+	let start = VirtAddr::new(NEXT_MAPPING.fetch_add(size, Ordering::Relaxed));
+	let mut mapper = active_page_table()?;
+	let frame_allocator = unsafe { (*core::ptr::addr_of_mut!(FRAME_ALLOCATOR)).as_mut()? };
+	let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | no_execute();
+
+	let first = Page::<Size4KiB>::containing_address(start);
+	let last = Page::containing_address(start + (size - 1));
+	for page in Page::range_inclusive(first, last) {
+		let frame = frame_allocator.allocate_frame()?;
+		unsafe { mapper.map_to(page, frame, flags, frame_allocator).ok()?.flush() };
+	}
+	unsafe { core::ptr::write_bytes(start.as_mut_ptr::<u8>(), 0, size as usize) };
+	Some(start)
+}
+
+/// Allocate `size` bytes of zeroed, physically contiguous memory for a
+/// device to read and write by DMA, returning its physical address and
+/// where the kernel reaches it
+///
+/// Frames come from the frame allocator in order, so runs are contiguous
+/// unless they reach the end of a usable region; frames before such a gap
+/// are given up.
+pub fn allocate_dma(size: u64) -> Option<(PhysAddr, VirtAddr)> {
+	let frames = size.max(1).div_ceil(PAGE_SIZE);
+	let frame_allocator = unsafe { (*core::ptr::addr_of_mut!(FRAME_ALLOCATOR)).as_mut()? };
+	let mut start = frame_allocator.allocate_frame()?.start_address();
+	let mut run = 1;
+	while run < frames {
+		let frame = frame_allocator.allocate_frame()?.start_address();
+		if frame == start + run * PAGE_SIZE {
+			run += 1;
+		} else {
+			(start, run) = (frame, 1);
+		}
+	}
+	let virtual_address = phys_to_virt(start)?;
+	unsafe { core::ptr::write_bytes(virtual_address.as_mut_ptr::<u8>(), 0, (frames * PAGE_SIZE) as usize) };
+	Some((start, virtual_address))
+}
+
 /// Frame allocator that returns usable frames from the bootloader's memory map
 pub struct BootInfoFrameAllocator {
 	memory_map: &'static MemoryMap,
@@ -47,6 +322,15 @@ impl BootInfoFrameAllocator {
 		let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
 		frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
 	}
+
+	/// Number of usable frames not handed out yet
+	fn free_frames(&self) -> usize {
+		let total: u64 = self.memory_map.iter()
+			.filter(|r| r.region_type == MemoryRegionType::Usable)
+			.map(|r| (r.range.end_addr() - r.range.start_addr()) / PAGE_SIZE)
+			.sum();
+		(total as usize).saturating_sub(self.next)
+	}
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
@@ -55,4 +339,32 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
 		self.next += 1;
 		frame
 	}
-} 
\ No newline at end of file
+} 
+
+/// Test which pages of an image laid out as `rust-lld` lays out the
+/// kernel's stay writable and executable
+#[test_case]
+fn test_page_access() {
+	let loads = [
+		Segment { start: 0x20_0000, end: 0x31_9fc0, flags: 4 },
+		Segment { start: 0x31_9fc0, end: 0x3f_62e0, flags: 4 | PF_X },
+		Segment { start: 0x3f_62e0, end: 0x40_0000, flags: 4 | PF_W },
+		Segment { start: 0x40_0a00, end: 0x54_02c8, flags: 4 | PF_W },
+	];
+	let relro = Some(Segment { start: 0x3f_62e0, end: 0x40_0000, flags: 4 });
+	assert_eq!(page_access(0x20_0000, &loads, relro), (false, false));
+	assert_eq!(page_access(0x31_9000, &loads, relro), (false, true));
+	assert_eq!(page_access(0x3f_6000, &loads, relro), (false, true));
+	assert_eq!(page_access(0x3f_7000, &loads, relro), (false, false));
+	assert_eq!(page_access(0x3f_7000, &loads, None), (true, false));
+	assert_eq!(page_access(0x40_0000, &loads, relro), (true, false));
+}
+
+/// Test ranges past the end of the address space or into the hole never
+/// counting as mapped, and an empty one always
+#[test_case]
+fn test_is_range_mapped() {
+	assert!(is_range_mapped(0x8000_0000_0000, 0));
+	assert!(!is_range_mapped(u64::MAX, 2));
+	assert!(!is_range_mapped(0x8000_0000_0000, 1));
+}
@@ -0,0 +1,112 @@
+use crate::sync::Mutex;
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::fmt;
+
+/// A directory bound over another: paths under `target` are looked up
+/// under `source` instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+	/// Where the directory really is in the file system
+	pub source: String,
+	/// Where it is seen, before the namespace's other mounts apply
+	pub target: String,
+}
+
+/// The mounts a group of processes sees
+///
+/// A process shares its parent's namespace, so mounts made by either are
+/// seen by both, unless it is created with `CLONE_NEWNS` or unshares: then
+/// it gets a copy of its own that later mounts on either side leave alone.
+pub struct Namespace {
+	mounts: Mutex<Vec<Mount>>,
+}
+
+impl fmt::Debug for Namespace {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_list().entries(self.mounts.lock().iter()).finish()
+	}
+}
+
+impl Namespace {
+	/// A namespace with nothing mounted
+	pub fn new() -> Arc<Namespace> {
+		Arc::new(Namespace { mounts: Mutex::new(Vec::new()) })
+	}
+
+	/// A namespace of its own holding the same mounts as this one
+	pub fn copy(&self) -> Arc<Namespace> {
+		Arc::new(Namespace { mounts: Mutex::new(self.mounts()) })
+	}
+
+	/// Every mount, oldest first
+	pub fn mounts(&self) -> Vec<Mount> {
+		self.mounts.lock().clone()
+	}
+
+	/// Bind `source`, a path in the file system, over `target`; a later
+	/// mount on the same target hides the earlier one until it is unmounted
+	pub fn bind(&self, source: String, target: String) {
+		self.mounts.lock().push(Mount { source, target });
+	}
+
+	/// Take away the latest mount on `target`, returning whether there was one
+	pub fn unmount(&self, target: &str) -> bool {
+		let mut mounts = self.mounts.lock();
+		match mounts.iter().rposition(|mount| mount.target == target) {
+			Some(index) => {
+				mounts.remove(index);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Whether anything is mounted on `target`
+	pub fn is_mount_point(&self, target: &str) -> bool {
+		self.mounts.lock().iter().any(|mount| mount.target == target)
+	}
+
+	/// Where `path`, normalized and absolute, is in the file system
+	pub fn translate(&self, path: &str) -> String {
+		translate(&self.mounts.lock(), path)
+	}
+}
+
+/// The part of `path` below `dir`, empty if they are the same, or `None`
+/// if `path` is not under it
+fn below<'a>(dir: &str, path: &'a str) -> Option<&'a str> {
+	if dir == "/" {
+		return Some(if path == "/" { "" } else { path });
+	}
+	let rest = path.strip_prefix(dir)?;
+	(rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+/// Look `path` up through `mounts`: the mount with the longest target it is
+/// under wins, and of those on the same target, the latest
+fn translate(mounts: &[Mount], path: &str) -> String {
+	let found = mounts
+		.iter()
+		.enumerate()
+		.filter_map(|(index, mount)| below(&mount.target, path).map(|rest| (mount.target.len(), index, mount, rest)))
+		.max_by_key(|&(len, index, _, _)| (len, index));
+	match found {
+		Some((_, _, mount, rest)) if mount.source == "/" && !rest.is_empty() => String::from(rest),
+		Some((_, _, mount, rest)) => format!("{}{}", mount.source, rest),
+		None => String::from(path),
+	}
+}
+
+/// Test that paths are looked up through the deepest and latest mount
+#[test_case]
+fn test_translate() {
+	let bind = |source: &str, target: &str| Mount { source: String::from(source), target: String::from(target) };
+	let mounts = [bind("/home/guest/a", "/mnt"), bind("/tmp", "/mnt/tmp"), bind("/home/guest/b", "/mnt")];
+	assert_eq!(translate(&mounts, "/etc/passwd"), "/etc/passwd");
+	assert_eq!(translate(&mounts, "/mnt"), "/home/guest/b");
+	assert_eq!(translate(&mounts, "/mnt/x"), "/home/guest/b/x");
+	assert_eq!(translate(&mounts, "/mntx"), "/mntx");
+	assert_eq!(translate(&mounts, "/mnt/tmp/y"), "/tmp/y");
+	assert_eq!(translate(&[bind("/", "/jail/root")], "/jail/root/etc"), "/etc");
+	assert_eq!(translate(&[bind("/", "/jail/root")], "/jail/root"), "/");
+}
@@ -0,0 +1,208 @@
+use super::{ethernet, MacAddress};
+use crate::time::Instant;
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::net::Ipv4Addr;
+use core::time::Duration;
+use crate::sync::Mutex;
+
+/// Size of an ARP packet for IPv4 over Ethernet
+const PACKET_SIZE: usize = 28;
+/// Hardware and protocol types and sizes of IPv4 over Ethernet
+const HARDWARE_ETHERNET: u16 = 1;
+/// Operations
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+
+/// How long a learned address is trusted
+const ENTRY_LIFETIME: Duration = Duration::from_secs(300);
+/// Packets held for an address being resolved; more are dropped
+const MAX_WAITING: usize = 8;
+/// How long before asking again for an address nobody answered for
+const RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// An ARP packet for IPv4 over Ethernet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+	pub operation: u16,
+	pub sender_mac: MacAddress,
+	pub sender_ip: Ipv4Addr,
+	pub target_mac: MacAddress,
+	pub target_ip: Ipv4Addr,
+}
+
+impl Packet {
+	pub fn parse(data: &[u8]) -> Option<Packet> {
+		if data.len() < PACKET_SIZE {
+			return None;
+		}
+		let word = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+		let hardware_ok = word(0) == HARDWARE_ETHERNET && data[4] == 6;
+		let protocol_ok = word(2) == ethernet::ETHERTYPE_IPV4 && data[5] == 4;
+		if !hardware_ok || !protocol_ok {
+			return None;
+		}
+		let mac = |at: usize| MacAddress(data[at..at + 6].try_into().unwrap_or_default());
+		let ip = |at: usize| Ipv4Addr::new(data[at], data[at + 1], data[at + 2], data[at + 3]);
+		Some(Packet { operation: word(6), sender_mac: mac(8), sender_ip: ip(14), target_mac: mac(18), target_ip: ip(24) })
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut data = Vec::with_capacity(PACKET_SIZE);
+		data.extend_from_slice(&HARDWARE_ETHERNET.to_be_bytes());
+		data.extend_from_slice(&ethernet::ETHERTYPE_IPV4.to_be_bytes());
+		data.extend_from_slice(&[6, 4]);
+		data.extend_from_slice(&self.operation.to_be_bytes());
+		data.extend_from_slice(&self.sender_mac.0);
+		data.extend_from_slice(&self.sender_ip.octets());
+		data.extend_from_slice(&self.target_mac.0);
+		data.extend_from_slice(&self.target_ip.octets());
+		data
+	}
+}
+
+/// What is known about an address on an interface's link
+enum Entry {
+	Resolved { mac: MacAddress, expires: Instant },
+	/// Asked for; the IPv4 packets to send once it answers
+	Pending { asked: Instant, waiting: Vec<Vec<u8>> },
+}
+
+/// Addresses learned on each interface's link
+static CACHE: Mutex<BTreeMap<(usize, Ipv4Addr), Entry>> = Mutex::new(BTreeMap::new());
+
+/// Send an ARP packet out of `interface`
+fn send(interface: usize, destination: MacAddress, packet: Packet) {
+	let _ = super::send_frame(interface, destination, ethernet::ETHERTYPE_ARP, &packet.to_bytes());
+}
+
+/// Ask who has `ip` on `interface`'s link
+fn request(interface: usize, ip: Ipv4Addr) {
+	let Some((mac, address)) = super::mac(interface).zip(super::config(interface).address) else {
+		return;
+	};
+	let packet = Packet { operation: OP_REQUEST, sender_mac: mac, sender_ip: address, target_mac: MacAddress([0; 6]), target_ip: ip };
+	send(interface, MacAddress::BROADCAST, packet);
+}
+
+/// The hardware address of `ip` on `interface`'s link, if known
+pub fn lookup(interface: usize, ip: Ipv4Addr) -> Option<MacAddress> {
+	match CACHE.lock().get(&(interface, ip)) {
+		Some(&Entry::Resolved { mac, expires }) if Instant::now() < expires => Some(mac),
+		_ => None,
+	}
+}
+
+/// Send an IPv4 packet to `next_hop` on `interface`'s link, resolving its
+/// hardware address first if need be; the packet waits for the answer
+pub fn send_ipv4(interface: usize, next_hop: Ipv4Addr, packet: Vec<u8>) {
+	if let Some(mac) = lookup(interface, next_hop) {
+		let _ = super::send_frame(interface, mac, ethernet::ETHERTYPE_IPV4, &packet);
+		return;
+	}
+	let now = Instant::now();
+	let ask = {
+		let mut cache = CACHE.lock();
+		let entry = cache.entry((interface, next_hop)).or_insert(Entry::Pending { asked: now, waiting: Vec::new() });
+		match entry {
+			Entry::Pending { asked, waiting } => {
+				if waiting.len() < MAX_WAITING {
+					waiting.push(packet);
+				}
+				// A new entry asks right away, an old one once in a while
+				let ask = waiting.len() == 1 || now >= *asked + RETRY_AFTER;
+				if ask {
+					*asked = now;
+				}
+				ask
+			}
+			// Expired, so ask again and wait
+			Entry::Resolved { .. } => {
+				*entry = Entry::Pending { asked: now, waiting: alloc::vec![packet] };
+				true
+			}
+		}
+	};
+	if ask {
+		request(interface, next_hop);
+	}
+}
+
+/// Learn `ip`'s hardware address, sending whatever was waiting for it
+fn learn(interface: usize, ip: Ipv4Addr, mac: MacAddress) {
+	let entry = Entry::Resolved { mac, expires: Instant::now() + ENTRY_LIFETIME };
+	let previous = CACHE.lock().insert((interface, ip), entry);
+	if let Some(Entry::Pending { waiting, .. }) = previous {
+		for packet in waiting {
+			let _ = super::send_frame(interface, mac, ethernet::ETHERTYPE_IPV4, &packet);
+		}
+	}
+}
+
+/// Handle an ARP packet received on `interface`: learn the sender, and
+/// answer requests for the interface's own address
+pub fn receive(interface: usize, data: &[u8]) {
+	let Some(packet) = Packet::parse(data) else {
+		return;
+	};
+	let Some(address) = super::config(interface).address else {
+		return;
+	};
+	let for_us = packet.target_ip == address;
+	// Only refresh addresses already known, unless the packet is for us
+	let known = CACHE.lock().contains_key(&(interface, packet.sender_ip));
+	if !packet.sender_ip.is_unspecified() && (for_us || known) {
+		learn(interface, packet.sender_ip, packet.sender_mac);
+	}
+	if for_us && packet.operation == OP_REQUEST {
+		let Some(mac) = super::mac(interface) else {
+			return;
+		};
+		let reply = Packet { operation: OP_REPLY, sender_mac: mac, sender_ip: address, target_mac: packet.sender_mac, target_ip: packet.sender_ip };
+		send(interface, packet.sender_mac, reply);
+	}
+}
+
+/// A cache entry as `arp` shows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Neighbor {
+	pub interface: usize,
+	pub ip: Ipv4Addr,
+	/// `None` while still being resolved
+	pub mac: Option<MacAddress>,
+}
+
+/// Every address in the cache that has not expired
+pub fn neighbors() -> Vec<Neighbor> {
+	let now = Instant::now();
+	CACHE
+		.lock()
+		.iter()
+		.filter_map(|(&(interface, ip), entry)| match *entry {
+			Entry::Resolved { mac, expires } => (now < expires).then_some(Neighbor { interface, ip, mac: Some(mac) }),
+			Entry::Pending { .. } => Some(Neighbor { interface, ip, mac: None }),
+		})
+		.collect()
+}
+
+/// Test that packets survive a round trip and learned addresses are found
+#[test_case]
+fn test_arp() {
+	let packet = Packet {
+		operation: OP_REPLY,
+		sender_mac: MacAddress([2, 0, 0, 0, 0, 1]),
+		sender_ip: Ipv4Addr::new(10, 0, 2, 2),
+		target_mac: MacAddress([2, 0, 0, 0, 0, 2]),
+		target_ip: Ipv4Addr::new(10, 0, 2, 15),
+	};
+	let bytes = packet.to_bytes();
+	assert_eq!(bytes.len(), PACKET_SIZE);
+	assert_eq!(&bytes[..8], &[0, 1, 8, 0, 6, 4, 0, 2]);
+	assert_eq!(Packet::parse(&bytes), Some(packet));
+	assert_eq!(Packet::parse(&bytes[..27]), None);
+
+	let interface = usize::MAX;
+	assert_eq!(lookup(interface, packet.sender_ip), None);
+	learn(interface, packet.sender_ip, packet.sender_mac);
+	assert_eq!(lookup(interface, packet.sender_ip), Some(packet.sender_mac));
+	CACHE.lock().remove(&(interface, packet.sender_ip));
+}
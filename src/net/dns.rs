@@ -0,0 +1,289 @@
+use super::udp;
+use crate::cmdline;
+use crate::time::Instant;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::sync::atomic::{AtomicU16, Ordering};
+use core::time::Duration;
+use crate::sync::Mutex;
+
+/// Port nameservers answer on
+pub const PORT: u16 = 53;
+/// The nameserver of QEMU's user networking
+const QEMU_NAMESERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 3);
+
+/// Size of a message's header
+const HEADER_SIZE: usize = 12;
+/// Header flags: a response, recursion desired, and the response code
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_MASK: u16 = 0x000f;
+/// Response code of a name that does not exist; any other but 0 is a failure
+const RCODE_NAME_ERROR: u16 = 3;
+/// Record type and class of an IPv4 address
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+/// Longest name, and longest label in it
+const MAX_NAME: usize = 253;
+const MAX_LABEL: usize = 63;
+
+/// How long each query is waited for, and how many are sent
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const QUERY_ATTEMPTS: usize = 3;
+/// Names the cache holds; the one expiring soonest makes room
+const CACHE_CAPACITY: usize = 32;
+/// Bounds on how long an answer is cached, whatever its TTL says
+const MIN_TTL: Duration = Duration::from_secs(5);
+const MAX_TTL: Duration = Duration::from_secs(3600);
+
+/// Why a name could not be resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsError {
+	/// Empty, too long, or with an empty or too long label
+	InvalidName,
+	/// No nameserver is configured
+	NoNameserver,
+	/// The network has no route to the nameserver
+	NoRoute,
+	/// The nameserver says the name does not exist, or has no address
+	NotFound,
+	/// The nameserver could not answer
+	ServerFailure,
+	/// No answer came
+	TimedOut,
+	/// Ctrl+C was pressed while waiting
+	Interrupted,
+}
+
+impl DnsError {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			DnsError::InvalidName => "invalid name",
+			DnsError::NoNameserver => "no nameserver configured",
+			DnsError::NoRoute => "network is unreachable",
+			DnsError::NotFound => "name not found",
+			DnsError::ServerFailure => "server failure",
+			DnsError::TimedOut => "timed out",
+			DnsError::Interrupted => "interrupted",
+		}
+	}
+}
+
+/// The nameserver set with `set_nameserver`, once it has been
+static NAMESERVER: Mutex<Option<Option<Ipv4Addr>>> = Mutex::new(None);
+/// Answers by name, with when they expire
+static CACHE: Mutex<BTreeMap<String, (Vec<Ipv4Addr>, Instant)>> = Mutex::new(BTreeMap::new());
+/// Identifier of the next query
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// The nameserver queries go to: the one last set, or from the `dns=`
+/// parameter, or QEMU's
+pub fn nameserver() -> Option<Ipv4Addr> {
+	if let Some(nameserver) = *NAMESERVER.lock() {
+		return nameserver;
+	}
+	match cmdline::param("dns") {
+		Some("off") => None,
+		Some(text) => text.parse().ok(),
+		None => Some(QEMU_NAMESERVER),
+	}
+}
+
+/// Send queries to `nameserver`, or to none; forgets every cached answer
+pub fn set_nameserver(nameserver: Option<Ipv4Addr>) {
+	*NAMESERVER.lock() = Some(nameserver);
+	CACHE.lock().clear();
+}
+
+/// A query for the A records of `name`, which must be valid
+fn query(id: u16, name: &str) -> Vec<u8> {
+	let mut message = Vec::with_capacity(HEADER_SIZE + name.len() + 6);
+	message.extend_from_slice(&id.to_be_bytes());
+	message.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+	message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+	for label in name.split('.') {
+		message.push(label.len() as u8);
+		message.extend_from_slice(label.as_bytes());
+	}
+	message.push(0);
+	message.extend_from_slice(&TYPE_A.to_be_bytes());
+	message.extend_from_slice(&CLASS_IN.to_be_bytes());
+	message
+}
+
+/// Skip the possibly compressed name at `at`, returning where it ends
+fn skip_name(message: &[u8], mut at: usize) -> Option<usize> {
+	loop {
+		let length = *message.get(at)?;
+		match length {
+			0 => return Some(at + 1),
+			// A pointer elsewhere ends the name where it is
+			_ if length & 0xc0 == 0xc0 => return (at + 2 <= message.len()).then_some(at + 2),
+			_ => at += 1 + usize::from(length),
+		}
+	}
+}
+
+/// The addresses, and the shortest TTL among them, in a response to query
+/// `id`; `None` if the message is no such response
+fn parse_response(id: u16, message: &[u8]) -> Option<Result<(Vec<Ipv4Addr>, Duration), DnsError>> {
+	if message.len() < HEADER_SIZE {
+		return None;
+	}
+	let field = |at: usize| message.get(at..at + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+	let flags = field(2)?;
+	if field(0)? != id || flags & FLAG_RESPONSE == 0 {
+		return None;
+	}
+	match flags & RCODE_MASK {
+		0 => {}
+		RCODE_NAME_ERROR => return Some(Err(DnsError::NotFound)),
+		_ => return Some(Err(DnsError::ServerFailure)),
+	}
+	let (questions, answers) = (field(4)?, field(6)?);
+	let mut at = HEADER_SIZE;
+	for _ in 0..questions {
+		at = skip_name(message, at)? + 4;
+	}
+	let mut addresses = Vec::new();
+	let mut ttl = MAX_TTL;
+	for _ in 0..answers {
+		at = skip_name(message, at)?;
+		let (kind, class) = (field(at)?, field(at + 2)?);
+		let record_ttl = u32::from_be_bytes(message.get(at + 4..at + 8)?.try_into().ok()?);
+		let length = usize::from(field(at + 8)?);
+		let data = message.get(at + 10..at + 10 + length)?;
+		// Aliases come before the addresses they lead to, and are skipped
+		if kind == TYPE_A && class == CLASS_IN && length == 4 {
+			addresses.push(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+			ttl = ttl.min(Duration::from_secs(u64::from(record_ttl)));
+		}
+		at += 10 + length;
+	}
+	if addresses.is_empty() {
+		return Some(Err(DnsError::NotFound));
+	}
+	Some(Ok((addresses, ttl.max(MIN_TTL))))
+}
+
+/// Whether `name` can be put in a query
+fn valid_name(name: &str) -> bool {
+	!name.is_empty() && name.len() <= MAX_NAME && name.split('.').all(|label| !label.is_empty() && label.len() <= MAX_LABEL)
+}
+
+/// Cache `addresses` for `name` until `ttl` has passed
+fn remember(name: &str, addresses: &[Ipv4Addr], ttl: Duration) {
+	let mut cache = CACHE.lock();
+	let now = Instant::now();
+	cache.retain(|_, (_, expires)| *expires > now);
+	if cache.len() >= CACHE_CAPACITY && !cache.contains_key(name) {
+		let soonest = cache.iter().min_by_key(|(_, (_, expires))| *expires).map(|(name, _)| name.clone());
+		if let Some(soonest) = soonest {
+			cache.remove(&soonest);
+		}
+	}
+	cache.insert(String::from(name), (Vec::from(addresses), now + ttl));
+}
+
+/// Ask `nameserver` for the addresses of `name`, waiting for the answer
+fn ask(nameserver: Ipv4Addr, name: &str) -> Result<(Vec<Ipv4Addr>, Duration), DnsError> {
+	let socket = udp::create();
+	let result = exchange(socket, SocketAddrV4::new(nameserver, PORT), name);
+	udp::close(socket);
+	result
+}
+
+/// Send queries for `name` from `socket` to `server` until one is answered
+fn exchange(socket: udp::SocketId, server: SocketAddrV4, name: &str) -> Result<(Vec<Ipv4Addr>, Duration), DnsError> {
+	for _ in 0..QUERY_ATTEMPTS {
+		let id = NEXT_ID.fetch_add(1, Ordering::Relaxed) ^ (crate::time::ticks() as u16);
+		udp::send_to(socket, server, &query(id, name)).map_err(|_| DnsError::NoRoute)?;
+		let sent = Instant::now();
+		while sent.elapsed() < QUERY_TIMEOUT {
+			super::poll();
+			while let Ok(Some(datagram)) = udp::recv_from(socket) {
+				if datagram.source != server {
+					continue;
+				}
+				if let Some(result) = parse_response(id, &datagram.data) {
+					return result;
+				}
+			}
+			if crate::task::keyboard::interrupt_requested() {
+				return Err(DnsError::Interrupted);
+			}
+			x86_64::instructions::hlt();
+		}
+	}
+	Err(DnsError::TimedOut)
+}
+
+/// Every address of `name`, from the cache or the nameserver; a dotted
+/// address is its own answer
+pub fn lookup(name: &str) -> Result<Vec<Ipv4Addr>, DnsError> {
+	lookup_cached(name, None)
+}
+
+/// Every address of `name`, asking `nameserver` rather than the usual one
+/// and leaving the cache alone
+pub fn lookup_with(nameserver: Ipv4Addr, name: &str) -> Result<Vec<Ipv4Addr>, DnsError> {
+	lookup_cached(name, Some(nameserver))
+}
+
+/// Look `name` up in the cache and then at the usual nameserver, or only
+/// at `server` if given
+fn lookup_cached(name: &str, server: Option<Ipv4Addr>) -> Result<Vec<Ipv4Addr>, DnsError> {
+	if let Ok(address) = name.parse::<Ipv4Addr>() {
+		return Ok(alloc::vec![address]);
+	}
+	let name = name.strip_suffix('.').unwrap_or(name);
+	if name.eq_ignore_ascii_case("localhost") {
+		return Ok(alloc::vec![Ipv4Addr::LOCALHOST]);
+	}
+	if !valid_name(name) {
+		return Err(DnsError::InvalidName);
+	}
+	let name = name.to_ascii_lowercase();
+	if let Some(server) = server {
+		return ask(server, &name).map(|(addresses, _)| addresses);
+	}
+	if let Some((addresses, expires)) = CACHE.lock().get(&name) {
+		if *expires > Instant::now() {
+			return Ok(addresses.clone());
+		}
+	}
+	let (addresses, ttl) = ask(nameserver().ok_or(DnsError::NoNameserver)?, &name)?;
+	remember(&name, &addresses, ttl.min(MAX_TTL));
+	Ok(addresses)
+}
+
+/// The first address of `name`
+pub fn resolve(name: &str) -> Result<Ipv4Addr, DnsError> {
+	lookup(name).map(|addresses| addresses[0])
+}
+
+/// Test building queries and reading responses, compressed names included
+#[test_case]
+fn test_dns_messages() {
+	let query = query(0x1234, "a.bc");
+	assert_eq!(query, [0x12, 0x34, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, b'a', 2, b'b', b'c', 0, 0, 1, 0, 1]);
+
+	// The question, then an alias and an address both named by pointers
+	let mut response = query.clone();
+	response[2] |= 0x80;
+	response[7] = 2;
+	response.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 14]);
+	response.extend_from_slice(&[0xc0, 14, 0, 1, 0, 1, 0, 0, 0, 1, 0, 4, 10, 0, 2, 2]);
+	assert_eq!(parse_response(0x1234, &response), Some(Ok((alloc::vec![Ipv4Addr::new(10, 0, 2, 2)], MIN_TTL))));
+	assert_eq!(parse_response(0x1235, &response), None);
+	assert_eq!(parse_response(0x1234, &query), None);
+
+	response[3] |= RCODE_NAME_ERROR as u8;
+	assert_eq!(parse_response(0x1234, &response), Some(Err(DnsError::NotFound)));
+	// Server failure
+	response[3] = (response[3] & !0xf) | 2;
+	assert_eq!(parse_response(0x1234, &response), Some(Err(DnsError::ServerFailure)));
+
+	assert!(valid_name("example.com") && !valid_name("a..b") && !valid_name(""));
+	assert_eq!(lookup("10.0.2.2"), Ok(alloc::vec![Ipv4Addr::new(10, 0, 2, 2)]));
+}
@@ -0,0 +1,375 @@
+use super::{MacAddress, TransmitError, MAX_FRAME};
+use crate::pci::{self, Bar, DeviceMatch};
+use crate::{interrupts, memory};
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Device registers, as offsets into BAR 0
+const REG_CTRL: usize = 0x0000;
+const REG_STATUS: usize = 0x0008;
+const REG_EERD: usize = 0x0014;
+const REG_ICR: usize = 0x00c0;
+const REG_IMS: usize = 0x00d0;
+const REG_IMC: usize = 0x00d8;
+const REG_RCTL: usize = 0x0100;
+const REG_TCTL: usize = 0x0400;
+const REG_TIPG: usize = 0x0410;
+const REG_RDBAL: usize = 0x2800;
+const REG_RDBAH: usize = 0x2804;
+const REG_RDLEN: usize = 0x2808;
+const REG_RDH: usize = 0x2810;
+const REG_RDT: usize = 0x2818;
+const REG_TDBAL: usize = 0x3800;
+const REG_TDBAH: usize = 0x3804;
+const REG_TDLEN: usize = 0x3808;
+const REG_TDH: usize = 0x3810;
+const REG_TDT: usize = 0x3818;
+const REG_MTA: usize = 0x5200;
+const REG_RAL: usize = 0x5400;
+const REG_RAH: usize = 0x5404;
+/// Size of BAR 0 that the registers above fit in
+const REGISTERS_SIZE: u64 = 0x20000;
+
+/// Control register bits: set link up, auto-detect speed, and reset
+const CTRL_ASDE: u32 = 1 << 5;
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+/// Status register bit set while the link is up
+const STATUS_LU: u32 = 1 << 1;
+
+/// EEPROM read register: the 82540 family puts the word address at bit 8
+/// and reports done at bit 4, the 82574 at bits 2 and 1
+const EERD_START: u32 = 1 << 0;
+const EERD_DONE: u32 = 1 << 4;
+const EERD_DONE_82574: u32 = 1 << 1;
+/// Reads of a register waiting for a bit before giving up on it
+const POLL_TRIES: usize = 10_000;
+
+/// Interrupt causes: link status change, receive descriptors running low,
+/// receive overrun, and a frame received
+const INT_LSC: u32 = 1 << 2;
+const INT_RXDMT0: u32 = 1 << 4;
+const INT_RXO: u32 = 1 << 6;
+const INT_RXT0: u32 = 1 << 7;
+
+/// Receive control: enable, accept broadcasts, 2 KiB buffers (the zero
+/// size setting), and strip the CRC
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_BAM: u32 = 1 << 15;
+const RCTL_SECRC: u32 = 1 << 26;
+/// Transmit control: enable, pad short frames, and the collision
+/// threshold and distance full duplex wants
+const TCTL_EN: u32 = 1 << 1;
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x0f << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+/// Inter-packet gap timings recommended for copper
+const TIPG_COPPER: u32 = 10 | 8 << 10 | 6 << 20;
+
+/// Descriptor status bits: the device is done with it, and it holds the
+/// end of a frame
+const DESC_DD: u8 = 1 << 0;
+const DESC_EOP: u8 = 1 << 1;
+/// Transmit commands: end of frame, insert the CRC, and report status
+const CMD_EOP: u8 = 1 << 0;
+const CMD_IFCS: u8 = 1 << 1;
+const CMD_RS: u8 = 1 << 3;
+
+/// Descriptors in each ring; a ring must be a multiple of 128 bytes
+const RX_COUNT: usize = 32;
+const TX_COUNT: usize = 16;
+/// Size of each packet buffer; two fit in a frame of DMA memory
+const BUFFER_SIZE: usize = 2048;
+const BUFFERS_PER_FRAME: usize = 4096 / BUFFER_SIZE;
+
+/// Devices the interrupt handler checks, since several can share a line
+const MAX_DEVICES: usize = 4;
+
+/// The IDs of the e1000 models emulators provide
+pub static DRIVER: pci::Driver = pci::Driver {
+	name: "e1000",
+	matches: &[
+		DeviceMatch::Id(0x8086, 0x100e), // 82540EM, QEMU's default NIC
+		DeviceMatch::Id(0x8086, 0x100f), // 82545EM, VMware's
+		DeviceMatch::Id(0x8086, 0x10d3), // 82574L, QEMU's e1000e
+	],
+	probe,
+};
+
+/// A receive descriptor, as the device reads and writes it
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RxDescriptor {
+	address: u64,
+	length: u16,
+	checksum: u16,
+	status: u8,
+	errors: u8,
+	special: u16,
+}
+
+/// A transmit descriptor, in the legacy format
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TxDescriptor {
+	address: u64,
+	length: u16,
+	cso: u8,
+	command: u8,
+	status: u8,
+	css: u8,
+	special: u16,
+}
+
+/// Registers of each device bound, for the interrupt handler; 0 for none
+static REGISTERS: [AtomicU64; MAX_DEVICES] = [const { AtomicU64::new(0) }; MAX_DEVICES];
+
+/// Write a device register
+fn write(registers: VirtAddr, offset: usize, value: u32) {
+	unsafe { (registers + offset as u64).as_mut_ptr::<u32>().write_volatile(value) }
+}
+
+/// Read a device register
+fn read(registers: VirtAddr, offset: usize) -> u32 {
+	unsafe { (registers + offset as u64).as_ptr::<u32>().read_volatile() }
+}
+
+/// An e1000 and the DMA memory it shares with the kernel
+struct E1000 {
+	registers: VirtAddr,
+	mac: MacAddress,
+	rx_ring: VirtAddr,
+	/// Where the kernel reaches each receive buffer
+	rx_buffers: [VirtAddr; RX_COUNT],
+	/// The next descriptor the device will fill
+	rx_next: usize,
+	tx_ring: VirtAddr,
+	tx_buffers: [VirtAddr; TX_COUNT],
+	/// The next descriptor to hand the device
+	tx_next: usize,
+}
+
+/// Allocate DMA frames for `N` packet buffers, returning each buffer's
+/// physical and virtual address
+fn allocate_buffers<const N: usize>() -> Option<[(PhysAddr, VirtAddr); N]> {
+	let mut buffers = [(PhysAddr::zero(), VirtAddr::zero()); N];
+	for chunk in buffers.chunks_mut(BUFFERS_PER_FRAME) {
+		let (physical, virtual_address) = memory::allocate_dma(4096)?;
+		for (index, buffer) in chunk.iter_mut().enumerate() {
+			let offset = (index * BUFFER_SIZE) as u64;
+			*buffer = (physical + offset, virtual_address + offset);
+		}
+	}
+	Some(buffers)
+}
+
+impl E1000 {
+	fn rx_descriptor(&self, index: usize) -> *mut RxDescriptor {
+		unsafe { self.rx_ring.as_mut_ptr::<RxDescriptor>().add(index) }
+	}
+
+	fn tx_descriptor(&self, index: usize) -> *mut TxDescriptor {
+		unsafe { self.tx_ring.as_mut_ptr::<TxDescriptor>().add(index) }
+	}
+
+	/// Read a word of the EEPROM, or `None` if it does not answer
+	fn read_eeprom(registers: VirtAddr, word: u8) -> Option<u16> {
+		// Try the 82540's layout, then the 82574's
+		for (shift, done) in [(8, EERD_DONE), (2, EERD_DONE_82574)] {
+			write(registers, REG_EERD, u32::from(word) << shift | EERD_START);
+			for _ in 0..POLL_TRIES {
+				let value = read(registers, REG_EERD);
+				if value & done != 0 {
+					return Some((value >> 16) as u16);
+				}
+				core::hint::spin_loop();
+			}
+		}
+		None
+	}
+
+	/// The MAC address from the EEPROM, or the one the firmware loaded
+	/// into the first receive address if there is no EEPROM
+	fn read_mac(registers: VirtAddr) -> MacAddress {
+		let words = [0, 1, 2].map(|word| Self::read_eeprom(registers, word));
+		if let [Some(a), Some(b), Some(c)] = words {
+			let [a0, a1] = a.to_le_bytes();
+			let [b0, b1] = b.to_le_bytes();
+			let [c0, c1] = c.to_le_bytes();
+			return MacAddress([a0, a1, b0, b1, c0, c1]);
+		}
+		let low = read(registers, REG_RAL).to_le_bytes();
+		let high = read(registers, REG_RAH).to_le_bytes();
+		MacAddress([low[0], low[1], low[2], low[3], high[0], high[1]])
+	}
+
+	/// Reset the device and bring up its rings; interrupts stay masked
+	fn new(registers: VirtAddr) -> Option<E1000> {
+		write(registers, REG_IMC, u32::MAX);
+		write(registers, REG_CTRL, read(registers, REG_CTRL) | CTRL_RST);
+		// The reset bit clears itself within a microsecond or so
+		for _ in 0..POLL_TRIES {
+			if read(registers, REG_CTRL) & CTRL_RST == 0 {
+				break;
+			}
+			core::hint::spin_loop();
+		}
+		write(registers, REG_IMC, u32::MAX);
+		read(registers, REG_ICR);
+		write(registers, REG_CTRL, read(registers, REG_CTRL) | CTRL_SLU | CTRL_ASDE);
+
+		let mac = Self::read_mac(registers);
+		// Receive addresses go in the first filter, marked valid
+		let [m0, m1, m2, m3, m4, m5] = mac.0;
+		write(registers, REG_RAL, u32::from_le_bytes([m0, m1, m2, m3]));
+		write(registers, REG_RAH, u32::from_le_bytes([m4, m5, 0, 0]) | 1 << 31);
+		for entry in 0..128 {
+			write(registers, REG_MTA + entry * 4, 0);
+		}
+
+		let (rx_ring_physical, rx_ring) = memory::allocate_dma(4096)?;
+		let rx = allocate_buffers::<RX_COUNT>()?;
+		let (tx_ring_physical, tx_ring) = memory::allocate_dma(4096)?;
+		let tx = allocate_buffers::<TX_COUNT>()?;
+		let device = E1000 {
+			registers,
+			mac,
+			rx_ring,
+			rx_buffers: rx.map(|(_, virtual_address)| virtual_address),
+			rx_next: 0,
+			tx_ring,
+			tx_buffers: tx.map(|(_, virtual_address)| virtual_address),
+			tx_next: 0,
+		};
+		for (index, &(physical, _)) in rx.iter().enumerate() {
+			let descriptor = RxDescriptor { address: physical.as_u64(), length: 0, checksum: 0, status: 0, errors: 0, special: 0 };
+			unsafe { device.rx_descriptor(index).write_volatile(descriptor) };
+		}
+		for (index, &(physical, _)) in tx.iter().enumerate() {
+			// Free slots are the ones the device is done with
+			let descriptor = TxDescriptor { address: physical.as_u64(), length: 0, cso: 0, command: 0, status: DESC_DD, css: 0, special: 0 };
+			unsafe { device.tx_descriptor(index).write_volatile(descriptor) };
+		}
+
+		write(registers, REG_RDBAL, rx_ring_physical.as_u64() as u32);
+		write(registers, REG_RDBAH, (rx_ring_physical.as_u64() >> 32) as u32);
+		write(registers, REG_RDLEN, (RX_COUNT * core::mem::size_of::<RxDescriptor>()) as u32);
+		write(registers, REG_RDH, 0);
+		// Every buffer but one is the device's to fill
+		write(registers, REG_RDT, RX_COUNT as u32 - 1);
+		write(registers, REG_RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+
+		write(registers, REG_TDBAL, tx_ring_physical.as_u64() as u32);
+		write(registers, REG_TDBAH, (tx_ring_physical.as_u64() >> 32) as u32);
+		write(registers, REG_TDLEN, (TX_COUNT * core::mem::size_of::<TxDescriptor>()) as u32);
+		write(registers, REG_TDH, 0);
+		write(registers, REG_TDT, 0);
+		write(registers, REG_TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+		write(registers, REG_TIPG, TIPG_COPPER);
+		Some(device)
+	}
+}
+
+impl super::Driver for E1000 {
+	fn mac(&self) -> MacAddress {
+		self.mac
+	}
+
+	fn link_up(&self) -> bool {
+		read(self.registers, REG_STATUS) & STATUS_LU != 0
+	}
+
+	fn transmit(&mut self, frame: &[u8]) -> Result<(), TransmitError> {
+		if frame.len() > MAX_FRAME {
+			return Err(TransmitError::TooLong);
+		}
+		let descriptor = self.tx_descriptor(self.tx_next);
+		let mut entry = unsafe { descriptor.read_volatile() };
+		if entry.status & DESC_DD == 0 {
+			return Err(TransmitError::Busy);
+		}
+		let buffer = self.tx_buffers[self.tx_next].as_mut_ptr::<u8>();
+		unsafe { core::ptr::copy_nonoverlapping(frame.as_ptr(), buffer, frame.len()) };
+		entry.length = frame.len() as u16;
+		entry.command = CMD_EOP | CMD_IFCS | CMD_RS;
+		entry.status = 0;
+		unsafe { descriptor.write_volatile(entry) };
+		self.tx_next = (self.tx_next + 1) % TX_COUNT;
+		write(self.registers, REG_TDT, self.tx_next as u32);
+		Ok(())
+	}
+
+	fn receive(&mut self, deliver: &mut dyn FnMut(&[u8])) {
+		loop {
+			let descriptor = self.rx_descriptor(self.rx_next);
+			let mut entry = unsafe { descriptor.read_volatile() };
+			if entry.status & DESC_DD == 0 {
+				break;
+			}
+			// Frames fit a buffer, so anything split or damaged is dropped
+			if entry.status & DESC_EOP != 0 && entry.errors == 0 {
+				let length = usize::from(entry.length).min(BUFFER_SIZE);
+				let buffer = self.rx_buffers[self.rx_next].as_ptr::<u8>();
+				deliver(unsafe { core::slice::from_raw_parts(buffer, length) });
+			}
+			entry.status = 0;
+			unsafe { descriptor.write_volatile(entry) };
+			// The buffer just emptied is the device's again
+			write(self.registers, REG_RDT, self.rx_next as u32);
+			self.rx_next = (self.rx_next + 1) % RX_COUNT;
+		}
+	}
+}
+
+/// Acknowledge the interrupts of every e1000 that raised one, and collect
+/// received frames afterwards if any did
+fn handle_interrupt() {
+	let mut pending = false;
+	for registers in &REGISTERS {
+		let registers = registers.load(Ordering::Acquire);
+		if registers != 0 {
+			// Reading the cause clears it
+			pending |= read(VirtAddr::new(registers), REG_ICR) != 0;
+		}
+	}
+	if pending {
+		super::receive_interrupt();
+	}
+}
+
+/// Bind an e1000: map its registers, set up its rings, and take its
+/// interrupt, by message if it can send one
+fn probe(device: &pci::Device) -> Result<(), &'static str> {
+	let Some(&Bar::Memory { address, .. }) = device.bars.first() else {
+		return Err("no register BAR");
+	};
+	let slot = REGISTERS.iter().position(|registers| registers.load(Ordering::Relaxed) == 0).ok_or("too many devices")?;
+	let registers = memory::map_mmio(PhysAddr::new(address), REGISTERS_SIZE).ok_or("cannot map registers")?;
+	device.enable_bus_master();
+	let e1000 = E1000::new(registers).ok_or("out of DMA memory")?;
+
+	REGISTERS[slot].store(registers.as_u64(), Ordering::Release);
+	if device.enable_msi(handle_interrupt).is_err() {
+		let line = device.interrupt_line.filter(|&line| interrupts::register_irq(line, handle_interrupt).is_ok());
+		let Some(line) = line else {
+			REGISTERS[slot].store(0, Ordering::Release);
+			return Err("no interrupt");
+		};
+		interrupts::enable_irq(line);
+	}
+	super::register(Box::new(e1000));
+	write(registers, REG_IMS, INT_LSC | INT_RXDMT0 | INT_RXO | INT_RXT0);
+	Ok(())
+}
+
+/// Test that descriptors have the layout the device expects and buffers
+/// split frames evenly
+#[test_case]
+fn test_descriptors() {
+	assert_eq!(core::mem::size_of::<RxDescriptor>(), 16);
+	assert_eq!(core::mem::size_of::<TxDescriptor>(), 16);
+	assert_eq!((RX_COUNT * 16) % 128, 0);
+	assert_eq!((TX_COUNT * 16) % 128, 0);
+	assert_eq!(RX_COUNT % BUFFERS_PER_FRAME, 0);
+}
@@ -0,0 +1,67 @@
+use super::MacAddress;
+use alloc::vec::Vec;
+
+/// Size of an Ethernet II header
+pub const HEADER_SIZE: usize = 14;
+/// The most a frame carries after its header
+pub const MTU: usize = super::MAX_FRAME - HEADER_SIZE;
+
+/// What a frame carries, as its header numbers it
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+/// The header at the start of every frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+	pub destination: MacAddress,
+	pub source: MacAddress,
+	pub ethertype: u16,
+}
+
+impl Header {
+	/// Split a frame into its header and payload
+	pub fn parse(frame: &[u8]) -> Option<(Header, &[u8])> {
+		if frame.len() < HEADER_SIZE {
+			return None;
+		}
+		let mac = |start: usize| MacAddress(frame[start..start + 6].try_into().unwrap_or_default());
+		let header = Header {
+			destination: mac(0),
+			source: mac(6),
+			ethertype: u16::from_be_bytes([frame[12], frame[13]]),
+		};
+		Some((header, &frame[HEADER_SIZE..]))
+	}
+
+	/// A frame of this header followed by `payload`
+	pub fn frame(&self, payload: &[u8]) -> Vec<u8> {
+		let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+		frame.extend_from_slice(&self.destination.0);
+		frame.extend_from_slice(&self.source.0);
+		frame.extend_from_slice(&self.ethertype.to_be_bytes());
+		frame.extend_from_slice(payload);
+		frame
+	}
+}
+
+impl MacAddress {
+	/// Whether frames to this address go to every station or a group
+	pub fn is_multicast(self) -> bool {
+		self.0[0] & 1 != 0
+	}
+}
+
+/// Test that headers survive a round trip and short frames are refused
+#[test_case]
+fn test_ethernet_header() {
+	let header = Header {
+		destination: MacAddress::BROADCAST,
+		source: MacAddress([0x52, 0x54, 0, 0x12, 0x34, 0x56]),
+		ethertype: ETHERTYPE_ARP,
+	};
+	let frame = header.frame(&[1, 2, 3]);
+	assert_eq!(&frame[12..], &[0x08, 0x06, 1, 2, 3]);
+	assert_eq!(Header::parse(&frame), Some((header, &[1u8, 2, 3][..])));
+	assert_eq!(Header::parse(&frame[..13]), None);
+	assert!(MacAddress::BROADCAST.is_multicast() && !header.source.is_multicast());
+}
@@ -0,0 +1,130 @@
+use super::ipv4::{self, SendError};
+use crate::time::Instant;
+use alloc::{collections::BTreeMap, collections::VecDeque, vec::Vec};
+use core::net::Ipv4Addr;
+use core::sync::atomic::{AtomicU16, Ordering};
+use crate::sync::Mutex;
+
+/// Message types
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_ECHO_REQUEST: u8 = 8;
+/// Size of an echo message before its data
+pub const ECHO_HEADER_SIZE: usize = 8;
+
+/// Replies that can wait for a pinger at once; more are dropped
+const MAX_REPLIES: usize = 16;
+
+/// An echo reply, as a pinger gets it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EchoReply {
+	pub source: Ipv4Addr,
+	pub sequence: u16,
+	pub ttl: u8,
+	/// Bytes of the message, header included
+	pub length: usize,
+	pub received: Instant,
+}
+
+/// Replies waiting for each open echo identifier
+static PINGERS: Mutex<BTreeMap<u16, VecDeque<EchoReply>>> = Mutex::new(BTreeMap::new());
+/// The identifier the next pinger tries first
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// An echo message of `kind` with its checksum in place
+fn echo_message(kind: u8, id: u16, sequence: u16, data: &[u8]) -> Vec<u8> {
+	let mut message = Vec::with_capacity(ECHO_HEADER_SIZE + data.len());
+	message.extend_from_slice(&[kind, 0, 0, 0]);
+	message.extend_from_slice(&id.to_be_bytes());
+	message.extend_from_slice(&sequence.to_be_bytes());
+	message.extend_from_slice(data);
+	let sum = ipv4::checksum(&[&message]);
+	message[2..4].copy_from_slice(&sum.to_be_bytes());
+	message
+}
+
+/// Handle an ICMP message addressed to us: answer echo requests, and hand
+/// echo replies to whoever is waiting for them
+pub fn receive(header: &ipv4::Header, message: &[u8]) {
+	if message.len() < ECHO_HEADER_SIZE || ipv4::checksum(&[message]) != 0 {
+		return;
+	}
+	let id = u16::from_be_bytes([message[4], message[5]]);
+	let sequence = u16::from_be_bytes([message[6], message[7]]);
+	match message[0] {
+		TYPE_ECHO_REQUEST => {
+			// Requests to a broadcast address go unanswered, as most hosts do
+			if super::is_local_address(header.destination) {
+				let reply = echo_message(TYPE_ECHO_REPLY, id, sequence, &message[ECHO_HEADER_SIZE..]);
+				let _ = ipv4::send(header.source, ipv4::PROTOCOL_ICMP, &reply);
+			}
+		}
+		TYPE_ECHO_REPLY => {
+			let mut pingers = PINGERS.lock();
+			if let Some(replies) = pingers.get_mut(&id) {
+				if replies.len() < MAX_REPLIES {
+					let received = Instant::now();
+					replies.push_back(EchoReply { source: header.source, sequence, ttl: header.ttl, length: message.len(), received });
+				}
+			}
+		}
+		_ => {}
+	}
+}
+
+/// An echo identifier of one's own, collecting the replies to it until it
+/// is dropped
+pub struct Pinger {
+	id: u16,
+}
+
+impl Pinger {
+	pub fn new() -> Pinger {
+		let mut pingers = PINGERS.lock();
+		let mut id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+		while pingers.contains_key(&id) {
+			id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+		}
+		pingers.insert(id, VecDeque::new());
+		Pinger { id }
+	}
+
+	/// Send an echo request carrying `data` to `destination`
+	pub fn send(&self, destination: Ipv4Addr, sequence: u16, data: &[u8]) -> Result<(), SendError> {
+		ipv4::send(destination, ipv4::PROTOCOL_ICMP, &echo_message(TYPE_ECHO_REQUEST, self.id, sequence, data))
+	}
+
+	/// The oldest reply not yet taken
+	pub fn take_reply(&self) -> Option<EchoReply> {
+		PINGERS.lock().get_mut(&self.id)?.pop_front()
+	}
+}
+
+impl Default for Pinger {
+	fn default() -> Pinger {
+		Pinger::new()
+	}
+}
+
+impl Drop for Pinger {
+	fn drop(&mut self) {
+		PINGERS.lock().remove(&self.id);
+	}
+}
+
+/// Test echo messages' layout and checksum, and that replies reach the
+/// pinger they are for
+#[test_case]
+fn test_echo() {
+	let request = echo_message(TYPE_ECHO_REQUEST, 0x1234, 1, b"ab");
+	assert_eq!(&request[..ECHO_HEADER_SIZE], &[8, 0, 0x84, 0x68, 0x12, 0x34, 0, 1]);
+	assert_eq!(ipv4::checksum(&[&request]), 0);
+
+	let pinger = Pinger::new();
+	let header = ipv4::Header { source: Ipv4Addr::new(10, 0, 2, 2), destination: Ipv4Addr::UNSPECIFIED, protocol: ipv4::PROTOCOL_ICMP, ttl: 255 };
+	receive(&header, &echo_message(TYPE_ECHO_REPLY, pinger.id.wrapping_add(1), 1, b""));
+	assert_eq!(pinger.take_reply(), None);
+	receive(&header, &echo_message(TYPE_ECHO_REPLY, pinger.id, 3, b"ab"));
+	let reply = pinger.take_reply().unwrap();
+	assert_eq!((reply.source, reply.sequence, reply.ttl, reply.length), (header.source, 3, 255, 10));
+	assert_eq!(pinger.take_reply(), None);
+}
@@ -0,0 +1,201 @@
+use super::{ethernet, MacAddress};
+use crate::klog;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// Size of a header without options
+pub const HEADER_SIZE: usize = 20;
+/// The most a packet carries after its header, so it fits one frame
+pub const MAX_PAYLOAD: usize = ethernet::MTU - HEADER_SIZE;
+
+/// Protocols a packet carries, as its header numbers them
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+/// Hops a packet we send may take
+const DEFAULT_TTL: u8 = 64;
+/// Flags: don't fragment, and more fragments follow, beside the offset
+const FLAG_DF: u16 = 0x4000;
+const FLAG_MF: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1fff;
+
+/// Identification of the next packet sent
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// Why a packet could not be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+	/// No interface is configured for a network with the destination in it,
+	/// and none has a gateway
+	NoRoute,
+	/// Longer than `MAX_PAYLOAD`; we don't fragment
+	TooLong,
+}
+
+/// The Internet checksum of `parts` one after another; every part but the
+/// last must have an even length
+pub fn checksum(parts: &[&[u8]]) -> u16 {
+	let mut sum: u32 = 0;
+	for part in parts {
+		for pair in part.chunks(2) {
+			let high = u32::from(pair[0]) << 8;
+			sum += high | pair.get(1).copied().map_or(0, u32::from);
+		}
+	}
+	while sum > 0xffff {
+		sum = (sum & 0xffff) + (sum >> 16);
+	}
+	!(sum as u16)
+}
+
+/// The checksum UDP and TCP use: of `data`, after a pseudo-header of the
+/// addresses, the protocol, and the length of `data`
+pub fn pseudo_header_checksum(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, data: &[u8]) -> u16 {
+	let mut pseudo_header = [0; 12];
+	pseudo_header[..4].copy_from_slice(&source.octets());
+	pseudo_header[4..8].copy_from_slice(&destination.octets());
+	pseudo_header[9] = protocol;
+	pseudo_header[10..].copy_from_slice(&(data.len() as u16).to_be_bytes());
+	checksum(&[&pseudo_header, data])
+}
+
+/// The fields of a header the stack looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+	pub source: Ipv4Addr,
+	pub destination: Ipv4Addr,
+	pub protocol: u8,
+	pub ttl: u8,
+}
+
+impl Header {
+	/// Split a packet into its header and payload; packets with a bad
+	/// checksum, and fragments, which we don't reassemble, are refused
+	pub fn parse(packet: &[u8]) -> Option<(Header, &[u8])> {
+		if packet.len() < HEADER_SIZE || packet[0] >> 4 != 4 {
+			return None;
+		}
+		let header_length = usize::from(packet[0] & 0xf) * 4;
+		let total_length = usize::from(u16::from_be_bytes([packet[2], packet[3]]));
+		if header_length < HEADER_SIZE || total_length < header_length || total_length > packet.len() {
+			return None;
+		}
+		if checksum(&[&packet[..header_length]]) != 0 {
+			return None;
+		}
+		let flags = u16::from_be_bytes([packet[6], packet[7]]);
+		if flags & (FLAG_MF | FRAGMENT_OFFSET) != 0 {
+			return None;
+		}
+		let ip = |at: usize| Ipv4Addr::new(packet[at], packet[at + 1], packet[at + 2], packet[at + 3]);
+		let header = Header { source: ip(12), destination: ip(16), protocol: packet[9], ttl: packet[8] };
+		// Frames are padded to a minimum length, so the packet may end early
+		Some((header, &packet[header_length..total_length]))
+	}
+
+	/// A packet of this header followed by `payload`, identified by `id`
+	pub fn packet(&self, id: u16, payload: &[u8]) -> Vec<u8> {
+		let total_length = (HEADER_SIZE + payload.len()) as u16;
+		let mut packet = Vec::with_capacity(HEADER_SIZE + payload.len());
+		packet.extend_from_slice(&[0x45, 0]);
+		packet.extend_from_slice(&total_length.to_be_bytes());
+		packet.extend_from_slice(&id.to_be_bytes());
+		packet.extend_from_slice(&FLAG_DF.to_be_bytes());
+		packet.extend_from_slice(&[self.ttl, self.protocol, 0, 0]);
+		packet.extend_from_slice(&self.source.octets());
+		packet.extend_from_slice(&self.destination.octets());
+		let sum = checksum(&[&packet]);
+		packet[10..12].copy_from_slice(&sum.to_be_bytes());
+		packet.extend_from_slice(payload);
+		packet
+	}
+}
+
+/// Where a packet to `destination` goes: the interface, the next hop on its
+/// link, and the address to send from
+///
+/// Packets to ourselves go through `lo`; then an interface with the
+/// destination on its network comes first, and otherwise the first
+/// interface with a gateway.
+pub fn route(destination: Ipv4Addr) -> Option<(usize, Ipv4Addr, Ipv4Addr)> {
+	let configs = super::configs();
+	if let Some(loopback) = super::loopback().filter(|_| super::is_local_address(destination)) {
+		let source = if configs[loopback].contains(destination) { configs[loopback].address? } else { destination };
+		return Some((loopback, destination, source));
+	}
+	let local = configs.iter().enumerate().find_map(|(index, config)| {
+		let address = config.address?;
+		(config.contains(destination) || destination.is_broadcast()).then_some((index, destination, address))
+	});
+	local.or_else(|| {
+		configs.iter().enumerate().find_map(|(index, config)| Some((index, config.gateway?, config.address?)))
+	})
+}
+
+/// Send `payload` to `destination` as a packet of `protocol`
+///
+/// The packet waits in the ARP cache if the next hop has to be resolved
+/// first; a packet that is lost on the way is not reported.
+pub fn send(destination: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), SendError> {
+	if payload.len() > MAX_PAYLOAD {
+		return Err(SendError::TooLong);
+	}
+	let (interface, next_hop, source) = route(destination).ok_or(SendError::NoRoute)?;
+	crate::trace_event!(Net, "ipv4 send to {} proto {} len {}", destination, protocol, payload.len());
+	let header = Header { source, destination, protocol, ttl: DEFAULT_TTL };
+	let packet = header.packet(NEXT_ID.fetch_add(1, Ordering::Relaxed), payload);
+	if Some(interface) == super::loopback() {
+		let mac = super::mac(interface).unwrap_or(MacAddress::BROADCAST);
+		let _ = super::send_frame(interface, mac, ethernet::ETHERTYPE_IPV4, &packet);
+	} else if destination.is_broadcast() || super::config(interface).broadcast() == Some(destination) {
+		let _ = super::send_frame(interface, MacAddress::BROADCAST, ethernet::ETHERTYPE_IPV4, &packet);
+	} else {
+		super::arp::send_ipv4(interface, next_hop, packet);
+	}
+	Ok(())
+}
+
+/// Handle an IPv4 packet received on `interface`
+pub fn receive(interface: usize, data: &[u8]) {
+	let Some((header, payload)) = Header::parse(data) else {
+		return;
+	};
+	let config = super::config(interface);
+	// Whatever comes in on `lo` was sent to one of our addresses
+	let for_us = Some(header.destination) == config.address
+		|| header.destination.is_broadcast()
+		|| Some(header.destination) == config.broadcast()
+		|| (Some(interface) == super::loopback() && super::is_local_address(header.destination));
+	if !for_us {
+		return;
+	}
+	crate::trace_event!(Net, "ipv4 receive from {} proto {} len {}", header.source, header.protocol, payload.len());
+	match header.protocol {
+		PROTOCOL_ICMP => super::icmp::receive(&header, payload),
+		PROTOCOL_TCP => super::tcp::receive(&header, payload),
+		PROTOCOL_UDP => super::udp::receive(&header, payload),
+		protocol => klog!(Trace, "{} -> {}: ignoring protocol {}", header.source, header.destination, protocol),
+	}
+}
+
+/// Test checksums, building and parsing headers, and refusing fragments
+#[test_case]
+fn test_ipv4_header() {
+	// A header off the wire, with its checksum of b861 in place
+	let sample = [0x45, 0, 0, 0x73, 0, 0, 0x40, 0, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0, 1, 0xc0, 0xa8, 0, 0xc7];
+	assert_eq!(checksum(&[&sample]), 0);
+	assert_eq!(checksum(&[&[0x01], &[]]), !0x0100);
+
+	let header = Header { source: Ipv4Addr::new(10, 0, 2, 15), destination: Ipv4Addr::new(10, 0, 2, 2), protocol: PROTOCOL_UDP, ttl: 64 };
+	let mut packet = header.packet(7, &[1, 2, 3]);
+	assert_eq!(packet.len(), HEADER_SIZE + 3);
+	assert_eq!(Header::parse(&packet), Some((header, &[1u8, 2, 3][..])));
+	// Padding after the packet is not payload
+	packet.push(0);
+	assert_eq!(Header::parse(&packet).map(|(_, payload)| payload.len()), Some(3));
+
+	packet[6] |= (FLAG_MF >> 8) as u8;
+	assert_eq!(Header::parse(&packet), None);
+}
@@ -0,0 +1,520 @@
+pub mod arp;
+pub mod dns;
+pub mod e1000;
+pub mod ethernet;
+pub mod icmp;
+pub mod ipv4;
+pub mod loopback;
+pub mod pcap;
+pub mod rtl8139;
+pub mod sntp;
+pub mod tcp;
+pub mod udp;
+pub mod virtio_net;
+
+use crate::{cmdline, klog};
+use alloc::{boxed::Box, collections::{BTreeMap, VecDeque}, format, string::String, vec::Vec};
+use core::fmt;
+use core::net::Ipv4Addr;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::task::Poll;
+use futures_util::task::AtomicWaker;
+use crate::sync::Mutex;
+
+/// Largest Ethernet frame a driver sends or receives, without the CRC
+pub const MAX_FRAME: usize = 1514;
+
+/// Received frames that can wait for the stack at once; more are dropped
+const INPUT_CAPACITY: usize = 256;
+/// Ports handed to sockets that send, connect, or listen before binding
+pub const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// A link-layer address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+	pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+}
+
+impl fmt::Display for MacAddress {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let [a, b, c, d, e, g] = self.0;
+		write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+	}
+}
+
+/// Why a frame could not be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransmitError {
+	/// Longer than `MAX_FRAME`
+	TooLong,
+	/// Every transmit slot is still waiting for the device
+	Busy,
+	/// No interface has that index
+	NoInterface,
+	/// The interface has been taken down
+	Down,
+}
+
+/// What a network interface's driver does for the stack
+pub trait Driver: Send {
+	fn mac(&self) -> MacAddress;
+
+	/// Whether the link is up, as far as the device knows
+	fn link_up(&self) -> bool;
+
+	/// Queue a frame for the device to send
+	fn transmit(&mut self, frame: &[u8]) -> Result<(), TransmitError>;
+
+	/// Hand each frame received since the last call to `deliver`
+	fn receive(&mut self, deliver: &mut dyn FnMut(&[u8]));
+}
+
+/// An interface's IPv4 address, the network it is on, and the gateway off
+/// that network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IpConfig {
+	/// `None` while the interface has no address, so takes no part in IP
+	pub address: Option<Ipv4Addr>,
+	/// Length of the network part of the address, in bits
+	pub prefix: u8,
+	pub gateway: Option<Ipv4Addr>,
+}
+
+impl IpConfig {
+	/// What QEMU's user networking hands out
+	pub const QEMU_USER: IpConfig = IpConfig {
+		address: Some(Ipv4Addr::new(10, 0, 2, 15)),
+		prefix: 24,
+		gateway: Some(Ipv4Addr::new(10, 0, 2, 2)),
+	};
+
+	/// What `lo` has
+	pub const LOOPBACK: IpConfig = IpConfig { address: Some(Ipv4Addr::LOCALHOST), prefix: 8, gateway: None };
+
+	/// Parse `ADDRESS/PREFIX[,GATEWAY]`, or `off` for no address
+	pub fn parse(text: &str) -> Option<IpConfig> {
+		if text == "off" {
+			return Some(IpConfig::default());
+		}
+		let (network, gateway) = match text.split_once(',') {
+			Some((network, gateway)) => (network, Some(gateway.parse().ok()?)),
+			None => (text, None),
+		};
+		let (address, prefix) = network.split_once('/')?;
+		let prefix = prefix.parse().ok().filter(|&prefix| prefix <= 32)?;
+		Some(IpConfig { address: Some(address.parse().ok()?), prefix, gateway })
+	}
+
+	pub fn netmask(&self) -> Ipv4Addr {
+		Ipv4Addr::from(u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0))
+	}
+
+	/// Whether `ip` is on this interface's network
+	pub fn contains(&self, ip: Ipv4Addr) -> bool {
+		let mask = u32::from(self.netmask());
+		self.address.is_some_and(|address| u32::from(address) & mask == u32::from(ip) & mask)
+	}
+
+	/// The address reaching every host on the network
+	pub fn broadcast(&self) -> Option<Ipv4Addr> {
+		Some(Ipv4Addr::from(u32::from(self.address?) | !u32::from(self.netmask())))
+	}
+}
+
+impl fmt::Display for IpConfig {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let Some(address) = self.address else {
+			return write!(f, "off");
+		};
+		write!(f, "{}/{}", address, self.prefix)?;
+		match self.gateway {
+			Some(gateway) => write!(f, ",{}", gateway),
+			None => Ok(()),
+		}
+	}
+}
+
+/// The configuration of the first interface: from the `ip=` parameter, or
+/// what QEMU's user networking expects
+fn boot_config() -> IpConfig {
+	match cmdline::param("ip") {
+		Some(text) => IpConfig::parse(text).unwrap_or_else(|| {
+			klog!(Warn, "bad ip={}, leaving eth0 unconfigured", text);
+			IpConfig::default()
+		}),
+		None => IpConfig::QEMU_USER,
+	}
+}
+
+/// Why a port could not be bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortError {
+	/// Another socket is bound to it
+	InUse,
+	/// Every ephemeral port is taken
+	NoneFree,
+}
+
+/// Which socket of one protocol each port is bound to
+pub struct PortAllocator<Id> {
+	ports: BTreeMap<u16, Id>,
+	/// Where the search for a free ephemeral port starts
+	next_ephemeral: u16,
+}
+
+impl<Id: Copy + PartialEq> Default for PortAllocator<Id> {
+	fn default() -> Self {
+		PortAllocator::new()
+	}
+}
+
+impl<Id: Copy + PartialEq> PortAllocator<Id> {
+	pub const fn new() -> Self {
+		PortAllocator { ports: BTreeMap::new(), next_ephemeral: *EPHEMERAL_PORTS.start() }
+	}
+
+	/// Bind `id` to `port`, or to a free ephemeral port for 0
+	pub fn bind(&mut self, id: Id, port: u16) -> Result<u16, PortError> {
+		let port = match port {
+			0 => self.free_ephemeral().ok_or(PortError::NoneFree)?,
+			port if self.ports.contains_key(&port) => return Err(PortError::InUse),
+			port => port,
+		};
+		self.ports.insert(port, id);
+		Ok(port)
+	}
+
+	/// The next ephemeral port nothing is bound to, taking them in turn
+	fn free_ephemeral(&mut self) -> Option<u16> {
+		for _ in EPHEMERAL_PORTS {
+			let port = self.next_ephemeral;
+			self.next_ephemeral = if port == *EPHEMERAL_PORTS.end() { *EPHEMERAL_PORTS.start() } else { port + 1 };
+			if !self.ports.contains_key(&port) {
+				return Some(port);
+			}
+		}
+		None
+	}
+
+	/// The socket bound to `port`
+	pub fn get(&self, port: u16) -> Option<Id> {
+		self.ports.get(&port).copied()
+	}
+
+	/// Unbind `port` if `id` is what is bound to it
+	pub fn release(&mut self, port: u16, id: Id) {
+		if self.get(port) == Some(id) {
+			self.ports.remove(&port);
+		}
+	}
+}
+
+/// A network interface and what has passed through it
+struct Interface {
+	name: String,
+	driver: Box<dyn Driver>,
+	ip: IpConfig,
+	/// Whether it sends and receives; one taken down with `ifconfig` doesn't
+	up: bool,
+	rx_packets: u64,
+	rx_bytes: u64,
+	tx_packets: u64,
+	tx_bytes: u64,
+}
+
+/// Every interface, in the order its driver registered it
+static INTERFACES: Mutex<Vec<Interface>> = Mutex::new(Vec::new());
+/// Index of `lo`, once it is registered
+static LOOPBACK: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// A received frame waiting for the stack, with the interface it came in on
+pub struct Frame {
+	pub interface: usize,
+	pub data: Vec<u8>,
+}
+
+/// Frames received and not yet handled by the stack
+static INPUT: Mutex<VecDeque<Frame>> = Mutex::new(VecDeque::new());
+/// Wakes the stack's task when frames arrive
+static INPUT_WAKER: AtomicWaker = AtomicWaker::new();
+/// Frames dropped because the input queue was full
+static INPUT_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Add an interface, named `eth0`, `eth1`, and so on; returns its index
+pub fn register(driver: Box<dyn Driver>) -> usize {
+	let mut interfaces = INTERFACES.lock();
+	let number = interfaces.len() - usize::from(LOOPBACK.load(Ordering::Relaxed) < interfaces.len());
+	// Only the first interface is configured; others need `ifconfig`
+	let ip = if number == 0 { boot_config() } else { IpConfig::default() };
+	klog!(Info, "eth{} has address {}, IP {}", number, driver.mac(), ip);
+	add(&mut interfaces, format!("eth{}", number), driver, ip)
+}
+
+/// Add `lo`, the interface that sends to ourselves
+fn register_loopback() {
+	let mut interfaces = INTERFACES.lock();
+	let index = add(&mut interfaces, String::from("lo"), Box::new(loopback::Loopback::new()), IpConfig::LOOPBACK);
+	LOOPBACK.store(index, Ordering::Relaxed);
+}
+
+/// Add an interface that is up, returning its index
+fn add(interfaces: &mut Vec<Interface>, name: String, driver: Box<dyn Driver>, ip: IpConfig) -> usize {
+	interfaces.push(Interface { name, driver, ip, up: true, rx_packets: 0, rx_bytes: 0, tx_packets: 0, tx_bytes: 0 });
+	interfaces.len() - 1
+}
+
+/// The index of `lo`, if it is registered
+pub fn loopback() -> Option<usize> {
+	let index = LOOPBACK.load(Ordering::Relaxed);
+	(index != usize::MAX).then_some(index)
+}
+
+/// The index of the interface called `name`
+pub fn find(name: &str) -> Option<usize> {
+	INTERFACES.lock().iter().position(|interface| interface.name == name)
+}
+
+/// The IP configuration of an interface; an unconfigured one if there is
+/// no such interface
+pub fn config(interface: usize) -> IpConfig {
+	INTERFACES.lock().get(interface).map(|interface| interface.ip).unwrap_or_default()
+}
+
+/// The IP configuration of every interface, in index order; one that is
+/// down has no address, so takes no part in IP
+pub fn configs() -> Vec<IpConfig> {
+	INTERFACES.lock().iter().map(|interface| if interface.up { interface.ip } else { IpConfig::default() }).collect()
+}
+
+/// Whether `ip` is ours: an up interface's address, or on `lo`'s network
+pub fn is_local_address(ip: Ipv4Addr) -> bool {
+	let loopback = loopback();
+	configs().iter().enumerate().any(|(index, config)| config.address == Some(ip) || (Some(index) == loopback && config.contains(ip)))
+}
+
+/// Change an interface's IP configuration; false if there is no such
+/// interface
+pub fn configure(interface: usize, ip: IpConfig) -> bool {
+	let mut interfaces = INTERFACES.lock();
+	let Some(interface) = interfaces.get_mut(interface) else {
+		return false;
+	};
+	interface.ip = ip;
+	true
+}
+
+/// Bring an interface up or take it down; false if there is no such
+/// interface
+pub fn set_up(interface: usize, up: bool) -> bool {
+	let mut interfaces = INTERFACES.lock();
+	let Some(interface) = interfaces.get_mut(interface) else {
+		return false;
+	};
+	interface.up = up;
+	true
+}
+
+/// An interface's hardware address
+pub fn mac(interface: usize) -> Option<MacAddress> {
+	INTERFACES.lock().get(interface).map(|interface| interface.driver.mac())
+}
+
+/// Interrupt-time half of receiving: have `poll_receive` collect frames
+///
+/// Called from drivers' interrupt handlers, so it must not block or
+/// allocate.
+pub fn receive_interrupt() {
+	crate::task::deferred::defer(poll_receive, 0);
+}
+
+/// Move every frame the interfaces have received onto the input queue
+fn poll_receive(_: usize) {
+	let mut received = false;
+	for (index, interface) in INTERFACES.lock().iter_mut().enumerate() {
+		let (mut packets, mut bytes) = (0, 0);
+		let up = interface.up;
+		interface.driver.receive(&mut |data| {
+			// Frames still have to be taken off a device that is down
+			if !up {
+				return;
+			}
+			pcap::capture(index, false, data);
+			packets += 1;
+			bytes += data.len() as u64;
+			let mut input = INPUT.lock();
+			if input.len() < INPUT_CAPACITY {
+				input.push_back(Frame { interface: index, data: Vec::from(data) });
+			} else {
+				INPUT_DROPPED.fetch_add(1, Ordering::Relaxed);
+			}
+		});
+		interface.rx_packets += packets;
+		interface.rx_bytes += bytes;
+		received |= packets > 0;
+	}
+	if received {
+		INPUT_WAKER.wake();
+	}
+}
+
+/// Send a frame out of an interface
+pub fn transmit(index: usize, frame: &[u8]) -> Result<(), TransmitError> {
+	let mut interfaces = INTERFACES.lock();
+	let interface = interfaces.get_mut(index).ok_or(TransmitError::NoInterface)?;
+	if !interface.up {
+		return Err(TransmitError::Down);
+	}
+	interface.driver.transmit(frame)?;
+	pcap::capture(index, true, frame);
+	interface.tx_packets += 1;
+	interface.tx_bytes += frame.len() as u64;
+	Ok(())
+}
+
+/// Send `payload` out of an interface in a frame of `ethertype` to
+/// `destination`
+pub fn send_frame(interface: usize, destination: MacAddress, ethertype: u16, payload: &[u8]) -> Result<(), TransmitError> {
+	let source = mac(interface).ok_or(TransmitError::NoInterface)?;
+	let frame = ethernet::Header { destination, source, ethertype }.frame(payload);
+	transmit(interface, &frame)
+}
+
+/// An interface as `ifconfig` shows it
+#[derive(Debug, Clone)]
+pub struct InterfaceInfo {
+	pub name: String,
+	pub mac: MacAddress,
+	pub link_up: bool,
+	pub up: bool,
+	pub loopback: bool,
+	pub ip: IpConfig,
+	pub rx_packets: u64,
+	pub rx_bytes: u64,
+	pub tx_packets: u64,
+	pub tx_bytes: u64,
+}
+
+/// A snapshot of every interface
+pub fn interfaces() -> Vec<InterfaceInfo> {
+	let loopback = loopback();
+	INTERFACES
+		.lock()
+		.iter()
+		.enumerate()
+		.map(|(index, interface)| InterfaceInfo {
+			name: interface.name.clone(),
+			mac: interface.driver.mac(),
+			link_up: interface.driver.link_up(),
+			up: interface.up,
+			loopback: Some(index) == loopback,
+			ip: interface.ip,
+			rx_packets: interface.rx_packets,
+			rx_bytes: interface.rx_bytes,
+			tx_packets: interface.tx_packets,
+			tx_bytes: interface.tx_bytes,
+		})
+		.collect()
+}
+
+/// Frames dropped since boot because the stack fell behind
+pub fn input_dropped() -> u64 {
+	INPUT_DROPPED.load(Ordering::Relaxed)
+}
+
+/// Take the next received frame off the input queue
+fn next_frame() -> Option<Frame> {
+	INPUT.lock().pop_front()
+}
+
+/// Hand a received frame to the protocol it carries, if it is for us
+fn handle_frame(frame: Frame) {
+	let Some((header, payload)) = ethernet::Header::parse(&frame.data) else {
+		return;
+	};
+	if !header.destination.is_multicast() && Some(header.destination) != mac(frame.interface) {
+		return;
+	}
+	match header.ethertype {
+		ethernet::ETHERTYPE_ARP => arp::receive(frame.interface, payload),
+		ethernet::ETHERTYPE_IPV4 => ipv4::receive(frame.interface, payload),
+		ethertype => klog!(Trace, "eth{}: ignoring ethertype {:#06x}", frame.interface, ethertype),
+	}
+}
+
+/// Add `lo` and `/dev/pcap`, and register the network drivers with the
+/// PCI bus; virtio comes first, so it is `eth0` when a machine has both
+pub fn init() {
+	register_loopback();
+	pcap::init();
+	crate::pci::register_driver(&virtio_net::DRIVER);
+	crate::pci::register_driver(&e1000::DRIVER);
+	crate::pci::register_driver(&rtl8139::DRIVER);
+}
+
+/// Collect and handle every frame received so far, for code that waits on
+/// the network while the network task cannot run
+pub fn poll() {
+	poll_receive(0);
+	while let Some(frame) = next_frame() {
+		handle_frame(frame);
+	}
+	tcp::tick();
+}
+
+/// The task that hands received frames to the stack and runs its timers
+pub async fn run_network() {
+	let frames = futures_util::future::poll_fn(|cx| {
+		INPUT_WAKER.register(cx.waker());
+		while let Some(frame) = next_frame() {
+			handle_frame(frame);
+		}
+		Poll::<()>::Pending
+	});
+	let timers = async {
+		let mut interval = crate::task::timer::interval(tcp::TICK);
+		loop {
+			interval.tick().await;
+			tcp::tick();
+		}
+	};
+	futures_util::future::join(frames, timers).await;
+}
+
+/// Test that MAC addresses print the usual way, and sending needs an
+/// interface
+#[test_case]
+fn test_mac_address() {
+	assert_eq!(format!("{}", MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56])), "52:54:00:12:34:56");
+	assert_eq!(format!("{}", MacAddress::BROADCAST), "ff:ff:ff:ff:ff:ff");
+	assert_eq!(transmit(usize::MAX, &[0; 60]), Err(TransmitError::NoInterface));
+}
+
+/// Test binding ports, and ephemeral ports being handed out in turn
+#[test_case]
+fn test_port_allocator() {
+	let mut ports = PortAllocator::new();
+	assert_eq!(ports.bind(1, 80), Ok(80));
+	assert_eq!(ports.bind(2, 80), Err(PortError::InUse));
+	assert_eq!(ports.bind(2, 0), Ok(*EPHEMERAL_PORTS.start()));
+	assert_eq!(ports.bind(3, 0), Ok(*EPHEMERAL_PORTS.start() + 1));
+	ports.release(80, 2);
+	assert_eq!(ports.get(80), Some(1));
+	ports.release(80, 1);
+	assert_eq!(ports.get(80), None);
+}
+
+/// Test parsing IP configurations and the networks they describe
+#[test_case]
+fn test_ip_config() {
+	let config = IpConfig::parse("10.0.2.15/24,10.0.2.2").unwrap();
+	assert_eq!(config, IpConfig::QEMU_USER);
+	assert_eq!(format!("{}", config), "10.0.2.15/24,10.0.2.2");
+	assert_eq!(config.netmask(), Ipv4Addr::new(255, 255, 255, 0));
+	assert_eq!(config.broadcast(), Some(Ipv4Addr::new(10, 0, 2, 255)));
+	assert!(config.contains(Ipv4Addr::new(10, 0, 2, 3)) && !config.contains(Ipv4Addr::new(10, 0, 3, 3)));
+	assert_eq!(IpConfig::parse("off"), Some(IpConfig::default()));
+	assert_eq!(IpConfig::parse("192.168.1.2/33"), None);
+	assert_eq!(IpConfig::parse("192.168.1.2/0").map(|config| config.netmask()), Some(Ipv4Addr::UNSPECIFIED));
+}
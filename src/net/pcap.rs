@@ -0,0 +1,266 @@
+use super::{arp, ethernet, ipv4};
+use crate::{cmdline, fs, time};
+use alloc::{format, string::String, string::ToString, vec::Vec};
+use core::net::Ipv4Addr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::ring::Ring;
+use crate::sync::Mutex;
+
+/// Bytes of each frame kept; the rest is counted but not copied
+pub const SNAPLEN: usize = 256;
+/// Frames the ring holds for readers that fall behind
+const RING_CAPACITY: usize = 128;
+
+/// Sizes of a pcap file's header and of each record's
+const FILE_HEADER_SIZE: usize = 24;
+const RECORD_HEADER_SIZE: usize = 16;
+/// Magic number, version, and link type (Ethernet) a pcap file starts with
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION: (u16, u16) = (2, 4);
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// TCP header flags, in the order `tcpdump` shows them
+const TCP_FLAGS: [(u8, char); 5] = [(0x02, 'S'), (0x01, 'F'), (0x08, 'P'), (0x04, 'R'), (0x10, '.')];
+
+/// A captured frame, cut to `SNAPLEN`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capture {
+	/// Wall-clock time in nanoseconds since the epoch
+	pub time_ns: i64,
+	pub interface: usize,
+	/// Sent by us, rather than received
+	pub outgoing: bool,
+	/// Length of the whole frame
+	pub length: usize,
+	/// Bytes of `data` copied
+	len: usize,
+	data: [u8; SNAPLEN],
+}
+
+impl Capture {
+	const EMPTY: Capture = Capture { time_ns: 0, interface: 0, outgoing: false, length: 0, len: 0, data: [0; SNAPLEN] };
+
+	/// The bytes of the frame kept
+	pub fn data(&self) -> &[u8] {
+		&self.data[..self.len]
+	}
+
+	/// The record a pcap file holds for this frame
+	fn record(&self) -> Vec<u8> {
+		let micros = self.time_ns.max(0) / 1000;
+		let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + self.len);
+		record.extend_from_slice(&((micros / 1_000_000) as u32).to_le_bytes());
+		record.extend_from_slice(&((micros % 1_000_000) as u32).to_le_bytes());
+		record.extend_from_slice(&(self.len as u32).to_le_bytes());
+		record.extend_from_slice(&(self.length as u32).to_le_bytes());
+		record.extend_from_slice(self.data());
+		record
+	}
+}
+
+/// The latest frames, numbered by a running sequence
+static RING: Mutex<Ring<Capture, RING_CAPACITY>> = Mutex::new(Ring::new(Capture::EMPTY));
+/// Whether frames are being copied; on unless booted with `pcap=off`
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Start or stop copying frames into the ring
+pub fn set_enabled(enabled: bool) {
+	ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+	ENABLED.load(Ordering::Relaxed)
+}
+
+/// Copy a frame passing through `interface` into the ring, dropping the
+/// oldest when it is full
+pub fn capture(interface: usize, outgoing: bool, frame: &[u8]) {
+	if !enabled() {
+		return;
+	}
+	let len = frame.len().min(SNAPLEN);
+	let mut capture = Capture { time_ns: time::now_ns(), interface, outgoing, length: frame.len(), len, ..Capture::EMPTY };
+	capture.data[..len].copy_from_slice(&frame[..len]);
+	RING.lock().push(capture);
+}
+
+/// Sequence number the next frame will get
+pub fn next_seq() -> u64 {
+	RING.lock().next_seq()
+}
+
+/// The oldest frame held numbered `seq` or later, with its number
+pub fn read_from(seq: u64) -> Option<(u64, Capture)> {
+	RING.lock().read_from(seq)
+}
+
+/// The header a pcap file starts with
+fn file_header() -> [u8; FILE_HEADER_SIZE] {
+	let mut header = [0; FILE_HEADER_SIZE];
+	header[0..4].copy_from_slice(&PCAP_MAGIC.to_le_bytes());
+	header[4..6].copy_from_slice(&PCAP_VERSION.0.to_le_bytes());
+	header[6..8].copy_from_slice(&PCAP_VERSION.1.to_le_bytes());
+	header[16..20].copy_from_slice(&(SNAPLEN as u32).to_le_bytes());
+	header[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+	header
+}
+
+/// Read `/dev/pcap`: a pcap file's header, then a whole record per frame
+/// from the oldest still held
+///
+/// `*position` is 0 before the header and one past the next frame's
+/// sequence number after. Returns 0 once the reader has every frame, or
+/// when `buffer` cannot hold the next piece whole.
+fn read(position: &mut usize, buffer: &mut [u8]) -> usize {
+	let mut copied = 0;
+	if *position == 0 {
+		if buffer.len() < FILE_HEADER_SIZE {
+			return 0;
+		}
+		buffer[..FILE_HEADER_SIZE].copy_from_slice(&file_header());
+		copied = FILE_HEADER_SIZE;
+		*position = 1;
+	}
+	while let Some((seq, capture)) = read_from(*position as u64 - 1) {
+		let record = capture.record();
+		if buffer.len() - copied < record.len() {
+			break;
+		}
+		buffer[copied..copied + record.len()].copy_from_slice(&record);
+		copied += record.len();
+		*position = seq as usize + 2;
+	}
+	copied
+}
+
+/// Create `/dev/pcap`, and stop capturing if booted with `pcap=off`
+pub fn init() {
+	if cmdline::param("pcap") == Some("off") {
+		set_enabled(false);
+	}
+	fs::with_filesystem(|fs| {
+		let _ = fs.create_directory("/dev".to_string());
+		let _ = fs.create_device("/dev/pcap".to_string(), read);
+	});
+}
+
+/// A one-line summary of a frame, as `tcpdump` prints it
+pub fn describe(frame: &[u8]) -> String {
+	let Some((header, payload)) = ethernet::Header::parse(frame) else {
+		return format!("truncated frame, length {}", frame.len());
+	};
+	match header.ethertype {
+		ethernet::ETHERTYPE_ARP => describe_arp(payload),
+		ethernet::ETHERTYPE_IPV4 => describe_ipv4(payload),
+		ethertype => format!("{} > {}, ethertype {:#06x}, length {}", header.source, header.destination, ethertype, frame.len()),
+	}
+}
+
+fn describe_arp(payload: &[u8]) -> String {
+	match arp::Packet::parse(payload) {
+		Some(packet) if packet.operation == 1 => format!("ARP, Request who-has {} tell {}", packet.target_ip, packet.sender_ip),
+		Some(packet) if packet.operation == 2 => format!("ARP, Reply {} is-at {}", packet.sender_ip, packet.sender_mac),
+		Some(packet) => format!("ARP, operation {}", packet.operation),
+		None => String::from("ARP, truncated"),
+	}
+}
+
+/// An IPv4 packet, read without checking its checksum or length, as the
+/// capture may have cut it short
+fn describe_ipv4(packet: &[u8]) -> String {
+	if packet.len() < ipv4::HEADER_SIZE {
+		return String::from("IP, truncated");
+	}
+	let ip = |at: usize| Ipv4Addr::new(packet[at], packet[at + 1], packet[at + 2], packet[at + 3]);
+	let (source, destination) = (ip(12), ip(16));
+	let header_length = usize::from(packet[0] & 0xf) * 4;
+	let total_length = usize::from(u16::from_be_bytes([packet[2], packet[3]]));
+	let payload = packet.get(header_length..).unwrap_or(&[]);
+	let payload_length = total_length.saturating_sub(header_length);
+	let word = |at: usize| payload.get(at..at + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+	let long = |at: usize| payload.get(at..at + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+	match packet[9] {
+		ipv4::PROTOCOL_ICMP => {
+			let kind = match payload.first() {
+				Some(0) => "echo reply",
+				Some(8) => "echo request",
+				Some(3) => "unreachable",
+				Some(11) => "time exceeded",
+				_ => "type unknown",
+			};
+			let (id, sequence) = (word(4).unwrap_or(0), word(6).unwrap_or(0));
+			format!("IP {} > {}: ICMP {}, id {}, seq {}, length {}", source, destination, kind, id, sequence, payload_length)
+		}
+		ipv4::PROTOCOL_UDP => {
+			let (Some(source_port), Some(destination_port)) = (word(0), word(2)) else {
+				return format!("IP {} > {}: UDP, truncated", source, destination);
+			};
+			let length = payload_length.saturating_sub(8);
+			format!("IP {}.{} > {}.{}: UDP, length {}", source, source_port, destination, destination_port, length)
+		}
+		ipv4::PROTOCOL_TCP => {
+			let (Some(source_port), Some(destination_port), Some(seq), Some(ack), Some(window)) = (word(0), word(2), long(4), long(8), word(14)) else {
+				return format!("IP {} > {}: TCP, truncated", source, destination);
+			};
+			let flags = payload[13];
+			let shown: String = TCP_FLAGS.iter().filter(|&&(bit, _)| flags & bit != 0).map(|&(_, name)| name).collect();
+			let length = payload_length.saturating_sub(usize::from(payload[12] >> 4) * 4);
+			let ack = if flags & 0x10 != 0 { format!(", ack {}", ack) } else { String::new() };
+			format!(
+				"IP {}.{} > {}.{}: Flags [{}], seq {}{}, win {}, length {}",
+				source,
+				source_port,
+				destination,
+				destination_port,
+				if shown.is_empty() { "none" } else { &shown },
+				seq,
+				ack,
+				window,
+				length
+			)
+		}
+		protocol => format!("IP {} > {}: protocol {}, length {}", source, destination, protocol, payload_length),
+	}
+}
+
+/// Test the ring, the pcap layout `/dev/pcap` reads, and decoding
+#[test_case]
+fn test_pcap() {
+	let start = next_seq();
+	let header = ethernet::Header { destination: super::MacAddress::BROADCAST, source: super::MacAddress([2; 6]), ethertype: ethernet::ETHERTYPE_ARP };
+	let request = arp::Packet {
+		operation: 1,
+		sender_mac: super::MacAddress([2; 6]),
+		sender_ip: Ipv4Addr::new(10, 0, 2, 15),
+		target_mac: super::MacAddress([0; 6]),
+		target_ip: Ipv4Addr::new(10, 0, 2, 2),
+	};
+	let frame = header.frame(&request.to_bytes());
+	assert_eq!(describe(&frame), "ARP, Request who-has 10.0.2.2 tell 10.0.2.15");
+	capture(7, true, &frame);
+	capture(7, false, &[0; SNAPLEN + 10]);
+	let (seq, captured) = read_from(start).unwrap();
+	assert_eq!((seq, captured.interface, captured.outgoing, captured.data().len()), (start, 7, true, frame.len()));
+	let (_, long) = read_from(start + 1).unwrap();
+	assert_eq!((long.length, long.data().len()), (SNAPLEN + 10, SNAPLEN));
+
+	// The file header, then each record whole
+	let mut position = 0;
+	let mut buffer = [0; FILE_HEADER_SIZE + RECORD_HEADER_SIZE + 60];
+	let copied = read(&mut position, &mut buffer[..FILE_HEADER_SIZE]);
+	assert_eq!((copied, &buffer[..4]), (FILE_HEADER_SIZE, &PCAP_MAGIC.to_le_bytes()[..]));
+	position = start as usize + 1;
+	assert_eq!(read(&mut position, &mut buffer), RECORD_HEADER_SIZE + frame.len());
+	assert_eq!(&buffer[RECORD_HEADER_SIZE..][..frame.len()], &frame[..]);
+
+	// A SYN from 10.0.2.15:1000 to 10.0.2.2:80
+	let mut segment = [0u8; 20];
+	segment[0..2].copy_from_slice(&1000u16.to_be_bytes());
+	segment[2..4].copy_from_slice(&80u16.to_be_bytes());
+	segment[4..8].copy_from_slice(&7u32.to_be_bytes());
+	(segment[12], segment[13]) = (5 << 4, 0x02);
+	segment[14..16].copy_from_slice(&512u16.to_be_bytes());
+	let ip = ipv4::Header { source: Ipv4Addr::new(10, 0, 2, 15), destination: Ipv4Addr::new(10, 0, 2, 2), protocol: ipv4::PROTOCOL_TCP, ttl: 64 };
+	let frame = ethernet::Header { ethertype: ethernet::ETHERTYPE_IPV4, ..header }.frame(&ip.packet(1, &segment));
+	assert_eq!(describe(&frame), "IP 10.0.2.15.1000 > 10.0.2.2.80: Flags [S], seq 7, win 512, length 0");
+}
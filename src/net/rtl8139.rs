@@ -0,0 +1,282 @@
+use super::{MacAddress, TransmitError, MAX_FRAME};
+use crate::pci::{self, Bar, DeviceMatch};
+use crate::{interrupts, memory};
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU16, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::VirtAddr;
+
+/// Device registers, as offsets into the I/O BAR
+const REG_IDR: u16 = 0x00;
+const REG_MAR: u16 = 0x08;
+const REG_TSD: u16 = 0x10;
+const REG_TSAD: u16 = 0x20;
+const REG_RBSTART: u16 = 0x30;
+const REG_CR: u16 = 0x37;
+const REG_CAPR: u16 = 0x38;
+const REG_IMR: u16 = 0x3c;
+const REG_ISR: u16 = 0x3e;
+const REG_TCR: u16 = 0x40;
+const REG_RCR: u16 = 0x44;
+const REG_MSR: u16 = 0x58;
+const REG_CONFIG1: u16 = 0x52;
+
+/// Command register bits: reset, enable receive and transmit, and the
+/// receive buffer is empty
+const CR_RST: u8 = 1 << 4;
+const CR_RE: u8 = 1 << 3;
+const CR_TE: u8 = 1 << 2;
+const CR_BUFE: u8 = 1 << 0;
+
+/// Interrupt bits: received, receive error, sent, send error, and the
+/// receive buffer overflowed
+const INT_ROK: u16 = 1 << 0;
+const INT_RER: u16 = 1 << 1;
+const INT_TOK: u16 = 1 << 2;
+const INT_TER: u16 = 1 << 3;
+const INT_RXOVW: u16 = 1 << 4;
+
+/// Receive configuration: accept frames to our address, multicast, and
+/// broadcast, and write frames past the ring's end rather than wrap them
+const RCR_APM: u32 = 1 << 1;
+const RCR_AM: u32 = 1 << 2;
+const RCR_AB: u32 = 1 << 3;
+const RCR_WRAP: u32 = 1 << 7;
+/// Transmit configuration: the largest DMA burst, 2048 bytes
+const TCR_MXDMA_2048: u32 = 0b111 << 8;
+
+/// Transmit status bits: the host owns the slot again, and it was sent
+const TSD_OWN: u32 = 1 << 13;
+/// Media status bit set while the link is down
+const MSR_LINKB: u8 = 1 << 2;
+
+/// The receive ring, with the slack needed after its end for a frame
+/// written past it, and the 16 bytes the device also wants
+const RX_RING: usize = 8192;
+const RX_ALLOCATION: usize = RX_RING + 16 + MAX_FRAME + 4;
+/// Each frame in the ring starts with a status and a length
+const RX_HEADER: usize = 4;
+/// The CRC at the end of every frame in the ring
+const CRC_SIZE: usize = 4;
+/// The device reads the ring pointer this far behind where reading is
+const CAPR_OFFSET: u16 = 16;
+
+/// Transmit slots and the size of each one's buffer
+const TX_SLOTS: usize = 4;
+const TX_BUFFER: usize = 2048;
+/// Shortest frame; the device does not pad shorter ones itself
+const MIN_FRAME: usize = 60;
+
+/// Reads of a register waiting for a bit before giving up on it
+const POLL_TRIES: usize = 10_000;
+
+/// Devices the interrupt handler checks, since several can share a line
+const MAX_DEVICES: usize = 4;
+
+/// The RTL8139 and the clones that keep its IDs
+pub static DRIVER: pci::Driver = pci::Driver {
+	name: "8139too",
+	matches: &[DeviceMatch::Id(0x10ec, 0x8139)],
+	probe,
+};
+
+/// I/O ports of each device bound, for the interrupt handler; 0 for none
+static PORTS: [AtomicU16; MAX_DEVICES] = [const { AtomicU16::new(0) }; MAX_DEVICES];
+
+fn read_u8(port: u16, offset: u16) -> u8 {
+	unsafe { Port::new(port + offset).read() }
+}
+
+fn write_u8(port: u16, offset: u16, value: u8) {
+	unsafe { Port::new(port + offset).write(value) }
+}
+
+fn read_u16(port: u16, offset: u16) -> u16 {
+	unsafe { Port::new(port + offset).read() }
+}
+
+fn write_u16(port: u16, offset: u16, value: u16) {
+	unsafe { Port::new(port + offset).write(value) }
+}
+
+fn read_u32(port: u16, offset: u16) -> u32 {
+	unsafe { Port::new(port + offset).read() }
+}
+
+fn write_u32(port: u16, offset: u16, value: u32) {
+	unsafe { Port::new(port + offset).write(value) }
+}
+
+/// An RTL8139 with its receive ring and transmit buffers
+struct Rtl8139 {
+	port: u16,
+	mac: MacAddress,
+	rx_ring: VirtAddr,
+	/// Where the next frame starts in the ring
+	rx_offset: usize,
+	/// Physical and virtual address of each slot's buffer
+	tx_buffers: [(u32, VirtAddr); TX_SLOTS],
+	/// Slots handed to the device, which it may still be sending from
+	tx_sent: [bool; TX_SLOTS],
+	/// The slot to send from next; the device goes through them in turn
+	tx_next: usize,
+}
+
+impl Rtl8139 {
+	/// Power on and reset the device, then start receiving and sending;
+	/// interrupts stay masked
+	fn new(port: u16) -> Option<Rtl8139> {
+		write_u8(port, REG_CONFIG1, 0);
+		write_u8(port, REG_CR, CR_RST);
+		for _ in 0..POLL_TRIES {
+			if read_u8(port, REG_CR) & CR_RST == 0 {
+				break;
+			}
+			core::hint::spin_loop();
+		}
+		write_u16(port, REG_IMR, 0);
+
+		// The device takes 32-bit addresses only
+		let (rx_physical, rx_ring) = memory::allocate_dma(RX_ALLOCATION as u64)?;
+		let (tx_physical, tx_memory) = memory::allocate_dma((TX_SLOTS * TX_BUFFER) as u64)?;
+		let rx_physical = u32::try_from(rx_physical.as_u64()).ok()?;
+		let tx_physical = u32::try_from(tx_physical.as_u64()).ok()?;
+		let tx_buffers = core::array::from_fn(|slot| {
+			let offset = slot * TX_BUFFER;
+			(tx_physical + offset as u32, tx_memory + offset as u64)
+		});
+
+		let mac = MacAddress(core::array::from_fn(|byte| read_u8(port, REG_IDR + byte as u16)));
+		write_u32(port, REG_RBSTART, rx_physical);
+		write_u32(port, REG_MAR, u32::MAX);
+		write_u32(port, REG_MAR + 4, u32::MAX);
+		for (slot, &(physical, _)) in tx_buffers.iter().enumerate() {
+			write_u32(port, REG_TSAD + 4 * slot as u16, physical);
+		}
+		write_u32(port, REG_TCR, TCR_MXDMA_2048);
+		let mut device = Rtl8139 { port, mac, rx_ring, rx_offset: 0, tx_buffers, tx_sent: [false; TX_SLOTS], tx_next: 0 };
+		device.start_receive();
+		Some(device)
+	}
+
+	/// Start receiving at the ring's beginning; turning receive off and on
+	/// again also gets it back in step after a bad frame header
+	fn start_receive(&mut self) {
+		write_u8(self.port, REG_CR, CR_TE);
+		write_u8(self.port, REG_CR, CR_RE | CR_TE);
+		write_u32(self.port, REG_RCR, RCR_APM | RCR_AM | RCR_AB | RCR_WRAP);
+		self.rx_offset = 0;
+		write_u16(self.port, REG_CAPR, 0u16.wrapping_sub(CAPR_OFFSET));
+	}
+}
+
+/// Where the frame after one of `length` bytes at `offset` starts in the
+/// ring: past its header, 4-byte aligned, wrapped to the ring's size
+fn next_rx_offset(offset: usize, length: usize) -> usize {
+	(offset + RX_HEADER + length).next_multiple_of(4) % RX_RING
+}
+
+impl super::Driver for Rtl8139 {
+	fn mac(&self) -> MacAddress {
+		self.mac
+	}
+
+	fn link_up(&self) -> bool {
+		read_u8(self.port, REG_MSR) & MSR_LINKB == 0
+	}
+
+	fn transmit(&mut self, frame: &[u8]) -> Result<(), TransmitError> {
+		if frame.len() > MAX_FRAME {
+			return Err(TransmitError::TooLong);
+		}
+		let slot = self.tx_next;
+		let status = REG_TSD + 4 * slot as u16;
+		if self.tx_sent[slot] && read_u32(self.port, status) & TSD_OWN == 0 {
+			return Err(TransmitError::Busy);
+		}
+		let length = frame.len().max(MIN_FRAME);
+		let buffer = self.tx_buffers[slot].1.as_mut_ptr::<u8>();
+		unsafe {
+			core::ptr::copy_nonoverlapping(frame.as_ptr(), buffer, frame.len());
+			core::ptr::write_bytes(buffer.add(frame.len()), 0, length - frame.len());
+		}
+		// Writing the length hands the slot to the device, clearing OWN
+		write_u32(self.port, status, length as u32);
+		self.tx_sent[slot] = true;
+		self.tx_next = (slot + 1) % TX_SLOTS;
+		Ok(())
+	}
+
+	fn receive(&mut self, deliver: &mut dyn FnMut(&[u8])) {
+		while read_u8(self.port, REG_CR) & CR_BUFE == 0 {
+			let header = unsafe { self.rx_ring.as_ptr::<u8>().add(self.rx_offset) };
+			let (status, length) = unsafe {
+				let header = core::slice::from_raw_parts(header, RX_HEADER);
+				(u16::from_le_bytes([header[0], header[1]]), usize::from(u16::from_le_bytes([header[2], header[3]])))
+			};
+			// A length no frame has means the ring is out of step
+			if !(CRC_SIZE..=MAX_FRAME + CRC_SIZE).contains(&length) {
+				self.start_receive();
+				break;
+			}
+			// Bit 0 of the status marks a good frame
+			if status & 1 != 0 {
+				let frame = unsafe { core::slice::from_raw_parts(header.add(RX_HEADER), length - CRC_SIZE) };
+				deliver(frame);
+			}
+			self.rx_offset = next_rx_offset(self.rx_offset, length);
+			write_u16(self.port, REG_CAPR, (self.rx_offset as u16).wrapping_sub(CAPR_OFFSET));
+		}
+	}
+}
+
+/// Acknowledge the interrupts of every RTL8139 that raised one, and
+/// collect received frames afterwards if any were for receiving
+fn handle_interrupt() {
+	let mut received = false;
+	for port in &PORTS {
+		let port = port.load(Ordering::Acquire);
+		if port == 0 {
+			continue;
+		}
+		// Causes are cleared by writing them back
+		let status = read_u16(port, REG_ISR);
+		if status != 0 {
+			write_u16(port, REG_ISR, status);
+		}
+		received |= status & (INT_ROK | INT_RER | INT_RXOVW) != 0;
+	}
+	if received {
+		super::receive_interrupt();
+	}
+}
+
+/// Bind an RTL8139: reset it, set up its buffers, and take its interrupt
+/// line, as it has no message interrupts
+fn probe(device: &pci::Device) -> Result<(), &'static str> {
+	let Some(port) = device.bars.iter().find_map(|bar| match *bar {
+		Bar::Io { port, .. } => Some(port as u16),
+		Bar::Memory { .. } => None,
+	}) else {
+		return Err("no I/O BAR");
+	};
+	let slot = PORTS.iter().position(|port| port.load(Ordering::Relaxed) == 0).ok_or("too many devices")?;
+	let line = device.interrupt_line.ok_or("no interrupt")?;
+	device.enable_bus_master();
+	let rtl8139 = Rtl8139::new(port).ok_or("no DMA memory below 4 GiB")?;
+	interrupts::register_irq(line, handle_interrupt).map_err(|_| "interrupt line full")?;
+	PORTS[slot].store(port, Ordering::Release);
+	super::register(Box::new(rtl8139));
+	write_u16(port, REG_IMR, INT_ROK | INT_RER | INT_TOK | INT_TER | INT_RXOVW);
+	interrupts::enable_irq(line);
+	Ok(())
+}
+
+/// Test stepping through the receive ring, including past its end
+#[test_case]
+fn test_rx_offset() {
+	assert_eq!(next_rx_offset(0, 64), 68);
+	assert_eq!(next_rx_offset(0, 61), 68);
+	assert_eq!(next_rx_offset(RX_RING - 4, 64), 64);
+	assert!(RX_ALLOCATION >= RX_RING + 16 + RX_HEADER + MAX_FRAME + CRC_SIZE - 4);
+}
@@ -0,0 +1,199 @@
+use super::{dns, udp};
+use crate::task::timer;
+use crate::time::{self, Instant};
+use crate::{cmdline, klog};
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::time::Duration;
+
+/// Port NTP servers answer on
+pub const PORT: u16 = 123;
+/// Server asked unless `ntp=` names another, or `off`
+const DEFAULT_SERVER: &str = "pool.ntp.org";
+
+/// Size of a packet without extensions
+const PACKET_SIZE: usize = 48;
+/// First byte of a request: no leap warning, version 4, client mode
+const CLIENT_REQUEST: u8 = 0x23;
+/// Modes in the low bits of the first byte
+const MODE_MASK: u8 = 0x07;
+const MODE_SERVER: u8 = 4;
+/// Offsets of the originate, receive, and transmit timestamps
+const ORIGINATE: usize = 24;
+const RECEIVE: usize = 32;
+const TRANSMIT: usize = 40;
+/// Seconds from the NTP epoch, 1900, to the Unix one
+const UNIX_EPOCH: i64 = 2_208_988_800;
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+/// How long an answer is waited for, and how often the socket is checked
+const TIMEOUT: Duration = Duration::from_secs(2);
+const CHECK_EVERY: Duration = Duration::from_millis(50);
+/// Time between syncs, and between tries after one fails
+const SYNC_INTERVAL: Duration = Duration::from_secs(1024);
+const RETRY_INTERVAL: Duration = Duration::from_secs(64);
+/// Offsets below this are slewed in; larger ones step the clock
+const STEP_THRESHOLD_NS: i64 = 128_000_000;
+
+/// Why the server's time could not be had
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SntpError {
+	/// The server's name could not be resolved
+	Resolve(dns::DnsError),
+	/// The network has no route to the server
+	NoRoute,
+	/// No answer came
+	TimedOut,
+	/// The server answered with a kiss-o'-death or an unsynchronized clock
+	Unsynchronized,
+}
+
+/// What one exchange with a server found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+	/// How far the server's clock is ahead of ours
+	pub offset_ns: i64,
+	/// The round trip, less the time the server held the request
+	pub delay_ns: i64,
+}
+
+/// The server `ntp=` names, or the default one; `None` for `ntp=off`
+pub fn server() -> Option<&'static str> {
+	match cmdline::param("ntp") {
+		Some("off") => None,
+		Some(server) => Some(server),
+		None => Some(DEFAULT_SERVER),
+	}
+}
+
+/// A Unix time in nanoseconds as an NTP timestamp
+fn to_ntp(unix_ns: i64) -> u64 {
+	let ns = unix_ns + UNIX_EPOCH * NANOS_PER_SECOND;
+	let (seconds, fraction) = (ns.div_euclid(NANOS_PER_SECOND), ns.rem_euclid(NANOS_PER_SECOND));
+	((seconds as u64) << 32) | (((fraction as u64) << 32) / NANOS_PER_SECOND as u64)
+}
+
+/// An NTP timestamp as a Unix time in nanoseconds
+fn from_ntp(timestamp: u64) -> i64 {
+	let seconds = (timestamp >> 32) as i64 - UNIX_EPOCH;
+	let fraction = ((timestamp & 0xffff_ffff) * NANOS_PER_SECOND as u64) >> 32;
+	seconds * NANOS_PER_SECOND + fraction as i64
+}
+
+/// A request sent at `sent_ns`, which the answer carries back
+fn request(sent_ns: i64) -> [u8; PACKET_SIZE] {
+	let mut packet = [0; PACKET_SIZE];
+	packet[0] = CLIENT_REQUEST;
+	packet[TRANSMIT..TRANSMIT + 8].copy_from_slice(&to_ntp(sent_ns).to_be_bytes());
+	packet
+}
+
+/// The sample an answer to the request sent at `sent_ns` gives, received
+/// at `received_ns`; `None` if it answers some other request
+fn parse_answer(packet: &[u8], sent_ns: i64, received_ns: i64) -> Option<Result<Sample, SntpError>> {
+	if packet.len() < PACKET_SIZE || packet[0] & MODE_MASK != MODE_SERVER {
+		return None;
+	}
+	let timestamp = |at: usize| u64::from_be_bytes(packet[at..at + 8].try_into().unwrap());
+	if timestamp(ORIGINATE) != to_ntp(sent_ns) {
+		return None;
+	}
+	// Stratum 0 is a kiss-o'-death, and leap bits of 3 an unset clock
+	if packet[1] == 0 || packet[0] >> 6 == 3 {
+		return Some(Err(SntpError::Unsynchronized));
+	}
+	let (server_received, server_sent) = (from_ntp(timestamp(RECEIVE)), from_ntp(timestamp(TRANSMIT)));
+	let offset_ns = ((server_received - sent_ns) + (server_sent - received_ns)) / 2;
+	let delay_ns = (received_ns - sent_ns) - (server_sent - server_received);
+	Some(Ok(Sample { offset_ns, delay_ns }))
+}
+
+/// Ask `server` for the time, waiting without holding up other tasks
+pub async fn query(server: Ipv4Addr) -> Result<Sample, SntpError> {
+	let socket = udp::create();
+	let result = exchange(socket, SocketAddrV4::new(server, PORT)).await;
+	udp::close(socket);
+	result
+}
+
+/// Send one request from `socket` to `server` and wait for its answer
+async fn exchange(socket: udp::SocketId, server: SocketAddrV4) -> Result<Sample, SntpError> {
+	let sent_ns = time::now_ns();
+	udp::send_to(socket, server, &request(sent_ns)).map_err(|_| SntpError::NoRoute)?;
+	let sent = Instant::now();
+	while sent.elapsed() < TIMEOUT {
+		while let Ok(Some(datagram)) = udp::recv_from(socket) {
+			if datagram.source != server {
+				continue;
+			}
+			if let Some(result) = parse_answer(&datagram.data, sent_ns, time::now_ns()) {
+				return result;
+			}
+		}
+		timer::sleep(CHECK_EVERY).await;
+	}
+	Err(SntpError::TimedOut)
+}
+
+/// Bring the wall clock to the server's time: slewing small offsets in,
+/// stepping past large ones
+fn apply(sample: Sample) {
+	if sample.offset_ns.abs() < STEP_THRESHOLD_NS {
+		time::slew_time(sample.offset_ns);
+	} else {
+		time::set_time_ns(time::now_ns() + sample.offset_ns);
+	}
+}
+
+/// Resolve the server and sync the clock to it once
+async fn sync(server: &str) -> Result<Sample, SntpError> {
+	let address = dns::resolve(server).map_err(SntpError::Resolve)?;
+	let sample = query(address).await?;
+	apply(sample);
+	Ok(sample)
+}
+
+/// The task that keeps the wall clock set from `server()`, at boot and
+/// every `SYNC_INTERVAL` after
+pub async fn run_sntp() {
+	let Some(server) = server() else {
+		return;
+	};
+	loop {
+		let wait = match sync(server).await {
+			Ok(sample) => {
+				let step = if sample.offset_ns.abs() < STEP_THRESHOLD_NS { "slewing" } else { "stepped" };
+				klog!(Info, "{}: offset {} us, delay {} us, {}", server, sample.offset_ns / 1000, sample.delay_ns / 1000, step);
+				SYNC_INTERVAL
+			}
+			Err(err) => {
+				klog!(Debug, "{}: no time: {:?}", server, err);
+				RETRY_INTERVAL
+			}
+		};
+		timer::sleep(wait).await;
+	}
+}
+
+/// Test timestamp conversion and reading an answer
+#[test_case]
+fn test_sntp() {
+	let unix_ns = 1_700_000_000 * NANOS_PER_SECOND + 250_000_000;
+	assert_eq!(to_ntp(unix_ns), ((1_700_000_000 + UNIX_EPOCH as u64) << 32) | 0x4000_0000);
+	assert_eq!(from_ntp(to_ntp(unix_ns)), unix_ns);
+
+	// The server is 1 s ahead, and each way takes 10 ms
+	let (sent, received) = (unix_ns, unix_ns + 30_000_000);
+	let mut answer = request(sent);
+	answer[0] = 0x24;
+	answer[1] = 2;
+	let (server_received, server_sent) = (sent + NANOS_PER_SECOND + 10_000_000, sent + NANOS_PER_SECOND + 20_000_000);
+	answer[ORIGINATE..ORIGINATE + 8].copy_from_slice(&to_ntp(sent).to_be_bytes());
+	answer[RECEIVE..RECEIVE + 8].copy_from_slice(&to_ntp(server_received).to_be_bytes());
+	answer[TRANSMIT..TRANSMIT + 8].copy_from_slice(&to_ntp(server_sent).to_be_bytes());
+	let sample = parse_answer(&answer, sent, received).unwrap().unwrap();
+	assert!((sample.offset_ns - NANOS_PER_SECOND).abs() < 2 && (sample.delay_ns - 20_000_000).abs() < 2);
+
+	assert_eq!(parse_answer(&answer, sent + 1_000_000, received), None);
+	answer[1] = 0;
+	assert_eq!(parse_answer(&answer, sent, received), Some(Err(SntpError::Unsynchronized)));
+}
@@ -0,0 +1,1084 @@
+use super::ipv4;
+use super::{PortAllocator, PortError};
+use crate::time::Instant;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+use crate::sync::Mutex;
+
+/// Size of a header without options
+const HEADER_SIZE: usize = 20;
+/// Header flags
+const FIN: u8 = 1 << 0;
+const SYN: u8 = 1 << 1;
+const RST: u8 = 1 << 2;
+const PSH: u8 = 1 << 3;
+const ACK: u8 = 1 << 4;
+/// The maximum segment size option, sent with SYN
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+/// The most data in a segment we send, so it fits one packet, and what a
+/// peer that names no size gets
+const MAX_SEGMENT: usize = ipv4::MAX_PAYLOAD - HEADER_SIZE;
+const DEFAULT_PEER_SEGMENT: usize = 536;
+
+/// Bytes a socket buffers each way; the receive side is the window offered
+const SEND_CAPACITY: usize = 16 * 1024;
+const RECEIVE_CAPACITY: usize = 16 * 1024;
+
+/// Retransmission timeout at first and at most, and the retransmissions
+/// before the connection is given up on
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MAX_RTO: Duration = Duration::from_secs(32);
+const MAX_RETRIES: u32 = 8;
+/// How long a closed connection lingers so stray segments find it; a
+/// short stand-in for twice the maximum segment lifetime
+const TIME_WAIT: Duration = Duration::from_secs(10);
+/// How long a socket whose owner has gone waits for the peer to close
+const FIN_WAIT_2_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often timers are checked
+pub const TICK: Duration = Duration::from_millis(100);
+
+/// Connections a listener holds before accepting them, at most
+const MAX_BACKLOG: usize = 128;
+
+/// Identifier of a TCP socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SocketId(pub usize);
+
+/// Where a connection is in its life, as RFC 793 names it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+	Closed,
+	Listen,
+	SynSent,
+	SynReceived,
+	Established,
+	FinWait1,
+	FinWait2,
+	CloseWait,
+	Closing,
+	LastAck,
+	TimeWait,
+}
+
+impl State {
+	pub fn name(self) -> &'static str {
+		match self {
+			State::Closed => "CLOSED",
+			State::Listen => "LISTEN",
+			State::SynSent => "SYN_SENT",
+			State::SynReceived => "SYN_RECV",
+			State::Established => "ESTABLISHED",
+			State::FinWait1 => "FIN_WAIT1",
+			State::FinWait2 => "FIN_WAIT2",
+			State::CloseWait => "CLOSE_WAIT",
+			State::Closing => "CLOSING",
+			State::LastAck => "LAST_ACK",
+			State::TimeWait => "TIME_WAIT",
+		}
+	}
+
+	/// Whether both ends have exchanged SYNs
+	fn synchronized(self) -> bool {
+		!matches!(self, State::Closed | State::Listen | State::SynSent)
+	}
+
+	/// Whether the peer may still send data
+	fn receiving(self) -> bool {
+		matches!(self, State::Established | State::FinWait1 | State::FinWait2)
+	}
+}
+
+/// Why a socket operation failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpError {
+	/// No socket has that ID
+	NoSocket,
+	/// Another socket is bound to the port, or this one is bound already
+	AddressInUse,
+	/// No port is free to bind to
+	NoPortsFree,
+	/// The socket is in the wrong state for that, such as accepting on one
+	/// that is not listening
+	InvalidState,
+	/// Connecting a socket already connected or listening
+	AlreadyConnected,
+	/// Connecting a socket whose handshake is still going on
+	Connecting,
+	/// Sending or receiving on a socket never connected
+	NotConnected,
+	/// Sending after writing was shut down
+	Shutdown,
+	/// The peer answered the SYN with a reset
+	ConnectionRefused,
+	/// The peer reset the connection
+	ConnectionReset,
+	/// The peer stopped acknowledging what we sent
+	TimedOut,
+	/// The network layer has no route to the peer
+	NoRoute,
+}
+
+impl From<PortError> for TcpError {
+	fn from(error: PortError) -> Self {
+		match error {
+			PortError::InUse => TcpError::AddressInUse,
+			PortError::NoneFree => TcpError::NoPortsFree,
+		}
+	}
+}
+
+/// A segment on its way in or out
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+	source: SocketAddrV4,
+	destination: SocketAddrV4,
+	seq: u32,
+	ack: u32,
+	flags: u8,
+	window: u16,
+	/// The maximum segment size option, on SYNs
+	mss: Option<u16>,
+	data: Vec<u8>,
+}
+
+impl Segment {
+	/// Parse a segment, checking its checksum
+	fn parse(header: &ipv4::Header, bytes: &[u8]) -> Option<Segment> {
+		if bytes.len() < HEADER_SIZE || ipv4::pseudo_header_checksum(header.source, header.destination, ipv4::PROTOCOL_TCP, bytes) != 0 {
+			return None;
+		}
+		let word = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+		let long = |at: usize| u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+		let data_offset = usize::from(bytes[12] >> 4) * 4;
+		if data_offset < HEADER_SIZE || data_offset > bytes.len() {
+			return None;
+		}
+		Some(Segment {
+			source: SocketAddrV4::new(header.source, word(0)),
+			destination: SocketAddrV4::new(header.destination, word(2)),
+			seq: long(4),
+			ack: long(8),
+			flags: bytes[13],
+			window: word(14),
+			mss: parse_mss(&bytes[HEADER_SIZE..data_offset]),
+			data: Vec::from(&bytes[data_offset..]),
+		})
+	}
+
+	fn to_bytes(&self) -> Vec<u8> {
+		let options = if self.mss.is_some() { 4 } else { 0 };
+		let mut bytes = Vec::with_capacity(HEADER_SIZE + options + self.data.len());
+		bytes.extend_from_slice(&self.source.port().to_be_bytes());
+		bytes.extend_from_slice(&self.destination.port().to_be_bytes());
+		bytes.extend_from_slice(&self.seq.to_be_bytes());
+		bytes.extend_from_slice(&self.ack.to_be_bytes());
+		bytes.extend_from_slice(&[(((HEADER_SIZE + options) / 4) as u8) << 4, self.flags]);
+		bytes.extend_from_slice(&self.window.to_be_bytes());
+		bytes.extend_from_slice(&[0, 0, 0, 0]);
+		if let Some(mss) = self.mss {
+			bytes.extend_from_slice(&[OPTION_MSS, 4]);
+			bytes.extend_from_slice(&mss.to_be_bytes());
+		}
+		bytes.extend_from_slice(&self.data);
+		let sum = ipv4::pseudo_header_checksum(*self.source.ip(), *self.destination.ip(), ipv4::PROTOCOL_TCP, &bytes);
+		bytes[16..18].copy_from_slice(&sum.to_be_bytes());
+		bytes
+	}
+
+	/// Sequence numbers the segment takes up: its data, and SYN and FIN
+	fn length(&self) -> u32 {
+		self.data.len() as u32 + u32::from(self.flags & SYN != 0) + u32::from(self.flags & FIN != 0)
+	}
+
+	/// The reset answering this segment, for which no connection exists
+	fn reset_reply(&self) -> Segment {
+		let (seq, ack, flags) = if self.flags & ACK != 0 {
+			(self.ack, 0, RST)
+		} else {
+			(0, self.seq.wrapping_add(self.length()), RST | ACK)
+		};
+		Segment { source: self.destination, destination: self.source, seq, ack, flags, window: 0, mss: None, data: Vec::new() }
+	}
+}
+
+/// The maximum segment size in a header's options, if there is one
+fn parse_mss(mut options: &[u8]) -> Option<u16> {
+	while let [kind, rest @ ..] = options {
+		match *kind {
+			OPTION_END => return None,
+			OPTION_NOP => options = rest,
+			_ => {
+				let length = usize::from(*rest.first()?);
+				if length < 2 || length > options.len() {
+					return None;
+				}
+				if *kind == OPTION_MSS && length == 4 {
+					return Some(u16::from_be_bytes([options[2], options[3]]));
+				}
+				options = &options[length..];
+			}
+		}
+	}
+	None
+}
+
+/// Whether sequence number `a` comes before `b`, allowing for wrap-around
+fn seq_lt(a: u32, b: u32) -> bool {
+	(a.wrapping_sub(b) as i32) < 0
+}
+
+/// A sequence number to start a connection at, different each time
+fn initial_sequence() -> u32 {
+	static COUNTER: AtomicU32 = AtomicU32::new(0);
+	let micros = Instant::now().since_boot().as_micros() as u32;
+	micros.wrapping_mul(4) ^ COUNTER.fetch_add(1, Ordering::Relaxed).wrapping_mul(0x9e37_79b9)
+}
+
+/// A socket: listening, or one end of a connection
+struct Socket {
+	state: State,
+	/// Our address; its IP stays unspecified until there is a connection
+	local: SocketAddrV4,
+	remote: Option<SocketAddrV4>,
+	nonblocking: bool,
+	/// Our first sequence number, the oldest one not acknowledged, and the
+	/// next one to send
+	iss: u32,
+	snd_una: u32,
+	snd_nxt: u32,
+	/// The peer's window and largest segment
+	snd_wnd: u32,
+	mss: usize,
+	/// Data from `snd_una` on: sent and waiting to be acknowledged, then
+	/// not yet sent
+	send_buffer: VecDeque<u8>,
+	/// Writing is shut down, so a FIN follows the data; and it has been sent
+	fin_queued: bool,
+	fin_sent: bool,
+	/// The next sequence number expected from the peer
+	rcv_nxt: u32,
+	receive_buffer: VecDeque<u8>,
+	/// The peer sent FIN, or we stopped reading
+	fin_received: bool,
+	read_shutdown: bool,
+	/// When to retransmit, the timeout after that, and how many times
+	/// the oldest segment has been sent again
+	retransmit_at: Option<Instant>,
+	rto: Duration,
+	retries: u32,
+	/// When TIME_WAIT, or an orphan's FIN_WAIT2, ends
+	linger_until: Option<Instant>,
+	/// Why the connection ended, until the owner is told
+	error: Option<TcpError>,
+	/// A listener's established connections not yet accepted
+	backlog: VecDeque<SocketId>,
+	backlog_limit: usize,
+	/// The listener a connection came in on, until it is accepted
+	parent: Option<SocketId>,
+	/// The owner closed it; it goes once the connection is over
+	orphaned: bool,
+}
+
+impl Socket {
+	fn new() -> Socket {
+		Socket {
+			state: State::Closed,
+			local: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0),
+			remote: None,
+			nonblocking: false,
+			iss: 0,
+			snd_una: 0,
+			snd_nxt: 0,
+			snd_wnd: 0,
+			mss: DEFAULT_PEER_SEGMENT,
+			send_buffer: VecDeque::new(),
+			fin_queued: false,
+			fin_sent: false,
+			rcv_nxt: 0,
+			receive_buffer: VecDeque::new(),
+			fin_received: false,
+			read_shutdown: false,
+			retransmit_at: None,
+			rto: INITIAL_RTO,
+			retries: 0,
+			linger_until: None,
+			error: None,
+			backlog: VecDeque::new(),
+			backlog_limit: 0,
+			parent: None,
+			orphaned: false,
+		}
+	}
+
+	/// The window we offer: what the receive buffer has room for
+	fn window(&self) -> u16 {
+		(RECEIVE_CAPACITY - self.receive_buffer.len()).min(usize::from(u16::MAX)) as u16
+	}
+
+	/// A segment to the peer with our current acknowledgement and window
+	fn segment(&self, seq: u32, flags: u8, data: Vec<u8>) -> Segment {
+		let ack = if flags & ACK != 0 { self.rcv_nxt } else { 0 };
+		let remote = self.remote.unwrap_or(self.local);
+		Segment { source: self.local, destination: remote, seq, ack, flags, window: self.window(), mss: None, data }
+	}
+
+	/// Our SYN, or SYN-ACK once the peer's SYN is in
+	fn syn(&self) -> Segment {
+		let flags = if self.state == State::SynReceived { SYN | ACK } else { SYN };
+		let mut segment = self.segment(self.iss, flags, Vec::new());
+		segment.mss = Some(MAX_SEGMENT as u16);
+		segment
+	}
+
+	fn start_timer(&mut self) {
+		if self.retransmit_at.is_none() {
+			self.retransmit_at = Some(Instant::now() + self.rto);
+		}
+	}
+
+	/// Send what the window allows of the data not yet sent, then the FIN
+	/// if writing is shut down; `probe` sends a byte into a closed window
+	fn flush(&mut self, probe: bool, out: &mut Vec<Segment>) {
+		let sending = matches!(self.state, State::Established | State::CloseWait | State::FinWait1 | State::Closing | State::LastAck);
+		if !sending {
+			return;
+		}
+		let window = self.snd_wnd.max(u32::from(probe)) as usize;
+		loop {
+			let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+			if in_flight >= self.send_buffer.len() || in_flight >= window {
+				break;
+			}
+			let length = (self.send_buffer.len() - in_flight).min(window - in_flight).min(self.mss);
+			let data: Vec<u8> = self.send_buffer.range(in_flight..in_flight + length).copied().collect();
+			out.push(self.segment(self.snd_nxt, ACK | PSH, data));
+			self.snd_nxt = self.snd_nxt.wrapping_add(length as u32);
+			self.start_timer();
+		}
+		let all_sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize == self.send_buffer.len();
+		if self.fin_queued && !self.fin_sent && all_sent {
+			out.push(self.segment(self.snd_nxt, FIN | ACK, Vec::new()));
+			self.snd_nxt = self.snd_nxt.wrapping_add(1);
+			self.fin_sent = true;
+			self.state = match self.state {
+				State::Established => State::FinWait1,
+				State::CloseWait => State::LastAck,
+				state => state,
+			};
+			self.start_timer();
+		}
+		// A closed window is probed once the timer runs out
+		if !all_sent && self.snd_wnd == 0 {
+			self.start_timer();
+		}
+	}
+
+	/// End the connection with `error`, if any, for the owner to see
+	fn abort(&mut self, error: Option<TcpError>) {
+		self.state = State::Closed;
+		self.error = error;
+		self.retransmit_at = None;
+		self.linger_until = None;
+		self.send_buffer.clear();
+	}
+
+	/// Take in an acknowledgement; false if it is for data never sent
+	fn acknowledge(&mut self, ack: u32, window: u16) -> bool {
+		if seq_lt(self.snd_nxt, ack) {
+			return false;
+		}
+		if seq_lt(self.snd_una, ack) {
+			let acked = ack.wrapping_sub(self.snd_una) as usize;
+			let data = acked.min(self.send_buffer.len());
+			self.send_buffer.drain(..data);
+			self.snd_una = ack;
+			self.rto = INITIAL_RTO;
+			self.retries = 0;
+			self.retransmit_at = None;
+			if self.snd_una != self.snd_nxt {
+				self.start_timer();
+			}
+		}
+		self.snd_wnd = u32::from(window);
+		true
+	}
+
+	/// Whether our FIN has been sent and acknowledged
+	fn fin_acked(&self) -> bool {
+		self.fin_sent && self.snd_una == self.snd_nxt
+	}
+}
+
+/// Live sockets and the ports bound to them
+struct SocketTable {
+	sockets: BTreeMap<SocketId, Socket>,
+	ports: PortAllocator<SocketId>,
+	next_id: usize,
+}
+
+static SOCKETS: Mutex<SocketTable> = Mutex::new(SocketTable::new());
+
+impl SocketTable {
+	const fn new() -> Self {
+		SocketTable { sockets: BTreeMap::new(), ports: PortAllocator::new(), next_id: 0 }
+	}
+
+	fn socket(&mut self, id: SocketId) -> Result<&mut Socket, TcpError> {
+		self.sockets.get_mut(&id).ok_or(TcpError::NoSocket)
+	}
+
+	fn insert(&mut self, socket: Socket) -> SocketId {
+		let id = SocketId(self.next_id);
+		self.next_id += 1;
+		self.sockets.insert(id, socket);
+		id
+	}
+
+	/// Bind a socket to `port`, or to a free ephemeral port for 0
+	fn bind(&mut self, id: SocketId, port: u16) -> Result<u16, TcpError> {
+		if self.socket(id)?.local.port() != 0 {
+			return Err(TcpError::AddressInUse);
+		}
+		let port = self.ports.bind(id, port)?;
+		self.socket(id)?.local.set_port(port);
+		Ok(port)
+	}
+
+	/// Free a socket and its port
+	fn remove(&mut self, id: SocketId) {
+		let Some(socket) = self.sockets.remove(&id) else {
+			return;
+		};
+		self.ports.release(socket.local.port(), id);
+		if let Some(parent) = socket.parent.and_then(|parent| self.sockets.get_mut(&parent)) {
+			parent.backlog.retain(|&child| child != id);
+		}
+	}
+
+	/// Free a socket once its connection is over and nobody will ask about
+	/// it: its owner closed it, or no one ever accepted it
+	fn remove_if_done(&mut self, id: SocketId) {
+		if self.sockets.get(&id).is_some_and(|socket| socket.state == State::Closed && (socket.orphaned || socket.parent.is_some())) {
+			self.remove(id);
+		}
+	}
+
+	/// The socket a segment belongs to: its connection, or else a listener
+	/// on its port
+	fn find(&self, segment: &Segment) -> Option<SocketId> {
+		let connection = self.sockets.iter().find(|(_, socket)| {
+			socket.state != State::Listen
+				&& socket.local.port() == segment.destination.port()
+				&& socket.remote == Some(segment.source)
+		});
+		connection.map(|(&id, _)| id).or_else(|| {
+			let id = self.ports.get(segment.destination.port())?;
+			(self.sockets.get(&id)?.state == State::Listen).then_some(id)
+		})
+	}
+
+	/// Handle a segment for a listener: a SYN starts a connection
+	fn listener_receives(&mut self, id: SocketId, segment: &Segment, out: &mut Vec<Segment>) {
+		if segment.flags & RST != 0 {
+			return;
+		}
+		if segment.flags & ACK != 0 {
+			out.push(segment.reset_reply());
+			return;
+		}
+		if segment.flags & SYN == 0 {
+			return;
+		}
+		let full = {
+			let limit = self.sockets.get(&id).map_or(0, |listener| listener.backlog_limit);
+			self.sockets.values().filter(|socket| socket.parent == Some(id)).count() >= limit
+		};
+		if full {
+			return;
+		}
+		let mut child = Socket::new();
+		child.state = State::SynReceived;
+		child.local = segment.destination;
+		child.remote = Some(segment.source);
+		child.parent = Some(id);
+		child.iss = initial_sequence();
+		child.snd_una = child.iss;
+		child.snd_nxt = child.iss.wrapping_add(1);
+		child.snd_wnd = u32::from(segment.window);
+		child.mss = usize::from(segment.mss.unwrap_or(DEFAULT_PEER_SEGMENT as u16)).clamp(1, MAX_SEGMENT);
+		child.rcv_nxt = segment.seq.wrapping_add(1);
+		out.push(child.syn());
+		child.start_timer();
+		self.insert(child);
+	}
+
+	/// Handle a segment for a socket whose SYN is out
+	fn syn_sent_receives(socket: &mut Socket, segment: &Segment, out: &mut Vec<Segment>) {
+		let ack_ok = segment.flags & ACK != 0 && segment.ack == socket.snd_nxt;
+		if segment.flags & ACK != 0 && !ack_ok {
+			if segment.flags & RST == 0 {
+				out.push(segment.reset_reply());
+			}
+			return;
+		}
+		if segment.flags & RST != 0 {
+			if ack_ok {
+				socket.abort(Some(TcpError::ConnectionRefused));
+			}
+			return;
+		}
+		// A SYN without an ACK would be a simultaneous open, which is left out
+		if segment.flags & SYN == 0 || !ack_ok {
+			return;
+		}
+		socket.rcv_nxt = segment.seq.wrapping_add(1);
+		socket.mss = usize::from(segment.mss.unwrap_or(DEFAULT_PEER_SEGMENT as u16)).clamp(1, MAX_SEGMENT);
+		socket.acknowledge(segment.ack, segment.window);
+		socket.state = State::Established;
+		let before = out.len();
+		socket.flush(false, out);
+		if out.len() == before {
+			out.push(socket.segment(socket.snd_nxt, ACK, Vec::new()));
+		}
+	}
+
+	/// Handle a segment for a synchronized connection
+	fn connection_receives(&mut self, id: SocketId, mut segment: Segment, out: &mut Vec<Segment>) {
+		let Some(socket) = self.sockets.get_mut(&id) else {
+			return;
+		};
+		let ack_now = |socket: &Socket, out: &mut Vec<Segment>| out.push(socket.segment(socket.snd_nxt, ACK, Vec::new()));
+
+		// Only what continues from `rcv_nxt` is taken; anything ahead of it is
+		// dropped for the peer to send again
+		if seq_lt(socket.rcv_nxt, segment.seq) {
+			if segment.flags & RST == 0 {
+				ack_now(socket, out);
+			}
+			return;
+		}
+		let old = socket.rcv_nxt.wrapping_sub(segment.seq);
+		if old > 0 {
+			if old >= segment.length() {
+				// Nothing new: the peer sent it again, so it missed our acknowledgement
+				if segment.flags & RST == 0 {
+					ack_now(socket, out);
+				}
+				return;
+			}
+			let mut skip = old as usize;
+			if segment.flags & SYN != 0 {
+				segment.flags &= !SYN;
+				skip -= 1;
+			}
+			segment.data.drain(..skip.min(segment.data.len()));
+			segment.seq = socket.rcv_nxt;
+		}
+
+		if segment.flags & RST != 0 {
+			if socket.parent.is_some() {
+				socket.abort(None);
+			} else {
+				socket.abort(Some(TcpError::ConnectionReset));
+			}
+			self.remove_if_done(id);
+			return;
+		}
+		if segment.flags & SYN != 0 {
+			ack_now(socket, out);
+			return;
+		}
+		if segment.flags & ACK == 0 {
+			return;
+		}
+
+		if socket.state == State::SynReceived {
+			if segment.ack != socket.snd_nxt {
+				out.push(segment.reset_reply());
+				return;
+			}
+			socket.state = State::Established;
+			if let Some(parent) = socket.parent {
+				if let Some(listener) = self.sockets.get_mut(&parent) {
+					listener.backlog.push_back(id);
+				}
+			}
+		}
+		let Some(socket) = self.sockets.get_mut(&id) else {
+			return;
+		};
+		if !socket.acknowledge(segment.ack, segment.window) {
+			ack_now(socket, out);
+			return;
+		}
+		if socket.fin_acked() {
+			match socket.state {
+				State::FinWait1 => {
+					socket.state = State::FinWait2;
+					if socket.orphaned {
+						socket.linger_until = Some(Instant::now() + FIN_WAIT_2_TIMEOUT);
+					}
+				}
+				State::Closing => {
+					socket.state = State::TimeWait;
+					socket.linger_until = Some(Instant::now() + TIME_WAIT);
+				}
+				State::LastAck => {
+					socket.abort(None);
+					self.remove_if_done(id);
+					return;
+				}
+				_ => {}
+			}
+		}
+
+		let mut need_ack = false;
+		let mut complete = true;
+		if !segment.data.is_empty() && socket.state.receiving() {
+			let room = RECEIVE_CAPACITY - socket.receive_buffer.len();
+			let taken = segment.data.len().min(room);
+			complete = taken == segment.data.len();
+			if !socket.read_shutdown {
+				socket.receive_buffer.extend(&segment.data[..taken]);
+			}
+			socket.rcv_nxt = socket.rcv_nxt.wrapping_add(taken as u32);
+			need_ack = true;
+		}
+		if segment.flags & FIN != 0 && complete && !socket.fin_received {
+			socket.rcv_nxt = socket.rcv_nxt.wrapping_add(1);
+			socket.fin_received = true;
+			need_ack = true;
+			socket.state = match socket.state {
+				State::SynReceived | State::Established => State::CloseWait,
+				State::FinWait1 if socket.fin_acked() => State::TimeWait,
+				State::FinWait1 => State::Closing,
+				State::FinWait2 => State::TimeWait,
+				state => state,
+			};
+			if socket.state == State::TimeWait {
+				socket.linger_until = Some(Instant::now() + TIME_WAIT);
+			}
+		}
+		let before = out.len();
+		socket.flush(false, out);
+		if need_ack && out.len() == before {
+			ack_now(socket, out);
+		}
+	}
+
+	/// Retransmit what has waited too long, and end connections whose time
+	/// is up
+	fn tick(&mut self, now: Instant, out: &mut Vec<Segment>) {
+		let ids: Vec<SocketId> = self.sockets.keys().copied().collect();
+		for id in ids {
+			let Some(socket) = self.sockets.get_mut(&id) else {
+				continue;
+			};
+			if socket.linger_until.is_some_and(|until| now >= until) {
+				socket.abort(None);
+				self.remove_if_done(id);
+				continue;
+			}
+			if socket.retransmit_at.is_none_or(|at| now < at) {
+				continue;
+			}
+			socket.retries += 1;
+			if socket.retries > MAX_RETRIES {
+				if socket.state.synchronized() {
+					out.push(socket.segment(socket.snd_nxt, RST | ACK, Vec::new()));
+				}
+				socket.abort(Some(TcpError::TimedOut));
+				self.remove_if_done(id);
+				continue;
+			}
+			socket.rto = (socket.rto * 2).min(MAX_RTO);
+			socket.retransmit_at = None;
+			match socket.state {
+				State::SynSent | State::SynReceived => {
+					out.push(socket.syn());
+					socket.start_timer();
+				}
+				_ => {
+					// Go back to the oldest unacknowledged byte and send again
+					let probe = socket.snd_wnd == 0;
+					socket.snd_nxt = socket.snd_una;
+					socket.fin_sent = false;
+					socket.flush(probe, out);
+				}
+			}
+		}
+	}
+}
+
+/// Send segments made while the table was locked
+fn transmit(segments: Vec<Segment>) {
+	for segment in segments {
+		let _ = ipv4::send(*segment.destination.ip(), ipv4::PROTOCOL_TCP, &segment.to_bytes());
+	}
+}
+
+/// Run `f` on the socket table, then send the segments it made
+fn with_table<T>(f: impl FnOnce(&mut SocketTable, &mut Vec<Segment>) -> T) -> T {
+	let mut out = Vec::new();
+	let result = f(&mut SOCKETS.lock(), &mut out);
+	transmit(out);
+	result
+}
+
+/// Create a socket, neither listening nor connected
+pub fn create() -> SocketId {
+	SOCKETS.lock().insert(Socket::new())
+}
+
+/// Bind a socket to `port`, or to a free ephemeral port for 0; returns the
+/// port bound
+pub fn bind(id: SocketId, port: u16) -> Result<u16, TcpError> {
+	SOCKETS.lock().bind(id, port)
+}
+
+/// Whether operations on a socket fail rather than wait
+pub fn nonblocking(id: SocketId) -> bool {
+	SOCKETS.lock().sockets.get(&id).is_some_and(|socket| socket.nonblocking)
+}
+
+/// Make operations on a socket fail rather than wait, or wait again
+pub fn set_nonblocking(id: SocketId, nonblocking: bool) {
+	if let Some(socket) = SOCKETS.lock().sockets.get_mut(&id) {
+		socket.nonblocking = nonblocking;
+	}
+}
+
+/// Start taking connections on a socket, binding it first if need be
+pub fn listen(id: SocketId, backlog: usize) -> Result<(), TcpError> {
+	let mut table = SOCKETS.lock();
+	let socket = table.socket(id)?;
+	match socket.state {
+		State::Closed if socket.remote.is_none() => {}
+		State::Listen => {}
+		_ => return Err(TcpError::InvalidState),
+	}
+	if table.socket(id)?.local.port() == 0 {
+		table.bind(id, 0)?;
+	}
+	let socket = table.socket(id)?;
+	socket.state = State::Listen;
+	socket.backlog_limit = backlog.clamp(1, MAX_BACKLOG);
+	Ok(())
+}
+
+/// Start connecting a socket to `remote`; `connected` says when it is done
+pub fn connect(id: SocketId, remote: SocketAddrV4) -> Result<(), TcpError> {
+	let (_, _, source) = ipv4::route(*remote.ip()).ok_or(TcpError::NoRoute)?;
+	with_table(|table, out| {
+		let socket = table.socket(id)?;
+		match socket.state {
+			State::Closed if socket.remote.is_none() => {}
+			State::SynSent | State::SynReceived => return Err(TcpError::Connecting),
+			_ => return Err(TcpError::AlreadyConnected),
+		}
+		if socket.local.port() == 0 {
+			table.bind(id, 0)?;
+		}
+		let socket = table.socket(id)?;
+		socket.local.set_ip(source);
+		socket.remote = Some(remote);
+		socket.iss = initial_sequence();
+		socket.snd_una = socket.iss;
+		socket.snd_nxt = socket.iss.wrapping_add(1);
+		socket.state = State::SynSent;
+		out.push(socket.syn());
+		socket.start_timer();
+		Ok(())
+	})
+}
+
+/// Whether a socket's connection is up: `None` while the handshake goes on
+pub fn connected(id: SocketId) -> Result<Option<()>, TcpError> {
+	let mut table = SOCKETS.lock();
+	let socket = table.socket(id)?;
+	match socket.state {
+		State::SynSent | State::SynReceived => Ok(None),
+		State::Closed => Err(socket.error.take().unwrap_or(TcpError::NotConnected)),
+		State::Listen => Err(TcpError::InvalidState),
+		_ => Ok(Some(())),
+	}
+}
+
+/// Take a connection off a listener's backlog, with the peer's address;
+/// `None` if none has come in
+pub fn accept(id: SocketId) -> Result<Option<(SocketId, SocketAddrV4)>, TcpError> {
+	let mut table = SOCKETS.lock();
+	if table.socket(id)?.state != State::Listen {
+		return Err(TcpError::InvalidState);
+	}
+	let Some(child) = table.socket(id)?.backlog.pop_front() else {
+		return Ok(None);
+	};
+	let socket = table.socket(child)?;
+	socket.parent = None;
+	Ok(socket.remote.map(|remote| (child, remote)))
+}
+
+/// Queue as much of `data` as fits to go out on a connection; 0 means the
+/// send buffer is full for now
+pub fn send(id: SocketId, data: &[u8]) -> Result<usize, TcpError> {
+	with_table(|table, out| {
+		let socket = table.socket(id)?;
+		match socket.state {
+			State::Established | State::CloseWait if !socket.fin_queued => {}
+			State::Closed => return Err(socket.error.take().unwrap_or(TcpError::NotConnected)),
+			State::Listen | State::SynSent | State::SynReceived => return Err(TcpError::NotConnected),
+			_ => return Err(TcpError::Shutdown),
+		}
+		let count = data.len().min(SEND_CAPACITY - socket.send_buffer.len());
+		socket.send_buffer.extend(&data[..count]);
+		socket.flush(false, out);
+		Ok(count)
+	})
+}
+
+/// Take received data into `buffer`: `Some(0)` once the peer has finished
+/// sending, `None` while there is nothing yet
+pub fn recv(id: SocketId, buffer: &mut [u8]) -> Result<Option<usize>, TcpError> {
+	with_table(|table, out| {
+		let socket = table.socket(id)?;
+		if !socket.receive_buffer.is_empty() {
+			let closed_window = usize::from(socket.window()) < socket.mss;
+			let count = buffer.len().min(socket.receive_buffer.len());
+			for (slot, byte) in buffer.iter_mut().zip(socket.receive_buffer.drain(..count)) {
+				*slot = byte;
+			}
+			// Tell the peer once there is room again
+			if closed_window && usize::from(socket.window()) >= socket.mss && socket.state.synchronized() {
+				out.push(socket.segment(socket.snd_nxt, ACK, Vec::new()));
+			}
+			return Ok(Some(count));
+		}
+		if socket.fin_received || socket.read_shutdown || buffer.is_empty() {
+			return Ok(Some(0));
+		}
+		match socket.state {
+			State::Closed => match socket.error.take() {
+				Some(error) => Err(error),
+				None if socket.remote.is_some() => Ok(Some(0)),
+				None => Err(TcpError::NotConnected),
+			},
+			State::Listen => Err(TcpError::NotConnected),
+			_ => Ok(None),
+		}
+	})
+}
+
+/// Stop reading, writing, or both; stopping writing sends a FIN after the
+/// data already queued
+pub fn shutdown(id: SocketId, read: bool, write: bool) -> Result<(), TcpError> {
+	with_table(|table, out| {
+		let socket = table.socket(id)?;
+		if !socket.state.synchronized() {
+			return Err(TcpError::NotConnected);
+		}
+		if read {
+			socket.read_shutdown = true;
+			socket.receive_buffer.clear();
+		}
+		if write && !socket.fin_queued {
+			socket.fin_queued = true;
+			socket.flush(false, out);
+		}
+		Ok(())
+	})
+}
+
+/// Give up a socket: a connection closes gracefully in the background,
+/// and a listener resets the connections nobody accepted
+pub fn close(id: SocketId) {
+	with_table(|table, out| {
+		let Ok(socket) = table.socket(id) else {
+			return;
+		};
+		socket.orphaned = true;
+		match socket.state {
+			State::Established | State::CloseWait => {
+				socket.fin_queued = true;
+				socket.flush(false, out);
+			}
+			State::Listen => {
+				let children: Vec<SocketId> = table.sockets.iter().filter(|(_, child)| child.parent == Some(id)).map(|(&child, _)| child).collect();
+				for child in children {
+					if let Some(socket) = table.sockets.get(&child) {
+						out.push(socket.segment(socket.snd_nxt, RST | ACK, Vec::new()));
+					}
+					table.remove(child);
+				}
+				table.remove(id);
+			}
+			State::SynSent | State::SynReceived => {
+				socket.abort(None);
+				table.remove(id);
+			}
+			State::Closed => table.remove(id),
+			_ => {}
+		}
+	})
+}
+
+/// A socket's local address and, once it has one, its peer's
+pub fn addresses(id: SocketId) -> Option<(SocketAddrV4, Option<SocketAddrV4>)> {
+	let table = SOCKETS.lock();
+	let socket = table.sockets.get(&id)?;
+	Some((socket.local, socket.remote))
+}
+
+/// A socket as `netstat` shows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketInfo {
+	pub local: SocketAddrV4,
+	pub remote: Option<SocketAddrV4>,
+	pub state: State,
+	/// Bytes received and not yet read, and sent and not yet acknowledged
+	pub receive_queue: usize,
+	pub send_queue: usize,
+}
+
+/// A snapshot of every socket, orphaned ones still closing included
+pub fn sockets() -> Vec<SocketInfo> {
+	SOCKETS
+		.lock()
+		.sockets
+		.values()
+		.map(|socket| SocketInfo {
+			local: socket.local,
+			remote: socket.remote,
+			state: socket.state,
+			receive_queue: socket.receive_buffer.len(),
+			send_queue: socket.send_buffer.len(),
+		})
+		.collect()
+}
+
+/// Why a socket's connection failed, clearing it
+pub fn take_error(id: SocketId) -> Option<TcpError> {
+	SOCKETS.lock().sockets.get_mut(&id)?.error.take()
+}
+
+/// Handle a segment addressed to us
+pub fn receive(header: &ipv4::Header, bytes: &[u8]) {
+	let Some(segment) = Segment::parse(header, bytes) else {
+		return;
+	};
+	with_table(|table, out| {
+		let Some(id) = table.find(&segment) else {
+			if segment.flags & RST == 0 {
+				out.push(segment.reset_reply());
+			}
+			return;
+		};
+		let Some(state) = table.sockets.get(&id).map(|socket| socket.state) else {
+			return;
+		};
+		match state {
+			State::Listen => table.listener_receives(id, &segment, out),
+			State::SynSent => {
+				if let Some(socket) = table.sockets.get_mut(&id) {
+					SocketTable::syn_sent_receives(socket, &segment, out);
+				}
+				table.remove_if_done(id);
+			}
+			State::Closed => {
+				if segment.flags & RST == 0 {
+					out.push(segment.reset_reply());
+				}
+			}
+			_ => table.connection_receives(id, segment, out),
+		}
+	})
+}
+
+/// Retransmit and time out connections; run every `TICK`
+pub fn tick() {
+	with_table(|table, out| table.tick(Instant::now(), out));
+}
+
+/// Test segments' layout, sequence arithmetic, and a connection's life:
+/// handshake, data both ways, and close, against a peer played here
+#[test_case]
+fn test_tcp() {
+	assert!(seq_lt(u32::MAX, 1) && !seq_lt(1, u32::MAX) && !seq_lt(5, 5));
+	assert_eq!(parse_mss(&[OPTION_NOP, OPTION_MSS, 4, 0x05, 0xb4]), Some(1460));
+	assert_eq!(parse_mss(&[OPTION_END, OPTION_MSS, 4, 0x05, 0xb4]), None);
+
+	let ours = SocketAddrV4::new(Ipv4Addr::new(10, 0, 2, 15), 8080);
+	let peer = SocketAddrV4::new(Ipv4Addr::new(10, 0, 2, 2), 40000);
+	let header = ipv4::Header { source: *peer.ip(), destination: *ours.ip(), protocol: ipv4::PROTOCOL_TCP, ttl: 64 };
+	let syn = Segment { source: peer, destination: ours, seq: 1000, ack: 0, flags: SYN, window: 4096, mss: Some(1000), data: Vec::new() };
+	assert_eq!(Segment::parse(&header, &syn.to_bytes()), Some(syn.clone()));
+
+	let mut table = SocketTable::new();
+	let listener = table.insert(Socket::new());
+	table.bind(listener, 8080).unwrap();
+	let socket = table.socket(listener).unwrap();
+	socket.state = State::Listen;
+	socket.backlog_limit = 1;
+	let mut out = Vec::new();
+
+	// The handshake: SYN, SYN-ACK, ACK
+	let id = table.find(&syn).unwrap();
+	table.listener_receives(id, &syn, &mut out);
+	let syn_ack = out.pop().unwrap();
+	assert_eq!((syn_ack.flags, syn_ack.ack, syn_ack.mss), (SYN | ACK, 1001, Some(MAX_SEGMENT as u16)));
+	let ack = |seq: u32, flags: u8, data: &[u8]| Segment {
+		source: peer, destination: ours, seq, ack: syn_ack.seq.wrapping_add(1), flags: ACK | flags, window: 4096, mss: None, data: Vec::from(data),
+	};
+	let child = table.find(&ack(1001, 0, b"")).unwrap();
+	table.connection_receives(child, ack(1001, 0, b""), &mut out);
+	assert!(out.is_empty());
+	assert_eq!(table.socket(listener).unwrap().backlog.pop_front(), Some(child));
+	table.socket(child).unwrap().parent = None;
+	assert_eq!(table.socket(child).unwrap().state, State::Established);
+
+	// Data in, acknowledged; a segment from the future is not taken
+	table.connection_receives(child, ack(1006, 0, b"world"), &mut out);
+	assert_eq!(out.pop().map(|segment| segment.ack), Some(1001));
+	table.connection_receives(child, ack(1001, PSH, b"hello"), &mut out);
+	assert_eq!(out.pop().map(|segment| (segment.flags, segment.ack)), Some((ACK, 1006)));
+	assert_eq!(table.socket(child).unwrap().receive_buffer.len(), 5);
+
+	// Data out, in segments of the peer's size, then taken off when acknowledged
+	let socket = table.socket(child).unwrap();
+	socket.send_buffer.extend(core::iter::repeat_n(7, 1500));
+	socket.flush(false, &mut out);
+	assert_eq!(out.iter().map(|segment| segment.data.len()).collect::<Vec<_>>(), [1000, 500]);
+	let sent_to = out.last().map(|segment| segment.seq.wrapping_add(500)).unwrap();
+	out.clear();
+	let mut acked = ack(1006, 0, b"");
+	acked.ack = sent_to;
+	table.connection_receives(child, acked.clone(), &mut out);
+	let socket = table.socket(child).unwrap();
+	assert!(socket.send_buffer.is_empty() && socket.retransmit_at.is_none());
+
+	// The peer closes, and so do we
+	table.connection_receives(child, Segment { flags: ACK | FIN, ..acked.clone() }, &mut out);
+	assert_eq!(out.pop().map(|segment| segment.ack), Some(1007));
+	assert_eq!(table.socket(child).unwrap().state, State::CloseWait);
+	let socket = table.socket(child).unwrap();
+	socket.fin_queued = true;
+	socket.flush(false, &mut out);
+	assert_eq!(out.pop().map(|segment| segment.flags), Some(FIN | ACK));
+	assert_eq!(table.socket(child).unwrap().state, State::LastAck);
+	table.socket(child).unwrap().orphaned = true;
+	acked.seq = 1007;
+	acked.ack = sent_to.wrapping_add(1);
+	table.connection_receives(child, acked, &mut out);
+	assert!(table.sockets.get(&child).is_none());
+
+	// Nobody listens on other ports
+	let stray = Segment { destination: SocketAddrV4::new(*ours.ip(), 9), ..syn };
+	assert_eq!(table.find(&stray), None);
+	assert_eq!(stray.reset_reply().flags, RST | ACK);
+}
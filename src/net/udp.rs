@@ -0,0 +1,251 @@
+use super::ipv4::{self, SendError};
+use super::{PortAllocator, PortError};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use crate::sync::Mutex;
+
+/// Size of the header before a datagram's data
+pub const HEADER_SIZE: usize = 8;
+/// The most data a datagram carries, so it fits one packet
+pub const MAX_DATA: usize = ipv4::MAX_PAYLOAD - HEADER_SIZE;
+
+/// Datagrams a socket holds before dropping new ones
+const QUEUE_CAPACITY: usize = 32;
+
+/// Identifier of a UDP socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SocketId(pub usize);
+
+/// Why a socket operation failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpError {
+	/// No socket has that ID
+	NoSocket,
+	/// Another socket is bound to the port, or this one is bound already
+	AddressInUse,
+	/// No port is free to bind to
+	NoPortsFree,
+	/// More than `MAX_DATA`
+	TooLong,
+	/// The network layer has no route to the destination
+	NoRoute,
+}
+
+impl From<PortError> for UdpError {
+	fn from(error: PortError) -> Self {
+		match error {
+			PortError::InUse => UdpError::AddressInUse,
+			PortError::NoneFree => UdpError::NoPortsFree,
+		}
+	}
+}
+
+/// A datagram waiting to be received, with where it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+	pub source: SocketAddrV4,
+	pub data: Vec<u8>,
+}
+
+/// A socket's port, once bound, and what it has received
+struct Socket {
+	port: Option<u16>,
+	/// Whether receiving fails rather than waits while the queue is empty
+	nonblocking: bool,
+	queue: VecDeque<Datagram>,
+}
+
+/// Live sockets and the ports they are bound to
+struct SocketTable {
+	sockets: BTreeMap<SocketId, Socket>,
+	ports: PortAllocator<SocketId>,
+	next_id: usize,
+}
+
+impl SocketTable {
+	/// Bind a socket to `port`, or to a free ephemeral port for 0
+	fn bind(&mut self, id: SocketId, port: u16) -> Result<u16, UdpError> {
+		let socket = self.sockets.get(&id).ok_or(UdpError::NoSocket)?;
+		if socket.port.is_some() {
+			return Err(UdpError::AddressInUse);
+		}
+		let port = self.ports.bind(id, port)?;
+		if let Some(socket) = self.sockets.get_mut(&id) {
+			socket.port = Some(port);
+		}
+		Ok(port)
+	}
+}
+
+static SOCKETS: Mutex<SocketTable> = Mutex::new(SocketTable {
+	sockets: BTreeMap::new(),
+	ports: PortAllocator::new(),
+	next_id: 0,
+});
+
+/// The checksum of a datagram, its header included, between two addresses
+fn checksum(source: Ipv4Addr, destination: Ipv4Addr, datagram: &[u8]) -> u16 {
+	ipv4::pseudo_header_checksum(source, destination, ipv4::PROTOCOL_UDP, datagram)
+}
+
+/// A datagram from `source` to `destination` carrying `data`
+fn datagram(source: SocketAddrV4, destination: SocketAddrV4, data: &[u8]) -> Vec<u8> {
+	let mut datagram = Vec::with_capacity(HEADER_SIZE + data.len());
+	datagram.extend_from_slice(&source.port().to_be_bytes());
+	datagram.extend_from_slice(&destination.port().to_be_bytes());
+	datagram.extend_from_slice(&((HEADER_SIZE + data.len()) as u16).to_be_bytes());
+	datagram.extend_from_slice(&[0, 0]);
+	datagram.extend_from_slice(data);
+	// A sum of zero is sent as all ones, as zero means "no checksum"
+	let sum = match checksum(*source.ip(), *destination.ip(), &datagram) {
+		0 => 0xffff,
+		sum => sum,
+	};
+	datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+	datagram
+}
+
+/// Create an unbound socket
+pub fn create() -> SocketId {
+	let mut table = SOCKETS.lock();
+	let id = SocketId(table.next_id);
+	table.next_id += 1;
+	table.sockets.insert(id, Socket { port: None, nonblocking: false, queue: VecDeque::new() });
+	id
+}
+
+/// Bind a socket to `port`, or to a free ephemeral port for 0; returns the
+/// port bound
+pub fn bind(id: SocketId, port: u16) -> Result<u16, UdpError> {
+	SOCKETS.lock().bind(id, port)
+}
+
+/// The port a socket is bound to, if it is
+pub fn local_port(id: SocketId) -> Option<u16> {
+	SOCKETS.lock().sockets.get(&id)?.port
+}
+
+/// Whether receiving on a socket fails rather than waits for a datagram
+pub fn nonblocking(id: SocketId) -> bool {
+	SOCKETS.lock().sockets.get(&id).is_some_and(|socket| socket.nonblocking)
+}
+
+/// Make receiving on a socket fail rather than wait, or wait again
+pub fn set_nonblocking(id: SocketId, nonblocking: bool) {
+	if let Some(socket) = SOCKETS.lock().sockets.get_mut(&id) {
+		socket.nonblocking = nonblocking;
+	}
+}
+
+/// Send `data` from a socket to `destination`, binding the socket to an
+/// ephemeral port first if it is not bound
+pub fn send_to(id: SocketId, destination: SocketAddrV4, data: &[u8]) -> Result<usize, UdpError> {
+	if data.len() > MAX_DATA {
+		return Err(UdpError::TooLong);
+	}
+	let port = {
+		let mut table = SOCKETS.lock();
+		match table.sockets.get(&id).ok_or(UdpError::NoSocket)?.port {
+			Some(port) => port,
+			None => table.bind(id, 0)?,
+		}
+	};
+	let (_, _, source) = ipv4::route(*destination.ip()).ok_or(UdpError::NoRoute)?;
+	let datagram = datagram(SocketAddrV4::new(source, port), destination, data);
+	match ipv4::send(*destination.ip(), ipv4::PROTOCOL_UDP, &datagram) {
+		Ok(()) => Ok(data.len()),
+		Err(SendError::NoRoute) => Err(UdpError::NoRoute),
+		Err(SendError::TooLong) => Err(UdpError::TooLong),
+	}
+}
+
+/// Take the oldest datagram a socket has received, if any
+pub fn recv_from(id: SocketId) -> Result<Option<Datagram>, UdpError> {
+	Ok(SOCKETS.lock().sockets.get_mut(&id).ok_or(UdpError::NoSocket)?.queue.pop_front())
+}
+
+/// A socket as `netstat` shows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketInfo {
+	pub port: Option<u16>,
+	/// Bytes of the datagrams waiting to be received
+	pub receive_queue: usize,
+}
+
+/// A snapshot of every socket
+pub fn sockets() -> Vec<SocketInfo> {
+	SOCKETS
+		.lock()
+		.sockets
+		.values()
+		.map(|socket| SocketInfo { port: socket.port, receive_queue: socket.queue.iter().map(|datagram| datagram.data.len()).sum() })
+		.collect()
+}
+
+/// Free a socket and its port
+pub fn close(id: SocketId) {
+	let mut table = SOCKETS.lock();
+	if let Some(port) = table.sockets.remove(&id).and_then(|socket| socket.port) {
+		table.ports.release(port, id);
+	}
+}
+
+/// Handle a datagram addressed to us: queue it on the socket bound to its
+/// port, dropping it if there is none or the socket is full
+pub fn receive(header: &ipv4::Header, datagram: &[u8]) {
+	if datagram.len() < HEADER_SIZE {
+		return;
+	}
+	let length = usize::from(u16::from_be_bytes([datagram[4], datagram[5]]));
+	if length < HEADER_SIZE || length > datagram.len() {
+		return;
+	}
+	let datagram = &datagram[..length];
+	let sum = u16::from_be_bytes([datagram[6], datagram[7]]);
+	if sum != 0 && checksum(header.source, header.destination, datagram) != 0 {
+		return;
+	}
+	let source_port = u16::from_be_bytes([datagram[0], datagram[1]]);
+	let destination_port = u16::from_be_bytes([datagram[2], datagram[3]]);
+	let mut table = SOCKETS.lock();
+	let Some(id) = table.ports.get(destination_port) else {
+		return;
+	};
+	if let Some(socket) = table.sockets.get_mut(&id) {
+		if socket.queue.len() < QUEUE_CAPACITY {
+			let source = SocketAddrV4::new(header.source, source_port);
+			socket.queue.push_back(Datagram { source, data: Vec::from(&datagram[HEADER_SIZE..]) });
+		}
+	}
+}
+
+/// Test binding, checksums, and delivery to the socket bound to a port
+#[test_case]
+fn test_udp() {
+	let (a, b) = (create(), create());
+	let port = bind(a, 0).unwrap();
+	assert!(super::EPHEMERAL_PORTS.contains(&port));
+	assert_eq!(bind(b, port), Err(UdpError::AddressInUse));
+	assert_eq!(bind(a, 1), Err(UdpError::AddressInUse));
+
+	let source = SocketAddrV4::new(Ipv4Addr::new(10, 0, 2, 2), 53);
+	let destination = SocketAddrV4::new(Ipv4Addr::new(10, 0, 2, 15), port);
+	let bytes = datagram(source, destination, b"hello");
+	assert_eq!(checksum(*source.ip(), *destination.ip(), &bytes), 0);
+	let header = ipv4::Header { source: *source.ip(), destination: *destination.ip(), protocol: ipv4::PROTOCOL_UDP, ttl: 64 };
+	receive(&header, &bytes);
+	assert_eq!(recv_from(a), Ok(Some(Datagram { source, data: Vec::from(&b"hello"[..]) })));
+	assert_eq!(recv_from(a), Ok(None));
+
+	// A corrupted datagram is dropped
+	let mut corrupted = bytes.clone();
+	corrupted[HEADER_SIZE] ^= 1;
+	receive(&header, &corrupted);
+	assert_eq!(recv_from(a), Ok(None));
+
+	close(a);
+	assert_eq!(recv_from(a), Err(UdpError::NoSocket));
+	assert_eq!(bind(b, port), Ok(port));
+	close(b);
+}
@@ -0,0 +1,242 @@
+use super::{MacAddress, TransmitError, MAX_FRAME};
+use crate::interrupts;
+use crate::pci::{self, DeviceMatch};
+use crate::virtio::{self, Buffer, Transport, Virtqueue};
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicU16, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Feature bits: the device has a MAC address, and reports link status
+const F_MAC: u32 = 1 << 5;
+const F_STATUS: u32 = 1 << 16;
+/// Configuration offsets of the MAC address and link status
+const CONFIG_MAC: u16 = 0;
+const CONFIG_STATUS: u16 = 6;
+const STATUS_LINK_UP: u16 = 1;
+
+/// Queue numbers
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+
+/// The header before every frame; without checksum offload or merged
+/// receive buffers it stays all zeroes
+const HEADER_SIZE: usize = 10;
+/// Where a frame starts in its buffer, after the header
+const FRAME_OFFSET: usize = 16;
+/// Size of each buffer, holding a header and a frame
+const BUFFER_SIZE: usize = 2048;
+/// Buffers kept for receiving and sending, at most
+const RX_BUFFERS: usize = 64;
+const TX_BUFFERS: usize = 32;
+
+/// Devices the interrupt handler checks, since several can share a line
+const MAX_DEVICES: usize = 4;
+
+/// Transitional network devices, which QEMU gives `-device virtio-net`
+pub static DRIVER: pci::Driver = pci::Driver {
+	name: "virtio-net",
+	matches: &[DeviceMatch::Id(virtio::VENDOR_ID, 0x1000)],
+	probe,
+};
+
+/// I/O ports of each device bound without MSI-X, for the interrupt
+/// handler; 0 for none
+static PORTS: [AtomicU16; MAX_DEVICES] = [const { AtomicU16::new(0) }; MAX_DEVICES];
+
+/// A buffer shared with the device
+#[derive(Clone, Copy)]
+struct PacketBuffer {
+	physical: PhysAddr,
+	virtual_address: VirtAddr,
+}
+
+impl PacketBuffer {
+	/// The header and the frame, as separate buffers as legacy devices
+	/// expect
+	fn chain(self, frame_length: usize, writable: bool) -> [Buffer; 2] {
+		[
+			Buffer { address: self.physical, length: HEADER_SIZE as u32, writable },
+			Buffer { address: self.physical + FRAME_OFFSET as u64, length: frame_length as u32, writable },
+		]
+	}
+}
+
+/// A virtio network device and its two queues
+struct VirtioNet {
+	transport: Transport,
+	mac: MacAddress,
+	has_status: bool,
+	rx: Virtqueue,
+	/// The receive buffer behind each chain ID handed out
+	rx_buffers: Vec<Option<PacketBuffer>>,
+	tx: Virtqueue,
+	/// Send buffers not in the queue
+	tx_free: Vec<PacketBuffer>,
+	/// The send buffer behind each chain ID handed out
+	tx_in_flight: Vec<Option<PacketBuffer>>,
+}
+
+/// Allocate `count` packet buffers
+fn allocate(count: usize) -> Option<Vec<PacketBuffer>> {
+	let buffers = virtio::allocate_buffers(count, BUFFER_SIZE)?;
+	Some(buffers.into_iter().map(|(physical, virtual_address)| PacketBuffer { physical, virtual_address }).collect())
+}
+
+impl VirtioNet {
+	/// Negotiate features and set up the queues, with every receive buffer
+	/// posted and the queues' interrupts sent by MSI-X if `msix`; returns
+	/// `None`, with the device marked failed, if it cannot
+	fn new(mut transport: Transport, msix: bool) -> Option<VirtioNet> {
+		transport.reset();
+		transport.add_status(virtio::STATUS_ACKNOWLEDGE | virtio::STATUS_DRIVER);
+		if msix {
+			transport.use_msix(2);
+		}
+		// No checksum offload: frames go out and come in with their checksums
+		let features = transport.negotiate(F_MAC | F_STATUS);
+		let device = Self::set_up(transport, features);
+		if let Some(device) = &device {
+			device.transport.add_status(virtio::STATUS_DRIVER_OK);
+		}
+		device
+	}
+
+	fn set_up(transport: Transport, features: u32) -> Option<VirtioNet> {
+		let Some((rx, tx)) = transport.setup_queue(RECEIVE_QUEUE).zip(transport.setup_queue(TRANSMIT_QUEUE)) else {
+			transport.add_status(virtio::STATUS_FAILED);
+			return None;
+		};
+		let mac = if features & F_MAC != 0 {
+			MacAddress(core::array::from_fn(|byte| transport.config_u8(CONFIG_MAC + byte as u16)))
+		} else {
+			// A locally administered address of our own
+			MacAddress([0x02, 0x53, 0x4f, 0x53, 0x00, 0x01])
+		};
+		let rx_count = RX_BUFFERS.min(usize::from(rx.size()) / 2);
+		let tx_count = TX_BUFFERS.min(usize::from(tx.size()) / 2);
+		let (Some(rx_pool), Some(tx_free)) = (allocate(rx_count), allocate(tx_count)) else {
+			transport.add_status(virtio::STATUS_FAILED);
+			return None;
+		};
+		let mut device = VirtioNet {
+			mac,
+			has_status: features & F_STATUS != 0,
+			rx_buffers: alloc::vec![None; usize::from(rx.size())],
+			tx_in_flight: alloc::vec![None; usize::from(tx.size())],
+			rx,
+			tx,
+			tx_free,
+			transport,
+		};
+		// Completed sends are collected when sending, not by interrupt
+		device.tx.disable_interrupts();
+		for buffer in rx_pool {
+			device.post_receive(buffer);
+		}
+		device.transport.notify(&device.rx);
+		Some(device)
+	}
+
+	/// Hand a receive buffer to the device
+	fn post_receive(&mut self, buffer: PacketBuffer) {
+		if let Some(id) = self.rx.add(&buffer.chain(BUFFER_SIZE - FRAME_OFFSET, true)) {
+			self.rx_buffers[usize::from(id)] = Some(buffer);
+		}
+	}
+
+	/// Take back the send buffers the device is done with
+	fn reclaim_sent(&mut self) {
+		while let Some((id, _)) = self.tx.take_used() {
+			self.tx_free.extend(self.tx_in_flight[usize::from(id)].take());
+		}
+	}
+}
+
+impl super::Driver for VirtioNet {
+	fn mac(&self) -> MacAddress {
+		self.mac
+	}
+
+	fn link_up(&self) -> bool {
+		!self.has_status || self.transport.config_u16(CONFIG_STATUS) & STATUS_LINK_UP != 0
+	}
+
+	fn transmit(&mut self, frame: &[u8]) -> Result<(), TransmitError> {
+		if frame.len() > MAX_FRAME {
+			return Err(TransmitError::TooLong);
+		}
+		self.reclaim_sent();
+		let buffer = self.tx_free.pop().ok_or(TransmitError::Busy)?;
+		unsafe {
+			let start = buffer.virtual_address.as_mut_ptr::<u8>();
+			core::ptr::write_bytes(start, 0, HEADER_SIZE);
+			core::ptr::copy_nonoverlapping(frame.as_ptr(), start.add(FRAME_OFFSET), frame.len());
+		}
+		let Some(id) = self.tx.add(&buffer.chain(frame.len(), false)) else {
+			self.tx_free.push(buffer);
+			return Err(TransmitError::Busy);
+		};
+		self.tx_in_flight[usize::from(id)] = Some(buffer);
+		self.transport.notify(&self.tx);
+		Ok(())
+	}
+
+	fn receive(&mut self, deliver: &mut dyn FnMut(&[u8])) {
+		let mut received = false;
+		while let Some((id, length)) = self.rx.take_used() {
+			let Some(buffer) = self.rx_buffers[usize::from(id)].take() else {
+				continue;
+			};
+			// The length counts the header too
+			let length = (length as usize).saturating_sub(HEADER_SIZE).min(BUFFER_SIZE - FRAME_OFFSET);
+			let frame = unsafe { core::slice::from_raw_parts(buffer.virtual_address.as_ptr::<u8>().add(FRAME_OFFSET), length) };
+			deliver(frame);
+			self.post_receive(buffer);
+			received = true;
+		}
+		if received {
+			self.transport.notify(&self.rx);
+		}
+	}
+}
+
+/// Interrupt of a device with a message vector of its own
+fn message_interrupt() {
+	super::receive_interrupt();
+}
+
+/// Interrupt on a line that may be shared: collect frames if one of ours
+/// raised it
+fn line_interrupt() {
+	let mut pending = false;
+	for port in &PORTS {
+		let port = port.load(Ordering::Acquire);
+		if port != 0 {
+			pending |= virtio::queue_interrupt(port);
+		}
+	}
+	if pending {
+		super::receive_interrupt();
+	}
+}
+
+/// Bind a virtio network device: take its interrupt, by message if it can
+/// send one, then negotiate and set up its queues
+fn probe(device: &pci::Device) -> Result<(), &'static str> {
+	let transport = Transport::new(device).ok_or("no legacy I/O BAR")?;
+	device.enable_bus_master();
+	let msix = match device.enable_msi(message_interrupt) {
+		Ok(_) => device.msix.is_some(),
+		Err(_) => {
+			let slot = PORTS.iter().position(|port| port.load(Ordering::Relaxed) == 0).ok_or("too many devices")?;
+			let line = device.interrupt_line.ok_or("no interrupt")?;
+			interrupts::register_irq(line, line_interrupt).map_err(|_| "interrupt line full")?;
+			PORTS[slot].store(transport.port(), Ordering::Release);
+			interrupts::enable_irq(line);
+			false
+		}
+	};
+	let net = VirtioNet::new(transport, msix).ok_or("device setup failed")?;
+	super::register(Box::new(net));
+	Ok(())
+}
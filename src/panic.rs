@@ -0,0 +1,204 @@
+use crate::{console, framebuffer, kdb, ksyms, memory, power, serial, vga_buffer};
+use crate::vga_buffer::{Color, WRITER};
+use core::cell::Cell;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use x86_64::instructions::port::Port;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+
+/// Colors of the panic screen, readable whatever theme was in use
+const FOREGROUND: Color = Color::White;
+const BACKGROUND: Color = Color::Blue;
+
+/// Most stack frames shown in the backtrace
+const MAX_FRAMES: usize = 16;
+
+/// 8042 keyboard controller ports, polled with interrupts off
+const KBC_DATA: u16 = 0x60;
+const KBC_STATUS: u16 = 0x64;
+/// Status bits: a byte is waiting, and it came from the mouse
+const KBC_OUTPUT_FULL: u8 = 0x01;
+const KBC_AUX_DATA: u8 = 0x20;
+/// Scancode set 1 make code of the R key
+const SCANCODE_R: u8 = 0x13;
+
+/// Control and stack registers at the time of the panic
+pub(crate) struct Registers {
+	rsp: u64,
+	pub(crate) rbp: u64,
+	rflags: u64,
+	cr0: u64,
+	cr2: u64,
+	cr3: u64,
+	cr4: u64,
+}
+
+impl Registers {
+	/// Read the registers of the panicking code
+	pub(crate) fn capture() -> Registers {
+		let (rsp, rbp): (u64, u64);
+		unsafe {
+			core::arch::asm!("mov {}, rsp", "mov {}, rbp", out(reg) rsp, out(reg) rbp, options(nomem, nostack, preserves_flags));
+		}
+		Registers {
+			rsp,
+			rbp,
+			rflags: x86_64::registers::rflags::read_raw(),
+			cr0: Cr0::read_raw(),
+			cr2: Cr2::read().as_u64(),
+			cr3: Cr3::read().0.start_address().as_u64(),
+			cr4: Cr4::read_raw(),
+		}
+	}
+}
+
+impl fmt::Display for Registers {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "  RSP {:016x}  RBP {:016x}  RFLAGS {:016x}", self.rsp, self.rbp, self.rflags)?;
+		writeln!(f, "  CR0 {:016x}  CR2 {:016x}  CR3 {:016x}  CR4 {:016x}", self.cr0, self.cr2, self.cr3, self.cr4)
+	}
+}
+
+/// Return addresses found by following the saved frame pointers from `rbp`,
+/// stopping at the first frame `readable` rejects
+pub(crate) fn backtrace(mut rbp: u64, readable: impl Fn(u64) -> bool) -> impl Iterator<Item = u64> {
+	core::iter::from_fn(move || {
+		if rbp == 0 || !rbp.is_multiple_of(8) || !readable(rbp) || !readable(rbp + 8) {
+			return None;
+		}
+		let (next, address) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+		// Frames are older the higher up the stack they are
+		rbp = if next > rbp { next } else { 0 };
+		(address != 0).then_some(address)
+	})
+	.take(MAX_FRAMES)
+}
+
+/// Whether an address is mapped, so a stack frame there can be read
+pub(crate) fn is_mapped(addr: u64) -> bool {
+	memory::is_range_mapped(addr, 1)
+}
+
+/// Like `is_mapped`, walking the page tables only for a page other than the
+/// last one found mapped, for walks done often
+pub(crate) fn cached_is_mapped() -> impl Fn(u64) -> bool {
+	let checked = Cell::new(u64::MAX);
+	move |addr| {
+		if addr >> 12 == checked.get() {
+			return true;
+		}
+		let mapped = is_mapped(addr);
+		if mapped {
+			checked.set(addr >> 12);
+		}
+		mapped
+	}
+}
+
+/// The frame pointer of the function this is called from
+#[inline(always)]
+pub(crate) fn frame_pointer() -> u64 {
+	let rbp: u64;
+	unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+	rbp
+}
+
+/// Writes the panic report to the screen and to the serial port, unless the
+/// console is drawn there anyway
+struct Report;
+
+impl Write for Report {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		WRITER.lock().write_str(s)?;
+		if console::on_serial() {
+			// The screen is already drawn there
+			return Ok(());
+		}
+		serial::SERIAL1.lock().write_str(s)
+	}
+}
+
+/// Restart if R was pressed on the keyboard, keeping the screen shown
+pub(crate) fn poll_reboot_key() {
+	// Nothing else will show the screen's new contents with interrupts off
+	framebuffer::present();
+	let state = unsafe { Port::<u8>::new(KBC_STATUS).read() };
+	if state & KBC_OUTPUT_FULL == 0 {
+		return;
+	}
+	let scancode = unsafe { Port::<u8>::new(KBC_DATA).read() };
+	if state & KBC_AUX_DATA == 0 && scancode == SCANCODE_R {
+		power::reboot();
+	}
+}
+
+/// Wait for R on the keyboard, then restart
+fn wait_for_reboot() -> ! {
+	loop {
+		poll_reboot_key();
+		core::hint::spin_loop();
+	}
+}
+
+/// Show the panic screen: where and why the kernel panicked, its registers,
+/// and a backtrace named from the symbol map, on the console and the serial
+/// port; then wait for R to reboot, with the debugger on the serial port
+pub fn panic_screen(info: &PanicInfo) -> ! {
+	x86_64::instructions::interrupts::disable();
+	let registers = Registers::capture();
+	// Whatever locks are left held are about to be taken from their holders
+	#[cfg(debug_assertions)]
+	crate::lockdep::stop();
+	unsafe {
+		// Whatever held these locks will never run again
+		serial::SERIAL1.force_unlock();
+		framebuffer::take_over();
+		vga_buffer::take_over(FOREGROUND, BACKGROUND);
+	}
+
+	let mut report = Report;
+	let _ = writeln!(report, "\n *** KERNEL PANIC ***\n");
+	if let Some(location) = info.location() {
+		let _ = writeln!(report, " Panic at: {}:{}:{}", location.file(), location.line(), location.column());
+	}
+	let _ = writeln!(report, " Message: {}\n", info.message());
+	let _ = writeln!(report, " Registers:\n{}", registers);
+	let _ = writeln!(report, " Backtrace:");
+	let mut frames = 0;
+	for (number, address) in backtrace(registers.rbp, is_mapped).enumerate() {
+		let _ = match ksyms::resolve(address) {
+			Some(symbol) => writeln!(report, "  #{:<2} {:#018x} {}", number, address, symbol),
+			None => writeln!(report, "  #{:<2} {:#018x}", number, address),
+		};
+		frames += 1;
+	}
+	if frames == 0 {
+		let _ = writeln!(report, "  (no frames)");
+	}
+	if kdb::enabled() {
+		let _ = writeln!(report, "\n System halted. Press R to reboot, or use kdb on the serial port.");
+		kdb::enter(kdb::Entry::Panic, None);
+	} else {
+		let _ = writeln!(report, "\n System halted. Press R to reboot.");
+	}
+	wait_for_reboot();
+}
+
+/// Test walking a chain of saved frame pointers
+#[test_case]
+fn test_backtrace() {
+	let mut stack = [0u64; 6];
+	let base = stack.as_ptr() as u64;
+	stack[0] = base + 16;
+	stack[1] = 0x1111;
+	stack[2] = base + 32;
+	stack[3] = 0x2222;
+	stack[5] = 0x3333;
+	let mut frames = backtrace(base, |_| true);
+	assert_eq!(frames.next(), Some(0x1111));
+	assert_eq!(frames.next(), Some(0x2222));
+	assert_eq!(frames.next(), Some(0x3333));
+	assert_eq!(frames.next(), None);
+	assert_eq!(backtrace(base + 4, |_| true).count(), 0);
+	assert_eq!(backtrace(base, |addr| addr < base + 16).count(), 1);
+}
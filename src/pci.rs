@@ -0,0 +1,567 @@
+use crate::klog;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use crate::sync::Mutex;
+use x86_64::instructions::port::Port;
+
+/// Configuration mechanism #1 address and data ports
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+
+/// Configuration space offsets
+const REG_VENDOR_ID: u8 = 0x00;
+const REG_COMMAND: u8 = 0x04;
+const REG_STATUS: u8 = 0x06;
+const REG_CLASS: u8 = 0x08;
+const REG_HEADER_TYPE: u8 = 0x0e;
+const REG_BAR0: u8 = 0x10;
+const REG_CAPABILITIES: u8 = 0x34;
+const REG_INTERRUPT: u8 = 0x3c;
+
+/// Command register bits that enable I/O and memory decoding
+const COMMAND_DECODE: u16 = 0x3;
+/// Command register bits letting the function write memory, as message
+/// interrupts are, and turning off its INTx# pin
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+/// Status register bit set when there is a capability list
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+/// Capability IDs
+const CAP_MSI: u8 = 0x05;
+const CAP_MSIX: u8 = 0x11;
+
+/// MSI message control bits
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_MULTIPLE_MESSAGES: u16 = 0b111 << 4;
+const MSI_64BIT: u16 = 1 << 7;
+/// MSI-X message control bits
+const MSIX_TABLE_SIZE: u16 = 0x7ff;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+/// Size of an MSI-X table entry, and its vector control bit masking it
+const MSIX_ENTRY_SIZE: usize = 16;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// Where message interrupts are written to reach a local APIC
+const MSI_ADDRESS_BASE: u32 = 0xfee0_0000;
+/// Header type bit marking a multi-function device
+const MULTI_FUNCTION: u8 = 0x80;
+/// Vendor ID read back when no function is present
+const NO_DEVICE: u16 = 0xffff;
+
+/// A location in PCI configuration space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address {
+	pub bus: u8,
+	pub device: u8,
+	pub function: u8,
+}
+
+/// A decoded base address register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+	Memory { address: u64, size: u64, prefetchable: bool, is_64bit: bool },
+	Io { port: u32, size: u32 },
+}
+
+/// A PCI function found during enumeration
+#[derive(Debug, Clone)]
+pub struct Device {
+	pub address: Address,
+	pub vendor_id: u16,
+	pub device_id: u16,
+	pub class: u8,
+	pub subclass: u8,
+	pub prog_if: u8,
+	pub revision: u8,
+	pub header_type: u8,
+	pub bars: Vec<Bar>,
+	/// Legacy PIC line assigned by the firmware, if any
+	pub interrupt_line: Option<u8>,
+	/// INTA#-INTD# as 1-4, or 0 for none
+	pub interrupt_pin: u8,
+	/// Offset of the MSI capability, if the function has one
+	pub msi: Option<u8>,
+	/// Offset of the MSI-X capability, if the function has one
+	pub msix: Option<u8>,
+}
+
+/// Why message interrupts could not be enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsiError {
+	/// Messages go to a local APIC, and the 8259s are in use
+	NoApic,
+	/// The function has neither MSI nor MSI-X
+	Unsupported,
+	/// Every vector that can be handed out is taken
+	NoVector,
+	/// The BAR holding the MSI-X table could not be mapped
+	Unmapped,
+}
+
+impl Address {
+	/// The CONFIG_ADDRESS value selecting a dword register of this function
+	fn config_address(self, offset: u8) -> u32 {
+		0x8000_0000
+			| (self.bus as u32) << 16
+			| (self.device as u32) << 11
+			| (self.function as u32) << 8
+			| (offset as u32 & 0xfc)
+	}
+
+	/// Read a dword of configuration space
+	pub fn read_u32(self, offset: u8) -> u32 {
+		x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+			Port::new(CONFIG_ADDRESS).write(self.config_address(offset));
+			Port::<u32>::new(CONFIG_DATA).read()
+		})
+	}
+
+	/// Write a dword of configuration space
+	pub fn write_u32(self, offset: u8, value: u32) {
+		x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+			Port::new(CONFIG_ADDRESS).write(self.config_address(offset));
+			Port::new(CONFIG_DATA).write(value);
+		})
+	}
+
+	/// Read a word of configuration space
+	pub fn read_u16(self, offset: u8) -> u16 {
+		(self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+	}
+
+	/// Read a byte of configuration space
+	pub fn read_u8(self, offset: u8) -> u8 {
+		(self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+	}
+
+	/// Write a word of configuration space, preserving the other half of its dword
+	pub fn write_u16(self, offset: u8, value: u16) {
+		let shift = (offset & 2) * 8;
+		let dword = self.read_u32(offset) & !(0xffff << shift);
+		self.write_u32(offset, dword | (value as u32) << shift);
+	}
+}
+
+/// The address and data of a message interrupt raising `vector` on the local
+/// APIC `destination`, edge-triggered with fixed delivery
+fn msi_message(destination: u8, vector: u8) -> (u32, u32) {
+	(MSI_ADDRESS_BASE | (destination as u32) << 12, vector as u32)
+}
+
+/// Walk a function's capability list, returning the offset of each
+/// capability by ID
+fn capabilities(address: Address) -> Vec<(u8, u8)> {
+	let mut found = Vec::new();
+	if address.read_u16(REG_STATUS) & STATUS_CAPABILITIES == 0 {
+		return found;
+	}
+	let mut offset = address.read_u8(REG_CAPABILITIES) & !0x3;
+	// A list longer than configuration space holds is looping
+	while offset != 0 && found.len() < 48 {
+		let header = address.read_u16(offset);
+		found.push((header as u8, offset));
+		offset = (header >> 8) as u8 & !0x3;
+	}
+	found
+}
+
+impl Device {
+	/// Deliver the function's interrupts as messages to a vector of their
+	/// own, running `handler`, with MSI-X if it has it and else MSI; its
+	/// INTx# pin is turned off. Returns the vector.
+	pub fn enable_msi(&self, handler: crate::interrupts::IrqHandler) -> Result<u8, MsiError> {
+		let destination = crate::apic::local_id().ok_or(MsiError::NoApic)?;
+		if self.msix.is_none() && self.msi.is_none() {
+			return Err(MsiError::Unsupported);
+		}
+		let vector = crate::interrupts::allocate_vector(handler).ok_or(MsiError::NoVector)?;
+		let (message_address, data) = msi_message(destination, vector);
+		let enabled = match self.msix {
+			Some(cap) => self.enable_msix(cap, message_address, data),
+			None => self.msi.map(|cap| self.enable_msi_capability(cap, message_address, data)).ok_or(MsiError::Unsupported),
+		};
+		if let Err(error) = enabled {
+			crate::interrupts::free_vector(vector);
+			return Err(error);
+		}
+		let command = self.address.read_u16(REG_COMMAND);
+		self.address.write_u16(REG_COMMAND, command | COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE);
+		Ok(vector)
+	}
+
+	/// Program a single MSI message
+	fn enable_msi_capability(&self, cap: u8, message_address: u32, data: u32) {
+		let address = self.address;
+		let control = address.read_u16(cap + 2);
+		address.write_u32(cap + 4, message_address);
+		let data_offset = if control & MSI_64BIT != 0 {
+			address.write_u32(cap + 8, 0);
+			cap + 12
+		} else {
+			cap + 8
+		};
+		address.write_u16(data_offset, data as u16);
+		// One message, not the several the function may ask for
+		address.write_u16(cap + 2, (control & !MSI_MULTIPLE_MESSAGES) | MSI_ENABLE);
+	}
+
+	/// Send the first MSI-X table entry to the message and mask the rest
+	fn enable_msix(&self, cap: u8, message_address: u32, data: u32) -> Result<(), MsiError> {
+		let address = self.address;
+		let control = address.read_u16(cap + 2);
+		let entries = (control & MSIX_TABLE_SIZE) as usize + 1;
+		let table = address.read_u32(cap + 4);
+		let bar = REG_BAR0 + (table & 0x7) as u8 * 4;
+		let raw = address.read_u32(bar);
+		let mut base = (raw & !0xf) as u64;
+		if raw & 0x6 == 0x4 {
+			base |= (address.read_u32(bar + 4) as u64) << 32;
+		}
+		let start = x86_64::PhysAddr::new(base + (table & !0x7) as u64);
+		let table = crate::memory::map_mmio(start, (entries * MSIX_ENTRY_SIZE) as u64).ok_or(MsiError::Unmapped)?;
+
+		// Mask the whole function while the table is written
+		address.write_u16(cap + 2, control | MSIX_FUNCTION_MASK | MSIX_ENABLE);
+		for entry in 0..entries {
+			let entry_base = (table + (entry * MSIX_ENTRY_SIZE) as u64).as_mut_ptr::<u32>();
+			unsafe {
+				if entry == 0 {
+					entry_base.write_volatile(message_address);
+					entry_base.add(1).write_volatile(0);
+					entry_base.add(2).write_volatile(data);
+					entry_base.add(3).write_volatile(0);
+				} else {
+					entry_base.add(3).write_volatile(MSIX_ENTRY_MASKED);
+				}
+			}
+		}
+		address.write_u16(cap + 2, (control & !MSIX_FUNCTION_MASK) | MSIX_ENABLE);
+		Ok(())
+	}
+
+	/// Turn on the function's I/O and memory decoding, and let it master
+	/// the bus so it can DMA to memory
+	pub fn enable_bus_master(&self) {
+		let command = self.address.read_u16(REG_COMMAND);
+		self.address.write_u16(REG_COMMAND, command | COMMAND_DECODE | COMMAND_BUS_MASTER);
+	}
+
+	/// Vectors the function's MSI-X table has room for, if it has MSI-X
+	pub fn msix_vectors(&self) -> Option<usize> {
+		self.msix.map(|cap| (self.address.read_u16(cap + 2) & MSIX_TABLE_SIZE) as usize + 1)
+	}
+}
+
+/// Decode a BAR from its original value and the value read back after writing
+/// all ones; the high dword of 64-bit memory BARs is passed alongside
+fn decode_bar(raw: u32, mask: u32, raw_high: u32, mask_high: u32) -> Option<Bar> {
+	if raw & 1 == 1 {
+		let mask = mask & !0x3;
+		let size = (!mask).wrapping_add(1) & 0xffff;
+		return (mask != 0).then_some(Bar::Io { port: raw & !0x3, size });
+	}
+	if mask == 0 && mask_high == 0 {
+		return None;
+	}
+	let is_64bit = (raw >> 1) & 0x3 == 0x2;
+	let prefetchable = raw & 0x8 != 0;
+	let (address, mask) = if is_64bit {
+		((raw_high as u64) << 32 | (raw & !0xf) as u64, (mask_high as u64) << 32 | (mask & !0xf) as u64)
+	} else {
+		((raw & !0xf) as u64, 0xffff_ffff_0000_0000 | (mask & !0xf) as u64)
+	};
+	let size = (!mask).wrapping_add(1);
+	Some(Bar::Memory { address, size, prefetchable, is_64bit })
+}
+
+/// Size and decode the BARs of a function with decoding briefly disabled
+fn read_bars(address: Address, count: u8) -> Vec<Bar> {
+	let command = address.read_u16(REG_COMMAND);
+	address.write_u16(REG_COMMAND, command & !COMMAND_DECODE);
+
+	let mut bars = Vec::new();
+	let mut index = 0;
+	while index < count {
+		let offset = REG_BAR0 + index * 4;
+		let size_probe = |offset: u8| {
+			let raw = address.read_u32(offset);
+			address.write_u32(offset, 0xffff_ffff);
+			let mask = address.read_u32(offset);
+			address.write_u32(offset, raw);
+			(raw, mask)
+		};
+		let (raw, mask) = size_probe(offset);
+		let (raw_high, mask_high) = if raw & 0x7 == 0x4 && index + 1 < count {
+			index += 1;
+			size_probe(offset + 4)
+		} else {
+			(0, 0)
+		};
+		bars.extend(decode_bar(raw, mask, raw_high, mask_high));
+		index += 1;
+	}
+
+	address.write_u16(REG_COMMAND, command);
+	bars
+}
+
+/// Read the identity and resources of a present function
+fn probe(address: Address) -> Option<Device> {
+	let vendor_id = address.read_u16(REG_VENDOR_ID);
+	if vendor_id == NO_DEVICE {
+		return None;
+	}
+	let class = address.read_u32(REG_CLASS);
+	let header_type = address.read_u8(REG_HEADER_TYPE);
+	let bar_count = match header_type & !MULTI_FUNCTION {
+		0x00 => 6,
+		0x01 => 2,
+		_ => 0,
+	};
+	let interrupt = address.read_u32(REG_INTERRUPT);
+	let line = interrupt as u8;
+	let capabilities = capabilities(address);
+	let find = |id: u8| capabilities.iter().find(|&&(cap, _)| cap == id).map(|&(_, offset)| offset);
+	Some(Device {
+		address,
+		vendor_id,
+		device_id: address.read_u16(REG_VENDOR_ID + 2),
+		class: (class >> 24) as u8,
+		subclass: (class >> 16) as u8,
+		prog_if: (class >> 8) as u8,
+		revision: class as u8,
+		header_type,
+		bars: read_bars(address, bar_count),
+		interrupt_line: (line != 0xff && line != 0).then_some(line),
+		interrupt_pin: (interrupt >> 8) as u8,
+		msi: find(CAP_MSI),
+		msix: find(CAP_MSIX),
+	})
+}
+
+/// Functions found by the last scan
+static DEVICES: Mutex<Vec<Device>> = Mutex::new(Vec::new());
+
+/// Enumerate every bus, device, and function, returning how many were found
+pub fn init() -> usize {
+	let mut found = Vec::new();
+	for bus in 0..=255u8 {
+		for device in 0..32u8 {
+			let Some(first) = probe(Address { bus, device, function: 0 }) else {
+				continue;
+			};
+			let functions = if first.header_type & MULTI_FUNCTION != 0 { 8 } else { 1 };
+			found.push(first);
+			for function in 1..functions {
+				found.extend(probe(Address { bus, device, function }));
+			}
+		}
+	}
+	let count = found.len();
+	*DEVICES.lock() = found;
+	bind_all();
+	count
+}
+
+/// A snapshot of the enumerated devices
+pub fn devices() -> Vec<Device> {
+	DEVICES.lock().clone()
+}
+
+/// Which devices a driver can drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceMatch {
+	/// A vendor and device ID
+	Id(u16, u16),
+	/// A base class and subclass, such as 0x01, 0x06 for SATA
+	Class(u8, u8),
+	/// A base class, subclass, and programming interface
+	ClassInterface(u8, u8, u8),
+}
+
+impl DeviceMatch {
+	pub fn matches(self, device: &Device) -> bool {
+		match self {
+			DeviceMatch::Id(vendor_id, device_id) => (device.vendor_id, device.device_id) == (vendor_id, device_id),
+			DeviceMatch::Class(class, subclass) => (device.class, device.subclass) == (class, subclass),
+			DeviceMatch::ClassInterface(class, subclass, prog_if) => {
+				(device.class, device.subclass, device.prog_if) == (class, subclass, prog_if)
+			}
+		}
+	}
+}
+
+/// A driver for PCI functions, claiming those it matches
+pub struct Driver {
+	pub name: &'static str,
+	pub matches: &'static [DeviceMatch],
+	/// Set up a matched function, or give why it cannot be driven, which
+	/// leaves it free for another driver
+	pub probe: fn(&Device) -> Result<(), &'static str>,
+}
+
+/// Drivers in the order they registered
+static DRIVERS: Mutex<Vec<&'static Driver>> = Mutex::new(Vec::new());
+/// The driver bound to each claimed function
+static BOUND: Mutex<BTreeMap<Address, &'static str>> = Mutex::new(BTreeMap::new());
+
+/// Offer `device`, if unclaimed, to `driver`, returning whether it bound
+fn try_bind(driver: &'static Driver, device: &Device) -> bool {
+	if BOUND.lock().contains_key(&device.address) || !driver.matches.iter().any(|m| m.matches(device)) {
+		return false;
+	}
+	let address = device.address;
+	// Probing runs unlocked: drivers read configuration space and may
+	// look at other devices
+	match (driver.probe)(device) {
+		Ok(()) => {
+			klog!(Info, "pci {:02x}:{:02x}.{}: bound to {}", address.bus, address.device, address.function, driver.name);
+			BOUND.lock().insert(address, driver.name);
+			true
+		}
+		Err(reason) => {
+			klog!(Warn, "pci {:02x}:{:02x}.{}: {} failed: {}", address.bus, address.device, address.function, driver.name, reason);
+			false
+		}
+	}
+}
+
+/// Add a driver and let it claim every unclaimed function it matches,
+/// returning how many it bound; functions found by later scans are offered
+/// to it too
+pub fn register_driver(driver: &'static Driver) -> usize {
+	DRIVERS.lock().push(driver);
+	devices().iter().filter(|device| try_bind(driver, device)).count()
+}
+
+/// Offer every unclaimed function to the registered drivers, first
+/// registered first
+fn bind_all() {
+	let drivers = DRIVERS.lock().clone();
+	for device in devices() {
+		for &driver in &drivers {
+			if try_bind(driver, &device) {
+				break;
+			}
+		}
+	}
+}
+
+/// Name of the driver bound to a function, if one claimed it
+pub fn driver_of(address: Address) -> Option<&'static str> {
+	BOUND.lock().get(&address).copied()
+}
+
+/// Name of a class code, falling back to just the base class
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+	match (class, subclass) {
+		(0x00, 0x01) => "VGA compatible unclassified device",
+		(0x01, 0x01) => "IDE interface",
+		(0x01, 0x06) => "SATA controller",
+		(0x01, 0x08) => "Non-Volatile memory controller",
+		(0x01, _) => "Mass storage controller",
+		(0x02, 0x00) => "Ethernet controller",
+		(0x02, _) => "Network controller",
+		(0x03, 0x00) => "VGA compatible controller",
+		(0x03, _) => "Display controller",
+		(0x04, 0x01) => "Multimedia audio controller",
+		(0x04, 0x03) => "Audio device",
+		(0x04, _) => "Multimedia controller",
+		(0x05, _) => "Memory controller",
+		(0x06, 0x00) => "Host bridge",
+		(0x06, 0x01) => "ISA bridge",
+		(0x06, 0x04) => "PCI bridge",
+		(0x06, _) => "Bridge",
+		(0x07, _) => "Communication controller",
+		(0x08, _) => "System peripheral",
+		(0x09, _) => "Input device controller",
+		(0x0c, 0x03) => "USB controller",
+		(0x0c, 0x05) => "SMBus",
+		(0x0c, _) => "Serial bus controller",
+		(0x0d, _) => "Wireless controller",
+		(0xff, _) => "Unassigned class",
+		_ => "Unclassified device",
+	}
+}
+
+/// Name of a vendor ID, for the vendors commonly seen in emulators
+pub fn vendor_name(vendor_id: u16) -> Option<&'static str> {
+	Some(match vendor_id {
+		0x8086 => "Intel Corporation",
+		0x1022 => "Advanced Micro Devices, Inc.",
+		0x10de => "NVIDIA Corporation",
+		0x10ec => "Realtek Semiconductor Co., Ltd.",
+		0x1234 => "QEMU",
+		0x15ad => "VMware",
+		0x1af4 => "Red Hat, Inc.",
+		0x1b36 => "Red Hat, Inc.",
+		0x80ee => "InnoTek Systemberatung GmbH",
+		_ => return None,
+	})
+}
+
+/// Name of a device, for the devices commonly seen in emulators
+pub fn device_name(vendor_id: u16, device_id: u16) -> Option<&'static str> {
+	Some(match (vendor_id, device_id) {
+		(0x8086, 0x1237) => "440FX - 82441FX PMC [Natoma]",
+		(0x8086, 0x7000) => "82371SB PIIX3 ISA [Natoma/Triton II]",
+		(0x8086, 0x7010) => "82371SB PIIX3 IDE [Natoma/Triton II]",
+		(0x8086, 0x7113) => "82371AB/EB/MB PIIX4 ACPI",
+		(0x8086, 0x100e) => "82540EM Gigabit Ethernet Controller",
+		(0x8086, 0x10d3) => "82574L Gigabit Network Connection",
+		(0x8086, 0x29c0) => "82G33/G31/P35/P31 Express DRAM Controller",
+		(0x8086, 0x2918) => "82801IB (ICH9) LPC Interface Controller",
+		(0x8086, 0x2922) => "82801IR/IO/IH (ICH9R/DO/DH) 6 port SATA Controller [AHCI mode]",
+		(0x8086, 0x2930) => "82801I (ICH9 Family) SMBus Controller",
+		(0x1234, 0x1111) => "Standard VGA",
+		(0x10ec, 0x8139) => "RTL-8100/8101L/8139 PCI Fast Ethernet Adapter",
+		(0x1af4, 0x1000) => "Virtio network device",
+		(0x1af4, 0x1001) => "Virtio block device",
+		(0x1af4, 0x1041) => "Virtio 1.0 network device",
+		(0x1af4, 0x1042) => "Virtio 1.0 block device",
+		_ => return None,
+	})
+}
+
+/// Test BAR decoding for I/O, 32-bit, and 64-bit memory BARs
+#[test_case]
+fn test_decode_bar() {
+	assert_eq!(decode_bar(0xc001, 0xffff_ffc1, 0, 0), Some(Bar::Io { port: 0xc000, size: 64 }));
+	assert_eq!(
+		decode_bar(0xfebc_0000, 0xfffe_0000, 0, 0),
+		Some(Bar::Memory { address: 0xfebc_0000, size: 128 * 1024, prefetchable: false, is_64bit: false })
+	);
+	assert_eq!(
+		decode_bar(0xc, 0xffff_c00c, 0x1, 0xffff_ffff),
+		Some(Bar::Memory { address: 0x1_0000_0000, size: 16 * 1024, prefetchable: true, is_64bit: true })
+	);
+	assert_eq!(decode_bar(0, 0, 0, 0), None);
+	assert_eq!(class_name(0x02, 0x00), "Ethernet controller");
+	assert_eq!(class_name(0x06, 0x80), "Bridge");
+	assert_eq!(msi_message(3, 0x51), (0xfee0_3000, 0x51));
+}
+
+/// Test that drivers match on IDs and classes, and claim a function once
+#[test_case]
+fn test_driver_binding() {
+	let device = Device {
+		address: Address { bus: 0xff, device: 31, function: 7 },
+		vendor_id: 0x8086, device_id: 0x2922, class: 0x01, subclass: 0x06, prog_if: 0x01, revision: 2,
+		header_type: 0, bars: Vec::new(), interrupt_line: None, interrupt_pin: 0, msi: None, msix: None,
+	};
+	assert!(DeviceMatch::Id(0x8086, 0x2922).matches(&device));
+	assert!(!DeviceMatch::Id(0x8086, 0x2918).matches(&device));
+	assert!(DeviceMatch::Class(0x01, 0x06).matches(&device));
+	assert!(!DeviceMatch::ClassInterface(0x01, 0x06, 0x00).matches(&device));
+
+	static REFUSES: Driver = Driver { name: "refuses", matches: &[DeviceMatch::Class(0x01, 0x06)], probe: |_| Err("test") };
+	static AHCI: Driver = Driver { name: "ahci", matches: &[DeviceMatch::ClassInterface(0x01, 0x06, 0x01)], probe: |_| Ok(()) };
+	static OTHER: Driver = Driver { name: "other", matches: &[DeviceMatch::Id(0x8086, 0x2922)], probe: |_| Ok(()) };
+	assert!(!try_bind(&REFUSES, &device));
+	assert!(try_bind(&AHCI, &device));
+	assert!(!try_bind(&OTHER, &device));
+	assert_eq!(driver_of(device.address), Some("ahci"));
+	BOUND.lock().remove(&device.address);
+}
@@ -0,0 +1,89 @@
+use crate::smp::MAX_CPUS;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::registers::model_specific::GsBase;
+use x86_64::VirtAddr;
+
+/// What each processor's GS base points at
+#[repr(C)]
+struct CpuArea {
+	/// Index of the processor, read with `gs:[0]`
+	index: usize,
+}
+
+/// Every processor's area, by index
+static AREAS: [CpuArea; MAX_CPUS] = {
+	let mut areas = [const { CpuArea { index: 0 } }; MAX_CPUS];
+	let mut index = 0;
+	while index < MAX_CPUS {
+		areas[index].index = index;
+		index += 1;
+	}
+	areas
+};
+
+/// Set once the boot processor's GS base points at its area
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Point this processor's GS base at the per-CPU area of processor `cpu`;
+/// the boot processor is 0 and must come first
+pub fn init(cpu: usize) {
+	GsBase::write(VirtAddr::from_ptr(&AREAS[cpu.min(MAX_CPUS - 1)]));
+	READY.store(true, Ordering::Release);
+}
+
+/// Index of the processor this runs on, 0 before `init`
+pub fn cpu_index() -> usize {
+	if !READY.load(Ordering::Acquire) {
+		return 0;
+	}
+	let index: usize;
+	unsafe { core::arch::asm!("mov {}, gs:[0]", out(reg) index, options(readonly, nostack, preserves_flags)) };
+	index
+}
+
+/// A value each processor has its own copy of, declared with `per_cpu!`
+pub struct PerCpu<T> {
+	values: [T; MAX_CPUS],
+}
+
+// Each processor only touches its own value, and only with interrupts off
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+	#[doc(hidden)]
+	pub const fn new(values: [T; MAX_CPUS]) -> Self {
+		PerCpu { values }
+	}
+
+	/// Run `f` on this processor's value, with interrupts disabled so no
+	/// handler runs in between
+	pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+		x86_64::instructions::interrupts::without_interrupts(|| f(&self.values[cpu_index()]))
+	}
+}
+
+/// Declare statics with a value per processor, each starting as `init`:
+///
+/// ```ignore
+/// per_cpu! {
+///     static TICKS: Cell<u64> = Cell::new(0);
+/// }
+/// TICKS.with(|ticks| ticks.set(ticks.get() + 1));
+/// ```
+#[macro_export]
+macro_rules! per_cpu {
+	($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+		$(
+			$(#[$attr])*
+			$vis static $name: $crate::percpu::PerCpu<$ty> =
+				$crate::percpu::PerCpu::new([const { $init }; $crate::smp::MAX_CPUS]);
+		)*
+	};
+}
+
+/// Test that each processor's area knows its index
+#[test_case]
+fn test_cpu_areas() {
+	assert!(AREAS.iter().enumerate().all(|(index, area)| area.index == index));
+	assert_eq!(cpu_index(), 0);
+}
@@ -0,0 +1,74 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use crate::sync::Mutex;
+
+/// Maximum number of bytes a pipe buffers before writes are refused
+pub const PIPE_CAPACITY: usize = 16 * 1024;
+
+/// Identifier of an in-kernel pipe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PipeId(pub usize);
+
+/// Table of live pipes and their buffered data
+struct PipeTable {
+	pipes: BTreeMap<PipeId, VecDeque<u8>>,
+	next_id: usize,
+}
+
+static PIPES: Mutex<PipeTable> = Mutex::new(PipeTable {
+	pipes: BTreeMap::new(),
+	next_id: 0,
+});
+
+/// Create an empty pipe
+pub fn create() -> PipeId {
+	let mut table = PIPES.lock();
+	let id = PipeId(table.next_id);
+	table.next_id += 1;
+	table.pipes.insert(id, VecDeque::new());
+	id
+}
+
+/// Read buffered bytes from a pipe, returning how many were read
+///
+/// An empty pipe reads as end-of-file; there is nothing to block on while the
+/// writer runs to completion before the reader starts.
+pub fn read(id: PipeId, buf: &mut [u8]) -> Option<usize> {
+	let mut table = PIPES.lock();
+	let data = table.pipes.get_mut(&id)?;
+	let n = buf.len().min(data.len());
+	for (slot, byte) in buf.iter_mut().zip(data.drain(..n)) {
+		*slot = byte;
+	}
+	Some(n)
+}
+
+/// Append bytes to a pipe, returning how many fit
+pub fn write(id: PipeId, buf: &[u8]) -> Option<usize> {
+	let mut table = PIPES.lock();
+	let data = table.pipes.get_mut(&id)?;
+	let n = buf.len().min(PIPE_CAPACITY - data.len());
+	data.extend(&buf[..n]);
+	Some(n)
+}
+
+/// Free a pipe once neither end is open
+pub fn destroy(id: PipeId) {
+	PIPES.lock().pipes.remove(&id);
+}
+
+/// Test that pipes deliver bytes in order and respect their capacity
+#[test_case]
+fn test_pipe_roundtrip() {
+	let id = create();
+	assert_eq!(write(id, b"hello"), Some(5));
+	let mut buf = [0u8; 3];
+	assert_eq!(read(id, &mut buf), Some(3));
+	assert_eq!(&buf, b"hel");
+	assert_eq!(read(id, &mut buf), Some(2));
+	assert_eq!(&buf[..2], b"lo");
+	assert_eq!(read(id, &mut buf), Some(0));
+	let big = alloc::vec![0u8; PIPE_CAPACITY + 1];
+	assert_eq!(write(id, &big), Some(PIPE_CAPACITY));
+	destroy(id);
+	assert_eq!(read(id, &mut buf), None);
+}
@@ -0,0 +1,173 @@
+use crate::acpi::{self, Fadt};
+use crate::{hlt_loop, klog, memory};
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+/// 8042 keyboard controller status/command port
+const KBC_COMMAND: u16 = 0x64;
+/// Status bit set while the controller's input buffer is full
+const KBC_INPUT_FULL: u8 = 0x02;
+/// Command that pulses the CPU reset line
+const KBC_PULSE_RESET: u8 = 0xfe;
+
+/// PM1 control register fields: SCI_EN is set once the firmware has handed
+/// over to ACPI, and setting SLP_EN enters the sleep state in SLP_TYP
+const PM1_SCI_EN: u16 = 1 << 0;
+const PM1_SLP_TYP: u16 = 0b111 << 10;
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_EN: u16 = 1 << 13;
+/// Sleep state that turns the machine off
+const SOFT_OFF: u8 = 5;
+/// Generic address structure space for memory-mapped registers
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+/// ACPI PM1a control ports and S5 values hard-wired by common emulators,
+/// used when the firmware tables do not say how to power off
+const POWEROFF_PORTS: [(u16, u16); 3] = [
+	// QEMU q35 and recent i440fx machines
+	(0x604, 0x2000),
+	// Bochs and older QEMU
+	(0xb004, 0x2000),
+	// VirtualBox
+	(0x4004, 0x3400),
+];
+
+/// How the machine should go down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+	Halt,
+	Reboot,
+	PowerOff,
+}
+
+/// Spin long enough for the hardware to act on a reset or power-off
+fn settle() {
+	for _ in 0..0x100000 {
+		core::hint::spin_loop();
+	}
+}
+
+/// Write the FADT's reset value to its reset register, if it has one
+fn acpi_reset() {
+	let Some((register, value)) = Fadt::find().and_then(|fadt| Some((fadt.reset_register?, fadt.reset_value))) else {
+		return;
+	};
+	if let Some(port) = register.port() {
+		unsafe { Port::<u8>::new(port).write(value) };
+	} else if register.space == ADDRESS_SPACE_MEMORY {
+		if let Some(virt) = memory::map_mmio(PhysAddr::new(register.address), 1) {
+			unsafe { virt.as_mut_ptr::<u8>().write_volatile(value) };
+		}
+	}
+	settle();
+}
+
+/// Reset the machine through the ACPI reset register, falling back to the
+/// keyboard controller and then a triple fault
+pub fn reboot() -> ! {
+	klog!(Notice, "Restarting system");
+	x86_64::instructions::interrupts::disable();
+	acpi_reset();
+	unsafe {
+		let mut command: Port<u8> = Port::new(KBC_COMMAND);
+		for _ in 0..0x10000 {
+			if command.read() & KBC_INPUT_FULL == 0 {
+				break;
+			}
+			core::hint::spin_loop();
+		}
+		command.write(KBC_PULSE_RESET);
+	}
+	// Give the reset line a moment before giving up on it
+	settle();
+	triple_fault()
+}
+
+/// Force a CPU reset by taking an exception with no usable IDT
+fn triple_fault() -> ! {
+	use x86_64::instructions::tables::{lidt, DescriptorTablePointer};
+	use x86_64::VirtAddr;
+
+	let empty = DescriptorTablePointer { limit: 0, base: VirtAddr::new(0) };
+	unsafe {
+		lidt(&empty);
+		core::arch::asm!("int3", options(nomem, nostack));
+	}
+	hlt_loop()
+}
+
+/// Hand the hardware over to ACPI if the firmware still owns it
+fn enable_acpi(fadt: &Fadt, pm1a: &mut Port<u16>) {
+	if fadt.smi_command == 0 || fadt.acpi_enable == 0 || unsafe { pm1a.read() } & PM1_SCI_EN != 0 {
+		return;
+	}
+	unsafe { Port::<u8>::new(fadt.smi_command).write(fadt.acpi_enable) };
+	for _ in 0..0x100000 {
+		if unsafe { pm1a.read() } & PM1_SCI_EN != 0 {
+			return;
+		}
+		core::hint::spin_loop();
+	}
+	klog!(Warn, "ACPI: firmware did not enter ACPI mode");
+}
+
+/// Enter S5 through the PM1 control registers with the DSDT's `\_S5`
+/// values, returning if the firmware tables do not say how
+fn acpi_poweroff() {
+	let Some(fadt) = Fadt::find() else {
+		return;
+	};
+	let (Some(pm1a), Some((type_a, type_b))) = (fadt.pm1a_control, acpi::sleep_type(SOFT_OFF)) else {
+		klog!(Warn, "ACPI: no PM1a control block or \\_S5 object");
+		return;
+	};
+	let mut pm1a = Port::<u16>::new(pm1a);
+	enable_acpi(fadt, &mut pm1a);
+	let mut pm1b = fadt.pm1b_control.map(Port::<u16>::new);
+	let set_type = |port: &mut Port<u16>, sleep_type: u16| unsafe {
+		let control = port.read() & !PM1_SLP_TYP | sleep_type << PM1_SLP_TYP_SHIFT & PM1_SLP_TYP;
+		port.write(control);
+		control
+	};
+	// Both halves take the sleep type before either is told to sleep
+	let control_a = set_type(&mut pm1a, type_a);
+	let control_b = pm1b.as_mut().map(|port| set_type(port, type_b));
+	unsafe {
+		if let (Some(port), Some(control)) = (pm1b.as_mut(), control_b) {
+			port.write(control | PM1_SLP_EN);
+		}
+		pm1a.write(control_a | PM1_SLP_EN);
+	}
+	settle();
+}
+
+/// Turn the machine off through ACPI, then the ports emulators use, halting
+/// if nothing works
+pub fn poweroff() -> ! {
+	klog!(Notice, "Powering off");
+	x86_64::instructions::interrupts::disable();
+	acpi_poweroff();
+	for (port, value) in POWEROFF_PORTS {
+		unsafe { Port::new(port).write(value) };
+	}
+	// QEMU's isa-debug-exit device, when configured, ends the emulator
+	crate::exit_qemu(crate::QemuExitCode::Success);
+	klog!(Emerg, "Power off failed; system halted");
+	hlt_loop()
+}
+
+/// Stop the CPU without powering off
+pub fn halt() -> ! {
+	klog!(Notice, "System halted");
+	x86_64::instructions::interrupts::disable();
+	hlt_loop()
+}
+
+/// Carry out a power action
+pub fn perform(action: PowerAction) -> ! {
+	match action {
+		PowerAction::Halt => halt(),
+		PowerAction::Reboot => reboot(),
+		PowerAction::PowerOff => poweroff(),
+	}
+}
@@ -1,7 +1,12 @@
-use alloc::{collections::BTreeMap, vec::Vec, string::String};
+use alloc::{collections::BTreeMap, vec::Vec, string::String, sync::Arc};
 use alloc::string::ToString;
-use spin::Mutex;
 use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::audit;
+use crate::fs::FileDescriptor;
+use crate::mount::Namespace;
+use crate::net::{tcp, udp};
+use crate::pipe::PipeId;
+use crate::sync::SpinLockIrqSave;
 
 /// Process identifier
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -21,9 +26,192 @@ pub enum ProcessState {
 	Ready,
 	Running,
 	Blocked,
+	/// Suspended by SIGSTOP/SIGTSTP until SIGCONT
+	Stopped,
 	Terminated,
 }
 
+/// Signals understood by the kernel, numbered as on Linux
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Signal {
+	Hup = 1,
+	Int = 2,
+	Quit = 3,
+	Ill = 4,
+	Trap = 5,
+	Bus = 7,
+	Fpe = 8,
+	Kill = 9,
+	Segv = 11,
+	Term = 15,
+	Cont = 18,
+	Stop = 19,
+	Tstp = 20,
+}
+
+impl Signal {
+	/// All supported signals, in numeric order
+	pub const ALL: [Signal; 13] = [
+		Signal::Hup, Signal::Int, Signal::Quit, Signal::Ill, Signal::Trap,
+		Signal::Bus, Signal::Fpe, Signal::Kill, Signal::Segv, Signal::Term,
+		Signal::Cont, Signal::Stop, Signal::Tstp,
+	];
+
+	/// Look up a signal by number
+	pub fn from_number(number: usize) -> Option<Signal> {
+		Self::ALL.iter().copied().find(|&sig| sig as usize == number)
+	}
+
+	/// Look up a signal by name, with or without the `SIG` prefix
+	pub fn from_name(name: &str) -> Option<Signal> {
+		let name = name.strip_prefix("SIG").unwrap_or(name);
+		Self::ALL.iter().copied().find(|sig| sig.name() == name)
+	}
+
+	/// The signal's name without the `SIG` prefix
+	pub fn name(self) -> &'static str {
+		match self {
+			Signal::Hup => "HUP",
+			Signal::Int => "INT",
+			Signal::Quit => "QUIT",
+			Signal::Ill => "ILL",
+			Signal::Trap => "TRAP",
+			Signal::Bus => "BUS",
+			Signal::Fpe => "FPE",
+			Signal::Kill => "KILL",
+			Signal::Segv => "SEGV",
+			Signal::Term => "TERM",
+			Signal::Cont => "CONT",
+			Signal::Stop => "STOP",
+			Signal::Tstp => "TSTP",
+		}
+	}
+
+	/// Human-readable description, as shown by the shell for finished jobs
+	pub fn description(self) -> &'static str {
+		match self {
+			Signal::Hup => "Hangup",
+			Signal::Int => "Interrupt",
+			Signal::Quit => "Quit",
+			Signal::Ill => "Illegal instruction",
+			Signal::Trap => "Trace/breakpoint trap",
+			Signal::Bus => "Bus error",
+			Signal::Fpe => "Floating point exception",
+			Signal::Kill => "Killed",
+			Signal::Segv => "Segmentation fault",
+			Signal::Term => "Terminated",
+			Signal::Cont => "Continued",
+			Signal::Stop | Signal::Tstp => "Stopped",
+		}
+	}
+}
+
+/// What a process-level file descriptor refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdEntry {
+	/// The system console (keyboard in, screen out)
+	Console,
+	/// An open file description in the file system
+	File(FileDescriptor),
+	/// The read end of a pipe
+	PipeRead(PipeId),
+	/// The write end of a pipe
+	PipeWrite(PipeId),
+	/// A UDP socket
+	UdpSocket(udp::SocketId),
+	/// A TCP socket
+	TcpSocket(tcp::SocketId),
+}
+
+/// Who a process acts as: its user, its group, and the other groups it is in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+	pub uid: u32,
+	pub gid: u32,
+	/// Supplementary groups, not including `gid`
+	pub groups: Vec<u32>,
+}
+
+impl Credentials {
+	/// The superuser, which the kernel's own processes run as
+	pub fn root() -> Credentials {
+		Credentials { uid: 0, gid: 0, groups: Vec::new() }
+	}
+
+	/// Whether these are the superuser's
+	pub fn is_root(&self) -> bool {
+		self.uid == 0
+	}
+
+	/// Whether `gid` is the primary group or one of the others
+	pub fn in_group(&self, gid: u32) -> bool {
+		self.gid == gid || self.groups.contains(&gid)
+	}
+}
+
+/// System calls a filter has a bit for; those numbered higher are denied
+/// once a process has one
+pub const FILTERED_SYSCALLS: usize = 512;
+
+/// Which system calls a process may make: bit `n` allows call `n`
+///
+/// Every call is allowed until a filter is installed, and filters installed
+/// after that can only take calls away, so a sandboxed process and its
+/// children can never get them back. `exit` is always allowed, so one can
+/// still end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyscallFilter {
+	allowed: [u64; FILTERED_SYSCALLS / 64],
+}
+
+impl SyscallFilter {
+	/// The `exit` system call
+	const EXIT: usize = 60;
+
+	/// No filter: every call allowed
+	pub const fn allow_all() -> SyscallFilter {
+		SyscallFilter { allowed: [u64::MAX; FILTERED_SYSCALLS / 64] }
+	}
+
+	/// Allow just the calls listed
+	pub fn allowing(numbers: &[usize]) -> SyscallFilter {
+		let mut filter = SyscallFilter { allowed: [0; FILTERED_SYSCALLS / 64] };
+		for &number in numbers.iter().filter(|&&number| number < FILTERED_SYSCALLS) {
+			filter.allowed[number / 64] |= 1 << (number % 64);
+		}
+		filter
+	}
+
+	/// A bitmap as a program passes one, least significant bit of the first
+	/// byte first; calls past its end are denied
+	pub fn from_bitmap(bitmap: &[u8]) -> SyscallFilter {
+		let mut filter = SyscallFilter { allowed: [0; FILTERED_SYSCALLS / 64] };
+		for (i, &byte) in bitmap.iter().take(FILTERED_SYSCALLS / 8).enumerate() {
+			filter.allowed[i / 8] |= u64::from(byte) << (i % 8 * 8);
+		}
+		filter
+	}
+
+	/// Whether call `number` is allowed
+	pub fn allows(&self, number: usize) -> bool {
+		number == Self::EXIT
+			|| (number < FILTERED_SYSCALLS && self.allowed[number / 64] & 1 << (number % 64) != 0)
+	}
+
+	/// Take away whatever `other` does not allow as well
+	pub fn restrict(&mut self, other: &SyscallFilter) {
+		for (word, other) in self.allowed.iter_mut().zip(other.allowed) {
+			*word &= other;
+		}
+	}
+
+	/// Whether any call has been taken away
+	pub fn is_restricted(&self) -> bool {
+		*self != SyscallFilter::allow_all()
+	}
+}
+
 /// Process control block
 #[derive(Debug, Clone)]
 pub struct Process {
@@ -35,7 +223,21 @@ pub struct Process {
 	pub memory_base: usize,
 	pub memory_size: usize,
 	pub registers: ProcessRegisters,
-	pub open_files: Vec<usize>, // File descriptors
+	pub fd_table: BTreeMap<usize, FdEntry>,
+	pub environ: BTreeMap<String, String>,
+	/// Working directory, as the process sees it inside `root`
+	pub cwd: String,
+	/// Directory the process sees as `/`, before its mounts apply
+	pub root: String,
+	/// Mounts the process sees, shared with the processes it was created
+	/// alongside unless it has a private namespace
+	pub mounts: Arc<Namespace>,
+	pub pgid: ProcessId,
+	pub credentials: Credentials,
+	pub syscall_filter: SyscallFilter,
+	/// Bit `n` is set while signal `n` is waiting to be noticed
+	pub pending_signals: u32,
+	pub exit_status: Option<i32>,
 }
 
 /// Saved process registers
@@ -76,8 +278,9 @@ impl Default for ProcessRegisters {
 impl Process {
 	/// Create a new process
 	pub fn new(name: String, parent_pid: Option<ProcessId>) -> Self {
+		let pid = ProcessId::new();
 		Process {
-			pid: ProcessId::new(),
+			pid,
 			parent_pid,
 			state: ProcessState::Ready,
 			name,
@@ -85,10 +288,55 @@ impl Process {
 			memory_base: 0,
 			memory_size: 0,
 			registers: ProcessRegisters::default(),
-			open_files: Vec::new(),
+			fd_table: Self::standard_fds(),
+			environ: BTreeMap::new(),
+			cwd: "/".to_string(),
+			root: "/".to_string(),
+			mounts: Namespace::new(),
+			pgid: pid,
+			credentials: Credentials::root(),
+			syscall_filter: SyscallFilter::allow_all(),
+			pending_signals: 0,
+			exit_status: None,
 		}
 	}
 
+	/// The default stdin/stdout/stderr table
+	fn standard_fds() -> BTreeMap<usize, FdEntry> {
+		let mut fds = BTreeMap::new();
+		fds.insert(0, FdEntry::Console);
+		fds.insert(1, FdEntry::Console);
+		fds.insert(2, FdEntry::Console);
+		fds
+	}
+
+	/// Look up a file descriptor
+	pub fn fd(&self, fd: usize) -> Option<FdEntry> {
+		self.fd_table.get(&fd).copied()
+	}
+
+	/// Install an entry in the lowest free descriptor slot
+	pub fn alloc_fd(&mut self, entry: FdEntry) -> usize {
+		let fd = (0..).find(|fd| !self.fd_table.contains_key(fd)).unwrap();
+		self.fd_table.insert(fd, entry);
+		fd
+	}
+
+	/// Remove a descriptor, returning what it referred to
+	pub fn close_fd(&mut self, fd: usize) -> Option<FdEntry> {
+		self.fd_table.remove(&fd)
+	}
+
+	/// Install an entry at a specific descriptor, returning the entry it replaced
+	pub fn set_fd(&mut self, fd: usize, entry: FdEntry) -> Option<FdEntry> {
+		self.fd_table.insert(fd, entry)
+	}
+
+	/// Whether any descriptor still refers to `entry`
+	pub fn references(&self, entry: FdEntry) -> bool {
+		self.fd_table.values().any(|&e| e == entry)
+	}
+
 	/// Set process as running
 	pub fn set_running(&mut self) {
 		self.state = ProcessState::Running;
@@ -108,6 +356,48 @@ impl Process {
 	pub fn terminate(&mut self) {
 		self.state = ProcessState::Terminated;
 	}
+
+	/// Apply a signal's default action
+	pub fn deliver(&mut self, signal: Signal) {
+		if self.state == ProcessState::Terminated {
+			return;
+		}
+		match signal {
+			Signal::Stop | Signal::Tstp => self.state = ProcessState::Stopped,
+			Signal::Cont => {
+				if self.state == ProcessState::Stopped {
+					self.state = ProcessState::Ready;
+				}
+			}
+			// Faults are raised by the CPU, so the process cannot go on
+			Signal::Kill | Signal::Ill | Signal::Trap | Signal::Bus | Signal::Fpe | Signal::Segv => {
+				self.exit(128 + signal as i32)
+			}
+			Signal::Hup | Signal::Int | Signal::Quit | Signal::Term => {
+				// Code running on behalf of the process may poll for these and
+				// stop early; the default action still terminates it
+				self.pending_signals |= 1 << signal as u32;
+				self.exit(128 + signal as i32);
+			}
+		}
+	}
+
+	/// Whether `signal` has been delivered, clearing it
+	pub fn take_signal(&mut self, signal: Signal) -> bool {
+		let bit = 1 << signal as u32;
+		let pending = self.pending_signals & bit != 0;
+		self.pending_signals &= !bit;
+		pending
+	}
+
+	/// Terminate with an exit status, leaving the entry to be reaped
+	pub fn exit(&mut self, status: i32) {
+		if self.state != ProcessState::Terminated {
+			audit::record(audit::Kind::Exit, audit::Actor::of(self), format_args!("{} exited with status {}", self.name, status));
+		}
+		self.exit_status = Some(status);
+		self.terminate();
+	}
 }
 
 /// Process scheduler
@@ -217,12 +507,17 @@ impl Scheduler {
 
 	/// List all processes
 	pub fn list_processes(&self) -> Vec<&Process> {
-		self.processes.values().collect()
+		self.processes().collect()
+	}
+
+	/// Every process, by PID, without collecting them
+	pub fn processes(&self) -> impl Iterator<Item = &Process> {
+		self.processes.values()
 	}
 }
 
 /// Global process scheduler
-static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler {
+static SCHEDULER: SpinLockIrqSave<Scheduler> = SpinLockIrqSave::new(Scheduler {
 	processes: BTreeMap::new(),
 	ready_queue: Vec::new(),
 	current_process: None,
@@ -259,20 +554,114 @@ where
 	f(&mut SCHEDULER.lock())
 }
 
+/// Like `with_scheduler`, but gives up rather than wait for the lock, for
+/// the debugger, which may have interrupted its holder
+pub fn try_with_scheduler<F, R>(f: F) -> Option<R>
+where
+	F: FnOnce(&mut Scheduler) -> R,
+{
+	SCHEDULER.try_lock().map(|mut scheduler| f(&mut scheduler))
+}
+
 /// Get the current process ID
 pub fn current_pid() -> Option<ProcessId> {
 	SCHEDULER.lock().current_process
 }
 
-/// Create a new process
+/// Make another existing process the current one, returning whether it exists
+pub fn set_current_pid(pid: ProcessId) -> bool {
+	let mut scheduler = SCHEDULER.lock();
+	let exists = scheduler.processes.contains_key(&pid);
+	if exists {
+		scheduler.current_process = Some(pid);
+	}
+	exists
+}
+
+/// Like `with_current_process`, but gives up rather than wait for the
+/// scheduler lock, for exception handlers that may have interrupted its holder
+pub fn try_with_current_process<F, R>(f: F) -> Option<R>
+where
+	F: FnOnce(&mut Process) -> R,
+{
+	SCHEDULER.try_lock()?.current_process_mut().map(f)
+}
+
+/// Execute a function with access to the current process, if any
+pub fn with_current_process<F, R>(f: F) -> Option<R>
+where
+	F: FnOnce(&mut Process) -> R,
+{
+	SCHEDULER.lock().current_process_mut().map(f)
+}
+
+/// Credentials of the current process; the kernel's when there is none
+pub fn current_credentials() -> Credentials {
+	with_current_process(|p| p.credentials.clone()).unwrap_or_else(Credentials::root)
+}
+
+/// `clone` and `unshare` flag: give the process a mount namespace of its
+/// own, a copy of the one it had
+pub const CLONE_NEWNS: usize = 0x0002_0000;
+
+/// Create a new process, inheriting the parent's environment, working
+/// directory, root, mounts, credentials, and system call filter
 pub fn spawn_process(name: String, parent_pid: Option<ProcessId>) -> ProcessId {
-	let process = Process::new(name, parent_pid);
+	spawn_process_with(name, parent_pid, 0)
+}
+
+/// Create a new process as `spawn_process` does; with `CLONE_NEWNS` in
+/// `flags`, its mounts start as a copy of its parent's instead of shared
+pub fn spawn_process_with(name: String, parent_pid: Option<ProcessId>, flags: usize) -> ProcessId {
+	let mut process = Process::new(name, parent_pid);
 	let pid = process.pid;
 	
-	SCHEDULER.lock().add_process(process);
+	let mut scheduler = SCHEDULER.lock();
+	let mut creator = audit::Actor::KERNEL;
+	if let Some(parent) = parent_pid.and_then(|ppid| scheduler.get_process(ppid)) {
+		creator = audit::Actor::of(parent);
+		process.environ = parent.environ.clone();
+		process.cwd = parent.cwd.clone();
+		process.root = parent.root.clone();
+		process.mounts = if flags & CLONE_NEWNS != 0 { parent.mounts.copy() } else { parent.mounts.clone() };
+		process.pgid = parent.pgid;
+		process.credentials = parent.credentials.clone();
+		process.syscall_filter = parent.syscall_filter.clone();
+	}
+	audit::record(audit::Kind::Spawn, creator, format_args!("created pid {} ({})", pid.0, process.name));
+	scheduler.add_process(process);
 	pid
 }
 
+/// Send a signal to one process, returning whether it exists
+pub fn send_signal(pid: ProcessId, signal: Signal) -> bool {
+	let sender = audit::Actor::current();
+	let mut scheduler = SCHEDULER.lock();
+	let Some(process) = scheduler.get_process_mut(pid) else {
+		return false;
+	};
+	audit::record(audit::Kind::Signal, sender, format_args!("sent SIG{} to pid {} ({})", signal.name(), pid.0, process.name));
+	let was_stopped = process.state == ProcessState::Stopped;
+	process.deliver(signal);
+	if was_stopped && process.state == ProcessState::Ready {
+		scheduler.ready_queue.push(pid);
+	}
+	true
+}
+
+/// Send a signal to every process in a group, returning how many received it
+pub fn signal_group(pgid: ProcessId, signal: Signal) -> usize {
+	let members: Vec<ProcessId> = with_scheduler(|scheduler| {
+		scheduler.processes.values().filter(|p| p.pgid == pgid).map(|p| p.pid).collect()
+	});
+	members.into_iter().filter(|&pid| send_signal(pid, signal)).count()
+}
+
+/// Move a process into a process group
+pub fn set_pgid(pid: ProcessId, pgid: ProcessId) -> bool {
+	with_scheduler(|scheduler| scheduler.get_process_mut(pid).map(|p| p.pgid = pgid).is_some())
+}
+
 /// Terminate a process
 pub fn terminate_process(pid: ProcessId) {
 	SCHEDULER.lock().remove_process(pid);
@@ -281,4 +670,35 @@ pub fn terminate_process(pid: ProcessId) {
 /// Handle timer interrupt for scheduling
 pub fn handle_timer_interrupt() {
 	SCHEDULER.lock().timer_tick();
-} 
\ No newline at end of file
+}
+
+/// Test default signal actions on a process
+#[test_case]
+fn test_signal_delivery() {
+	let mut process = Process::new("test".to_string(), None);
+	process.deliver(Signal::Tstp);
+	assert_eq!(process.state, ProcessState::Stopped);
+	process.deliver(Signal::Cont);
+	assert_eq!(process.state, ProcessState::Ready);
+	process.deliver(Signal::Int);
+	assert_eq!(process.state, ProcessState::Terminated);
+	assert_eq!(process.exit_status, Some(130));
+	assert!(process.take_signal(Signal::Int));
+	assert!(!process.take_signal(Signal::Int));
+	assert_eq!(Signal::from_name("SIGTERM"), Some(Signal::Term));
+	assert_eq!(Signal::from_number(9), Some(Signal::Kill));
+}
+
+/// Test that filters only ever take calls away, and never `exit`
+#[test_case]
+fn test_syscall_filter() {
+	let mut filter = SyscallFilter::allow_all();
+	assert!(!filter.is_restricted());
+	filter.restrict(&SyscallFilter::allowing(&[0, 1, 39]));
+	assert!(filter.is_restricted());
+	assert!(filter.allows(39) && filter.allows(SyscallFilter::EXIT) && !filter.allows(2));
+	filter.restrict(&SyscallFilter::from_bitmap(&[0b1]));
+	assert!(filter.allows(0) && !filter.allows(1) && !filter.allows(39));
+	assert!(!SyscallFilter::allow_all().allows(FILTERED_SYSCALLS));
+	assert_eq!(SyscallFilter::from_bitmap(&[0, 0, 0, 0, 0x80]), SyscallFilter::allowing(&[39]));
+}
@@ -0,0 +1,158 @@
+use crate::sync::Mutex;
+use crate::{ksyms, panic};
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// Frames kept per sample: the interrupted instruction and its callers
+const DEPTH: usize = 8;
+/// Distinct stacks counted; once full, samples of new ones are dropped
+const STACKS: usize = 512;
+
+/// Set while the timer interrupt takes samples
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// A stack, innermost frame first, padded with zeros
+type Stack = [u64; DEPTH];
+
+/// Samples counted by stack, in an open-addressed table
+struct Profile {
+	stacks: [Stack; STACKS],
+	counts: [u64; STACKS],
+	samples: u64,
+	dropped: u64,
+}
+
+impl Profile {
+	const fn new() -> Profile {
+		Profile { stacks: [[0; DEPTH]; STACKS], counts: [0; STACKS], samples: 0, dropped: 0 }
+	}
+
+	/// Forget every sample, in place: the table is too big for the stack
+	fn clear(&mut self) {
+		self.counts.fill(0);
+		self.samples = 0;
+		self.dropped = 0;
+	}
+
+	/// Count one sample of `stack`
+	fn add(&mut self, stack: &Stack) {
+		self.samples += 1;
+		// FNV-1a over the frames
+		let hash = stack.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &frame| (hash ^ frame).wrapping_mul(0x100_0000_01b3));
+		let start = hash as usize % STACKS;
+		for slot in (start..STACKS).chain(0..start) {
+			if self.counts[slot] == 0 {
+				self.stacks[slot] = *stack;
+				self.counts[slot] = 1;
+				return;
+			}
+			if self.stacks[slot] == *stack {
+				self.counts[slot] += 1;
+				return;
+			}
+		}
+		self.dropped += 1;
+	}
+
+	/// Every stack counted, with its count
+	fn stacks(&self) -> impl Iterator<Item = (&Stack, u64)> {
+		self.stacks.iter().zip(self.counts.iter().copied()).filter(|&(_, count)| count != 0)
+	}
+}
+
+static PROFILE: Mutex<Profile> = Mutex::new(Profile::new());
+
+/// Forget earlier samples and start taking them at every timer tick
+pub fn start() {
+	x86_64::instructions::interrupts::without_interrupts(|| PROFILE.lock().clear());
+	RUNNING.store(true, Ordering::Release);
+}
+
+/// Stop taking samples, keeping those taken for `folded`
+pub fn stop() {
+	RUNNING.store(false, Ordering::Release);
+}
+
+/// Whether samples are being taken
+pub fn running() -> bool {
+	RUNNING.load(Ordering::Acquire)
+}
+
+/// Samples taken, and how many of them were dropped with the table full
+pub fn samples() -> (u64, u64) {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let profile = PROFILE.lock();
+		(profile.samples, profile.dropped)
+	})
+}
+
+/// Sample what the timer interrupt interrupted; called from its entry, so
+/// the frame pointers lead from here through the entry into that code
+pub(crate) fn sample(frame: &InterruptStackFrame) {
+	if !running() {
+		return;
+	}
+	let mut stack = [0; DEPTH];
+	stack[0] = frame.instruction_pointer.as_u64();
+	// User code's frame pointers are its own, and may lead anywhere
+	if frame.code_segment & 3 == 0 {
+		// The first return is into the interrupt entry, the next the
+		// interrupted instruction, already in the frame
+		let callers = panic::backtrace(panic::frame_pointer(), panic::cached_is_mapped()).skip(2);
+		for (slot, caller) in stack[1..].iter_mut().zip(callers) {
+			*slot = caller;
+		}
+	}
+	// Skipped rather than waited for while a report reads the table
+	if let Some(mut profile) = PROFILE.try_lock() {
+		profile.add(&stack);
+	}
+}
+
+/// Name a frame for a flame graph: its function, or its address if the
+/// symbol map has none; `;` separates frames there, so it cannot be in a name
+fn frame_name(addr: u64) -> String {
+	match ksyms::resolve(addr) {
+		Some(symbol) => symbol.name.replace(';', ","),
+		None => format!("{:#x}", addr),
+	}
+}
+
+/// A stack as a folded line's frames, outermost first
+fn fold(stack: &Stack, name: impl Fn(u64) -> String) -> String {
+	let names: Vec<String> = stack.iter().rev().filter(|&&frame| frame != 0).map(|&frame| name(frame)).collect();
+	names.join(";")
+}
+
+/// The samples as folded stacks, `outer;...;inner` to a count, with stacks
+/// that only differ within functions merged
+pub fn folded() -> BTreeMap<String, u64> {
+	let counted: Vec<(Stack, u64)> = x86_64::instructions::interrupts::without_interrupts(|| {
+		PROFILE.lock().stacks().map(|(stack, count)| (*stack, count)).collect()
+	});
+	let mut folded = BTreeMap::new();
+	for (stack, count) in counted {
+		*folded.entry(fold(&stack, frame_name)).or_insert(0) += count;
+	}
+	folded
+}
+
+/// Test counting stacks and folding them
+#[test_case]
+fn test_profile() {
+	let mut profile = PROFILE.lock();
+	profile.clear();
+	let mut stack = [0; DEPTH];
+	stack[..3].copy_from_slice(&[0x30, 0x20, 0x10]);
+	profile.add(&stack);
+	profile.add(&stack);
+	stack[0] = 0x31;
+	profile.add(&stack);
+	let mut counts: Vec<u64> = profile.stacks().map(|(_, count)| count).collect();
+	counts.sort();
+	assert_eq!(counts, [1, 2]);
+	assert_eq!(profile.samples, 3);
+	profile.clear();
+	assert_eq!(fold(&stack, |addr| format!("f{:x}", addr)), "f10;f20;f31");
+}
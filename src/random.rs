@@ -0,0 +1,288 @@
+use crate::cpu::{self, Feature};
+use crate::sha256::{self, Sha256};
+use crate::sync::Mutex;
+use crate::task::keyboard;
+use crate::{fs, klog, time};
+use alloc::string::ToString;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Entropy the pool must be credited with before it reseeds the generator
+const RESEED_BITS: u32 = 256;
+/// Most entropy the pool is credited with; more just stirs it
+const POOL_BITS: u32 = 4096;
+/// Times RDSEED is asked for a value before giving up on it
+const RDSEED_RETRIES: usize = 16;
+
+/// `getrandom` flags: fail rather than wait for the generator to be seeded,
+/// and draw from `/dev/random` rather than `/dev/urandom`, which here are
+/// the same once it has been
+pub const GRND_NONBLOCK: usize = 1;
+pub const GRND_RANDOM: usize = 2;
+
+/// Where an entropy sample came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Source {
+	/// When a timer tick arrived, as the time stamp counter measures it
+	Timer = 0,
+	/// When a key was pressed or released
+	Keyboard = 1,
+	/// The processor's generator
+	Cpu = 2,
+}
+
+/// Samples hashed together, with a guess at how much entropy they hold
+struct Pool {
+	hasher: Sha256,
+	/// Bits of entropy credited since the last reseed
+	bits: u32,
+	/// The time stamp counter at the previous timer tick, and the gap before it
+	last_tick: u64,
+	last_gap: u64,
+}
+
+impl Pool {
+	/// Mix in a sample, crediting it with `bits` of entropy
+	fn add(&mut self, source: Source, sample: u64, bits: u32) {
+		self.hasher.update(&[source as u8]);
+		self.hasher.update(&sample.to_le_bytes());
+		self.bits = (self.bits + bits).min(POOL_BITS);
+	}
+
+	/// Mix in when a timer tick arrived: the clocks are in step, so only the
+	/// jitter in the gaps between ticks is any use, and a bit is credited
+	/// only when the gap changed
+	fn add_tick(&mut self, tsc: u64) {
+		let gap = tsc.wrapping_sub(self.last_tick);
+		let bits = u32::from(self.last_tick != 0 && gap != self.last_gap);
+		self.last_tick = tsc;
+		self.last_gap = gap;
+		self.add(Source::Timer, tsc, bits);
+	}
+
+	/// Hash what has been mixed in into a seed, and start over from it
+	fn take_seed(&mut self) -> [u8; sha256::DIGEST_LEN] {
+		let seed = core::mem::take(&mut self.hasher).finish();
+		// Later seeds depend on every earlier sample, not only the ones since
+		self.hasher.update(&sha256::digest(&seed));
+		self.bits = 0;
+		seed
+	}
+}
+
+static POOL: Mutex<Pool> = Mutex::new(Pool { hasher: Sha256::new(), bits: 0, last_tick: 0, last_gap: 0 });
+
+/// The ChaCha20 block function, with a 64-bit counter and nonce
+fn chacha20_block(key: &[u8; 32], counter: u64, nonce: u64) -> [u8; 64] {
+	fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+		state[a] = state[a].wrapping_add(state[b]);
+		state[d] = (state[d] ^ state[a]).rotate_left(16);
+		state[c] = state[c].wrapping_add(state[d]);
+		state[b] = (state[b] ^ state[c]).rotate_left(12);
+		state[a] = state[a].wrapping_add(state[b]);
+		state[d] = (state[d] ^ state[a]).rotate_left(8);
+		state[c] = state[c].wrapping_add(state[d]);
+		state[b] = (state[b] ^ state[c]).rotate_left(7);
+	}
+
+	let mut input = [0u32; 16];
+	// "expand 32-byte k"
+	input[..4].copy_from_slice(&[0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574]);
+	for (word, bytes) in input[4..12].iter_mut().zip(key.chunks_exact(4)) {
+		*word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+	}
+	input[12..].copy_from_slice(&[counter as u32, (counter >> 32) as u32, nonce as u32, (nonce >> 32) as u32]);
+	let mut state = input;
+	for _ in 0..10 {
+		quarter_round(&mut state, 0, 4, 8, 12);
+		quarter_round(&mut state, 1, 5, 9, 13);
+		quarter_round(&mut state, 2, 6, 10, 14);
+		quarter_round(&mut state, 3, 7, 11, 15);
+		quarter_round(&mut state, 0, 5, 10, 15);
+		quarter_round(&mut state, 1, 6, 11, 12);
+		quarter_round(&mut state, 2, 7, 8, 13);
+		quarter_round(&mut state, 3, 4, 9, 14);
+	}
+	let mut block = [0; 64];
+	for ((bytes, word), start) in block.chunks_exact_mut(4).zip(state).zip(input) {
+		bytes.copy_from_slice(&word.wrapping_add(start).to_le_bytes());
+	}
+	block
+}
+
+/// A ChaCha20 keystream whose key is replaced after every request, so what
+/// it gave out cannot be worked back to from its state
+struct Generator {
+	key: [u8; 32],
+	counter: u64,
+}
+
+impl Generator {
+	/// Fill `buffer` from the keystream, then move to a new key
+	fn fill(&mut self, buffer: &mut [u8]) {
+		for chunk in buffer.chunks_mut(64) {
+			let block = chacha20_block(&self.key, self.counter, 0);
+			self.counter += 1;
+			chunk.copy_from_slice(&block[..chunk.len()]);
+		}
+		let block = chacha20_block(&self.key, self.counter, 0);
+		self.key.copy_from_slice(&block[..32]);
+		self.counter = 0;
+	}
+
+	/// Mix a seed from the pool into the key
+	fn reseed(&mut self, seed: &[u8; sha256::DIGEST_LEN]) {
+		let mut hasher = Sha256::new();
+		hasher.update(&self.key);
+		hasher.update(seed);
+		self.key = hasher.finish();
+		self.counter = 0;
+	}
+}
+
+static GENERATOR: Mutex<Generator> = Mutex::new(Generator { key: [0; 32], counter: 0 });
+
+/// Set once the generator has been seeded with `RESEED_BITS` of entropy
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Note when a timer tick arrived; called from the timer interrupt, so it
+/// drops the sample rather than wait for the pool
+pub(crate) fn add_timer_tick() {
+	let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+	if let Some(mut pool) = POOL.try_lock() {
+		pool.add_tick(tsc);
+	}
+}
+
+/// Note when a key interrupt arrived, and the scancode; people type
+/// irregularly enough for a couple of bits each
+pub(crate) fn add_keyboard_timing(scancode: u8) {
+	let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+	if let Some(mut pool) = POOL.try_lock() {
+		pool.add(Source::Keyboard, tsc ^ u64::from(scancode) << 56, 2);
+	}
+}
+
+/// RDSEED, which draws straight from the processor's entropy source, or
+/// `None` without one
+fn rdseed() -> Option<u64> {
+	if !cpu::has(Feature::Rdseed) {
+		return None;
+	}
+	for _ in 0..RDSEED_RETRIES {
+		let mut value = 0;
+		if unsafe { core::arch::x86_64::_rdseed64_step(&mut value) } == 1 {
+			return Some(value);
+		}
+	}
+	None
+}
+
+/// Whether the generator has been seeded with enough entropy for keys
+pub fn ready() -> bool {
+	READY.load(Ordering::Acquire)
+}
+
+/// Reseed the generator if the pool has gathered enough since it last did,
+/// or, until it first has, whenever the pool has anything new
+fn maybe_reseed(generator: &mut Generator) {
+	let seed = x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut pool = POOL.lock();
+		// RDRAND output is mixed in but not credited, since it cannot be checked
+		if let Some(value) = cpu::rdrand() {
+			pool.add(Source::Cpu, value, 0);
+		}
+		let enough = pool.bits >= RESEED_BITS;
+		(enough || (!ready() && pool.bits > 0)).then(|| (pool.take_seed(), enough))
+	});
+	if let Some((seed, enough)) = seed {
+		generator.reseed(&seed);
+		if enough && !READY.swap(true, Ordering::AcqRel) {
+			klog!(Info, "random: generator seeded");
+		}
+	}
+}
+
+/// Fill `buffer` with random bytes, seeded or not; for `/dev/urandom`
+pub fn fill(buffer: &mut [u8]) {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut generator = GENERATOR.lock();
+		maybe_reseed(&mut generator);
+		generator.fill(buffer);
+	});
+}
+
+/// Wait until the generator has been seeded, returning `false` if Ctrl-C
+/// stopped the wait first
+pub fn wait_ready() -> bool {
+	while !ready() {
+		fill(&mut []);
+		if ready() {
+			break;
+		}
+		if keyboard::interrupt_requested() {
+			return false;
+		}
+		x86_64::instructions::hlt();
+	}
+	true
+}
+
+/// Read `/dev/urandom`: as many bytes as asked for, at once
+fn read_urandom(_position: &mut usize, buffer: &mut [u8]) -> usize {
+	fill(buffer);
+	buffer.len()
+}
+
+/// Read `/dev/random`: the same, once the generator is seeded; the wait
+/// holds up the filesystem, but only happens in the seconds after boot
+fn read_random(_position: &mut usize, buffer: &mut [u8]) -> usize {
+	if !wait_ready() {
+		return 0;
+	}
+	fill(buffer);
+	buffer.len()
+}
+
+/// Stir in what is known at boot and whatever the processor's generator
+/// gives, then create `/dev/random` and `/dev/urandom`
+pub fn init() {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut pool = POOL.lock();
+		pool.add(Source::Timer, unsafe { core::arch::x86_64::_rdtsc() }, 0);
+		pool.add(Source::Timer, time::now(), 0);
+		for _ in 0..RESEED_BITS / 64 {
+			match rdseed() {
+				Some(value) => pool.add(Source::Cpu, value, 64),
+				None => break,
+			}
+		}
+	});
+	fill(&mut []);
+	if !ready() {
+		klog!(Info, "random: no RDSEED; seeding from timer and keyboard timing");
+	}
+	fs::with_filesystem(|fs| {
+		let _ = fs.create_directory("/dev".to_string());
+		let _ = fs.create_device("/dev/random".to_string(), read_random);
+		let _ = fs.create_device("/dev/urandom".to_string(), read_urandom);
+	});
+}
+
+/// Test the block function against RFC 7539 and that the key moves on
+#[test_case]
+fn test_chacha20() {
+	let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+	// RFC 7539's counter of 1 and nonce of 00:00:00:09:00:00:00:4a:00:00:00:00
+	let block = chacha20_block(&key, 1 | 0x0900_0000 << 32, 0x4a00_0000);
+	assert_eq!(block[..8], [0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15]);
+	assert_eq!(block[56..], [0xcb, 0xd0, 0x83, 0xe8, 0xa2, 0x50, 0x3c, 0x4e]);
+
+	let mut generator = Generator { key, counter: 0 };
+	let (mut first, mut second) = ([0; 80], [0; 80]);
+	generator.fill(&mut first);
+	assert_ne!(generator.key, key);
+	generator.fill(&mut second);
+	assert_ne!(first, second);
+	assert_eq!(first[..64], chacha20_block(&key, 0, 0));
+}
@@ -0,0 +1,99 @@
+use core::fmt;
+
+/// Fixed-size ring of `N` items, numbered by a running sequence; when full,
+/// the oldest is overwritten
+///
+/// Never allocates, so the logs built on it can be written from interrupt
+/// handlers and before the heap exists.
+pub struct Ring<T, const N: usize> {
+	items: [T; N],
+	/// Sequence number the next item will get
+	next_seq: u64,
+	/// Items currently held, ending at `next_seq`
+	len: usize,
+}
+
+impl<T: Copy, const N: usize> Ring<T, N> {
+	/// An empty ring, its slots holding `empty` until pushed over
+	pub const fn new(empty: T) -> Self {
+		Ring { items: [empty; N], next_seq: 0, len: 0 }
+	}
+
+	/// Add an item, overwriting the oldest when full
+	pub fn push(&mut self, item: T) {
+		self.items[(self.next_seq % N as u64) as usize] = item;
+		self.next_seq += 1;
+		self.len = (self.len + 1).min(N);
+	}
+
+	/// The oldest held item numbered `seq` or later, with its number
+	pub fn read_from(&self, seq: u64) -> Option<(u64, T)> {
+		let seq = seq.max(self.next_seq - self.len as u64);
+		(seq < self.next_seq).then(|| (seq, self.items[(seq % N as u64) as usize]))
+	}
+
+	/// Sequence number the next item will get: how many were ever pushed
+	pub fn next_seq(&self) -> u64 {
+		self.next_seq
+	}
+
+	/// Drop every held item; numbering carries on where it was
+	pub fn clear(&mut self) {
+		self.len = 0;
+	}
+}
+
+/// Text of at most `N` bytes, filled in with `write!`
+#[derive(Clone, Copy)]
+pub struct FixedText<const N: usize> {
+	len: usize,
+	bytes: [u8; N],
+}
+
+impl<const N: usize> FixedText<N> {
+	pub const EMPTY: Self = FixedText { len: 0, bytes: [0; N] };
+
+	pub fn as_str(&self) -> &str {
+		// Only whole characters are ever copied in
+		core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+	}
+}
+
+impl<const N: usize> fmt::Write for FixedText<N> {
+	/// Append text, silently dropping whatever does not fit
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		let mut n = s.len().min(N - self.len);
+		while !s.is_char_boundary(n) {
+			n -= 1;
+		}
+		self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+		self.len += n;
+		Ok(())
+	}
+}
+
+/// Test wrap-around, reads by sequence number, and clearing
+#[test_case]
+fn test_ring() {
+	let mut ring: Ring<usize, 4> = Ring::new(0);
+	for i in 0..6 {
+		ring.push(i);
+	}
+	assert_eq!(ring.read_from(0), Some((2, 2)));
+	assert_eq!(ring.read_from(5), Some((5, 5)));
+	assert_eq!(ring.read_from(6), None);
+	ring.clear();
+	assert_eq!(ring.read_from(0), None);
+	ring.push(6);
+	assert_eq!((ring.read_from(0), ring.next_seq()), (Some((6, 6)), 7));
+}
+
+/// Test that text is cut at a character boundary when it does not fit
+#[test_case]
+fn test_fixed_text() {
+	use core::fmt::Write;
+	let mut text = FixedText::<5>::EMPTY;
+	let _ = write!(text, "{}", "abé");
+	let _ = write!(text, "{}", "éé");
+	assert_eq!(text.as_str(), "abé");
+}
@@ -0,0 +1,283 @@
+use crate::interrupts::{self, InterruptIndex};
+use crate::time::DateTime;
+use crate::{cmdline, klog};
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use crate::sync::Mutex;
+use x86_64::instructions::port::Port;
+
+/// CMOS register index and data ports
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Set in the index byte to keep NMIs masked while the CMOS is addressed
+const NMI_DISABLE: u8 = 0x80;
+
+/// RTC registers
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+/// Century register on most PCs, used unless the ACPI FADT names another
+const REG_CENTURY: u8 = 0x32;
+/// The time registers in the order `decode` takes them, century aside
+const TIME_REGISTERS: [u8; 6] = [REG_SECONDS, REG_MINUTES, REG_HOURS, REG_DAY, REG_MONTH, REG_YEAR];
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+
+/// Status A: an update cycle is in progress and the time registers are unstable
+const UPDATE_IN_PROGRESS: u8 = 0x80;
+/// Status B: halt updates while the time is being set
+const STATUS_B_SET: u8 = 0x80;
+/// Status B: values are binary rather than BCD
+const STATUS_B_BINARY: u8 = 0x04;
+/// Status B: hours are 24-hour rather than 12-hour with a PM bit
+const STATUS_B_24_HOUR: u8 = 0x02;
+/// Status B: raise the periodic and update-ended interrupts
+const STATUS_B_PERIODIC: u8 = 0x40;
+const STATUS_B_UPDATE_ENDED: u8 = 0x10;
+/// Status A: the periodic interrupt's rate divider
+const STATUS_A_RATE: u8 = 0x0f;
+/// Status C, read to acknowledge an interrupt: which ones are pending
+const STATUS_C_PERIODIC: u8 = 0x40;
+const STATUS_C_UPDATE_ENDED: u8 = 0x10;
+/// PM flag in the hours register in 12-hour mode
+const HOUR_PM: u8 = 0x80;
+
+/// The RTC's time base, which the periodic rate divides
+const BASE_HZ: u32 = 32_768;
+/// Rate dividers usable for the periodic interrupt: 8192 Hz down to 2 Hz
+const RATES: core::ops::RangeInclusive<u8> = 3..=15;
+
+/// The time read when the last update ended, or 0 until the update-ended
+/// interrupt is running
+static LAST_UPDATE: AtomicU64 = AtomicU64::new(0);
+/// Periodic interrupts since they were enabled
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+/// CMOS index of the century register, or 0 if the FADT says there is none
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(REG_CENTURY);
+
+/// The CMOS index/data port pair
+struct Cmos {
+	address: Port<u8>,
+	data: Port<u8>,
+}
+
+impl Cmos {
+	/// Read a CMOS register
+	fn read(&mut self, reg: u8) -> u8 {
+		unsafe {
+			self.address.write(NMI_DISABLE | reg);
+			self.data.read()
+		}
+	}
+
+	/// Write a CMOS register
+	fn write(&mut self, reg: u8, value: u8) {
+		unsafe {
+			self.address.write(NMI_DISABLE | reg);
+			self.data.write(value);
+		}
+	}
+
+	/// Read the time registers as they are, century last and 0 without one
+	fn read_registers(&mut self) -> [u8; 7] {
+		let mut raw = [0; 7];
+		for (value, reg) in raw.iter_mut().zip(TIME_REGISTERS) {
+			*value = self.read(reg);
+		}
+		raw[6] = match CENTURY_REGISTER.load(Ordering::Relaxed) {
+			0 => 0,
+			reg => self.read(reg),
+		};
+		raw
+	}
+
+	/// Read the raw time registers once no update is in progress
+	fn read_raw(&mut self) -> [u8; 7] {
+		while self.read(REG_STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+			core::hint::spin_loop();
+		}
+		self.read_registers()
+	}
+}
+
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos {
+	address: Port::new(CMOS_ADDRESS),
+	data: Port::new(CMOS_DATA),
+});
+
+/// Convert a BCD byte to binary
+fn from_bcd(value: u8) -> u8 {
+	(value & 0x0f) + (value >> 4) * 10
+}
+
+/// Convert a binary value below 100 to BCD
+fn to_bcd(value: u8) -> u8 {
+	((value / 10) << 4) | (value % 10)
+}
+
+/// Decode raw time registers in the format described by status register B
+fn decode(raw: [u8; 7], status_b: u8) -> DateTime {
+	let binary = status_b & STATUS_B_BINARY != 0;
+	let convert = |value: u8| if binary { value } else { from_bcd(value) };
+	let [second, minute, hour, day, month, year, century] = raw;
+
+	let pm = status_b & STATUS_B_24_HOUR == 0 && hour & HOUR_PM != 0;
+	let mut hour = convert(hour & !HOUR_PM);
+	if status_b & STATUS_B_24_HOUR == 0 {
+		// 12 AM is hour 0 and 12 PM is hour 12
+		hour = hour % 12 + if pm { 12 } else { 0 };
+	}
+	// Assume the 21st century when there is no usable century register
+	let century = match convert(century) {
+		century @ 19..=99 => century as u16,
+		_ => 20,
+	};
+	DateTime {
+		year: century * 100 + convert(year) as u16,
+		month: convert(month),
+		day: convert(day),
+		hour,
+		minute: convert(minute),
+		second: convert(second),
+	}
+}
+
+/// Read the current date and time from the RTC (as UTC)
+pub fn read() -> DateTime {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut cmos = CMOS.lock();
+		// Read until two passes agree so no field is torn by an update
+		let mut raw = cmos.read_raw();
+		loop {
+			let again = cmos.read_raw();
+			if again == raw {
+				break;
+			}
+			raw = again;
+		}
+		decode(raw, cmos.read(REG_STATUS_B))
+	})
+}
+
+/// The RTC's time as seconds since the Unix epoch, kept by the update-ended
+/// interrupt so that most reads need no port I/O
+pub fn timestamp() -> u64 {
+	match LAST_UPDATE.load(Ordering::Relaxed) {
+		0 => read().timestamp(),
+		timestamp => timestamp,
+	}
+}
+
+/// Periodic interrupts since `rtc_hz=` enabled them
+pub fn periodic_ticks() -> u64 {
+	PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+/// The rate divider for a periodic interrupt at `hz`, which must be a power
+/// of two from 2 to 8192
+fn periodic_rate(hz: u32) -> Option<u8> {
+	RATES.into_iter().find(|rate| BASE_HZ >> (rate - 1) == hz)
+}
+
+/// Turn on the update-ended interrupt, which refreshes the time once a
+/// second, and with `rtc_hz=` the periodic interrupt as a secondary timer
+pub fn init() {
+	if let Some(fadt) = crate::acpi::Fadt::find() {
+		CENTURY_REGISTER.store(fadt.century, Ordering::Relaxed);
+	}
+	let rate = match cmdline::param("rtc_hz").map(|hz| hz.parse().ok().and_then(periodic_rate)) {
+		Some(None) => {
+			klog!(Warn, "rtc: rtc_hz must be a power of two from 2 to 8192");
+			None
+		}
+		Some(rate) => rate,
+		None => None,
+	};
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut cmos = CMOS.lock();
+		if let Some(rate) = rate {
+			let status_a = cmos.read(REG_STATUS_A);
+			cmos.write(REG_STATUS_A, status_a & !STATUS_A_RATE | rate);
+		}
+		let periodic = if rate.is_some() { STATUS_B_PERIODIC } else { 0 };
+		let status_b = cmos.read(REG_STATUS_B);
+		cmos.write(REG_STATUS_B, status_b | STATUS_B_UPDATE_ENDED | periodic);
+		// Clear anything pending, or the RTC never interrupts again
+		cmos.read(REG_STATUS_C);
+	});
+	interrupts::enable_irq(InterruptIndex::Rtc.irq());
+	if let Some(rate) = rate {
+		klog!(Info, "rtc: periodic interrupt at {} Hz", BASE_HZ >> (rate - 1));
+	}
+}
+
+/// Acknowledge an RTC interrupt, counting periodic ones and re-reading the
+/// time when an update has ended
+///
+/// Registered as the handler of the RTC's IRQ.
+pub(crate) fn handle_interrupt() {
+	let mut cmos = CMOS.lock();
+	let pending = cmos.read(REG_STATUS_C);
+	if pending & STATUS_C_PERIODIC != 0 {
+		PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+	}
+	if pending & STATUS_C_UPDATE_ENDED != 0 {
+		// The registers hold still for almost a second after an update
+		let raw = cmos.read_registers();
+		let time = decode(raw, cmos.read(REG_STATUS_B));
+		LAST_UPDATE.store(time.timestamp(), Ordering::Relaxed);
+	}
+}
+
+/// Set the RTC to a date and time, in whatever format it is configured for
+pub fn write(time: &DateTime) {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut cmos = CMOS.lock();
+		let status_b = cmos.read(REG_STATUS_B);
+		let binary = status_b & STATUS_B_BINARY != 0;
+		let convert = |value: u8| if binary { value } else { to_bcd(value) };
+
+		let hour = if status_b & STATUS_B_24_HOUR != 0 {
+			convert(time.hour)
+		} else {
+			let pm = if time.hour >= 12 { HOUR_PM } else { 0 };
+			convert(match time.hour % 12 { 0 => 12, hour => hour }) | pm
+		};
+
+		cmos.write(REG_STATUS_B, status_b | STATUS_B_SET);
+		cmos.write(REG_SECONDS, convert(time.second));
+		cmos.write(REG_MINUTES, convert(time.minute));
+		cmos.write(REG_HOURS, hour);
+		cmos.write(REG_DAY, convert(time.day));
+		cmos.write(REG_MONTH, convert(time.month));
+		cmos.write(REG_YEAR, convert((time.year % 100) as u8));
+		match CENTURY_REGISTER.load(Ordering::Relaxed) {
+			0 => {}
+			reg => cmos.write(reg, convert((time.year / 100) as u8)),
+		}
+		cmos.write(REG_STATUS_B, status_b & !STATUS_B_SET);
+		// Until the next update-ended interrupt, the new time is the latest
+		if LAST_UPDATE.load(Ordering::Relaxed) != 0 {
+			LAST_UPDATE.store(time.timestamp(), Ordering::Relaxed);
+		}
+	});
+}
+
+/// Test decoding BCD and 12-hour register values, and periodic rates
+#[test_case]
+fn test_decode_registers() {
+	let time = decode([0x59, 0x30, 0x12 | HOUR_PM, 0x14, 0x10, 0x26, 0x20], 0);
+	assert_eq!(time, DateTime { year: 2026, month: 10, day: 14, hour: 12, minute: 30, second: 59 });
+	let time = decode([0x00, 0x00, 0x12, 0x01, 0x01, 0x00, 0x00], 0);
+	assert_eq!((time.year, time.hour), (2000, 0));
+	let time = decode([5, 4, 23, 31, 12, 99, 19], STATUS_B_BINARY | STATUS_B_24_HOUR);
+	assert_eq!(time, DateTime { year: 1999, month: 12, day: 31, hour: 23, minute: 4, second: 5 });
+	assert_eq!(to_bcd(59), 0x59);
+	assert_eq!(periodic_rate(1024), Some(6));
+	assert_eq!(periodic_rate(8192), Some(3));
+	assert_eq!(periodic_rate(2), Some(15));
+	assert_eq!(periodic_rate(1000), None);
+}
@@ -1,22 +1,75 @@
+use crate::console::Console;
+use crate::cp437;
+use crate::vga_buffer::Color;
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt::Write;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
 use uart_16550::SerialPort;
-use spin::Mutex;
+use x86_64::instructions::port::Port;
+use crate::sync::SpinLockIrqSave;
 use lazy_static::lazy_static;
 
+/// I/O base of the first serial port
+pub(crate) const COM1: u16 = 0x3f8;
+/// Line status register, and its bit set while a received byte is waiting
+const LINE_STATUS: u16 = COM1 + 5;
+const DATA_READY: u8 = 0x01;
+
 lazy_static! {
 	/// Serial port 1 for debugging output
-	pub static ref SERIAL1: Mutex<SerialPort> = {
-		let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+	pub static ref SERIAL1: SpinLockIrqSave<SerialPort> = {
+		let mut serial_port = unsafe { SerialPort::new(COM1) };
 		serial_port.init();
-		Mutex::new(serial_port)
+		SpinLockIrqSave::new(serial_port)
 	};
 }
 
+/// Set while a program has the serial line to itself for binary transfers
+static RAW_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Give the serial line to a program for a binary transfer, or hand it back
+///
+/// While it is raw, received bytes are not treated as keys and other output
+/// is held back or dropped, so nothing gets mixed into the transfer.
+pub fn set_raw_mode(raw: bool) {
+	RAW_MODE.store(raw, Ordering::Relaxed);
+}
+
+/// Whether a program has the serial line to itself
+pub fn raw_mode() -> bool {
+	RAW_MODE.load(Ordering::Relaxed)
+}
+
+/// Send bytes as they are, for a program in raw mode
+pub fn write_raw(bytes: &[u8]) {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut port = SERIAL1.lock();
+		for &byte in bytes {
+			port.send_raw(byte);
+		}
+	});
+}
+
+/// Take a received byte from the first serial port, if one is waiting
+///
+/// Reads the port directly rather than through `SERIAL1`, so the interrupt
+/// handler never waits for the lock.
+pub(crate) fn read_byte() -> Option<u8> {
+	let mut status: Port<u8> = Port::new(LINE_STATUS);
+	let mut data: Port<u8> = Port::new(COM1);
+	unsafe { (status.read() & DATA_READY != 0).then(|| data.read()) }
+}
+
 /// Internal print function for serial output
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
-	use core::fmt::Write;
 	use x86_64::instructions::interrupts;
 
+	// The kernel log keeps what is dropped during a transfer
+	if raw_mode() {
+		return;
+	}
 	interrupts::without_interrupts(|| {
 		SERIAL1.lock().write_fmt(args).expect("Printing to serial failed");
 	});
@@ -35,4 +88,121 @@ macro_rules! serial_print {
 macro_rules! serial_println {
 	() => ($crate::serial_print!("\n"));
 	($($arg:tt)*) => ($crate::serial_print!("{}\n", format_args!($($arg)*)));
-} 
\ No newline at end of file
+} 
+/// A terminal on the serial port drawn as a console display, by moving its
+/// cursor and setting its colors with ANSI escape sequences
+pub struct SerialConsole {
+	rows: usize,
+	cols: usize,
+	/// What the terminal shows in every cell, so unchanged cells are skipped
+	shown: Vec<Option<(u8, Color, Color)>>,
+	/// Where the terminal's cursor is, if known
+	position: Option<(usize, usize)>,
+	/// Colors the terminal draws in, if known
+	colors: Option<(Color, Color)>,
+	/// Escape sequences and text not sent yet
+	output: String,
+}
+
+impl SerialConsole {
+	/// A console of `(rows, cols)` cells; every cell is sent the first time
+	/// it is drawn, covering whatever the terminal showed before
+	pub fn new((rows, cols): (usize, usize)) -> SerialConsole {
+		SerialConsole {
+			rows,
+			cols,
+			shown: vec![None; rows * cols],
+			position: None,
+			colors: None,
+			output: String::new(),
+		}
+	}
+
+	/// Send what has been drawn to the serial port, unless a transfer has it
+	fn flush(&mut self) {
+		if raw_mode() {
+			return;
+		}
+		SERIAL1.lock().write_str(&self.output).expect("Printing to serial failed");
+		self.output.clear();
+	}
+
+	/// Move the terminal's cursor unless it is already there
+	fn move_to(&mut self, row: usize, col: usize) {
+		if self.position != Some((row, col)) {
+			let _ = write!(self.output, "\x1b[{};{}H", row + 1, col + 1);
+			self.position = Some((row, col));
+		}
+	}
+
+	/// Queue a cell's character, in its colors, without sending it
+	fn put(&mut self, row: usize, col: usize, byte: u8, foreground: Color, background: Color) {
+		let cell = Some((byte, foreground, background));
+		if row >= self.rows || col >= self.cols || self.shown[row * self.cols + col] == cell {
+			return;
+		}
+		self.shown[row * self.cols + col] = cell;
+		self.move_to(row, col);
+		if self.colors != Some((foreground, background)) {
+			let (fg, bg) = (foreground.ansi_code(), background.ansi_code());
+			let fg = if fg < 8 { 30 + fg } else { 90 + fg - 8 };
+			let bg = if bg < 8 { 40 + bg } else { 100 + bg - 8 };
+			let _ = write!(self.output, "\x1b[{};{}m", fg, bg);
+			self.colors = Some((foreground, background));
+		}
+		self.output.push(cp437::decode(byte));
+		// Terminals differ at the right margin, so move explicitly from there
+		self.position = (col + 1 < self.cols).then_some((row, col + 1));
+	}
+}
+
+impl Console for SerialConsole {
+	fn size(&self) -> (usize, usize) {
+		(self.rows, self.cols)
+	}
+
+	fn draw(&mut self, row: usize, col: usize, byte: u8, foreground: Color, background: Color) {
+		self.put(row, col, byte, foreground, background);
+		self.flush();
+	}
+
+	fn scroll_up(&mut self, rows: Range<usize>) {
+		let rows = rows.start..rows.end.min(self.rows);
+		if rows.len() < 2 {
+			return;
+		}
+		// Scroll the terminal itself inside a region of the same rows, so the
+		// lines that leave the top stay in its history
+		let _ = write!(self.output, "\x1b[{};{}r\x1b[{};1H\n\x1b[r", rows.start + 1, rows.end, rows.end);
+		self.position = None;
+		self.shown.copy_within((rows.start + 1) * self.cols..rows.end * self.cols, rows.start * self.cols);
+		// The new bottom row is blank in whatever colors the terminal uses
+		self.shown[(rows.end - 1) * self.cols..rows.end * self.cols].fill(None);
+		self.flush();
+	}
+
+	fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+		match cursor {
+			Some((row, col)) => {
+				self.move_to(row, col);
+				self.output.push_str("\x1b[?25h");
+			}
+			None => self.output.push_str("\x1b[?25l"),
+		}
+		self.flush();
+	}
+}
+
+/// Test the escape sequences a serial console sends for drawing
+#[test_case]
+fn test_serial_console() {
+	let mut console = SerialConsole::new((2, 3));
+	console.put(0, 0, b'a', Color::LightGray, Color::Black);
+	console.put(0, 1, b'b', Color::LightGray, Color::Black);
+	console.put(1, 2, 0xc9, Color::White, Color::Blue);
+	assert_eq!(console.output, "\x1b[1;1H\x1b[37;40mab\x1b[2;3H\x1b[97;44m╔");
+	console.output.clear();
+	console.put(0, 1, b'b', Color::LightGray, Color::Black);
+	assert_eq!(console.output, "");
+	console.flush();
+}
@@ -0,0 +1,545 @@
+use super::{copy_fd, is_var_name, NoMatch, Shell};
+use super::editor::Editor;
+use crate::fs::{FileType, O_RDONLY};
+use crate::println;
+use crate::syscall;
+use alloc::string::String;
+
+/// Names of all shell builtins
+pub const BUILTINS: &[&str] = &[
+	"help", "clear", "color", "keymap", "kbdrate", "stty", "echo", "cat", "ls", "touch", "mkdir", "rm", "cp", "mv", "chmod", "cd", "pwd",
+	"grep", "head", "tail", "wc", "sort", "hexdump", "edit", "snake",
+	"jobs", "fg", "bg", "kill", "tasks", "sym", "profile", "trace",
+	"date", "hwclock", "dmesg", "auditlog", "lspci", "cpuinfo", "rx", "uname", "whoami", "id", "su", "chroot", "unshare", "mount", "umount", "uptime", "memory", "version",
+	"ifconfig", "netstat", "tcpdump", "ping", "nslookup", "wget",
+	"history", "set", "export", "unset", "env", "alias", "unalias", "which", "type", "sh", "source", ".", "true", "false", "[", "test",
+	"exit", "reboot", "shutdown",
+];
+
+/// Whether `name` can be defined as an alias: non-empty, with no characters
+/// the parser would treat specially
+fn is_alias_name(name: &str) -> bool {
+	!name.is_empty() && name.chars().all(|c| !c.is_whitespace() && !"'\"\\$;&|<>/".contains(c))
+}
+
+/// Ask the kernel to go down, reporting failure if it refuses
+fn power_down(cmd: usize) -> i32 {
+	match syscall::sys_reboot(cmd) {
+		Ok(_) => 0,
+		Err(err) => {
+			errln!("reboot: {}", err.as_str());
+			1
+		}
+	}
+}
+
+impl Shell {
+	/// Run a builtin, returning its exit status, or `None` if `cmd` is not a builtin
+	pub(super) fn run_builtin(&mut self, cmd: &str, args: &[&str]) -> Option<i32> {
+		let status = match cmd {
+			"help" => self.cmd_help(),
+			"clear" => self.cmd_clear(),
+			"color" => self.cmd_color(args),
+			"keymap" => self.cmd_keymap(args),
+			"kbdrate" => self.cmd_kbdrate(args),
+			"stty" => self.cmd_stty(args),
+			"echo" => self.cmd_echo(args),
+			"cat" => self.cmd_cat(args),
+			"ls" => self.cmd_ls(args),
+			"touch" => self.cmd_touch(args),
+			"mkdir" => self.cmd_mkdir(args),
+			"rm" => self.cmd_rm(args),
+			"cp" => self.cmd_cp(args),
+			"mv" => self.cmd_mv(args),
+			"chmod" => self.cmd_chmod(args),
+			"cd" => self.cmd_cd(args),
+			"pwd" => self.cmd_pwd(),
+			"grep" => self.cmd_grep(args),
+			"head" => self.cmd_head(args),
+			"tail" => self.cmd_tail(args),
+			"wc" => self.cmd_wc(args),
+			"sort" => self.cmd_sort(args),
+			"hexdump" => self.cmd_hexdump(args),
+			"edit" => self.cmd_edit(args),
+			"snake" => self.cmd_snake(args),
+			"jobs" => self.cmd_jobs(),
+			"fg" => self.cmd_fg(args),
+			"bg" => self.cmd_bg(args),
+			"kill" => self.cmd_kill(args),
+			"tasks" => self.cmd_tasks(),
+			"sym" => self.cmd_sym(args),
+			"profile" => self.cmd_profile(args),
+			"trace" => self.cmd_trace(args),
+			#[cfg(feature = "kernel-debug")]
+			"peek" => self.cmd_peek(args),
+			#[cfg(feature = "kernel-debug")]
+			"poke" => self.cmd_poke(args),
+			"date" => self.cmd_date(args),
+			"hwclock" => self.cmd_hwclock(args),
+			"dmesg" => self.cmd_dmesg(args),
+			"auditlog" => self.cmd_auditlog(args),
+			"lspci" => self.cmd_lspci(args),
+			"cpuinfo" => self.cmd_cpuinfo(),
+			"rx" => self.cmd_rx(args),
+			"ifconfig" => self.cmd_ifconfig(args),
+			"netstat" => self.cmd_netstat(args),
+			"tcpdump" => self.cmd_tcpdump(args),
+			"ping" => self.cmd_ping(args),
+			"nslookup" => self.cmd_nslookup(args),
+			"wget" => self.cmd_wget(args),
+			"uname" => self.cmd_uname(),
+			"whoami" => self.cmd_whoami(),
+			"id" => self.cmd_id(),
+			"su" => self.cmd_su(args),
+			"chroot" => self.cmd_chroot(args),
+			"unshare" => self.cmd_unshare(args),
+			"mount" => self.cmd_mount(args),
+			"umount" => self.cmd_umount(args),
+			"uptime" => self.cmd_uptime(),
+			"memory" => self.cmd_memory(),
+			"version" => self.cmd_version(),
+			"history" => self.cmd_history(),
+			"set" => self.cmd_set(args),
+			"export" => self.cmd_export(args),
+			"unset" => self.cmd_unset(args),
+			"env" => self.cmd_env(),
+			"which" => self.cmd_which(args),
+			"type" => self.cmd_type(args),
+			"alias" => self.cmd_alias(args),
+			"unalias" => self.cmd_unalias(args),
+			"sh" | "source" | "." => self.cmd_sh(args),
+			"true" => 0,
+			"false" => 1,
+			"[" => self.cmd_bracket(args),
+			"test" => self.cmd_test(args),
+			"exit" => self.cmd_exit(),
+			"reboot" => self.cmd_reboot(),
+			"shutdown" => self.cmd_shutdown(args),
+			_ => return None,
+		};
+		Some(status)
+	}
+
+	/// Show help information
+	fn cmd_help(&self) -> i32 {
+		outln!("ScottOS Shell - Available Commands:");
+		outln!("  help      - Show this help message");
+		outln!("  clear     - Clear the screen");
+		outln!("  color     - Set console colors (color FG [BG], -t THEME, -l list)");
+		outln!("  keymap    - Show or change the keyboard layout (keymap [LAYOUT])");
+		outln!("  kbdrate   - Set the key repeat rate and delay (kbdrate [-s] [-r RATE] [-d DELAY])");
+		outln!("  stty      - Show or change terminal modes (stty [sane|raw|[-]icanon|[-]echo]...)");
+		outln!("  echo      - Echo arguments to the screen");
+		outln!("  cat       - Print files (or standard input)");
+		outln!("  ls        - List directory contents (-l long format, -a show hidden)");
+		outln!("  touch     - Create empty files");
+		outln!("  mkdir     - Create directories (-p create parents)");
+		outln!("  rm        - Remove files (-r recursive, -f ignore missing)");
+		outln!("  cp        - Copy files (-r recursive)");
+		outln!("  mv        - Move or rename files");
+		outln!("  chmod     - Change file permissions (chmod 755 FILE, chmod +x FILE)");
+		outln!("  cd        - Change the working directory");
+		outln!("  pwd       - Print the working directory");
+		outln!("  grep      - Print matching lines (-i, -v, -n, -c)");
+		outln!("  head      - Print the first lines of input (-n N)");
+		outln!("  tail      - Print the last lines of input (-n N)");
+		outln!("  wc        - Count lines, words, and bytes (-l, -w, -c)");
+		outln!("  sort      - Sort lines (-r reverse, -n numeric, -u unique)");
+		outln!("  hexdump   - Dump a file or memory address as hex (hexdump <path|0xaddr> [len])");
+		outln!("  edit      - Edit a file full-screen (Ctrl-S save, Ctrl-Q quit, Ctrl-Z suspend)");
+		outln!("  snake     - Play snake (arrows or WASD steer, p pause, q quit)");
+		outln!("  jobs      - List background and stopped jobs (run a command with & to background it)");
+		outln!("  fg        - Continue a job in the foreground (fg [%N])");
+		outln!("  bg        - Continue a stopped job in the background (bg [%N])");
+		outln!("  kill      - Send a signal to a job or process (kill [-SIG] %N|PID, kill -l)");
+		outln!("  tasks     - List kernel tasks with their state, polls, and poll time");
+		outln!("  sym       - Name the kernel functions addresses are in (sym ADDR...)");
+		outln!("  profile   - Sample the kernel (profile start|stop|report [-n N]); folded stacks go to serial");
+		outln!("  trace     - Trace kernel events (trace [on|off SUBSYS...|all] | dump | clear)");
+		#[cfg(feature = "kernel-debug")]
+		{
+			outln!("  peek      - Read memory (peek [-p] ADDR [1|2|4|8])");
+			outln!("  poke      - Write memory (poke [-p] ADDR VALUE [1|2|4|8])");
+		}
+		outln!("  date      - Print or set the time (date [+FORMAT], date -s 'YYYY-MM-DD HH:MM:SS')");
+		outln!("  hwclock   - Read or set the hardware clock (-r show, -s to system, -w from system)");
+		outln!("  dmesg     - Show the kernel log (-l LEVEL filter, -x show levels, -c clear)");
+		outln!("  auditlog  - Show denials, signals, process starts and exits, and mounts, as root (auditlog [KIND...])");
+		outln!("  lspci     - List PCI devices (-n numeric, -v show BARs and IRQs, -k drivers)");
+		outln!("  cpuinfo   - Show the processor model and CPU features");
+		outln!("  rx        - Receive a file over the serial port with XMODEM (rx <path>)");
+		outln!("  ifconfig  - Show or configure interfaces (ifconfig [IFACE [ADDR/PREFIX[,GW] | off | up | down]])");
+		outln!("  netstat   - Show sockets (-a all, -l listening, -t TCP, -u UDP) or interfaces (-i)");
+		outln!("  tcpdump   - Print frames as they pass (-i IFACE, -c COUNT, -x hex); also /dev/pcap");
+		outln!("  ping      - Send ICMP echo requests and show round-trip times (ping HOST [-c COUNT])");
+		outln!("  nslookup  - Look up the addresses of a host name (nslookup NAME [SERVER])");
+		outln!("  wget      - Download a file over HTTP (wget URL [PATH])");
+		outln!("  uname     - Show system information");
+		outln!("  whoami    - Show current user");
+		outln!("  id        - Show the current user and groups");
+		outln!("  su        - Run as another user until exit (su [-] [USER], root by default)");
+		outln!("  chroot    - Make a directory the root until exit, as root (chroot DIR)");
+		outln!("  unshare   - Keep mounts made until exit to this shell, as root (unshare -m)");
+		outln!("  mount     - List mounts, or bind a directory over another as root (mount [--bind SOURCE TARGET])");
+		outln!("  umount    - Undo the latest mount on a directory, as root (umount TARGET)");
+		outln!("  uptime    - Show system uptime (placeholder)");
+		outln!("  memory    - Show memory information (placeholder)");
+		outln!("  version   - Show ScottOS version");
+		outln!("  history   - Show command history");
+		outln!("  set       - List or set shell variables (set NAME=value, set -o OPTION)");
+		outln!("  export    - Export variables to spawned processes");
+		outln!("  unset     - Remove shell variables");
+		outln!("  env       - Show exported environment variables");
+		outln!("  alias     - List or define command aliases (alias ll='ls -l')");
+		outln!("  unalias   - Remove command aliases (-a removes all)");
+		outln!("  which     - Show where a command is found in $PATH (-a all matches)");
+		outln!("  type      - Show whether a name is an alias, keyword, builtin, or file");
+		outln!("  sh        - Run a shell script (sh <path> [ARG...], also source and .)");
+		outln!("  [ ... ]   - Evaluate a condition (-f, -d, -e, -z, -n, =, !=)");
+		outln!("  true      - Succeed (exit status 0)");
+		outln!("  false     - Fail (exit status 1)");
+		outln!("  test      - Run various tests");
+		outln!("  exit      - Leave su, chroot or unshare, or exit the shell (power off)");
+		outln!("  reboot    - Reboot the system");
+		outln!("  shutdown  - Power off (-P), halt (-H), or reboot (-r) the system");
+		outln!("Keys: Ctrl-C interrupt, Ctrl-L clear screen, Ctrl-D exit (at an empty prompt), Shift-PgUp/PgDn scroll,");
+		outln!("      Alt-F1..F4 switch console");
+		0
+	}
+
+	/// Clear the screen
+	fn cmd_clear(&self) -> i32 {
+		x86_64::instructions::interrupts::without_interrupts(|| {
+			crate::vga_buffer::WRITER.lock().clear_screen();
+		});
+		0
+	}
+
+	/// Echo command - print arguments
+	fn cmd_echo(&self, args: &[&str]) -> i32 {
+		outln!("{}", args.join(" "));
+		0
+	}
+
+	/// Concatenate files (or standard input) to standard output
+	fn cmd_cat(&self, args: &[&str]) -> i32 {
+		if args.is_empty() {
+			copy_fd(0);
+			return 0;
+		}
+		let mut status = 0;
+		for path in args {
+			match syscall::sys_open(path, O_RDONLY, 0) {
+				Ok(fd) => {
+					copy_fd(fd);
+					let _ = syscall::sys_close(fd);
+				}
+				Err(err) => {
+					errln!("cat: {}: {}", path, err.as_str());
+					status = 1;
+				}
+			}
+		}
+		status
+	}
+
+	/// Open a file in the full-screen editor
+	fn cmd_edit(&mut self, args: &[&str]) -> i32 {
+		let [path] = args else {
+			errln!("usage: edit <path>");
+			return 2;
+		};
+		match Editor::open(path) {
+			Ok(editor) => {
+				editor.render();
+				self.editor = Some(editor);
+				0
+			}
+			Err(err) => {
+				errln!("edit: {}: {}", path, err.as_str());
+				1
+			}
+		}
+	}
+
+	/// Show system information
+	fn cmd_uname(&self) -> i32 {
+		outln!("ScottOS x86_64");
+		0
+	}
+
+	/// Show system uptime (placeholder)
+	fn cmd_uptime(&self) -> i32 {
+		outln!("System uptime: Running since boot (timer not implemented)");
+		0
+	}
+
+	/// Show memory information (placeholder)
+	fn cmd_memory(&self) -> i32 {
+		outln!("Memory usage: Basic allocator active (detailed stats not implemented)");
+		0
+	}
+
+	/// Show ScottOS version
+	fn cmd_version(&self) -> i32 {
+		outln!("ScottOS v0.1.0 - A minimalist POSIX-compliant operating system");
+		outln!("Built with Rust (nightly)");
+		outln!("Target: x86_64-scottos");
+		0
+	}
+
+	/// Show command history
+	fn cmd_history(&self) -> i32 {
+		outln!("Command history:");
+		for i in 0..self.history_count {
+			let cmd_bytes = &self.command_history[i];
+			// Find the end of the command (first null byte)
+			let mut len = 0;
+			for &byte in cmd_bytes {
+				if byte == 0 { break; }
+				len += 1;
+			}
+			if len > 0 {
+				if let Ok(cmd_str) = core::str::from_utf8(&cmd_bytes[..len]) {
+					outln!("  {}: {}", i + 1, cmd_str);
+				}
+			}
+		}
+		0
+	}
+
+	/// List all shell variables, assign `NAME=value` pairs, or toggle
+	/// options with `-o`/`+o`
+	fn cmd_set(&mut self, args: &[&str]) -> i32 {
+		match args {
+			["-o"] | ["+o"] => {
+				outln!("nullglob\t{}", if self.glob_nomatch == NoMatch::Null { "on" } else { "off" });
+				outln!("failglob\t{}", if self.glob_nomatch == NoMatch::Fail { "on" } else { "off" });
+				return 0;
+			}
+			[flag @ ("-o" | "+o"), option] => {
+				let enable = *flag == "-o";
+				let mode = match *option {
+					"nullglob" => NoMatch::Null,
+					"failglob" => NoMatch::Fail,
+					_ => {
+						errln!("set: unknown option: {}", option);
+						return 1;
+					}
+				};
+				if enable {
+					self.glob_nomatch = mode;
+				} else if self.glob_nomatch == mode {
+					self.glob_nomatch = NoMatch::Keep;
+				}
+				return 0;
+			}
+			_ => {}
+		}
+		if args.is_empty() {
+			for (name, var) in &self.vars {
+				outln!("{}={}", name, var.value);
+			}
+			return 0;
+		}
+		let mut status = 0;
+		for assignment in args {
+			match assignment.split_once('=') {
+				Some((name, value)) if is_var_name(name) => self.set_var(name, value, false),
+				_ => {
+					errln!("set: invalid assignment: {}", assignment);
+					status = 1;
+				}
+			}
+		}
+		status
+	}
+
+	/// Mark variables as exported, optionally assigning them
+	fn cmd_export(&mut self, args: &[&str]) -> i32 {
+		if args.is_empty() {
+			return self.cmd_env();
+		}
+		let mut status = 0;
+		for &word in args {
+			let (name, value) = match word.split_once('=') {
+				Some((name, value)) => (name, String::from(value)),
+				None => (word, String::from(self.var(word).unwrap_or(""))),
+			};
+			if is_var_name(name) {
+				self.set_var(name, &value, true);
+			} else {
+				errln!("export: not a valid identifier: {}", name);
+				status = 1;
+			}
+		}
+		status
+	}
+
+	/// Remove variables from the shell
+	fn cmd_unset(&mut self, args: &[&str]) -> i32 {
+		for name in args {
+			self.unset_var(name);
+		}
+		0
+	}
+
+	/// Print the exported environment
+	fn cmd_env(&self) -> i32 {
+		for (name, var) in self.vars.iter().filter(|(_, var)| var.exported) {
+			outln!("{}={}", name, var.value);
+		}
+		0
+	}
+
+	/// List aliases, show the named ones, or define `NAME=VALUE` aliases
+	fn cmd_alias(&mut self, args: &[&str]) -> i32 {
+		let print = |name: &str, value: &str| outln!("alias {}='{}'", name, value.replace('\'', "'\\''"));
+		if args.is_empty() {
+			for (name, value) in &self.aliases {
+				print(name, value);
+			}
+			return 0;
+		}
+		let mut status = 0;
+		for &arg in args {
+			match arg.split_once('=') {
+				Some((name, value)) if is_alias_name(name) => {
+					self.aliases.insert(String::from(name), String::from(value));
+				}
+				Some((name, _)) => {
+					errln!("alias: invalid alias name: {}", name);
+					status = 1;
+				}
+				None => match self.aliases.get(arg) {
+					Some(value) => print(arg, value),
+					None => {
+						errln!("alias: {}: not found", arg);
+						status = 1;
+					}
+				},
+			}
+		}
+		status
+	}
+
+	/// Remove aliases (`-a` removes all of them)
+	fn cmd_unalias(&mut self, args: &[&str]) -> i32 {
+		if args.is_empty() {
+			errln!("usage: unalias [-a] NAME...");
+			return 2;
+		}
+		if args.contains(&"-a") {
+			self.aliases.clear();
+			return 0;
+		}
+		let mut status = 0;
+		for &name in args {
+			if self.aliases.remove(name).is_none() {
+				errln!("unalias: {}: not found", name);
+				status = 1;
+			}
+		}
+		status
+	}
+
+	/// Run a script file from the file system, passing it any further arguments
+	fn cmd_sh(&mut self, args: &[&str]) -> i32 {
+		match args.split_first() {
+			Some((path, rest)) => self.run_script_as(path, rest),
+			None => {
+				errln!("usage: sh <path> [ARG...]");
+				2
+			}
+		}
+	}
+
+	/// Evaluate a `[ ... ]` condition, returning 0 when it holds
+	fn cmd_bracket(&self, args: &[&str]) -> i32 {
+		let Some((&"]", words)) = args.split_last() else {
+			errln!("[: missing ]");
+			return 2;
+		};
+		let (negate, words) = match words.split_first() {
+			Some((&"!", rest)) => (true, rest),
+			_ => (false, words),
+		};
+
+		let file_type = |path: &str| {
+			let path = syscall::resolve_path(path);
+			crate::fs::with_filesystem(|fs| fs.stat(&path)).ok().map(|m| m.file_type)
+		};
+		let result = match *words {
+			[] => false,
+			[s] => !s.is_empty(),
+			["-z", s] => s.is_empty(),
+			["-n", s] => !s.is_empty(),
+			["-e", path] => file_type(path).is_some(),
+			["-f", path] => file_type(path) == Some(FileType::Regular),
+			["-d", path] => file_type(path) == Some(FileType::Directory),
+			[a, "=", b] => a == b,
+			[a, "!=", b] => a != b,
+			_ => {
+				errln!("[: unsupported expression: {}", words.join(" "));
+				return 2;
+			}
+		};
+		if result != negate { 0 } else { 1 }
+	}
+
+	/// Run various tests
+	fn cmd_test(&self, args: &[&str]) -> i32 {
+		let args = args.join(" ");
+		if args.is_empty() {
+			outln!("Available tests: keyboard, interrupts");
+			return 0;
+		}
+
+		match args.as_str() {
+			"keyboard" => {
+				outln!("Keyboard test: Type some characters, they should appear on screen");
+			}
+			"interrupts" => {
+				outln!("Testing interrupts...");
+				// Trigger a breakpoint to test interrupt handling
+				x86_64::instructions::interrupts::int3();
+				outln!("Breakpoint interrupt handled successfully!");
+			}
+			_ => {
+				outln!("Unknown test: {}", args);
+				return 1;
+			}
+		}
+		0
+	}
+
+	/// Go back from `su`, or else exit the shell (halt the system)
+	fn cmd_exit(&mut self) -> i32 {
+		if self.end_session() {
+			return 0;
+		}
+		println!("Shutting down ScottOS...");
+		println!("Thank you for using ScottOS!");
+		power_down(syscall::REBOOT_CMD_POWER_OFF)
+	}
+
+	/// Reboot the system
+	fn cmd_reboot(&self) -> i32 {
+		println!("Rebooting ScottOS...");
+		power_down(syscall::REBOOT_CMD_RESTART)
+	}
+
+	/// Power off, halt, or reboot: `shutdown [-P | -h | -H | -r]`
+	fn cmd_shutdown(&self, args: &[&str]) -> i32 {
+		let cmd = match args {
+			[] | ["-P" | "--poweroff"] | ["-h"] => syscall::REBOOT_CMD_POWER_OFF,
+			["-H" | "--halt"] => syscall::REBOOT_CMD_HALT,
+			["-r" | "--reboot"] => syscall::REBOOT_CMD_RESTART,
+			_ => {
+				errln!("usage: shutdown [-P | -h | -H | -r]");
+				return 1;
+			}
+		};
+		println!("Shutting down ScottOS...");
+		power_down(cmd)
+	}}
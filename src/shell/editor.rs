@@ -0,0 +1,338 @@
+use super::{read_file, write_file};
+use crate::cp437;
+use crate::fs::{self, FileType};
+use crate::syscall::{self, SyscallError};
+use crate::task::keyboard::KeyPress;
+use crate::vga_buffer::{self, Color, WRITER};
+use alloc::{format, string::String, vec::Vec};
+use pc_keyboard::{DecodedKey, KeyCode};
+
+/// Screen rows available for text and screen columns; the bottom row is
+/// the status bar
+fn text_area() -> (usize, usize) {
+	let (rows, cols) = vga_buffer::screen_size();
+	(rows - 1, cols)
+}
+/// Spaces inserted for the Tab key
+const TAB_WIDTH: usize = 4;
+
+/// Control characters produced by Ctrl+letter
+const CTRL_Q: char = '\u{11}';
+const CTRL_S: char = '\u{13}';
+const CTRL_X: char = '\u{18}';
+
+/// Whether Ctrl-arrow word movement treats a character as part of a word
+fn is_word_char(c: char) -> bool {
+	c.is_alphanumeric() || c == '_'
+}
+
+/// What the shell should do after the editor handles a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorAction {
+	Continue,
+	Quit,
+}
+
+/// A full-screen, nano-like text editor for one file
+pub struct Editor {
+	path: String,
+	lines: Vec<Vec<char>>,
+	row: usize,
+	col: usize,
+	top: usize,
+	left: usize,
+	dirty: bool,
+	confirm_quit: bool,
+	message: String,
+}
+
+impl Editor {
+	/// Load `path` (relative to the working directory), or start an empty
+	/// buffer if it does not exist yet
+	pub fn open(path: &str) -> Result<Editor, SyscallError> {
+		let path = syscall::visible_path(path);
+		let real = syscall::resolve_path(&path);
+		let (lines, message) = match fs::with_filesystem(|fs| fs.stat(&real)) {
+			Ok(metadata) if metadata.file_type == FileType::Directory => return Err(SyscallError::IsADirectory),
+			Ok(_) => {
+				let text = String::from_utf8_lossy(&read_file(&path)?).into_owned();
+				let lines: Vec<Vec<char>> = text.lines().map(|line| line.chars().collect()).collect();
+				let message = format!("Read {} lines", lines.len());
+				(lines, message)
+			}
+			Err(_) => (Vec::new(), String::from("New file")),
+		};
+		let lines = if lines.is_empty() { alloc::vec![Vec::new()] } else { lines };
+		Ok(Editor {
+			path,
+			lines,
+			row: 0,
+			col: 0,
+			top: 0,
+			left: 0,
+			dirty: false,
+			confirm_quit: false,
+			message,
+		})
+	}
+
+	/// The absolute path of the file being edited
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	/// Apply one key press
+	pub fn handle_key(&mut self, press: KeyPress) -> EditorAction {
+		let confirming = core::mem::take(&mut self.confirm_quit);
+		match press.key {
+			DecodedKey::Unicode(CTRL_Q | CTRL_X) => {
+				if self.dirty && !confirming {
+					self.confirm_quit = true;
+					self.message = String::from("Unsaved changes! Press Ctrl-Q again to discard them");
+				} else {
+					return EditorAction::Quit;
+				}
+			}
+			DecodedKey::Unicode(CTRL_S) => self.save(),
+			DecodedKey::Unicode('\n' | '\r') => self.split_line(),
+			DecodedKey::Unicode('\u{8}') => self.backspace(),
+			DecodedKey::Unicode('\u{7f}') => self.delete(),
+			DecodedKey::Unicode('\t') => {
+				for _ in 0..TAB_WIDTH {
+					self.insert(' ');
+				}
+			}
+			DecodedKey::Unicode(c) if !c.is_control() => self.insert(c),
+			DecodedKey::Unicode(_) => {}
+			DecodedKey::RawKey(code) if press.modifiers.ctrl() => self.jump_cursor(code),
+			DecodedKey::RawKey(code) => self.move_cursor(code),
+		}
+		self.scroll();
+		EditorAction::Continue
+	}
+
+	/// Insert a character at the cursor
+	fn insert(&mut self, c: char) {
+		self.lines[self.row].insert(self.col, c);
+		self.col += 1;
+		self.dirty = true;
+	}
+
+	/// Break the current line at the cursor
+	fn split_line(&mut self) {
+		let rest = self.lines[self.row].split_off(self.col);
+		self.row += 1;
+		self.col = 0;
+		self.lines.insert(self.row, rest);
+		self.dirty = true;
+	}
+
+	/// Delete the character before the cursor, joining lines at column 0
+	fn backspace(&mut self) {
+		if self.col > 0 {
+			self.col -= 1;
+			self.lines[self.row].remove(self.col);
+		} else if self.row > 0 {
+			let line = self.lines.remove(self.row);
+			self.row -= 1;
+			self.col = self.lines[self.row].len();
+			self.lines[self.row].extend(line);
+		} else {
+			return;
+		}
+		self.dirty = true;
+	}
+
+	/// Delete the character under the cursor, joining with the next line at the end
+	fn delete(&mut self) {
+		if self.col < self.lines[self.row].len() {
+			self.lines[self.row].remove(self.col);
+		} else if self.row + 1 < self.lines.len() {
+			let next = self.lines.remove(self.row + 1);
+			self.lines[self.row].extend(next);
+		} else {
+			return;
+		}
+		self.dirty = true;
+	}
+
+	/// Handle navigation keys
+	fn move_cursor(&mut self, code: KeyCode) {
+		match code {
+			KeyCode::ArrowUp => self.row = self.row.saturating_sub(1),
+			KeyCode::ArrowDown => self.row = (self.row + 1).min(self.lines.len() - 1),
+			KeyCode::ArrowLeft if self.col > 0 => self.col -= 1,
+			KeyCode::ArrowLeft if self.row > 0 => {
+				self.row -= 1;
+				self.col = self.lines[self.row].len();
+			}
+			KeyCode::ArrowRight if self.col < self.lines[self.row].len() => self.col += 1,
+			KeyCode::ArrowRight if self.row + 1 < self.lines.len() => {
+				self.row += 1;
+				self.col = 0;
+			}
+			KeyCode::Home => self.col = 0,
+			KeyCode::End => self.col = self.lines[self.row].len(),
+			KeyCode::PageUp => self.row = self.row.saturating_sub(text_area().0),
+			KeyCode::PageDown => self.row = (self.row + text_area().0).min(self.lines.len() - 1),
+			_ => {}
+		}
+		self.col = self.col.min(self.lines[self.row].len());
+	}
+
+	/// Move the cursor for a key held with Ctrl: a word at a time with the
+	/// arrows, or to the start or end of the file
+	fn jump_cursor(&mut self, code: KeyCode) {
+		match code {
+			KeyCode::ArrowLeft => self.word_left(),
+			KeyCode::ArrowRight => self.word_right(),
+			KeyCode::Home => (self.row, self.col) = (0, 0),
+			KeyCode::End => {
+				self.row = self.lines.len() - 1;
+				self.col = self.lines[self.row].len();
+			}
+			_ => self.move_cursor(code),
+		}
+	}
+
+	/// Move to the start of this word, or the one before, crossing to the
+	/// end of the previous line at column 0
+	fn word_left(&mut self) {
+		if self.col == 0 {
+			return self.move_cursor(KeyCode::ArrowLeft);
+		}
+		let line = &self.lines[self.row];
+		while self.col > 0 && !is_word_char(line[self.col - 1]) {
+			self.col -= 1;
+		}
+		while self.col > 0 && is_word_char(line[self.col - 1]) {
+			self.col -= 1;
+		}
+	}
+
+	/// Move past the end of this word, or the next one, crossing to the
+	/// start of the next line at the end of one
+	fn word_right(&mut self) {
+		let line = &self.lines[self.row];
+		if self.col == line.len() {
+			return self.move_cursor(KeyCode::ArrowRight);
+		}
+		while self.col < line.len() && !is_word_char(line[self.col]) {
+			self.col += 1;
+		}
+		while self.col < line.len() && is_word_char(line[self.col]) {
+			self.col += 1;
+		}
+	}
+
+	/// Adjust the viewport so the cursor stays on screen
+	fn scroll(&mut self) {
+		let (text_rows, text_cols) = text_area();
+		if self.row < self.top {
+			self.top = self.row;
+		} else if self.row >= self.top + text_rows {
+			self.top = self.row + 1 - text_rows;
+		}
+		if self.col < self.left {
+			self.left = self.col;
+		} else if self.col >= self.left + text_cols {
+			self.left = self.col + 1 - text_cols;
+		}
+	}
+
+	/// The buffer as file contents, newline-terminated unless empty
+	fn contents(&self) -> String {
+		let mut text = String::new();
+		for line in &self.lines {
+			text.extend(line);
+			text.push('\n');
+		}
+		if self.lines.len() == 1 && self.lines[0].is_empty() {
+			text.clear();
+		}
+		text
+	}
+
+	/// Write the buffer back to its file
+	fn save(&mut self) {
+		let text = self.contents();
+		self.message = match write_file(&self.path, text.as_bytes()) {
+			Ok(()) => {
+				self.dirty = false;
+				format!("Wrote {} bytes to {}", text.len(), self.path)
+			}
+			Err(err) => format!("Error writing {}: {}", self.path, err.as_str()),
+		};
+	}
+
+	/// Redraw the text area, status bar, and cursor
+	pub fn render(&self) {
+		let (text_rows, text_cols) = text_area();
+		x86_64::instructions::interrupts::without_interrupts(|| {
+			let mut writer = WRITER.lock();
+			for screen_row in 0..text_rows {
+				let line = self.lines.get(self.top + screen_row);
+				for screen_col in 0..text_cols {
+					let c = match line {
+						Some(line) => line.get(self.left + screen_col).copied().unwrap_or(' '),
+						None if screen_col == 0 => '~',
+						None => ' ',
+					};
+					writer.put_char(screen_row, screen_col, cp437::encode(c), Color::LightGray, Color::Black);
+				}
+			}
+
+			let modified = if self.dirty { " [modified]" } else { "" };
+			let position = format!("Ln {}, Col {} ", self.row + 1, self.col + 1);
+			let left = format!(" {}{} | {} ", self.path, modified, self.message);
+			let right = format!("^S save ^Q quit  {}", position);
+			let padding = text_cols.saturating_sub(left.len() + right.len());
+			let status = format!("{}{:padding$}{}", left, "", right);
+			writer.write_at(text_rows, 0, &status, Color::Black, Color::LightGray);
+			writer.set_position(self.row - self.top, self.col - self.left);
+		});
+	}
+}
+
+/// Test editing operations and the saved file layout
+#[test_case]
+fn test_editor_editing() {
+	use crate::task::keyboard::Modifiers;
+
+	let mut editor = Editor {
+		path: String::from("/tmp/edit-test"),
+		lines: alloc::vec![Vec::new()],
+		row: 0,
+		col: 0,
+		top: 0,
+		left: 0,
+		dirty: false,
+		confirm_quit: false,
+		message: String::new(),
+	};
+	for c in "ab".chars() {
+		editor.handle_key(KeyPress::new(DecodedKey::Unicode(c)));
+	}
+	editor.handle_key(KeyPress::new(DecodedKey::RawKey(KeyCode::ArrowLeft)));
+	editor.handle_key(KeyPress::new(DecodedKey::Unicode('\n')));
+	assert_eq!(editor.contents(), "a\nb\n");
+	editor.handle_key(KeyPress::new(DecodedKey::Unicode('\u{8}')));
+	assert_eq!(editor.contents(), "ab\n");
+	assert!(editor.dirty);
+
+	// The cursor is between the a and the b
+	for c in " foo_1, bar".chars() {
+		editor.handle_key(KeyPress::new(DecodedKey::Unicode(c)));
+	}
+	let ctrl = |code| KeyPress::with(DecodedKey::RawKey(code), Modifiers::CTRL);
+	editor.handle_key(ctrl(KeyCode::ArrowLeft));
+	assert_eq!(editor.col, 9);
+	editor.handle_key(ctrl(KeyCode::ArrowLeft));
+	assert_eq!(editor.col, 2);
+	editor.handle_key(ctrl(KeyCode::ArrowRight));
+	assert_eq!(editor.col, 7);
+	editor.handle_key(ctrl(KeyCode::Home));
+	assert_eq!((editor.row, editor.col), (0, 0));
+	assert_eq!(editor.handle_key(KeyPress::new(DecodedKey::Unicode(CTRL_Q))), EditorAction::Continue);
+	assert_eq!(editor.handle_key(KeyPress::new(DecodedKey::Unicode(CTRL_Q))), EditorAction::Quit);
+}
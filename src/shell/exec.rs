@@ -0,0 +1,201 @@
+use super::builtins::BUILTINS;
+use super::fileutils::parse_flags;
+use super::script::KEYWORDS;
+use super::{read_file, Shell};
+use crate::fs::{self, FileType};
+use crate::syscall::{self, SyscallError, X_OK};
+use alloc::{format, string::String, vec::Vec};
+
+/// First bytes of an ELF executable
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+/// Whether `path` is a regular file the caller may execute
+fn is_executable(path: &str) -> bool {
+	let path = syscall::resolve_path(path);
+	let regular = fs::with_filesystem(|fs| fs.stat(&path)).is_ok_and(|m| m.file_type == FileType::Regular);
+	regular && syscall::access_resolved(&path, X_OK).is_ok()
+}
+
+/// Refuse binaries and scripts whose `#!` line names another interpreter
+fn check_script(path: &str) -> Result<(), String> {
+	let source = read_file(path).map_err(|err| String::from(err.as_str()))?;
+	if source.starts_with(ELF_MAGIC) {
+		return Err(String::from("cannot execute binary file"));
+	}
+	if let Some(line) = source.strip_prefix(b"#!") {
+		let line = line.split(|&b| b == b'\n').next().unwrap_or(b"");
+		let line = String::from_utf8_lossy(line);
+		let interpreter = line.split_whitespace().next().unwrap_or("");
+		if fs::file_name(interpreter) != "sh" {
+			return Err(format!("bad interpreter: {}", interpreter));
+		}
+	}
+	Ok(())
+}
+
+impl Shell {
+	/// Every executable `name` resolves to through `$PATH`, in search order
+	///
+	/// Names containing a `/` are not searched for; an empty `$PATH` entry
+	/// means the working directory.
+	fn search_path(&self, name: &str) -> Vec<String> {
+		if name.contains('/') {
+			return if is_executable(name) { alloc::vec![String::from(name)] } else { Vec::new() };
+		}
+		self.var("PATH").unwrap_or("")
+			.split(':')
+			.map(|dir| match dir {
+				"" => String::from(name),
+				"/" => format!("/{}", name),
+				dir => format!("{}/{}", dir.trim_end_matches('/'), name),
+			})
+			.filter(|path| is_executable(path))
+			.collect()
+	}
+
+	/// Run a command that is not a builtin, or report that it does not exist
+	pub(super) fn run_external(&mut self, argv: &[String]) -> i32 {
+		let cmd = argv[0].as_str();
+		let path = if cmd.contains('/') {
+			String::from(cmd)
+		} else {
+			match self.search_path(cmd).into_iter().next() {
+				Some(path) => path,
+				None => {
+					errln!("Command '{}' not found. Type 'help' for available commands.", cmd);
+					return 127;
+				}
+			}
+		};
+
+		match syscall::sys_execve(&path) {
+			Ok(_) => 0,
+			// Not a format the kernel can load: run it as a script, as execvp does
+			Err(SyscallError::ExecFormatError) => match check_script(&path) {
+				Ok(()) => {
+					let args: Vec<&str> = argv[1..].iter().map(String::as_str).collect();
+					self.run_script_as(&path, &args)
+				}
+				Err(msg) => {
+					errln!("sh: {}: {}", cmd, msg);
+					126
+				}
+			},
+			Err(err @ SyscallError::NoSuchFileOrDirectory) => {
+				errln!("sh: {}: {}", cmd, err.as_str());
+				127
+			}
+			Err(err) => {
+				errln!("sh: {}: {}", cmd, err.as_str());
+				126
+			}
+		}
+	}
+
+	/// Run a script with `$0` set to its path and `$1`... set to `args`
+	pub(super) fn run_script_as(&mut self, path: &str, args: &[&str]) -> i32 {
+		let mut positional = alloc::vec![String::from(path)];
+		positional.extend(args.iter().map(|&arg| String::from(arg)));
+		let saved = core::mem::replace(&mut self.positional, positional);
+		let status = self.run_script_file(path);
+		self.positional = saved;
+		status
+	}
+
+	/// Show where commands resolve in `$PATH` (`-a` lists every match)
+	pub(super) fn cmd_which(&self, args: &[&str]) -> i32 {
+		let (flags, names) = match parse_flags(args, "a") {
+			Ok(parsed) => parsed,
+			Err(flag) => {
+				errln!("which: invalid option -- '{}'", flag);
+				return 2;
+			}
+		};
+		let mut status = 0;
+		for name in names {
+			let found = self.search_path(name);
+			if found.is_empty() {
+				status = 1;
+			}
+			let shown = if flags.contains(&'a') { found.len() } else { found.len().min(1) };
+			for path in &found[..shown] {
+				outln!("{}", path);
+			}
+		}
+		status
+	}
+
+	/// Describe how each name would be interpreted as a command
+	/// (`-t` prints just the kind, `-a` lists every interpretation)
+	pub(super) fn cmd_type(&self, args: &[&str]) -> i32 {
+		let (flags, names) = match parse_flags(args, "ta") {
+			Ok(parsed) => parsed,
+			Err(flag) => {
+				errln!("type: invalid option -- '{}'", flag);
+				return 2;
+			}
+		};
+		let terse = flags.contains(&'t');
+		let all = flags.contains(&'a');
+
+		let mut status = 0;
+		for name in names {
+			let mut kinds: Vec<(&str, String)> = Vec::new();
+			if let Some(value) = self.aliases.get(name) {
+				kinds.push(("alias", format!("{} is aliased to `{}'", name, value)));
+			}
+			if KEYWORDS.contains(&name) {
+				kinds.push(("keyword", format!("{} is a shell keyword", name)));
+			}
+			if BUILTINS.contains(&name) {
+				kinds.push(("builtin", format!("{} is a shell builtin", name)));
+			}
+			for path in self.search_path(name) {
+				kinds.push(("file", format!("{} is {}", name, path)));
+			}
+
+			if kinds.is_empty() {
+				if !terse {
+					errln!("type: {}: not found", name);
+				}
+				status = 1;
+				continue;
+			}
+			let shown = if all { kinds.len() } else { 1 };
+			for (kind, description) in &kinds[..shown] {
+				outln!("{}", if terse { *kind } else { description.as_str() });
+			}
+		}
+		status
+	}
+}
+
+/// Test that commands run by absolute path and through `$PATH` inside a
+/// chroot, where each path is put under the root once
+#[test_case]
+fn test_exec_in_chroot() {
+	if crate::process::current_pid().is_none() {
+		crate::process::init();
+	}
+	if fs::with_filesystem(|fs| fs.stat("/")).is_err() {
+		fs::init_filesystem();
+	}
+	fs::with_filesystem(|fs| {
+		for dir in ["/srv", "/srv/jail", "/srv/jail/bin"] {
+			let _ = fs.create_directory(String::from(dir));
+		}
+		let _ = fs.create_file(String::from("/srv/jail/bin/hello"), b"#!/bin/sh\ntrue\n".to_vec());
+		fs.set_permissions("/srv/jail/bin/hello", 0o755).unwrap();
+	});
+	let mut shell = Shell::new();
+	shell.set_var("PATH", "/bin", true);
+	assert_eq!(syscall::sys_chroot("/srv/jail"), Ok(0));
+	let by_path = shell.run_external(&[String::from("/bin/hello")]);
+	let by_search = shell.run_external(&[String::from("hello")]);
+	crate::process::with_current_process(|p| {
+		p.root = String::from("/");
+		p.cwd = String::from("/");
+	});
+	assert_eq!(by_path, 0);
+	assert_eq!(by_search, 0);
+}
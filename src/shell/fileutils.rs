@@ -0,0 +1,553 @@
+use super::{read_file, write_file, Shell};
+use crate::fs::{self, FileMetadata, FileType, O_CREAT, O_WRONLY};
+use crate::syscall::{self, SyscallError};
+use crate::{process, users};
+use alloc::{format, string::String, vec::Vec};
+
+/// Width of the console used to lay out `ls` columns
+const SCREEN_WIDTH: usize = 80;
+
+/// Split leading `-abc` flags from operands, rejecting flags not in `allowed`
+///
+/// Returns the flags seen and the remaining operands, or the offending flag.
+pub(super) fn parse_flags<'a>(args: &[&'a str], allowed: &str) -> Result<(Vec<char>, Vec<&'a str>), char> {
+	let mut flags = Vec::new();
+	let mut rest = args;
+	while let Some((arg, tail)) = rest.split_first() {
+		if *arg == "--" {
+			rest = tail;
+			break;
+		}
+		if !arg.starts_with('-') || arg.len() == 1 {
+			break;
+		}
+		for flag in arg[1..].chars() {
+			if !allowed.contains(flag) {
+				return Err(flag);
+			}
+			flags.push(flag);
+		}
+		rest = tail;
+	}
+	Ok((flags, rest.to_vec()))
+}
+
+/// Look up metadata for a path relative to the working directory, if the
+/// shell's process can reach it
+fn stat(path: &str) -> Result<FileMetadata, SyscallError> {
+	let path = syscall::resolve_path(path);
+	let who = process::current_credentials();
+	Ok(fs::with_filesystem(|fs| fs.access(&path, &who, 0).and_then(|()| fs.stat(&path)))?)
+}
+
+/// List a directory relative to the working directory, sorted by name, if
+/// the shell's process may read it
+fn list(path: &str) -> Result<Vec<String>, SyscallError> {
+	let path = syscall::resolve_path(path);
+	let who = process::current_credentials();
+	let mut entries = fs::with_filesystem(|fs| fs.access(&path, &who, fs::MAY_READ).and_then(|()| fs.list_directory(&path)))?;
+	entries.sort();
+	Ok(entries)
+}
+
+/// Join a directory and an entry name
+fn join(dir: &str, name: &str) -> String {
+	if dir.ends_with('/') {
+		format!("{}{}", dir, name)
+	} else {
+		format!("{}/{}", dir, name)
+	}
+}
+
+/// Render a file type and permission bits as `drwxr-xr-x`
+fn mode_string(metadata: &FileMetadata) -> String {
+	let mut mode = String::new();
+	mode.push(match metadata.file_type {
+		FileType::Regular => '-',
+		FileType::Directory => 'd',
+		FileType::Symlink => 'l',
+		FileType::Device => 'c',
+	});
+	for shift in [6, 3, 0] {
+		let bits = metadata.permissions >> shift;
+		mode.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+		mode.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+		mode.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+	}
+	mode
+}
+
+/// A file's owner as `ls -l` shows it: the user's name, or else the number
+fn owner_name(uid: u32) -> String {
+	users::by_uid(uid).map_or_else(|| format!("{}", uid), |user| user.name)
+}
+
+/// A file's group as `ls -l` shows it
+fn group_name(gid: u32) -> String {
+	users::group_name(gid).unwrap_or_else(|| format!("{}", gid))
+}
+
+/// Print names across the screen in as many lines as needed
+fn print_columns(names: &[String]) {
+	let mut width = 0;
+	for name in names {
+		if width > 0 && width + 2 + name.len() > SCREEN_WIDTH {
+			outln!();
+			width = 0;
+		}
+		if width > 0 {
+			out!("  ");
+			width += 2;
+		}
+		out!("{}", name);
+		width += name.len();
+	}
+	if width > 0 {
+		outln!();
+	}
+}
+
+impl Shell {
+	/// List directory contents (`-l` long format, `-a` include hidden entries)
+	pub(super) fn cmd_ls(&self, args: &[&str]) -> i32 {
+		let (flags, mut operands) = match parse_flags(args, "la") {
+			Ok(parsed) => parsed,
+			Err(flag) => {
+				errln!("ls: invalid option -- '{}'", flag);
+				return 2;
+			}
+		};
+		let long = flags.contains(&'l');
+		let all = flags.contains(&'a');
+		if operands.is_empty() {
+			operands.push(".");
+		}
+
+		let mut status = 0;
+		let mut files = Vec::new();
+		let mut dirs = Vec::new();
+		for &path in &operands {
+			match stat(path) {
+				Ok(metadata) if metadata.file_type == FileType::Directory => dirs.push(path),
+				Ok(metadata) => files.push((String::from(path), metadata)),
+				Err(err) => {
+					errln!("ls: cannot access '{}': {}", path, err.as_str());
+					status = 2;
+				}
+			}
+		}
+
+		let show = |entries: &[(String, FileMetadata)]| {
+			if long {
+				for (name, metadata) in entries {
+					outln!(
+						"{} 1 {:<8} {:<8} {:>8} {}",
+						mode_string(metadata), owner_name(metadata.owner), group_name(metadata.group), metadata.size, name
+					);
+				}
+			} else {
+				let names: Vec<String> = entries.iter().map(|(name, _)| name.clone()).collect();
+				print_columns(&names);
+			}
+		};
+
+		show(&files);
+		let headers = operands.len() > 1;
+		for (i, dir) in dirs.iter().enumerate() {
+			if headers {
+				if i > 0 || !files.is_empty() {
+					outln!();
+				}
+				outln!("{}:", dir);
+			}
+			let names = match list(dir) {
+				Ok(names) => names,
+				Err(err) => {
+					errln!("ls: cannot open directory '{}': {}", dir, err.as_str());
+					status = 2;
+					continue;
+				}
+			};
+			let mut entries = Vec::new();
+			if all {
+				for special in [".", ".."] {
+					if let Ok(metadata) = stat(&join(dir, special)) {
+						entries.push((String::from(special), metadata));
+					}
+				}
+			}
+			for name in names {
+				if !all && name.starts_with('.') {
+					continue;
+				}
+				if let Ok(metadata) = stat(&join(dir, &name)) {
+					entries.push((name, metadata));
+				}
+			}
+			show(&entries);
+		}
+		status
+	}
+
+	/// Create empty files that do not exist yet
+	pub(super) fn cmd_touch(&self, args: &[&str]) -> i32 {
+		if args.is_empty() {
+			errln!("touch: missing file operand");
+			return 1;
+		}
+		let mut status = 0;
+		for path in args {
+			if stat(path).is_ok() {
+				continue;
+			}
+			match syscall::sys_open(path, O_WRONLY | O_CREAT, 0o644) {
+				Ok(fd) => {
+					let _ = syscall::sys_close(fd);
+				}
+				Err(err) => {
+					errln!("touch: cannot touch '{}': {}", path, err.as_str());
+					status = 1;
+				}
+			}
+		}
+		status
+	}
+
+	/// Create directories (`-p` creates missing parents and ignores existing ones)
+	pub(super) fn cmd_mkdir(&self, args: &[&str]) -> i32 {
+		let (flags, operands) = match parse_flags(args, "p") {
+			Ok(parsed) => parsed,
+			Err(flag) => {
+				errln!("mkdir: invalid option -- '{}'", flag);
+				return 1;
+			}
+		};
+		let parents = flags.contains(&'p');
+		if operands.is_empty() {
+			errln!("mkdir: missing operand");
+			return 1;
+		}
+
+		let mut status = 0;
+		for path in operands {
+			let result = if parents {
+				make_parents(&syscall::visible_path(path))
+			} else {
+				syscall::sys_mkdir(path, 0o755).map(|_| ())
+			};
+			if let Err(err) = result {
+				errln!("mkdir: cannot create directory '{}': {}", path, err.as_str());
+				status = 1;
+			}
+		}
+		status
+	}
+
+	/// Remove files (`-r` removes directories recursively, `-f` ignores missing files)
+	pub(super) fn cmd_rm(&self, args: &[&str]) -> i32 {
+		let (flags, operands) = match parse_flags(args, "rRf") {
+			Ok(parsed) => parsed,
+			Err(flag) => {
+				errln!("rm: invalid option -- '{}'", flag);
+				return 1;
+			}
+		};
+		let recursive = flags.contains(&'r') || flags.contains(&'R');
+		let force = flags.contains(&'f');
+		if operands.is_empty() && !force {
+			errln!("rm: missing operand");
+			return 1;
+		}
+
+		let mut status = 0;
+		for path in operands {
+			let result = match stat(path) {
+				Ok(metadata) if metadata.file_type == FileType::Directory => {
+					if recursive {
+						remove_tree(&syscall::visible_path(path))
+					} else {
+						Err(SyscallError::IsADirectory)
+					}
+				}
+				Ok(_) => syscall::sys_unlink(path).map(|_| ()),
+				Err(SyscallError::NoSuchFileOrDirectory) if force => Ok(()),
+				Err(err) => Err(err),
+			};
+			if let Err(err) = result {
+				errln!("rm: cannot remove '{}': {}", path, err.as_str());
+				status = 1;
+			}
+		}
+		status
+	}
+
+	/// Copy files (`-r` copies directories recursively) to a file or into a directory
+	pub(super) fn cmd_cp(&self, args: &[&str]) -> i32 {
+		let (flags, operands) = match parse_flags(args, "rR") {
+			Ok(parsed) => parsed,
+			Err(flag) => {
+				errln!("cp: invalid option -- '{}'", flag);
+				return 1;
+			}
+		};
+		let recursive = !flags.is_empty();
+		let Some((targets, sources)) = transfer_targets("cp", &operands) else {
+			return 1;
+		};
+
+		let mut status = 0;
+		for (source, target) in sources.iter().zip(targets) {
+			let from = syscall::visible_path(source);
+			let result = match stat(source) {
+				Ok(metadata) if metadata.file_type == FileType::Directory => {
+					if !recursive {
+						errln!("cp: -r not specified; omitting directory '{}'", source);
+						status = 1;
+						continue;
+					}
+					if target == from || target.starts_with(&join(&from, "")) {
+						errln!("cp: cannot copy a directory, '{}', into itself", source);
+						status = 1;
+						continue;
+					}
+					copy_tree(&from, &target)
+				}
+				Ok(_) => copy_file(&from, &target),
+				Err(err) => Err(err),
+			};
+			if let Err(err) = result {
+				errln!("cp: cannot copy '{}': {}", source, err.as_str());
+				status = 1;
+			}
+		}
+		status
+	}
+
+	/// Move or rename files to a new name or into a directory
+	pub(super) fn cmd_mv(&self, args: &[&str]) -> i32 {
+		let Some((targets, sources)) = transfer_targets("mv", args) else {
+			return 1;
+		};
+		let mut status = 0;
+		for (source, target) in sources.iter().zip(targets) {
+			if let Err(err) = syscall::sys_rename(source, &target) {
+				errln!("mv: cannot move '{}': {}", source, err.as_str());
+				status = 1;
+			}
+		}
+		status
+	}
+
+	/// Change the working directory (`cd` alone goes home, `cd -` goes back)
+	pub(super) fn cmd_cd(&mut self, args: &[&str]) -> i32 {
+		let path = match args {
+			[] => String::from(self.var("HOME").unwrap_or("/")),
+			["-"] => match self.var("OLDPWD") {
+				Some(old) => String::from(old),
+				None => {
+					errln!("cd: OLDPWD not set");
+					return 1;
+				}
+			},
+			[path] => String::from(*path),
+			_ => {
+				errln!("cd: too many arguments");
+				return 1;
+			}
+		};
+		match self.change_dir(&path) {
+			Ok(()) => {
+				if args == ["-"] {
+					outln!("{}", syscall::visible_path("."));
+				}
+				0
+			}
+			Err(err) => {
+				errln!("cd: {}: {}", path, err.as_str());
+				1
+			}
+		}
+	}
+
+	/// Print the working directory
+	pub(super) fn cmd_pwd(&self) -> i32 {
+		outln!("{}", syscall::visible_path("."));
+		0
+	}
+
+	/// Change permission bits: `chmod MODE FILE...` with an octal or
+	/// `[ugoa][+-=][rwx],...` mode
+	pub(super) fn cmd_chmod(&self, args: &[&str]) -> i32 {
+		let Some((&mode, paths)) = args.split_first().filter(|(_, paths)| !paths.is_empty()) else {
+			errln!("usage: chmod MODE FILE...");
+			return 1;
+		};
+		let mut status = 0;
+		for &path in paths {
+			let result = stat(path).and_then(|metadata| {
+				let permissions = apply_mode(mode, metadata.permissions).ok_or(SyscallError::InvalidArgument)?;
+				syscall::sys_chmod(path, permissions as usize)
+			});
+			match result {
+				Ok(_) => {}
+				Err(SyscallError::InvalidArgument) => {
+					errln!("chmod: invalid mode: '{}'", mode);
+					return 1;
+				}
+				Err(err) => {
+					errln!("chmod: cannot access '{}': {}", path, err.as_str());
+					status = 1;
+				}
+			}
+		}
+		status
+	}
+
+	/// Change directory and keep `PWD`/`OLDPWD` in step
+	pub(super) fn change_dir(&mut self, path: &str) -> Result<(), SyscallError> {
+		let old = syscall::visible_path(".");
+		syscall::sys_chdir(path)?;
+		self.set_var("OLDPWD", &old, true);
+		self.set_var("PWD", &syscall::visible_path("."), true);
+		Ok(())
+	}
+}
+
+/// Work out where each source goes for `cp`/`mv SOURCE... DEST`
+///
+/// A destination that is an existing directory receives each source under its
+/// own name; otherwise there must be exactly one source.
+fn transfer_targets<'a>(cmd: &str, operands: &[&'a str]) -> Option<(Vec<String>, Vec<&'a str>)> {
+	let Some((&dest, sources)) = operands.split_last().filter(|(_, sources)| !sources.is_empty()) else {
+		errln!("{}: missing destination file operand", cmd);
+		return None;
+	};
+	let dest_path = syscall::visible_path(dest);
+	let into_dir = stat(dest).is_ok_and(|m| m.file_type == FileType::Directory);
+	if !into_dir && sources.len() > 1 {
+		errln!("{}: target '{}' is not a directory", cmd, dest);
+		return None;
+	}
+	let targets = sources.iter()
+		.map(|source| {
+			if into_dir {
+				join(&dest_path, fs::file_name(&syscall::visible_path(source)))
+			} else {
+				dest_path.clone()
+			}
+		})
+		.collect();
+	Some((targets, sources.to_vec()))
+}
+
+/// Create a directory and any missing ancestors
+fn make_parents(path: &str) -> Result<(), SyscallError> {
+	match stat(path) {
+		Ok(metadata) if metadata.file_type == FileType::Directory => return Ok(()),
+		Ok(_) => return Err(SyscallError::FileExists),
+		Err(_) => {}
+	}
+	if let Some(parent) = fs::parent_path(path) {
+		make_parents(parent)?;
+	}
+	syscall::sys_mkdir(path, 0o755).map(|_| ())
+}
+
+/// Remove a directory and everything below it
+fn remove_tree(path: &str) -> Result<(), SyscallError> {
+	for name in list(path)? {
+		let child = join(path, &name);
+		if stat(&child)?.file_type == FileType::Directory {
+			remove_tree(&child)?;
+		} else {
+			syscall::sys_unlink(&child)?;
+		}
+	}
+	syscall::sys_rmdir(path).map(|_| ())
+}
+
+/// Copy one regular file's contents
+fn copy_file(from: &str, to: &str) -> Result<(), SyscallError> {
+	if stat(to).is_ok_and(|m| m.file_type == FileType::Directory) {
+		return Err(SyscallError::IsADirectory);
+	}
+	let data = read_file(from)?;
+	write_file(to, &data)
+}
+
+/// Copy a directory and everything below it
+fn copy_tree(from: &str, to: &str) -> Result<(), SyscallError> {
+	match stat(to) {
+		Ok(metadata) if metadata.file_type != FileType::Directory => return Err(SyscallError::NotADirectory),
+		Ok(_) => {}
+		Err(_) => {
+			syscall::sys_mkdir(to, 0o755)?;
+		}
+	}
+	for name in list(from)? {
+		let (src, dst) = (join(from, &name), join(to, &name));
+		if stat(&src)?.file_type == FileType::Directory {
+			copy_tree(&src, &dst)?;
+		} else {
+			copy_file(&src, &dst)?;
+		}
+	}
+	Ok(())
+}
+
+/// Apply an octal or symbolic `chmod` mode to existing permission bits
+fn apply_mode(mode: &str, permissions: u32) -> Option<u32> {
+	if mode.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+		return u32::from_str_radix(mode, 8).ok().filter(|&bits| bits <= 0o7777);
+	}
+	let mut permissions = permissions;
+	for clause in mode.split(',') {
+		let op_at = clause.find(['+', '-', '='])?;
+		let (who, rest) = clause.split_at(op_at);
+		let mut classes = 0;
+		for c in who.chars() {
+			classes |= match c {
+				'u' => 0o700,
+				'g' => 0o070,
+				'o' => 0o007,
+				'a' => 0o777,
+				_ => return None,
+			};
+		}
+		if classes == 0 {
+			classes = 0o777;
+		}
+		let mut bits = 0;
+		for c in rest[1..].chars() {
+			bits |= match c {
+				'r' => 0o444,
+				'w' => 0o222,
+				'x' => 0o111,
+				_ => return None,
+			};
+		}
+		permissions = match rest.as_bytes()[0] {
+			b'+' => permissions | (bits & classes),
+			b'-' => permissions & !(bits & classes),
+			_ => (permissions & !classes) | (bits & classes),
+		};
+	}
+	Some(permissions)
+}
+
+/// Test short-flag parsing shared by the file utilities
+#[test_case]
+fn test_parse_flags() {
+	let (flags, operands) = parse_flags(&["-la", "-a", "/etc", "-x"], "la").unwrap();
+	assert_eq!(flags, ['l', 'a', 'a']);
+	assert_eq!(operands, ["/etc", "-x"]);
+	let (flags, operands) = parse_flags(&["--", "-r"], "r").unwrap();
+	assert!(flags.is_empty());
+	assert_eq!(operands, ["-r"]);
+	assert_eq!(parse_flags(&["-z"], "r"), Err('z'));
+	assert_eq!(apply_mode("755", 0o644), Some(0o755));
+	assert_eq!(apply_mode("+x", 0o644), Some(0o755));
+	assert_eq!(apply_mode("go-r,u=rw", 0o755), Some(0o611));
+	assert_eq!(apply_mode("u+z", 0o644), None);
+	assert_eq!(apply_mode("9", 0o644), None);
+}
@@ -0,0 +1,167 @@
+use crate::{fs, process, syscall};
+use alloc::{format, string::String, vec::Vec};
+
+/// Whether a pattern contains an unescaped `*`, `?`, or `[`
+pub fn has_wildcards(pattern: &str) -> bool {
+	let mut chars = pattern.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => {
+				chars.next();
+			}
+			'*' | '?' | '[' => return true,
+			_ => {}
+		}
+	}
+	false
+}
+
+/// Remove backslash escapes from a pattern
+pub fn unescape(pattern: &str) -> String {
+	let mut result = String::new();
+	let mut chars = pattern.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => result.extend(chars.next()),
+			c => result.push(c),
+		}
+	}
+	result
+}
+
+/// Match a `[...]` bracket expression at the start of `pattern` against `c`,
+/// returning whether it matched and the rest of the pattern
+fn match_class(pattern: &[char], c: char) -> Option<(bool, &[char])> {
+	let mut i = 0;
+	let negate = matches!(pattern.first(), Some('!' | '^'));
+	if negate {
+		i += 1;
+	}
+	let mut matched = false;
+	let mut first = true;
+	while i < pattern.len() {
+		let lo = pattern[i];
+		if lo == ']' && !first {
+			return Some((matched != negate, &pattern[i + 1..]));
+		}
+		first = false;
+		if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&hi| hi != ']') {
+			let hi = pattern[i + 2];
+			matched |= lo <= c && c <= hi;
+			i += 3;
+		} else {
+			matched |= lo == c;
+			i += 1;
+		}
+	}
+	// Unterminated bracket: not a class
+	None
+}
+
+/// Match a single path component against a glob pattern
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+	match pattern.split_first() {
+		None => name.is_empty(),
+		Some(('*', rest)) => (0..=name.len()).any(|skip| match_chars(rest, &name[skip..])),
+		Some(('?', rest)) => !name.is_empty() && match_chars(rest, &name[1..]),
+		Some(('[', rest)) => match (name.first(), match_class(rest, *name.first().unwrap_or(&'\0'))) {
+			(Some(_), Some((true, rest))) => match_chars(rest, &name[1..]),
+			(Some(_), Some((false, _))) => false,
+			// Unterminated `[` matches literally
+			(Some('['), None) => match_chars(rest, &name[1..]),
+			_ => false,
+		},
+		Some(('\\', rest)) if !rest.is_empty() => {
+			name.first() == Some(&rest[0]) && match_chars(&rest[1..], &name[1..])
+		}
+		Some((&c, rest)) => name.first() == Some(&c) && match_chars(rest, &name[1..]),
+	}
+}
+
+/// Whether `name` matches the glob `pattern`
+pub fn matches(pattern: &str, name: &str) -> bool {
+	let pattern: Vec<char> = pattern.chars().collect();
+	let name: Vec<char> = name.chars().collect();
+	match_chars(&pattern, &name)
+}
+
+/// Join a directory and an entry name
+fn join(dir: &str, name: &str) -> String {
+	if dir.is_empty() {
+		String::from(name)
+	} else if dir.ends_with('/') {
+		format!("{}{}", dir, name)
+	} else {
+		format!("{}/{}", dir, name)
+	}
+}
+
+/// Expand a pattern against the file system, returning sorted matching paths
+///
+/// Relative patterns are resolved against `cwd` but reported relative, as
+/// typed. Directories the current process may not read match nothing.
+pub fn expand(pattern: &str, cwd: &str) -> Vec<String> {
+	let who = process::current_credentials();
+	let absolute = pattern.starts_with('/');
+	// Each candidate is (path as displayed, absolute path as the process
+	// sees it, inside its root)
+	let mut candidates: Vec<(String, String)> = if absolute {
+		alloc::vec![(String::from("/"), String::from("/"))]
+	} else {
+		alloc::vec![(String::new(), String::from(cwd))]
+	};
+
+	for component in pattern.split('/').filter(|c| !c.is_empty()) {
+		let mut next = Vec::new();
+		for (shown, path) in &candidates {
+			if has_wildcards(component) {
+				let real = syscall::resolve_path(path);
+				let listed = fs::with_filesystem(|fs| fs.access(&real, &who, fs::MAY_READ).and_then(|()| fs.list_directory(&real)));
+				let Ok(mut entries) = listed else {
+					continue;
+				};
+				entries.sort();
+				for entry in entries {
+					// Hidden files only match patterns that start with a dot
+					if entry.starts_with('.') && !component.starts_with('.') {
+						continue;
+					}
+					if matches(component, &entry) {
+						next.push((join(shown, &entry), join(path, &entry)));
+					}
+				}
+			} else {
+				let literal = unescape(component);
+				let path = join(path, &literal);
+				let real = syscall::resolve_path(&path);
+				if fs::with_filesystem(|fs| fs.access(&real, &who, 0)).is_ok() {
+					next.push((join(shown, &literal), path));
+				}
+			}
+		}
+		candidates = next;
+	}
+
+	if pattern.ends_with('/') {
+		for (shown, _) in candidates.iter_mut() {
+			shown.push('/');
+		}
+	}
+	candidates.into_iter().map(|(shown, _)| shown).collect()
+}
+
+/// Test glob matching of wildcards, classes, and escapes
+#[test_case]
+fn test_glob_matches() {
+	assert!(matches("*", "passwd"));
+	assert!(matches("test?", "test1"));
+	assert!(!matches("test?", "test"));
+	assert!(matches("*.rs", "main.rs"));
+	assert!(!matches("*.rs", "main.rs.bak"));
+	assert!(matches("[a-c]at", "bat"));
+	assert!(!matches("[!a-c]at", "bat"));
+	assert!(matches("\\*", "*"));
+	assert!(!matches("\\*", "x"));
+	assert!(has_wildcards("/etc/*"));
+	assert!(!has_wildcards("/etc/\\*"));
+}
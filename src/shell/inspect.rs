@@ -0,0 +1,252 @@
+use super::{read_fd, read_file, Shell};
+use alloc::string::String;
+
+/// Bytes shown on each line of a hex dump
+const BYTES_PER_LINE: usize = 16;
+/// Bytes of memory `hexdump` shows when no length is given
+#[cfg(feature = "kernel-debug")]
+const DEFAULT_MEMORY_LEN: usize = 256;
+
+/// Parse a `0x`-prefixed hexadecimal or plain decimal number
+fn parse_number(s: &str) -> Option<u64> {
+	match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+		Some(hex) => u64::from_str_radix(hex, 16).ok(),
+		None => s.parse().ok(),
+	}
+}
+
+/// Format one hex dump line: offset, hex bytes in two groups of eight, and ASCII
+fn format_line(offset: usize, bytes: &[u8]) -> String {
+	use core::fmt::Write;
+	let mut line = String::new();
+	let _ = write!(line, "{:08x} ", offset);
+	for i in 0..BYTES_PER_LINE {
+		if i % 8 == 0 {
+			line.push(' ');
+		}
+		match bytes.get(i) {
+			Some(b) => {
+				let _ = write!(line, "{:02x} ", b);
+			}
+			None => line.push_str("   "),
+		}
+	}
+	line.push_str(" |");
+	for &b in bytes {
+		line.push(if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' });
+	}
+	line.push('|');
+	line
+}
+
+/// Print `data` in canonical hex+ASCII form
+fn dump(data: &[u8]) {
+	for (i, chunk) in data.chunks(BYTES_PER_LINE).enumerate() {
+		outln!("{}", format_line(i * BYTES_PER_LINE, chunk));
+	}
+	outln!("{:08x}", data.len());
+}
+
+/// Raw memory access for `hexdump <addr>`, `peek`, and `poke`
+#[cfg(feature = "kernel-debug")]
+mod raw {
+	use crate::memory;
+	use x86_64::{PhysAddr, VirtAddr};
+
+	/// Resolve an address to a virtual address, checking that all `len` bytes
+	/// from it are mapped so the access cannot page fault
+	pub fn checked_addr(addr: u64, len: usize, physical: bool) -> Result<VirtAddr, &'static str> {
+		let start = if physical {
+			memory::phys_to_virt(PhysAddr::try_new(addr).map_err(|_| "invalid physical address")?)
+				.ok_or("physical memory is not mapped")?
+		} else {
+			VirtAddr::try_new(addr).map_err(|_| "non-canonical address")?
+		};
+		let end = start.as_u64().checked_add(len as u64).ok_or("range overflows")?;
+		let mut page = start.align_down(4096u64).as_u64();
+		while page < end.max(start.as_u64() + 1) {
+			let virt = VirtAddr::try_new(page).map_err(|_| "non-canonical address")?;
+			if memory::translate(virt).is_none() {
+				return Err("address is not mapped");
+			}
+			page += 4096;
+		}
+		Ok(start)
+	}
+}
+
+impl Shell {
+	/// Dump a file, standard input, or (in kernel-debug builds) memory as hex
+	pub(super) fn cmd_hexdump(&self, args: &[&str]) -> i32 {
+		let len = match args.get(1).map(|s| parse_number(s)) {
+			None => None,
+			Some(Some(len)) => Some(len as usize),
+			Some(None) => {
+				errln!("hexdump: invalid length: {}", args[1]);
+				return 1;
+			}
+		};
+		let target = args.first().copied().unwrap_or("-");
+
+		if target.starts_with("0x") {
+			return self.hexdump_memory(target, len);
+		}
+		let data = if target == "-" { read_fd(0) } else { read_file(target) };
+		match data {
+			Ok(data) => {
+				let len = len.unwrap_or(data.len()).min(data.len());
+				dump(&data[..len]);
+				0
+			}
+			Err(err) => {
+				errln!("hexdump: {}: {}", target, err.as_str());
+				1
+			}
+		}
+	}
+
+	/// Dump `len` bytes of mapped virtual memory starting at `target`
+	#[cfg(feature = "kernel-debug")]
+	fn hexdump_memory(&self, target: &str, len: Option<usize>) -> i32 {
+		let len = len.unwrap_or(DEFAULT_MEMORY_LEN);
+		let Some(addr) = parse_number(target) else {
+			errln!("hexdump: invalid address: {}", target);
+			return 1;
+		};
+		match raw::checked_addr(addr, len, false) {
+			Ok(start) => {
+				// The whole range was checked to be mapped above
+				let data = unsafe { core::slice::from_raw_parts(start.as_ptr::<u8>(), len) };
+				outln!("Memory at {:#x}:", addr);
+				dump(data);
+				0
+			}
+			Err(msg) => {
+				errln!("hexdump: {}: {}", target, msg);
+				1
+			}
+		}
+	}
+
+	/// Memory dumps are only available in kernel-debug builds
+	#[cfg(not(feature = "kernel-debug"))]
+	fn hexdump_memory(&self, target: &str, _len: Option<usize>) -> i32 {
+		errln!("hexdump: {}: memory access requires a kernel-debug build", target);
+		1
+	}
+
+	/// Name the kernel functions addresses fall in: `sym ADDR...`
+	pub(super) fn cmd_sym(&self, args: &[&str]) -> i32 {
+		if args.is_empty() {
+			errln!("usage: sym ADDR...");
+			return 2;
+		}
+		if crate::ksyms::count() == 0 {
+			errln!("sym: no symbol map; run tools/ksyms.py on the kernel");
+			return 1;
+		}
+		let mut status = 0;
+		for arg in args {
+			let Some(addr) = parse_number(arg) else {
+				errln!("sym: {}: not an address", arg);
+				status = 1;
+				continue;
+			};
+			match crate::ksyms::resolve(addr) {
+				Some(symbol) => outln!("{:#018x} {}", addr, symbol),
+				None => {
+					errln!("sym: {:#x}: no symbol", addr);
+					status = 1;
+				}
+			}
+		}
+		status
+	}
+
+	/// Read a byte/word/dword/qword from memory: `peek [-p] ADDR [1|2|4|8]`
+	#[cfg(feature = "kernel-debug")]
+	pub(super) fn cmd_peek(&self, args: &[&str]) -> i32 {
+		let (physical, args) = match args.split_first() {
+			Some((&"-p", rest)) => (true, rest),
+			_ => (false, args),
+		};
+		let (addr, width) = match args {
+			[addr] => (parse_number(addr), Some(1)),
+			[addr, width] => (parse_number(addr), parse_number(width)),
+			_ => (None, None),
+		};
+		let (Some(addr), Some(width @ (1 | 2 | 4 | 8))) = (addr, width) else {
+			errln!("usage: peek [-p] ADDR [1|2|4|8]");
+			return 2;
+		};
+		let ptr = match raw::checked_addr(addr, width as usize, physical) {
+			Ok(start) => start.as_ptr::<u8>(),
+			Err(msg) => {
+				errln!("peek: {:#x}: {}", addr, msg);
+				return 1;
+			}
+		};
+		// Mapped per the check above; volatile so device registers are really read
+		let value = unsafe {
+			match width {
+				1 => u64::from(core::ptr::read_volatile(ptr)),
+				2 => u64::from(core::ptr::read_volatile(ptr as *const u16)),
+				4 => u64::from(core::ptr::read_volatile(ptr as *const u32)),
+				_ => core::ptr::read_volatile(ptr as *const u64),
+			}
+		};
+		outln!("{:#x}: {:#0width$x}", addr, value, width = width as usize * 2 + 2);
+		0
+	}
+
+	/// Write a value to memory: `poke [-p] ADDR VALUE [1|2|4|8]`
+	#[cfg(feature = "kernel-debug")]
+	pub(super) fn cmd_poke(&self, args: &[&str]) -> i32 {
+		let (physical, args) = match args.split_first() {
+			Some((&"-p", rest)) => (true, rest),
+			_ => (false, args),
+		};
+		let (addr, value, width) = match args {
+			[addr, value] => (parse_number(addr), parse_number(value), Some(1)),
+			[addr, value, width] => (parse_number(addr), parse_number(value), parse_number(width)),
+			_ => (None, None, None),
+		};
+		let (Some(addr), Some(value), Some(width @ (1 | 2 | 4 | 8))) = (addr, value, width) else {
+			errln!("usage: poke [-p] ADDR VALUE [1|2|4|8]");
+			return 2;
+		};
+		if width < 8 && value >> (width * 8) != 0 {
+			errln!("poke: value {:#x} does not fit in {} byte(s)", value, width);
+			return 1;
+		}
+		let ptr = match raw::checked_addr(addr, width as usize, physical) {
+			Ok(start) => start.as_mut_ptr::<u8>(),
+			Err(msg) => {
+				errln!("poke: {:#x}: {}", addr, msg);
+				return 1;
+			}
+		};
+		// Mapped per the check above; the caller takes responsibility for the contents
+		unsafe {
+			match width {
+				1 => core::ptr::write_volatile(ptr, value as u8),
+				2 => core::ptr::write_volatile(ptr as *mut u16, value as u16),
+				4 => core::ptr::write_volatile(ptr as *mut u32, value as u32),
+				_ => core::ptr::write_volatile(ptr as *mut u64, value),
+			}
+		}
+		0
+	}
+}
+
+/// Test hex dump line layout and number parsing
+#[test_case]
+fn test_hexdump_format() {
+	assert_eq!(
+		format_line(0x10, b"Hello, world!\n"),
+		"00000010  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a        |Hello, world!.|"
+	);
+	assert_eq!(parse_number("0x1F"), Some(31));
+	assert_eq!(parse_number("42"), Some(42));
+	assert_eq!(parse_number("0xZZ"), None);
+}
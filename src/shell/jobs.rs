@@ -0,0 +1,358 @@
+use super::editor::Editor;
+use super::parser::Pipeline;
+use super::{Shell, SHELLS};
+use crate::println;
+use crate::task::sync::Notify;
+use crate::task::{keyboard, CancellationToken};
+use crate::process::{self, ProcessId, ProcessState, Signal};
+use alloc::{format, string::String, vec::Vec};
+
+/// Wakes the background job runner when there is work for it
+static RUNNER: Notify = Notify::new();
+
+/// What a job still has left to do
+pub(super) enum Work {
+	/// A pipeline that has not run yet
+	Pipeline(Pipeline),
+	/// An editor suspended with Ctrl-Z
+	Editor(Editor),
+}
+
+/// Job status as shown by `jobs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum JobState {
+	Running,
+	Stopped,
+	Done(i32),
+}
+
+/// A background or stopped job and the process group that represents it
+pub(super) struct Job {
+	id: usize,
+	pgid: ProcessId,
+	command: String,
+	state: JobState,
+	work: Option<Work>,
+	/// Cancelled once the job's process is gone, so a pipeline the job is
+	/// part way through stops at its next stage
+	cancel: CancellationToken,
+}
+
+impl Job {
+	/// Status column for `jobs` and completion notices
+	fn status(&self) -> String {
+		match self.state {
+			JobState::Running => String::from("Running"),
+			JobState::Stopped => String::from("Stopped"),
+			JobState::Done(0) => String::from("Done"),
+			JobState::Done(status) => match status.checked_sub(128).and_then(|n| Signal::from_number(n as usize)) {
+				Some(signal) => String::from(signal.description()),
+				None => format!("Exit {}", status),
+			},
+		}
+	}
+}
+
+/// Resolve a job from the process table's view of its process
+fn process_state(pgid: ProcessId) -> Option<(ProcessState, Option<i32>)> {
+	process::with_scheduler(|s| s.get_process(pgid).map(|p| (p.state, p.exit_status)))
+}
+
+/// Let the job runner know there may be a job to run
+pub(super) fn wake_runner() {
+	RUNNER.notify_one();
+}
+
+impl Shell {
+	/// Start a pipeline as a background job
+	pub(super) fn launch_job(&mut self, pipeline: Pipeline) -> i32 {
+		let command = format!("{}", pipeline);
+		let pgid = self.new_job_process(&command);
+		let id = self.add_job(pgid, command, JobState::Running, Work::Pipeline(pipeline));
+		outln!("[{}] {}", id, pgid.0);
+		wake_runner();
+		0
+	}
+
+	/// Turn the open editor into a stopped job (Ctrl-Z)
+	pub(super) fn suspend_editor(&mut self, editor: Editor) {
+		let command = format!("edit {}", editor.path());
+		let pgid = self.new_job_process(&command);
+		process::send_signal(pgid, Signal::Tstp);
+		let id = self.add_job(pgid, command, JobState::Stopped, Work::Editor(editor));
+		let job = self.jobs.last().expect("job was just added");
+		println!("[{}]+  {:<24}{}", id, job.status(), job.command);
+	}
+
+	/// Create the process (and process group) that stands for a new job
+	fn new_job_process(&self, command: &str) -> ProcessId {
+		let pid = process::spawn_process(String::from(command), process::current_pid());
+		process::set_pgid(pid, pid);
+		pid
+	}
+
+	/// Record a job under the lowest free job number
+	fn add_job(&mut self, pgid: ProcessId, command: String, state: JobState, work: Work) -> usize {
+		let id = (1..).find(|id| self.jobs.iter().all(|job| job.id != *id)).unwrap();
+		self.jobs.push(Job { id, pgid, command, state, work: Some(work), cancel: CancellationToken::new() });
+		id
+	}
+
+	/// Bring job states up to date with their processes
+	fn refresh_jobs(&mut self) {
+		for job in &mut self.jobs {
+			if let JobState::Done(_) = job.state {
+				continue;
+			}
+			job.state = match process_state(job.pgid) {
+				Some((ProcessState::Terminated, status)) => JobState::Done(status.unwrap_or(0)),
+				Some((ProcessState::Stopped, _)) => JobState::Stopped,
+				Some(_) => JobState::Running,
+				None => JobState::Done(0),
+			};
+			if let JobState::Done(_) = job.state {
+				job.cancel.cancel();
+			}
+		}
+	}
+
+	/// Report and forget jobs that have finished since the last prompt
+	pub(super) fn notify_jobs(&mut self) {
+		self.refresh_jobs();
+		let count = self.jobs.len();
+		let mut index = 0;
+		while index < self.jobs.len() {
+			if let JobState::Done(_) = self.jobs[index].state {
+				let job = self.jobs.remove(index);
+				let marker = if index + 1 == count { '+' } else { '-' };
+				println!("[{}]{}  {:<24}{}", job.id, marker, job.status(), job.command);
+				process::terminate_process(job.pgid);
+			} else {
+				index += 1;
+			}
+		}
+	}
+
+	/// Run the next queued background job, returning whether one ran
+	fn run_next_job(&mut self) -> bool {
+		// A job's output would scribble over a full-screen editor
+		if self.editor.is_some() {
+			return false;
+		}
+		self.refresh_jobs();
+		let next = self.jobs.iter_mut()
+			.find(|job| job.state == JobState::Running && matches!(job.work, Some(Work::Pipeline(_))));
+		let Some(job) = next else {
+			return false;
+		};
+		let Some(Work::Pipeline(pipeline)) = job.work.take() else {
+			return false;
+		};
+		let pgid = job.pgid;
+		self.job_cancel = Some(job.cancel.clone());
+
+		// Jobs run on the shell's own descriptors and leave `$?` alone, and
+		// stop for `kill` rather than Ctrl-C, which is for the foreground
+		let saved_status = self.last_status;
+		let status = keyboard::in_background(|| self.execute_pipeline(&pipeline));
+		self.last_status = saved_status;
+		self.job_cancel = None;
+		process::with_scheduler(|s| s.get_process_mut(pgid).map(|p| p.exit(status)));
+		true
+	}
+
+	/// Find a job from a `%N`, `%+`, `%-`, or bare `N` spec, or the current job
+	fn find_job(&self, spec: Option<&str>) -> Result<usize, String> {
+		let spec = spec.unwrap_or("%+");
+		let index = match spec.strip_prefix('%').unwrap_or(spec) {
+			"+" | "%" | "" => self.jobs.len().checked_sub(1),
+			"-" => self.jobs.len().checked_sub(2).or(self.jobs.len().checked_sub(1)),
+			n => n.parse().ok().and_then(|id: usize| self.jobs.iter().position(|job| job.id == id)),
+		};
+		index.ok_or_else(|| format!("{}: no such job", spec))
+	}
+
+	/// List jobs
+	pub(super) fn cmd_jobs(&mut self) -> i32 {
+		self.refresh_jobs();
+		let count = self.jobs.len();
+		for (i, job) in self.jobs.iter().enumerate() {
+			let marker = match count - i {
+				1 => '+',
+				2 => '-',
+				_ => ' ',
+			};
+			let suffix = if job.state == JobState::Running { " &" } else { "" };
+			outln!("[{}]{}  {:<24}{}{}", job.id, marker, job.status(), job.command, suffix);
+		}
+		0
+	}
+
+	/// Continue a job in the foreground
+	pub(super) fn cmd_fg(&mut self, args: &[&str]) -> i32 {
+		self.refresh_jobs();
+		let index = match self.find_job(args.first().copied()) {
+			Ok(index) => index,
+			Err(msg) => {
+				errln!("fg: {}", msg);
+				return 1;
+			}
+		};
+		let job = self.jobs.remove(index);
+		if let JobState::Done(_) = job.state {
+			errln!("fg: job has terminated");
+			process::terminate_process(job.pgid);
+			return 1;
+		}
+		process::signal_group(job.pgid, Signal::Cont);
+		outln!("{}", job.command);
+		match job.work {
+			Some(Work::Pipeline(pipeline)) => {
+				let status = self.execute_pipeline(&pipeline);
+				if keyboard::interrupt_requested() {
+					// Ctrl-C goes to the job in the foreground
+					process::signal_group(job.pgid, Signal::Int);
+				}
+				process::terminate_process(job.pgid);
+				status
+			}
+			Some(Work::Editor(editor)) => {
+				// The editor runs as the shell's foreground again until the next Ctrl-Z
+				process::terminate_process(job.pgid);
+				editor.render();
+				self.editor = Some(editor);
+				0
+			}
+			None => {
+				errln!("fg: job has terminated");
+				process::terminate_process(job.pgid);
+				1
+			}
+		}
+	}
+
+	/// Continue stopped jobs in the background
+	pub(super) fn cmd_bg(&mut self, args: &[&str]) -> i32 {
+		self.refresh_jobs();
+		let specs: Vec<Option<&str>> = if args.is_empty() {
+			alloc::vec![None]
+		} else {
+			args.iter().map(|&arg| Some(arg)).collect()
+		};
+		let mut status = 0;
+		for spec in specs {
+			let index = match self.find_job(spec) {
+				Ok(index) => index,
+				Err(msg) => {
+					errln!("bg: {}", msg);
+					status = 1;
+					continue;
+				}
+			};
+			let job = &mut self.jobs[index];
+			match (&job.work, job.state) {
+				(Some(Work::Editor(_)), _) => {
+					errln!("bg: job {} needs the terminal; use fg", job.id);
+					status = 1;
+				}
+				(_, JobState::Done(_)) => {
+					errln!("bg: job has terminated");
+					status = 1;
+				}
+				(_, JobState::Running) => {
+					errln!("bg: job {} already in background", job.id);
+				}
+				(_, JobState::Stopped) => {
+					process::signal_group(job.pgid, Signal::Cont);
+					job.state = JobState::Running;
+					outln!("[{}]+ {} &", job.id, job.command);
+					wake_runner();
+				}
+			}
+		}
+		status
+	}
+
+	/// Send a signal to jobs or processes: `kill [-SIG | -s SIG] %JOB|PID...`, `kill -l`
+	pub(super) fn cmd_kill(&mut self, args: &[&str]) -> i32 {
+		let (signal, targets) = match args {
+			["-l", ..] => {
+				for signal in Signal::ALL {
+					outln!("{:>2}) SIG{}", signal as u8, signal.name());
+				}
+				return 0;
+			}
+			["-s", name, rest @ ..] => (Signal::from_name(name), rest),
+			[flag, rest @ ..] if flag.starts_with('-') => {
+				let name = &flag[1..];
+				let signal = match name.parse() {
+					Ok(number) => Signal::from_number(number),
+					Err(_) => Signal::from_name(name),
+				};
+				(signal, rest)
+			}
+			_ => (Some(Signal::Term), args),
+		};
+		let Some(signal) = signal else {
+			errln!("kill: invalid signal specification");
+			return 1;
+		};
+		if targets.is_empty() {
+			errln!("usage: kill [-s SIGNAL | -SIGNAL] %JOB|PID...");
+			return 2;
+		}
+
+		self.refresh_jobs();
+		let mut status = 0;
+		for &target in targets {
+			let delivered = if target.starts_with('%') {
+				match self.find_job(Some(target)) {
+					Ok(index) => process::signal_group(self.jobs[index].pgid, signal) > 0,
+					Err(msg) => {
+						errln!("kill: {}", msg);
+						status = 1;
+						continue;
+					}
+				}
+			} else {
+				match target.parse() {
+					Ok(pid) => process::send_signal(ProcessId(pid), signal),
+					Err(_) => {
+						errln!("kill: {}: arguments must be process or job IDs", target);
+						status = 1;
+						continue;
+					}
+				}
+			};
+			if !delivered {
+				errln!("kill: ({}) - No such process", target);
+				status = 1;
+			}
+		}
+		self.refresh_jobs();
+		if signal == Signal::Cont {
+			wake_runner();
+		}
+		status
+	}
+}
+
+/// Async task that runs background jobs whenever the shells are idle
+pub async fn run_background_jobs() {
+	loop {
+		for shell in SHELLS.iter() {
+			let mut shell = shell.lock().await;
+			shell.activate();
+			let mut ran = false;
+			while crate::task::blocking(|| shell.run_next_job()) {
+				ran = true;
+			}
+			if ran {
+				// Report completions and redraw the prompt the job's output interrupted
+				shell.notify_jobs();
+				shell.redraw_prompt();
+			}
+		}
+		RUNNER.notified().await;
+	}
+}
@@ -0,0 +1,275 @@
+use super::{Shell, CTRL_C, MAX_COMMAND_LEN};
+use crate::audit;
+use crate::mount::Namespace;
+use crate::process::{self, Credentials};
+use crate::syscall;
+use crate::tty::{self, Termios};
+use crate::users::{self, User};
+use crate::{print, println};
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+
+/// What the login prompt is waiting for
+pub(super) enum Prompt {
+	Name,
+	/// The password of the account named
+	Password(String),
+}
+
+/// Who the shell ran as, and what it saw, before `su`, `chroot` or
+/// `unshare`, for `exit` to go back to
+pub(super) struct Session {
+	credentials: Credentials,
+	user: String,
+	home: String,
+	cwd: String,
+	root: String,
+	mounts: Arc<Namespace>,
+}
+
+/// Read a line from the terminal without echoing it, or `None` at end of
+/// input or on Ctrl-C
+fn read_password(console: usize) -> Option<String> {
+	print!("Password: ");
+	let saved = tty::termios(console);
+	tty::set_termios(console, Termios { echo: false, ..saved });
+	let mut line = Vec::new();
+	let mut byte = [0u8; 1];
+	let password = loop {
+		match syscall::sys_read(0, &mut byte) {
+			Ok(1) if byte[0] == b'\n' => break Some(String::from_utf8_lossy(&line).into_owned()),
+			Ok(1) => line.push(byte[0]),
+			_ => break None,
+		}
+	};
+	tty::set_termios(console, saved);
+	println!();
+	password
+}
+
+impl Shell {
+	/// Ask who is logging in on this console
+	pub(super) fn begin_login(&mut self) {
+		println!("\nScottOS v0.1.0 (tty{})\n", self.console + 1);
+		self.login = Some(Prompt::Name);
+		print!("login: ");
+	}
+
+	/// Handle a character typed at the login prompt; what is typed for a
+	/// password is not shown
+	pub(super) fn login_char(&mut self, c: char) {
+		let echo = matches!(self.login, Some(Prompt::Name));
+		match c {
+			'\n' | '\r' => {
+				println!();
+				let line = String::from(core::str::from_utf8(&self.current_line[..self.current_pos]).unwrap_or(""));
+				self.current_line = [0; MAX_COMMAND_LEN];
+				self.current_pos = 0;
+				self.login_line(line);
+			}
+			CTRL_C => {
+				println!("^C");
+				self.current_line = [0; MAX_COMMAND_LEN];
+				self.current_pos = 0;
+				self.login = Some(Prompt::Name);
+				print!("login: ");
+			}
+			'\u{8}' => {
+				if self.current_pos > 0 {
+					self.current_pos -= 1;
+					if echo {
+						print!("\u{8} \u{8}");
+					}
+				}
+			}
+			c if c.is_ascii() && !c.is_control() && self.current_pos < MAX_COMMAND_LEN - 1 => {
+				self.current_line[self.current_pos] = c as u8;
+				self.current_pos += 1;
+				if echo {
+					print!("{}", c);
+				}
+			}
+			_ => {}
+		}
+	}
+
+	/// Act on a line entered at the login prompt
+	fn login_line(&mut self, line: String) {
+		let name = match self.login.take() {
+			Some(Prompt::Name) if line.is_empty() => {
+				self.login = Some(Prompt::Name);
+				print!("login: ");
+				return;
+			}
+			// Unknown names are asked for a password too, so trying names
+			// does not say which exist
+			Some(Prompt::Name) if users::needs_password(&line) => {
+				self.login = Some(Prompt::Password(line));
+				print!("Password: ");
+				return;
+			}
+			Some(Prompt::Name) => line,
+			Some(Prompt::Password(name)) if users::check_password(&name, &line) => name,
+			Some(Prompt::Password(name)) => {
+				audit::record(audit::Kind::Denied, audit::Actor::current(), format_args!("login as {} on tty{}: wrong password", name, self.console + 1));
+				String::new()
+			}
+			None => String::new(),
+		};
+		match users::by_name(&name) {
+			Some(user) => self.log_in(&user),
+			None => {
+				println!("\nLogin incorrect");
+				self.login = Some(Prompt::Name);
+				print!("login: ");
+			}
+		}
+	}
+
+	/// Start a session as `user`: take on who they are, go to their home
+	/// directory, and source `/etc/shellrc`
+	pub(super) fn log_in(&mut self, user: &User) {
+		self.login = None;
+		self.become_user(user);
+		let _ = self.change_dir(&user.home);
+		if crate::fs::with_filesystem(|fs| fs.stat("/etc/shellrc")).is_ok() {
+			self.run_script_file("/etc/shellrc");
+		}
+		self.start();
+	}
+
+	/// Run as `user` from now on: the process's credentials, `USER`, and `HOME`
+	fn become_user(&mut self, user: &User) {
+		let credentials = user.credentials();
+		process::with_current_process(|p| p.credentials = credentials);
+		self.set_var("USER", &user.name, true);
+		self.set_var("HOME", &user.home, true);
+	}
+
+	/// Run as another user until `exit`: `su [-] [USER]`, root if none is
+	/// named, asking for their password unless run by root; with `-`, start
+	/// in their home directory
+	pub(super) fn cmd_su(&mut self, args: &[&str]) -> i32 {
+		let (login, name) = match args {
+			[] => (false, "root"),
+			["-" | "-l"] => (true, "root"),
+			["-" | "-l", name] => (true, *name),
+			[name] if !name.starts_with('-') => (false, *name),
+			_ => {
+				errln!("usage: su [-] [USER]");
+				return 1;
+			}
+		};
+		let Some(user) = users::by_name(name) else {
+			errln!("su: user {} does not exist", name);
+			return 1;
+		};
+		if !process::current_credentials().is_root() && users::needs_password(name) {
+			let password = read_password(self.console);
+			if !password.is_some_and(|password| users::check_password(name, &password)) {
+				audit::record(audit::Kind::Denied, audit::Actor::current(), format_args!("su to {}: wrong password", name));
+				errln!("su: Authentication failure");
+				return 1;
+			}
+		}
+		let session = self.session();
+		self.sessions.push(session);
+		self.become_user(&user);
+		if login {
+			let _ = self.change_dir(&user.home);
+		}
+		0
+	}
+
+	/// Change the root directory until `exit`: `chroot DIR`, as root
+	pub(super) fn cmd_chroot(&mut self, args: &[&str]) -> i32 {
+		let [dir] = args else {
+			errln!("usage: chroot DIR");
+			return 1;
+		};
+		let session = self.session();
+		if let Err(err) = syscall::sys_chroot(dir) {
+			errln!("chroot: cannot change root directory to '{}': {}", dir, err.as_str());
+			return 1;
+		}
+		self.sessions.push(session);
+		let _ = self.change_dir("/");
+		0
+	}
+
+	/// Take a copy of the mounts until `exit`, so mounts made meanwhile are
+	/// seen by no other process: `unshare -m`, as root
+	pub(super) fn cmd_unshare(&mut self, args: &[&str]) -> i32 {
+		if args != ["-m"] {
+			errln!("usage: unshare -m");
+			return 1;
+		}
+		let session = self.session();
+		if let Err(err) = syscall::sys_unshare(process::CLONE_NEWNS) {
+			errln!("unshare: {}", err.as_str());
+			return 1;
+		}
+		self.sessions.push(session);
+		0
+	}
+
+	/// Who the shell runs as and what it sees now, for `exit` to come back to
+	fn session(&self) -> Session {
+		let (root, mounts) = process::with_current_process(|p| (p.root.clone(), p.mounts.clone()))
+			.unwrap_or_else(|| (String::from("/"), Namespace::new()));
+		Session {
+			credentials: process::current_credentials(),
+			user: String::from(self.var("USER").unwrap_or("")),
+			home: String::from(self.var("HOME").unwrap_or("/")),
+			cwd: syscall::visible_path("."),
+			root,
+			mounts,
+		}
+	}
+
+	/// Go back to who the shell ran as, and what it saw, before the last
+	/// `su`, `chroot` or `unshare`, returning whether there was one
+	pub(super) fn end_session(&mut self) -> bool {
+		let Some(session) = self.sessions.pop() else {
+			return false;
+		};
+		process::with_current_process(|p| {
+			p.credentials = session.credentials;
+			p.root = session.root;
+			p.mounts = session.mounts;
+		});
+		self.set_var("USER", &session.user, true);
+		self.set_var("HOME", &session.home, true);
+		let _ = self.change_dir(&session.cwd);
+		true
+	}
+
+	/// Print who the shell runs as: `whoami`
+	pub(super) fn cmd_whoami(&self) -> i32 {
+		let uid = process::current_credentials().uid;
+		match users::by_uid(uid) {
+			Some(user) => outln!("{}", user.name),
+			None => outln!("{}", uid),
+		}
+		0
+	}
+
+	/// Print the user and groups the shell runs as: `id`
+	pub(super) fn cmd_id(&self) -> i32 {
+		let credentials = process::current_credentials();
+		let name = |id: u32, name: Option<String>| match name {
+			Some(name) => format!("{}({})", id, name),
+			None => format!("{}", id),
+		};
+		let groups: Vec<String> = core::iter::once(credentials.gid)
+			.chain(credentials.groups.iter().copied())
+			.map(|gid| name(gid, users::group_name(gid)))
+			.collect();
+		outln!(
+			"uid={} gid={} groups={}",
+			name(credentials.uid, users::by_uid(credentials.uid).map(|user| user.name)),
+			name(credentials.gid, users::group_name(credentials.gid)),
+			groups.join(",")
+		);
+		0
+	}
+}
@@ -0,0 +1,768 @@
+use crate::{println, print};
+use crate::fs::{O_APPEND, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY};
+use crate::syscall::{self, SyscallError};
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use alloc::string::ToString;
+use core::fmt;
+use crate::process::{self, ProcessId};
+use crate::task::keyboard::{self, KeyPress};
+use crate::task::CancellationToken;
+use crate::vga_buffer::{self, CONSOLE_COUNT};
+use futures_util::stream::StreamExt;
+use pc_keyboard::DecodedKey;
+
+/// Writer that sends formatted output through a file descriptor
+struct FdWriter(usize);
+
+impl fmt::Write for FdWriter {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		syscall::sys_write(self.0, s.as_bytes()).map(|_| ()).map_err(|_| fmt::Error)
+	}
+}
+
+#[doc(hidden)]
+pub fn _write_fd(fd: usize, args: fmt::Arguments) {
+	use core::fmt::Write;
+	let _ = FdWriter(fd).write_fmt(args);
+}
+
+/// Print to the shell's standard output, honoring redirection
+macro_rules! out {
+	($($arg:tt)*) => ($crate::shell::_write_fd(1, format_args!($($arg)*)));
+}
+
+/// Print a line to the shell's standard output, honoring redirection
+macro_rules! outln {
+	() => (out!("\n"));
+	($($arg:tt)*) => (out!("{}\n", format_args!($($arg)*)));
+}
+
+/// Print a line to the shell's standard error
+macro_rules! errln {
+	($($arg:tt)*) => ($crate::shell::_write_fd(2, format_args!("{}\n", format_args!($($arg)*))));
+}
+
+mod builtins;
+mod editor;
+mod exec;
+mod fileutils;
+mod glob;
+mod inspect;
+mod jobs;
+mod login;
+mod netutils;
+mod parser;
+mod prompt;
+mod script;
+mod snake;
+mod sysutils;
+mod textutils;
+mod wget;
+mod xmodem;
+
+use parser::{Connector, Part, Pipeline, RedirectKind, SimpleCommand, Word};
+
+pub use builtins::BUILTINS;
+pub use jobs::run_background_jobs;
+
+/// Point the redirected descriptors at their targets, returning `(fd, saved)` pairs
+fn apply_redirects(redirects: &[(usize, RedirectKind, String)]) -> Result<Vec<(usize, usize)>, (String, SyscallError)> {
+	let mut saved = Vec::new();
+	for (fd, kind, target) in redirects {
+		let source = match kind {
+			RedirectKind::Read => syscall::sys_open(target, O_RDONLY, 0),
+			RedirectKind::Write => syscall::sys_open(target, O_WRONLY | O_CREAT | O_TRUNC, 0o644),
+			RedirectKind::Append => syscall::sys_open(target, O_WRONLY | O_CREAT | O_APPEND, 0o644),
+			RedirectKind::Dup => target.parse().map_err(|_| SyscallError::BadFileNumber)
+				.and_then(syscall::sys_dup),
+		};
+		let result = source.and_then(|source_fd| {
+			let backup = syscall::sys_dup(*fd)?;
+			syscall::sys_dup2(source_fd, *fd)?;
+			syscall::sys_close(source_fd)?;
+			Ok(backup)
+		});
+		match result {
+			Ok(backup) => saved.push((*fd, backup)),
+			Err(err) => {
+				restore_redirects(&saved);
+				return Err((target.clone(), err));
+			}
+		}
+	}
+	Ok(saved)
+}
+
+/// Undo `apply_redirects`, restoring the original descriptors
+fn restore_redirects(saved: &[(usize, usize)]) {
+	for &(fd, backup) in saved.iter().rev() {
+		let _ = syscall::sys_dup2(backup, fd);
+		let _ = syscall::sys_close(backup);
+	}
+}
+
+/// Control characters with special meaning at the prompt
+const CTRL_C: char = '\u{3}';
+const CTRL_D: char = '\u{4}';
+const CTRL_L: char = '\u{c}';
+/// Ctrl-Z, which suspends the foreground editor
+const CTRL_Z: char = '\u{1a}';
+/// Exit status of a command stopped by Ctrl-C (128 + SIGINT)
+const STATUS_INTERRUPTED: i32 = 130;
+
+/// Maximum command line length
+const MAX_COMMAND_LEN: usize = 256;
+/// Maximum number of command history entries
+const MAX_HISTORY: usize = 10;
+
+/// A shell variable and whether it is exported to spawned processes
+#[derive(Debug, Clone)]
+struct ShellVar {
+	value: String,
+	exported: bool,
+}
+
+/// What pathname expansion does when a pattern matches nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NoMatch {
+	/// Pass the pattern through unchanged (POSIX default)
+	Keep,
+	/// Remove the word (`set -o nullglob`)
+	Null,
+	/// Fail the command (`set -o failglob`)
+	Fail,
+}
+
+/// A field produced by expansion along with its glob pattern, in which
+/// quoted wildcard characters are backslash-escaped
+#[derive(Debug, Default)]
+struct Field {
+	text: String,
+	pattern: String,
+}
+
+impl Field {
+	/// Append text, escaping wildcards in the pattern when `quoted`
+	fn push_str(&mut self, text: &str, quoted: bool) {
+		self.text.push_str(text);
+		for c in text.chars() {
+			if quoted && matches!(c, '*' | '?' | '[' | '\\') {
+				self.pattern.push('\\');
+			}
+			self.pattern.push(c);
+		}
+	}
+}
+
+/// Whether `name` is a valid variable name
+fn is_var_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+		&& chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Simple command-line shell for ScottOS
+pub struct Shell {
+	current_line: [u8; MAX_COMMAND_LEN],
+	current_pos: usize,
+	command_history: [[u8; MAX_COMMAND_LEN]; MAX_HISTORY],
+	history_count: usize,
+	vars: BTreeMap<String, ShellVar>,
+	last_status: i32,
+	glob_nomatch: NoMatch,
+	editor: Option<editor::Editor>,
+	jobs: Vec<jobs::Job>,
+	/// Token of the background job running now, checked between its
+	/// pipeline stages
+	job_cancel: Option<CancellationToken>,
+	aliases: BTreeMap<String, String>,
+	/// `$0` followed by the positional parameters `$1`...
+	positional: Vec<String>,
+	/// Virtual console the shell reads from and writes to
+	console: usize,
+	/// Process the shell runs as, once it has its own
+	pid: Option<ProcessId>,
+	/// Set until someone logs in on the console
+	login: Option<login::Prompt>,
+	/// Who ran each `su` still in effect, innermost last
+	sessions: Vec<login::Session>,
+}
+
+impl Shell {
+	/// Create a new shell instance
+	pub fn new() -> Self {
+		let mut shell = Shell {
+			current_line: [0; MAX_COMMAND_LEN],
+			current_pos: 0,
+			command_history: [[0; MAX_COMMAND_LEN]; MAX_HISTORY],
+			history_count: 0,
+			vars: BTreeMap::new(),
+			last_status: 0,
+			glob_nomatch: NoMatch::Keep,
+			editor: None,
+			jobs: Vec::new(),
+			job_cancel: None,
+			aliases: BTreeMap::new(),
+			positional: alloc::vec![String::from("sh")],
+			console: 0,
+			pid: None,
+			login: None,
+			sessions: Vec::new(),
+		};
+		shell.set_var("HOME", "/root", true);
+		shell.set_var("PATH", "/bin:/usr/bin", true);
+		shell.set_var("SHELL", "/bin/sh", true);
+		shell.set_var("USER", "root", true);
+		shell.set_var("PS1", prompt::DEFAULT_PS1, false);
+		shell
+	}
+
+	/// Look up a shell variable
+	pub fn var(&self, name: &str) -> Option<&str> {
+		self.vars.get(name).map(|v| v.value.as_str())
+	}
+
+	/// Set a shell variable, keeping an existing export flag
+	pub fn set_var(&mut self, name: &str, value: &str, export: bool) {
+		let exported = export || self.vars.get(name).is_some_and(|v| v.exported);
+		self.vars.insert(name.to_string(), ShellVar { value: value.to_string(), exported });
+		if exported {
+			self.sync_environment();
+		}
+	}
+
+	/// Remove a shell variable
+	pub fn unset_var(&mut self, name: &str) {
+		if let Some(var) = self.vars.remove(name) {
+			if var.exported {
+				self.sync_environment();
+			}
+		}
+	}
+
+	/// Copy exported variables into the shell process's environment so that
+	/// spawned processes inherit them
+	fn sync_environment(&self) {
+		let environ: BTreeMap<String, String> = self.vars.iter()
+			.filter(|(_, var)| var.exported)
+			.map(|(name, var)| (name.clone(), var.value.clone()))
+			.collect();
+		crate::process::with_current_process(|p| p.environ = environ);
+	}
+
+	/// Value of a variable reference, including the special `$?`, `$$`, `$#`,
+	/// `$@`, and positional parameters
+	fn lookup(&self, name: &str) -> String {
+		match name {
+			"?" => self.last_status.to_string(),
+			"$" => crate::process::current_pid().map_or(0, |pid| pid.0).to_string(),
+			"#" => (self.positional.len() - 1).to_string(),
+			"@" | "*" => self.positional[1..].join(" "),
+			_ if name.bytes().all(|b| b.is_ascii_digit()) => {
+				name.parse().ok().and_then(|n: usize| self.positional.get(n)).cloned().unwrap_or_default()
+			}
+			_ => String::from(self.var(name).unwrap_or("")),
+		}
+	}
+
+	/// Expand a word into fields: variables are substituted, unquoted
+	/// variable values are split on whitespace, and quotes are removed
+	fn expand_fields(&self, word: &Word) -> Vec<Field> {
+		let mut fields = Vec::new();
+		let mut current = Field::default();
+		let mut has_current = false;
+
+		for part in &word.0 {
+			match part {
+				Part::Literal(text) => {
+					current.push_str(text, false);
+					has_current = true;
+				}
+				Part::Quoted(text) => {
+					current.push_str(text, true);
+					has_current = true;
+				}
+				Part::QuotedVar(name) => {
+					current.push_str(&self.lookup(name), true);
+					has_current = true;
+				}
+				Part::Var(name) => {
+					let value = self.lookup(name);
+					let mut pieces = value.split_whitespace().peekable();
+					if value.starts_with(char::is_whitespace) && has_current {
+						fields.push(core::mem::take(&mut current));
+						has_current = false;
+					}
+					while let Some(piece) = pieces.next() {
+						current.push_str(piece, false);
+						has_current = true;
+						if pieces.peek().is_some() {
+							fields.push(core::mem::take(&mut current));
+						}
+					}
+					if value.ends_with(char::is_whitespace) && has_current {
+						fields.push(core::mem::take(&mut current));
+						has_current = false;
+					}
+				}
+			}
+		}
+		if has_current {
+			fields.push(current);
+		}
+		fields
+	}
+
+	/// Expand a word into fields without pathname expansion
+	fn expand_word(&self, word: &Word) -> Vec<String> {
+		self.expand_fields(word).into_iter().map(|field| field.text).collect()
+	}
+
+	/// Expand a list of words into fields, including pathname expansion
+	fn expand_words(&self, words: &[Word]) -> Result<Vec<String>, String> {
+		let mut result = Vec::new();
+		for field in words.iter().flat_map(|word| self.expand_fields(word)) {
+			if !glob::has_wildcards(&field.pattern) {
+				result.push(field.text);
+				continue;
+			}
+			let matches = glob::expand(&field.pattern, &syscall::visible_path("."));
+			if !matches.is_empty() {
+				result.extend(matches);
+				continue;
+			}
+			match self.glob_nomatch {
+				NoMatch::Keep => result.push(field.text),
+				NoMatch::Null => {}
+				NoMatch::Fail => return Err(format!("no match: {}", field.text)),
+			}
+		}
+		Ok(result)
+	}
+
+	/// Tokenize and expand free text (such as a `for` word list) into fields
+	fn expand_text(&self, text: &str) -> Result<Vec<String>, String> {
+		let words: Vec<Word> = parser::tokenize(text).map_err(String::from)?
+			.into_iter()
+			.filter_map(|token| match token {
+				parser::Token::Word(word) => Some(word),
+				_ => None,
+			})
+			.collect();
+		self.expand_words(&words)
+	}
+
+	/// Make this shell's console and process the current ones, before it
+	/// handles input or runs jobs
+	fn activate(&self) {
+		vga_buffer::select_output(self.console);
+		if let Some(pid) = self.pid {
+			process::set_current_pid(pid);
+		}
+	}
+
+	/// Start the shell and display the prompt
+	pub fn start(&mut self) {
+		println!("\nWelcome to ScottOS Shell v0.1.0 (tty{})", self.console + 1);
+		println!("Type 'help' for available commands");
+		self.show_prompt();
+	}
+
+	/// Process a decoded key press, routing it to the editor while one is open
+	pub fn process_key(&mut self, press: KeyPress) {
+		if let Some(editor) = self.editor.as_mut() {
+			if press.key == DecodedKey::Unicode(CTRL_C) {
+				// The editor has nothing to interrupt
+				keyboard::clear_interrupt();
+			}
+			let action = if press.key == DecodedKey::Unicode(CTRL_Z) {
+				None
+			} else {
+				Some(editor.handle_key(press))
+			};
+			match action {
+				Some(editor::EditorAction::Continue) => editor.render(),
+				Some(editor::EditorAction::Quit) | None => {
+					let editor = self.editor.take().expect("editor is open");
+					x86_64::instructions::interrupts::without_interrupts(|| {
+						crate::vga_buffer::WRITER.lock().clear_screen();
+					});
+					if action.is_none() {
+						self.suspend_editor(editor);
+					}
+					self.notify_jobs();
+					self.show_prompt();
+					jobs::wake_runner();
+				}
+			}
+			return;
+		}
+		if let DecodedKey::Unicode(c) = press.key {
+			if self.login.is_some() {
+				self.login_char(c);
+			} else {
+				self.process_char(c);
+			}
+		}
+	}
+
+	/// Process a character input from the keyboard
+	pub fn process_char(&mut self, c: char) {
+		match c {
+			'\n' | '\r' => {
+				// Enter pressed - execute command
+				println!();
+				if self.current_pos > 0 {
+					// Copy the line out so the command can borrow the shell mutably
+					let command_str = String::from(
+						core::str::from_utf8(&self.current_line[..self.current_pos]).unwrap_or("")
+					);
+					// What the command reads from the console comes from the keyboard
+					crate::tty::set_foreground(self.console, true);
+					self.execute_line(&command_str);
+					crate::tty::set_foreground(self.console, false);
+					if keyboard::clear_interrupt() {
+						println!("^C");
+					}
+					
+					// Add to history
+					if self.history_count < MAX_HISTORY {
+						self.command_history[self.history_count][..self.current_pos]
+							.copy_from_slice(&self.current_line[..self.current_pos]);
+						// Clear the rest
+						for i in self.current_pos..MAX_COMMAND_LEN {
+							self.command_history[self.history_count][i] = 0;
+						}
+						self.history_count += 1;
+					}
+					
+					// Clear current line
+					self.current_line = [0; MAX_COMMAND_LEN];
+					self.current_pos = 0;
+				}
+				// An editor opened by the command owns the screen until it quits
+				if self.editor.is_none() {
+					self.notify_jobs();
+					self.show_prompt();
+				}
+			}
+			CTRL_C => {
+				// Already acknowledged if it interrupted a running command
+				if keyboard::clear_interrupt() {
+					println!("^C");
+					self.current_line = [0; MAX_COMMAND_LEN];
+					self.current_pos = 0;
+					self.last_status = STATUS_INTERRUPTED;
+					self.notify_jobs();
+					self.show_prompt();
+				}
+			}
+			CTRL_L => {
+				x86_64::instructions::interrupts::without_interrupts(|| {
+					crate::vga_buffer::WRITER.lock().clear_screen();
+				});
+				self.redraw_prompt();
+			}
+			CTRL_D if self.current_pos == 0 => {
+				// End of input at an empty prompt ends the session, as `exit` does
+				println!("exit");
+				self.run_builtin("exit", &[]);
+			}
+			'\u{8}' => {
+				// Backspace pressed
+				if self.current_pos > 0 {
+					self.current_pos -= 1;
+					self.current_line[self.current_pos] = 0;
+					print!("\u{8} \u{8}"); // Backspace, space, backspace to clear character
+				}
+			}
+			c if c.is_ascii() && !c.is_control() && self.current_pos < MAX_COMMAND_LEN - 1 => {
+				// Regular ASCII character
+				self.current_line[self.current_pos] = c as u8;
+				self.current_pos += 1;
+				print!("{}", c);
+			}
+			_ => {
+				// Ignore other characters (function keys, etc.)
+			}
+		}
+	}
+
+	/// Display the shell prompt, expanded from `PS1`
+	fn show_prompt(&self) {
+		let cwd = syscall::visible_path(".");
+		let ctx = prompt::PromptContext {
+			user: self.var("USER").unwrap_or("root"),
+			host: self.var("HOSTNAME").unwrap_or(prompt::DEFAULT_HOSTNAME),
+			cwd: &cwd,
+			home: self.var("HOME"),
+		};
+		prompt::print_colored(&prompt::expand(self.var("PS1").unwrap_or(prompt::DEFAULT_PS1), &ctx));
+	}
+
+	/// Display the prompt again along with any partially typed line
+	fn redraw_prompt(&self) {
+		self.show_prompt();
+		print!("{}", core::str::from_utf8(&self.current_line[..self.current_pos]).unwrap_or(""));
+	}
+
+	/// Execute a `;`/`&&`/`||` chain of commands, recording each status in `$?`
+	fn execute_line(&mut self, line: &str) -> i32 {
+		let chain = match parser::parse_line(line, &self.aliases) {
+			Ok(chain) => chain,
+			Err(msg) => {
+				println!("sh: {}", msg);
+				self.last_status = 2;
+				return 2;
+			}
+		};
+
+		for (connector, pipeline) in chain {
+			let run = match connector {
+				Connector::Always => true,
+				Connector::And => self.last_status == 0,
+				Connector::Or => self.last_status != 0,
+			};
+			if run {
+				self.last_status = if pipeline.background {
+					self.launch_job(pipeline)
+				} else {
+					self.execute_pipeline(&pipeline)
+				};
+			}
+			if keyboard::interrupt_requested() {
+				self.last_status = STATUS_INTERRUPTED;
+				break;
+			}
+		}
+		self.last_status
+	}
+
+	/// Run a pipeline, returning the status of its last command
+	///
+	/// Stages run one after another, each writing into a pipe that the next
+	/// stage then reads as standard input.
+	fn execute_pipeline(&mut self, pipeline: &Pipeline) -> i32 {
+		let Some((last, stages)) = pipeline.commands.split_last() else {
+			return 0;
+		};
+		let mut input = None;
+		for command in stages {
+			if keyboard::interrupt_requested() || self.job_cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+				break;
+			}
+			let (read_fd, write_fd) = match syscall::sys_pipe() {
+				Ok(fds) => fds,
+				Err(err) => {
+					errln!("sh: pipe: {}", err.as_str());
+					if let Some(fd) = input {
+						let _ = syscall::sys_close(fd);
+					}
+					return 1;
+				}
+			};
+			self.with_stdio(input, Some(write_fd), |shell| shell.execute_command(command));
+			let _ = syscall::sys_close(write_fd);
+			if let Some(fd) = input {
+				let _ = syscall::sys_close(fd);
+			}
+			input = Some(read_fd);
+		}
+		let status = self.with_stdio(input, None, |shell| shell.execute_command(last));
+		if let Some(fd) = input {
+			let _ = syscall::sys_close(fd);
+		}
+		status
+	}
+
+	/// Run `f` with standard input and/or output temporarily pointed at other descriptors
+	fn with_stdio<R>(&mut self, stdin: Option<usize>, stdout: Option<usize>, f: impl FnOnce(&mut Self) -> R) -> R {
+		let mut saved = Vec::new();
+		for (fd, source) in [(0, stdin), (1, stdout)] {
+			let Some(source) = source else {
+				continue;
+			};
+			if let Ok(backup) = syscall::sys_dup(fd) {
+				if syscall::sys_dup2(source, fd).is_ok() {
+					saved.push((fd, backup));
+				} else {
+					let _ = syscall::sys_close(backup);
+				}
+			}
+		}
+		let result = f(self);
+		restore_redirects(&saved);
+		result
+	}
+
+	/// Expand and execute a single command, applying any I/O redirections
+	/// around it, and return its exit status
+	fn execute_command(&mut self, command: &SimpleCommand) -> i32 {
+		let mut redirects = Vec::new();
+		for redirect in &command.redirects {
+			let mut targets = self.expand_word(&redirect.target);
+			if targets.len() != 1 {
+				println!("sh: ambiguous redirect");
+				return 1;
+			}
+			redirects.push((redirect.fd, redirect.kind, targets.remove(0)));
+		}
+		let argv = match self.expand_words(&command.words) {
+			Ok(argv) => argv,
+			Err(msg) => {
+				println!("sh: {}", msg);
+				return 1;
+			}
+		};
+
+		let saved = match apply_redirects(&redirects) {
+			Ok(saved) => saved,
+			Err((path, err)) => {
+				println!("sh: {}: {}", path, err.as_str());
+				return 1;
+			}
+		};
+		let status = self.run_simple_command(&argv);
+		restore_redirects(&saved);
+		status
+	}
+
+	/// Run a single expanded command
+	fn run_simple_command(&mut self, argv: &[String]) -> i32 {
+		let Some((cmd, args)) = argv.split_first() else {
+			return 0;
+		};
+
+		if let Some((name, value)) = cmd.split_once('=') {
+			if is_var_name(name) && args.is_empty() {
+				self.set_var(name, value, false);
+				return 0;
+			}
+		}
+
+		let args: Vec<&str> = args.iter().map(String::as_str).collect();
+		match self.run_builtin(cmd, &args) {
+			Some(status) => status,
+			None => self.run_external(argv),
+		}
+	}
+}
+
+/// Read a whole file through the syscall layer
+fn read_file(path: &str) -> Result<Vec<u8>, SyscallError> {
+	let fd = syscall::sys_open(path, O_RDONLY, 0)?;
+	let result = read_fd(fd);
+	let _ = syscall::sys_close(fd);
+	result
+}
+
+/// Read everything from an open descriptor until end-of-file
+fn read_fd(fd: usize) -> Result<Vec<u8>, SyscallError> {
+	let mut data = Vec::new();
+	let mut buf = [0u8; 128];
+	while !keyboard::interrupt_requested() {
+		match syscall::sys_read(fd, &mut buf)? {
+			0 => return Ok(data),
+			n => data.extend_from_slice(&buf[..n]),
+		}
+	}
+	Err(SyscallError::InterruptedSystemCall)
+}
+
+/// Create or truncate a file and write `data` to it through the syscall layer
+fn write_file(path: &str, data: &[u8]) -> Result<(), SyscallError> {
+	let fd = syscall::sys_open(path, O_WRONLY | O_CREAT | O_TRUNC, 0o644)?;
+	let result = write_all(fd, data);
+	let _ = syscall::sys_close(fd);
+	result
+}
+
+/// Write all of `data` to `fd`, however many writes it takes
+fn write_all(fd: usize, data: &[u8]) -> Result<(), SyscallError> {
+	let mut written = 0;
+	while written < data.len() {
+		written += syscall::sys_write(fd, &data[written..])?;
+	}
+	Ok(())
+}
+
+/// Copy everything readable from `fd` to standard output
+fn copy_fd(fd: usize) {
+	let mut buf = [0u8; 128];
+	while let Ok(n) = syscall::sys_read(fd, &mut buf) {
+		if n == 0 || keyboard::interrupt_requested() || syscall::sys_write(1, &buf[..n]).is_err() {
+			break;
+		}
+	}
+}
+
+/// Global shell instance for async keyboard processing
+use crate::task::sync::Mutex;
+use lazy_static::lazy_static;
+
+lazy_static! {
+	/// The shell of each virtual console; an async lock, so a console's task
+	/// yields while a background job holds its shell instead of spinning,
+	/// and commands run under it can wait for interrupts
+	static ref SHELLS: [Mutex<Shell>; CONSOLE_COUNT] = core::array::from_fn(|console| {
+		Mutex::new(Shell { console, ..Shell::new() })
+	});
+}
+
+/// Initialize the shell of every console, each running as its own process
+///
+/// The first console's shell runs as the boot process and alone runs
+/// `/etc/rc`, as root; then every console asks who is logging in, unless
+/// booted with `login=off`, which logs in root. Each shell sources
+/// `/etc/shellrc` once someone has.
+pub fn init_shell() {
+	let autologin = match crate::cmdline::param("login") {
+		Some("off") => crate::users::by_name("root"),
+		_ => None,
+	};
+	vga_buffer::init_consoles();
+	let boot_pid = process::current_pid();
+	for (console, shell) in SHELLS.iter().enumerate() {
+		let mut shell = shell.lock_blocking();
+		shell.pid = match console {
+			0 => boot_pid,
+			_ => Some(process::spawn_process(String::from("sh"), boot_pid)),
+		};
+		shell.activate();
+		shell.sync_environment();
+		let home = String::from(shell.var("HOME").unwrap_or("/"));
+		let _ = shell.change_dir(&home);
+		if console == 0 && crate::fs::with_filesystem(|fs| fs.stat("/etc/rc")).is_ok() {
+			shell.run_script_file("/etc/rc");
+		}
+		match &autologin {
+			Some(root) => shell.log_in(root),
+			None => shell.begin_login(),
+		}
+	}
+	SHELLS[0].lock_blocking().activate();
+}
+
+/// Async task feeding the keys typed on one console to its shell
+pub async fn run_console(console: usize) {
+	let mut keys = keyboard::KeyStream::new(console);
+	while let Some(key) = keys.next().await {
+		let mut shell = SHELLS[console].lock().await;
+		shell.activate();
+		// Commands run to completion inside this poll
+		crate::task::blocking(|| shell.process_key(key));
+	}
+}
+
+/// Test variable expansion, quoting, and field splitting
+#[test_case]
+fn test_expand_word() {
+	let mut shell = Shell::new();
+	shell.vars.insert("FOO".to_string(), ShellVar { value: "a  b".to_string(), exported: false });
+	assert_eq!(shell.expand_text("$FOO \"$FOO\" x${FOO}y '$FOO' $MISSING").unwrap(),
+		["a", "b", "a  b", "xa", "by", "$FOO"]);
+	assert_eq!(shell.expand_text("\"\" cost: $ 5").unwrap(), ["", "cost:", "$", "5"]);
+	assert_eq!(shell.expand_text("/etc/pass* '/etc/*' /nothing/*").unwrap(), ["/etc/passwd", "/etc/*", "/nothing/*"]);
+	shell.glob_nomatch = NoMatch::Null;
+	assert!(shell.expand_text("/nothing/*").unwrap().is_empty());
+}
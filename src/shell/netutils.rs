@@ -0,0 +1,422 @@
+use super::Shell;
+use crate::net::{self, dns, ethernet, icmp::Pinger, ipv4::SendError, pcap, tcp, udp, InterfaceInfo, IpConfig};
+use crate::task::keyboard;
+use crate::time::{DateTime, Instant};
+use alloc::{format, string::String, vec::Vec};
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::time::Duration;
+
+/// Echo requests `ping` sends unless told otherwise
+const PING_COUNT: u16 = 4;
+/// Bytes of data in each echo request, as other pings send
+const PING_DATA: usize = 56;
+/// Time between echo requests, and how long the last one is waited for
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A duration in milliseconds to three places, as `ping` shows times
+fn format_ms(duration: Duration) -> String {
+	let micros = duration.as_micros();
+	format!("{}.{:03}", micros / 1000, micros % 1000)
+}
+
+/// Parse `ping`'s arguments: the host and the number of requests
+fn parse_ping_args<'a>(args: &[&'a str]) -> Result<(&'a str, u16), &'static str> {
+	let mut count = PING_COUNT;
+	let mut host = None;
+	let mut args = args.iter();
+	while let Some(&arg) = args.next() {
+		match arg {
+			"-c" => {
+				let value = args.next().ok_or("option requires an argument -- 'c'")?;
+				count = value.parse().ok().filter(|&count| count > 0).ok_or("invalid count")?;
+			}
+			_ if host.is_none() => host = Some(arg),
+			_ => return Err("usage: ping HOST [-c COUNT]"),
+		}
+	}
+	Ok((host.ok_or("usage: ping HOST [-c COUNT]")?, count))
+}
+
+/// An interface's flags, as `netstat -i` shows them: broadcast or
+/// loopback, running, and up
+fn interface_flags(info: &InterfaceInfo) -> String {
+	let mut flags = String::from(if info.loopback { "L" } else { "B" });
+	if info.link_up {
+		flags.push('R');
+	}
+	if info.up {
+		flags.push('U');
+	}
+	flags
+}
+
+/// A socket's address as `netstat` shows it, `*` standing for any port
+fn socket_address(address: Option<SocketAddrV4>) -> String {
+	match address {
+		Some(address) if address.port() != 0 => format!("{}", address),
+		Some(address) => format!("{}:*", address.ip()),
+		None => String::from("0.0.0.0:*"),
+	}
+}
+
+/// Parse `tcpdump`'s arguments: the interface to watch, if not all of
+/// them, how many frames to print, if not until Ctrl+C, and whether to dump
+/// their bytes
+fn parse_tcpdump_args<'a>(args: &[&'a str]) -> Result<(Option<&'a str>, Option<usize>, bool), &'static str> {
+	let (mut interface, mut count, mut hex) = (None, None, false);
+	let mut args = args.iter();
+	while let Some(&arg) = args.next() {
+		match arg {
+			"-i" => interface = Some(*args.next().ok_or("option requires an argument -- 'i'")?),
+			"-c" => {
+				let value = args.next().ok_or("option requires an argument -- 'c'")?;
+				count = Some(value.parse().ok().filter(|&count| count > 0).ok_or("invalid count")?);
+			}
+			"-x" => hex = true,
+			_ => return Err("usage: tcpdump [-i IFACE] [-c COUNT] [-x]"),
+		}
+	}
+	Ok((interface, count, hex))
+}
+
+/// Print an interface the way `ifconfig` shows it
+fn show_interface(info: &InterfaceInfo) {
+	let mut flags = Vec::new();
+	if info.up {
+		flags.push("UP");
+	}
+	flags.push(if info.loopback { "LOOPBACK" } else { "BROADCAST" });
+	if info.link_up {
+		flags.push("RUNNING");
+	}
+	outln!("{}: flags=<{}>  mtu {}", info.name, flags.join(","), ethernet::MTU);
+	if let Some(address) = info.ip.address {
+		let broadcast = if info.loopback { String::new() } else { format!("  broadcast {}", info.ip.broadcast().unwrap_or(address)) };
+		outln!("        inet {}  netmask {}{}", address, info.ip.netmask(), broadcast);
+	}
+	if let Some(gateway) = info.ip.gateway {
+		outln!("        gateway {}", gateway);
+	}
+	if info.loopback {
+		outln!("        loop");
+	} else {
+		outln!("        ether {}", info.mac);
+	}
+	outln!("        RX packets {}  bytes {}", info.rx_packets, info.rx_bytes);
+	outln!("        TX packets {}  bytes {}", info.tx_packets, info.tx_bytes);
+}
+
+impl Shell {
+	/// Send echo requests and report the replies: `ping HOST [-c COUNT]`
+	pub(super) fn cmd_ping(&self, args: &[&str]) -> i32 {
+		let (host, count) = match parse_ping_args(args) {
+			Ok(parsed) => parsed,
+			Err(msg) => {
+				errln!("ping: {}", msg);
+				return 2;
+			}
+		};
+		let destination = match dns::resolve(host) {
+			Ok(address) => address,
+			Err(err) => {
+				errln!("ping: {}: {}", host, err.as_str());
+				return 2;
+			}
+		};
+		let pinger = Pinger::new();
+		let data: Vec<u8> = (0..PING_DATA as u8).collect();
+		let mut sent_at = Vec::new();
+		let (mut received, mut min, mut max, mut total) = (0u32, Duration::MAX, Duration::ZERO, Duration::ZERO);
+		if host == format!("{}", destination) {
+			outln!("PING {}: {} data bytes", destination, PING_DATA);
+		} else {
+			outln!("PING {} ({}): {} data bytes", host, destination, PING_DATA);
+		}
+		'pinging: for sequence in 1..=count {
+			match pinger.send(destination, sequence, &data) {
+				Ok(()) => {}
+				Err(SendError::NoRoute) => {
+					errln!("ping: {}: network is unreachable", destination);
+					return 1;
+				}
+				Err(SendError::TooLong) => {
+					errln!("ping: message too long");
+					return 1;
+				}
+			}
+			let start = Instant::now();
+			sent_at.push(start);
+			while start.elapsed() < PING_INTERVAL {
+				net::poll();
+				while let Some(reply) = pinger.take_reply() {
+					let Some(&sent) = sent_at.get(usize::from(reply.sequence).wrapping_sub(1)) else {
+						continue;
+					};
+					let rtt = reply.received - sent;
+					received += 1;
+					(min, max, total) = (min.min(rtt), max.max(rtt), total + rtt);
+					outln!("{} bytes from {}: icmp_seq={} ttl={} time={} ms", reply.length, reply.source, reply.sequence, reply.ttl, format_ms(rtt));
+				}
+				if keyboard::interrupt_requested() {
+					break 'pinging;
+				}
+				// Frames and timer ticks both wake us
+				x86_64::instructions::hlt();
+			}
+		}
+		let sent = sent_at.len() as u32;
+		outln!("--- {} ping statistics ---", destination);
+		outln!("{} packets transmitted, {} received, {}% packet loss", sent, received, (sent - received.min(sent)) * 100 / sent.max(1));
+		if received > 0 {
+			outln!("rtt min/avg/max = {}/{}/{} ms", format_ms(min), format_ms(total / received), format_ms(max));
+		}
+		if received > 0 { 0 } else { 1 }
+	}
+
+	/// Show or change interfaces: `ifconfig [IFACE [ADDR/PREFIX[,GW] | off | up | down]]`
+	pub(super) fn cmd_ifconfig(&self, args: &[&str]) -> i32 {
+		let interfaces = net::interfaces();
+		let (name, setting) = match args {
+			[] => {
+				for (index, info) in interfaces.iter().enumerate() {
+					if index > 0 {
+						outln!("");
+					}
+					show_interface(info);
+				}
+				return 0;
+			}
+			[name] => (*name, None),
+			[name, setting] => (*name, Some(*setting)),
+			_ => {
+				errln!("usage: ifconfig [IFACE [ADDR/PREFIX[,GW] | off | up | down]]");
+				return 2;
+			}
+		};
+		let Some(index) = net::find(name) else {
+			errln!("ifconfig: {}: no such interface", name);
+			return 1;
+		};
+		match setting {
+			None => show_interface(&interfaces[index]),
+			Some("up") => {
+				net::set_up(index, true);
+			}
+			Some("down") => {
+				net::set_up(index, false);
+			}
+			Some(text) => match IpConfig::parse(text) {
+				Some(config) => {
+					net::configure(index, config);
+				}
+				None => {
+					errln!("ifconfig: {}: expected ADDR/PREFIX[,GW], off, up, or down", text);
+					return 2;
+				}
+			},
+		}
+		0
+	}
+
+	/// Show sockets, or interface counters: `netstat [-a | -l] [-t] [-u] [-i]`
+	pub(super) fn cmd_netstat(&self, args: &[&str]) -> i32 {
+		let (mut all, mut listening, mut tcp_only, mut udp_only, mut interfaces) = (false, false, false, false, false);
+		for arg in args {
+			let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+				errln!("usage: netstat [-a | -l] [-t] [-u] [-i]");
+				return 2;
+			};
+			for flag in flags.chars() {
+				match flag {
+					'a' => all = true,
+					'l' => listening = true,
+					't' => tcp_only = true,
+					'u' => udp_only = true,
+					'i' => interfaces = true,
+					_ => {
+						errln!("netstat: invalid option -- '{}'", flag);
+						return 2;
+					}
+				}
+			}
+		}
+		if interfaces {
+			outln!("{:<8} {:>5} {:>8} {:>10} {:>8} {:>10} Flg", "Iface", "MTU", "RX-OK", "RX-bytes", "TX-OK", "TX-bytes");
+			for info in net::interfaces() {
+				outln!(
+					"{:<8} {:>5} {:>8} {:>10} {:>8} {:>10} {}",
+					info.name,
+					ethernet::MTU,
+					info.rx_packets,
+					info.rx_bytes,
+					info.tx_packets,
+					info.tx_bytes,
+					interface_flags(&info)
+				);
+			}
+			if net::input_dropped() > 0 {
+				outln!("{} frames dropped with the input queue full", net::input_dropped());
+			}
+			return 0;
+		}
+		let (show_tcp, show_udp) = if tcp_only || udp_only { (tcp_only, udp_only) } else { (true, true) };
+		// Without -a or -l only connections are shown, as UDP sockets have none
+		let shown = |listener: bool| all || listener == listening;
+		outln!("{:<5} {:>6} {:>6} {:<22} {:<22} State", "Proto", "Recv-Q", "Send-Q", "Local Address", "Foreign Address");
+		if show_tcp {
+			for socket in tcp::sockets().iter().filter(|socket| shown(socket.state == tcp::State::Listen)) {
+				let local = socket_address(Some(socket.local));
+				outln!("{:<5} {:>6} {:>6} {:<22} {:<22} {}", "tcp", socket.receive_queue, socket.send_queue, local, socket_address(socket.remote), socket.state.name());
+			}
+		}
+		if show_udp {
+			for socket in udp::sockets().iter().filter(|_| shown(true)) {
+				let local = socket_address(Some(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, socket.port.unwrap_or(0))));
+				outln!("{:<5} {:>6} {:>6} {:<22} {:<22}", "udp", socket.receive_queue, 0, local, socket_address(None));
+			}
+		}
+		0
+	}
+
+	/// Print frames as they pass: `tcpdump [-i IFACE] [-c COUNT] [-x]`
+	pub(super) fn cmd_tcpdump(&self, args: &[&str]) -> i32 {
+		let (interface, count, hex) = match parse_tcpdump_args(args) {
+			Ok(parsed) => parsed,
+			Err(msg) => {
+				errln!("tcpdump: {}", msg);
+				return 2;
+			}
+		};
+		let names: Vec<String> = net::interfaces().into_iter().map(|info| info.name).collect();
+		let index = match interface {
+			Some(name) => match names.iter().position(|known| known == name) {
+				Some(index) => Some(index),
+				None => {
+					errln!("tcpdump: {}: no such interface", name);
+					return 1;
+				}
+			},
+			None => None,
+		};
+		outln!("tcpdump: listening on {}, snapshot length {} bytes", interface.unwrap_or("all interfaces"), pcap::SNAPLEN);
+		let was_enabled = pcap::enabled();
+		pcap::set_enabled(true);
+		let (mut seq, mut printed) = (pcap::next_seq(), 0);
+		'watching: loop {
+			net::poll();
+			while let Some((next, capture)) = pcap::read_from(seq) {
+				seq = next + 1;
+				if index.is_some_and(|index| index != capture.interface) {
+					continue;
+				}
+				let seconds = capture.time_ns.max(0) / 1_000_000_000;
+				let micros = capture.time_ns.max(0) % 1_000_000_000 / 1000;
+				let name = names.get(capture.interface).map_or("?", |name| name.as_str());
+				let direction = if capture.outgoing { "Out" } else { "In" };
+				let time = DateTime::from_timestamp(seconds as u64).format("%T");
+				outln!("{}.{:06} {} {} {}", time, micros, name, direction, pcap::describe(capture.data()));
+				if hex {
+					for (line, bytes) in capture.data().chunks(16).enumerate() {
+						let words: Vec<String> = bytes.chunks(2).map(|pair| pair.iter().map(|byte| format!("{:02x}", byte)).collect()).collect();
+						outln!("\t0x{:04x}:  {}", line * 16, words.join(" "));
+					}
+				}
+				printed += 1;
+				if count.is_some_and(|count| printed >= count) {
+					break 'watching;
+				}
+			}
+			if keyboard::interrupt_requested() {
+				break;
+			}
+			x86_64::instructions::hlt();
+		}
+		pcap::set_enabled(was_enabled);
+		outln!("{} packets captured", printed);
+		0
+	}
+
+	/// Look up the addresses of a host name: `nslookup NAME [SERVER]`
+	pub(super) fn cmd_nslookup(&self, args: &[&str]) -> i32 {
+		let (name, server) = match args {
+			[name] => (*name, dns::nameserver()),
+			[name, server] => match server.parse::<Ipv4Addr>() {
+				Ok(server) => (*name, Some(server)),
+				Err(_) => {
+					errln!("nslookup: invalid server address: {}", server);
+					return 2;
+				}
+			},
+			_ => {
+				errln!("usage: nslookup NAME [SERVER]");
+				return 2;
+			}
+		};
+		let Some(server) = server else {
+			errln!("nslookup: {}", dns::DnsError::NoNameserver.as_str());
+			return 1;
+		};
+		outln!("Server:\t\t{}", server);
+		outln!("Address:\t{}#{}", server, dns::PORT);
+		outln!("");
+		let result = if args.len() == 2 { dns::lookup_with(server, name) } else { dns::lookup(name) };
+		match result {
+			Ok(addresses) => {
+				for address in addresses {
+					outln!("Name:\t{}", name);
+					outln!("Address: {}", address);
+				}
+				0
+			}
+			Err(err) => {
+				errln!("** server can't find {}: {}", name, err.as_str());
+				1
+			}
+		}
+	}
+}
+
+/// Test parsing `ping`'s arguments
+#[test_case]
+fn test_ping_args() {
+	assert_eq!(parse_ping_args(&["10.0.2.2"]), Ok(("10.0.2.2", PING_COUNT)));
+	assert_eq!(parse_ping_args(&["-c", "2", "host"]), Ok(("host", 2)));
+	assert!(parse_ping_args(&["10.0.2.2", "-c", "0"]).is_err());
+	assert!(parse_ping_args(&["10.0.2.2", "host"]).is_err());
+	assert!(parse_ping_args(&[]).is_err());
+	assert_eq!(format_ms(Duration::from_micros(1_234)), "1.234");
+}
+
+/// Test parsing `tcpdump`'s arguments
+#[test_case]
+fn test_tcpdump_args() {
+	assert_eq!(parse_tcpdump_args(&[]), Ok((None, None, false)));
+	assert_eq!(parse_tcpdump_args(&["-i", "lo", "-c", "3", "-x"]), Ok((Some("lo"), Some(3), true)));
+	assert!(parse_tcpdump_args(&["-c", "0"]).is_err());
+	assert!(parse_tcpdump_args(&["-i"]).is_err());
+	assert!(parse_tcpdump_args(&["port", "80"]).is_err());
+}
+
+/// Test how `netstat` shows addresses and interface flags
+#[test_case]
+fn test_netstat_format() {
+	assert_eq!(socket_address(Some(SocketAddrV4::new(Ipv4Addr::new(10, 0, 2, 15), 80))), "10.0.2.15:80");
+	assert_eq!(socket_address(Some(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))), "0.0.0.0:*");
+	assert_eq!(socket_address(None), "0.0.0.0:*");
+
+	let mut info = InterfaceInfo {
+		name: String::from("lo"),
+		mac: net::MacAddress([0; 6]),
+		link_up: true,
+		up: true,
+		loopback: true,
+		ip: IpConfig::LOOPBACK,
+		rx_packets: 0,
+		rx_bytes: 0,
+		tx_packets: 0,
+		tx_bytes: 0,
+	};
+	assert_eq!(interface_flags(&info), "LRU");
+	(info.loopback, info.up) = (false, false);
+	assert_eq!(interface_flags(&info), "BR");
+}
@@ -0,0 +1,545 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt;
+
+/// A piece of a shell word, remembering how it was quoted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Part {
+	/// Unquoted text
+	Literal(String),
+	/// Text from single quotes, double quotes, or a backslash escape
+	Quoted(String),
+	/// Unquoted `$NAME` (subject to field splitting)
+	Var(String),
+	/// `$NAME` inside double quotes
+	QuotedVar(String),
+}
+
+/// A shell word before expansion
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Word(pub Vec<Part>);
+
+impl Word {
+	/// Append text to the word, merging with a trailing part of the same kind
+	fn push_text(&mut self, c: char, quoted: bool) {
+		match (self.0.last_mut(), quoted) {
+			(Some(Part::Literal(s)), false) | (Some(Part::Quoted(s)), true) => s.push(c),
+			_ => {
+				let text = String::from(c);
+				self.0.push(if quoted { Part::Quoted(text) } else { Part::Literal(text) });
+			}
+		}
+	}
+
+	/// The word as plain text if it contains no quoting or expansions
+	pub fn as_literal(&self) -> Option<&str> {
+		match self.0.as_slice() {
+			[Part::Literal(s)] => Some(s),
+			_ => None,
+		}
+	}
+}
+
+/// Command separators and control operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+	/// `;`
+	Semi,
+	/// `&&`
+	And,
+	/// `||`
+	Or,
+	/// `|`
+	Pipe,
+	/// `&`
+	Background,
+}
+
+/// What a redirection does with its target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKind {
+	/// `<`: open the target for reading
+	Read,
+	/// `>`: create/truncate the target for writing
+	Write,
+	/// `>>`: create/append to the target
+	Append,
+	/// `>&`: duplicate the target descriptor number
+	Dup,
+}
+
+/// A lexical token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+	Word(Word),
+	Op(Operator),
+	/// A redirection operator applying to `fd`; the next token is its target
+	Redirect(usize, RedirectKind),
+}
+
+/// Characters that end an unquoted word
+fn is_metachar(c: char) -> bool {
+	c.is_whitespace() || matches!(c, ';' | '&' | '|' | '<' | '>')
+}
+
+/// Whether a character can appear in a variable name
+fn is_name_char(c: char) -> bool {
+	c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Split a command line into tokens, handling quotes, escapes, and comments
+pub fn tokenize(line: &str) -> Result<Vec<Token>, &'static str> {
+	let mut tokens = Vec::new();
+	let mut chars = line.chars().peekable();
+	let mut word: Option<Word> = None;
+
+	while let Some(c) = chars.next() {
+		let op = match c {
+			c if c.is_whitespace() => None,
+			'#' if word.is_none() => break,
+			';' => Some(Token::Op(Operator::Semi)),
+			'&' if chars.peek() == Some(&'&') => {
+				chars.next();
+				Some(Token::Op(Operator::And))
+			}
+			'&' => Some(Token::Op(Operator::Background)),
+			'|' if chars.peek() == Some(&'|') => {
+				chars.next();
+				Some(Token::Op(Operator::Or))
+			}
+			'|' => Some(Token::Op(Operator::Pipe)),
+			'<' | '>' => {
+				// A bare digit right before the operator names the descriptor
+				let explicit_fd = word.as_ref()
+					.and_then(Word::as_literal)
+					.filter(|s| s.len() == 1)
+					.and_then(|s| s.parse::<usize>().ok());
+				if explicit_fd.is_some() {
+					word = None;
+				}
+				let kind = if c == '<' {
+					RedirectKind::Read
+				} else if chars.peek() == Some(&'>') {
+					chars.next();
+					RedirectKind::Append
+				} else if chars.peek() == Some(&'&') {
+					chars.next();
+					RedirectKind::Dup
+				} else {
+					RedirectKind::Write
+				};
+				let default_fd = if c == '<' { 0 } else { 1 };
+				Some(Token::Redirect(explicit_fd.unwrap_or(default_fd), kind))
+			}
+			_ => {
+				let w = word.get_or_insert_with(Word::default);
+				lex_word_char(c, &mut chars, w)?;
+				continue;
+			}
+		};
+
+		if let Some(w) = word.take() {
+			tokens.push(Token::Word(w));
+		}
+		if let Some(op) = op {
+			tokens.push(op);
+		}
+	}
+
+	if let Some(w) = word.take() {
+		tokens.push(Token::Word(w));
+	}
+	Ok(tokens)
+}
+
+/// Lex one (possibly multi-character) element of a word
+fn lex_word_char<I>(c: char, chars: &mut core::iter::Peekable<I>, word: &mut Word) -> Result<(), &'static str>
+where
+	I: Iterator<Item = char>,
+{
+	match c {
+		'\\' => match chars.next() {
+			Some(escaped) => word.push_text(escaped, true),
+			None => word.push_text('\\', false),
+		},
+		'\'' => {
+			let mut text = String::new();
+			loop {
+				match chars.next() {
+					Some('\'') => break,
+					Some(c) => text.push(c),
+					None => return Err("unterminated single quote"),
+				}
+			}
+			word.0.push(Part::Quoted(text));
+		}
+		'"' => {
+			// An empty quoted part still makes `""` an (empty) word
+			word.0.push(Part::Quoted(String::new()));
+			loop {
+				match chars.next() {
+					Some('"') => break,
+					Some('\\') => match chars.peek() {
+						Some(&next @ ('$' | '"' | '\\' | '`')) => {
+							chars.next();
+							word.push_text(next, true);
+						}
+						_ => word.push_text('\\', true),
+					},
+					Some('$') => lex_dollar(chars, word, true),
+					Some(c) => word.push_text(c, true),
+					None => return Err("unterminated double quote"),
+				}
+			}
+		}
+		'$' => lex_dollar(chars, word, false),
+		c => word.push_text(c, false),
+	}
+	Ok(())
+}
+
+/// Lex the variable reference following a `$`
+fn lex_dollar<I>(chars: &mut core::iter::Peekable<I>, word: &mut Word, quoted: bool)
+where
+	I: Iterator<Item = char>,
+{
+	let mut name = String::new();
+	match chars.peek() {
+		Some(&c @ ('?' | '$' | '#' | '@' | '*')) => {
+			chars.next();
+			name.push(c);
+		}
+		Some('{') => {
+			chars.next();
+			for c in chars.by_ref() {
+				if c == '}' {
+					break;
+				}
+				name.push(c);
+			}
+		}
+		_ => {
+			while let Some(&c) = chars.peek() {
+				if !is_name_char(c) {
+					break;
+				}
+				name.push(c);
+				chars.next();
+			}
+		}
+	}
+
+	if name.is_empty() {
+		word.push_text('$', quoted);
+	} else if quoted {
+		word.0.push(Part::QuotedVar(name));
+	} else {
+		word.0.push(Part::Var(name));
+	}
+}
+
+/// How a command in a chain is connected to the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+	/// First command, or after `;`
+	Always,
+	/// After `&&`: run only if the previous command succeeded
+	And,
+	/// After `||`: run only if the previous command failed
+	Or,
+}
+
+/// A redirection attached to a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+	pub fd: usize,
+	pub kind: RedirectKind,
+	pub target: Word,
+}
+
+/// A command with its words and redirections, before expansion
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+	pub words: Vec<Word>,
+	pub redirects: Vec<Redirect>,
+}
+
+/// Commands joined by `|`, each reading the previous one's output
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pipeline {
+	pub commands: Vec<SimpleCommand>,
+	/// Terminated by `&`: run as a background job
+	pub background: bool,
+}
+
+impl fmt::Display for Word {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for part in &self.0 {
+			match part {
+				Part::Literal(s) => write!(f, "{}", s)?,
+				Part::Quoted(s) => write!(f, "'{}'", s)?,
+				Part::Var(name) => write!(f, "${{{}}}", name)?,
+				Part::QuotedVar(name) => write!(f, "\"${{{}}}\"", name)?,
+			}
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Display for Pipeline {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (i, command) in self.commands.iter().enumerate() {
+			if i > 0 {
+				write!(f, " | ")?;
+			}
+			let mut first = true;
+			for word in &command.words {
+				write!(f, "{}{}", if first { "" } else { " " }, word)?;
+				first = false;
+			}
+			for redirect in &command.redirects {
+				let op = match redirect.kind {
+					RedirectKind::Read => "<",
+					RedirectKind::Write => ">",
+					RedirectKind::Append => ">>",
+					RedirectKind::Dup => ">&",
+				};
+				let default_fd = if redirect.kind == RedirectKind::Read { 0 } else { 1 };
+				let space = if first { "" } else { " " };
+				first = false;
+				if redirect.fd == default_fd {
+					write!(f, "{}{}{}", space, op, redirect.target)?;
+				} else {
+					write!(f, "{}{}{}{}", space, redirect.fd, op, redirect.target)?;
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Replace unquoted alias names in command position with their values
+pub fn expand_aliases(tokens: Vec<Token>, aliases: &BTreeMap<String, String>) -> Result<Vec<Token>, &'static str> {
+	let mut expanded = Vec::new();
+	substitute_aliases(tokens, aliases, &mut Vec::new(), &mut expanded)?;
+	Ok(expanded)
+}
+
+/// Append `tokens` to `out`, expanding aliases other than the `active` ones
+/// being expanded already; returns whether the next word is in command position
+fn substitute_aliases<'a>(
+	tokens: Vec<Token>,
+	aliases: &'a BTreeMap<String, String>,
+	active: &mut Vec<&'a str>,
+	out: &mut Vec<Token>,
+) -> Result<bool, &'static str> {
+	let mut command_position = true;
+	let mut redirect_target = false;
+	for token in tokens {
+		match token {
+			Token::Word(word) if command_position && !redirect_target => {
+				let alias = word.as_literal()
+					.and_then(|name| aliases.get_key_value(name))
+					.filter(|(name, _)| !active.contains(&name.as_str()));
+				let Some((name, value)) = alias else {
+					out.push(Token::Word(word));
+					command_position = false;
+					continue;
+				};
+				active.push(name);
+				let ends_in_command = substitute_aliases(tokenize(value)?, aliases, active, out)?;
+				active.pop();
+				// A value ending in a blank makes the following word an alias candidate too
+				command_position = ends_in_command || value.ends_with(char::is_whitespace);
+			}
+			Token::Word(word) => {
+				out.push(Token::Word(word));
+				if !redirect_target {
+					command_position = false;
+				}
+				redirect_target = false;
+			}
+			Token::Redirect(..) => {
+				out.push(token);
+				redirect_target = true;
+			}
+			Token::Op(_) => {
+				out.push(token);
+				command_position = true;
+			}
+		}
+	}
+	Ok(command_position)
+}
+
+/// Parse a command line into a chain of pipelines, expanding `aliases`
+pub fn parse_line(line: &str, aliases: &BTreeMap<String, String>) -> Result<Vec<(Connector, Pipeline)>, &'static str> {
+	let mut chain = Vec::new();
+	let mut connector = Connector::Always;
+	let mut pipeline = Pipeline::default();
+	let mut command = SimpleCommand::default();
+	let mut tokens = expand_aliases(tokenize(line)?, aliases)?.into_iter();
+
+	while let Some(token) = tokens.next() {
+		let next = match token {
+			Token::Word(word) => {
+				command.words.push(word);
+				continue;
+			}
+			Token::Redirect(fd, kind) => match tokens.next() {
+				Some(Token::Word(target)) => {
+					command.redirects.push(Redirect { fd, kind, target });
+					continue;
+				}
+				_ => return Err("syntax error: expected file name after redirection"),
+			},
+			Token::Op(Operator::Pipe) => {
+				if command.words.is_empty() && command.redirects.is_empty() {
+					return Err("syntax error: missing command in pipeline");
+				}
+				pipeline.commands.push(core::mem::take(&mut command));
+				continue;
+			}
+			Token::Op(Operator::Semi) => Connector::Always,
+			Token::Op(Operator::And) => Connector::And,
+			Token::Op(Operator::Or) => Connector::Or,
+			Token::Op(Operator::Background) => {
+				if command.words.is_empty() && command.redirects.is_empty() {
+					return Err("syntax error near unexpected '&'");
+				}
+				pipeline.commands.push(core::mem::take(&mut command));
+				pipeline.background = true;
+				chain.push((connector, core::mem::take(&mut pipeline)));
+				connector = Connector::Always;
+				continue;
+			}
+		};
+
+		let empty = command.words.is_empty() && command.redirects.is_empty();
+		if empty && (next != Connector::Always || connector != Connector::Always || !pipeline.commands.is_empty()) {
+			return Err("syntax error: missing command in chain");
+		}
+		if !empty {
+			pipeline.commands.push(core::mem::take(&mut command));
+			chain.push((connector, core::mem::take(&mut pipeline)));
+		}
+		connector = next;
+	}
+
+	if command.words.is_empty() && command.redirects.is_empty() {
+		if connector != Connector::Always || !pipeline.commands.is_empty() {
+			return Err("syntax error: missing command after operator");
+		}
+	} else {
+		pipeline.commands.push(command);
+		chain.push((connector, pipeline));
+	}
+	Ok(chain)
+}
+
+/// Split a script line on unquoted `;`, stopping at an unquoted comment
+pub fn split_statements(line: &str) -> Vec<&str> {
+	let mut stmts = Vec::new();
+	let mut start = 0;
+	let mut quote = None;
+	let mut escaped = false;
+	let mut word_start = true;
+
+	for (i, c) in line.char_indices() {
+		if escaped {
+			escaped = false;
+			word_start = false;
+			continue;
+		}
+		match (quote, c) {
+			(Some('\''), '\'') | (Some('"'), '"') => quote = None,
+			(Some('\''), _) => {}
+			(_, '\\') => escaped = true,
+			(Some(_), _) => {}
+			(None, '\'' | '"') => quote = Some(c),
+			(None, '#') if word_start => {
+				stmts.push(&line[start..i]);
+				start = line.len();
+				break;
+			}
+			(None, ';') => {
+				stmts.push(&line[start..i]);
+				start = i + 1;
+			}
+			_ => {}
+		}
+		word_start = quote.is_none() && is_metachar(c);
+	}
+	if start < line.len() {
+		stmts.push(&line[start..]);
+	}
+	stmts.into_iter().map(str::trim).filter(|s| !s.is_empty()).collect()
+}
+
+/// Test quoting, escapes, and variable parts in the lexer
+#[test_case]
+fn test_tokenize_quotes() {
+	let tokens = tokenize(r#"echo "hello   $USER" 'a $b' c\ d"#).unwrap();
+	assert_eq!(tokens.len(), 4);
+	assert_eq!(tokens[1], Token::Word(Word(alloc::vec![
+		Part::Quoted(String::from("hello   ")),
+		Part::QuotedVar(String::from("USER")),
+	])));
+	assert_eq!(tokens[2], Token::Word(Word(alloc::vec![Part::Quoted(String::from("a $b"))])));
+	assert_eq!(tokens[3], Token::Word(Word(alloc::vec![
+		Part::Literal(String::from("c")),
+		Part::Quoted(String::from(" d")),
+	])));
+	assert!(tokenize("echo 'oops").is_err());
+}
+
+/// Test chains and redirections in the command parser
+#[test_case]
+fn test_parse_line() {
+	let aliases = BTreeMap::new();
+	let chain = parse_line("false && echo a>>/tmp/x || echo b 2>&1; echo '&&'", &aliases).unwrap();
+	let connectors: Vec<Connector> = chain.iter().map(|(c, _)| *c).collect();
+	assert_eq!(connectors, [Connector::Always, Connector::And, Connector::Or, Connector::Always]);
+	assert_eq!(chain[1].1.commands[0].redirects[0].kind, RedirectKind::Append);
+	assert_eq!(chain[2].1.commands[0].redirects[0].fd, 2);
+	assert_eq!(chain[2].1.commands[0].redirects[0].kind, RedirectKind::Dup);
+	assert_eq!(chain[3].1.commands[0].words.len(), 2);
+	assert!(parse_line("echo a &&", &aliases).is_err());
+	assert!(parse_line("echo >", &aliases).is_err());
+	let chain = parse_line("cat /etc/passwd | grep root|wc -l && echo '|'", &aliases).unwrap();
+	assert_eq!(chain.len(), 2);
+	assert_eq!(chain[0].1.commands.len(), 3);
+	assert_eq!(chain[1].1.commands.len(), 1);
+	assert!(parse_line("echo a |", &aliases).is_err());
+	assert!(parse_line("| wc", &aliases).is_err());
+	assert!(parse_line("echo a | ; wc", &aliases).is_err());
+	let chain = parse_line("cat /etc/passwd | wc -l >out & echo $HOME", &aliases).unwrap();
+	assert!(chain[0].1.background && !chain[1].1.background);
+	assert_eq!(alloc::format!("{}", chain[0].1), "cat /etc/passwd | wc -l >out");
+	assert!(parse_line("& echo", &aliases).is_err());
+	assert_eq!(split_statements("echo 'a;b'; echo c # done"), ["echo 'a;b'", "echo c"]);
+}
+
+/// Test alias expansion in command position, chaining, and recursion guards
+#[test_case]
+fn test_expand_aliases() {
+	let mut aliases = BTreeMap::new();
+	aliases.insert(String::from("ll"), String::from("ls -l"));
+	aliases.insert(String::from("ls"), String::from("ls -a"));
+	aliases.insert(String::from("nice"), String::from("echo "));
+	aliases.insert(String::from("loop"), String::from("loop x"));
+	let words = |line: &str| -> Vec<Vec<String>> {
+		parse_line(line, &aliases).unwrap().iter()
+			.flat_map(|(_, pipeline)| pipeline.commands.iter())
+			.map(|command| command.words.iter().map(|w| alloc::format!("{}", w)).collect())
+			.collect()
+	};
+	assert_eq!(words("ll /tmp; echo ll | ll"), [
+		alloc::vec!["ls", "-a", "-l", "/tmp"],
+		alloc::vec!["echo", "ll"],
+		alloc::vec!["ls", "-a", "-l"],
+	]);
+	assert_eq!(words("nice ll"), [alloc::vec!["echo", "ls", "-a", "-l"]]);
+	assert_eq!(words("'ll' ls"), [alloc::vec!["'ll'", "ls"]]);
+	assert_eq!(words("loop"), [alloc::vec!["loop", "x"]]);
+	assert_eq!(words(">out ll"), [alloc::vec!["ls", "-a", "-l"]]);
+}
@@ -0,0 +1,84 @@
+use crate::print;
+use alloc::string::String;
+
+/// Prompt used when `PS1` is unset: green `user@host`, blue working directory
+pub const DEFAULT_PS1: &str = "\\[\\e[1;32m\\]\\u@\\h\\[\\e[0m\\]:\\[\\e[1;34m\\]\\w\\[\\e[0m\\]\\$ ";
+
+/// Host name shown by `\h` when `HOSTNAME` is unset
+pub const DEFAULT_HOSTNAME: &str = "scottos";
+
+/// Values substituted into a prompt template
+pub struct PromptContext<'a> {
+	pub user: &'a str,
+	pub host: &'a str,
+	pub cwd: &'a str,
+	pub home: Option<&'a str>,
+}
+
+/// Expand `\u`, `\h`, `\w`, `\W`, `\$`, `\e`, `\033`, `\n`, and `\\` in a
+/// PS1-style template; `\[` and `\]` are dropped
+pub fn expand(template: &str, ctx: &PromptContext) -> String {
+	// `~` stands for the home directory at the start of `\w`
+	let short_cwd = match ctx.home {
+		Some(home) if home != "/" && ctx.cwd == home => String::from("~"),
+		Some(home) if home != "/" && ctx.cwd.strip_prefix(home).is_some_and(|rest| rest.starts_with('/')) => {
+			alloc::format!("~{}", &ctx.cwd[home.len()..])
+		}
+		_ => String::from(ctx.cwd),
+	};
+
+	let mut result = String::new();
+	let mut chars = template.chars().peekable();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			result.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('u') => result.push_str(ctx.user),
+			Some('h') => result.push_str(ctx.host.split('.').next().unwrap_or(ctx.host)),
+			Some('H') => result.push_str(ctx.host),
+			Some('w') => result.push_str(&short_cwd),
+			Some('W') => result.push_str(match short_cwd.rsplit_once('/') {
+				Some((_, base)) if !base.is_empty() => base,
+				_ => &short_cwd,
+			}),
+			Some('$') => result.push(if ctx.user == "root" { '#' } else { '$' }),
+			Some('e') => result.push('\x1b'),
+			Some('0') if chars.peek() == Some(&'3') => {
+				chars.next();
+				if chars.next_if_eq(&'3').is_some() {
+					result.push('\x1b');
+				} else {
+					result.push_str("\\03");
+				}
+			}
+			Some('n') => result.push('\n'),
+			Some('\\') => result.push('\\'),
+			Some('[' | ']') => {}
+			Some(other) => {
+				result.push('\\');
+				result.push(other);
+			}
+			None => result.push('\\'),
+		}
+	}
+	result
+}
+
+/// Print text containing ANSI SGR color sequences, restoring the default
+/// colors afterwards
+pub fn print_colored(text: &str) {
+	print!("{}\x1b[0m", text);
+}
+
+/// Test prompt escapes and home-directory shortening
+#[test_case]
+fn test_expand_prompt() {
+	let ctx = PromptContext { user: "root", host: "scottos.local", cwd: "/root/src", home: Some("/root") };
+	assert_eq!(expand("\\u@\\h:\\w\\$ ", &ctx), "root@scottos:~/src# ");
+	assert_eq!(expand("\\W \\[\\e[1m\\]>", &ctx), "src \x1b[1m>");
+	assert_eq!(expand("\\033[0m", &ctx), "\x1b[0m");
+	let ctx = PromptContext { user: "guest", host: "box", cwd: "/rooty", home: Some("/root") };
+	assert_eq!(expand("\\w\\$", &ctx), "/rooty$");
+}
@@ -0,0 +1,255 @@
+use super::{read_file, Shell, STATUS_INTERRUPTED};
+use crate::task::keyboard;
+use super::parser::split_statements;
+use alloc::{string::String, vec::Vec, format};
+
+/// A parsed script statement
+#[derive(Debug, PartialEq)]
+enum Node {
+	Command(String),
+	If {
+		condition: String,
+		then_branch: Vec<Node>,
+		else_branch: Vec<Node>,
+	},
+	For {
+		var: String,
+		words: String,
+		body: Vec<Node>,
+	},
+	Exit(String),
+}
+
+/// How a block of statements finished
+enum Flow {
+	/// Fell off the end with the given last status
+	Normal(i32),
+	/// An `exit` statement ended the script
+	Exit(i32),
+}
+
+/// Keywords that may be followed by a command on the same statement
+const LEADING_KEYWORDS: &[&str] = &["then", "else", "do"];
+
+/// Words with special meaning to the script parser
+pub(super) const KEYWORDS: &[&str] = &["if", "then", "else", "fi", "for", "in", "do", "done"];
+
+/// Split a script into `;`/newline separated statements, dropping comments
+fn statements(source: &str) -> Vec<&str> {
+	let mut stmts = Vec::new();
+	for line in source.lines() {
+		for stmt in split_statements(line) {
+			let (first, rest) = split_word(stmt);
+			if LEADING_KEYWORDS.contains(&first) && !rest.is_empty() {
+				stmts.push(first);
+				stmts.push(rest);
+			} else {
+				stmts.push(stmt);
+			}
+		}
+	}
+	stmts
+}
+
+/// Split off the first word of a statement
+fn split_word(stmt: &str) -> (&str, &str) {
+	match stmt.split_once(char::is_whitespace) {
+		Some((first, rest)) => (first, rest.trim()),
+		None => (stmt, ""),
+	}
+}
+
+/// Recursive-descent parser over script statements
+struct Parser<'a> {
+	stmts: Vec<&'a str>,
+	pos: usize,
+}
+
+impl<'a> Parser<'a> {
+	/// Parse statements up to one of `terminators`, returning the terminator found
+	///
+	/// An empty terminator list parses to the end of the script.
+	fn block(&mut self, terminators: &[&'a str]) -> Result<(Vec<Node>, &'a str), String> {
+		let mut nodes = Vec::new();
+		while let Some(&stmt) = self.stmts.get(self.pos) {
+			self.pos += 1;
+			let (word, rest) = split_word(stmt);
+			if terminators.contains(&word) && rest.is_empty() {
+				return Ok((nodes, word));
+			}
+			let node = match word {
+				"if" => self.if_statement(rest)?,
+				"for" => self.for_statement(rest)?,
+				"exit" => Node::Exit(String::from(rest)),
+				"then" | "else" | "fi" | "do" | "done" => {
+					return Err(format!("syntax error near unexpected '{}'", word));
+				}
+				_ => Node::Command(String::from(stmt)),
+			};
+			nodes.push(node);
+		}
+
+		match terminators.first() {
+			None => Ok((nodes, "")),
+			Some(expected) => Err(format!("syntax error: expected '{}' before end of script", expected)),
+		}
+	}
+
+	/// Consume a statement that must be exactly `keyword`
+	fn expect(&mut self, keyword: &str) -> Result<(), String> {
+		match self.stmts.get(self.pos) {
+			Some(&stmt) if stmt == keyword => {
+				self.pos += 1;
+				Ok(())
+			}
+			_ => Err(format!("syntax error: expected '{}'", keyword)),
+		}
+	}
+
+	/// Parse `if COND; then ...; [else ...;] fi`
+	fn if_statement(&mut self, condition: &str) -> Result<Node, String> {
+		if condition.is_empty() {
+			return Err(String::from("syntax error: if without a condition"));
+		}
+		self.expect("then")?;
+		let (then_branch, end) = self.block(&["else", "fi"])?;
+		let else_branch = if end == "else" {
+			self.block(&["fi"])?.0
+		} else {
+			Vec::new()
+		};
+		Ok(Node::If { condition: String::from(condition), then_branch, else_branch })
+	}
+
+	/// Parse `for VAR in WORDS...; do ...; done`
+	fn for_statement(&mut self, header: &str) -> Result<Node, String> {
+		let (var, rest) = split_word(header);
+		let words = match split_word(rest) {
+			("in", words) => words,
+			_ => return Err(String::from("syntax error: expected 'for NAME in WORDS'")),
+		};
+		self.expect("do")?;
+		let (body, _) = self.block(&["done"])?;
+		Ok(Node::For { var: String::from(var), words: String::from(words), body })
+	}
+}
+
+/// Parse a whole script into statements
+fn parse(source: &str) -> Result<Vec<Node>, String> {
+	let mut parser = Parser { stmts: statements(source), pos: 0 };
+	Ok(parser.block(&[])?.0)
+}
+
+impl Shell {
+	/// Read and run a script from the file system, returning its exit status
+	pub fn run_script_file(&mut self, path: &str) -> i32 {
+		let source = match read_file(path) {
+			Ok(data) => data,
+			Err(err) => {
+				errln!("sh: {}: {}", path, err.as_str());
+				return 127;
+			}
+		};
+		match core::str::from_utf8(&source) {
+			Ok(source) => self.run_script(source),
+			Err(_) => {
+				errln!("sh: {}: not a text file", path);
+				126
+			}
+		}
+	}
+
+	/// Run script source text, returning its exit status
+	pub fn run_script(&mut self, source: &str) -> i32 {
+		match parse(source) {
+			Ok(nodes) => match self.run_nodes(&nodes) {
+				Flow::Normal(status) | Flow::Exit(status) => status,
+			},
+			Err(msg) => {
+				errln!("sh: {}", msg);
+				2
+			}
+		}
+	}
+
+	/// Execute parsed statements in order
+	fn run_nodes(&mut self, nodes: &[Node]) -> Flow {
+		let mut status = 0;
+		for node in nodes {
+			let flow = match node {
+				Node::Command(command) => Flow::Normal(self.execute_line(command)),
+				Node::If { condition, then_branch, else_branch } => {
+					if self.execute_line(condition) == 0 {
+						self.run_nodes(then_branch)
+					} else {
+						self.run_nodes(else_branch)
+					}
+				}
+				Node::For { var, words, body } => {
+					let words = match self.expand_text(words) {
+						Ok(words) => words,
+						Err(msg) => {
+							errln!("sh: {}", msg);
+							return Flow::Exit(2);
+						}
+					};
+					let mut flow = Flow::Normal(0);
+					for word in &words {
+						if keyboard::interrupt_requested() {
+							break;
+						}
+						self.set_var(var, word, false);
+						flow = self.run_nodes(body);
+						if let Flow::Exit(_) = flow {
+							break;
+						}
+					}
+					flow
+				}
+				Node::Exit(arg) => {
+					let arg = self.expand_text(arg).unwrap_or_default().join(" ");
+					let code = if arg.is_empty() { Ok(status) } else { arg.trim().parse() };
+					match code {
+						Ok(code) => Flow::Exit(code),
+						Err(_) => {
+							errln!("exit: numeric argument required: {}", arg);
+							Flow::Exit(2)
+						}
+					}
+				}
+			};
+			match flow {
+				Flow::Normal(s) => {
+					status = s;
+					self.last_status = s;
+				}
+				Flow::Exit(code) => {
+					self.last_status = code;
+					return Flow::Exit(code);
+				}
+			}
+			if keyboard::interrupt_requested() {
+				self.last_status = STATUS_INTERRUPTED;
+				return Flow::Exit(STATUS_INTERRUPTED);
+			}
+		}
+		Flow::Normal(status)
+	}
+}
+
+/// Test parsing nested control flow with same-line keywords
+#[test_case]
+fn test_parse_script() {
+	let nodes = parse("# boot script\nfor x in a b; do\n  if [ $x = a ]; then echo yes; else echo no; fi\ndone\nexit 3").unwrap();
+	assert_eq!(nodes.len(), 2);
+	match &nodes[0] {
+		Node::For { var, words, body } => {
+			assert_eq!((var.as_str(), words.as_str()), ("x", "a b"));
+			assert!(matches!(&body[0], Node::If { then_branch, else_branch, .. }
+				if then_branch.len() == 1 && else_branch.len() == 1));
+		}
+		other => panic!("unexpected node {:?}", other),
+	}
+	assert_eq!(nodes[1], Node::Exit(String::from("3")));
+	assert!(parse("if true; then echo").is_err());
+}
@@ -0,0 +1,298 @@
+use super::Shell;
+use crate::cpu;
+use crate::keymap;
+use crate::task::keyboard;
+use crate::time;
+use crate::vga_buffer::{self, Color, WRITER};
+use alloc::{collections::VecDeque, format};
+use core::ops::Range;
+use pc_keyboard::{DecodedKey, KeyCode};
+
+/// Length of a new snake
+const START_LENGTH: usize = 3;
+/// Milliseconds between moves at the start, and the fastest it gets
+const START_INTERVAL_MS: u64 = 150;
+const MIN_INTERVAL_MS: u64 = 60;
+/// Milliseconds shaved off the interval for each piece of food eaten
+const SPEEDUP_MS: u64 = 5;
+/// The Escape key as decoded to a character
+const ESCAPE: char = '\u{1b}';
+
+/// A screen cell as `(row, col)`
+type Point = (usize, usize);
+
+/// Direction the snake is heading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+	Up,
+	Down,
+	Left,
+	Right,
+}
+
+impl Direction {
+	fn opposite(self) -> Direction {
+		match self {
+			Direction::Up => Direction::Down,
+			Direction::Down => Direction::Up,
+			Direction::Left => Direction::Right,
+			Direction::Right => Direction::Left,
+		}
+	}
+}
+
+/// The state of one game, independent of the screen and keyboard
+struct Game {
+	/// Rows the snake can move in; the rows around them are walls and the
+	/// bottom row of the screen is the status bar
+	rows: Range<usize>,
+	/// Columns the snake can move in, inside the side walls
+	cols: Range<usize>,
+	/// Cells covered by the snake, head first
+	body: VecDeque<Point>,
+	/// Direction of the last move
+	direction: Direction,
+	/// Direction of the next move, as last steered
+	heading: Direction,
+	food: Point,
+	score: u32,
+	alive: bool,
+	/// xorshift state for placing food
+	rng: u64,
+}
+
+impl Game {
+	/// Start a game on a screen of `(rows, cols)` with the snake in the
+	/// middle of the field heading right
+	fn new(seed: u64, (screen_rows, screen_cols): (usize, usize)) -> Game {
+		let (rows, cols) = (1..screen_rows - 2, 1..screen_cols - 1);
+		let row = (rows.start + rows.end) / 2;
+		let col = (cols.start + cols.end) / 2;
+		let mut game = Game {
+			rows,
+			cols,
+			body: (0..START_LENGTH).map(|i| (row, col - i)).collect(),
+			direction: Direction::Right,
+			heading: Direction::Right,
+			food: (0, 0),
+			score: 0,
+			alive: true,
+			rng: seed | 1,
+		};
+		game.place_food();
+		game
+	}
+
+	/// Next pseudo-random number
+	fn random(&mut self) -> u64 {
+		self.rng ^= self.rng << 13;
+		self.rng ^= self.rng >> 7;
+		self.rng ^= self.rng << 17;
+		self.rng
+	}
+
+	/// Put the food on a random cell the snake does not cover
+	fn place_food(&mut self) {
+		loop {
+			let row = self.rows.start + (self.random() % self.rows.len() as u64) as usize;
+			let col = self.cols.start + (self.random() % self.cols.len() as u64) as usize;
+			if !self.body.contains(&(row, col)) {
+				self.food = (row, col);
+				return;
+			}
+		}
+	}
+
+	/// Steer for the next move; the snake cannot turn back on itself
+	fn steer(&mut self, direction: Direction) {
+		if direction != self.direction.opposite() {
+			self.heading = direction;
+		}
+	}
+
+	/// Move one cell, growing when the food is eaten and dying on a wall or
+	/// the snake's own body
+	fn step(&mut self) {
+		if !self.alive {
+			return;
+		}
+		self.direction = self.heading;
+		let (row, col) = self.body[0];
+		let head = match self.direction {
+			Direction::Up => (row.wrapping_sub(1), col),
+			Direction::Down => (row + 1, col),
+			Direction::Left => (row, col.wrapping_sub(1)),
+			Direction::Right => (row, col + 1),
+		};
+		let eating = head == self.food;
+		// The tail moves out of the way unless the snake is growing
+		let solid = self.body.len() - if eating { 0 } else { 1 };
+		if !self.rows.contains(&head.0) || !self.cols.contains(&head.1) || self.body.iter().take(solid).any(|&p| p == head) {
+			self.alive = false;
+			return;
+		}
+		self.body.push_front(head);
+		if eating {
+			self.score += 1;
+			self.place_food();
+		} else {
+			self.body.pop_back();
+		}
+	}
+
+	/// Milliseconds until the next move, shrinking as the score grows
+	fn interval_ms(&self) -> u64 {
+		START_INTERVAL_MS.saturating_sub(self.score as u64 * SPEEDUP_MS).max(MIN_INTERVAL_MS)
+	}
+
+	/// Draw the whole field and status bar straight onto the screen
+	fn render(&self, paused: bool) {
+		let status_row = self.rows.end + 1;
+		let width = self.cols.end + 1;
+		x86_64::instructions::interrupts::without_interrupts(|| {
+			let mut writer = WRITER.lock();
+			for row in 0..status_row {
+				for col in 0..width {
+					let wall = !self.rows.contains(&row) || !self.cols.contains(&col);
+					let (byte, color) = if wall {
+						(b'#', Color::DarkGray)
+					} else if (row, col) == self.body[0] {
+						(b'@', if self.alive { Color::LightGreen } else { Color::LightRed })
+					} else if self.body.contains(&(row, col)) {
+						(b'o', Color::Green)
+					} else if (row, col) == self.food {
+						(b'*', Color::LightRed)
+					} else {
+						(b' ', Color::Black)
+					};
+					writer.put_char(row, col, byte, color, Color::Black);
+				}
+			}
+
+			let state = match (self.alive, paused) {
+				(false, _) => "GAME OVER - r restart, q quit",
+				(true, true) => "PAUSED - p resume, q quit",
+				(true, false) => "arrows/WASD steer, p pause, q quit",
+			};
+			let left = format!(" Snake | Score: {} | Length: {} ", self.score, self.body.len());
+			let padding = width.saturating_sub(left.len() + state.len() + 1);
+			let status = format!("{}{:padding$}{} ", left, "", state);
+			writer.write_at(status_row, 0, &status, Color::Black, Color::LightGray);
+		});
+	}
+}
+
+impl Shell {
+	/// Play snake full-screen until `q`, Escape, or Ctrl-C
+	///
+	/// Reads raw scancodes itself rather than waiting for keys from the
+	/// shell, moving on timer ticks in between.
+	pub(super) fn cmd_snake(&self, args: &[&str]) -> i32 {
+		if !args.is_empty() {
+			errln!("usage: snake");
+			return 1;
+		}
+		// Keys typed before the game started are not moves
+		while keyboard::read_scancode().is_some() {}
+		let mut decoder = keymap::decoder();
+		let seed = || cpu::rdrand().unwrap_or_else(|| time::uptime_ms() ^ (time::now() << 16));
+		let mut game = Game::new(seed(), vga_buffer::screen_size());
+		let mut paused = false;
+		let mut next_move = time::uptime_ms() + game.interval_ms();
+		x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().set_cursor_visible(false));
+		game.render(paused);
+
+		'playing: while !keyboard::interrupt_requested() {
+			let mut changed = false;
+			while let Some(scancode) = keyboard::read_scancode() {
+				let Ok(Some(event)) = decoder.add_byte(scancode) else {
+					continue;
+				};
+				let Some(key) = decoder.process_keyevent(event) else {
+					continue;
+				};
+				let direction = match key {
+					DecodedKey::RawKey(KeyCode::ArrowUp) | DecodedKey::Unicode('w' | 'k') => Some(Direction::Up),
+					DecodedKey::RawKey(KeyCode::ArrowDown) | DecodedKey::Unicode('s' | 'j') => Some(Direction::Down),
+					DecodedKey::RawKey(KeyCode::ArrowLeft) | DecodedKey::Unicode('a' | 'h') => Some(Direction::Left),
+					DecodedKey::RawKey(KeyCode::ArrowRight) | DecodedKey::Unicode('d' | 'l') => Some(Direction::Right),
+					DecodedKey::RawKey(KeyCode::Escape) | DecodedKey::Unicode('q' | ESCAPE) => break 'playing,
+					DecodedKey::Unicode('p' | ' ') if game.alive => {
+						paused = !paused;
+						changed = true;
+						None
+					}
+					DecodedKey::Unicode('r') if !game.alive => {
+						game = Game::new(seed(), vga_buffer::screen_size());
+						changed = true;
+						None
+					}
+					_ => None,
+				};
+				if let Some(direction) = direction.filter(|_| !paused) {
+					game.steer(direction);
+				}
+			}
+
+			let now = time::uptime_ms();
+			if !paused && game.alive && now >= next_move {
+				game.step();
+				next_move = now + game.interval_ms();
+				changed = true;
+			}
+			if changed {
+				game.render(paused);
+			}
+			// Sleep until the next timer tick or key press
+			x86_64::instructions::hlt();
+		}
+
+		x86_64::instructions::interrupts::without_interrupts(|| {
+			let mut writer = WRITER.lock();
+			writer.clear_screen();
+			writer.set_cursor_visible(true);
+		});
+		outln!("Snake: scored {} (length {})", game.score, game.body.len());
+		0
+	}
+}
+
+/// Test movement, steering, growth, and collisions
+#[test_case]
+fn test_snake_rules() {
+	let screen = (vga_buffer::BUFFER_HEIGHT, vga_buffer::BUFFER_WIDTH);
+	let mut game = Game::new(42, screen);
+	let (row, col) = game.body[0];
+	game.food = (row, col + 1);
+	game.step();
+	assert_eq!(game.body[0], (row, col + 1));
+	assert_eq!((game.score, game.body.len()), (1, START_LENGTH + 1));
+	assert!(!game.body.contains(&game.food));
+	game.food = (game.rows.end - 1, game.cols.start);
+
+	// Reversing is ignored; turning takes effect on the next move
+	game.steer(Direction::Left);
+	game.step();
+	assert_eq!(game.body[0], (row, col + 2));
+	game.steer(Direction::Up);
+	game.step();
+	assert_eq!(game.body[0], (row - 1, col + 2));
+	assert_eq!(game.body.len(), START_LENGTH + 1);
+
+	// Running into the top wall ends the game
+	while game.alive {
+		game.step();
+	}
+	assert_eq!(game.body[0].0, game.rows.start);
+
+	// So does turning back into the body
+	let mut game = Game::new(7, screen);
+	game.body = [(5, 5), (5, 6), (6, 6), (6, 5), (6, 4)].into_iter().collect();
+	game.direction = Direction::Down;
+	game.heading = Direction::Down;
+	game.food = (1, 1);
+	game.steer(Direction::Right);
+	game.step();
+	assert!(!game.alive);
+	assert!(game.interval_ms() >= MIN_INTERVAL_MS);
+}
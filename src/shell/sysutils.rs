@@ -0,0 +1,666 @@
+use super::fileutils::parse_flags;
+use super::Shell;
+use crate::audit;
+use crate::cpu;
+use crate::keyboard;
+use crate::keymap::{self, Layout};
+use crate::klog::{self, Level};
+use crate::pci::{self, Bar};
+use crate::profile;
+use crate::rtc;
+use crate::syscall::{self, SyscallError};
+use crate::time::{self, DateTime};
+use crate::trace::{self, Subsystem};
+use crate::tty::{self, Termios};
+use crate::vga_buffer::{self, Color, THEMES, WRITER};
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+
+/// Functions `profile report` lists unless told otherwise
+const PROFILE_TOP: usize = 10;
+/// Output format of `date` when no `+FORMAT` is given
+const DATE_FORMAT: &str = "%a %b %e %H:%M:%S %Z %Y";
+/// Output format of `hwclock`
+const HWCLOCK_FORMAT: &str = "%Y-%m-%d %H:%M:%S %Z";
+
+/// Parse a decimal such as `10.9` as a count of tenths
+fn parse_tenths(text: &str) -> Option<u32> {
+	let (whole, tenths) = text.split_once('.').unwrap_or((text, "0"));
+	let tenth = match tenths.as_bytes() {
+		[digit, ..] if digit.is_ascii_digit() && tenths.bytes().all(|byte| byte.is_ascii_digit()) => u32::from(digit - b'0'),
+		_ => return None,
+	};
+	whole.parse::<u32>().ok()?.checked_mul(10)?.checked_add(tenth)
+}
+
+/// Parse a date argument: `@SECONDS` or `[YYYY-MM-DD] [HH:MM[:SS]]`
+fn parse_time(text: &str) -> Option<DateTime> {
+	let now = DateTime::from_timestamp(syscall::sys_time().unwrap_or(0) as u64);
+	match text.strip_prefix('@') {
+		Some(seconds) => seconds.parse().ok().map(DateTime::from_timestamp),
+		None => DateTime::parse(text, &now),
+	}
+}
+
+/// The value of a `--name=value` or `--name value` option, taking the next
+/// argument for the latter; `None` if `arg` is not the `long` option
+fn option_value<'a>(arg: &'a str, long: &str, rest: &mut core::slice::Iter<'_, &'a str>) -> Option<Option<&'a str>> {
+	match arg.strip_prefix(long) {
+		Some("") => Some(rest.next().copied()),
+		Some(value) => value.strip_prefix('=').map(Some),
+		None => None,
+	}
+}
+
+/// A BAR size the way `lspci` shows it, e.g. `128K`
+fn format_size(size: u64) -> String {
+	const UNITS: [(u64, &str); 3] = [(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+	match UNITS.iter().find(|&&(unit, _)| size >= unit && size.is_multiple_of(unit)) {
+		Some(&(unit, suffix)) => format!("{}{}", size / unit, suffix),
+		None => format!("{}", size),
+	}
+}
+
+impl Shell {
+	/// Print or set the system time: `date [-u] [-d DATE] [-s DATE] [+FORMAT]`
+	pub(super) fn cmd_date(&self, args: &[&str]) -> i32 {
+		let mut format = DATE_FORMAT;
+		let mut show = None;
+		let mut set = None;
+		let mut args = args.iter();
+		while let Some(&arg) = args.next() {
+			let value = match arg {
+				"-u" | "--utc" | "--universal" => continue,
+				_ if arg.starts_with('+') => {
+					format = &arg[1..];
+					continue;
+				}
+				"-d" | "-s" => Some(args.next().copied()),
+				_ => option_value(arg, "--date", &mut args).or_else(|| option_value(arg, "--set", &mut args)),
+			};
+			let Some(value) = value else {
+				errln!("date: invalid option: {}", arg);
+				return 1;
+			};
+			let Some(value) = value else {
+				errln!("date: option requires an argument: {}", arg);
+				return 1;
+			};
+			let Some(time) = parse_time(value) else {
+				errln!("date: invalid date '{}'", value);
+				return 1;
+			};
+			if arg.starts_with("-s") || arg.starts_with("--set") {
+				set = Some(time);
+			} else {
+				show = Some(time);
+			}
+		}
+
+		if let Some(time) = set {
+			if let Err(err) = syscall::sys_settimeofday(time.timestamp() as i64) {
+				errln!("date: cannot set date: {}", err.as_str());
+				return 1;
+			}
+		}
+		let time = match show.or(set) {
+			Some(time) => time,
+			None => match syscall::sys_time() {
+				Ok(seconds) => DateTime::from_timestamp(seconds as u64),
+				Err(err) => {
+					errln!("date: {}", err.as_str());
+					return 1;
+				}
+			},
+		};
+		outln!("{}", time.format(format));
+		0
+	}
+
+	/// Read or set the CMOS real-time clock: `hwclock [-r | -s | -w | --set --date DATE]`
+	pub(super) fn cmd_hwclock(&self, args: &[&str]) -> i32 {
+		let mut set = false;
+		let mut date = None;
+		let mut action = "-r";
+		let mut args = args.iter();
+		while let Some(&arg) = args.next() {
+			match arg {
+				"-r" | "--show" | "-u" | "--utc" => {}
+				"-s" | "--hctosys" => action = "-s",
+				"-w" | "--systohc" => action = "-w",
+				"--set" => set = true,
+				_ => match option_value(arg, "--date", &mut args) {
+					Some(Some(value)) => date = Some(value),
+					Some(None) => {
+						errln!("hwclock: option requires an argument: --date");
+						return 1;
+					}
+					None => {
+						errln!("usage: hwclock [-r | -s | -w | --set --date DATE]");
+						return 1;
+					}
+				},
+			}
+		}
+
+		if set {
+			let Some(value) = date else {
+				errln!("hwclock: --set requires --date");
+				return 1;
+			};
+			let Some(time) = parse_time(value) else {
+				errln!("hwclock: invalid date '{}'", value);
+				return 1;
+			};
+			rtc::write(&time);
+			return 0;
+		}
+		match action {
+			"-s" => {
+				// Bring the system clock back in line with the hardware clock
+				if let Err(err) = syscall::sys_settimeofday(rtc::read().timestamp() as i64) {
+					errln!("hwclock: {}", err.as_str());
+					return 1;
+				}
+			}
+			"-w" => match syscall::sys_time() {
+				Ok(seconds) => rtc::write(&DateTime::from_timestamp(seconds as u64)),
+				Err(err) => {
+					errln!("hwclock: {}", err.as_str());
+					return 1;
+				}
+			},
+			_ => outln!("{}", rtc::read().format(HWCLOCK_FORMAT)),
+		}
+		0
+	}
+
+	/// Print the kernel log: `dmesg [-x] [-l LEVEL[,LEVEL...]] [-c | -C] [-n LEVEL]`
+	pub(super) fn cmd_dmesg(&self, args: &[&str]) -> i32 {
+		let mut levels: Option<Vec<Level>> = None;
+		let mut decode = false;
+		let mut clear_after = false;
+		let mut args = args.iter();
+		while let Some(&arg) = args.next() {
+			match arg {
+				"-x" | "--decode" => decode = true,
+				"-c" | "--read-clear" => clear_after = true,
+				"-C" | "--clear" => {
+					klog::clear();
+					return 0;
+				}
+				"-l" | "--level" | "-n" | "--console-level" => {
+					let Some(&value) = args.next() else {
+						errln!("dmesg: option requires an argument: {}", arg);
+						return 1;
+					};
+					let parsed: Option<Vec<Level>> = value.split(',').map(Level::from_name).collect();
+					let Some(parsed) = parsed else {
+						errln!("dmesg: unknown level '{}'", value);
+						return 1;
+					};
+					if matches!(arg, "-n" | "--console-level") {
+						if let Some(&level) = parsed.first() {
+							klog::set_console_level(level);
+						}
+						return 0;
+					}
+					levels = Some(parsed);
+				}
+				_ => {
+					errln!("usage: dmesg [-x] [-l LEVEL[,LEVEL...]] [-c | -C] [-n LEVEL]");
+					return 1;
+				}
+			}
+		}
+
+		let mut seq = 0;
+		while let Some((number, record)) = klog::read_from(seq) {
+			seq = number + 1;
+			if levels.as_ref().is_some_and(|levels| !levels.contains(&record.level)) {
+				continue;
+			}
+			if decode {
+				out!("{:<6} : ", record.level.name());
+			}
+			outln!("[{:5}.{:03}] {}", record.timestamp_ms / 1000, record.timestamp_ms % 1000, record.message());
+		}
+		if clear_after {
+			klog::clear();
+		}
+		0
+	}
+
+	/// Show the audit log, to root alone: `auditlog [KIND...]`, limited to
+	/// the kinds named
+	pub(super) fn cmd_auditlog(&self, args: &[&str]) -> i32 {
+		if !crate::process::current_credentials().is_root() {
+			errln!("auditlog: Permission denied");
+			return 1;
+		}
+		let kinds: Option<Vec<audit::Kind>> = args.iter().map(|&name| audit::Kind::from_name(name)).collect();
+		let Some(kinds) = kinds else {
+			let names: Vec<&str> = audit::Kind::ALL.iter().map(|kind| kind.name()).collect();
+			errln!("usage: auditlog [{}]...", names.join("|"));
+			return 1;
+		};
+
+		let mut seq = 0;
+		while let Some((number, record)) = audit::read_from(seq) {
+			seq = number + 1;
+			if !kinds.is_empty() && !kinds.contains(&record.kind) {
+				continue;
+			}
+			let pid = match record.actor.pid {
+				Some(pid) => format!("{}", pid),
+				None => String::from("kernel"),
+			};
+			outln!(
+				"[{:5}.{:03}] {:<6} pid={} uid={} gid={} {}",
+				record.timestamp_ms / 1000,
+				record.timestamp_ms % 1000,
+				record.kind.name(),
+				pid,
+				record.actor.uid,
+				record.actor.gid,
+				record.message()
+			);
+		}
+		0
+	}
+
+	/// List what this shell has mounted, or bind a directory over another:
+	/// `mount [--bind SOURCE TARGET]`
+	pub(super) fn cmd_mount(&self, args: &[&str]) -> i32 {
+		match args {
+			[] => {
+				let mounts = crate::process::with_current_process(|p| p.mounts.clone()).map(|ns| ns.mounts());
+				outln!("ramfs on / type ramfs");
+				for mount in mounts.unwrap_or_default() {
+					outln!("{} on {} type none (bind)", mount.source, mount.target);
+				}
+				0
+			}
+			["--bind" | "-B", source, target] => match syscall::sys_mount(source, target, syscall::MS_BIND) {
+				Ok(_) => 0,
+				Err(err) => {
+					errln!("mount: {} on {}: {}", source, target, err.as_str());
+					1
+				}
+			},
+			_ => {
+				errln!("usage: mount [--bind SOURCE TARGET]");
+				1
+			}
+		}
+	}
+
+	/// Undo the latest mount on a directory: `umount TARGET`
+	pub(super) fn cmd_umount(&self, args: &[&str]) -> i32 {
+		let [target] = args else {
+			errln!("usage: umount TARGET");
+			return 1;
+		};
+		match syscall::sys_umount(target) {
+			Ok(_) => 0,
+			Err(SyscallError::InvalidArgument) => {
+				errln!("umount: {}: not mounted", target);
+				1
+			}
+			Err(err) => {
+				errln!("umount: {}: {}", target, err.as_str());
+				1
+			}
+		}
+	}
+
+	/// List the executor's tasks, to find stuck or busy-looping ones
+	pub(super) fn cmd_tasks(&self) -> i32 {
+		outln!("{:>4} {:>3} {:<10} {:<5} {:>8} {:>10} {:<6} NAME", "ID", "CPU", "PRIORITY", "STATE", "POLLS", "TIME(ms)", "WAKE");
+		for task in crate::task::tasks() {
+			let micros = task.poll_time.as_micros();
+			outln!(
+				"{:>4} {:>3} {:<10} {:<5} {:>8} {:>6}.{:03} {:<6} {}",
+				task.id,
+				task.cpu,
+				format!("{:?}", task.priority),
+				task.state.name(),
+				task.polls,
+				micros / 1000,
+				micros % 1000,
+				task.last_wake.name(),
+				task.name.as_deref().unwrap_or("-"),
+			);
+		}
+		0
+	}
+
+	/// Sample the kernel at every timer tick: `profile start`, `profile stop`,
+	/// and `profile report [-n N]`, which shows the N busiest functions and
+	/// writes every stack to the serial port, folded for a flame graph
+	pub(super) fn cmd_profile(&self, args: &[&str]) -> i32 {
+		match args {
+			["start"] => {
+				profile::start();
+				outln!("profile: sampling at every timer tick");
+			}
+			["stop"] => {
+				profile::stop();
+				let (samples, dropped) = profile::samples();
+				outln!("profile: stopped after {} samples ({} dropped)", samples, dropped);
+			}
+			["report"] => return self.profile_report(PROFILE_TOP),
+			["report", "-n", count] => match count.parse() {
+				Ok(count) => return self.profile_report(count),
+				Err(_) => {
+					errln!("profile: {}: not a count", count);
+					return 2;
+				}
+			},
+			_ => {
+				errln!("usage: profile start | stop | report [-n N]");
+				return 2;
+			}
+		}
+		0
+	}
+
+	/// Show the `top` busiest functions, then write the folded stacks to the serial port
+	fn profile_report(&self, top: usize) -> i32 {
+		let (samples, dropped) = profile::samples();
+		if samples == 0 {
+			errln!("profile: no samples; run profile start first");
+			return 1;
+		}
+		let folded = profile::folded();
+		// Samples by the function they landed in, the innermost frame
+		let mut functions: BTreeMap<&str, u64> = BTreeMap::new();
+		for (stack, &count) in &folded {
+			let function = stack.rsplit(';').next().unwrap_or(stack);
+			*functions.entry(function).or_insert(0) += count;
+		}
+		let mut functions: Vec<(&str, u64)> = functions.into_iter().collect();
+		functions.sort_by_key(|f| core::cmp::Reverse(f.1));
+		outln!("{} samples, {} dropped{}", samples, dropped, if profile::running() { ", still sampling" } else { "" });
+		outln!("{:>7} {:>6}  FUNCTION", "SAMPLES", "%");
+		for (function, count) in functions.into_iter().take(top) {
+			let tenths = count * 1000 / samples;
+			outln!("{:>7} {:>4}.{}  {}", count, tenths / 10, tenths % 10, function);
+		}
+		crate::serial_println!("# profile: folded stacks begin");
+		for (stack, count) in &folded {
+			crate::serial_println!("{} {}", stack, count);
+		}
+		crate::serial_println!("# profile: folded stacks end");
+		outln!("profile: {} folded stacks written to the serial port", folded.len());
+		0
+	}
+
+	/// Show which subsystems are traced, change that, or show the events recorded
+	pub(super) fn cmd_trace(&self, args: &[&str]) -> i32 {
+		match args {
+			[] => {
+				for subsystem in Subsystem::ALL {
+					outln!("{:<8} {}", subsystem.name(), if trace::enabled(subsystem) { "on" } else { "off" });
+				}
+				outln!("{} events recorded since boot", trace::recorded());
+			}
+			[switch @ ("on" | "off"), names @ ..] if !names.is_empty() => {
+				let mut subsystems = Vec::new();
+				for &name in names {
+					match (name, Subsystem::from_name(name)) {
+						("all", _) => subsystems.extend(Subsystem::ALL),
+						(_, Some(subsystem)) => subsystems.push(subsystem),
+						(_, None) => {
+							errln!("trace: unknown subsystem '{}'", name);
+							return 1;
+						}
+					}
+				}
+				for subsystem in subsystems {
+					trace::set_enabled(subsystem, *switch == "on");
+				}
+			}
+			["dump"] => self.trace_dump(),
+			["clear"] => trace::clear(),
+			_ => {
+				errln!("usage: trace [on|off SUBSYSTEM...|all] | dump | clear");
+				errln!("subsystems: {}", Subsystem::ALL.map(Subsystem::name).join(" "));
+				return 2;
+			}
+		}
+		0
+	}
+
+	/// Show the recorded events, oldest first, timed from the first of them
+	fn trace_dump(&self) {
+		let mut seq = 0;
+		let mut first = None;
+		while let Some((number, event)) = trace::read_from(seq) {
+			seq = number + 1;
+			let start = *first.get_or_insert(event.tsc);
+			let cycles = event.tsc.wrapping_sub(start);
+			match time::tsc_hz() {
+				Some(hz) => {
+					let micros = (cycles as u128 * 1_000_000 / hz as u128) as u64;
+					out!("[{:6}.{:06}]", micros / 1_000_000, micros % 1_000_000);
+				}
+				None => out!("[{:14}]", cycles),
+			}
+			outln!(" cpu{} {:<7} {}", event.cpu, event.subsystem.name(), event.message());
+		}
+		if first.is_none() {
+			outln!("trace: no events; turn a subsystem on with trace on");
+		}
+	}
+
+	/// Show the processor's identity and the features CPUID reports
+	pub(super) fn cmd_cpuinfo(&self) -> i32 {
+		let info = cpu::info();
+		outln!("Vendor:     {}", info.vendor());
+		outln!("Model name: {}", info.brand().unwrap_or("unknown"));
+		outln!("Family {}, model {}, stepping {}", info.family, info.model, info.stepping);
+		outln!("CPUs:       {} online", crate::smp::cpu_count());
+		let flags: Vec<&str> = info.flags().collect();
+		outln!("Flags:      {}", flags.join(" "));
+		0
+	}
+
+	/// List PCI devices (`-n` numeric IDs only, `-v` show BARs and IRQs)
+	pub(super) fn cmd_lspci(&self, args: &[&str]) -> i32 {
+		let flags = match parse_flags(args, "knv") {
+			Ok((flags, operands)) if operands.is_empty() => flags,
+			_ => {
+				errln!("usage: lspci [-knv]");
+				return 1;
+			}
+		};
+		let numeric = flags.contains(&'n');
+		let verbose = flags.contains(&'v');
+		let drivers = flags.contains(&'k');
+
+		for device in pci::devices() {
+			let address = device.address;
+			out!("{:02x}:{:02x}.{} ", address.bus, address.device, address.function);
+			let class_code = (device.class as u16) << 8 | device.subclass as u16;
+			if numeric {
+				out!("{:04x}: {:04x}:{:04x}", class_code, device.vendor_id, device.device_id);
+			} else {
+				let vendor = pci::vendor_name(device.vendor_id)
+					.map_or_else(|| format!("Vendor {:04x}", device.vendor_id), String::from);
+				let name = pci::device_name(device.vendor_id, device.device_id)
+					.map_or_else(|| format!("Device {:04x}", device.device_id), String::from);
+				out!("{} [{:04x}]: {} {} [{:04x}:{:04x}]", pci::class_name(device.class, device.subclass),
+					class_code, vendor, name, device.vendor_id, device.device_id);
+			}
+			if device.revision != 0 {
+				out!(" (rev {:02x})", device.revision);
+			}
+			outln!();
+			if drivers {
+				if let Some(driver) = pci::driver_of(address) {
+					outln!("\tKernel driver in use: {}", driver);
+				}
+			}
+			if !verbose {
+				continue;
+			}
+
+			match (device.interrupt_pin, device.interrupt_line) {
+				(0, _) => {}
+				(pin, Some(line)) => outln!("\tInterrupt: pin {} routed to IRQ {}", (b'A' + pin - 1) as char, line),
+				(pin, None) => outln!("\tInterrupt: pin {} not routed", (b'A' + pin - 1) as char),
+			}
+			if device.msi.is_some() {
+				outln!("\tCapabilities: MSI");
+			}
+			if let Some(vectors) = device.msix_vectors() {
+				outln!("\tCapabilities: MSI-X with {} vectors", vectors);
+			}
+			for bar in &device.bars {
+				match *bar {
+					Bar::Memory { address, size, prefetchable, is_64bit } => outln!(
+						"\tMemory at {:08x} ({}-bit, {}prefetchable) [size={}]",
+						address, if is_64bit { 64 } else { 32 }, if prefetchable { "" } else { "non-" }, format_size(size)
+					),
+					Bar::Io { port, size } => outln!("\tI/O ports at {:04x} [size={}]", port, format_size(size as u64)),
+				}
+			}
+			outln!();
+		}
+		0
+	}
+}
+
+impl Shell {
+	/// Show or change console colors: `color [FG [BG]]`, `color -t THEME`, `color -l`
+	pub(super) fn cmd_color(&self, args: &[&str]) -> i32 {
+		match args {
+			[] | ["-l"] => {
+				let (foreground, background) = x86_64::instructions::interrupts::without_interrupts(|| {
+					WRITER.lock().default_colors()
+				});
+				outln!("Current: {} on {} (theme {})", foreground.name(), background.name(), vga_buffer::theme().name);
+				let names: Vec<&str> = Color::ALL.iter().map(|color| color.name()).collect();
+				outln!("Colors: {}", names.join(" "));
+				let themes: Vec<&str> = THEMES.iter().map(|theme| theme.name).collect();
+				outln!("Themes: {}", themes.join(" "));
+				0
+			}
+			["-t", name] => {
+				if vga_buffer::set_theme(name) {
+					0
+				} else {
+					errln!("color: unknown theme '{}'", name);
+					1
+				}
+			}
+			[foreground] | [foreground, _] if !foreground.starts_with('-') => {
+				let parsed: Option<Vec<Color>> = args.iter().map(|name| Color::from_name(name)).collect();
+				let Some(colors) = parsed else {
+					errln!("color: unknown color (see 'color -l')");
+					return 1;
+				};
+				let background = colors.get(1).copied().unwrap_or(vga_buffer::theme().background);
+				vga_buffer::set_color(colors[0], background);
+				0
+			}
+			_ => {
+				errln!("usage: color [FG [BG]] | color -t THEME | color -l");
+				1
+			}
+		}
+	}
+
+	/// Set the keyboard's repeat delay and rate: `kbdrate [-s] [-r RATE] [-d DELAY]`,
+	/// with what is not given going back to 10.9 a second after 250 ms
+	pub(super) fn cmd_kbdrate(&self, args: &[&str]) -> i32 {
+		let (mut rate, mut delay_ms, mut silent) = (109, 250, false);
+		let mut rest = args;
+		while let Some((&flag, tail)) = rest.split_first() {
+			let value = tail.first().copied();
+			let parsed = match flag {
+				"-s" => {
+					silent = true;
+					rest = tail;
+					continue;
+				}
+				"-r" => value.and_then(parse_tenths).map(|value| rate = value),
+				"-d" => value.and_then(|value| value.parse().ok()).map(|value| delay_ms = value),
+				_ => None,
+			};
+			if parsed.is_none() {
+				errln!("usage: kbdrate [-s] [-r RATE] [-d DELAY]");
+				return 1;
+			}
+			rest = &tail[1..];
+		}
+		let (delay_ms, rate) = keyboard::set_typematic(delay_ms, rate);
+		if !silent {
+			outln!("Typematic Rate set to {}.{} cps (delay = {} ms)", rate / 10, rate % 10, delay_ms);
+		}
+		0
+	}
+
+	/// Show or change the console's terminal modes: `stty [SETTING]...`
+	pub(super) fn cmd_stty(&self, args: &[&str]) -> i32 {
+		let mut termios = tty::termios(self.console);
+		for &setting in args {
+			match setting {
+				"sane" => termios = Termios::SANE,
+				"raw" => termios = Termios::RAW,
+				"icanon" | "-raw" | "cooked" => termios.canonical = true,
+				"-icanon" => termios.canonical = false,
+				"echo" => termios.echo = true,
+				"-echo" => termios.echo = false,
+				_ => {
+					errln!("stty: invalid argument '{}'", setting);
+					return 1;
+				}
+			}
+		}
+		if args.is_empty() {
+			let flag = |on: bool, name: &str| format!("{}{}", if on { "" } else { "-" }, name);
+			outln!("{} {}", flag(termios.canonical, "icanon"), flag(termios.echo, "echo"));
+		} else {
+			tty::set_termios(self.console, termios);
+		}
+		0
+	}
+
+	/// Show or change the keyboard layout: `keymap [LAYOUT]`
+	pub(super) fn cmd_keymap(&self, args: &[&str]) -> i32 {
+		match args {
+			[] => {
+				outln!("Current: {}", keymap::current().name());
+				let names: Vec<&str> = Layout::ALL.iter().map(|layout| layout.name()).collect();
+				outln!("Layouts: {}", names.join(" "));
+				0
+			}
+			[name] => match Layout::from_name(name) {
+				Some(layout) => {
+					keymap::set(layout);
+					0
+				}
+				None => {
+					errln!("keymap: unknown layout '{}'", name);
+					1
+				}
+			},
+			_ => {
+				errln!("usage: keymap [LAYOUT]");
+				1
+			}
+		}
+	}
+}
+
+/// Test BAR size formatting and date argument parsing
+#[test_case]
+fn test_sysutil_helpers() {
+	assert_eq!(format_size(128 * 1024), "128K");
+	assert_eq!(format_size(16 << 20), "16M");
+	assert_eq!(format_size(100), "100");
+	assert_eq!(parse_time("@86400").map(|t| t.format("%F %T")).as_deref(), Some("1970-01-02 00:00:00"));
+	assert!(parse_time("yesterday").is_none());
+	assert_eq!((parse_tenths("10.9"), parse_tenths("30"), parse_tenths("2.")), (Some(109), Some(300), None));
+}
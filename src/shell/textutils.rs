@@ -0,0 +1,258 @@
+use super::fileutils::parse_flags;
+use super::{read_fd, read_file, Shell};
+use alloc::{string::String, vec::Vec};
+use alloc::string::ToString;
+
+/// Number of lines `head` and `tail` show by default
+const DEFAULT_LINES: usize = 10;
+
+/// Read each operand (or standard input when there are none, or for `-`)
+///
+/// Unreadable files are reported and skipped; the flag records whether any failed.
+fn read_inputs(cmd: &str, operands: &[&str]) -> (Vec<(String, String)>, bool) {
+	let mut inputs = Vec::new();
+	let mut failed = false;
+	let operands = if operands.is_empty() { &["-"][..] } else { operands };
+	for &name in operands {
+		let data = if name == "-" { read_fd(0) } else { read_file(name) };
+		match data {
+			Ok(data) => inputs.push((name.to_string(), String::from_utf8_lossy(&data).into_owned())),
+			Err(err) => {
+				errln!("{}: {}: {}", cmd, name, err.as_str());
+				failed = true;
+			}
+		}
+	}
+	(inputs, failed)
+}
+
+/// Parse the `-n N`, `-nN`, or `-N` line count taken by `head` and `tail`
+fn parse_line_count<'a>(args: &[&'a str]) -> Result<(usize, Vec<&'a str>), String> {
+	let mut count = DEFAULT_LINES;
+	let mut operands = Vec::new();
+	let mut args = args.iter();
+	while let Some(&arg) = args.next() {
+		let value = match arg {
+			"-n" => args.next().copied().ok_or("option requires an argument -- 'n'")?,
+			_ if arg.starts_with("-n") => &arg[2..],
+			_ if arg.len() > 1 && arg.starts_with('-') && arg[1..].bytes().all(|b| b.is_ascii_digit()) => &arg[1..],
+			_ if arg.len() > 1 && arg.starts_with('-') => return Err(alloc::format!("invalid option -- '{}'", &arg[1..])),
+			_ => {
+				operands.push(arg);
+				continue;
+			}
+		};
+		count = value.parse().map_err(|_| alloc::format!("invalid number of lines: '{}'", value))?;
+	}
+	Ok((count, operands))
+}
+
+/// Whether `line` matches a grep pattern: a substring, optionally anchored
+/// with `^` and/or `$`
+fn grep_matches(pattern: &str, line: &str) -> bool {
+	let (start, pattern) = match pattern.strip_prefix('^') {
+		Some(rest) => (true, rest),
+		None => (false, pattern),
+	};
+	let (end, pattern) = match pattern.strip_suffix('$') {
+		Some(rest) => (true, rest),
+		None => (false, pattern),
+	};
+	match (start, end) {
+		(true, true) => line == pattern,
+		(true, false) => line.starts_with(pattern),
+		(false, true) => line.ends_with(pattern),
+		(false, false) => line.contains(pattern),
+	}
+}
+
+/// Compare two lines by their leading number, as `sort -n` does
+fn numeric_key(line: &str) -> i64 {
+	let line = line.trim_start();
+	let digits = line.char_indices()
+		.take_while(|&(i, c)| c.is_ascii_digit() || (i == 0 && c == '-'))
+		.count();
+	line[..digits].parse().unwrap_or(0)
+}
+
+impl Shell {
+	/// Print lines matching a pattern (`-i` ignore case, `-v` invert, `-n` line
+	/// numbers, `-c` count only)
+	pub(super) fn cmd_grep(&self, args: &[&str]) -> i32 {
+		let (flags, operands) = match parse_flags(args, "ivnc") {
+			Ok(parsed) => parsed,
+			Err(flag) => {
+				errln!("grep: invalid option -- '{}'", flag);
+				return 2;
+			}
+		};
+		let Some((&pattern, files)) = operands.split_first() else {
+			errln!("usage: grep [-ivnc] PATTERN [FILE...]");
+			return 2;
+		};
+		let ignore_case = flags.contains(&'i');
+		let invert = flags.contains(&'v');
+		let numbered = flags.contains(&'n');
+		let count_only = flags.contains(&'c');
+		let pattern = if ignore_case { pattern.to_lowercase() } else { pattern.to_string() };
+
+		let (inputs, failed) = read_inputs("grep", files);
+		let prefix = files.len() > 1;
+		let mut found = false;
+		for (name, text) in &inputs {
+			let mut count = 0;
+			for (i, line) in text.lines().enumerate() {
+				let matched = if ignore_case {
+					grep_matches(&pattern, &line.to_lowercase())
+				} else {
+					grep_matches(&pattern, line)
+				};
+				if matched == invert {
+					continue;
+				}
+				count += 1;
+				if count_only {
+					continue;
+				}
+				if prefix {
+					out!("{}:", name);
+				}
+				if numbered {
+					out!("{}:", i + 1);
+				}
+				outln!("{}", line);
+			}
+			if count_only {
+				if prefix {
+					out!("{}:", name);
+				}
+				outln!("{}", count);
+			}
+			found |= count > 0;
+		}
+		if failed { 2 } else if found { 0 } else { 1 }
+	}
+
+	/// Print the first lines of each input (`-n N`)
+	pub(super) fn cmd_head(&self, args: &[&str]) -> i32 {
+		self.print_lines("head", args, |lines, count| &lines[..count.min(lines.len())])
+	}
+
+	/// Print the last lines of each input (`-n N`)
+	pub(super) fn cmd_tail(&self, args: &[&str]) -> i32 {
+		self.print_lines("tail", args, |lines, count| &lines[lines.len().saturating_sub(count)..])
+	}
+
+	/// Shared driver for `head` and `tail`: `select` picks which lines to show
+	fn print_lines<F>(&self, cmd: &str, args: &[&str], select: F) -> i32
+	where
+		F: for<'a> Fn(&'a [&'a str], usize) -> &'a [&'a str],
+	{
+		let (count, operands) = match parse_line_count(args) {
+			Ok(parsed) => parsed,
+			Err(msg) => {
+				errln!("{}: {}", cmd, msg);
+				return 1;
+			}
+		};
+		let (inputs, failed) = read_inputs(cmd, &operands);
+		let headers = operands.len() > 1;
+		for (i, (name, text)) in inputs.iter().enumerate() {
+			if headers {
+				if i > 0 {
+					outln!();
+				}
+				outln!("==> {} <==", name);
+			}
+			let lines: Vec<&str> = text.lines().collect();
+			for line in select(&lines, count) {
+				outln!("{}", line);
+			}
+		}
+		if failed { 1 } else { 0 }
+	}
+
+	/// Count lines, words, and bytes (`-l`, `-w`, `-c` select columns)
+	pub(super) fn cmd_wc(&self, args: &[&str]) -> i32 {
+		let (mut flags, operands) = match parse_flags(args, "lwc") {
+			Ok(parsed) => parsed,
+			Err(flag) => {
+				errln!("wc: invalid option -- '{}'", flag);
+				return 1;
+			}
+		};
+		if flags.is_empty() {
+			flags = alloc::vec!['l', 'w', 'c'];
+		}
+		let (inputs, failed) = read_inputs("wc", &operands);
+
+		let print = |counts: [usize; 3], name: &str| {
+			for (flag, count) in ['l', 'w', 'c'].iter().zip(counts) {
+				if flags.contains(flag) {
+					out!("{:>7} ", count);
+				}
+			}
+			outln!("{}", name);
+		};
+		let mut total = [0; 3];
+		for (name, text) in &inputs {
+			let counts = [
+				text.bytes().filter(|&b| b == b'\n').count(),
+				text.split_whitespace().count(),
+				text.len(),
+			];
+			for (sum, count) in total.iter_mut().zip(counts) {
+				*sum += count;
+			}
+			print(counts, if operands.is_empty() { "" } else { name });
+		}
+		if inputs.len() > 1 {
+			print(total, "total");
+		}
+		if failed { 1 } else { 0 }
+	}
+
+	/// Sort lines (`-r` reverse, `-n` numeric, `-u` drop duplicates)
+	pub(super) fn cmd_sort(&self, args: &[&str]) -> i32 {
+		let (flags, operands) = match parse_flags(args, "rnu") {
+			Ok(parsed) => parsed,
+			Err(flag) => {
+				errln!("sort: invalid option -- '{}'", flag);
+				return 2;
+			}
+		};
+		let (inputs, failed) = read_inputs("sort", &operands);
+		let mut lines: Vec<&str> = inputs.iter().flat_map(|(_, text)| text.lines()).collect();
+		if flags.contains(&'n') {
+			lines.sort_by(|a, b| numeric_key(a).cmp(&numeric_key(b)).then(a.cmp(b)));
+		} else {
+			lines.sort();
+		}
+		if flags.contains(&'u') {
+			lines.dedup();
+		}
+		if flags.contains(&'r') {
+			lines.reverse();
+		}
+		for line in lines {
+			outln!("{}", line);
+		}
+		if failed { 2 } else { 0 }
+	}
+}
+
+/// Test grep anchors and head/tail line-count parsing
+#[test_case]
+fn test_text_helpers() {
+	assert!(grep_matches("root", "root:x:0:0"));
+	assert!(grep_matches("^root", "root:x:0:0"));
+	assert!(!grep_matches("^x", "root:x:0:0"));
+	assert!(grep_matches("sh$", "/bin/sh"));
+	assert!(grep_matches("^$", ""));
+	assert_eq!(parse_line_count(&["-n", "3", "a"]), Ok((3, alloc::vec!["a"])));
+	assert_eq!(parse_line_count(&["-5"]), Ok((5, Vec::new())));
+	assert_eq!(parse_line_count(&["b"]), Ok((DEFAULT_LINES, alloc::vec!["b"])));
+	assert!(parse_line_count(&["-n"]).is_err());
+	assert_eq!(numeric_key("  42 apples"), 42);
+	assert_eq!(numeric_key("-7"), -7);
+}
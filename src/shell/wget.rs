@@ -0,0 +1,389 @@
+use super::{write_all, Shell};
+use crate::fs::{O_CREAT, O_TRUNC, O_WRONLY};
+use crate::net::dns;
+use crate::syscall::{self, SyscallError};
+use alloc::{format, string::String, vec::Vec};
+use core::net::SocketAddrV4;
+
+/// Port of a URL that names none
+const HTTP_PORT: u16 = 80;
+/// Redirects followed before giving up
+const MAX_REDIRECTS: usize = 5;
+/// Longest response head accepted
+const MAX_HEAD: usize = 8192;
+/// Longest chunk-size line accepted
+const MAX_CHUNK_LINE: usize = 64;
+/// Bytes read from the connection at a time
+const READ_SIZE: usize = 1460;
+
+/// The parts of an `http://` URL a request needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Url {
+	host: String,
+	port: u16,
+	/// From the `/` on, query included
+	path: String,
+}
+
+impl Url {
+	/// Parse `[http://]HOST[:PORT][/PATH]`
+	fn parse(text: &str) -> Result<Url, &'static str> {
+		let rest = match text.split_once("://") {
+			Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+			Some(_) => return Err("only http:// URLs are supported"),
+			None => text,
+		};
+		let (authority, path) = match rest.find(['/', '?']) {
+			Some(at) if rest[at..].starts_with('/') => (&rest[..at], String::from(&rest[at..])),
+			Some(at) => (&rest[..at], format!("/{}", &rest[at..])),
+			None => (rest, String::from("/")),
+		};
+		let (host, port) = match authority.rsplit_once(':') {
+			Some((host, port)) => (host, port.parse().map_err(|_| "invalid port")?),
+			None => (authority, HTTP_PORT),
+		};
+		if host.is_empty() {
+			return Err("missing host");
+		}
+		Ok(Url { host: String::from(host), port, path })
+	}
+
+	/// Where a `Location` header points, relative to this URL
+	fn join(&self, location: &str) -> Result<Url, &'static str> {
+		if location.starts_with('/') {
+			Ok(Url { host: self.host.clone(), port: self.port, path: String::from(location) })
+		} else {
+			Url::parse(location)
+		}
+	}
+
+	/// The file a download is saved to unless told otherwise: the last part
+	/// of the path, or `index.html`
+	fn file_name(&self) -> &str {
+		let path = self.path.split(['?', '#']).next().unwrap_or("");
+		match path.rsplit('/').next() {
+			Some(name) if !name.is_empty() => name,
+			_ => "index.html",
+		}
+	}
+}
+
+/// What a response's head says about it and its body
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Head {
+	status: u16,
+	reason: String,
+	content_length: Option<usize>,
+	chunked: bool,
+	location: Option<String>,
+}
+
+impl Head {
+	/// Parse a response's status line and headers, without the blank line
+	fn parse(text: &str) -> Option<Head> {
+		let mut lines = text.split("\r\n");
+		let mut status_line = lines.next()?.splitn(3, ' ');
+		if !status_line.next()?.starts_with("HTTP/1.") {
+			return None;
+		}
+		let status = status_line.next()?.parse().ok()?;
+		let reason = String::from(status_line.next().unwrap_or(""));
+		let mut head = Head { status, reason, content_length: None, chunked: false, location: None };
+		for line in lines {
+			let Some((name, value)) = line.split_once(':') else {
+				continue;
+			};
+			let value = value.trim();
+			if name.eq_ignore_ascii_case("content-length") {
+				head.content_length = value.parse().ok();
+			} else if name.eq_ignore_ascii_case("transfer-encoding") {
+				head.chunked = value.eq_ignore_ascii_case("chunked");
+			} else if name.eq_ignore_ascii_case("location") {
+				head.location = Some(String::from(value));
+			}
+		}
+		Some(head)
+	}
+}
+
+/// Where a chunked body's decoding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChunkState {
+	/// Waiting for a chunk's size line
+	Size,
+	/// Inside a chunk, with this much of it to come
+	Data(usize),
+	/// Waiting for the line break after a chunk
+	DataEnd,
+	/// The last chunk has been seen; trailers are ignored
+	Done,
+}
+
+/// Decodes a chunked body as it arrives, however it is split
+struct ChunkDecoder {
+	pending: Vec<u8>,
+	state: ChunkState,
+}
+
+impl ChunkDecoder {
+	fn new() -> ChunkDecoder {
+		ChunkDecoder { pending: Vec::new(), state: ChunkState::Size }
+	}
+
+	/// Decode what of `input` can be, adding the data to `out`
+	fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), &'static str> {
+		self.pending.extend_from_slice(input);
+		let mut at = 0;
+		loop {
+			let rest = &self.pending[at..];
+			match self.state {
+				ChunkState::Done => break,
+				ChunkState::Data(remaining) => {
+					let take = remaining.min(rest.len());
+					if take == 0 {
+						break;
+					}
+					out.extend_from_slice(&rest[..take]);
+					at += take;
+					self.state = if take == remaining { ChunkState::DataEnd } else { ChunkState::Data(remaining - take) };
+				}
+				ChunkState::DataEnd => {
+					if rest.len() < 2 {
+						break;
+					}
+					if &rest[..2] != b"\r\n" {
+						return Err("bad chunk");
+					}
+					at += 2;
+					self.state = ChunkState::Size;
+				}
+				ChunkState::Size => {
+					let Some(end) = rest.windows(2).position(|pair| pair == b"\r\n") else {
+						if rest.len() > MAX_CHUNK_LINE {
+							return Err("bad chunk size");
+						}
+						break;
+					};
+					let line = core::str::from_utf8(&rest[..end]).map_err(|_| "bad chunk size")?;
+					let size = line.split(';').next().unwrap_or("").trim();
+					let size = usize::from_str_radix(size, 16).map_err(|_| "bad chunk size")?;
+					at += end + 2;
+					self.state = if size == 0 { ChunkState::Done } else { ChunkState::Data(size) };
+				}
+			}
+		}
+		self.pending.drain(..at);
+		Ok(())
+	}
+
+	fn done(&self) -> bool {
+		self.state == ChunkState::Done
+	}
+}
+
+/// Read from `fd` until the end of a response's head, returning the head
+/// and whatever of the body came with it
+fn read_head(fd: usize) -> Result<(Head, Vec<u8>), String> {
+	let mut received = Vec::new();
+	let mut buf = [0; READ_SIZE];
+	loop {
+		if let Some(end) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+			let head = core::str::from_utf8(&received[..end]).ok().and_then(Head::parse).ok_or("malformed response")?;
+			return Ok((head, received.split_off(end + 4)));
+		}
+		if received.len() > MAX_HEAD {
+			return Err(String::from("response head too long"));
+		}
+		let count = syscall::sys_read(fd, &mut buf).map_err(|err| String::from(err.as_str()))?;
+		if count == 0 {
+			return Err(String::from("connection closed before a response"));
+		}
+		received.extend_from_slice(&buf[..count]);
+	}
+}
+
+/// Copy a response's body from `fd` to `file`, starting with the part of
+/// it already read; returns the bytes written
+fn save_body(fd: usize, file: usize, head: &Head, start: Vec<u8>) -> Result<usize, String> {
+	let message = |err: SyscallError| String::from(err.as_str());
+	let mut decoder = head.chunked.then(ChunkDecoder::new);
+	let mut saved = 0;
+	let mut data = start;
+	let mut buf = [0; READ_SIZE];
+	loop {
+		let mut decoded = Vec::new();
+		let chunk = match decoder.as_mut() {
+			Some(decoder) => {
+				decoder.feed(&data, &mut decoded)?;
+				&decoded[..]
+			}
+			None => {
+				let left = head.content_length.map_or(data.len(), |length| length - saved);
+				&data[..data.len().min(left)]
+			}
+		};
+		write_all(file, chunk).map_err(message)?;
+		saved += chunk.len();
+		let finished = match &decoder {
+			Some(decoder) => decoder.done(),
+			None => head.content_length == Some(saved),
+		};
+		if finished {
+			return Ok(saved);
+		}
+		let count = syscall::sys_read(fd, &mut buf).map_err(message)?;
+		if count == 0 {
+			// Without a length or chunks, the body ends with the connection
+			return if decoder.is_none() && head.content_length.is_none() { Ok(saved) } else { Err(String::from("connection closed early")) };
+		}
+		data = Vec::from(&buf[..count]);
+	}
+}
+
+impl Shell {
+	/// Download a URL into a file: `wget URL [PATH]`
+	pub(super) fn cmd_wget(&self, args: &[&str]) -> i32 {
+		let (url, path) = match args {
+			[url] => (*url, None),
+			[url, path] => (*url, Some(*path)),
+			_ => {
+				errln!("usage: wget URL [PATH]");
+				return 2;
+			}
+		};
+		let mut url = match Url::parse(url) {
+			Ok(url) => url,
+			Err(msg) => {
+				errln!("wget: {}: {}", url, msg);
+				return 2;
+			}
+		};
+		for _ in 0..=MAX_REDIRECTS {
+			let fd = match connect(&url) {
+				Ok(fd) => fd,
+				Err(msg) => {
+					errln!("wget: {}", msg);
+					return 4;
+				}
+			};
+			let result = request(fd, &url);
+			let (head, body) = match result {
+				Ok(response) => response,
+				Err(msg) => {
+					let _ = syscall::sys_close(fd);
+					errln!("wget: {}", msg);
+					return 4;
+				}
+			};
+			outln!("{} {}", head.status, head.reason);
+			if (300..400).contains(&head.status) {
+				if let Some(location) = &head.location {
+					let _ = syscall::sys_close(fd);
+					url = match url.join(location) {
+						Ok(next) => next,
+						Err(msg) => {
+							errln!("wget: {}: {}", location, msg);
+							return 8;
+						}
+					};
+					outln!("Location: {} [following]", location);
+					continue;
+				}
+			}
+			if !(200..300).contains(&head.status) {
+				let _ = syscall::sys_close(fd);
+				errln!("wget: ERROR {}: {}", head.status, head.reason);
+				return 8;
+			}
+			let path = path.unwrap_or(url.file_name());
+			let status = self.save(fd, path, &head, body);
+			let _ = syscall::sys_close(fd);
+			return status;
+		}
+		errln!("wget: {} redirects exceeded", MAX_REDIRECTS);
+		8
+	}
+
+	/// Save a response's body to `path`, reporting how it went
+	fn save(&self, fd: usize, path: &str, head: &Head, body: Vec<u8>) -> i32 {
+		match head.content_length {
+			Some(length) => outln!("Length: {}", length),
+			None => outln!("Length: unspecified"),
+		}
+		outln!("Saving to: '{}'", path);
+		let file = match syscall::sys_open(path, O_WRONLY | O_CREAT | O_TRUNC, 0o644) {
+			Ok(file) => file,
+			Err(err) => {
+				errln!("wget: {}: {}", path, err.as_str());
+				return 3;
+			}
+		};
+		let result = save_body(fd, file, head, body);
+		let _ = syscall::sys_close(file);
+		match result {
+			Ok(saved) => {
+				outln!("'{}' saved [{}]", path, saved);
+				0
+			}
+			Err(msg) => {
+				errln!("wget: {}: {}", path, msg);
+				4
+			}
+		}
+	}
+}
+
+/// Resolve a URL's host and open a connection to it
+fn connect(url: &Url) -> Result<usize, String> {
+	let address = dns::resolve(&url.host).map_err(|err| format!("{}: {}", url.host, err.as_str()))?;
+	out!("Connecting to {} ({}):{}... ", url.host, address, url.port);
+	let fd = syscall::sys_socket(syscall::AF_INET, syscall::SOCK_STREAM, 0).map_err(|err| String::from(err.as_str()))?;
+	if let Err(err) = syscall::sys_connect(fd, SocketAddrV4::new(address, url.port)) {
+		let _ = syscall::sys_close(fd);
+		outln!("failed.");
+		return Err(format!("{}:{}: {}", url.host, url.port, err.as_str()));
+	}
+	outln!("connected.");
+	Ok(fd)
+}
+
+/// Send a GET for a URL on a connection and read the response's head
+fn request(fd: usize, url: &Url) -> Result<(Head, Vec<u8>), String> {
+	let host = if url.port == HTTP_PORT { url.host.clone() } else { format!("{}:{}", url.host, url.port) };
+	let request = format!(
+		"GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: ScottOS-wget/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+		url.path,
+		host,
+		env!("CARGO_PKG_VERSION")
+	);
+	syscall::sys_sendto(fd, request.as_bytes(), None).map_err(|err| String::from(err.as_str()))?;
+	out!("HTTP request sent, awaiting response... ");
+	read_head(fd)
+}
+
+/// Test parsing URLs and response heads, and decoding chunks split anywhere
+#[test_case]
+fn test_http() {
+	let url = Url::parse("http://example.com:8080/files/a.txt?v=1").unwrap();
+	assert_eq!(url, Url { host: String::from("example.com"), port: 8080, path: String::from("/files/a.txt?v=1") });
+	assert_eq!(url.file_name(), "a.txt");
+	assert_eq!(Url::parse("example.com").unwrap().path, "/");
+	assert_eq!(Url::parse("example.com?q").unwrap().path, "/?q");
+	assert_eq!(Url::parse("example.com").unwrap().file_name(), "index.html");
+	assert!(Url::parse("https://example.com/").is_err());
+	assert_eq!(url.join("/b").unwrap().port, 8080);
+
+	let head = Head::parse("HTTP/1.1 301 Moved Permanently\r\nlocation: /new\r\nContent-Length: 0").unwrap();
+	assert_eq!((head.status, head.reason.as_str(), head.content_length, head.location.as_deref()), (301, "Moved Permanently", Some(0), Some("/new")));
+	assert_eq!(Head::parse("SSH-2.0 hello"), None);
+
+	let body = b"5;ext\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+	for split in 0..body.len() {
+		let (mut decoder, mut out) = (ChunkDecoder::new(), Vec::new());
+		decoder.feed(&body[..split], &mut out).unwrap();
+		decoder.feed(&body[split..], &mut out).unwrap();
+		assert!(decoder.done());
+		assert_eq!(out, b"hello world");
+	}
+	assert!(ChunkDecoder::new().feed(b"zz\r\n", &mut Vec::new()).is_err());
+}
@@ -0,0 +1,273 @@
+use super::{write_file, Shell};
+use crate::serial;
+use crate::task::{keyboard, serial as serial_input};
+use crate::time::Instant;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// XMODEM control bytes
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Padding after the end of the file in the last block
+const SUB: u8 = 0x1a;
+/// Sent instead of NAK to ask for blocks with a CRC
+const CRC_REQUEST: u8 = b'C';
+
+/// Times the receiver asks the sender to start, and how long it waits each time
+const START_TRIES: usize = 10;
+const START_TIMEOUT_MS: u64 = 3000;
+/// Tries asking for CRC blocks before falling back to checksums
+const CRC_TRIES: usize = 4;
+/// Longest gap allowed between the bytes of a block
+const BYTE_TIMEOUT_MS: u64 = 1000;
+/// Bad blocks in a row before giving up
+const MAX_ERRORS: usize = 10;
+
+/// CRC-16/XMODEM of `data`
+fn crc16(data: &[u8]) -> u16 {
+	let mut crc: u16 = 0;
+	for &byte in data {
+		crc ^= (byte as u16) << 8;
+		for _ in 0..8 {
+			crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+		}
+	}
+	crc
+}
+
+/// What to tell the sender about a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reply {
+	Ack,
+	Nak,
+	/// The sender skipped a block; the transfer cannot recover
+	Cancel,
+}
+
+/// The state of a file being received
+struct Transfer {
+	/// Whether blocks end in a CRC rather than a checksum
+	crc: bool,
+	/// Number of the block expected next, modulo 256
+	expected: u8,
+	data: Vec<u8>,
+}
+
+impl Transfer {
+	fn new(crc: bool) -> Transfer {
+		Transfer { crc, expected: 1, data: Vec::new() }
+	}
+
+	/// Length of a block after its start byte: number, complement, data, and check
+	fn block_len(&self, start: u8) -> usize {
+		let data = if start == STX { 1024 } else { 128 };
+		2 + data + if self.crc { 2 } else { 1 }
+	}
+
+	/// Check a block received after its start byte, keeping its data if it
+	/// is the next one
+	fn receive(&mut self, block: &[u8]) -> Reply {
+		let (number, complement) = (block[0], block[1]);
+		let check_len = if self.crc { 2 } else { 1 };
+		let (data, check) = block[2..].split_at(block.len() - 2 - check_len);
+		let valid = if self.crc {
+			crc16(data) == u16::from_be_bytes([check[0], check[1]])
+		} else {
+			data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == check[0]
+		};
+		if number != !complement || !valid {
+			return Reply::Nak;
+		}
+		if number == self.expected {
+			self.data.extend_from_slice(data);
+			self.expected = self.expected.wrapping_add(1);
+			Reply::Ack
+		} else if number == self.expected.wrapping_sub(1) {
+			// Our ACK was lost and the sender repeated the block
+			Reply::Ack
+		} else {
+			Reply::Cancel
+		}
+	}
+
+	/// The file, without the padding at the end of its last block
+	fn into_file(mut self) -> Vec<u8> {
+		while self.data.last() == Some(&SUB) {
+			self.data.pop();
+		}
+		self.data
+	}
+}
+
+/// Wait up to `timeout_ms` for a byte from the serial port
+fn read_byte(timeout_ms: u64) -> Option<u8> {
+	let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+	loop {
+		if let Some(byte) = serial_input::read_byte() {
+			return Some(byte);
+		}
+		if Instant::now() >= deadline || keyboard::interrupt_requested() {
+			return None;
+		}
+		// The next byte or timer tick wakes us
+		x86_64::instructions::hlt();
+	}
+}
+
+/// Why a transfer failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+	/// The sender never started
+	NoSender,
+	/// The sender, or Ctrl-C, cancelled it
+	Cancelled,
+	/// Too many bad blocks in a row
+	TooManyErrors,
+	/// The sender skipped a block
+	OutOfSequence,
+}
+
+impl Failure {
+	fn as_str(self) -> &'static str {
+		match self {
+			Failure::NoSender => "no sender started a transfer",
+			Failure::Cancelled => "transfer cancelled",
+			Failure::TooManyErrors => "too many errors",
+			Failure::OutOfSequence => "blocks out of sequence",
+		}
+	}
+}
+
+/// Receive a file with XMODEM, asking for CRC blocks first and checksums
+/// if the sender does not answer
+fn receive_file() -> Result<Vec<u8>, Failure> {
+	let cancel = || serial::write_raw(&[CAN, CAN]);
+	let mut start = None;
+	let mut crc = true;
+	for attempt in 0..START_TRIES {
+		crc = attempt < CRC_TRIES;
+		serial::write_raw(&[if crc { CRC_REQUEST } else { NAK }]);
+		start = read_byte(START_TIMEOUT_MS);
+		if start.is_some() || keyboard::interrupt_requested() {
+			break;
+		}
+	}
+	let mut transfer = Transfer::new(crc);
+	let mut errors = 0;
+	let mut block = Vec::new();
+	loop {
+		if keyboard::interrupt_requested() {
+			cancel();
+			return Err(Failure::Cancelled);
+		}
+		match start.take() {
+			Some(EOT) => {
+				serial::write_raw(&[ACK]);
+				return Ok(transfer.into_file());
+			}
+			Some(CAN) if read_byte(BYTE_TIMEOUT_MS) == Some(CAN) => return Err(Failure::Cancelled),
+			Some(header @ (SOH | STX)) => {
+				block.clear();
+				while block.len() < transfer.block_len(header) {
+					let Some(byte) = read_byte(BYTE_TIMEOUT_MS) else {
+						break;
+					};
+					block.push(byte);
+				}
+				let reply = if block.len() == transfer.block_len(header) { transfer.receive(&block) } else { Reply::Nak };
+				match reply {
+					Reply::Ack => {
+						errors = 0;
+						serial::write_raw(&[ACK]);
+					}
+					Reply::Nak => errors += 1,
+					Reply::Cancel => {
+						cancel();
+						return Err(Failure::OutOfSequence);
+					}
+				}
+			}
+			None if transfer.expected == 1 && transfer.data.is_empty() && errors == 0 => return Err(Failure::NoSender),
+			// Noise or a timeout
+			_ => errors += 1,
+		}
+		if errors > MAX_ERRORS {
+			cancel();
+			return Err(Failure::TooManyErrors);
+		}
+		if errors > 0 {
+			// Let the line go quiet, then ask again
+			while read_byte(BYTE_TIMEOUT_MS).is_some() {}
+			serial::write_raw(&[NAK]);
+		}
+		start = read_byte(START_TIMEOUT_MS);
+	}
+}
+
+impl Shell {
+	/// Receive a file over the serial port with XMODEM: `rx <path>`
+	pub(super) fn cmd_rx(&self, args: &[&str]) -> i32 {
+		let [path] = args else {
+			errln!("usage: rx <path>");
+			return 1;
+		};
+		outln!("rx: ready to receive {} with XMODEM; start sending now (Ctrl-C cancels)", path);
+
+		// Bytes typed before the transfer are not part of it
+		while serial_input::read_byte().is_some() {}
+		serial::set_raw_mode(true);
+		let result = receive_file();
+		serial::set_raw_mode(false);
+		keyboard::clear_interrupt();
+
+		let data = match result {
+			Ok(data) => data,
+			Err(failure) => {
+				errln!("rx: {}", failure.as_str());
+				return 1;
+			}
+		};
+		if let Err(err) = write_file(path, &data) {
+			errln!("rx: {}: {}", path, err.as_str());
+			return 1;
+		}
+		outln!("rx: received {} bytes into {}", data.len(), path);
+		0
+	}
+}
+
+/// Test block checking, duplicates, and padding removal
+#[test_case]
+fn test_xmodem_blocks() {
+	assert_eq!(crc16(b"123456789"), 0x31c3);
+
+	let block = |number: u8, fill: u8, crc: bool| {
+		let mut block = alloc::vec![number, !number];
+		block.extend_from_slice(&[fill; 128]);
+		if crc {
+			block.extend_from_slice(&crc16(&[fill; 128]).to_be_bytes());
+		} else {
+			block.push((0..128).fold(0u8, |sum, _| sum.wrapping_add(fill)));
+		}
+		block
+	};
+	let mut transfer = Transfer::new(true);
+	assert_eq!(transfer.block_len(SOH), 132);
+	assert_eq!(transfer.receive(&block(1, b'a', true)), Reply::Ack);
+	assert_eq!(transfer.receive(&block(1, b'a', true)), Reply::Ack);
+	let mut corrupt = block(2, SUB, true);
+	corrupt[5] ^= 1;
+	assert_eq!(transfer.receive(&corrupt), Reply::Nak);
+	assert_eq!(transfer.receive(&block(2, SUB, true)), Reply::Ack);
+	assert_eq!(transfer.receive(&block(4, b'c', true)), Reply::Cancel);
+	assert_eq!(transfer.into_file(), [b'a'; 128]);
+
+	let mut transfer = Transfer::new(false);
+	assert_eq!(transfer.block_len(STX), 1027);
+	assert_eq!(transfer.receive(&block(1, b'z', false)), Reply::Ack);
+	assert_eq!(transfer.data.len(), 128);
+}
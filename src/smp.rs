@@ -0,0 +1,478 @@
+use crate::task::Priority;
+use crate::{apic, gdt, interrupts, klog, memory, percpu, task, time};
+use alloc::{boxed::Box, collections::VecDeque, string::String};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::time::Duration;
+use crate::sync::Mutex;
+use x86_64::registers::control::{Cr0, Cr3, Cr4};
+use x86_64::registers::model_specific::Efer;
+use x86_64::VirtAddr;
+
+/// Processors brought up, the boot processor included
+pub const MAX_CPUS: usize = 16;
+/// Vector of the inter-processor interrupt that wakes a halted processor to
+/// look at its run queue
+pub const RESCHEDULE_VECTOR: u8 = 0xf0;
+/// Vector asking the other processors to run a function
+pub const CALL_FUNCTION_VECTOR: u8 = 0xf1;
+/// Vector asking the other processors to drop stale TLB entries
+pub const TLB_FLUSH_VECTOR: u8 = 0xf2;
+/// Pages a shootdown flushes one by one; beyond this it flushes the whole TLB
+const FLUSH_ALL_THRESHOLD: u64 = 32;
+/// Stack each application processor runs its executor on
+const AP_STACK_SIZE: u64 = 64 * 1024;
+/// How long a started processor has to report in
+const STARTUP_TIMEOUT: Duration = Duration::from_millis(100);
+/// APIC ID no processor has; it addresses all of them
+const NO_APIC_ID: u8 = 0xff;
+/// EFER bit the processor sets itself once long mode is active
+const EFER_LONG_MODE_ACTIVE: u64 = 1 << 10;
+
+/// A task that can be handed to another processor's executor
+type SendFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A task waiting in a processor's inbox, with what its executor needs to
+/// know to spawn it
+pub(crate) struct Spawned {
+	pub(crate) priority: Priority,
+	pub(crate) name: Option<String>,
+	pub(crate) future: SendFuture,
+}
+
+/// Requests another processor has made of one, as bits
+const REQUEST_CALL: u8 = 1 << 0;
+const REQUEST_FLUSH: u8 = 1 << 1;
+
+/// A processor, the tasks spawned on it that its executor has yet to take,
+/// and what other processors have asked of it
+struct Cpu {
+	apic_id: AtomicU8,
+	online: AtomicBool,
+	inbox: Mutex<VecDeque<Spawned>>,
+	requests: AtomicU8,
+}
+
+impl Cpu {
+	const fn new() -> Cpu {
+		Cpu {
+			apic_id: AtomicU8::new(NO_APIC_ID),
+			online: AtomicBool::new(false),
+			inbox: Mutex::new(VecDeque::new()),
+			requests: AtomicU8::new(0),
+		}
+	}
+}
+
+/// Every processor by index; the boot processor is 0
+static CPUS: [Cpu; MAX_CPUS] = [const { Cpu::new() }; MAX_CPUS];
+/// Processors running an executor
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+/// Where `spawn` puts the next task
+static NEXT_CPU: AtomicUsize = AtomicUsize::new(0);
+
+/// Held by the processor whose request the others are handling; only one
+/// request is out at a time
+static BROADCAST: Mutex<()> = Mutex::new(());
+/// Processors yet to handle the request that is out
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+/// A function for other processors to run, and its argument
+type Call = (fn(usize), usize);
+
+/// The call request that is out
+static CALL: Mutex<Option<Call>> = Mutex::new(None);
+/// The first page and page count of the shootdown that is out; no pages
+/// means the whole TLB
+static FLUSH: Mutex<(VirtAddr, u64)> = Mutex::new((VirtAddr::zero(), 0));
+
+// Real-mode startup code, copied below 1 MiB for the SIPI to run. It enters
+// long mode with the boot processor's control registers and page tables,
+// then calls `ap_entry` on the stack left in the parameters. It runs at
+// whatever address it is copied to: the GDT pointer and far jump offsets
+// are relative to the start until `start_ap` relocates them, and data is
+// reached through EBX, which holds the start.
+core::arch::global_asm!(
+	r#"
+	.pushsection .text.smp_trampoline, "ax"
+	.global smp_trampoline_start
+	.global smp_trampoline_gdt_ptr
+	.global smp_trampoline_protected_jump
+	.global smp_trampoline_long_jump
+	.global smp_trampoline_params
+	.global smp_trampoline_end
+
+	.code16
+smp_trampoline_start:
+	cli
+	cld
+	mov %cs, %ax
+	mov %ax, %ds
+	xor %ebx, %ebx
+	mov %ax, %bx
+	shl $4, %ebx
+	lgdtl smp_trampoline_gdt_ptr - smp_trampoline_start
+	mov %cr0, %eax
+	or $1, %eax
+	mov %eax, %cr0
+	ljmpl *(smp_trampoline_protected_jump - smp_trampoline_start)
+
+	.code32
+smp_trampoline_protected:
+	mov $0x10, %ax
+	mov %ax, %ds
+	mov %ax, %es
+	mov %ax, %ss
+	mov (smp_trampoline_params + 16 - smp_trampoline_start)(%ebx), %eax
+	mov %eax, %cr4
+	mov (smp_trampoline_params + 8 - smp_trampoline_start)(%ebx), %eax
+	mov %eax, %cr3
+	mov $0xc0000080, %ecx
+	mov (smp_trampoline_params + 24 - smp_trampoline_start)(%ebx), %eax
+	mov (smp_trampoline_params + 28 - smp_trampoline_start)(%ebx), %edx
+	wrmsr
+	mov (smp_trampoline_params - smp_trampoline_start)(%ebx), %eax
+	mov %eax, %cr0
+	ljmp *(smp_trampoline_long_jump - smp_trampoline_start)(%ebx)
+
+	.code64
+smp_trampoline_long:
+	xor %ax, %ax
+	mov %ax, %ds
+	mov %ax, %es
+	mov %ax, %ss
+	mov %ax, %fs
+	mov %ax, %gs
+	mov %ebx, %ebx
+	mov (smp_trampoline_params + 32 - smp_trampoline_start)(%rbx), %rsp
+	mov (smp_trampoline_params + 48 - smp_trampoline_start)(%rbx), %rdi
+	mov (smp_trampoline_params + 40 - smp_trampoline_start)(%rbx), %rax
+	call *%rax
+1:
+	hlt
+	jmp 1b
+
+	.balign 8
+smp_trampoline_gdt:
+	.quad 0
+	.quad 0x00cf9a000000ffff
+	.quad 0x00cf92000000ffff
+	.quad 0x00af9a000000ffff
+smp_trampoline_gdt_ptr:
+	.word 4 * 8 - 1
+	.long smp_trampoline_gdt - smp_trampoline_start
+smp_trampoline_protected_jump:
+	.long smp_trampoline_protected - smp_trampoline_start
+	.word 0x08
+smp_trampoline_long_jump:
+	.long smp_trampoline_long - smp_trampoline_start
+	.word 0x18
+	.balign 8
+smp_trampoline_params:
+	.fill 7, 8, 0
+smp_trampoline_end:
+	.popsection
+"#,
+	options(att_syntax)
+);
+
+extern "C" {
+	static smp_trampoline_start: u8;
+	static smp_trampoline_gdt_ptr: u8;
+	static smp_trampoline_protected_jump: u8;
+	static smp_trampoline_long_jump: u8;
+	static smp_trampoline_params: u8;
+	static smp_trampoline_end: u8;
+}
+
+/// What the trampoline loads, in the order it expects
+#[repr(C)]
+struct TrampolineParams {
+	cr0: u64,
+	cr3: u64,
+	cr4: u64,
+	efer: u64,
+	stack: u64,
+	entry: u64,
+	cpu: u64,
+}
+
+/// Offset of a trampoline label from its start
+fn trampoline_offset(label: *const u8) -> usize {
+	label as usize - (&raw const smp_trampoline_start) as usize
+}
+
+/// Processors running an executor, the boot processor included
+pub fn cpu_count() -> usize {
+	ONLINE.load(Ordering::Relaxed)
+}
+
+/// Indexes of the processors running an executor, in order
+pub(crate) fn online_cpus() -> alloc::vec::Vec<usize> {
+	CPUS.iter().enumerate().filter(|(_, cpu)| cpu.online.load(Ordering::Acquire)).map(|(index, _)| index).collect()
+}
+
+/// Index of the processor this runs on
+pub fn current_cpu() -> usize {
+	percpu::cpu_index()
+}
+
+/// Run a task on the next processor in turn, returning which one has it
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) -> usize {
+	spawn_spawned(Spawned { priority: Priority::Normal, name: None, future: Box::pin(future) })
+}
+
+/// Run a task with a name for `tasks` to show on the next processor in turn
+pub fn spawn_named(name: &str, future: impl Future<Output = ()> + Send + 'static) -> usize {
+	spawn_spawned(Spawned { priority: Priority::Normal, name: Some(String::from(name)), future: Box::pin(future) })
+}
+
+/// Hand a task to the next processor in turn
+fn spawn_spawned(spawned: Spawned) -> usize {
+	let online = online_cpus();
+	// Everything goes to the boot processor until `init` brings it online
+	let cpu = match online.len() {
+		0 => 0,
+		count => online[NEXT_CPU.fetch_add(1, Ordering::Relaxed) % count],
+	};
+	push_spawned(cpu, spawned);
+	cpu
+}
+
+/// Run a task on a given processor; it waits there until that processor's
+/// executor starts
+pub fn spawn_on(cpu: usize, future: impl Future<Output = ()> + Send + 'static) {
+	spawn_on_with_priority(cpu, Priority::Normal, future);
+}
+
+/// Run a task on a given processor in the run queue for `priority`
+pub fn spawn_on_with_priority(cpu: usize, priority: Priority, future: impl Future<Output = ()> + Send + 'static) {
+	push_spawned(cpu, Spawned { priority, name: None, future: Box::pin(future) });
+}
+
+/// Put a task in a processor's inbox and make sure it looks
+pub(crate) fn push_spawned(cpu: usize, spawned: Spawned) {
+	let cpu = cpu.min(MAX_CPUS - 1);
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		CPUS[cpu].inbox.lock().push_back(spawned);
+	});
+	wake(cpu);
+}
+
+/// Tasks spawned on a processor since it last looked, for its executor
+pub(crate) fn take_spawned(cpu: usize) -> VecDeque<Spawned> {
+	x86_64::instructions::interrupts::without_interrupts(|| core::mem::take(&mut *CPUS[cpu].inbox.lock()))
+}
+
+/// Whether tasks are waiting to be taken by a processor's executor
+pub(crate) fn has_spawned(cpu: usize) -> bool {
+	x86_64::instructions::interrupts::without_interrupts(|| !CPUS[cpu].inbox.lock().is_empty())
+}
+
+/// Interrupt a processor that may be halted so it looks at its queues
+pub(crate) fn wake(cpu: usize) {
+	let target = &CPUS[cpu];
+	if !target.online.load(Ordering::Acquire) || cpu == current_cpu() {
+		return;
+	}
+	apic::send_ipi(target.apic_id.load(Ordering::Relaxed), RESCHEDULE_VECTOR as u32);
+}
+
+/// Make a request of every other online processor and wait until each has
+/// handled it
+fn broadcast(request: u8, vector: u8, prepare: impl FnOnce()) {
+	let current = current_cpu();
+	let others = || CPUS.iter().enumerate().filter(move |&(index, cpu)| index != current && cpu.online.load(Ordering::Acquire));
+	if others().next().is_none() {
+		return;
+	}
+	// Handle other processors' requests while waiting our turn, since they
+	// wait on this one and interrupts may be off
+	let _turn = loop {
+		match BROADCAST.try_lock() {
+			Some(turn) => break turn,
+			None => handle_requests(),
+		}
+	};
+	prepare();
+	PENDING.store(others().count(), Ordering::Release);
+	for (_, cpu) in others() {
+		cpu.requests.fetch_or(request, Ordering::AcqRel);
+		apic::send_ipi(cpu.apic_id.load(Ordering::Relaxed), vector as u32);
+	}
+	while PENDING.load(Ordering::Acquire) != 0 {
+		core::hint::spin_loop();
+	}
+}
+
+/// Run `function(arg)` on every other online processor, returning once all
+/// of them have; it runs in interrupt context there
+pub fn call_function(function: fn(usize), arg: usize) {
+	broadcast(REQUEST_CALL, CALL_FUNCTION_VECTOR, || *CALL.lock() = Some((function, arg)));
+}
+
+/// Make the other processors drop any TLB entries for `pages` pages from
+/// `start`, after this one changed or removed their mappings; every
+/// processor shares the kernel's page tables
+pub fn flush_tlb(start: VirtAddr, pages: u64) {
+	broadcast(REQUEST_FLUSH, TLB_FLUSH_VECTOR, || *FLUSH.lock() = (start, pages));
+}
+
+/// Drop this processor's TLB entries for `pages` pages from `start`
+fn flush_local(start: VirtAddr, pages: u64) {
+	use x86_64::instructions::tlb;
+
+	if pages == 0 || pages > FLUSH_ALL_THRESHOLD {
+		tlb::flush_all();
+		return;
+	}
+	for page in 0..pages {
+		tlb::flush(start + page * 4096);
+	}
+}
+
+/// Handle what other processors have asked of this one; called from the
+/// call-function and TLB flush interrupt handlers
+pub(crate) fn handle_requests() {
+	let requests = CPUS[current_cpu()].requests.swap(0, Ordering::AcqRel);
+	if requests & REQUEST_CALL != 0 {
+		if let Some((function, arg)) = *CALL.lock() {
+			function(arg);
+		}
+		PENDING.fetch_sub(1, Ordering::AcqRel);
+	}
+	if requests & REQUEST_FLUSH != 0 {
+		let (start, pages) = *FLUSH.lock();
+		flush_local(start, pages);
+		PENDING.fetch_sub(1, Ordering::AcqRel);
+	}
+}
+
+/// Wait at least `duration`, spinning
+fn spin_for(duration: Duration) {
+	let start = time::Instant::now();
+	while start.elapsed() < duration {
+		core::hint::spin_loop();
+	}
+}
+
+/// Start every other processor the MADT lists and run an executor on each;
+/// needs the APIC, the heap, and interrupts enabled for the startup delays
+pub fn init() {
+	let Some(bsp) = apic::local_id() else {
+		return;
+	};
+	CPUS[0].apic_id.store(bsp, Ordering::Relaxed);
+	CPUS[0].online.store(true, Ordering::Release);
+	let others: alloc::vec::Vec<u8> = apic::processors().into_iter().filter(|&id| id != bsp).collect();
+	if others.is_empty() {
+		return;
+	}
+	let (cr3, _) = Cr3::read();
+	if cr3.start_address().as_u64() > u32::MAX as u64 {
+		klog!(Warn, "SMP: page tables above 4 GiB, staying on one processor");
+		return;
+	}
+	let Some((frame, mapped)) = memory::identity_map_low_frame() else {
+		klog!(Warn, "SMP: no free page below 1 MiB for the startup code");
+		return;
+	};
+
+	let base = frame.start_address().as_u64();
+	let trampoline = base as *mut u8;
+	let length = trampoline_offset(&raw const smp_trampoline_end);
+	unsafe {
+		core::ptr::copy_nonoverlapping(&raw const smp_trampoline_start, trampoline, length);
+		// Make the GDT pointer and far jumps absolute; each holds a 32-bit
+		// offset from the start
+		let gdt_base = trampoline.add(trampoline_offset(&raw const smp_trampoline_gdt_ptr) + 2);
+		for field in [
+			gdt_base,
+			trampoline.add(trampoline_offset(&raw const smp_trampoline_protected_jump)),
+			trampoline.add(trampoline_offset(&raw const smp_trampoline_long_jump)),
+		] {
+			let field = field as *mut u32;
+			field.write_unaligned(field.read_unaligned() + base as u32);
+		}
+	}
+	let params = unsafe { trampoline.add(trampoline_offset(&raw const smp_trampoline_params)) } as *mut TrampolineParams;
+
+	let mut all_started = true;
+	for (index, apic_id) in (1..MAX_CPUS).zip(others.iter().copied()) {
+		if !start_ap(index, apic_id, (base >> 12) as u8, params) {
+			klog!(Warn, "SMP: processor {} did not start", apic_id);
+			all_started = false;
+		}
+	}
+	// A processor that did not start may yet run the startup code
+	if mapped && all_started {
+		memory::unmap_memory(VirtAddr::new(base), 4096);
+	}
+	if others.len() >= MAX_CPUS {
+		klog!(Warn, "SMP: using {} of {} processors", MAX_CPUS, others.len() + 1);
+	}
+	klog!(Info, "SMP: {} processor(s) online", cpu_count());
+}
+
+/// Bring up one processor with INIT-SIPI-SIPI and wait for it to run
+fn start_ap(index: usize, apic_id: u8, vector: u8, params: *mut TrampolineParams) -> bool {
+	let Some(stack) = memory::map_memory(AP_STACK_SIZE) else {
+		return false;
+	};
+	task::canary::arm(index, stack);
+	let cpu = &CPUS[index];
+	cpu.apic_id.store(apic_id, Ordering::Relaxed);
+	unsafe {
+		params.write_volatile(TrampolineParams {
+			cr0: Cr0::read_raw(),
+			cr3: Cr3::read().0.start_address().as_u64(),
+			cr4: Cr4::read_raw(),
+			efer: Efer::read_raw() & !EFER_LONG_MODE_ACTIVE,
+			stack: (stack + AP_STACK_SIZE).as_u64(),
+			entry: ap_entry as usize as u64,
+			cpu: index as u64,
+		});
+	}
+
+	apic::send_ipi(apic_id, apic::ICR_INIT | apic::ICR_ASSERT);
+	spin_for(Duration::from_millis(10));
+	for _ in 0..2 {
+		if cpu.online.load(Ordering::Acquire) {
+			break;
+		}
+		apic::send_ipi(apic_id, apic::ICR_STARTUP | vector as u32);
+		spin_for(Duration::from_micros(200));
+	}
+	let start = time::Instant::now();
+	while !cpu.online.load(Ordering::Acquire) {
+		if start.elapsed() > STARTUP_TIMEOUT {
+			cpu.apic_id.store(NO_APIC_ID, Ordering::Relaxed);
+			return false;
+		}
+		core::hint::spin_loop();
+	}
+	true
+}
+
+/// Where an application processor lands in long mode, on its own stack
+extern "C" fn ap_entry(cpu: usize) -> ! {
+	percpu::init(cpu);
+	gdt::init_ap();
+	interrupts::init_idt();
+	apic::init_ap();
+	let mut executor = task::Executor::new();
+	ONLINE.fetch_add(1, Ordering::Relaxed);
+	CPUS[cpu].online.store(true, Ordering::Release);
+	klog!(Debug, "SMP: processor {} running", CPUS[cpu].apic_id.load(Ordering::Relaxed));
+	x86_64::instructions::interrupts::enable();
+	executor.run();
+}
+
+/// Test that the trampoline's parameters sit where its code reads them
+#[test_case]
+fn test_trampoline_layout() {
+	let params = trampoline_offset(&raw const smp_trampoline_params);
+	assert_eq!(params % 8, 0);
+	assert_eq!(trampoline_offset(&raw const smp_trampoline_end), params + core::mem::size_of::<TrampolineParams>());
+	assert!(trampoline_offset(&raw const smp_trampoline_end) <= 4096);
+}
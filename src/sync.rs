@@ -0,0 +1,197 @@
+#[cfg(debug_assertions)]
+use crate::lockdep;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use x86_64::instructions::interrupts;
+
+/// A spinlock; in debug builds it also records the processor, task, and call
+/// site holding it, and panics where it would otherwise deadlock: when the
+/// processor holding it takes it again, or when two locks are taken in both
+/// orders
+pub struct Mutex<T> {
+	#[cfg(debug_assertions)]
+	holder: lockdep::Holder,
+	inner: spin::Mutex<T>,
+}
+
+/// Access to the data of a `Mutex`, which is free again once this is dropped
+pub struct MutexGuard<'a, T> {
+	guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+	#[cfg(debug_assertions)]
+	mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+	/// A free lock holding `value`
+	pub const fn new(value: T) -> Self {
+		Mutex {
+			#[cfg(debug_assertions)]
+			holder: lockdep::Holder::new(),
+			inner: spin::Mutex::new(value),
+		}
+	}
+
+	/// What the lock is known by in lock-order checks
+	#[cfg(debug_assertions)]
+	fn address(&self) -> usize {
+		self as *const Self as usize
+	}
+
+	/// Wait for the lock
+	#[track_caller]
+	pub fn lock(&self) -> MutexGuard<'_, T> {
+		#[cfg(debug_assertions)]
+		{
+			lockdep::before_lock(self.address(), &self.holder);
+			loop {
+				if let Some(guard) = self.try_lock() {
+					return guard;
+				}
+				lockdep::contended(self.address(), &self.holder);
+				core::hint::spin_loop();
+			}
+		}
+		#[cfg(not(debug_assertions))]
+		MutexGuard { guard: ManuallyDrop::new(self.inner.lock()) }
+	}
+
+	/// Take the lock if it is free
+	#[track_caller]
+	pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+		let guard = self.inner.try_lock()?;
+		#[cfg(debug_assertions)]
+		lockdep::acquired(self.address(), &self.holder);
+		Some(MutexGuard {
+			guard: ManuallyDrop::new(guard),
+			#[cfg(debug_assertions)]
+			mutex: self,
+		})
+	}
+
+	/// Release the lock whoever holds it, for the panic path
+	///
+	/// # Safety
+	///
+	/// The holder must never touch the data again.
+	pub unsafe fn force_unlock(&self) {
+		#[cfg(debug_assertions)]
+		lockdep::released(self.address(), &self.holder);
+		unsafe { self.inner.force_unlock() };
+	}
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.guard
+	}
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		&mut self.guard
+	}
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+	fn drop(&mut self) {
+		#[cfg(debug_assertions)]
+		lockdep::released(self.mutex.address(), &self.mutex.holder);
+		unsafe { ManuallyDrop::drop(&mut self.guard) };
+	}
+}
+
+/// A spinlock that disables interrupts while it is held, so an interrupt
+/// handler that takes it can never spin on a holder it interrupted
+pub struct SpinLockIrqSave<T> {
+	inner: Mutex<T>,
+}
+
+/// Access to the data of a `SpinLockIrqSave`; interrupts are back the way
+/// they were once it is dropped
+pub struct SpinLockIrqSaveGuard<'a, T> {
+	guard: ManuallyDrop<MutexGuard<'a, T>>,
+	interrupts_were_enabled: bool,
+}
+
+impl<T> SpinLockIrqSave<T> {
+	/// A free lock holding `value`
+	pub const fn new(value: T) -> Self {
+		SpinLockIrqSave { inner: Mutex::new(value) }
+	}
+
+	/// Disable interrupts and wait for the lock
+	#[track_caller]
+	pub fn lock(&self) -> SpinLockIrqSaveGuard<'_, T> {
+		let interrupts_were_enabled = interrupts::are_enabled();
+		interrupts::disable();
+		SpinLockIrqSaveGuard { guard: ManuallyDrop::new(self.inner.lock()), interrupts_were_enabled }
+	}
+
+	/// Take the lock if it is free, leaving interrupts alone if it is not
+	#[track_caller]
+	pub fn try_lock(&self) -> Option<SpinLockIrqSaveGuard<'_, T>> {
+		let interrupts_were_enabled = interrupts::are_enabled();
+		interrupts::disable();
+		match self.inner.try_lock() {
+			Some(guard) => Some(SpinLockIrqSaveGuard { guard: ManuallyDrop::new(guard), interrupts_were_enabled }),
+			None => {
+				if interrupts_were_enabled {
+					interrupts::enable();
+				}
+				None
+			}
+		}
+	}
+
+	/// Release the lock whoever holds it, for the panic path
+	///
+	/// # Safety
+	///
+	/// The holder must never touch the data again.
+	pub unsafe fn force_unlock(&self) {
+		unsafe { self.inner.force_unlock() };
+	}
+}
+
+impl<T> Deref for SpinLockIrqSaveGuard<'_, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.guard
+	}
+}
+
+impl<T> DerefMut for SpinLockIrqSaveGuard<'_, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		&mut self.guard
+	}
+}
+
+impl<T> Drop for SpinLockIrqSaveGuard<'_, T> {
+	fn drop(&mut self) {
+		// Release the lock before an interrupt can arrive and want it
+		unsafe { ManuallyDrop::drop(&mut self.guard) };
+		if self.interrupts_were_enabled {
+			interrupts::enable();
+		}
+	}
+}
+
+/// Test that the lock restores the interrupt flag it found
+#[test_case]
+fn test_spinlock_irqsave() {
+	let lock = SpinLockIrqSave::new(0);
+	let enabled = interrupts::are_enabled();
+	{
+		let mut value = lock.lock();
+		*value += 1;
+		assert!(!interrupts::are_enabled());
+		assert!(lock.try_lock().is_none());
+		assert!(!interrupts::are_enabled());
+	}
+	assert_eq!(interrupts::are_enabled(), enabled);
+	assert_eq!(*lock.try_lock().unwrap(), 1);
+	assert_eq!(interrupts::are_enabled(), enabled);
+}
@@ -1,4 +1,18 @@
 use crate::{println, print, hlt_loop};
+use crate::audit;
+use crate::fs::{self, FileType, FsError};
+use crate::memory;
+use crate::net::{self, tcp::{self, TcpError}, udp::{self, UdpError}};
+use crate::pipe;
+use crate::power::{self, PowerAction};
+use crate::random;
+use crate::time;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use x86_64::VirtAddr;
+use crate::process::{self, FdEntry, ProcessId, Signal};
 
 /// POSIX system call numbers
 #[derive(Debug, Clone, Copy)]
@@ -105,6 +119,22 @@ pub enum SyscallNumber {
 	Getrusage = 98,
 	Sysinfo = 99,
 	Times = 100,
+	Getuid = 102,
+	Getgid = 104,
+	Setuid = 105,
+	Setgid = 106,
+	Geteuid = 107,
+	Getegid = 108,
+	Getgroups = 115,
+	Prctl = 157,
+	Chroot = 161,
+	Settimeofday = 164,
+	Mount = 165,
+	Umount2 = 166,
+	Reboot = 169,
+	Time = 201,
+	Unshare = 272,
+	Getrandom = 318,
 }
 
 /// System call error codes
@@ -146,29 +176,477 @@ pub enum SyscallError {
 	BrokenPipe = -32,
 	MathArgumentOutOfDomain = -33,
 	MathResultNotRepresentable = -34,
+	DirectoryNotEmpty = -39,
+	NotASocket = -88,
+	DestinationAddressRequired = -89,
+	MessageTooLong = -90,
+	ProtocolNotSupported = -93,
+	AddressFamilyNotSupported = -97,
+	AddressInUse = -98,
+	AddressNotAvailable = -99,
+	NetworkUnreachable = -101,
+	ProtocolNotAvailable = -92,
+	OperationNotSupported = -95,
+	ConnectionReset = -104,
+	AlreadyConnected = -106,
+	NotConnected = -107,
+	TimedOut = -110,
+	ConnectionRefused = -111,
+	AlreadyInProgress = -114,
+	InProgress = -115,
+}
+
+impl SyscallError {
+	/// Human-readable description, in the spirit of `strerror`
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			SyscallError::Success => "Success",
+			SyscallError::PermissionDenied => "Operation not permitted",
+			SyscallError::PermissionDenied2 => "Permission denied",
+			SyscallError::NoSuchFileOrDirectory => "No such file or directory",
+			SyscallError::NoSuchProcess => "No such process",
+			SyscallError::InterruptedSystemCall => "Interrupted system call",
+			SyscallError::IoError => "Input/output error",
+			SyscallError::NoSuchDeviceOrAddress => "No such device or address",
+			SyscallError::ArgumentListTooLong => "Argument list too long",
+			SyscallError::ExecFormatError => "Exec format error",
+			SyscallError::BadFileNumber => "Bad file descriptor",
+			SyscallError::NoChildProcesses => "No child processes",
+			SyscallError::TryAgain => "Resource temporarily unavailable",
+			SyscallError::OutOfMemory => "Cannot allocate memory",
+			SyscallError::BadAddress => "Bad address",
+			SyscallError::BlockDeviceRequired => "Block device required",
+			SyscallError::DeviceOrResourceBusy => "Device or resource busy",
+			SyscallError::FileExists => "File exists",
+			SyscallError::CrossDeviceLink => "Invalid cross-device link",
+			SyscallError::NoSuchDevice => "No such device",
+			SyscallError::NotADirectory => "Not a directory",
+			SyscallError::IsADirectory => "Is a directory",
+			SyscallError::InvalidArgument => "Invalid argument",
+			SyscallError::FileTableOverflow => "Too many open files in system",
+			SyscallError::TooManyOpenFiles => "Too many open files",
+			SyscallError::NotATypewriter => "Inappropriate ioctl for device",
+			SyscallError::TextFileBusy => "Text file busy",
+			SyscallError::FileTooLarge => "File too large",
+			SyscallError::NoSpaceLeftOnDevice => "No space left on device",
+			SyscallError::IllegalSeek => "Illegal seek",
+			SyscallError::ReadOnlyFileSystem => "Read-only file system",
+			SyscallError::TooManyLinks => "Too many links",
+			SyscallError::BrokenPipe => "Broken pipe",
+			SyscallError::MathArgumentOutOfDomain => "Numerical argument out of domain",
+			SyscallError::MathResultNotRepresentable => "Numerical result out of range",
+			SyscallError::DirectoryNotEmpty => "Directory not empty",
+			SyscallError::NotASocket => "Socket operation on non-socket",
+			SyscallError::DestinationAddressRequired => "Destination address required",
+			SyscallError::MessageTooLong => "Message too long",
+			SyscallError::ProtocolNotSupported => "Protocol not supported",
+			SyscallError::AddressFamilyNotSupported => "Address family not supported by protocol",
+			SyscallError::AddressInUse => "Address already in use",
+			SyscallError::AddressNotAvailable => "Cannot assign requested address",
+			SyscallError::NetworkUnreachable => "Network is unreachable",
+			SyscallError::ProtocolNotAvailable => "Protocol not available",
+			SyscallError::OperationNotSupported => "Operation not supported",
+			SyscallError::ConnectionReset => "Connection reset by peer",
+			SyscallError::AlreadyConnected => "Transport endpoint is already connected",
+			SyscallError::NotConnected => "Transport endpoint is not connected",
+			SyscallError::TimedOut => "Connection timed out",
+			SyscallError::ConnectionRefused => "Connection refused",
+			SyscallError::AlreadyInProgress => "Operation already in progress",
+			SyscallError::InProgress => "Operation now in progress",
+		}
+	}
 }
 
 /// System call result type
 pub type SyscallResult = Result<usize, SyscallError>;
 
+impl From<FsError> for SyscallError {
+	fn from(err: FsError) -> Self {
+		match err {
+			FsError::NotFound => SyscallError::NoSuchFileOrDirectory,
+			FsError::PermissionDenied => SyscallError::PermissionDenied2,
+			FsError::NotPermitted => SyscallError::PermissionDenied,
+			FsError::AlreadyExists => SyscallError::FileExists,
+			FsError::IsDirectory => SyscallError::IsADirectory,
+			FsError::NotDirectory => SyscallError::NotADirectory,
+			FsError::InvalidPath => SyscallError::InvalidArgument,
+			FsError::IoError => SyscallError::IoError,
+			FsError::DirectoryNotEmpty => SyscallError::DirectoryNotEmpty,
+		}
+	}
+}
+
+impl From<UdpError> for SyscallError {
+	fn from(err: UdpError) -> Self {
+		match err {
+			UdpError::NoSocket => SyscallError::BadFileNumber,
+			UdpError::AddressInUse => SyscallError::AddressInUse,
+			UdpError::NoPortsFree => SyscallError::AddressNotAvailable,
+			UdpError::TooLong => SyscallError::MessageTooLong,
+			UdpError::NoRoute => SyscallError::NetworkUnreachable,
+		}
+	}
+}
+
+impl From<TcpError> for SyscallError {
+	fn from(err: TcpError) -> Self {
+		match err {
+			TcpError::NoSocket => SyscallError::BadFileNumber,
+			TcpError::AddressInUse => SyscallError::AddressInUse,
+			TcpError::NoPortsFree => SyscallError::AddressNotAvailable,
+			TcpError::InvalidState => SyscallError::InvalidArgument,
+			TcpError::AlreadyConnected => SyscallError::AlreadyConnected,
+			TcpError::Connecting => SyscallError::AlreadyInProgress,
+			TcpError::NotConnected => SyscallError::NotConnected,
+			TcpError::Shutdown => SyscallError::BrokenPipe,
+			TcpError::ConnectionRefused => SyscallError::ConnectionRefused,
+			TcpError::ConnectionReset => SyscallError::ConnectionReset,
+			TcpError::TimedOut => SyscallError::TimedOut,
+			TcpError::NoRoute => SyscallError::NetworkUnreachable,
+		}
+	}
+}
+
+/// Longest path accepted from a caller-supplied C string
+const MAX_PATH_LEN: usize = 4096;
+
+/// `access` modes: existence, then read, write, and execute permission
+pub const F_OK: usize = 0;
+pub const R_OK: usize = 4;
+pub const W_OK: usize = 2;
+pub const X_OK: usize = 1;
+
+/// Magic numbers `reboot` requires so it cannot be called by accident
+const REBOOT_MAGIC1: usize = 0xfee1_dead;
+const REBOOT_MAGIC2: usize = 0x2812_1969;
+/// `reboot` commands
+pub const REBOOT_CMD_RESTART: usize = 0x0123_4567;
+pub const REBOOT_CMD_HALT: usize = 0xcdef_0123;
+pub const REBOOT_CMD_POWER_OFF: usize = 0x4321_fedc;
+
+/// `prctl` options for reading and setting the system call filter, and the
+/// modes it takes: strict allows only `read`, `write`, and `exit`, and filter
+/// the calls set in a bitmap the caller passes
+pub const PR_GET_SECCOMP: usize = 21;
+pub const PR_SET_SECCOMP: usize = 22;
+pub const SECCOMP_MODE_STRICT: usize = 1;
+pub const SECCOMP_MODE_FILTER: usize = 2;
+
+/// `mount` flag asking for a bind mount, the only kind there is
+pub const MS_BIND: usize = 0x1000;
+
+/// Socket domains, types, and flags understood by `socket`
+pub const AF_INET: usize = 2;
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+const SOCK_TYPE_MASK: usize = 0xf;
+const SOCK_NONBLOCK: usize = 0o4000;
+const SOCK_CLOEXEC: usize = 0o2000000;
+const IPPROTO_TCP: usize = 6;
+const IPPROTO_UDP: usize = 17;
+/// `recvfrom` flag: fail rather than wait for a datagram
+const MSG_DONTWAIT: usize = 0x40;
+/// Size of `struct sockaddr_in`
+const SOCKADDR_IN_SIZE: usize = 16;
+/// `shutdown` directions
+const SHUT_RD: usize = 0;
+const SHUT_WR: usize = 1;
+const SHUT_RDWR: usize = 2;
+/// Socket option levels and names; the ones that can be set change nothing,
+/// as segments already go out without delay
+const SOL_SOCKET: usize = 1;
+const SO_REUSEADDR: usize = 2;
+const SO_TYPE: usize = 3;
+const SO_ERROR: usize = 4;
+const SO_SNDBUF: usize = 7;
+const SO_RCVBUF: usize = 8;
+const SO_KEEPALIVE: usize = 9;
+const TCP_NODELAY: usize = 1;
+/// Offsets in `struct msghdr` of the address, its length, the `iovec`
+/// array, its length, and the control data's length and the flags, which
+/// `recvmsg` clears; and the sizes of a `struct msghdr` and an `iovec`
+const MSGHDR_NAME: usize = 0;
+const MSGHDR_NAMELEN: usize = 8;
+const MSGHDR_IOV: usize = 16;
+const MSGHDR_IOVLEN: usize = 24;
+const MSGHDR_CONTROLLEN: usize = 40;
+const MSGHDR_FLAGS: usize = 48;
+const MSGHDR_SIZE: usize = 56;
+const IOVEC_SIZE: usize = 16;
+
+/// Size of the pages a string passed in is checked a page at a time in
+const PAGE_SIZE: usize = 4096;
+
+/// Check that the caller's `len` bytes at `ptr` can be touched: the range
+/// does not wrap, both ends are canonical and on the same side of the hole
+/// between, and every page in it is mapped
+pub fn validate_user_range(ptr: usize, len: usize) -> Result<(), SyscallError> {
+	if len == 0 {
+		return Ok(());
+	}
+	let end = ptr.checked_add(len - 1).ok_or(SyscallError::BadAddress)?;
+	if VirtAddr::try_new(ptr as u64).is_err() || VirtAddr::try_new(end as u64).is_err() || ptr >> 47 != end >> 47 {
+		return Err(SyscallError::BadAddress);
+	}
+	if memory::is_range_mapped(ptr as u64, len) {
+		Ok(())
+	} else {
+		Err(SyscallError::BadAddress)
+	}
+}
+
+/// Borrow `len` bytes the caller passed at `ptr`
+unsafe fn user_bytes<'a>(ptr: usize, len: usize) -> Result<&'a [u8], SyscallError> {
+	validate_user_range(ptr, len)?;
+	Ok(if len == 0 { &[] } else { core::slice::from_raw_parts(ptr as *const u8, len) })
+}
+
+/// Borrow `len` bytes the caller passed at `ptr` to be written
+unsafe fn user_bytes_mut<'a>(ptr: usize, len: usize) -> Result<&'a mut [u8], SyscallError> {
+	validate_user_range(ptr, len)?;
+	Ok(if len == 0 { &mut [] } else { core::slice::from_raw_parts_mut(ptr as *mut u8, len) })
+}
+
+/// Read a `struct sockaddr_in` passed by the caller
+unsafe fn user_sockaddr(ptr: *const u8, len: usize) -> Result<SocketAddrV4, SyscallError> {
+	if ptr.is_null() {
+		return Err(SyscallError::BadAddress);
+	}
+	if len < SOCKADDR_IN_SIZE {
+		return Err(SyscallError::InvalidArgument);
+	}
+	let bytes = user_bytes(ptr as usize, SOCKADDR_IN_SIZE)?;
+	if usize::from(u16::from_ne_bytes([bytes[0], bytes[1]])) != AF_INET {
+		return Err(SyscallError::AddressFamilyNotSupported);
+	}
+	let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+	Ok(SocketAddrV4::new(Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]), port))
+}
+
+/// Write `address` as a `struct sockaddr_in` to a caller's buffer of
+/// `*len` bytes, truncating it to fit and setting `*len` to its full size
+unsafe fn write_user_sockaddr(ptr: *mut u8, len: *mut u32, address: SocketAddrV4) -> Result<(), SyscallError> {
+	if ptr.is_null() {
+		return Ok(());
+	}
+	if len.is_null() {
+		return Err(SyscallError::BadAddress);
+	}
+	validate_user_range(len as usize, 4)?;
+	let mut bytes = [0; SOCKADDR_IN_SIZE];
+	bytes[..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+	bytes[2..4].copy_from_slice(&address.port().to_be_bytes());
+	bytes[4..8].copy_from_slice(&address.ip().octets());
+	let room = (*len as usize).min(SOCKADDR_IN_SIZE);
+	user_bytes_mut(ptr as usize, room)?.copy_from_slice(&bytes[..room]);
+	*len = SOCKADDR_IN_SIZE as u32;
+	Ok(())
+}
+
+/// Borrow a NUL-terminated string passed by the caller
+unsafe fn user_cstr<'a>(ptr: *const u8) -> Result<&'a str, SyscallError> {
+	if ptr.is_null() {
+		return Err(SyscallError::BadAddress);
+	}
+	let mut len = 0;
+	loop {
+		// Each page the string reaches into is checked before it is read
+		let address = (ptr as usize).checked_add(len).ok_or(SyscallError::BadAddress)?;
+		if len == 0 || address % PAGE_SIZE == 0 {
+			validate_user_range(address, 1)?;
+		}
+		if *ptr.add(len) == 0 {
+			break;
+		}
+		len += 1;
+		if len >= MAX_PATH_LEN {
+			return Err(SyscallError::InvalidArgument);
+		}
+	}
+	core::str::from_utf8(core::slice::from_raw_parts(ptr, len))
+		.map_err(|_| SyscallError::InvalidArgument)
+}
+
 /// Handle system call dispatch
 pub fn syscall_handler(
 	syscall_num: usize,
 	arg1: usize,
 	arg2: usize,
 	arg3: usize,
-	_arg4: usize,
-	_arg5: usize,
-	_arg6: usize,
+	arg4: usize,
+	arg5: usize,
+	arg6: usize,
 ) -> SyscallResult {
+	crate::trace_event!(Syscall, "syscall {} ({:#x}, {:#x}, {:#x})", syscall_num, arg1, arg2, arg3);
+	if !process::with_current_process(|p| p.syscall_filter.allows(syscall_num)).unwrap_or(true) {
+		audit::record(audit::Kind::Denied, audit::Actor::current(), format_args!("syscall {}, which its filter forbids", syscall_num));
+		return Err(SyscallError::PermissionDenied);
+	}
 	match syscall_num {
-		0 => sys_read(arg1, arg2 as *mut u8, arg3),
-		1 => sys_write(arg1, arg2 as *const u8, arg3),
-		2 => sys_open(arg1 as *const u8, arg2, arg3),
+		0 => sys_read(arg1, unsafe { user_bytes_mut(arg2, arg3)? }),
+		1 => sys_write(arg1, unsafe { user_bytes(arg2, arg3)? }),
+		2 => sys_open(unsafe { user_cstr(arg1 as *const u8)? }, arg2 as u32, arg3),
 		3 => sys_close(arg1),
+		16 => sys_ioctl(arg1, arg2, arg3 as *mut u8),
+		21 => sys_access(unsafe { user_cstr(arg1 as *const u8)? }, arg2),
+		22 => {
+			let fds = arg1 as *mut i32;
+			if fds.is_null() {
+				return Err(SyscallError::BadAddress);
+			}
+			validate_user_range(arg1, 8)?;
+			let (read_fd, write_fd) = sys_pipe()?;
+			unsafe {
+				*fds = read_fd as i32;
+				*fds.add(1) = write_fd as i32;
+			}
+			Ok(0)
+		}
+		32 => sys_dup(arg1),
+		33 => sys_dup2(arg1, arg2),
 		39 => sys_getpid(),
+		41 => sys_socket(arg1, arg2, arg3),
+		42 => sys_connect(arg1, unsafe { user_sockaddr(arg2 as *const u8, arg3)? }),
+		43 => {
+			let (fd, peer) = sys_accept(arg1)?;
+			unsafe { write_user_sockaddr(arg2 as *mut u8, arg3 as *mut u32, peer)? };
+			Ok(fd)
+		}
+		44 => {
+			let buf = unsafe { user_bytes(arg2, arg3)? };
+			let destination = if arg5 == 0 { None } else { Some(unsafe { user_sockaddr(arg5 as *const u8, arg6)? }) };
+			sys_sendto(arg1, buf, destination)
+		}
+		45 => {
+			let buf = unsafe { user_bytes_mut(arg2, arg3)? };
+			let (count, source) = sys_recvfrom(arg1, buf, arg4)?;
+			unsafe { write_user_sockaddr(arg5 as *mut u8, arg6 as *mut u32, source)? };
+			Ok(count)
+		}
+		46 => sys_sendmsg(arg1, arg2 as *const u8),
+		47 => sys_recvmsg(arg1, arg2 as *mut u8, arg3),
+		48 => sys_shutdown(arg1, arg2),
+		49 => sys_bind(arg1, unsafe { user_sockaddr(arg2 as *const u8, arg3)? }),
+		50 => sys_listen(arg1, arg2),
+		51 => {
+			let address = sys_getsockname(arg1)?;
+			unsafe { write_user_sockaddr(arg2 as *mut u8, arg3 as *mut u32, address)? };
+			Ok(0)
+		}
+		52 => {
+			let address = sys_getpeername(arg1)?;
+			unsafe { write_user_sockaddr(arg2 as *mut u8, arg3 as *mut u32, address)? };
+			Ok(0)
+		}
+		// Only Unix domain sockets come in pairs, and there are none
+		53 => Err(SyscallError::OperationNotSupported),
+		54 => {
+			let value = arg4 as *const i32;
+			if value.is_null() || arg5 < 4 {
+				return Err(SyscallError::InvalidArgument);
+			}
+			validate_user_range(arg4, 4)?;
+			sys_setsockopt(arg1, arg2, arg3, unsafe { value.read_unaligned() })
+		}
+		55 => {
+			let (value, length) = (arg4 as *mut i32, arg5 as *mut u32);
+			if value.is_null() || length.is_null() {
+				return Err(SyscallError::BadAddress);
+			}
+			validate_user_range(arg4, 4)?;
+			validate_user_range(arg5, 4)?;
+			let option = sys_getsockopt(arg1, arg2, arg3)?;
+			unsafe {
+				if (*length as usize) < 4 {
+					return Err(SyscallError::InvalidArgument);
+				}
+				value.write_unaligned(option);
+				*length = 4;
+			}
+			Ok(0)
+		}
+		59 => sys_execve(unsafe { user_cstr(arg1 as *const u8)? }),
 		60 => sys_exit(arg1 as i32),
+		62 => sys_kill(arg1 as isize, arg2),
 		63 => sys_uname(arg1 as *mut u8),
+		79 => sys_getcwd(unsafe { user_bytes_mut(arg1, arg2)? }),
+		80 => sys_chdir(unsafe { user_cstr(arg1 as *const u8)? }),
+		82 => sys_rename(unsafe { user_cstr(arg1 as *const u8)? }, unsafe { user_cstr(arg2 as *const u8)? }),
+		83 => sys_mkdir(unsafe { user_cstr(arg1 as *const u8)? }, arg2),
+		84 => sys_rmdir(unsafe { user_cstr(arg1 as *const u8)? }),
+		87 => sys_unlink(unsafe { user_cstr(arg1 as *const u8)? }),
+		90 => sys_chmod(unsafe { user_cstr(arg1 as *const u8)? }, arg2),
+		92 => sys_chown(unsafe { user_cstr(arg1 as *const u8)? }, arg2 as u32, arg3 as u32),
+		96 => {
+			// struct timeval { tv_sec, tv_usec }
+			let tv = arg1 as *mut i64;
+			if tv.is_null() {
+				return Err(SyscallError::BadAddress);
+			}
+			validate_user_range(arg1, 16)?;
+			let seconds = sys_time()?;
+			unsafe {
+				*tv = seconds as i64;
+				*tv.add(1) = 0;
+			}
+			Ok(0)
+		}
+		102 | 107 => sys_getuid(),
+		104 | 108 => sys_getgid(),
+		105 => sys_setuid(arg1 as u32),
+		106 => sys_setgid(arg1 as u32),
+		115 => {
+			let groups = process::current_credentials().groups;
+			if arg1 == 0 {
+				return Ok(groups.len());
+			}
+			let list = arg2 as *mut u32;
+			if list.is_null() {
+				return Err(SyscallError::BadAddress);
+			}
+			if arg1 < groups.len() {
+				return Err(SyscallError::InvalidArgument);
+			}
+			validate_user_range(arg2, groups.len() * 4)?;
+			for (i, &gid) in groups.iter().enumerate() {
+				unsafe { list.add(i).write_unaligned(gid) };
+			}
+			Ok(groups.len())
+		}
+		109 => sys_setpgid(arg1, arg2),
+		121 => sys_getpgid(arg1),
+		157 => {
+			let bitmap = if arg3 == 0 { &[][..] } else { unsafe { user_bytes(arg3, arg4)? } };
+			sys_prctl(arg1, arg2, bitmap)
+		}
+		161 => sys_chroot(unsafe { user_cstr(arg1 as *const u8)? }),
+		164 => {
+			let tv = arg1 as *const i64;
+			if tv.is_null() {
+				return Err(SyscallError::BadAddress);
+			}
+			validate_user_range(arg1, 8)?;
+			sys_settimeofday(unsafe { *tv })
+		}
+		165 => sys_mount(unsafe { user_cstr(arg1 as *const u8)? }, unsafe { user_cstr(arg2 as *const u8)? }, arg4),
+		166 => sys_umount(unsafe { user_cstr(arg1 as *const u8)? }),
+		169 => {
+			if arg1 != REBOOT_MAGIC1 || arg2 != REBOOT_MAGIC2 {
+				return Err(SyscallError::InvalidArgument);
+			}
+			sys_reboot(arg3)
+		}
+		201 => {
+			let seconds = sys_time()?;
+			let tloc = arg1 as *mut i64;
+			if !tloc.is_null() {
+				validate_user_range(arg1, 8)?;
+				unsafe { *tloc = seconds as i64 };
+			}
+			Ok(seconds)
+		}
+		272 => sys_unshare(arg1),
+		318 => sys_getrandom(unsafe { user_bytes_mut(arg1, arg2)? }, arg3),
 		_ => {
 			println!("Unimplemented system call: {}", syscall_num);
 			Err(SyscallError::InvalidArgument)
@@ -176,46 +654,704 @@ pub fn syscall_handler(
 	}
 }
 
-/// Read system call - placeholder implementation
-fn sys_read(_fd: usize, _buf: *mut u8, _count: usize) -> SyscallResult {
-	// For now, return 0 bytes read for stdin
-	if _fd == 0 {
-		Ok(0)
-	} else {
-		Err(SyscallError::BadFileNumber)
+/// Resolve a descriptor in the current process's fd table
+///
+/// Before the process table exists, the standard descriptors refer to the console.
+fn fd_entry(fd: usize) -> Result<FdEntry, SyscallError> {
+	match process::with_current_process(|p| p.fd(fd)) {
+		Some(entry) => entry.ok_or(SyscallError::BadFileNumber),
+		None if fd <= 2 => Ok(FdEntry::Console),
+		None => Err(SyscallError::BadFileNumber),
 	}
 }
 
-/// Write system call - basic implementation for stdout/stderr
-fn sys_write(fd: usize, buf: *const u8, count: usize) -> SyscallResult {
-	if fd == 1 || fd == 2 {
-		// stdout or stderr
-		let slice = unsafe { core::slice::from_raw_parts(buf, count) };
-		if let Ok(s) = core::str::from_utf8(slice) {
-			print!("{}", s);
-			Ok(count)
-		} else {
-			Err(SyscallError::InvalidArgument)
+/// Whether any descriptor of the current process still refers to `entry`
+fn referenced(entry: FdEntry) -> bool {
+	process::with_current_process(|p| p.references(entry)).unwrap_or(false)
+}
+
+/// Drop the object behind `entry` once no descriptor refers to it
+fn release(entry: FdEntry) {
+	match entry {
+		FdEntry::File(handle) if !referenced(entry) => {
+			let _ = fs::with_filesystem(|fs| fs.close(handle));
 		}
-	} else {
-		Err(SyscallError::BadFileNumber)
+		FdEntry::PipeRead(id) | FdEntry::PipeWrite(id) => {
+			if !referenced(FdEntry::PipeRead(id)) && !referenced(FdEntry::PipeWrite(id)) {
+				pipe::destroy(id);
+			}
+		}
+		FdEntry::UdpSocket(id) if !referenced(entry) => udp::close(id),
+		FdEntry::TcpSocket(id) if !referenced(entry) => tcp::close(id),
+		_ => {}
 	}
 }
 
-/// Open system call - placeholder implementation
-fn sys_open(_pathname: *const u8, _flags: usize, _mode: usize) -> SyscallResult {
-	// TODO: Implement file system and file opening
-	Err(SyscallError::NoSuchFileOrDirectory)
+/// Read system call
+pub fn sys_read(fd: usize, buf: &mut [u8]) -> SyscallResult {
+	match fd_entry(fd)? {
+		FdEntry::Console => crate::tty::read(crate::vga_buffer::output_console(), buf),
+		FdEntry::File(handle) => Ok(fs::with_filesystem(|fs| fs.read(handle, buf))?),
+		FdEntry::PipeRead(id) => pipe::read(id, buf).ok_or(SyscallError::BadFileNumber),
+		FdEntry::PipeWrite(_) => Err(SyscallError::BadFileNumber),
+		FdEntry::UdpSocket(_) | FdEntry::TcpSocket(_) => sys_recvfrom(fd, buf, 0).map(|(count, _)| count),
+	}
 }
 
-/// Close system call - placeholder implementation
-fn sys_close(fd: usize) -> SyscallResult {
-	// TODO: Implement file descriptor management
-	if fd > 2 {
-		Ok(0)
-	} else {
-		Err(SyscallError::BadFileNumber)
+/// Write system call
+pub fn sys_write(fd: usize, buf: &[u8]) -> SyscallResult {
+	match fd_entry(fd)? {
+		FdEntry::Console => {
+			// Standard error stands out in the theme's error color
+			let color = (fd == 2).then(|| crate::vga_buffer::theme().error);
+			for chunk in buf.utf8_chunks() {
+				let replacement = if chunk.invalid().is_empty() { "" } else { "\u{fffd}" };
+				match color {
+					Some(color) => crate::vga_buffer::print_in_color(color, format_args!("{}{}", chunk.valid(), replacement)),
+					None => print!("{}{}", chunk.valid(), replacement),
+				}
+			}
+			Ok(buf.len())
+		}
+		FdEntry::File(handle) => Ok(fs::with_filesystem(|fs| fs.write(handle, buf))?),
+		FdEntry::PipeWrite(id) => {
+			if !referenced(FdEntry::PipeRead(id)) {
+				return Err(SyscallError::BrokenPipe);
+			}
+			match pipe::write(id, buf).ok_or(SyscallError::BadFileNumber)? {
+				// The reader only runs once the writer finishes, so a full pipe cannot drain
+				0 if !buf.is_empty() => Err(SyscallError::TryAgain),
+				n => Ok(n),
+			}
+		}
+		FdEntry::PipeRead(_) => Err(SyscallError::BadFileNumber),
+		FdEntry::UdpSocket(_) | FdEntry::TcpSocket(_) => sys_sendto(fd, buf, None),
+	}
+}
+
+/// `path` as the current process sees it: absolute, against its working
+/// directory, and never above its root
+pub fn visible_path(path: &str) -> String {
+	let cwd = process::with_current_process(|p| p.cwd.clone()).unwrap_or_else(|| String::from("/"));
+	fs::normalize_path(&cwd, path)
+}
+
+/// Where `path` is inside the current process's root, before its mounts apply
+fn namespace_path(path: &str) -> String {
+	let root = process::with_current_process(|p| p.root.clone()).unwrap_or_else(|| String::from("/"));
+	fs::under_root(&root, &visible_path(path))
+}
+
+/// Resolve `path` to where it is in the file system: against the current
+/// process's working directory, inside its root, and through its mounts
+pub fn resolve_path(path: &str) -> String {
+	let path = namespace_path(path);
+	match process::with_current_process(|p| p.mounts.clone()) {
+		Some(mounts) => mounts.translate(&path),
+		None => path,
+	}
+}
+
+/// The permission opening a file with `flags` takes
+fn open_access(flags: u32) -> u32 {
+	let wanted = match flags & fs::O_ACCMODE {
+		fs::O_RDONLY => fs::MAY_READ,
+		fs::O_WRONLY => fs::MAY_WRITE,
+		_ => fs::MAY_READ | fs::MAY_WRITE,
+	};
+	if flags & fs::O_TRUNC != 0 { wanted | fs::MAY_WRITE } else { wanted }
+}
+
+/// Open system call; a file it creates belongs to the caller, with the
+/// permission bits of `mode`
+pub fn sys_open(path: &str, flags: u32, mode: usize) -> SyscallResult {
+	let path = resolve_path(path);
+	let who = process::current_credentials();
+	let handle = fs::with_filesystem(|fs| match fs.stat(&path) {
+		Err(FsError::NotFound) if flags & fs::O_CREAT != 0 => {
+			fs.access_entry(&path, &who)?;
+			let handle = fs.open(&path, flags)?;
+			fs.set_owner(&path, who.uid, who.gid)?;
+			fs.set_permissions(&path, mode as u32 & 0o777)?;
+			Ok(handle)
+		}
+		_ => {
+			fs.access(&path, &who, open_access(flags))?;
+			fs.open(&path, flags)
+		}
+	})?;
+	process::with_current_process(|p| p.alloc_fd(FdEntry::File(handle)))
+		.ok_or(SyscallError::NoSuchProcess)
+}
+
+/// Close system call
+pub fn sys_close(fd: usize) -> SyscallResult {
+	let entry = process::with_current_process(|p| p.close_fd(fd))
+		.flatten()
+		.ok_or(SyscallError::BadFileNumber)?;
+	release(entry);
+	Ok(0)
+}
+
+/// Create a pipe, returning its `(read, write)` descriptors
+pub fn sys_pipe() -> Result<(usize, usize), SyscallError> {
+	let id = pipe::create();
+	process::with_current_process(|p| {
+		(p.alloc_fd(FdEntry::PipeRead(id)), p.alloc_fd(FdEntry::PipeWrite(id)))
+	}).ok_or_else(|| {
+		pipe::destroy(id);
+		SyscallError::NoSuchProcess
+	})
+}
+
+/// The socket behind a descriptor
+fn socket_of(fd: usize) -> Result<FdEntry, SyscallError> {
+	match fd_entry(fd)? {
+		entry @ (FdEntry::UdpSocket(_) | FdEntry::TcpSocket(_)) => Ok(entry),
+		_ => Err(SyscallError::NotASocket),
+	}
+}
+
+/// Retry `attempt` until it has a result, collecting frames in between as
+/// the network task cannot run until the call returns; fails with
+/// `TryAgain` at once unless `wait`
+fn wait_for_network<T>(wait: bool, mut attempt: impl FnMut() -> Result<Option<T>, SyscallError>) -> Result<T, SyscallError> {
+	loop {
+		net::poll();
+		if let Some(result) = attempt()? {
+			return Ok(result);
+		}
+		if !wait {
+			return Err(SyscallError::TryAgain);
+		}
+		if crate::task::keyboard::interrupt_requested() {
+			return Err(SyscallError::InterruptedSystemCall);
+		}
+		x86_64::instructions::hlt();
+	}
+}
+
+/// Create a UDP or TCP socket over IPv4
+pub fn sys_socket(domain: usize, kind: usize, protocol: usize) -> SyscallResult {
+	if domain != AF_INET {
+		return Err(SyscallError::AddressFamilyNotSupported);
+	}
+	if kind & !(SOCK_TYPE_MASK | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+		return Err(SyscallError::InvalidArgument);
+	}
+	let nonblocking = kind & SOCK_NONBLOCK != 0;
+	let entry = match (kind & SOCK_TYPE_MASK, protocol) {
+		(SOCK_DGRAM, 0 | IPPROTO_UDP) => {
+			let id = udp::create();
+			udp::set_nonblocking(id, nonblocking);
+			FdEntry::UdpSocket(id)
+		}
+		(SOCK_STREAM, 0 | IPPROTO_TCP) => {
+			let id = tcp::create();
+			tcp::set_nonblocking(id, nonblocking);
+			FdEntry::TcpSocket(id)
+		}
+		_ => return Err(SyscallError::ProtocolNotSupported),
+	};
+	process::with_current_process(|p| p.alloc_fd(entry)).ok_or_else(|| {
+		release(entry);
+		SyscallError::NoSuchProcess
+	})
+}
+
+/// Bind a socket to a local port; the address must be one of ours, or any
+pub fn sys_bind(fd: usize, address: SocketAddrV4) -> SyscallResult {
+	let entry = socket_of(fd)?;
+	let ours = address.ip().is_unspecified() || net::configs().iter().any(|config| config.address == Some(*address.ip()));
+	if !ours {
+		return Err(SyscallError::AddressNotAvailable);
+	}
+	match entry {
+		FdEntry::TcpSocket(id) => tcp::bind(id, address.port()).map(drop)?,
+		FdEntry::UdpSocket(id) => udp::bind(id, address.port()).map(drop)?,
+		_ => unreachable!(),
+	}
+	Ok(0)
+}
+
+/// Connect a TCP socket to `address`, waiting for the handshake unless the
+/// socket is non-blocking
+pub fn sys_connect(fd: usize, address: SocketAddrV4) -> SyscallResult {
+	let FdEntry::TcpSocket(id) = socket_of(fd)? else {
+		return Err(SyscallError::OperationNotSupported);
+	};
+	tcp::connect(id, address)?;
+	if tcp::nonblocking(id) {
+		return Err(SyscallError::InProgress);
+	}
+	wait_for_network(true, || Ok(tcp::connected(id)?))?;
+	Ok(0)
+}
+
+/// Start taking connections on a TCP socket
+pub fn sys_listen(fd: usize, backlog: usize) -> SyscallResult {
+	let FdEntry::TcpSocket(id) = socket_of(fd)? else {
+		return Err(SyscallError::OperationNotSupported);
+	};
+	tcp::listen(id, backlog)?;
+	Ok(0)
+}
+
+/// Wait for a connection on a listening socket, returning a descriptor for
+/// it and the peer's address
+pub fn sys_accept(fd: usize) -> Result<(usize, SocketAddrV4), SyscallError> {
+	let FdEntry::TcpSocket(id) = socket_of(fd)? else {
+		return Err(SyscallError::OperationNotSupported);
+	};
+	let (child, peer) = wait_for_network(!tcp::nonblocking(id), || Ok(tcp::accept(id)?))?;
+	let new_fd = process::with_current_process(|p| p.alloc_fd(FdEntry::TcpSocket(child))).ok_or_else(|| {
+		tcp::close(child);
+		SyscallError::NoSuchProcess
+	})?;
+	Ok((new_fd, peer))
+}
+
+/// Send data on a socket: a datagram to `destination` from a UDP socket,
+/// or the stream of a connected TCP socket, which ignores `destination` and
+/// waits until all of `buf` is queued unless the socket is non-blocking
+pub fn sys_sendto(fd: usize, buf: &[u8], destination: Option<SocketAddrV4>) -> SyscallResult {
+	match socket_of(fd)? {
+		FdEntry::UdpSocket(id) => {
+			let destination = destination.ok_or(SyscallError::DestinationAddressRequired)?;
+			Ok(udp::send_to(id, destination, buf)?)
+		}
+		FdEntry::TcpSocket(id) => {
+			let wait = !tcp::nonblocking(id);
+			let mut sent = 0;
+			loop {
+				sent += wait_for_network(wait, || match tcp::send(id, &buf[sent..])? {
+					0 if !buf.is_empty() => Ok(None),
+					count => Ok(Some(count)),
+				})?;
+				if sent == buf.len() || !wait {
+					break;
+				}
+			}
+			Ok(sent)
+		}
+		_ => unreachable!(),
+	}
+}
+
+/// Receive on a socket, waiting unless the socket is non-blocking or
+/// `flags` has `MSG_DONTWAIT`: a datagram, of which what does not fit in
+/// `buf` is lost, or stream data, 0 bytes meaning the peer has finished
+pub fn sys_recvfrom(fd: usize, buf: &mut [u8], flags: usize) -> Result<(usize, SocketAddrV4), SyscallError> {
+	match socket_of(fd)? {
+		FdEntry::UdpSocket(id) => {
+			let wait = flags & MSG_DONTWAIT == 0 && !udp::nonblocking(id);
+			let datagram = wait_for_network(wait, || Ok(udp::recv_from(id)?))?;
+			let count = datagram.data.len().min(buf.len());
+			buf[..count].copy_from_slice(&datagram.data[..count]);
+			Ok((count, datagram.source))
+		}
+		FdEntry::TcpSocket(id) => {
+			let wait = flags & MSG_DONTWAIT == 0 && !tcp::nonblocking(id);
+			let count = wait_for_network(wait, || Ok(tcp::recv(id, buf)?))?;
+			let peer = tcp::addresses(id).and_then(|(_, peer)| peer).unwrap_or(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+			Ok((count, peer))
+		}
+		_ => unreachable!(),
+	}
+}
+
+/// The `iovec` array of a `struct msghdr`, as slices
+unsafe fn user_iovecs<'a>(message: *const u8) -> Result<Vec<&'a mut [u8]>, SyscallError> {
+	let iov = (message.add(MSGHDR_IOV) as *const usize).read_unaligned() as *const u8;
+	let count = (message.add(MSGHDR_IOVLEN) as *const usize).read_unaligned();
+	if iov.is_null() && count > 0 {
+		return Err(SyscallError::BadAddress);
+	}
+	validate_user_range(iov as usize, count.checked_mul(IOVEC_SIZE).ok_or(SyscallError::BadAddress)?)?;
+	(0..count)
+		.map(|index| {
+			let entry = iov.add(index * IOVEC_SIZE) as *const usize;
+			let (base, length) = (entry.read_unaligned(), entry.add(1).read_unaligned());
+			if base == 0 { Ok(&mut [][..]) } else { user_bytes_mut(base, length) }
+		})
+		.collect()
+}
+
+/// Send the buffers of a `struct msghdr` as one datagram or stretch of stream
+fn sys_sendmsg(fd: usize, message: *const u8) -> SyscallResult {
+	if message.is_null() {
+		return Err(SyscallError::BadAddress);
+	}
+	validate_user_range(message as usize, MSGHDR_SIZE)?;
+	let (name, name_length) = unsafe {
+		((message.add(MSGHDR_NAME) as *const usize).read_unaligned() as *const u8, (message.add(MSGHDR_NAMELEN) as *const u32).read_unaligned())
+	};
+	let destination = if name.is_null() { None } else { Some(unsafe { user_sockaddr(name, name_length as usize)? }) };
+	let data: Vec<u8> = unsafe { user_iovecs(message)? }.concat();
+	sys_sendto(fd, &data, destination)
+}
+
+/// Receive into the buffers of a `struct msghdr`, filling in the sender's
+/// address if it has room for one
+fn sys_recvmsg(fd: usize, message: *mut u8, flags: usize) -> SyscallResult {
+	if message.is_null() {
+		return Err(SyscallError::BadAddress);
+	}
+	validate_user_range(message as usize, MSGHDR_SIZE)?;
+	let mut buffers = unsafe { user_iovecs(message)? };
+	let mut data = alloc::vec![0; buffers.iter().map(|buffer| buffer.len()).sum::<usize>()];
+	let (count, source) = sys_recvfrom(fd, &mut data, flags)?;
+	let mut rest = &data[..count];
+	for buffer in buffers.iter_mut() {
+		let length = buffer.len().min(rest.len());
+		buffer[..length].copy_from_slice(&rest[..length]);
+		rest = &rest[length..];
+	}
+	unsafe {
+		let name = (message.add(MSGHDR_NAME) as *const usize).read_unaligned() as *mut u8;
+		write_user_sockaddr(name, message.add(MSGHDR_NAMELEN) as *mut u32, source)?;
+		(message.add(MSGHDR_CONTROLLEN) as *mut usize).write_unaligned(0);
+		(message.add(MSGHDR_FLAGS) as *mut i32).write_unaligned(0);
+	}
+	Ok(count)
+}
+
+/// Stop reading from, writing to, or both, a connected TCP socket
+pub fn sys_shutdown(fd: usize, how: usize) -> SyscallResult {
+	let (read, write) = match how {
+		SHUT_RD => (true, false),
+		SHUT_WR => (false, true),
+		SHUT_RDWR => (true, true),
+		_ => return Err(SyscallError::InvalidArgument),
+	};
+	match socket_of(fd)? {
+		FdEntry::TcpSocket(id) => tcp::shutdown(id, read, write)?,
+		_ => return Err(SyscallError::NotConnected),
+	}
+	Ok(0)
+}
+
+/// A socket's own address
+pub fn sys_getsockname(fd: usize) -> Result<SocketAddrV4, SyscallError> {
+	match socket_of(fd)? {
+		FdEntry::UdpSocket(id) => Ok(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, udp::local_port(id).unwrap_or(0))),
+		FdEntry::TcpSocket(id) => Ok(tcp::addresses(id).ok_or(SyscallError::BadFileNumber)?.0),
+		_ => unreachable!(),
+	}
+}
+
+/// The address of a socket's peer
+pub fn sys_getpeername(fd: usize) -> Result<SocketAddrV4, SyscallError> {
+	match socket_of(fd)? {
+		FdEntry::TcpSocket(id) => tcp::addresses(id).and_then(|(_, peer)| peer).ok_or(SyscallError::NotConnected),
+		_ => Err(SyscallError::NotConnected),
+	}
+}
+
+/// Set a socket option; the few known are accepted and change nothing
+pub fn sys_setsockopt(fd: usize, level: usize, name: usize, _value: i32) -> SyscallResult {
+	let entry = socket_of(fd)?;
+	match (level, name) {
+		(SOL_SOCKET, SO_REUSEADDR | SO_KEEPALIVE | SO_SNDBUF | SO_RCVBUF) => Ok(0),
+		(IPPROTO_TCP, TCP_NODELAY) if matches!(entry, FdEntry::TcpSocket(_)) => Ok(0),
+		_ => Err(SyscallError::ProtocolNotAvailable),
+	}
+}
+
+/// Get a socket option: its type, its pending error, which is cleared, or
+/// one of the options that can be set
+pub fn sys_getsockopt(fd: usize, level: usize, name: usize) -> Result<i32, SyscallError> {
+	let entry = socket_of(fd)?;
+	match (level, name, entry) {
+		(SOL_SOCKET, SO_TYPE, FdEntry::TcpSocket(_)) => Ok(SOCK_STREAM as i32),
+		(SOL_SOCKET, SO_TYPE, _) => Ok(SOCK_DGRAM as i32),
+		(SOL_SOCKET, SO_ERROR, FdEntry::TcpSocket(id)) => {
+			Ok(tcp::take_error(id).map_or(0, |err| -(SyscallError::from(err) as i32)))
+		}
+		(SOL_SOCKET, SO_ERROR, _) => Ok(0),
+		(SOL_SOCKET, SO_REUSEADDR | SO_KEEPALIVE, _) => Ok(0),
+		(SOL_SOCKET, SO_SNDBUF | SO_RCVBUF, _) => Ok(16 * 1024),
+		(IPPROTO_TCP, TCP_NODELAY, FdEntry::TcpSocket(_)) => Ok(1),
+		_ => Err(SyscallError::ProtocolNotAvailable),
+	}
+}
+
+/// `ioctl` requests on a terminal, and what they read or write: Linux's
+/// `struct termios`, of which only the local mode flags mean anything here
+const TCGETS: usize = 0x5401;
+const TCSETS: usize = 0x5402;
+const TERMIOS_SIZE: usize = 36;
+const TERMIOS_LFLAG: usize = 12;
+const ICANON: u32 = 0o2;
+const ECHO: u32 = 0o10;
+
+/// Device control; only getting and setting a terminal's modes is supported
+fn sys_ioctl(fd: usize, request: usize, arg: *mut u8) -> SyscallResult {
+	if fd_entry(fd)? != FdEntry::Console {
+		return Err(SyscallError::NotATypewriter);
+	}
+	if arg.is_null() {
+		return Err(SyscallError::BadAddress);
+	}
+	validate_user_range(arg as usize, TERMIOS_SIZE)?;
+	let console = crate::vga_buffer::output_console();
+	let lflag = unsafe { arg.add(TERMIOS_LFLAG) as *mut u32 };
+	match request {
+		TCGETS => {
+			let termios = crate::tty::termios(console);
+			let flags = if termios.canonical { ICANON } else { 0 } | if termios.echo { ECHO } else { 0 };
+			unsafe {
+				core::ptr::write_bytes(arg, 0, TERMIOS_SIZE);
+				lflag.write_unaligned(flags);
+			}
+		}
+		TCSETS => {
+			let flags = unsafe { lflag.read_unaligned() };
+			crate::tty::set_termios(console, crate::tty::Termios { canonical: flags & ICANON != 0, echo: flags & ECHO != 0 });
+		}
+		_ => return Err(SyscallError::InvalidArgument),
+	}
+	Ok(0)
+}
+
+/// Duplicate a descriptor into the lowest free slot
+pub fn sys_dup(fd: usize) -> SyscallResult {
+	let entry = fd_entry(fd)?;
+	process::with_current_process(|p| p.alloc_fd(entry))
+		.ok_or(SyscallError::NoSuchProcess)
+}
+
+/// Make `new_fd` refer to the same open file as `old_fd`, closing `new_fd` first
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> SyscallResult {
+	let entry = fd_entry(old_fd)?;
+	if old_fd == new_fd {
+		return Ok(new_fd);
+	}
+	let replaced = process::with_current_process(|p| p.set_fd(new_fd, entry))
+		.ok_or(SyscallError::NoSuchProcess)?;
+	if let Some(replaced) = replaced {
+		release(replaced);
+	}
+	Ok(new_fd)
+}
+
+/// Copy the working directory, NUL-terminated, into `buf`
+pub fn sys_getcwd(buf: &mut [u8]) -> SyscallResult {
+	let cwd = visible_path(".");
+	if cwd.len() + 1 > buf.len() {
+		return Err(SyscallError::MathResultNotRepresentable);
+	}
+	buf[..cwd.len()].copy_from_slice(cwd.as_bytes());
+	buf[cwd.len()] = 0;
+	Ok(cwd.len())
+}
+
+/// Change the working directory
+pub fn sys_chdir(path: &str) -> SyscallResult {
+	search_directory(path)?;
+	let path = visible_path(path);
+	process::with_current_process(|p| p.cwd = path).ok_or(SyscallError::NoSuchProcess)?;
+	Ok(0)
+}
+
+/// Check that `path` is a directory the caller may search, as one it
+/// changes into or mounts has to be
+fn search_directory(path: &str) -> SyscallResult {
+	let path = resolve_path(path);
+	let who = process::current_credentials();
+	let metadata = fs::with_filesystem(|fs| fs.access(&path, &who, 0).and_then(|()| fs.stat(&path)))?;
+	if metadata.file_type != FileType::Directory {
+		return Err(SyscallError::NotADirectory);
+	}
+	if !metadata.permits(&who, fs::MAY_EXEC) {
+		return Err(SyscallError::PermissionDenied2);
+	}
+	Ok(0)
+}
+
+/// Refuse to move or remove a directory something is mounted on
+fn not_mount_point(path: &str) -> Result<(), SyscallError> {
+	let target = namespace_path(path);
+	match process::with_current_process(|p| p.mounts.clone()) {
+		Some(mounts) if mounts.is_mount_point(&target) => Err(SyscallError::DeviceOrResourceBusy),
+		_ => Ok(()),
+	}
+}
+
+/// Refuse a caller other than root, auditing that it tried to `action`
+fn require_root(action: fmt::Arguments) -> Result<(), SyscallError> {
+	if process::current_credentials().is_root() {
+		return Ok(());
+	}
+	audit::record(audit::Kind::Denied, audit::Actor::current(), action);
+	Err(SyscallError::PermissionDenied)
+}
+
+/// Make `path` the current process's root directory, as only root may; the
+/// working directory moves to it, so nothing outside stays in reach
+pub fn sys_chroot(path: &str) -> SyscallResult {
+	require_root(format_args!("chroot to {}", path))?;
+	search_directory(path)?;
+	let root = namespace_path(path);
+	process::with_current_process(|p| {
+		p.root = root;
+		p.cwd = String::from("/");
+	}).ok_or(SyscallError::NoSuchProcess)?;
+	Ok(0)
+}
+
+/// Bind the directory at `source` over the one at `target` in the current
+/// process's mount namespace, as only root may; `flags` must ask for
+/// `MS_BIND`, since there are no other filesystems to mount
+pub fn sys_mount(source: &str, target: &str, flags: usize) -> SyscallResult {
+	require_root(format_args!("mount {} on {}", source, target))?;
+	if flags & MS_BIND == 0 {
+		return Err(SyscallError::NoSuchDevice);
+	}
+	search_directory(source)?;
+	search_directory(target)?;
+	let (source, target) = (resolve_path(source), namespace_path(target));
+	let mounts = process::with_current_process(|p| p.mounts.clone()).ok_or(SyscallError::NoSuchProcess)?;
+	audit::record(audit::Kind::Mount, audit::Actor::current(), format_args!("bound {} on {}", source, target));
+	mounts.bind(source, target);
+	Ok(0)
+}
+
+/// Take away the latest mount on `target` in the current process's
+/// namespace, as only root may
+pub fn sys_umount(target: &str) -> SyscallResult {
+	require_root(format_args!("umount {}", target))?;
+	let target = namespace_path(target);
+	let mounts = process::with_current_process(|p| p.mounts.clone()).ok_or(SyscallError::NoSuchProcess)?;
+	if !mounts.unmount(&target) {
+		return Err(SyscallError::InvalidArgument);
+	}
+	audit::record(audit::Kind::Mount, audit::Actor::current(), format_args!("unmounted {}", target));
+	Ok(0)
+}
+
+/// Give the current process its own copy of what `flags` names, as only
+/// root may; `CLONE_NEWNS`, its mounts, is all there is to unshare
+pub fn sys_unshare(flags: usize) -> SyscallResult {
+	if flags & !process::CLONE_NEWNS != 0 {
+		return Err(SyscallError::InvalidArgument);
+	}
+	if flags == 0 {
+		return Ok(0);
 	}
+	require_root(format_args!("unshare its mounts"))?;
+	let mounts = process::with_current_process(|p| p.mounts.clone()).ok_or(SyscallError::NoSuchProcess)?;
+	let copy = mounts.copy();
+	process::with_current_process(|p| p.mounts = copy).ok_or(SyscallError::NoSuchProcess)?;
+	Ok(0)
+}
+
+/// Rename a file or directory
+pub fn sys_rename(from: &str, to: &str) -> SyscallResult {
+	not_mount_point(from)?;
+	not_mount_point(to)?;
+	let (from, to) = (resolve_path(from), resolve_path(to));
+	let who = process::current_credentials();
+	fs::with_filesystem(|fs| {
+		fs.access_entry(&from, &who)?;
+		fs.access_entry(&to, &who)?;
+		fs.rename(&from, &to)
+	})?;
+	Ok(0)
+}
+
+/// Create a directory belonging to the caller, with the permission bits of `mode`
+pub fn sys_mkdir(path: &str, mode: usize) -> SyscallResult {
+	let path = resolve_path(path);
+	let who = process::current_credentials();
+	fs::with_filesystem(|fs| {
+		fs.access_entry(&path, &who)?;
+		fs.create_directory(path.clone())?;
+		fs.set_owner(&path, who.uid, who.gid)?;
+		fs.set_permissions(&path, mode as u32 & 0o1777)
+	})?;
+	Ok(0)
+}
+
+/// Remove an empty directory
+pub fn sys_rmdir(path: &str) -> SyscallResult {
+	not_mount_point(path)?;
+	let path = resolve_path(path);
+	let who = process::current_credentials();
+	fs::with_filesystem(|fs| match fs.stat(&path)?.file_type {
+		FileType::Directory => fs.access_entry(&path, &who).and_then(|()| fs.remove(&path)),
+		_ => Err(FsError::NotDirectory),
+	})?;
+	Ok(0)
+}
+
+/// Remove a (non-directory) file
+pub fn sys_unlink(path: &str) -> SyscallResult {
+	let path = resolve_path(path);
+	let who = process::current_credentials();
+	fs::with_filesystem(|fs| match fs.stat(&path)?.file_type {
+		FileType::Directory => Err(FsError::IsDirectory),
+		_ => fs.access_entry(&path, &who).and_then(|()| fs.remove(&path)),
+	})?;
+	Ok(0)
+}
+
+/// Check that the caller can reach a file and that its permission bits
+/// allow `mode`, some of `R_OK`, `W_OK` and `X_OK`, or `F_OK` for neither
+pub fn sys_access(path: &str, mode: usize) -> SyscallResult {
+	access_resolved(&resolve_path(path), mode)
+}
+
+/// `sys_access` for a path `resolve_path` has already resolved, which
+/// resolving again would put under the caller's root twice
+pub fn access_resolved(real: &str, mode: usize) -> SyscallResult {
+	let who = process::current_credentials();
+	let wanted = (mode & (R_OK | W_OK | X_OK)) as u32;
+	fs::with_filesystem(|fs| fs.access(real, &who, wanted))?;
+	Ok(0)
+}
+
+/// Change the permission bits of a file, as its owner or root
+pub fn sys_chmod(path: &str, mode: usize) -> SyscallResult {
+	let path = resolve_path(path);
+	let who = process::current_credentials();
+	fs::with_filesystem(|fs| fs.access_owner(&path, &who).and_then(|()| fs.set_permissions(&path, mode as u32)))?;
+	Ok(0)
+}
+
+/// Give a file to another user or group, leaving either as it is for
+/// `u32::MAX`: root may change both, and the owner the group, to one of theirs
+pub fn sys_chown(path: &str, owner: u32, group: u32) -> SyscallResult {
+	let path = resolve_path(path);
+	let who = process::current_credentials();
+	fs::with_filesystem(|fs| {
+		fs.access_owner(&path, &who)?;
+		let metadata = fs.stat(&path)?;
+		let owner = if owner == u32::MAX { metadata.owner } else { owner };
+		let group = if group == u32::MAX { metadata.group } else { group };
+		let allowed = owner == metadata.owner && (group == metadata.group || who.in_group(group));
+		if !who.is_root() && !allowed {
+			audit::record(audit::Kind::Denied, audit::Actor::with(&who), format_args!("chown {} to {}:{}", path, owner, group));
+			return Err(FsError::NotPermitted);
+		}
+		fs.set_owner(&path, owner, group)
+	})?;
+	Ok(0)
+}
+
+/// Replace the current program with the executable at `path`
+///
+/// There is no program loader yet: after the usual permission checks every
+/// file fails with `ExecFormatError`, which callers treat as "run it as a
+/// shell script" the way `execvp` does.
+pub fn sys_execve(path: &str) -> SyscallResult {
+	let path = resolve_path(path);
+	let metadata = fs::with_filesystem(|fs| fs.stat(&path))?;
+	if metadata.file_type != FileType::Regular {
+		return Err(SyscallError::PermissionDenied2);
+	}
+	access_resolved(&path, X_OK)?;
+	Err(SyscallError::ExecFormatError)
 }
 
 /// Get process ID system call
@@ -224,6 +1360,143 @@ fn sys_getpid() -> SyscallResult {
 	Ok(1)
 }
 
+/// The caller's user ID; there are no separate effective IDs, so `geteuid`
+/// gives the same
+pub fn sys_getuid() -> SyscallResult {
+	Ok(process::current_credentials().uid as usize)
+}
+
+/// The caller's group ID, for `getgid` and `getegid`
+pub fn sys_getgid() -> SyscallResult {
+	Ok(process::current_credentials().gid as usize)
+}
+
+/// Become user `uid`: root may become anyone, for good, since there is no
+/// saved ID to go back to; anyone else only who they already are
+pub fn sys_setuid(uid: u32) -> SyscallResult {
+	process::with_current_process(|p| {
+		if !p.credentials.is_root() && p.credentials.uid != uid {
+			audit::record(audit::Kind::Denied, audit::Actor::of(p), format_args!("setuid {}", uid));
+			return Err(SyscallError::PermissionDenied);
+		}
+		p.credentials.uid = uid;
+		Ok(0)
+	})
+	.ok_or(SyscallError::NoSuchProcess)?
+}
+
+/// Change to group `gid`, as `sys_setuid` does the user
+pub fn sys_setgid(gid: u32) -> SyscallResult {
+	process::with_current_process(|p| {
+		if !p.credentials.is_root() && p.credentials.gid != gid {
+			audit::record(audit::Kind::Denied, audit::Actor::of(p), format_args!("setgid {}", gid));
+			return Err(SyscallError::PermissionDenied);
+		}
+		p.credentials.gid = gid;
+		Ok(0)
+	})
+	.ok_or(SyscallError::NoSuchProcess)?
+}
+
+/// Send a signal to a process, or to process group `-pid` when `pid` is negative
+pub fn sys_kill(pid: isize, signal: usize) -> SyscallResult {
+	let signal = Signal::from_number(signal).ok_or(SyscallError::InvalidArgument)?;
+	let delivered = if pid < 0 {
+		process::signal_group(ProcessId(pid.unsigned_abs()), signal) > 0
+	} else {
+		process::send_signal(ProcessId(pid as usize), signal)
+	};
+	if delivered { Ok(0) } else { Err(SyscallError::NoSuchProcess) }
+}
+
+/// Move process `pid` (0 for the caller) into group `pgid` (0 for its own PID)
+pub fn sys_setpgid(pid: usize, pgid: usize) -> SyscallResult {
+	let pid = match pid {
+		0 => process::current_pid().ok_or(SyscallError::NoSuchProcess)?,
+		pid => ProcessId(pid),
+	};
+	let pgid = if pgid == 0 { pid } else { ProcessId(pgid) };
+	if process::set_pgid(pid, pgid) { Ok(0) } else { Err(SyscallError::NoSuchProcess) }
+}
+
+/// Get the process group of `pid` (0 for the caller)
+pub fn sys_getpgid(pid: usize) -> SyscallResult {
+	let pid = match pid {
+		0 => process::current_pid().ok_or(SyscallError::NoSuchProcess)?,
+		pid => ProcessId(pid),
+	};
+	process::with_scheduler(|s| s.get_process(pid).map(|p| p.pgid.0))
+		.ok_or(SyscallError::NoSuchProcess)
+}
+
+/// Seconds since the Unix epoch on the system wall clock
+pub fn sys_time() -> SyscallResult {
+	Ok(time::now() as usize)
+}
+
+/// Set the system wall clock to `seconds` since the Unix epoch
+pub fn sys_settimeofday(seconds: i64) -> SyscallResult {
+	let seconds = u64::try_from(seconds).map_err(|_| SyscallError::InvalidArgument)?;
+	time::set_time(seconds);
+	Ok(0)
+}
+
+/// Read or narrow the current process's system call filter: `PR_GET_SECCOMP`
+/// says whether it has one, and `PR_SET_SECCOMP` installs one, in strict
+/// mode or from `bitmap`, on top of any it already has
+///
+/// Anyone may install a filter, since it only takes calls away; a launcher
+/// running as root sets one before `setuid` to run something untrusted, and
+/// has to leave `setuid` allowed for that.
+pub fn sys_prctl(option: usize, mode: usize, bitmap: &[u8]) -> SyscallResult {
+	let filter = match (option, mode) {
+		(PR_GET_SECCOMP, _) => {
+			let restricted = process::with_current_process(|p| p.syscall_filter.is_restricted())
+				.ok_or(SyscallError::NoSuchProcess)?;
+			return Ok(if restricted { SECCOMP_MODE_FILTER } else { 0 });
+		}
+		(PR_SET_SECCOMP, SECCOMP_MODE_STRICT) => {
+			process::SyscallFilter::allowing(&[SyscallNumber::Read as usize, SyscallNumber::Write as usize])
+		}
+		(PR_SET_SECCOMP, SECCOMP_MODE_FILTER) if bitmap.is_empty() => return Err(SyscallError::BadAddress),
+		(PR_SET_SECCOMP, SECCOMP_MODE_FILTER) if bitmap.len() <= process::FILTERED_SYSCALLS / 8 => {
+			process::SyscallFilter::from_bitmap(bitmap)
+		}
+		_ => return Err(SyscallError::InvalidArgument),
+	};
+	process::with_current_process(|p| p.syscall_filter.restrict(&filter)).ok_or(SyscallError::NoSuchProcess)?;
+	Ok(0)
+}
+
+/// Fill `buf` with random bytes, waiting until the generator is seeded
+/// unless `GRND_NONBLOCK` says to fail instead
+pub fn sys_getrandom(buf: &mut [u8], flags: usize) -> SyscallResult {
+	if flags & !(random::GRND_NONBLOCK | random::GRND_RANDOM) != 0 {
+		return Err(SyscallError::InvalidArgument);
+	}
+	if !random::ready() {
+		if flags & random::GRND_NONBLOCK != 0 {
+			return Err(SyscallError::TryAgain);
+		}
+		if !random::wait_ready() {
+			return Err(SyscallError::InterruptedSystemCall);
+		}
+	}
+	random::fill(buf);
+	Ok(buf.len())
+}
+
+/// Restart, halt, or power off the machine; only returns on a bad command
+pub fn sys_reboot(cmd: usize) -> SyscallResult {
+	let action = match cmd {
+		REBOOT_CMD_RESTART => PowerAction::Reboot,
+		REBOOT_CMD_HALT => PowerAction::Halt,
+		REBOOT_CMD_POWER_OFF => PowerAction::PowerOff,
+		_ => return Err(SyscallError::InvalidArgument),
+	};
+	power::perform(action)
+}
+
 /// Exit the current process
 fn sys_exit(status: i32) -> SyscallResult {
 	println!("Process exiting with status: {}", status);
@@ -236,9 +1509,8 @@ fn sys_exit(status: i32) -> SyscallResult {
 fn sys_uname(buf: *mut u8) -> SyscallResult {
 	let uname_info = b"ScottOS\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0v0.1.0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0x86_64\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
 	
-	unsafe {
-		core::ptr::copy_nonoverlapping(uname_info.as_ptr(), buf, uname_info.len().min(390));
-	}
+	let length = uname_info.len().min(390);
+	unsafe { user_bytes_mut(buf as usize, length)? }.copy_from_slice(&uname_info[..length]);
 	
 	Ok(0)
 } 
\ No newline at end of file
@@ -0,0 +1,48 @@
+use super::{executor, JoinHandle, Priority};
+use crate::smp::{self, Spawned};
+use alloc::{boxed::Box, string::String};
+
+/// The processor blocking work goes to: the last one online, so the boot
+/// processor stays free for the consoles, or the boot processor when it is
+/// the only one
+fn blocking_cpu() -> usize {
+	smp::online_cpus().last().copied().unwrap_or(0)
+}
+
+/// Run work known to block, such as PIO disk transfers or a scan of the
+/// whole file system, away from the interactive tasks, returning a handle
+/// that resolves with its result
+///
+/// The work runs as a background task on another processor when there is
+/// one. With a single processor it still holds the processor while it runs,
+/// but only after every ready task of higher priority has had its turn.
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+	F: FnOnce() -> R + Send + 'static,
+	R: Send + 'static,
+{
+	let (future, handle) = super::join::joinable(async move { executor::blocking(f) });
+	let spawned = Spawned { priority: Priority::Background, name: Some(String::from("blocking")), future: Box::pin(future) };
+	smp::push_spawned(blocking_cpu(), spawned);
+	handle
+}
+
+/// Test that blocking work is queued as a background task and its result
+/// reaches the handle
+#[test_case]
+fn test_spawn_blocking() {
+	use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+
+	let mut cx = Context::from_waker(Waker::noop());
+
+	let cpu = blocking_cpu();
+	smp::take_spawned(cpu);
+	let mut handle = spawn_blocking(|| 6 * 7);
+	let mut spawned = smp::take_spawned(cpu);
+	assert_eq!(spawned.len(), 1);
+	let mut task = spawned.pop_front().unwrap();
+	assert_eq!((task.priority, task.name.as_deref()), (Priority::Background, Some("blocking")));
+	assert!(!handle.is_finished());
+	assert_eq!(task.future.as_mut().poll(&mut cx), Poll::Ready(()));
+	assert_eq!(Pin::new(&mut handle).poll(&mut cx), Poll::Ready(Ok(42)));
+}
@@ -0,0 +1,97 @@
+use crate::memory;
+use crate::smp::{self, MAX_CPUS};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::VirtAddr;
+
+/// Words at the bottom of each processor's stack that hold its canary
+const CANARY_WORDS: usize = 8;
+/// Furthest below where boot runs that the boot stack's bottom is looked for
+const MAX_BOOT_STACK_PAGES: u64 = 4096;
+
+/// Where a processor's canary is, 0 until it is armed, and what it holds
+struct Canary {
+	bottom: AtomicU64,
+	value: AtomicU64,
+}
+
+static CANARIES: [Canary; MAX_CPUS] = [const { Canary { bottom: AtomicU64::new(0), value: AtomicU64::new(0) } }; MAX_CPUS];
+
+/// Word `i` of a canary holding `value`; each differs, so a copy of one
+/// word over the others does not pass, and each has a zero low byte, so a
+/// string run off the end of a buffer stops at it
+fn canary_word(value: u64, i: usize) -> u64 {
+	value.rotate_left(i as u32 * 8) & !0xff
+}
+
+/// Write a canary holding `value` at `words`
+///
+/// # Safety
+///
+/// `words` must be valid for writes of `CANARY_WORDS` words.
+unsafe fn write_canary(words: *mut u64, value: u64) {
+	for i in 0..CANARY_WORDS {
+		words.add(i).write_volatile(canary_word(value, i));
+	}
+}
+
+/// Whether the canary at `words` still holds `value`
+///
+/// # Safety
+///
+/// `words` must be valid for reads of `CANARY_WORDS` words.
+unsafe fn canary_intact(words: *const u64, value: u64) -> bool {
+	(0..CANARY_WORDS).all(|i| words.add(i).read_volatile() == canary_word(value, i))
+}
+
+/// Guard processor `cpu`'s stack, which starts at `bottom`, with a canary
+/// that each switch between its tasks checks
+pub fn arm(cpu: usize, bottom: VirtAddr) {
+	let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+	let value = crate::cpu::rdrand().unwrap_or(0) ^ tsc.rotate_left(32) ^ bottom.as_u64();
+	unsafe { write_canary(bottom.as_mut_ptr(), value) };
+	CANARIES[cpu].value.store(value, Ordering::Relaxed);
+	CANARIES[cpu].bottom.store(bottom.as_u64(), Ordering::Release);
+}
+
+/// Guard the stack boot runs on, which the bootloader maps above a guard
+/// page: its bottom is the lowest page mapped below where boot is running
+pub fn arm_boot_stack() {
+	let rsp: u64;
+	unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags)) };
+	let mut bottom = VirtAddr::new(rsp).align_down(4096u64);
+	for _ in 0..MAX_BOOT_STACK_PAGES {
+		match memory::translate(bottom - 4096u64) {
+			Some(_) => bottom -= 4096u64,
+			None => break,
+		}
+	}
+	arm(0, bottom);
+	crate::klog!(Debug, "canary: boot stack {:#x}-{:#x}", bottom.as_u64(), rsp);
+}
+
+/// Panic if this processor's stack canary has been written over; called by
+/// the executor after every poll, so the task that did it is named
+pub(crate) fn check(task_id: u64, name: &str) {
+	let cpu = smp::current_cpu();
+	let canary = &CANARIES[cpu];
+	let bottom = canary.bottom.load(Ordering::Acquire);
+	if bottom == 0 {
+		return;
+	}
+	if !unsafe { canary_intact(bottom as *const u64, canary.value.load(Ordering::Relaxed)) } {
+		panic!("stack canary: task {} ({}) ran over the bottom of CPU {}'s stack at {:#x}", task_id, name, cpu, bottom);
+	}
+}
+
+/// Test that a canary passes until any of its bytes changes
+#[test_case]
+fn test_canary() {
+	let mut words = [0u64; CANARY_WORDS];
+	let value = 0x0123_4567_89ab_cdef;
+	unsafe { write_canary(words.as_mut_ptr(), value) };
+	assert!(unsafe { canary_intact(words.as_ptr(), value) });
+	assert!(!unsafe { canary_intact(words.as_ptr(), value ^ 1 << 40) });
+	assert!(words.iter().all(|word| word & 0xff == 0));
+	words[CANARY_WORDS - 1] ^= 0x100;
+	assert!(!unsafe { canary_intact(words.as_ptr(), value) });
+}
@@ -0,0 +1,88 @@
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::Poll;
+use futures_util::task::AtomicWaker;
+
+/// Work queued by interrupt handlers for later, with a word of context
+pub type Work = fn(usize);
+
+/// Work items that can wait at once
+const QUEUE_CAPACITY: usize = 256;
+
+/// Work waiting to run outside interrupt context
+static QUEUE: OnceCell<ArrayQueue<(Work, usize)>> = OnceCell::uninit();
+/// Wakes the task that runs deferred work
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// Work that was dropped because the queue was full or not yet set up
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Set up the work queue; interrupt handlers can defer work from here on
+pub fn init() {
+	let _ = QUEUE.try_init_once(|| ArrayQueue::new(QUEUE_CAPACITY));
+}
+
+/// Run `work(arg)` soon with interrupts enabled, outside the caller's
+/// interrupt handler; returns false, dropping it, if the queue is full
+///
+/// Called from interrupt handlers, so it must not block or allocate.
+pub fn defer(work: Work, arg: usize) -> bool {
+	let queued = QUEUE.try_get().is_ok_and(|queue| queue.push((work, arg)).is_ok());
+	if queued {
+		WAKER.wake();
+	} else {
+		DROPPED.fetch_add(1, Ordering::Relaxed);
+	}
+	queued
+}
+
+/// Work dropped since boot because the queue was full
+pub fn dropped() -> u64 {
+	DROPPED.load(Ordering::Relaxed)
+}
+
+/// Run everything queued so far, returning how many items ran
+fn run_pending() -> usize {
+	let Ok(queue) = QUEUE.try_get() else {
+		return 0;
+	};
+	let mut ran = 0;
+	while let Ok((work, arg)) = queue.pop() {
+		work(arg);
+		ran += 1;
+	}
+	ran
+}
+
+/// The task that runs deferred work; spawn it with `Task::urgent` so the
+/// work runs before other ready tasks
+pub async fn run_deferred_work() {
+	init();
+	futures_util::future::poll_fn(|cx| {
+		run_pending();
+		WAKER.register(cx.waker());
+		// Work queued before the waker was registered would wait otherwise
+		if run_pending() > 0 {
+			cx.waker().wake_by_ref();
+		}
+		Poll::<()>::Pending
+	})
+	.await
+}
+
+/// Test that deferred work runs in order with its argument
+#[test_case]
+fn test_deferred_work() {
+	static SUM: AtomicU64 = AtomicU64::new(0);
+	fn add(arg: usize) {
+		SUM.store(SUM.load(Ordering::Relaxed) * 10 + arg as u64, Ordering::Relaxed);
+	}
+
+	init();
+	run_pending();
+	assert!(defer(add, 1));
+	assert!(defer(add, 2));
+	assert_eq!(run_pending(), 2);
+	assert_eq!(SUM.load(Ordering::Relaxed), 12);
+	assert_eq!(run_pending(), 0);
+}
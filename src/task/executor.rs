@@ -1,12 +1,44 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use super::stats::{self, TaskStats, WakeReason};
+use super::{JoinHandle, Priority, Task, TaskId};
+use crate::{klog, time::Instant};
+use alloc::{collections::BTreeMap, sync::Arc, task::Wake, vec::Vec};
+use core::cell::RefCell;
+use core::future::Future;
+use core::time::Duration;
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
 
-/// Simple task executor for cooperative multitasking
+/// Size of a run queue's first segment; each one after is twice as big
+const FIRST_SEGMENT: usize = 64;
+/// Segments a run queue can grow to, enough for millions of tasks
+const SEGMENTS: usize = 16;
+
+/// A poll longer than this is reported as a runaway task, since nothing
+/// else on its processor runs until it returns
+const RUNAWAY_POLL: Duration = Duration::from_millis(200);
+
+/// Polls in a row a task that stays ready gets before the rest of the
+/// pass, lower priorities included, runs without it
+const MAX_STREAK: u32 = 16;
+
+/// Tasks spawned and not yet finished
+static TASK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of tasks the executor is running
+pub fn task_count() -> usize {
+	TASK_COUNT.load(Ordering::Relaxed)
+}
+
+/// Simple task executor for cooperative multitasking; each processor runs
+/// its own, with its own run queue
 pub struct Executor {
+	/// The processor this executor runs on
+	cpu: usize,
 	tasks: BTreeMap<TaskId, Task>,
-	task_queue: Arc<ArrayQueue<TaskId>>,
+	/// Woken tasks, a queue per priority, highest first
+	queues: [Arc<RunQueue>; Priority::ALL.len()],
 	waker_cache: BTreeMap<TaskId, Waker>,
 }
 
@@ -14,8 +46,9 @@ impl Executor {
 	/// Create a new executor
 	pub fn new() -> Self {
 		Executor {
+			cpu: crate::smp::current_cpu(),
 			tasks: BTreeMap::new(),
-			task_queue: Arc::new(ArrayQueue::new(100)),
+			queues: Priority::ALL.map(|_| Arc::new(RunQueue::new())),
 			waker_cache: BTreeMap::new(),
 		}
 	}
@@ -23,45 +56,107 @@ impl Executor {
 	/// Spawn a new task
 	pub fn spawn(&mut self, task: Task) {
 		let task_id = task.id;
+		let queue = &self.queues[task.stats.priority as usize];
+		task.stats.cpu.store(self.cpu, Ordering::Relaxed);
+		task.stats.queued.store(true, Ordering::Release);
+		stats::register(task_id, task.stats.clone());
 		if self.tasks.insert(task.id, task).is_some() {
 			panic!("task with same ID already in tasks");
 		}
-		self.task_queue.push(task_id).expect("queue full");
+		// Room for every task to be queued at once, so no waker finds it full
+		queue.reserve(self.tasks.len());
+		queue.push(task_id);
+		TASK_COUNT.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// A handle tasks running here can spawn more tasks with
+	pub fn spawner(&self) -> Spawner {
+		Spawner { cpu: self.cpu }
 	}
 
 	/// Run all tasks to completion
 	pub fn run(&mut self) -> ! {
 		loop {
+			self.take_spawned();
 			self.run_ready_tasks();
 			self.sleep_if_idle();
 		}
 	}
 
+	/// Spawn the tasks other processors handed this one
+	fn take_spawned(&mut self) {
+		for spawned in crate::smp::take_spawned(self.cpu) {
+			let task = Task::with_priority(spawned.priority, spawned.future);
+			self.spawn(match spawned.name {
+				Some(name) => task.named(name),
+				None => task,
+			});
+		}
+	}
+
 	/// Run all ready tasks
 	fn run_ready_tasks(&mut self) {
 		// destructure `self` to avoid borrow checker errors
 		let Self {
+			cpu,
 			tasks,
-			task_queue,
+			queues,
 			waker_cache,
 		} = self;
 
-		while let Ok(task_id) = task_queue.pop() {
+		// Tasks held back until the pass ends, still marked queued
+		let mut deferred = Vec::new();
+
+		// Look at the highest queue again after every task, so a task woken
+		// by an interrupt never waits behind a run of background ones
+		while let Some(task_id) = queues.iter().find_map(|queue| queue.pop()) {
 			let task = match tasks.get_mut(&task_id) {
 				Some(task) => task,
 				None => continue, // task no longer exists
 			};
+			// A task that is always ready, such as one reading a fast
+			// stream, would otherwise keep the pass going forever
+			if task.streak >= MAX_STREAK {
+				deferred.push(task_id);
+				continue;
+			}
+			let queue = &queues[task.stats.priority as usize];
 			let waker = waker_cache
 				.entry(task_id)
-				.or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+				.or_insert_with(|| TaskWaker::new(task_id, *cpu, queue.clone(), task.stats.clone()));
+			// Wakes from here on queue the task again
+			task.stats.queued.store(false, Ordering::Release);
+			task.stats.running.store(true, Ordering::Relaxed);
 			let mut context = Context::from_waker(waker);
-			match task.poll(&mut context) {
+			let started = Instant::now();
+			start_poll(task_id, task.stats.clone(), started);
+			let poll = task.poll(&mut context);
+			let elapsed = started.elapsed();
+			finish_poll(elapsed);
+			task.stats.record_poll(elapsed);
+			task.stats.running.store(false, Ordering::Relaxed);
+			super::canary::check(task_id.0, task_name(&task.stats));
+			crate::trace_event!(Sched, "task {} polled for {} us, {}", task_id.0, elapsed.as_micros(), if poll.is_ready() { "done" } else { "pending" });
+			match poll {
 				Poll::Ready(()) => {
 					// task done -> remove it and its cached waker
 					tasks.remove(&task_id);
 					waker_cache.remove(&task_id);
+					stats::unregister(task_id);
+					TASK_COUNT.fetch_sub(1, Ordering::Relaxed);
+				}
+				Poll::Pending => {
+					// Woken again while it ran, so it is still ready
+					let ready = task.stats.queued.load(Ordering::Acquire);
+					task.streak = if ready { task.streak + 1 } else { 0 };
 				}
-				Poll::Pending => {}
+			}
+		}
+
+		for task_id in deferred {
+			if let Some(task) = tasks.get_mut(&task_id) {
+				task.streak = 0;
+				queues[task.stats.priority as usize].push(task_id);
 			}
 		}
 	}
@@ -71,7 +166,7 @@ impl Executor {
 		use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
 		interrupts::disable();
-		if self.task_queue.is_empty() {
+		if self.queues.iter().all(|queue| queue.is_empty()) && !crate::smp::has_spawned(self.cpu) {
 			enable_and_hlt();
 		} else {
 			interrupts::enable();
@@ -79,24 +174,225 @@ impl Executor {
 	}
 }
 
+/// The poll a processor's executor is in, for the watchdog
+struct CurrentPoll {
+	task_id: TaskId,
+	stats: Arc<TaskStats>,
+	started: Instant,
+	/// Set once the watchdog has warned about this poll
+	reported: bool,
+	/// Set while the task runs work that is expected to block, such as a
+	/// shell command
+	blocking: bool,
+}
+
+crate::per_cpu! {
+	/// What each processor's executor is polling
+	static CURRENT_POLL: RefCell<Option<CurrentPoll>> = RefCell::new(None);
+}
+
+/// Name a task in a runaway report
+fn task_name(stats: &TaskStats) -> &str {
+	stats.name.as_deref().unwrap_or("unnamed")
+}
+
+fn start_poll(task_id: TaskId, stats: Arc<TaskStats>, started: Instant) {
+	CURRENT_POLL.with(|current| {
+		*current.borrow_mut() = Some(CurrentPoll { task_id, stats, started, reported: false, blocking: false });
+	});
+}
+
+/// The task this processor is polling, unless that cannot be told without
+/// waiting, as in an interrupt that arrived while the executor was updating it
+pub(crate) fn current_task() -> Option<u64> {
+	CURRENT_POLL.with(|current| Some(current.try_borrow().ok()?.as_ref()?.task_id.0))
+}
+
+/// Call `f` with the task this processor is polling, its name, and how long
+/// the poll has run; for reports from the timer interrupt, so it gives up
+/// rather than wait for the executor
+pub(crate) fn with_current_poll<R>(f: impl FnOnce(u64, &str, Duration) -> R) -> Option<R> {
+	CURRENT_POLL.with(|current| {
+		let current = current.try_borrow().ok()?;
+		let poll = current.as_ref()?;
+		Some(f(poll.task_id.0, task_name(&poll.stats), poll.started.elapsed()))
+	})
+}
+
+/// Report the poll that just returned if it ran too long
+fn finish_poll(elapsed: Duration) {
+	let Some(poll) = CURRENT_POLL.with(|current| current.borrow_mut().take()) else {
+		return;
+	};
+	if poll.blocking || elapsed < RUNAWAY_POLL {
+		return;
+	}
+	let (id, name, ms) = (poll.task_id.0, task_name(&poll.stats), elapsed.as_millis());
+	if poll.reported {
+		klog!(Warn, "executor: task {} ({}) yielded after {} ms", id, name, ms);
+	} else {
+		klog!(Warn, "executor: task {} ({}) ran {} ms in one poll, holding up CPU {}", id, name, ms,
+			poll.stats.cpu.load(Ordering::Relaxed));
+	}
+}
+
+/// Warn once about a poll on this processor that has run too long
+///
+/// Called from the timer interrupt handler, so a task that never yields
+/// is still reported while it holds up everything else.
+pub(crate) fn watchdog() {
+	CURRENT_POLL.with(|current| {
+		// The executor only borrows with interrupts off, so this never fails
+		let Ok(mut current) = current.try_borrow_mut() else {
+			return;
+		};
+		let Some(poll) = current.as_mut() else {
+			return;
+		};
+		if poll.reported || poll.blocking || poll.started.elapsed() < RUNAWAY_POLL {
+			return;
+		}
+		poll.reported = true;
+		klog!(Warn, "executor: task {} ({}) has run {} ms without yielding; nothing else on CPU {} can run",
+			poll.task_id.0, task_name(&poll.stats), poll.started.elapsed().as_millis(), crate::smp::current_cpu());
+	});
+}
+
+/// Run `f`, which is expected to block, without the watchdog reporting the
+/// task running it
+pub fn blocking<R>(f: impl FnOnce() -> R) -> R {
+	let set = |blocking| {
+		CURRENT_POLL.with(|current| {
+			current.borrow_mut().as_mut().map(|poll| core::mem::replace(&mut poll.blocking, blocking))
+		})
+	};
+	let outer = set(true);
+	let result = f();
+	set(outer.unwrap_or(false));
+	result
+}
+
+/// A cloneable handle that spawns tasks on one processor's executor from
+/// anywhere, including tasks that executor is running
+///
+/// Spawned futures wait in the processor's inbox until the run loop drains
+/// it, so unlike `Executor::spawn` this needs no `&mut Executor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spawner {
+	cpu: usize,
+}
+
+impl Spawner {
+	/// A spawner for the executor of the processor this runs on
+	pub fn current() -> Spawner {
+		Spawner { cpu: crate::smp::current_cpu() }
+	}
+
+	/// Queue a task for the executor, returning a handle for its output
+	pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+	where
+		F: Future + Send + 'static,
+		F::Output: Send,
+	{
+		self.spawn_with_priority(Priority::Normal, future)
+	}
+
+	/// Queue a task for the executor's run queue for `priority`
+	pub fn spawn_with_priority<F>(&self, priority: Priority, future: F) -> JoinHandle<F::Output>
+	where
+		F: Future + Send + 'static,
+		F::Output: Send,
+	{
+		let (future, handle) = super::join::joinable(future);
+		crate::smp::spawn_on_with_priority(self.cpu, priority, future);
+		handle
+	}
+}
+
+/// Woken task IDs for one priority, in segments the executor adds as it
+/// gets more tasks
+///
+/// Wakers run in interrupt handlers, so they can neither allocate nor wait
+/// for a segment being added; the executor adds segments ahead of need.
+struct RunQueue {
+	segments: [OnceCell<ArrayQueue<TaskId>>; SEGMENTS],
+}
+
+impl RunQueue {
+	fn new() -> Self {
+		let queue = RunQueue { segments: [const { OnceCell::uninit() }; SEGMENTS] };
+		queue.reserve(1);
+		queue
+	}
+
+	/// The segments added so far
+	fn ready_segments(&self) -> impl Iterator<Item = &ArrayQueue<TaskId>> {
+		self.segments.iter().map_while(|segment| segment.try_get().ok())
+	}
+
+	/// Add segments until `tasks` IDs fit
+	fn reserve(&self, tasks: usize) {
+		let mut capacity = 0;
+		for (index, segment) in self.segments.iter().enumerate() {
+			if capacity >= tasks {
+				break;
+			}
+			capacity += segment.get_or_init(|| ArrayQueue::new(FIRST_SEGMENT << index)).capacity();
+		}
+	}
+
+	/// Queue a task ID in the first segment with room, returning false if
+	/// every segment is full
+	fn push(&self, task_id: TaskId) -> bool {
+		self.ready_segments().any(|segment| segment.push(task_id).is_ok())
+	}
+
+	fn pop(&self) -> Option<TaskId> {
+		self.ready_segments().find_map(|segment| segment.pop().ok())
+	}
+
+	fn is_empty(&self) -> bool {
+		self.ready_segments().all(ArrayQueue::is_empty)
+	}
+}
+
 /// A waker that wakes a task by pushing its ID to the task queue
 struct TaskWaker {
 	task_id: TaskId,
-	task_queue: Arc<ArrayQueue<TaskId>>,
+	/// The processor whose executor runs the task
+	cpu: usize,
+	task_queue: Arc<RunQueue>,
+	/// The task's stats, whose `queued` flag is set while it is in the
+	/// queue so waking it again does nothing
+	stats: Arc<TaskStats>,
 }
 
 impl TaskWaker {
 	/// Create a new TaskWaker
-	fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+	fn new(task_id: TaskId, cpu: usize, task_queue: Arc<RunQueue>, stats: Arc<TaskStats>) -> Waker {
 		Waker::from(Arc::new(TaskWaker {
 			task_id,
+			cpu,
 			task_queue,
+			stats,
 		}))
 	}
 
-	/// Wake the task by pushing it to the task queue
+	/// Wake the task by pushing it to the task queue, interrupting its
+	/// processor in case that one is halted
 	fn wake_task(&self) {
-		self.task_queue.push(self.task_id).expect("task_queue full");
+		self.stats.set_last_wake(WakeReason::current(self.cpu));
+		if self.stats.queued.swap(true, Ordering::AcqRel) {
+			return;
+		}
+		// Each task is queued at most once and the executor keeps room for
+		// all of them, so this only fails if the segments ran out
+		if !self.task_queue.push(self.task_id) {
+			self.stats.queued.store(false, Ordering::Release);
+			klog!(Err, "executor: run queue full, dropping a wakeup");
+			return;
+		}
+		crate::smp::wake(self.cpu);
 	}
 }
 
@@ -108,4 +404,55 @@ impl Wake for TaskWaker {
 	fn wake_by_ref(self: &Arc<Self>) {
 		self.wake_task();
 	}
-} 
\ No newline at end of file
+} 
+/// Test that a task that always yields cannot keep a pass of the run loop
+/// going or starve a lower priority
+#[test_case]
+fn test_fair_pass() {
+	use core::sync::atomic::AtomicBool;
+
+	static YIELDS: AtomicUsize = AtomicUsize::new(0);
+	static RAN: AtomicBool = AtomicBool::new(false);
+	let mut executor = Executor::new();
+	executor.spawn(Task::new(async {
+		loop {
+			YIELDS.fetch_add(1, Ordering::Relaxed);
+			super::yield_now().await;
+		}
+	}));
+	executor.spawn(Task::with_priority(Priority::Background, async { RAN.store(true, Ordering::Relaxed) }));
+	executor.run_ready_tasks();
+	assert_eq!(YIELDS.load(Ordering::Relaxed), MAX_STREAK as usize);
+	assert!(RAN.load(Ordering::Relaxed));
+	assert!(!executor.queues[Priority::Normal as usize].is_empty());
+}
+
+/// Test that a spawner hands tasks to its executor's run loop, that run
+/// queues grow, and that the watchdog flags long polls
+#[test_case]
+fn test_spawner() {
+	let spawner = Spawner::current();
+	assert_eq!(spawner, Executor::new().spawner());
+	crate::smp::take_spawned(spawner.cpu);
+	spawner.spawn(async {});
+	super::spawn(async {});
+	assert!(crate::smp::has_spawned(spawner.cpu));
+	spawner.spawn_with_priority(Priority::Background, async {});
+
+	let queue = RunQueue::new();
+	queue.reserve(FIRST_SEGMENT + 1);
+	assert!((0..FIRST_SEGMENT as u64 * 3).all(|id| queue.push(TaskId(id))));
+	assert!(!queue.push(TaskId(0)));
+	assert_eq!(queue.pop(), Some(TaskId(0)));
+	let stats = Arc::new(TaskStats::new(None, Priority::Normal));
+	start_poll(TaskId(u64::MAX), stats, Instant::now());
+	assert_eq!(blocking(|| CURRENT_POLL.with(|current| current.borrow().as_ref().map(|poll| poll.blocking))), Some(true));
+	watchdog();
+	assert!(CURRENT_POLL.with(|current| current.borrow().as_ref().is_some_and(|poll| !poll.reported && !poll.blocking)));
+	finish_poll(RUNAWAY_POLL);
+	assert!(CURRENT_POLL.with(|current| current.borrow().is_none()));
+
+	let spawned = crate::smp::take_spawned(spawner.cpu);
+	let priorities: alloc::vec::Vec<Priority> = spawned.iter().map(|spawned| spawned.priority).collect();
+	assert_eq!(priorities, [Priority::Normal, Priority::Normal, Priority::Background]);
+}
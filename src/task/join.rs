@@ -0,0 +1,185 @@
+use super::Task;
+use alloc::{sync::Arc, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use crate::sync::Mutex;
+
+/// Shared state behind a `CancellationToken`
+struct Cancellation {
+	cancelled: AtomicBool,
+	/// Tasks waiting in `cancelled()`
+	wakers: Mutex<Vec<Waker>>,
+}
+
+/// A flag that asks work to stop at its next await point; clones share it
+#[derive(Clone)]
+pub struct CancellationToken {
+	inner: Arc<Cancellation>,
+}
+
+impl Default for CancellationToken {
+	fn default() -> Self {
+		CancellationToken {
+			inner: Arc::new(Cancellation { cancelled: AtomicBool::new(false), wakers: Mutex::new(Vec::new()) }),
+		}
+	}
+}
+
+impl CancellationToken {
+	/// A token that has not been cancelled
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Ask everything holding the token to stop, waking any waiting on it
+	pub fn cancel(&self) {
+		self.inner.cancelled.store(true, Ordering::Release);
+		let wakers = core::mem::take(&mut *self.inner.wakers.lock());
+		for waker in wakers {
+			waker.wake();
+		}
+	}
+
+	/// Whether `cancel` has been called on this token or a clone of it
+	pub fn is_cancelled(&self) -> bool {
+		self.inner.cancelled.load(Ordering::Acquire)
+	}
+
+	/// A future that completes once the token is cancelled
+	pub fn cancelled(&self) -> Cancelled<'_> {
+		Cancelled { token: self }
+	}
+
+	/// Run `future` until it finishes or the token is cancelled, whichever
+	/// comes first; a cancelled future is dropped at the await point it
+	/// was waiting at
+	pub async fn run_until_cancelled<F: Future>(&self, future: F) -> Option<F::Output> {
+		let future = core::pin::pin!(future);
+		match futures_util::future::select(future, self.cancelled()).await {
+			futures_util::future::Either::Left((output, _)) => Some(output),
+			futures_util::future::Either::Right(_) => None,
+		}
+	}
+}
+
+/// The future `CancellationToken::cancelled` returns
+pub struct Cancelled<'a> {
+	token: &'a CancellationToken,
+}
+
+impl Future for Cancelled<'_> {
+	type Output = ();
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+		if self.token.is_cancelled() {
+			return Poll::Ready(());
+		}
+		let mut wakers = self.token.inner.wakers.lock();
+		if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+			wakers.push(cx.waker().clone());
+		}
+		drop(wakers);
+		// A cancel between the check and the push would wake no one
+		if self.token.is_cancelled() { Poll::Ready(()) } else { Poll::Pending }
+	}
+}
+
+/// The error a `JoinHandle` gives for a task that was aborted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Aborted;
+
+/// A task's output, passed from the task to its handle
+struct JoinState<T> {
+	output: Option<T>,
+	finished: bool,
+	/// The task waiting on the handle
+	waker: Option<Waker>,
+}
+
+/// A future for a spawned task's output, and a way to abort the task
+pub struct JoinHandle<T> {
+	state: Arc<Mutex<JoinState<T>>>,
+	cancel: CancellationToken,
+}
+
+impl<T> JoinHandle<T> {
+	/// Stop the task at its next await point; the handle then gives `Aborted`
+	pub fn abort(&self) {
+		self.cancel.cancel();
+	}
+
+	/// Whether the task has finished or been aborted
+	pub fn is_finished(&self) -> bool {
+		self.state.lock().finished
+	}
+
+	/// The token the task stops on, for aborting it from elsewhere
+	pub fn cancellation_token(&self) -> CancellationToken {
+		self.cancel.clone()
+	}
+}
+
+impl<T> Future for JoinHandle<T> {
+	type Output = Result<T, Aborted>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, Aborted>> {
+		let mut state = self.state.lock();
+		if !state.finished {
+			state.waker = Some(cx.waker().clone());
+			return Poll::Pending;
+		}
+		Poll::Ready(state.output.take().ok_or(Aborted))
+	}
+}
+
+/// Wrap `future` so its output goes to a handle and the handle can abort it
+pub(crate) fn joinable<F: Future + 'static>(future: F) -> (impl Future<Output = ()> + 'static, JoinHandle<F::Output>) {
+	let state = Arc::new(Mutex::new(JoinState { output: None, finished: false, waker: None }));
+	let cancel = CancellationToken::new();
+	let handle = JoinHandle { state: state.clone(), cancel: cancel.clone() };
+	let task = async move {
+		let output = cancel.run_until_cancelled(future).await;
+		let waker = {
+			let mut state = state.lock();
+			state.output = output;
+			state.finished = true;
+			state.waker.take()
+		};
+		if let Some(waker) = waker {
+			waker.wake();
+		}
+	};
+	(task, handle)
+}
+
+impl Task {
+	/// Create a task along with a handle for its output
+	pub fn joinable<F: Future + 'static>(future: F) -> (Task, JoinHandle<F::Output>) {
+		let (future, handle) = joinable(future);
+		(Task::new(future), handle)
+	}
+}
+
+/// Test that a handle gets its task's output, or `Aborted` once aborted
+#[test_case]
+fn test_join_handle() {
+	use alloc::boxed::Box;
+
+	let mut cx = Context::from_waker(Waker::noop());
+
+	let (mut task, mut handle) = Task::joinable(async { 42 });
+	assert_eq!(Pin::new(&mut handle).poll(&mut cx), Poll::Pending);
+	assert_eq!(task.poll(&mut cx), Poll::Ready(()));
+	assert!(handle.is_finished());
+	assert_eq!(Pin::new(&mut handle).poll(&mut cx), Poll::Ready(Ok(42)));
+
+	let (task, mut handle) = joinable(futures_util::future::pending::<()>());
+	let mut task: Pin<Box<dyn Future<Output = ()>>> = Box::pin(task);
+	assert_eq!(task.as_mut().poll(&mut cx), Poll::Pending);
+	handle.abort();
+	assert_eq!(task.as_mut().poll(&mut cx), Poll::Ready(()));
+	assert_eq!(Pin::new(&mut handle).poll(&mut cx), Poll::Ready(Err(Aborted)));
+	assert!(handle.cancellation_token().is_cancelled());
+}
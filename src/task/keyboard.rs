@@ -1,22 +1,246 @@
 use conquer_once::spin::OnceCell;
 use crossbeam_queue::ArrayQueue;
-use core::{pin::Pin, task::{Poll, Context}};
+use core::{cell::Cell, pin::Pin, task::{Poll, Context}};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use alloc::collections::VecDeque;
 use futures_util::stream::{Stream, StreamExt};
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-use crate::{println, print};
+use futures_util::task::AtomicWaker;
+use pc_keyboard::{DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
+use crate::{keymap, klog, print, vga_buffer};
+use crate::vga_buffer::CONSOLE_COUNT;
+use crate::sync::Mutex;
 
 /// Keyboard scancode queue
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+/// Wakes the task waiting on the scancode stream
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Scancode set 1 codes watched at interrupt time for Ctrl-C
+const SCANCODE_CTRL: u8 = 0x1d;
+const SCANCODE_C: u8 = 0x2e;
+const SCANCODE_RELEASE: u8 = 0x80;
+/// What the keyboard sends for Alt+SysRq, which enters the debugger
+const SCANCODE_SYSRQ: u8 = 0x54;
+
+/// Modifier keys held down with a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+	pub const NONE: Modifiers = Modifiers(0);
+	pub const SHIFT: Modifiers = Modifiers(1 << 0);
+	pub const ALT: Modifiers = Modifiers(1 << 1);
+	pub const CTRL: Modifiers = Modifiers(1 << 2);
+
+	/// Whether every modifier in `other` is held
+	pub fn contains(self, other: Modifiers) -> bool {
+		self.0 & other.0 == other.0
+	}
+
+	pub fn shift(self) -> bool {
+		self.contains(Modifiers::SHIFT)
+	}
+
+	pub fn alt(self) -> bool {
+		self.contains(Modifiers::ALT)
+	}
+
+	pub fn ctrl(self) -> bool {
+		self.contains(Modifiers::CTRL)
+	}
+
+	/// Hold or release the modifiers in `other`
+	fn set(&mut self, other: Modifiers, held: bool) {
+		if held {
+			self.0 |= other.0;
+		} else {
+			self.0 &= !other.0;
+		}
+	}
+}
+
+impl core::ops::BitOr for Modifiers {
+	type Output = Modifiers;
+
+	fn bitor(self, other: Modifiers) -> Modifiers {
+		Modifiers(self.0 | other.0)
+	}
+}
+
+/// A decoded key with the modifiers held when it was pressed
+///
+/// Ctrl+letter still arrives as a control character (Ctrl-S as '\x13'),
+/// so code that only cares about characters can ignore the modifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+	pub key: DecodedKey,
+	pub modifiers: Modifiers,
+}
+
+impl KeyPress {
+	/// A key pressed with no modifiers
+	pub fn new(key: DecodedKey) -> KeyPress {
+		KeyPress { key, modifiers: Modifiers::NONE }
+	}
+
+	pub fn with(key: DecodedKey, modifiers: Modifiers) -> KeyPress {
+		KeyPress { key, modifiers }
+	}
+}
+
+/// The modifier a key code is, if any
+fn modifier_of(code: KeyCode) -> Option<Modifiers> {
+	match code {
+		KeyCode::ShiftLeft | KeyCode::ShiftRight => Some(Modifiers::SHIFT),
+		KeyCode::AltLeft | KeyCode::AltRight => Some(Modifiers::ALT),
+		KeyCode::ControlLeft | KeyCode::ControlRight => Some(Modifiers::CTRL),
+		_ => None,
+	}
+}
+
+/// Console whose shell receives keyboard input
+static FOCUS: AtomicUsize = AtomicUsize::new(0);
+/// Keys typed on each console, waiting for its shell
+static KEYS: Mutex<[VecDeque<KeyPress>; CONSOLE_COUNT]> = Mutex::new([const { VecDeque::new() }; CONSOLE_COUNT]);
+/// Wakes the shell task of each console when it has keys to handle
+static KEY_WAKERS: [AtomicWaker; CONSOLE_COUNT] = [const { AtomicWaker::new() }; CONSOLE_COUNT];
+
+/// Function keys that switch consoles together with Alt
+const CONSOLE_KEYS: [KeyCode; CONSOLE_COUNT] = [KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4];
+
+/// Lines scrolled back or forward by Shift+PgUp/PgDn: half a screen
+fn scroll_step() -> isize {
+	(vga_buffer::screen_size().0 / 2) as isize
+}
+
+/// Whether a Ctrl key is held, tracked from raw scancodes
+static CTRL_HELD: AtomicBool = AtomicBool::new(false);
+/// Set when Ctrl-C is pressed, even while the shell is busy running a command
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+crate::per_cpu! {
+	/// Set while a processor runs a background job, which Ctrl-C is not for
+	static IN_BACKGROUND: Cell<bool> = Cell::new(false);
+}
+
+/// Whether Ctrl-C has been pressed and not yet acknowledged
+///
+/// Long-running loops poll this to stop early. Background jobs never see
+/// it: it stays pending for the foreground.
+pub fn interrupt_requested() -> bool {
+	!IN_BACKGROUND.with(Cell::get) && INTERRUPT.load(Ordering::Relaxed)
+}
+
+/// Run `f` as a background job, which neither sees nor acknowledges Ctrl-C
+pub fn in_background<R>(f: impl FnOnce() -> R) -> R {
+	let outer = IN_BACKGROUND.with(|background| background.replace(true));
+	let result = f();
+	IN_BACKGROUND.with(|background| background.set(outer));
+	result
+}
+
+/// Note a Ctrl-C that arrived some other way than the keyboard
+pub(super) fn request_interrupt() {
+	INTERRUPT.store(true, Ordering::Relaxed);
+}
+
+/// Acknowledge a pending Ctrl-C, returning whether there was one
+pub fn clear_interrupt() -> bool {
+	!IN_BACKGROUND.with(Cell::get) && INTERRUPT.swap(false, Ordering::Relaxed)
+}
+
+/// Watch for Ctrl-C before the scancode is queued for decoding
+fn track_interrupt(scancode: u8) {
+	match scancode {
+		SCANCODE_CTRL => CTRL_HELD.store(true, Ordering::Relaxed),
+		code if code == SCANCODE_CTRL | SCANCODE_RELEASE => CTRL_HELD.store(false, Ordering::Relaxed),
+		SCANCODE_C if CTRL_HELD.load(Ordering::Relaxed) => INTERRUPT.store(true, Ordering::Relaxed),
+		_ => {}
+	}
+}
+
+/// Take the next raw scancode, bypassing the shell's decoder
+///
+/// For full-screen programs that poll the keyboard while they run.
+pub fn read_scancode() -> Option<u8> {
+	SCANCODE_QUEUE.try_get().ok().and_then(|queue| queue.pop().ok())
+}
 
 /// Called by the keyboard interrupt handler
 /// Must not block or allocate.
 pub(crate) fn add_scancode(scancode: u8) {
+	if crate::keyboard::command_reply(scancode) {
+		return;
+	}
+	if scancode == SCANCODE_SYSRQ && crate::kdb::enabled() {
+		// Straight from the interrupt, however stuck the tasks are
+		crate::kdb::enter(crate::kdb::Entry::Hotkey, None);
+		return;
+	}
+	track_interrupt(scancode);
+	crate::input::keyboard_scancode(scancode);
 	if let Ok(queue) = SCANCODE_QUEUE.try_get() {
 		if let Err(_) = queue.push(scancode) {
-			println!("WARNING: scancode queue full; dropping keyboard input");
+			klog!(Warn, "scancode queue full; dropping keyboard input");
+		} else {
+			WAKER.wake();
 		}
 	} else {
-		println!("WARNING: scancode queue uninitialized");
+		klog!(Warn, "scancode queue uninitialized");
+	}
+}
+
+/// The console that has keyboard focus
+pub fn focused_console() -> usize {
+	FOCUS.load(Ordering::Relaxed)
+}
+
+/// Give another console the keyboard and the screen
+pub fn switch_console(console: usize) {
+	if vga_buffer::show_console(console) {
+		FOCUS.store(console, Ordering::Relaxed);
+		super::status::update();
+	}
+}
+
+/// Queue a key for the shell of the focused console
+pub(super) fn deliver_key(key: KeyPress) {
+	let console = focused_console();
+	KEYS.lock()[console].push_back(key);
+	KEY_WAKERS[console].wake();
+}
+
+/// Take the next key typed on `console`, if there is one
+pub fn take_key(console: usize) -> Option<KeyPress> {
+	KEYS.lock()[console].pop_front()
+}
+
+/// Keys typed while one console had focus
+pub struct KeyStream {
+	console: usize,
+}
+
+impl KeyStream {
+	pub fn new(console: usize) -> Self {
+		KeyStream { console }
+	}
+}
+
+impl Stream for KeyStream {
+	type Item = KeyPress;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyPress>> {
+		if let Some(key) = KEYS.lock()[self.console].pop_front() {
+			return Poll::Ready(Some(key));
+		}
+		KEY_WAKERS[self.console].register(cx.waker());
+		match KEYS.lock()[self.console].pop_front() {
+			Some(key) => {
+				KEY_WAKERS[self.console].take();
+				Poll::Ready(Some(key))
+			}
+			None => Poll::Pending,
+		}
 	}
 }
 
@@ -36,7 +260,7 @@ impl ScancodeStream {
 impl Stream for ScancodeStream {
 	type Item = u8;
 
-	fn poll_next(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<u8>> {
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
 		let queue = SCANCODE_QUEUE.try_get().expect("scancode queue not initialized");
 
 		// fast path
@@ -44,39 +268,101 @@ impl Stream for ScancodeStream {
 			return Poll::Ready(Some(scancode));
 		}
 
-		// TODO: register waker for keyboard interrupt
-		Poll::Pending
+		// Register before re-checking so a scancode arriving in between still wakes us
+		WAKER.register(cx.waker());
+		match queue.pop() {
+			Ok(scancode) => {
+				WAKER.take();
+				Poll::Ready(Some(scancode))
+			}
+			Err(_) => Poll::Pending,
+		}
 	}
 }
 
-/// Async task for processing keypresses through the shell
-pub async fn process_shell_input() {
-	let mut scancodes = ScancodeStream::new();
-	let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1,
-		HandleControl::Ignore);
+/// The shell's scancode decoder, with the modifier and lock keys it has
+/// seen held down
+struct ShellDecoder {
+	keyboard: Keyboard<keymap::Configured, ScancodeSet1>,
+	modifiers: Modifiers,
+	/// Lock keys held down, so their repeats do not toggle them again
+	locks_held: u8,
+}
 
-	while let Some(scancode) = scancodes.next().await {
-		if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-			if let Some(key) = keyboard.process_keyevent(key_event) {
-				match key {
-					DecodedKey::Unicode(character) => {
-						// Send character to shell for processing
-						crate::shell::SHELL.lock().process_char(character);
-					}
-					DecodedKey::RawKey(_key) => {
-						// For now, ignore raw keys like function keys
-						// Could be extended to handle special keys
-					}
-				}
+/// Shared by the keyboard task and readers that decode while it cannot run
+static DECODER: Mutex<Option<ShellDecoder>> = Mutex::new(None);
+
+impl ShellDecoder {
+	fn new() -> Self {
+		ShellDecoder { keyboard: keymap::decoder(), modifiers: Modifiers::NONE, locks_held: 0 }
+	}
+
+	/// Decode a scancode, acting on console keys and queuing anything typed
+	fn add_scancode(&mut self, scancode: u8) {
+		let Ok(Some(key_event)) = self.keyboard.add_byte(scancode) else {
+			return;
+		};
+		let down = key_event.state == KeyState::Down;
+		if let Some(modifier) = modifier_of(key_event.code) {
+			self.modifiers.set(modifier, down);
+		}
+		if let Some(lock) = crate::keyboard::lock_key(key_event.code) {
+			if down && self.locks_held & lock == 0 {
+				crate::keyboard::toggle_lock(lock);
+			}
+			self.locks_held = if down { self.locks_held | lock } else { self.locks_held & !lock };
+		}
+		match (key_event.code, key_event.state) {
+			// Alt+F1..F4 switch virtual consoles
+			(code, KeyState::Down) if self.modifiers.alt() && CONSOLE_KEYS.contains(&code) => {
+				switch_console(CONSOLE_KEYS.iter().position(|&key| key == code).unwrap_or(0));
+				return;
+			}
+			// Shift+PgUp/PgDn scroll the console by half a screen
+			(KeyCode::PageUp, KeyState::Down) if self.modifiers.shift() => {
+				vga_buffer::scroll_view(scroll_step());
+				return;
 			}
+			(KeyCode::PageDown, KeyState::Down) if self.modifiers.shift() => {
+				vga_buffer::scroll_view(-scroll_step());
+				return;
+			}
+			_ => {}
+		}
+		if let Some(key) = self.keyboard.process_keyevent(key_event) {
+			// The console's shell hands it on to any open editor
+			deliver_key(KeyPress::with(key, self.modifiers));
 		}
 	}
 }
 
+/// Decode one scancode with the shell's decoder
+fn decode(scancode: u8) {
+	DECODER.lock().get_or_insert_with(ShellDecoder::new).add_scancode(scancode);
+}
+
+/// Decode every scancode still queued, as the keyboard task would
+///
+/// For code that waits for keys while the task that decodes them cannot
+/// run, such as a command reading its standard input.
+pub fn decode_pending() {
+	while let Some(scancode) = read_scancode() {
+		decode(scancode);
+	}
+}
+
+/// Async task decoding keypresses for the shell of the focused console
+pub async fn process_shell_input() {
+	let mut scancodes = ScancodeStream::new();
+	while let Some(scancode) = scancodes.next().await {
+		decode(scancode);
+	}
+}
+
 /// Async task for printing keypresses (legacy - kept for compatibility)
 pub async fn print_keypresses() {
 	let mut scancodes = ScancodeStream::new();
-	let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1,
+	let mut keyboard = Keyboard::new(keymap::Configured, ScancodeSet1,
 		HandleControl::Ignore);
 
 	while let Some(scancode) = scancodes.next().await {
@@ -89,4 +375,27 @@ pub async fn print_keypresses() {
 			}
 		}
 	}
-} 
\ No newline at end of file
+}
+
+/// Test that Ctrl-C is noticed from raw scancodes
+#[test_case]
+fn test_ctrl_c_tracking() {
+	clear_interrupt();
+	track_interrupt(SCANCODE_C);
+	assert!(!interrupt_requested());
+	track_interrupt(SCANCODE_CTRL);
+	track_interrupt(SCANCODE_C);
+	track_interrupt(SCANCODE_CTRL | SCANCODE_RELEASE);
+	assert!(clear_interrupt());
+	assert!(!interrupt_requested());
+	track_interrupt(SCANCODE_C);
+	assert!(!interrupt_requested());
+}
+
+/// Test that Ctrl-C stays pending for the foreground while a background job runs
+#[test_case]
+fn test_ctrl_c_skips_background() {
+	request_interrupt();
+	assert!(in_background(|| !interrupt_requested() && !clear_interrupt()));
+	assert!(clear_interrupt());
+}
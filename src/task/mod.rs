@@ -1,10 +1,65 @@
 use core::{future::Future, pin::Pin, task::{Context, Poll}};
-use alloc::boxed::Box;
+use alloc::{boxed::Box, string::String, sync::Arc};
 
+pub mod blocking;
+pub mod canary;
+pub mod deferred;
 pub mod executor;
+pub mod join;
 pub mod keyboard;
+pub mod serial;
+pub mod stats;
+pub mod status;
+pub mod sync;
+pub mod timer;
 
-pub use executor::Executor;
+pub use blocking::spawn_blocking;
+pub use executor::{blocking, task_count, Executor, Spawner};
+pub use join::{Aborted, CancellationToken, JoinHandle};
+pub use stats::{tasks, TaskInfo, TaskState, WakeReason};
+
+/// Spawn a task on this processor's executor, from inside a running task
+/// or anywhere else
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+	F: Future + Send + 'static,
+	F::Output: Send,
+{
+	Spawner::current().spawn(future)
+}
+
+/// Spawn a task on this processor's executor in the run queue for `priority`
+pub fn spawn_with_priority<F>(priority: Priority, future: F) -> JoinHandle<F::Output>
+where
+	F: Future + Send + 'static,
+	F::Output: Send,
+{
+	Spawner::current().spawn_with_priority(priority, future)
+}
+
+/// Let every other ready task run before carrying on
+pub fn yield_now() -> YieldNow {
+	YieldNow { yielded: false }
+}
+
+/// The future `yield_now` returns
+pub struct YieldNow {
+	yielded: bool,
+}
+
+impl Future for YieldNow {
+	type Output = ();
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+		if self.yielded {
+			return Poll::Ready(());
+		}
+		self.yielded = true;
+		// Back of the run queue, behind whatever else is ready
+		cx.waker().wake_by_ref();
+		Poll::Pending
+	}
+}
 
 /// Unique task identifier
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -18,21 +73,63 @@ impl TaskId {
 	}
 }
 
+/// Which run queue a task waits in; the executor always runs a ready task
+/// from the highest nonempty one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+	/// Follow-up work for interrupts, such as deferred work and decoding
+	/// keyboard and serial input
+	Interrupt,
+	Normal,
+	/// Work that can wait for everything else, such as background jobs
+	Background,
+}
+
+impl Priority {
+	/// Every priority, highest first
+	pub const ALL: [Priority; 3] = [Priority::Interrupt, Priority::Normal, Priority::Background];
+}
+
 /// A cooperative task with a unique ID
 pub struct Task {
 	pub(crate) id: TaskId,
 	pub(crate) future: Pin<Box<dyn Future<Output = ()>>>,
+	/// Its name, priority, and what it has done, shared with its waker and
+	/// the task table
+	pub(crate) stats: Arc<stats::TaskStats>,
+	/// Polls in a row the task was ready again right after, for the
+	/// executor to make it wait its turn
+	pub(crate) streak: u32,
 }
 
 impl Task {
 	/// Create a new Task with the given future
 	pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+		Task::with_priority(Priority::Normal, future)
+	}
+
+	/// Create a task that waits in the run queue for `priority`
+	pub fn with_priority(priority: Priority, future: impl Future<Output = ()> + 'static) -> Task {
 		Task {
 			id: TaskId::new(),
 			future: Box::pin(future),
+			stats: Arc::new(stats::TaskStats::new(None, priority)),
+			streak: 0,
 		}
 	}
 
+	/// Give the task a name for `tasks` to show
+	pub fn named(self, name: impl Into<String>) -> Task {
+		let priority = self.stats.priority;
+		Task { stats: Arc::new(stats::TaskStats::new(Some(name.into()), priority)), ..self }
+	}
+
+	/// Create a task that runs before ordinary ones, such as deferred
+	/// interrupt work
+	pub fn urgent(future: impl Future<Output = ()> + 'static) -> Task {
+		Task::with_priority(Priority::Interrupt, future)
+	}
+
 	/// Poll the task and return whether it's ready
 	pub(crate) fn poll(&mut self, context: &mut Context) -> Poll<()> {
 		self.future.as_mut().poll(context)
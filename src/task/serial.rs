@@ -0,0 +1,315 @@
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use core::{pin::Pin, task::{Poll, Context}};
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use pc_keyboard::{DecodedKey, KeyCode};
+use crate::{klog, serial};
+use super::keyboard::{self, KeyPress, Modifiers};
+use crate::sync::Mutex;
+
+/// Bytes received on the serial port
+static SERIAL_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+/// Wakes the task waiting on the serial stream
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Control characters with a meaning of their own on the serial line
+const CTRL_C: u8 = 0x03;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+/// Bytes decoded before letting other tasks run, so pasted or piped input
+/// does not hold up everything else
+const BURST: usize = 64;
+
+/// Called by the serial interrupt handler
+/// Must not block or allocate.
+pub(crate) fn add_byte(byte: u8) {
+	// Ctrl-C stops a running command even while the shell is busy, unless
+	// it is part of a binary transfer
+	if byte == CTRL_C && !serial::raw_mode() {
+		keyboard::request_interrupt();
+	}
+	if let Ok(queue) = SERIAL_QUEUE.try_get() {
+		if queue.push(byte).is_err() {
+			klog!(Warn, "serial queue full; dropping serial input");
+		} else {
+			WAKER.wake();
+		}
+	} else {
+		klog!(Warn, "serial queue uninitialized");
+	}
+}
+
+/// Take the next received byte, bypassing the shell's decoder
+///
+/// For programs that talk over the serial line while they run.
+pub fn read_byte() -> Option<u8> {
+	SERIAL_QUEUE.try_get().ok().and_then(|queue| queue.pop().ok())
+}
+
+/// Byte stream from the serial port for async processing
+pub struct SerialStream {
+	_private: (),
+}
+
+impl SerialStream {
+	fn new() -> Self {
+		SERIAL_QUEUE.try_init_once(|| ArrayQueue::new(256))
+			.expect("SerialStream::new should only be called once");
+		SerialStream { _private: () }
+	}
+}
+
+impl Stream for SerialStream {
+	type Item = u8;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+		let queue = SERIAL_QUEUE.try_get().expect("serial queue not initialized");
+
+		if let Ok(byte) = queue.pop() {
+			return Poll::Ready(Some(byte));
+		}
+
+		// Register before re-checking so a byte arriving in between still wakes us
+		WAKER.register(cx.waker());
+		match queue.pop() {
+			Ok(byte) => {
+				WAKER.take();
+				Poll::Ready(Some(byte))
+			}
+			Err(_) => Poll::Pending,
+		}
+	}
+}
+
+/// Where the decoder is within an escape sequence or UTF-8 character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+	Ground,
+	/// After `ESC`
+	Escape,
+	/// After `ESC [`, with the number collected so far
+	Csi(u16),
+	/// After `ESC O`
+	Ss3,
+	/// Inside a UTF-8 character, with its bits so far and the bytes to come
+	Utf8(u32, u8),
+}
+
+/// Turns what a terminal sends for each keypress into keys, as the keyboard
+/// decoder does for scancodes
+struct TerminalDecoder {
+	state: State,
+	/// Whether the last byte was a carriage return, so a line feed after it
+	/// is the same Enter
+	after_cr: bool,
+	/// The number before the `;` of a sequence with modifiers, such as the
+	/// `1` of `ESC [ 1 ; 5 C`
+	csi_key: Option<u16>,
+}
+
+/// Shared by the serial task and readers that decode while it cannot run
+static DECODER: Mutex<TerminalDecoder> = Mutex::new(TerminalDecoder::new());
+
+impl TerminalDecoder {
+	const fn new() -> Self {
+		TerminalDecoder { state: State::Ground, after_cr: false, csi_key: None }
+	}
+
+	/// The modifiers a sequence's number after the `;` encodes, one more
+	/// than Shift 1, Alt 2, and Ctrl 4 added up
+	fn csi_modifiers(&self, n: u16) -> Modifiers {
+		if self.csi_key.is_none() {
+			return Modifiers::NONE;
+		}
+		let bits = n.saturating_sub(1);
+		[(1, Modifiers::SHIFT), (2, Modifiers::ALT), (4, Modifiers::CTRL)]
+			.into_iter()
+			.filter(|&(bit, _)| bits & bit != 0)
+			.fold(Modifiers::NONE, |held, (_, modifier)| held | modifier)
+	}
+
+	/// Decode one byte, passing any finished keys to `key`
+	fn add_byte(&mut self, byte: u8, mut key: impl FnMut(KeyPress)) {
+		let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+		let mut press = |decoded| key(KeyPress::new(decoded));
+		self.state = match (self.state, byte) {
+			(State::Ground, b'\r') => {
+				press(DecodedKey::Unicode('\n'));
+				State::Ground
+			}
+			(State::Ground, b'\n') if after_cr => State::Ground,
+			(State::Ground, DEL) => {
+				press(DecodedKey::Unicode('\u{8}'));
+				State::Ground
+			}
+			(State::Ground, ESC) => State::Escape,
+			(State::Ground, 0x00..=0x7f) => {
+				press(DecodedKey::Unicode(byte as char));
+				State::Ground
+			}
+			(State::Ground, 0xc0..=0xdf) => State::Utf8((byte & 0x1f) as u32, 1),
+			(State::Ground, 0xe0..=0xef) => State::Utf8((byte & 0x0f) as u32, 2),
+			(State::Ground, 0xf0..=0xf7) => State::Utf8((byte & 0x07) as u32, 3),
+			(State::Ground, _) => State::Ground,
+			(State::Utf8(bits, left), 0x80..=0xbf) => {
+				let bits = bits << 6 | (byte & 0x3f) as u32;
+				if left > 1 {
+					State::Utf8(bits, left - 1)
+				} else {
+					if let Some(c) = char::from_u32(bits) {
+						press(DecodedKey::Unicode(c));
+					}
+					State::Ground
+				}
+			}
+			// A broken character; start over with this byte
+			(State::Utf8(..), _) => {
+				self.state = State::Ground;
+				return self.add_byte(byte, key);
+			}
+			(State::Escape, b'[') => {
+				self.csi_key = None;
+				State::Csi(0)
+			}
+			(State::Escape, b'O') => State::Ss3,
+			// A lone Escape, or Alt with a key, which the shell treats alike
+			(State::Escape, _) => {
+				press(DecodedKey::Unicode(ESC as char));
+				self.state = State::Ground;
+				return self.add_byte(byte, key);
+			}
+			(State::Csi(n), b'0'..=b'9') => State::Csi(n.saturating_mul(10).saturating_add((byte - b'0') as u16)),
+			// Modifiers follow the key number, as in `1;5`
+			(State::Csi(n), b';') => {
+				self.csi_key = Some(n);
+				State::Csi(0)
+			}
+			(State::Csi(n), b'~') => {
+				if let Some(code) = tilde_key(self.csi_key.unwrap_or(n)) {
+					key(KeyPress::with(DecodedKey::RawKey(code), self.csi_modifiers(n)));
+				}
+				State::Ground
+			}
+			(State::Csi(_), 0x20..=0x3f) => self.state,
+			(State::Csi(n), _) => {
+				if let Some(code) = final_key(byte) {
+					key(KeyPress::with(DecodedKey::RawKey(code), self.csi_modifiers(n)));
+				}
+				State::Ground
+			}
+			(State::Ss3, _) => {
+				if let Some(code) = final_key(byte) {
+					press(DecodedKey::RawKey(code));
+				}
+				State::Ground
+			}
+		};
+	}
+}
+
+/// The key of a `ESC [ n ~` sequence
+fn tilde_key(n: u16) -> Option<KeyCode> {
+	Some(match n {
+		1 | 7 => KeyCode::Home,
+		2 => KeyCode::Insert,
+		3 => KeyCode::Delete,
+		4 | 8 => KeyCode::End,
+		5 => KeyCode::PageUp,
+		6 => KeyCode::PageDown,
+		11 => KeyCode::F1,
+		12 => KeyCode::F2,
+		13 => KeyCode::F3,
+		14 => KeyCode::F4,
+		15 => KeyCode::F5,
+		17 => KeyCode::F6,
+		18 => KeyCode::F7,
+		19 => KeyCode::F8,
+		20 => KeyCode::F9,
+		21 => KeyCode::F10,
+		23 => KeyCode::F11,
+		24 => KeyCode::F12,
+		_ => return None,
+	})
+}
+
+/// The key of a `ESC [` or `ESC O` sequence ending in a letter
+fn final_key(byte: u8) -> Option<KeyCode> {
+	Some(match byte {
+		b'A' => KeyCode::ArrowUp,
+		b'B' => KeyCode::ArrowDown,
+		b'C' => KeyCode::ArrowRight,
+		b'D' => KeyCode::ArrowLeft,
+		b'H' => KeyCode::Home,
+		b'F' => KeyCode::End,
+		b'P' => KeyCode::F1,
+		b'Q' => KeyCode::F2,
+		b'R' => KeyCode::F3,
+		b'S' => KeyCode::F4,
+		_ => return None,
+	})
+}
+
+/// Decode every byte still queued, as the serial task would
+///
+/// For code that waits for keys while the task that decodes them cannot
+/// run, such as a command reading its standard input.
+pub fn decode_pending() {
+	while let Some(byte) = read_byte() {
+		DECODER.lock().add_byte(byte, keyboard::deliver_key);
+	}
+}
+
+/// Async task feeding what is typed on the serial port to the shell of the
+/// focused console, just like the keyboard
+pub async fn process_serial_input() {
+	let mut bytes = SerialStream::new();
+
+	let mut burst = 0;
+	while let Some(byte) = bytes.next().await {
+		DECODER.lock().add_byte(byte, keyboard::deliver_key);
+		burst += 1;
+		if burst == BURST {
+			burst = 0;
+			super::yield_now().await;
+		}
+	}
+}
+
+/// Test decoding what a terminal sends for keys, with their modifiers
+#[test_case]
+fn test_terminal_decoder() {
+	use alloc::vec::Vec;
+
+	let mut decoder = TerminalDecoder::new();
+	let mut presses = Vec::new();
+	for &byte in b"ls\r\n\x7f\x1b[A\x1b[3~\x1bOP\x1b[1;5C\xc3\xa9\x1bx" {
+		decoder.add_byte(byte, |press| presses.push(press));
+	}
+	let keys: Vec<DecodedKey> = presses.iter().map(|press| press.key).collect();
+	assert_eq!(keys, [
+		DecodedKey::Unicode('l'),
+		DecodedKey::Unicode('s'),
+		DecodedKey::Unicode('\n'),
+		DecodedKey::Unicode('\u{8}'),
+		DecodedKey::RawKey(KeyCode::ArrowUp),
+		DecodedKey::RawKey(KeyCode::Delete),
+		DecodedKey::RawKey(KeyCode::F1),
+		DecodedKey::RawKey(KeyCode::ArrowRight),
+		DecodedKey::Unicode('é'),
+		DecodedKey::Unicode('\x1b'),
+		DecodedKey::Unicode('x'),
+	]);
+	assert!(presses.iter().enumerate().all(|(index, press)| (press.modifiers == Modifiers::CTRL) == (index == 7)));
+
+	presses.clear();
+	for &byte in b"\x1b[3;2~\x1b[1;7D\x1b[5C" {
+		decoder.add_byte(byte, |press| presses.push(press));
+	}
+	assert_eq!(presses, [
+		KeyPress::with(DecodedKey::RawKey(KeyCode::Delete), Modifiers::SHIFT),
+		KeyPress::with(DecodedKey::RawKey(KeyCode::ArrowLeft), Modifiers::CTRL | Modifiers::ALT),
+		KeyPress::new(DecodedKey::RawKey(KeyCode::ArrowRight)),
+	]);
+}
@@ -0,0 +1,189 @@
+use super::{Priority, TaskId};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use core::time::Duration;
+use crate::sync::Mutex;
+
+/// What last woke a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeReason {
+	/// It has only run because it was spawned
+	Spawned,
+	/// An interrupt handler woke it
+	Interrupt,
+	/// Another task on its processor woke it
+	Task,
+	/// Something on another processor woke it
+	Remote,
+}
+
+impl WakeReason {
+	const ALL: [WakeReason; 4] = [WakeReason::Spawned, WakeReason::Interrupt, WakeReason::Task, WakeReason::Remote];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			WakeReason::Spawned => "spawn",
+			WakeReason::Interrupt => "irq",
+			WakeReason::Task => "task",
+			WakeReason::Remote => "remote",
+		}
+	}
+
+	/// Why a task that runs on processor `cpu` is being woken now
+	pub(crate) fn current(cpu: usize) -> WakeReason {
+		if crate::interrupts::in_interrupt() {
+			WakeReason::Interrupt
+		} else if crate::smp::current_cpu() != cpu {
+			WakeReason::Remote
+		} else {
+			WakeReason::Task
+		}
+	}
+}
+
+/// Where a task is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+	/// Being polled right now
+	Running,
+	/// Woken and waiting in its run queue
+	Ready,
+	/// Waiting for something to wake it
+	Waiting,
+}
+
+impl TaskState {
+	pub fn name(self) -> &'static str {
+		match self {
+			TaskState::Running => "run",
+			TaskState::Ready => "ready",
+			TaskState::Waiting => "wait",
+		}
+	}
+}
+
+/// What the executor and a task's waker record about it, shared with the
+/// task table
+pub(crate) struct TaskStats {
+	pub(crate) name: Option<String>,
+	pub(crate) priority: Priority,
+	pub(crate) cpu: AtomicUsize,
+	/// Set while the task waits in its run queue, so waking it again does
+	/// nothing
+	pub(crate) queued: AtomicBool,
+	pub(crate) running: AtomicBool,
+	pub(crate) polls: AtomicU64,
+	pub(crate) poll_ns: AtomicU64,
+	last_wake: AtomicU8,
+}
+
+impl TaskStats {
+	pub(crate) fn new(name: Option<String>, priority: Priority) -> Self {
+		TaskStats {
+			name,
+			priority,
+			cpu: AtomicUsize::new(0),
+			queued: AtomicBool::new(false),
+			running: AtomicBool::new(false),
+			polls: AtomicU64::new(0),
+			poll_ns: AtomicU64::new(0),
+			last_wake: AtomicU8::new(WakeReason::Spawned as u8),
+		}
+	}
+
+	pub(crate) fn set_last_wake(&self, reason: WakeReason) {
+		self.last_wake.store(reason as u8, Ordering::Relaxed);
+	}
+
+	/// Count a poll that took `elapsed`
+	pub(crate) fn record_poll(&self, elapsed: Duration) {
+		self.polls.fetch_add(1, Ordering::Relaxed);
+		self.poll_ns.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+	}
+
+	fn state(&self) -> TaskState {
+		if self.running.load(Ordering::Relaxed) {
+			TaskState::Running
+		} else if self.queued.load(Ordering::Relaxed) {
+			TaskState::Ready
+		} else {
+			TaskState::Waiting
+		}
+	}
+}
+
+/// Every unfinished task on every processor
+static TABLE: Mutex<BTreeMap<TaskId, Arc<TaskStats>>> = Mutex::new(BTreeMap::new());
+
+/// Add a spawned task to the table
+pub(crate) fn register(id: TaskId, stats: Arc<TaskStats>) {
+	TABLE.lock().insert(id, stats);
+}
+
+/// Take a finished task out of the table
+pub(crate) fn unregister(id: TaskId) {
+	TABLE.lock().remove(&id);
+}
+
+/// A task as `tasks` shows it
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+	pub id: u64,
+	pub name: Option<String>,
+	pub cpu: usize,
+	pub priority: Priority,
+	pub state: TaskState,
+	pub polls: u64,
+	/// Time spent in the task's `poll` altogether
+	pub poll_time: Duration,
+	pub last_wake: WakeReason,
+}
+
+/// Every unfinished task, in the order they were created
+pub fn tasks() -> Vec<TaskInfo> {
+	TABLE
+		.lock()
+		.iter()
+		.map(|(id, stats)| TaskInfo {
+			id: id.0,
+			name: stats.name.clone(),
+			cpu: stats.cpu.load(Ordering::Relaxed),
+			priority: stats.priority,
+			state: stats.state(),
+			polls: stats.polls.load(Ordering::Relaxed),
+			poll_time: Duration::from_nanos(stats.poll_ns.load(Ordering::Relaxed)),
+			last_wake: WakeReason::ALL[stats.last_wake.load(Ordering::Relaxed) as usize],
+		})
+		.collect()
+}
+
+/// Visit every unfinished task without allocating or waiting for the table,
+/// for the debugger: its ID, name, processor, state, and polls; `false` if
+/// the table was held
+pub(crate) fn try_for_each(mut visit: impl FnMut(u64, Option<&str>, usize, TaskState, u64)) -> bool {
+	let Some(table) = TABLE.try_lock() else {
+		return false;
+	};
+	for (id, stats) in table.iter() {
+		visit(id.0, stats.name.as_deref(), stats.cpu.load(Ordering::Relaxed), stats.state(), stats.polls.load(Ordering::Relaxed));
+	}
+	true
+}
+
+/// Test that task stats add up and read back through the table
+#[test_case]
+fn test_task_stats() {
+	let stats = Arc::new(TaskStats::new(Some(String::from("test")), Priority::Background));
+	let id = TaskId(u64::MAX);
+	register(id, stats.clone());
+	stats.record_poll(Duration::from_micros(3));
+	stats.record_poll(Duration::from_micros(4));
+	stats.set_last_wake(WakeReason::Remote);
+	stats.queued.store(true, Ordering::Relaxed);
+
+	let info = tasks().into_iter().find(|task| task.id == id.0).unwrap();
+	assert_eq!((info.name.as_deref(), info.polls, info.poll_time), (Some("test"), 2, Duration::from_micros(7)));
+	assert_eq!((info.state, info.last_wake, info.priority), (TaskState::Ready, WakeReason::Remote, Priority::Background));
+	unregister(id);
+	assert!(tasks().iter().all(|task| task.id != id.0));
+}
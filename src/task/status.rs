@@ -0,0 +1,87 @@
+use super::timer;
+use crate::time::{self, DateTime};
+use crate::{allocator, memory, vga_buffer};
+use alloc::{format, string::String};
+use core::time::Duration;
+
+/// Time between status line updates
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the status line shows
+struct Status {
+	/// Number of the displayed console, from 1
+	console: usize,
+	time: DateTime,
+	heap_free: usize,
+	heap_size: usize,
+	frames_free: Option<usize>,
+	tasks: usize,
+}
+
+impl Status {
+	/// Gather the current state of the system
+	fn current() -> Status {
+		let (heap_free, heap_size) = allocator::heap_usage();
+		Status {
+			console: vga_buffer::displayed_console() + 1,
+			time: DateTime::from_timestamp(time::now()),
+			heap_free,
+			heap_size,
+			frames_free: memory::free_frames(),
+			tasks: super::task_count(),
+		}
+	}
+
+	/// The status line for a screen `width` columns wide, with the clock at
+	/// the right edge
+	fn render(&self, width: usize) -> String {
+		let mut line = format!(" tty{} | heap {}K/{}K free", self.console, self.heap_free / 1024, self.heap_size / 1024);
+		if let Some(frames) = self.frames_free {
+			line.push_str(&format!(" | {} frames free", frames));
+		}
+		line.push_str(&format!(" | {} tasks", self.tasks));
+		let clock = self.time.format("%Y-%m-%d %H:%M:%S ");
+		let room = width.saturating_sub(clock.len());
+		line.truncate(room);
+		while line.len() < room {
+			line.push(' ');
+		}
+		line.push_str(&clock);
+		line
+	}
+}
+
+/// Redraw the status line with the current state
+pub fn update() {
+	let (_, width) = vga_buffer::screen_size();
+	vga_buffer::set_status_line(&Status::current().render(width));
+}
+
+/// Show the status line and keep it up to date
+pub async fn run_status_bar() {
+	vga_buffer::set_status_bar(true);
+	let mut interval = timer::interval(UPDATE_INTERVAL);
+	loop {
+		interval.tick().await;
+		update();
+	}
+}
+
+/// Test the layout of the status line
+#[test_case]
+fn test_status_render() {
+	let status = Status {
+		console: 2,
+		time: DateTime { year: 2024, month: 3, day: 1, hour: 9, minute: 5, second: 7 },
+		heap_free: 512 * 1024,
+		heap_size: 1024 * 1024,
+		frames_free: Some(100),
+		tasks: 7,
+	};
+	let line = status.render(80);
+	assert_eq!(line.len(), 80);
+	assert!(line.starts_with(" tty2 | heap 512K/1024K free | 100 frames free | 7 tasks "));
+	assert!(line.ends_with("2024-03-01 09:05:07 "));
+	// A narrow screen cuts the left side short to keep the clock
+	assert_eq!(status.render(30), " tty2 | he2024-03-01 09:05:07 ");
+}
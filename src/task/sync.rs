@@ -0,0 +1,487 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use futures_util::stream::Stream;
+
+/// Tasks waiting for something, in the order they started
+struct WaitList {
+	wakers: crate::sync::Mutex<VecDeque<Waker>>,
+}
+
+impl WaitList {
+	const fn new() -> Self {
+		WaitList { wakers: crate::sync::Mutex::new(VecDeque::new()) }
+	}
+
+	/// Remember a waiting task, once however often it polls
+	fn register(&self, waker: &Waker) {
+		let mut wakers = self.wakers.lock();
+		if !wakers.iter().any(|waiting| waiting.will_wake(waker)) {
+			wakers.push_back(waker.clone());
+		}
+	}
+
+	fn wake_one(&self) {
+		let waker = self.wakers.lock().pop_front();
+		if let Some(waker) = waker {
+			waker.wake();
+		}
+	}
+
+	fn wake_all(&self) {
+		let wakers = core::mem::take(&mut *self.wakers.lock());
+		for waker in wakers {
+			waker.wake();
+		}
+	}
+}
+
+/// Poll `attempt`, and if it fails, register to be woken and try once more
+/// in case the holder let go in between
+fn poll_with<R>(waiters: &WaitList, cx: &mut Context, mut attempt: impl FnMut() -> Option<R>) -> Poll<R> {
+	if let Some(result) = attempt() {
+		return Poll::Ready(result);
+	}
+	waiters.register(cx.waker());
+	match attempt() {
+		Some(result) => Poll::Ready(result),
+		None => Poll::Pending,
+	}
+}
+
+/// A lock that tasks wait for at an await point rather than by spinning,
+/// so a task never holds up its processor while another task, or a command
+/// on another processor, has the lock
+///
+/// Like everything here it is not for interrupt handlers.
+pub struct Mutex<T> {
+	locked: AtomicBool,
+	waiters: WaitList,
+	value: UnsafeCell<T>,
+}
+
+// The lock hands out one reference at a time
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+/// Access to a `Mutex`'s data, released when dropped
+pub struct MutexGuard<'a, T> {
+	mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+	pub const fn new(value: T) -> Self {
+		Mutex { locked: AtomicBool::new(false), waiters: WaitList::new(), value: UnsafeCell::new(value) }
+	}
+
+	/// Take the lock if it is free
+	pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+		self.locked
+			.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+			.is_ok()
+			.then_some(MutexGuard { mutex: self })
+	}
+
+	/// Wait for the lock
+	pub async fn lock(&self) -> MutexGuard<'_, T> {
+		core::future::poll_fn(|cx| poll_with(&self.waiters, cx, || self.try_lock())).await
+	}
+
+	/// Take the lock from code that cannot await, spinning until it is free
+	pub fn lock_blocking(&self) -> MutexGuard<'_, T> {
+		loop {
+			if let Some(guard) = self.try_lock() {
+				return guard;
+			}
+			core::hint::spin_loop();
+		}
+	}
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		unsafe { &*self.mutex.value.get() }
+	}
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		unsafe { &mut *self.mutex.value.get() }
+	}
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+	fn drop(&mut self) {
+		self.mutex.locked.store(false, Ordering::Release);
+		// Every waiter tries again, so one that gave up waiting cannot strand
+		// the rest
+		self.mutex.waiters.wake_all();
+	}
+}
+
+/// `RwLock` state: the reader count, or this while a writer has it
+const WRITER: usize = usize::MAX;
+
+/// A lock many tasks can read under at once, or one can write under
+pub struct RwLock<T> {
+	state: AtomicUsize,
+	waiters: WaitList,
+	value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: Send> Send for RwLock<T> {}
+
+/// Shared access to a `RwLock`'s data
+pub struct RwLockReadGuard<'a, T> {
+	lock: &'a RwLock<T>,
+}
+
+/// Exclusive access to a `RwLock`'s data
+pub struct RwLockWriteGuard<'a, T> {
+	lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+	pub const fn new(value: T) -> Self {
+		RwLock { state: AtomicUsize::new(0), waiters: WaitList::new(), value: UnsafeCell::new(value) }
+	}
+
+	/// Read if no writer has the lock
+	pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+		let mut state = self.state.load(Ordering::Relaxed);
+		while state < WRITER - 1 {
+			match self.state.compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed) {
+				Ok(_) => return Some(RwLockReadGuard { lock: self }),
+				Err(current) => state = current,
+			}
+		}
+		None
+	}
+
+	/// Write if nobody has the lock
+	pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+		self.state
+			.compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+			.is_ok()
+			.then_some(RwLockWriteGuard { lock: self })
+	}
+
+	/// Wait until there is no writer
+	pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+		core::future::poll_fn(|cx| poll_with(&self.waiters, cx, || self.try_read())).await
+	}
+
+	/// Wait until there are no readers or writer
+	pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+		core::future::poll_fn(|cx| poll_with(&self.waiters, cx, || self.try_write())).await
+	}
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		unsafe { &*self.lock.value.get() }
+	}
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+	fn drop(&mut self) {
+		if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+			self.lock.waiters.wake_all();
+		}
+	}
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		unsafe { &*self.lock.value.get() }
+	}
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		unsafe { &mut *self.lock.value.get() }
+	}
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+	fn drop(&mut self) {
+		self.lock.state.store(0, Ordering::Release);
+		self.lock.waiters.wake_all();
+	}
+}
+
+/// Wakes tasks waiting for an event
+///
+/// `notify_one` leaves a permit when nobody is waiting, so a task that
+/// checks for work and then waits cannot miss a notification in between.
+pub struct Notify {
+	permit: AtomicBool,
+	/// Counts `notify_waiters` calls, so each waiter can tell it was woken
+	generation: AtomicUsize,
+	waiters: WaitList,
+}
+
+impl Notify {
+	pub const fn new() -> Self {
+		Notify { permit: AtomicBool::new(false), generation: AtomicUsize::new(0), waiters: WaitList::new() }
+	}
+
+	/// Wake one waiting task, or the next to wait if none is
+	pub fn notify_one(&self) {
+		self.permit.store(true, Ordering::Release);
+		self.waiters.wake_one();
+	}
+
+	/// Wake every task waiting now, leaving no permit
+	pub fn notify_waiters(&self) {
+		self.generation.fetch_add(1, Ordering::Release);
+		self.waiters.wake_all();
+	}
+
+	/// Wait for a notification
+	pub async fn notified(&self) {
+		let generation = self.generation.load(Ordering::Acquire);
+		core::future::poll_fn(|cx| {
+			poll_with(&self.waiters, cx, || {
+				let woken = self.generation.load(Ordering::Acquire) != generation;
+				(woken || self.permit.swap(false, Ordering::AcqRel)).then_some(())
+			})
+		})
+		.await
+	}
+}
+
+impl Default for Notify {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Channels with any number of senders and one receiver
+pub mod mpsc {
+	use super::*;
+
+	/// What a channel's ends share
+	struct Channel<T> {
+		queue: crate::sync::Mutex<VecDeque<T>>,
+		senders: AtomicUsize,
+		receiver_alive: AtomicBool,
+		receiver: futures_util::task::AtomicWaker,
+	}
+
+	/// The sending end of a channel; clone it for more senders
+	pub struct Sender<T> {
+		channel: Arc<Channel<T>>,
+	}
+
+	/// The receiving end of a channel
+	pub struct Receiver<T> {
+		channel: Arc<Channel<T>>,
+	}
+
+	/// A channel with no bound on what it holds
+	pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+		let channel = Arc::new(Channel {
+			queue: crate::sync::Mutex::new(VecDeque::new()),
+			senders: AtomicUsize::new(1),
+			receiver_alive: AtomicBool::new(true),
+			receiver: futures_util::task::AtomicWaker::new(),
+		});
+		(Sender { channel: channel.clone() }, Receiver { channel })
+	}
+
+	impl<T> Sender<T> {
+		/// Queue a value, handing it back if the receiver is gone
+		pub fn send(&self, value: T) -> Result<(), T> {
+			if !self.channel.receiver_alive.load(Ordering::Acquire) {
+				return Err(value);
+			}
+			self.channel.queue.lock().push_back(value);
+			self.channel.receiver.wake();
+			Ok(())
+		}
+	}
+
+	impl<T> Clone for Sender<T> {
+		fn clone(&self) -> Self {
+			self.channel.senders.fetch_add(1, Ordering::Relaxed);
+			Sender { channel: self.channel.clone() }
+		}
+	}
+
+	impl<T> Drop for Sender<T> {
+		fn drop(&mut self) {
+			if self.channel.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+				self.channel.receiver.wake();
+			}
+		}
+	}
+
+	impl<T> Receiver<T> {
+		/// The next value if one is queued
+		pub fn try_recv(&mut self) -> Option<T> {
+			self.channel.queue.lock().pop_front()
+		}
+
+		/// Wait for the next value, or `None` once every sender is gone and
+		/// the queue is empty
+		pub async fn recv(&mut self) -> Option<T> {
+			core::future::poll_fn(|cx| self.poll_recv(cx)).await
+		}
+
+		fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<T>> {
+			if let Some(value) = self.try_recv() {
+				return Poll::Ready(Some(value));
+			}
+			self.channel.receiver.register(cx.waker());
+			match self.try_recv() {
+				Some(value) => Poll::Ready(Some(value)),
+				None if self.channel.senders.load(Ordering::Acquire) == 0 => Poll::Ready(None),
+				None => Poll::Pending,
+			}
+		}
+	}
+
+	impl<T> Stream for Receiver<T> {
+		type Item = T;
+
+		fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+			self.get_mut().poll_recv(cx)
+		}
+	}
+
+	impl<T> Drop for Receiver<T> {
+		fn drop(&mut self) {
+			self.channel.receiver_alive.store(false, Ordering::Release);
+		}
+	}
+}
+
+/// Channels that carry a single value
+pub mod oneshot {
+	use super::*;
+
+	/// The error a receiver gets when its sender was dropped without sending
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub struct Canceled;
+
+	/// What a channel's ends share
+	struct Slot<T> {
+		value: Option<T>,
+		sender_alive: bool,
+		receiver: Option<Waker>,
+	}
+
+	pub struct Sender<T> {
+		slot: Arc<crate::sync::Mutex<Slot<T>>>,
+	}
+
+	/// The receiving end, a future for the value
+	pub struct Receiver<T> {
+		slot: Arc<crate::sync::Mutex<Slot<T>>>,
+	}
+
+	pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+		let slot = Arc::new(crate::sync::Mutex::new(Slot { value: None, sender_alive: true, receiver: None }));
+		(Sender { slot: slot.clone() }, Receiver { slot })
+	}
+
+	impl<T> Sender<T> {
+		/// Send the value, handing it back if the receiver is gone
+		pub fn send(self, value: T) -> Result<(), T> {
+			if Arc::strong_count(&self.slot) == 1 {
+				return Err(value);
+			}
+			self.slot.lock().value = Some(value);
+			Ok(())
+		}
+	}
+
+	impl<T> Drop for Sender<T> {
+		fn drop(&mut self) {
+			let receiver = {
+				let mut slot = self.slot.lock();
+				slot.sender_alive = false;
+				slot.receiver.take()
+			};
+			if let Some(waker) = receiver {
+				waker.wake();
+			}
+		}
+	}
+
+	impl<T> Future for Receiver<T> {
+		type Output = Result<T, Canceled>;
+
+		fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, Canceled>> {
+			let mut slot = self.slot.lock();
+			if let Some(value) = slot.value.take() {
+				return Poll::Ready(Ok(value));
+			}
+			if !slot.sender_alive {
+				return Poll::Ready(Err(Canceled));
+			}
+			slot.receiver = Some(cx.waker().clone());
+			Poll::Pending
+		}
+	}
+}
+
+/// Test the locks, channels, and events without an executor
+#[test_case]
+fn test_task_sync() {
+	use alloc::boxed::Box;
+
+	let mut cx = Context::from_waker(Waker::noop());
+	fn poll<F: Future>(future: &mut Pin<Box<F>>, cx: &mut Context) -> Poll<F::Output> {
+		future.as_mut().poll(cx)
+	}
+
+	let mutex = Mutex::new(1);
+	let guard = mutex.try_lock().unwrap();
+	let mut waiting = Box::pin(mutex.lock());
+	assert!(poll(&mut waiting, &mut cx).is_pending());
+	drop(guard);
+	let Poll::Ready(mut guard) = poll(&mut waiting, &mut cx) else { panic!("lock not taken") };
+	*guard += 1;
+	drop(guard);
+	assert_eq!(*mutex.lock_blocking(), 2);
+
+	let lock = RwLock::new(0);
+	let (first, second) = (lock.try_read().unwrap(), lock.try_read().unwrap());
+	assert!(lock.try_write().is_none());
+	drop((first, second));
+	assert!(lock.try_write().is_some_and(|_| lock.try_read().is_none()));
+
+	let notify = Notify::new();
+	notify.notify_one();
+	assert!(poll(&mut Box::pin(notify.notified()), &mut cx).is_ready());
+	let mut waiting = Box::pin(notify.notified());
+	assert!(poll(&mut waiting, &mut cx).is_pending());
+	notify.notify_waiters();
+	assert!(poll(&mut waiting, &mut cx).is_ready());
+
+	let (sender, mut receiver) = mpsc::channel();
+	sender.clone().send(5).unwrap();
+	drop(sender);
+	assert_eq!(poll(&mut Box::pin(receiver.recv()), &mut cx), Poll::Ready(Some(5)));
+	assert_eq!(poll(&mut Box::pin(receiver.recv()), &mut cx), Poll::Ready(None));
+
+	let (sender, receiver) = oneshot::channel();
+	sender.send("done").unwrap();
+	assert_eq!(poll(&mut Box::pin(receiver), &mut cx), Poll::Ready(Ok("done")));
+	let (sender, receiver) = oneshot::channel::<()>();
+	drop(sender);
+	assert_eq!(poll(&mut Box::pin(receiver), &mut cx), Poll::Ready(Err(oneshot::Canceled)));
+}
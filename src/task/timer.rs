@@ -0,0 +1,177 @@
+use crate::sync::SpinLockIrqSave;
+use crate::time::Instant;
+use alloc::collections::BinaryHeap;
+use core::cmp::Ordering;
+use core::sync::atomic::{self, AtomicU64};
+use core::time::Duration;
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+use futures_util::future::{self, Either};
+
+/// A task waiting for a deadline
+struct Timer {
+	deadline: Instant,
+	/// Tells apart timers with the same deadline, and finds a timer to
+	/// cancel it
+	id: u64,
+	waker: Waker,
+}
+
+impl PartialEq for Timer {
+	fn eq(&self, other: &Timer) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+	fn partial_cmp(&self, other: &Timer) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Timer {
+	/// Reversed, so the heap's greatest timer is the one due first
+	fn cmp(&self, other: &Timer) -> Ordering {
+		(other.deadline, other.id).cmp(&(self.deadline, self.id))
+	}
+}
+
+/// Pending timers, soonest first; the timer interrupt pops the due ones
+static TIMERS: SpinLockIrqSave<BinaryHeap<Timer>> = SpinLockIrqSave::new(BinaryHeap::new());
+
+/// Wake every task whose deadline has passed
+///
+/// Called from the timer interrupt handler, so it only pops: pushing could
+/// allocate.
+pub(crate) fn tick() {
+	let now = Instant::now();
+	let mut timers = TIMERS.lock();
+	while timers.peek().is_some_and(|timer| timer.deadline <= now) {
+		if let Some(timer) = timers.pop() {
+			timer.waker.wake();
+		}
+	}
+}
+
+/// Timers waiting, for tests and diagnostics
+pub fn pending() -> usize {
+	TIMERS.lock().len()
+}
+
+/// A future that completes once the monotonic clock reaches a deadline
+pub struct Sleep {
+	deadline: Instant,
+	/// The timer's ID and the waker it holds, once registered
+	registered: Option<(u64, Waker)>,
+}
+
+/// Wait at least `duration`, letting other tasks run meanwhile
+///
+/// Sleepers wake on timer ticks, so the resolution is the tick rate: 10 ms
+/// with the APIC timer's default, 55 ms with the PIT.
+pub fn sleep(duration: Duration) -> Sleep {
+	sleep_until(Instant::now() + duration)
+}
+
+/// Wait until the monotonic clock reaches `deadline`
+pub fn sleep_until(deadline: Instant) -> Sleep {
+	Sleep { deadline, registered: None }
+}
+
+impl Sleep {
+	/// Take the timer out of the heap, if it has not fired yet
+	fn cancel(&mut self) {
+		if let Some((id, _)) = self.registered.take() {
+			TIMERS.lock().retain(|timer| timer.id != id);
+		}
+	}
+}
+
+impl Future for Sleep {
+	type Output = ();
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+		if Instant::now() >= self.deadline {
+			self.registered = None;
+			return Poll::Ready(());
+		}
+		// A timer with the same waker is still in the heap or has just fired
+		if self.registered.as_ref().is_some_and(|(_, waker)| waker.will_wake(cx.waker())) {
+			return Poll::Pending;
+		}
+		self.cancel();
+		static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+		let id = NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed);
+		TIMERS.lock().push(Timer { deadline: self.deadline, id, waker: cx.waker().clone() });
+		self.registered = Some((id, cx.waker().clone()));
+		Poll::Pending
+	}
+}
+
+impl Drop for Sleep {
+	fn drop(&mut self) {
+		self.cancel();
+	}
+}
+
+/// Deadlines a fixed period apart, for work that repeats
+pub struct Interval {
+	period: Duration,
+	next: Instant,
+}
+
+/// Ticks every `period`, the first one right away
+pub fn interval(period: Duration) -> Interval {
+	Interval { period, next: Instant::now() }
+}
+
+impl Interval {
+	/// Wait for the next deadline and return it; deadlines missed by more
+	/// than a period are skipped rather than run back to back
+	pub async fn tick(&mut self) -> Instant {
+		let deadline = self.next;
+		sleep_until(deadline).await;
+		self.next = deadline + self.period;
+		let now = Instant::now();
+		if now >= self.next + self.period {
+			self.next = now + self.period;
+		}
+		deadline
+	}
+}
+
+/// The error `timeout` returns when the deadline passes first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Run `future` for at most `duration`, dropping it if it takes longer
+pub async fn timeout<F: Future>(future: F, duration: Duration) -> Result<F::Output, Elapsed> {
+	let future = core::pin::pin!(future);
+	match future::select(future, sleep(duration)).await {
+		Either::Left((output, _)) => Ok(output),
+		Either::Right(_) => Err(Elapsed),
+	}
+}
+
+/// Test that sleeps complete at their deadline and cancel when dropped
+#[test_case]
+fn test_sleep() {
+	let mut cx = Context::from_waker(Waker::noop());
+	let before = pending();
+	assert_eq!(Pin::new(&mut sleep(Duration::ZERO)).poll(&mut cx), Poll::Ready(()));
+	let mut long = sleep(Duration::from_secs(60));
+	assert_eq!(Pin::new(&mut long).poll(&mut cx), Poll::Pending);
+	assert_eq!(Pin::new(&mut long).poll(&mut cx), Poll::Pending);
+	assert_eq!(pending(), before + 1);
+	tick();
+	assert_eq!(pending(), before + 1);
+	drop(long);
+	assert_eq!(pending(), before);
+
+	let mut quick = core::pin::pin!(timeout(async { 7 }, Duration::from_secs(60)));
+	assert_eq!(quick.as_mut().poll(&mut cx), Poll::Ready(Ok(7)));
+	let mut slow = core::pin::pin!(timeout(future::pending::<()>(), Duration::ZERO));
+	assert_eq!(slow.as_mut().poll(&mut cx), Poll::Ready(Err(Elapsed)));
+	assert_eq!(pending(), before);
+}
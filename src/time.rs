@@ -0,0 +1,542 @@
+use crate::interrupts::{self, InterruptIndex};
+use crate::cpu::{self, Feature};
+use crate::{apic, cmdline, hpet, klog, rtc};
+use alloc::string::String;
+use core::fmt::Write;
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicI64, AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+use x86_64::instructions::port::Port;
+
+/// Input clock of the programmable interval timer
+const PIT_FREQUENCY_HZ: u64 = 1_193_182;
+/// PIT reload value; the firmware default of 65536 gives about 18.2 ticks per second
+const PIT_DIVISOR: u64 = 65_536;
+
+/// PIT channel 0, which drives IRQ 0, and channel 2, which counts without
+/// interrupting; then the command port
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// Channel 2, low then high byte, mode 0 (interrupt on terminal count)
+const PIT_CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+/// Channel 0, low then high byte, mode 2 (rate generator)
+const PIT_CHANNEL_0_PERIODIC: u8 = 0b0011_0100;
+/// System control port B: channel 2's gate, speaker enable, and output bits
+const PIT_GATE_PORT: u16 = 0x61;
+const PIT_GATE: u8 = 0x01;
+const PIT_SPEAKER: u8 = 0x02;
+const PIT_OUTPUT: u8 = 0x20;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Timer interrupts per second unless `timer_hz=` picks another rate
+const DEFAULT_TICK_HZ: u64 = 100;
+/// Tick rates every tick source can be set to
+pub const TICK_HZ_RANGE: core::ops::RangeInclusive<u64> = 19..=10_000;
+
+/// What raises the timer interrupt, chosen with `timer=` on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickSource {
+	Pit,
+	Lapic,
+	Hpet,
+}
+
+impl TickSource {
+	pub fn name(self) -> &'static str {
+		match self {
+			TickSource::Pit => "pit",
+			TickSource::Lapic => "lapic",
+			TickSource::Hpet => "hpet",
+		}
+	}
+
+	fn from_name(name: &str) -> Option<TickSource> {
+		[TickSource::Pit, TickSource::Lapic, TickSource::Hpet].into_iter().find(|source| source.name() == name)
+	}
+}
+
+/// The tick source in use, as a `TickSource` discriminant
+static TICK_SOURCE: AtomicU8 = AtomicU8::new(TickSource::Pit as u8);
+
+/// Timer interrupts since boot
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Nanoseconds between timer interrupts
+static TICK_NS: AtomicU64 = AtomicU64::new(PIT_DIVISOR * NANOS_PER_SECOND / PIT_FREQUENCY_HZ);
+
+/// The time stamp counter's rate, or 0 while uptime is counted in ticks
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+/// The counter's value when uptime switched to it, and the uptime then
+static TSC_START: AtomicU64 = AtomicU64::new(0);
+static TSC_START_NS: AtomicU64 = AtomicU64::new(0);
+/// The tick count when the tick rate last changed, and the uptime then
+static TICK_START: AtomicU64 = AtomicU64::new(0);
+static TICK_START_NS: AtomicU64 = AtomicU64::new(0);
+
+/// How long the TSC is measured against the PIT
+const TSC_CALIBRATION_MS: u64 = 20;
+
+/// Wall-clock time at uptime zero in nanoseconds since the epoch, taken
+/// from the RTC the first time the clock is read, and changed by setting it
+static CLOCK_EPOCH_NS: AtomicI64 = AtomicI64::new(CLOCK_UNSET);
+const CLOCK_UNSET: i64 = i64::MIN;
+/// A correction being slewed into the wall clock, and the uptime in
+/// nanoseconds it started from
+static SLEW_NS: AtomicI64 = AtomicI64::new(0);
+static SLEW_START_NS: AtomicU64 = AtomicU64::new(0);
+/// How fast a correction is slewed in, in nanoseconds per second
+const SLEW_RATE_NS: u64 = 500_000;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+const WEEKDAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+const MONTHS: [&str; 12] = [
+	"January", "February", "March", "April", "May", "June",
+	"July", "August", "September", "October", "November", "December",
+];
+
+/// A calendar date and time of day in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+	pub year: u16,
+	pub month: u8,
+	pub day: u8,
+	pub hour: u8,
+	pub minute: u8,
+	pub second: u8,
+}
+
+/// Whether `year` is a leap year
+fn is_leap_year(year: i64) -> bool {
+	(year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// Number of days in a month of a year
+fn days_in_month(year: i64, month: u8) -> u8 {
+	match month {
+		2 if is_leap_year(year) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31,
+	}
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+	// Count from March so the leap day ends the year
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = year.div_euclid(400);
+	let year_of_era = year.rem_euclid(400);
+	let month_index = (month as i64 + 9) % 12;
+	let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146_097 + day_of_era - 719_468
+}
+
+/// The date `days` after 1970-01-01, as `(year, month, day)`
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+	let days = days + 719_468;
+	let era = days.div_euclid(146_097);
+	let day_of_era = days.rem_euclid(146_097);
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month_index = (5 * day_of_year + 2) / 153;
+	let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u8;
+	let month = (if month_index < 10 { month_index + 3 } else { month_index - 9 }) as u8;
+	let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+	(year, month, day)
+}
+
+impl DateTime {
+	/// The date and time `timestamp` seconds after the Unix epoch
+	pub fn from_timestamp(timestamp: u64) -> DateTime {
+		let timestamp = timestamp as i64;
+		let (year, month, day) = civil_from_days(timestamp.div_euclid(SECONDS_PER_DAY));
+		let secs = timestamp.rem_euclid(SECONDS_PER_DAY);
+		DateTime {
+			year: year as u16,
+			month,
+			day,
+			hour: (secs / 3600) as u8,
+			minute: (secs / 60 % 60) as u8,
+			second: (secs % 60) as u8,
+		}
+	}
+
+	/// Seconds since the Unix epoch
+	pub fn timestamp(&self) -> u64 {
+		let days = days_from_civil(self.year as i64, self.month, self.day);
+		(days * SECONDS_PER_DAY + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64).max(0) as u64
+	}
+
+	/// Whether every field is in range for a real date after the epoch
+	pub fn is_valid(&self) -> bool {
+		self.year >= 1970
+			&& (1..=12).contains(&self.month)
+			&& self.day >= 1
+			&& self.day <= days_in_month(self.year as i64, self.month)
+			&& self.hour < 24
+			&& self.minute < 60
+			&& self.second < 60
+	}
+
+	/// Day of the week, 0 for Sunday
+	pub fn weekday(&self) -> usize {
+		(days_from_civil(self.year as i64, self.month, self.day) + 4).rem_euclid(7) as usize
+	}
+
+	/// Day of the year, 1 for January 1st
+	pub fn day_of_year(&self) -> u16 {
+		(days_from_civil(self.year as i64, self.month, self.day) - days_from_civil(self.year as i64, 1, 1) + 1) as u16
+	}
+
+	/// Parse `YYYY-MM-DD`, `HH:MM[:SS]`, or both separated by a space or `T`;
+	/// a missing date is taken from `today` and a missing time is midnight
+	pub fn parse(text: &str, today: &DateTime) -> Option<DateTime> {
+		let text = text.trim();
+		let (date, time) = match text.split_once([' ', 'T']) {
+			Some((date, time)) => (Some(date), Some(time)),
+			None if text.contains(':') => (None, Some(text)),
+			None => (Some(text), None),
+		};
+		let mut result = DateTime { hour: 0, minute: 0, second: 0, ..*today };
+		if let Some(date) = date {
+			let mut fields = date.split('-');
+			let (Some(year), Some(month), Some(day), None) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+				return None;
+			};
+			result.year = year.parse().ok()?;
+			result.month = month.parse().ok()?;
+			result.day = day.parse().ok()?;
+		}
+		if let Some(time) = time {
+			let mut fields = time.trim().split(':');
+			result.hour = fields.next()?.parse().ok()?;
+			result.minute = fields.next()?.parse().ok()?;
+			result.second = fields.next().map_or(Some(0), |s| s.parse().ok())?;
+			if fields.next().is_some() {
+				return None;
+			}
+		}
+		result.is_valid().then_some(result)
+	}
+
+	/// Format with `strftime`-style conversions: `%Y %y %C %m %d %e %j %H %I %M
+	/// %S %p %a %A %b %B %h %F %T %D %R %c %s %u %w %Z %n %t %%`
+	pub fn format(&self, pattern: &str) -> String {
+		let mut out = String::new();
+		let mut chars = pattern.chars();
+		while let Some(c) = chars.next() {
+			if c != '%' {
+				out.push(c);
+				continue;
+			}
+			let weekday = WEEKDAYS[self.weekday()];
+			let month = MONTHS[(self.month as usize).clamp(1, 12) - 1];
+			let hour12 = match self.hour % 12 { 0 => 12, hour => hour };
+			let _ = match chars.next() {
+				Some('Y') => write!(out, "{}", self.year),
+				Some('y') => write!(out, "{:02}", self.year % 100),
+				Some('C') => write!(out, "{:02}", self.year / 100),
+				Some('m') => write!(out, "{:02}", self.month),
+				Some('d') => write!(out, "{:02}", self.day),
+				Some('e') => write!(out, "{:>2}", self.day),
+				Some('j') => write!(out, "{:03}", self.day_of_year()),
+				Some('H') => write!(out, "{:02}", self.hour),
+				Some('I') => write!(out, "{:02}", hour12),
+				Some('M') => write!(out, "{:02}", self.minute),
+				Some('S') => write!(out, "{:02}", self.second),
+				Some('p') => write!(out, "{}", if self.hour < 12 { "AM" } else { "PM" }),
+				Some('a') => write!(out, "{}", &weekday[..3]),
+				Some('A') => write!(out, "{}", weekday),
+				Some('b' | 'h') => write!(out, "{}", &month[..3]),
+				Some('B') => write!(out, "{}", month),
+				Some('F') => write!(out, "{}", self.format("%Y-%m-%d")),
+				Some('T') => write!(out, "{}", self.format("%H:%M:%S")),
+				Some('D') => write!(out, "{}", self.format("%m/%d/%y")),
+				Some('R') => write!(out, "{}", self.format("%H:%M")),
+				Some('c') => write!(out, "{}", self.format("%a %b %e %H:%M:%S %Y")),
+				Some('s') => write!(out, "{}", self.timestamp()),
+				Some('u') => write!(out, "{}", match self.weekday() { 0 => 7, day => day }),
+				Some('w') => write!(out, "{}", self.weekday()),
+				Some('Z') => write!(out, "UTC"),
+				Some('n') => writeln!(out),
+				Some('t') => write!(out, "\t"),
+				Some('%') => write!(out, "%"),
+				Some(other) => write!(out, "%{}", other),
+				None => write!(out, "%"),
+			};
+		}
+		out
+	}
+}
+
+/// Count one timer interrupt
+pub fn tick() {
+	TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Timer interrupts since boot
+pub fn ticks() -> u64 {
+	TICKS.load(Ordering::Relaxed)
+}
+
+/// Timer interrupts per second
+pub fn tick_rate() -> u64 {
+	NANOS_PER_SECOND / TICK_NS.load(Ordering::Relaxed).max(1)
+}
+
+/// Time since interrupts were enabled at boot, to the nanosecond once the
+/// time stamp counter is calibrated and to the tick before
+pub fn uptime() -> Duration {
+	let tsc_hz = TSC_HZ.load(Ordering::Relaxed);
+	if tsc_hz == 0 {
+		let ticks = ticks() - TICK_START.load(Ordering::Relaxed);
+		return Duration::from_nanos(TICK_START_NS.load(Ordering::Relaxed) + ticks * TICK_NS.load(Ordering::Relaxed));
+	}
+	let elapsed = unsafe { core::arch::x86_64::_rdtsc() }.wrapping_sub(TSC_START.load(Ordering::Relaxed));
+	let nanos = elapsed as u128 * NANOS_PER_SECOND as u128 / tsc_hz as u128;
+	Duration::from_nanos(TSC_START_NS.load(Ordering::Relaxed) + nanos as u64)
+}
+
+/// Milliseconds since interrupts were enabled at boot
+pub fn uptime_ms() -> u64 {
+	uptime().as_millis() as u64
+}
+
+/// Switch to a new tick rate, carrying the uptime on from where it is
+pub(crate) fn set_tick_rate(tick_hz: u64) {
+	let now = uptime().as_nanos() as u64;
+	TICK_START.store(ticks(), Ordering::Relaxed);
+	TICK_START_NS.store(now, Ordering::Relaxed);
+	TICK_NS.store(NANOS_PER_SECOND / tick_hz.max(1), Ordering::Relaxed);
+}
+
+/// Run PIT channel 0 at about `hz` interrupts per second
+fn set_pit_hz(hz: u64) {
+	let divisor = (PIT_FREQUENCY_HZ / hz.max(1)).clamp(1, 0xffff) as u16;
+	unsafe {
+		Port::<u8>::new(PIT_COMMAND).write(PIT_CHANNEL_0_PERIODIC);
+		let mut channel: Port<u8> = Port::new(PIT_CHANNEL_0);
+		channel.write(divisor as u8);
+		channel.write((divisor >> 8) as u8);
+	}
+	set_tick_rate(PIT_FREQUENCY_HZ / divisor as u64);
+}
+
+/// Start the timer interrupt from the source `timer=` names (the local APIC
+/// timer if there is one, otherwise the PIT) at `timer_hz=` ticks a second,
+/// falling back to the PIT if that source is missing
+pub fn start_tick() {
+	let hz = cmdline::param("timer_hz").and_then(|hz| hz.parse().ok()).unwrap_or(DEFAULT_TICK_HZ);
+	let hz = if TICK_HZ_RANGE.contains(&hz) {
+		hz
+	} else {
+		klog!(Warn, "time: timer_hz={} is outside {:?}", hz, TICK_HZ_RANGE);
+		DEFAULT_TICK_HZ
+	};
+	let requested = match cmdline::param("timer") {
+		Some(name) => TickSource::from_name(name).unwrap_or_else(|| {
+			klog!(Warn, "time: unknown timer '{}'", name);
+			TickSource::Lapic
+		}),
+		None => TickSource::Lapic,
+	};
+	let started = match requested {
+		TickSource::Lapic => apic::set_timer_hz(hz),
+		TickSource::Hpet => hpet::set_timer(hpet::Mode::Periodic(hz)),
+		TickSource::Pit => false,
+	};
+	let source = if started { requested } else { TickSource::Pit };
+	if source == TickSource::Pit {
+		if requested != TickSource::Pit && cmdline::param("timer").is_some() {
+			klog!(Warn, "time: no {} timer, ticking from the PIT", requested.name());
+		}
+		set_pit_hz(hz);
+	}
+	// The HPET's legacy route shares the PIT's IRQ
+	if source != TickSource::Lapic {
+		interrupts::enable_irq(InterruptIndex::Timer.irq());
+	}
+	TICK_SOURCE.store(source as u8, Ordering::Relaxed);
+	klog!(Info, "time: ticking from the {} at {} Hz", source.name(), tick_rate());
+}
+
+/// What raises the timer interrupt
+pub fn tick_source() -> TickSource {
+	match TICK_SOURCE.load(Ordering::Relaxed) {
+		source if source == TickSource::Lapic as u8 => TickSource::Lapic,
+		source if source == TickSource::Hpet as u8 => TickSource::Hpet,
+		_ => TickSource::Pit,
+	}
+}
+
+/// The time stamp counter's rate, once it has been measured and found
+/// invariant
+pub fn tsc_hz() -> Option<u64> {
+	Some(TSC_HZ.load(Ordering::Relaxed)).filter(|&hz| hz != 0)
+}
+
+/// Measure the time stamp counter against the PIT, and count uptime with it
+/// from here on if it is invariant; interrupts should be off
+pub fn init() {
+	if !cpu::has(Feature::InvariantTsc) {
+		klog!(Info, "time: no invariant TSC, counting uptime in timer ticks");
+		return;
+	}
+	let start = unsafe { core::arch::x86_64::_rdtsc() };
+	pit_wait_ms(TSC_CALIBRATION_MS);
+	let end = unsafe { core::arch::x86_64::_rdtsc() };
+	let tsc_hz = (end - start) * (1000 / TSC_CALIBRATION_MS);
+	if tsc_hz == 0 {
+		return;
+	}
+	let now = uptime().as_nanos() as u64;
+	TSC_START.store(unsafe { core::arch::x86_64::_rdtsc() }, Ordering::Relaxed);
+	TSC_START_NS.store(now, Ordering::Relaxed);
+	TSC_HZ.store(tsc_hz, Ordering::Relaxed);
+	klog!(Info, "time: invariant TSC at {} MHz", tsc_hz / 1_000_000);
+}
+
+/// A point on the monotonic clock, for measuring how long something takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+	pub fn now() -> Instant {
+		Instant(uptime())
+	}
+
+	/// Time since this instant
+	pub fn elapsed(&self) -> Duration {
+		Instant::now().duration_since(*self)
+	}
+
+	/// Time from `earlier` to this instant, or zero if `earlier` is later
+	pub fn duration_since(&self, earlier: Instant) -> Duration {
+		self.0.saturating_sub(earlier.0)
+	}
+
+	/// Time since boot at this instant
+	pub fn since_boot(&self) -> Duration {
+		self.0
+	}
+}
+
+impl Add<Duration> for Instant {
+	type Output = Instant;
+
+	fn add(self, duration: Duration) -> Instant {
+		Instant(self.0 + duration)
+	}
+}
+
+impl Sub for Instant {
+	type Output = Duration;
+
+	fn sub(self, earlier: Instant) -> Duration {
+		self.duration_since(earlier)
+	}
+}
+
+/// Busy-wait `ms` milliseconds, at most 54, on PIT channel 2 for calibrating
+/// other clocks against; interrupts should be off
+pub(crate) fn pit_wait_ms(ms: u64) {
+	let count = (PIT_FREQUENCY_HZ * ms / 1000).clamp(1, 0xffff) as u16;
+	let mut gate: Port<u8> = Port::new(PIT_GATE_PORT);
+	let mut command: Port<u8> = Port::new(PIT_COMMAND);
+	let mut channel: Port<u8> = Port::new(PIT_CHANNEL_2);
+	unsafe {
+		// Keep the speaker off, and hold the gate low while loading the count
+		let control = gate.read() & !(PIT_GATE | PIT_SPEAKER);
+		gate.write(control);
+		command.write(PIT_CHANNEL_2_ONE_SHOT);
+		channel.write(count as u8);
+		channel.write((count >> 8) as u8);
+		gate.write(control | PIT_GATE);
+		while gate.read() & PIT_OUTPUT == 0 {
+			core::hint::spin_loop();
+		}
+		gate.write(control);
+	}
+}
+
+/// Wall-clock time at uptime zero, reading the RTC if nothing has set it
+fn clock_epoch_ns() -> i64 {
+	let epoch = CLOCK_EPOCH_NS.load(Ordering::Relaxed);
+	if epoch != CLOCK_UNSET {
+		return epoch;
+	}
+	let epoch = rtc::timestamp() as i64 * NANOS_PER_SECOND as i64 - uptime().as_nanos() as i64;
+	match CLOCK_EPOCH_NS.compare_exchange(CLOCK_UNSET, epoch, Ordering::Relaxed, Ordering::Relaxed) {
+		Ok(_) => epoch,
+		Err(current) => current,
+	}
+}
+
+/// How much of the correction being slewed in is in by `uptime_ns`
+fn slewed_ns(uptime_ns: u64) -> i64 {
+	let elapsed = uptime_ns.saturating_sub(SLEW_START_NS.load(Ordering::Relaxed));
+	let limit = (elapsed as u128 * SLEW_RATE_NS as u128 / NANOS_PER_SECOND as u128).min(i64::MAX as u128) as i64;
+	SLEW_NS.load(Ordering::Relaxed).clamp(-limit, limit)
+}
+
+/// The system wall-clock time in nanoseconds since the Unix epoch
+pub fn now_ns() -> i64 {
+	let uptime_ns = uptime().as_nanos() as u64;
+	clock_epoch_ns() + uptime_ns as i64 + slewed_ns(uptime_ns)
+}
+
+/// The system wall-clock time in seconds since the Unix epoch
+pub fn now() -> u64 {
+	(now_ns().max(0) / NANOS_PER_SECOND as i64) as u64
+}
+
+/// Set the system wall clock; the RTC is left alone
+pub fn set_time(timestamp: u64) {
+	set_time_ns(timestamp as i64 * NANOS_PER_SECOND as i64);
+}
+
+/// Set the system wall clock to the nanosecond, dropping any correction
+/// being slewed in
+pub fn set_time_ns(timestamp_ns: i64) {
+	SLEW_NS.store(0, Ordering::Relaxed);
+	CLOCK_EPOCH_NS.store(timestamp_ns - uptime().as_nanos() as i64, Ordering::Relaxed);
+}
+
+/// Move the wall clock by `offset_ns` gradually, the way `adjtime` does,
+/// so it never jumps or runs backwards; replaces any correction still
+/// being slewed in, as `offset_ns` is measured against the clock as it is
+pub fn slew_time(offset_ns: i64) {
+	let uptime_ns = uptime().as_nanos() as u64;
+	let applied = slewed_ns(uptime_ns);
+	CLOCK_EPOCH_NS.store(clock_epoch_ns() + applied, Ordering::Relaxed);
+	SLEW_START_NS.store(uptime_ns, Ordering::Relaxed);
+	SLEW_NS.store(offset_ns, Ordering::Relaxed);
+}
+
+/// The part of the last correction not yet slewed in
+pub fn slew_remaining_ns() -> i64 {
+	SLEW_NS.load(Ordering::Relaxed) - slewed_ns(uptime().as_nanos() as u64)
+}
+
+/// Test epoch conversion, calendar rules, parsing, formatting, and instants
+#[test_case]
+fn test_calendar() {
+	let time = DateTime { year: 2024, month: 2, day: 29, hour: 13, minute: 5, second: 9 };
+	assert_eq!(time.timestamp(), 1_709_211_909);
+	assert_eq!(DateTime::from_timestamp(1_709_211_909), time);
+	assert_eq!(DateTime::from_timestamp(0).format("%F %T %A"), "1970-01-01 00:00:00 Thursday");
+	assert_eq!(time.format("%a %b %e %I:%M %p %j %%"), "Thu Feb 29 01:05 PM 060 %");
+	assert!(!DateTime { year: 2023, ..time }.is_valid());
+	assert_eq!(DateTime::parse("2026-10-14 08:30", &time).map(|t| t.format("%F %T")).as_deref(), Some("2026-10-14 08:30:00"));
+	assert_eq!(DateTime::parse("23:59:59", &time).map(|t| t.format("%F %T")).as_deref(), Some("2024-02-29 23:59:59"));
+	assert_eq!(DateTime::parse("2026-13-01", &time), None);
+	assert_eq!(DateTime::parse("12:61", &time), None);
+
+	let start = Instant::now();
+	let later = start + Duration::from_millis(5);
+	assert_eq!(later - start, Duration::from_millis(5));
+	assert_eq!(start - later, Duration::ZERO);
+	assert!(Instant::now() >= start);
+}
@@ -0,0 +1,184 @@
+use crate::{cmdline, klog};
+use crate::ring::{FixedText, Ring};
+use crate::sync::Mutex;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Number of events kept before the oldest is overwritten
+pub const TRACE_CAPACITY: usize = 1024;
+/// Longest message stored per event; longer messages are truncated
+pub const EVENT_LEN: usize = 64;
+
+/// Part of the kernel an event comes from, each traced or not on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Subsystem {
+	/// Task polls by the executor
+	Sched = 0,
+	/// IRQ handlers and how long they ran
+	Irq = 1,
+	/// Filesystem calls
+	Fs = 2,
+	/// System calls from user programs
+	Syscall = 3,
+	/// Packets sent and received
+	Net = 4,
+}
+
+impl Subsystem {
+	/// Every subsystem
+	pub const ALL: [Subsystem; 5] = [Subsystem::Sched, Subsystem::Irq, Subsystem::Fs, Subsystem::Syscall, Subsystem::Net];
+
+	/// Short name as used by `trace on` and `trace=`
+	pub fn name(self) -> &'static str {
+		match self {
+			Subsystem::Sched => "sched",
+			Subsystem::Irq => "irq",
+			Subsystem::Fs => "fs",
+			Subsystem::Syscall => "syscall",
+			Subsystem::Net => "net",
+		}
+	}
+
+	/// Look up a subsystem by name
+	pub fn from_name(name: &str) -> Option<Subsystem> {
+		Subsystem::ALL.into_iter().find(|subsystem| subsystem.name() == name)
+	}
+
+	fn bit(self) -> u32 {
+		1 << self as u8
+	}
+}
+
+/// One traced event
+#[derive(Clone, Copy)]
+pub struct Event {
+	/// The time stamp counter when it happened
+	pub tsc: u64,
+	pub cpu: u8,
+	pub subsystem: Subsystem,
+	text: FixedText<EVENT_LEN>,
+}
+
+impl Event {
+	const EMPTY: Event = Event { tsc: 0, cpu: 0, subsystem: Subsystem::Sched, text: FixedText::EMPTY };
+
+	/// The message text
+	pub fn message(&self) -> &str {
+		self.text.as_str()
+	}
+}
+
+/// Recent events, numbered by a running sequence
+static TRACE: Mutex<Ring<Event, TRACE_CAPACITY>> = Mutex::new(Ring::new(Event::EMPTY));
+
+/// The subsystems being traced, one bit each; none until asked for
+static ENABLED: AtomicU32 = AtomicU32::new(0);
+
+/// Whether events from `subsystem` are being recorded
+#[inline]
+pub fn enabled(subsystem: Subsystem) -> bool {
+	ENABLED.load(Ordering::Relaxed) & subsystem.bit() != 0
+}
+
+/// Start or stop recording events from `subsystem`
+pub fn set_enabled(subsystem: Subsystem, on: bool) {
+	if on {
+		ENABLED.fetch_or(subsystem.bit(), Ordering::Relaxed);
+	} else {
+		ENABLED.fetch_and(!subsystem.bit(), Ordering::Relaxed);
+	}
+}
+
+/// Turn on the subsystems named by `trace=`, a comma-separated list or `all`
+pub fn init() {
+	let Some(names) = cmdline::param("trace") else {
+		return;
+	};
+	for name in names.split(',').filter(|name| !name.is_empty()) {
+		if name == "all" {
+			Subsystem::ALL.into_iter().for_each(|subsystem| set_enabled(subsystem, true));
+		} else if let Some(subsystem) = Subsystem::from_name(name) {
+			set_enabled(subsystem, true);
+		} else {
+			klog!(Warn, "trace: unknown subsystem {}", name);
+		}
+	}
+}
+
+/// The time stamp counter, which events are stamped with
+#[inline]
+pub fn timestamp() -> u64 {
+	unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Record an event from `subsystem`; `trace_event!` checks it is enabled first
+///
+/// Does not allocate, so it is safe to use from interrupt handlers.
+pub fn record(subsystem: Subsystem, args: fmt::Arguments) {
+	let mut event = Event { tsc: timestamp(), cpu: crate::smp::current_cpu() as u8, subsystem, ..Event::EMPTY };
+	let _ = event.text.write_fmt(args);
+	x86_64::instructions::interrupts::without_interrupts(|| TRACE.lock().push(event));
+}
+
+/// The oldest event still held with sequence number `seq` or later
+pub fn read_from(seq: u64) -> Option<(u64, Event)> {
+	x86_64::instructions::interrupts::without_interrupts(|| TRACE.lock().read_from(seq))
+}
+
+/// Call `f` with each of the last `count` events, oldest first, unless the
+/// buffer is busy; for reports that cannot wait, such as from the timer interrupt
+pub fn try_recent(count: usize, mut f: impl FnMut(&Event)) -> bool {
+	let Some(trace) = TRACE.try_lock() else {
+		return false;
+	};
+	let mut seq = trace.next_seq().saturating_sub(count as u64);
+	while let Some((number, event)) = trace.read_from(seq) {
+		seq = number + 1;
+		f(&event);
+	}
+	true
+}
+
+/// Discard every held event
+pub fn clear() {
+	x86_64::instructions::interrupts::without_interrupts(|| TRACE.lock().clear());
+}
+
+/// Events recorded since boot, including those since overwritten
+pub fn recorded() -> u64 {
+	x86_64::instructions::interrupts::without_interrupts(|| TRACE.lock().next_seq())
+}
+
+/// Record an event if its subsystem is traced, formatting nothing otherwise:
+/// `trace_event!(Sched, "task {} woken", id)`
+#[macro_export]
+macro_rules! trace_event {
+	($subsystem:ident, $($arg:tt)*) => {
+		if $crate::trace::enabled($crate::trace::Subsystem::$subsystem) {
+			$crate::trace::record($crate::trace::Subsystem::$subsystem, format_args!($($arg)*));
+		}
+	};
+}
+
+/// Test enable masks, ring reads, and message truncation
+#[test_case]
+fn test_trace() {
+	let was_enabled = enabled(Subsystem::Net);
+	set_enabled(Subsystem::Net, true);
+	assert!(enabled(Subsystem::Net));
+	set_enabled(Subsystem::Net, false);
+	assert!(!enabled(Subsystem::Net));
+	set_enabled(Subsystem::Net, was_enabled);
+	assert_eq!(Subsystem::from_name("syscall"), Some(Subsystem::Syscall));
+	assert_eq!(Subsystem::from_name("disk"), None);
+
+	let start = recorded();
+	record(Subsystem::Net, format_args!("{}", "x".repeat(EVENT_LEN + 10)));
+	let (seq, event) = read_from(start).unwrap();
+	assert!(seq >= start);
+	if seq == start {
+		assert_eq!(event.subsystem, Subsystem::Net);
+		assert_eq!(event.message().len(), EVENT_LEN);
+	}
+}
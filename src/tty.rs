@@ -0,0 +1,249 @@
+use crate::print;
+use crate::syscall::SyscallError;
+use crate::task::keyboard::{self, KeyPress};
+use crate::task::serial;
+use crate::vga_buffer::CONSOLE_COUNT;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use pc_keyboard::{DecodedKey, KeyCode};
+use crate::sync::Mutex;
+
+/// Control characters the line discipline acts on
+const CTRL_C: char = '\x03';
+const CTRL_D: char = '\x04';
+const CTRL_U: char = '\x15';
+const BACKSPACE: char = '\x08';
+const DEL: char = '\x7f';
+
+/// Sent back to erase the character before the cursor
+const ERASE_ECHO: &str = "\x08 \x08";
+
+/// How a terminal treats what is typed before a program reads it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Termios {
+	/// Collect input into lines, with erase and kill; otherwise every key
+	/// is readable as soon as it is typed
+	pub canonical: bool,
+	/// Show what is typed
+	pub echo: bool,
+}
+
+impl Termios {
+	/// How a terminal starts out, and what `stty sane` goes back to
+	pub const SANE: Termios = Termios { canonical: true, echo: true };
+
+	/// In raw mode, with nothing echoed, as full-screen programs want it
+	pub const RAW: Termios = Termios { canonical: false, echo: false };
+}
+
+/// A console's line discipline: its settings, the line being typed, and
+/// input ready to read
+struct Tty {
+	termios: Termios,
+	/// The line being edited, not readable until Enter or Ctrl-D
+	line: String,
+	ready: VecDeque<u8>,
+	/// Set by Ctrl-D on an empty line, so the next read gets end of file
+	eof: bool,
+	/// Whether a command the user started is running, so reads wait for
+	/// keys rather than see end of file
+	foreground: bool,
+}
+
+impl Tty {
+	const fn new() -> Self {
+		Tty { termios: Termios::SANE, line: String::new(), ready: VecDeque::new(), eof: false, foreground: false }
+	}
+
+	/// Apply the line discipline to a key, adding what to show to `echo`
+	fn input(&mut self, press: KeyPress, echo: &mut String) {
+		match press.key {
+			DecodedKey::Unicode(c) if self.termios.canonical => self.canonical_char(c, echo),
+			DecodedKey::Unicode(c) => {
+				let mut bytes = [0; 4];
+				self.ready.extend(c.encode_utf8(&mut bytes).bytes());
+				if self.termios.echo && !c.is_control() {
+					echo.push(c);
+				}
+			}
+			// Edited lines have no use for cursor keys
+			DecodedKey::RawKey(_) if self.termios.canonical => {}
+			DecodedKey::RawKey(code) => {
+				if let Some(sequence) = key_sequence(code) {
+					self.ready.extend(sequence.bytes());
+				}
+			}
+		}
+	}
+
+	fn canonical_char(&mut self, c: char, echo: &mut String) {
+		let echoing = self.termios.echo;
+		match c {
+			'\n' | '\r' => {
+				self.line.push('\n');
+				self.ready.extend(self.line.bytes());
+				self.line.clear();
+				if echoing {
+					echo.push('\n');
+				}
+			}
+			BACKSPACE | DEL => {
+				if self.line.pop().is_some() && echoing {
+					echo.push_str(ERASE_ECHO);
+				}
+			}
+			CTRL_U => {
+				if echoing {
+					for _ in self.line.chars() {
+						echo.push_str(ERASE_ECHO);
+					}
+				}
+				self.line.clear();
+			}
+			// Ends the input on an empty line, otherwise hands over the line
+			// so far without a newline
+			CTRL_D if self.line.is_empty() => self.eof = true,
+			CTRL_D => {
+				self.ready.extend(self.line.bytes());
+				self.line.clear();
+			}
+			// The keyboard driver already flagged the interrupt
+			CTRL_C => {}
+			c if c.is_control() && c != '\t' => {}
+			c => {
+				self.line.push(c);
+				if echoing {
+					echo.push(c);
+				}
+			}
+		}
+	}
+
+	/// Move ready input into `buffer`: `Some(0)` at end of file, `None`
+	/// when nothing is ready yet
+	fn take(&mut self, buffer: &mut [u8]) -> Option<usize> {
+		if self.ready.is_empty() {
+			return core::mem::take(&mut self.eof).then_some(0);
+		}
+		let count = buffer.len().min(self.ready.len());
+		for (slot, byte) in buffer.iter_mut().zip(self.ready.drain(..count)) {
+			*slot = byte;
+		}
+		Some(count)
+	}
+}
+
+/// The escape sequence a terminal in raw mode sends for a key
+fn key_sequence(code: KeyCode) -> Option<&'static str> {
+	Some(match code {
+		KeyCode::ArrowUp => "\x1b[A",
+		KeyCode::ArrowDown => "\x1b[B",
+		KeyCode::ArrowRight => "\x1b[C",
+		KeyCode::ArrowLeft => "\x1b[D",
+		KeyCode::Home => "\x1b[H",
+		KeyCode::End => "\x1b[F",
+		KeyCode::Insert => "\x1b[2~",
+		KeyCode::Delete => "\x1b[3~",
+		KeyCode::PageUp => "\x1b[5~",
+		KeyCode::PageDown => "\x1b[6~",
+		_ => return None,
+	})
+}
+
+/// The line discipline of each console
+static TTYS: Mutex<[Tty; CONSOLE_COUNT]> = Mutex::new([const { Tty::new() }; CONSOLE_COUNT]);
+
+/// The settings of `console`'s terminal
+pub fn termios(console: usize) -> Termios {
+	TTYS.lock()[console].termios
+}
+
+/// Change the settings of `console`'s terminal; input already readable
+/// stays readable
+pub fn set_termios(console: usize, termios: Termios) {
+	TTYS.lock()[console].termios = termios;
+}
+
+/// Mark whether a command the user started on `console` is running
+///
+/// Only then do reads wait for the keyboard; scripts and background jobs
+/// see end of file, as they did before there was a terminal to read. Input
+/// a command left unread is dropped when it finishes.
+pub fn set_foreground(console: usize, foreground: bool) {
+	let mut ttys = TTYS.lock();
+	let tty = &mut ttys[console];
+	tty.foreground = foreground;
+	if !foreground {
+		tty.line.clear();
+		tty.ready.clear();
+		tty.eof = false;
+	}
+}
+
+/// Read what is typed on `console`, waiting until some is ready
+///
+/// Decodes keys itself, since the tasks that normally would cannot run
+/// while a command blocks the shell. Ctrl-C stops the wait.
+pub fn read(console: usize, buffer: &mut [u8]) -> Result<usize, SyscallError> {
+	if buffer.is_empty() {
+		return Ok(0);
+	}
+	loop {
+		keyboard::decode_pending();
+		serial::decode_pending();
+		let mut echo = String::new();
+		let taken = {
+			let mut ttys = TTYS.lock();
+			let tty = &mut ttys[console];
+			if !tty.foreground {
+				return Ok(0);
+			}
+			while let Some(press) = keyboard::take_key(console) {
+				tty.input(press, &mut echo);
+			}
+			tty.take(buffer)
+		};
+		print!("{}", echo);
+		if let Some(count) = taken {
+			return Ok(count);
+		}
+		if keyboard::interrupt_requested() {
+			return Err(SyscallError::InterruptedSystemCall);
+		}
+		x86_64::instructions::hlt();
+	}
+}
+
+/// Test line editing in canonical mode and key sequences in raw mode
+#[test_case]
+fn test_line_discipline() {
+	let mut tty = Tty::new();
+	let mut echo = String::new();
+	let type_str = |tty: &mut Tty, text: &str, echo: &mut String| {
+		for c in text.chars() {
+			tty.input(KeyPress::new(DecodedKey::Unicode(c)), echo);
+		}
+	};
+	let mut buffer = [0; 16];
+	type_str(&mut tty, "lq\x08s é\x7f\x7f", &mut echo);
+	assert_eq!(tty.take(&mut buffer), None);
+	type_str(&mut tty, "\n", &mut echo);
+	assert_eq!(echo, "lq\x08 \x08s é\x08 \x08\x08 \x08\n");
+	assert_eq!(tty.take(&mut buffer[..2]), Some(2));
+	assert_eq!(tty.take(&mut buffer[2..]), Some(1));
+	assert_eq!(&buffer[..3], b"ls\n");
+
+	type_str(&mut tty, "gone\x15ab\x04\x04", &mut echo);
+	assert_eq!(tty.take(&mut buffer), Some(2));
+	assert_eq!(&buffer[..2], b"ab");
+	assert_eq!(tty.take(&mut buffer), Some(0));
+	assert_eq!(tty.take(&mut buffer), None);
+
+	tty.termios = Termios::RAW;
+	echo.clear();
+	type_str(&mut tty, "q\x04", &mut echo);
+	tty.input(KeyPress::new(DecodedKey::RawKey(KeyCode::ArrowUp)), &mut echo);
+	assert_eq!(tty.take(&mut buffer), Some(5));
+	assert_eq!(&buffer[..5], b"q\x04\x1b[A");
+	assert!(echo.is_empty());
+}
@@ -0,0 +1,216 @@
+use crate::fs::{self, FsError};
+use crate::process::Credentials;
+use crate::sha256::{self, Sha256};
+use crate::{klog, random};
+use alloc::{format, string::{String, ToString}, vec::Vec};
+
+/// Where accounts, their passwords, and groups are kept
+pub const PASSWD: &str = "/etc/passwd";
+pub const SHADOW: &str = "/etc/shadow";
+pub const GROUP: &str = "/etc/group";
+
+/// Marks a password hashed by `hash_password`
+const SCHEME: &str = "$sha256$";
+/// Times the hash is applied, so guessing a password takes that much longer
+const ROUNDS: usize = 1000;
+
+/// Passwords set at first boot; an account not listed has none, and logs in
+/// without being asked for one
+const DEFAULT_PASSWORDS: &[(&str, &str)] = &[("guest", "guest")];
+
+/// An account in `/etc/passwd`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+	pub name: String,
+	pub uid: u32,
+	pub gid: u32,
+	pub home: String,
+	pub shell: String,
+}
+
+impl User {
+	/// Parse a `name:x:uid:gid:comment:home:shell` line
+	fn parse(line: &str) -> Option<User> {
+		let fields: Vec<&str> = line.split(':').collect();
+		let [name, _, uid, gid, _, home, shell] = fields[..] else {
+			return None;
+		};
+		Some(User {
+			name: name.to_string(),
+			uid: uid.parse().ok()?,
+			gid: gid.parse().ok()?,
+			home: home.to_string(),
+			shell: shell.to_string(),
+		})
+	}
+
+	/// What a process logged in as this user runs with
+	pub fn credentials(&self) -> Credentials {
+		let groups = groups()
+			.into_iter()
+			.filter(|group| group.gid != self.gid && group.members.contains(&self.name))
+			.map(|group| group.gid)
+			.collect();
+		Credentials { uid: self.uid, gid: self.gid, groups }
+	}
+}
+
+/// A group in `/etc/group`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+	pub name: String,
+	pub gid: u32,
+	/// Users in it besides those for whom it is the primary group
+	pub members: Vec<String>,
+}
+
+impl Group {
+	/// Parse a `name:x:gid:member,...` line
+	fn parse(line: &str) -> Option<Group> {
+		let fields: Vec<&str> = line.split(':').collect();
+		let [name, _, gid, members] = fields[..] else {
+			return None;
+		};
+		Some(Group {
+			name: name.to_string(),
+			gid: gid.parse().ok()?,
+			members: members.split(',').filter(|member| !member.is_empty()).map(String::from).collect(),
+		})
+	}
+}
+
+/// The lines of a file, skipping blank ones and comments; none if it is missing
+fn lines(path: &str) -> Vec<String> {
+	let text = fs::with_filesystem(|fs| fs.contents(path).map(|data| String::from_utf8_lossy(data).into_owned()));
+	text.unwrap_or_default()
+		.lines()
+		.filter(|line| !line.is_empty() && !line.starts_with('#'))
+		.map(String::from)
+		.collect()
+}
+
+/// Every account, in the order `/etc/passwd` lists them
+pub fn users() -> Vec<User> {
+	lines(PASSWD).iter().filter_map(|line| User::parse(line)).collect()
+}
+
+/// Every group, in the order `/etc/group` lists them
+pub fn groups() -> Vec<Group> {
+	lines(GROUP).iter().filter_map(|line| Group::parse(line)).collect()
+}
+
+/// The account called `name`
+pub fn by_name(name: &str) -> Option<User> {
+	users().into_iter().find(|user| user.name == name)
+}
+
+/// The account with user ID `uid`
+pub fn by_uid(uid: u32) -> Option<User> {
+	users().into_iter().find(|user| user.uid == uid)
+}
+
+/// The name of group `gid`
+pub fn group_name(gid: u32) -> Option<String> {
+	groups().into_iter().find(|group| group.gid == gid).map(|group| group.name)
+}
+
+/// Hash `password` with `salt` as `$sha256$SALT$DIGEST`, the form
+/// `/etc/shadow` keeps passwords in
+pub fn hash_password(password: &str, salt: &str) -> String {
+	let mut digest = [0; sha256::DIGEST_LEN];
+	for round in 0..ROUNDS {
+		let mut hasher = Sha256::new();
+		if round > 0 {
+			hasher.update(&digest);
+		}
+		hasher.update(salt.as_bytes());
+		hasher.update(password.as_bytes());
+		digest = hasher.finish();
+	}
+	let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+	format!("{}{}${}", SCHEME, salt, hex)
+}
+
+/// A salt for a new password, different each time one is set
+fn new_salt() -> String {
+	let mut salt = [0; 8];
+	random::fill(&mut salt);
+	salt.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Compare without stopping at the first difference, so how long it takes
+/// does not say how much of a guess was right
+fn same(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Whether `password` matches a hash from `/etc/shadow`: an empty one takes
+/// any password, and one in another form, such as `!` or `*`, none
+fn matches(hash: &str, password: &str) -> bool {
+	if hash.is_empty() {
+		return true;
+	}
+	let Some((salt, _)) = hash.strip_prefix(SCHEME).and_then(|rest| rest.split_once('$')) else {
+		return false;
+	};
+	same(hash_password(password, salt).as_bytes(), hash.as_bytes())
+}
+
+/// The hashed password of `name` in `/etc/shadow`
+fn shadow_hash(name: &str) -> Option<String> {
+	lines(SHADOW).into_iter().find_map(|line| {
+		let mut fields = line.split(':');
+		(fields.next() == Some(name)).then(|| fields.next().unwrap_or("").to_string())
+	})
+}
+
+/// Whether `name` can log in without a password
+pub fn needs_password(name: &str) -> bool {
+	shadow_hash(name).is_none_or(|hash| !hash.is_empty())
+}
+
+/// Whether `password` is `name`'s; an account missing from `/etc/shadow`
+/// cannot log in
+pub fn check_password(name: &str, password: &str) -> bool {
+	shadow_hash(name).is_some_and(|hash| matches(&hash, password))
+}
+
+/// Write `/etc/shadow` for the accounts in `/etc/passwd`, readable by root alone
+pub fn init() {
+	let mut shadow = String::new();
+	for user in users() {
+		let hash = match DEFAULT_PASSWORDS.iter().find(|&&(name, _)| name == user.name) {
+			Some(&(_, password)) => hash_password(password, &new_salt()),
+			None => String::new(),
+		};
+		shadow.push_str(&format!("{}:{}:\n", user.name, hash));
+	}
+	let created = fs::with_filesystem(|fs| {
+		fs.create_file(SHADOW.to_string(), shadow.into_bytes())?;
+		fs.set_permissions(SHADOW, 0o600)
+	});
+	match created {
+		Ok(()) | Err(FsError::AlreadyExists) => {}
+		Err(err) => klog!(Warn, "users: cannot write {}: {:?}", SHADOW, err),
+	}
+}
+
+/// Test parsing accounts and groups and checking passwords
+#[test_case]
+fn test_users() {
+	let user = User::parse("guest:x:1000:100:Guest:/home/guest:/bin/sh").unwrap();
+	assert_eq!((user.name.as_str(), user.uid, user.gid, user.home.as_str()), ("guest", 1000, 100, "/home/guest"));
+	assert_eq!(User::parse("guest:x:many:100:Guest:/home/guest:/bin/sh"), None);
+	assert_eq!(User::parse("guest:x:1000"), None);
+	let group = Group::parse("wheel:x:10:root,guest").unwrap();
+	assert_eq!((group.gid, group.members.len()), (10, 2));
+	assert!(Group::parse("empty:x:11:").unwrap().members.is_empty());
+
+	let hash = hash_password("hunter2", "salt");
+	assert!(hash.starts_with("$sha256$salt$"));
+	assert!(matches(&hash, "hunter2"));
+	assert!(!matches(&hash, "hunter3"));
+	assert_ne!(hash_password("hunter2", "pepper"), hash);
+	assert!(matches("", "anything"));
+	assert!(!matches("!", ""));
+}
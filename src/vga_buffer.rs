@@ -1,7 +1,13 @@
+use crate::console::{self, Console};
+use crate::cp437;
+use crate::sync::SpinLockIrqSave;
 use volatile::Volatile;
+use alloc::{boxed::Box, collections::VecDeque, string::String};
 use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::lazy_static;
-use spin::Mutex;
+use crate::sync::Mutex;
 
 /// Standard VGA colors
 #[allow(dead_code)]
@@ -26,6 +32,88 @@ pub enum Color {
 	White = 15,
 }
 
+impl Color {
+	/// Every color in VGA palette order
+	pub const ALL: [Color; 16] = [
+		Color::Black, Color::Blue, Color::Green, Color::Cyan,
+		Color::Red, Color::Magenta, Color::Brown, Color::LightGray,
+		Color::DarkGray, Color::LightBlue, Color::LightGreen, Color::LightCyan,
+		Color::LightRed, Color::Pink, Color::Yellow, Color::White,
+	];
+
+	/// The ANSI color number that is this color, bright ones from 8
+	pub fn ansi_code(self) -> u8 {
+		let code = |colors: &[Color; 8]| colors.iter().position(|&color| color == self);
+		match code(&ANSI_COLORS) {
+			Some(code) => code as u8,
+			None => code(&ANSI_BRIGHT_COLORS).map_or(7, |code| code as u8 + 8),
+		}
+	}
+
+	/// Name as accepted by the `color` command
+	pub fn name(self) -> &'static str {
+		match self {
+			Color::Black => "black",
+			Color::Blue => "blue",
+			Color::Green => "green",
+			Color::Cyan => "cyan",
+			Color::Red => "red",
+			Color::Magenta => "magenta",
+			Color::Brown => "brown",
+			Color::LightGray => "light-gray",
+			Color::DarkGray => "dark-gray",
+			Color::LightBlue => "light-blue",
+			Color::LightGreen => "light-green",
+			Color::LightCyan => "light-cyan",
+			Color::LightRed => "light-red",
+			Color::Pink => "pink",
+			Color::Yellow => "yellow",
+			Color::White => "white",
+		}
+	}
+
+	/// Look up a color by name (with or without the hyphen) or palette number
+	pub fn from_name(name: &str) -> Option<Color> {
+		if let Ok(n) = name.parse::<usize>() {
+			return Color::ALL.get(n).copied();
+		}
+		Color::ALL.into_iter().find(|color| color.name() == name || color.name().replace('-', "") == name)
+	}
+}
+
+/// Colors used for ordinary console text by the default theme
+pub const DEFAULT_FOREGROUND: Color = Color::Yellow;
+pub const DEFAULT_BACKGROUND: Color = Color::Black;
+
+/// A console color scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+	pub name: &'static str,
+	pub foreground: Color,
+	pub background: Color,
+	/// Foreground for error messages
+	pub error: Color,
+	/// Foreground for warnings
+	pub warning: Color,
+}
+
+/// Built-in themes; the first is the default
+pub const THEMES: &[Theme] = &[
+	Theme { name: "default", foreground: DEFAULT_FOREGROUND, background: DEFAULT_BACKGROUND, error: Color::LightRed, warning: Color::White },
+	Theme { name: "classic", foreground: Color::LightGray, background: Color::Black, error: Color::LightRed, warning: Color::Yellow },
+	Theme { name: "light", foreground: Color::Black, background: Color::LightGray, error: Color::Red, warning: Color::Brown },
+	Theme { name: "matrix", foreground: Color::LightGreen, background: Color::Black, error: Color::LightRed, warning: Color::Yellow },
+	Theme { name: "ocean", foreground: Color::White, background: Color::Blue, error: Color::LightRed, warning: Color::Yellow },
+];
+
+/// Index into `THEMES` of the theme in use
+static THEME: AtomicUsize = AtomicUsize::new(0);
+
+/// The theme in use
+pub fn theme() -> &'static Theme {
+	&THEMES[THEME.load(Ordering::Relaxed)]
+}
+
 /// Color code combining foreground and background colors
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
@@ -33,9 +121,14 @@ struct ColorCode(u8);
 
 impl ColorCode {
 	/// Create new color code with foreground and background colors
-	fn new(foreground: Color, background: Color) -> ColorCode {
+	const fn new(foreground: Color, background: Color) -> ColorCode {
 		ColorCode((background as u8) << 4 | (foreground as u8))
 	}
+
+	/// The foreground and background colors
+	fn colors(self) -> (Color, Color) {
+		(Color::ALL[(self.0 & 0xf) as usize], Color::ALL[(self.0 >> 4) as usize])
+	}
 }
 
 /// VGA character with color information
@@ -46,9 +139,35 @@ struct ScreenChar {
 	color_code: ColorCode,
 }
 
-/// VGA text buffer dimensions
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+/// A blank cell in the default colors
+const BLANK: ScreenChar = ScreenChar {
+	ascii_character: b' ',
+	color_code: ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+};
+
+impl ScreenChar {
+	/// Draw this cell on a display
+	fn draw(self, display: &mut dyn Console, row: usize, col: usize) {
+		let (foreground, background) = self.color_code.colors();
+		display.draw(row, col, self.ascii_character, foreground, background);
+	}
+}
+
+/// VGA text buffer dimensions, the screen size until a framebuffer takes over
+pub const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
+/// Largest screen in character cells a console can have
+pub const MAX_ROWS: usize = 64;
+pub const MAX_COLUMNS: usize = 160;
+
+/// Screens of text kept for scrolling back
+pub const SCROLLBACK_SCREENS: usize = 4;
+
+/// One row of screen cells kept in the scrollback history
+type Line = Box<[ScreenChar]>;
+
+/// Address of the VGA text buffer
+const VGA_BUFFER: usize = 0xb8000;
 
 /// VGA text buffer structure
 #[repr(transparent)]
@@ -56,28 +175,236 @@ struct Buffer {
 	chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+/// The VGA text mode screen
+///
+/// Zero-sized, so boxing it for the first console needs no heap.
+pub struct VgaText;
+
+impl VgaText {
+	fn buffer(&mut self) -> &mut Buffer {
+		unsafe { &mut *(VGA_BUFFER as *mut Buffer) }
+	}
+}
+
+impl Console for VgaText {
+	fn size(&self) -> (usize, usize) {
+		(BUFFER_HEIGHT, BUFFER_WIDTH)
+	}
+
+	fn draw(&mut self, row: usize, col: usize, byte: u8, foreground: Color, background: Color) {
+		if row < BUFFER_HEIGHT && col < BUFFER_WIDTH {
+			self.buffer().chars[row][col].write(ScreenChar {
+				ascii_character: byte,
+				color_code: ColorCode::new(foreground, background),
+			});
+		}
+	}
+
+	fn scroll_up(&mut self, rows: Range<usize>) {
+		let buffer = self.buffer();
+		for row in rows.start + 1..rows.end.min(BUFFER_HEIGHT) {
+			for col in 0..BUFFER_WIDTH {
+				let character = buffer.chars[row][col].read();
+				buffer.chars[row - 1][col].write(character);
+			}
+		}
+	}
+
+	fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+		match cursor {
+			Some((row, col)) => {
+				set_cursor(row, col);
+				show_cursor();
+			}
+			None => hide_cursor(),
+		}
+	}
+}
+
+/// A console's screen contents, kept whether or not it is displayed
+struct Grid {
+	cells: [[ScreenChar; MAX_COLUMNS]; MAX_ROWS],
+}
+
+impl Grid {
+	const BLANK: Grid = Grid { cells: [[BLANK; MAX_COLUMNS]; MAX_ROWS] };
+}
+
+/// Screen of the first console, which prints before the heap exists
+static mut BOOT_GRID: Grid = Grid::BLANK;
+
+/// Size of every console's screen in character cells
+static SCREEN_ROWS: AtomicUsize = AtomicUsize::new(BUFFER_HEIGHT);
+static SCREEN_COLUMNS: AtomicUsize = AtomicUsize::new(BUFFER_WIDTH);
+
+/// Rows and columns of the consoles' screens
+pub fn screen_size() -> (usize, usize) {
+	(SCREEN_ROWS.load(Ordering::Relaxed), SCREEN_COLUMNS.load(Ordering::Relaxed))
+}
+
+/// Number of virtual consoles, switched between with Alt+F1 and up
+pub const CONSOLE_COUNT: usize = 4;
+
+/// ANSI colors 0-7 and their bright variants as VGA colors
+const ANSI_COLORS: [Color; 8] = [
+	Color::Black, Color::Red, Color::Green, Color::Brown,
+	Color::Blue, Color::Magenta, Color::Cyan, Color::LightGray,
+];
+const ANSI_BRIGHT_COLORS: [Color; 8] = [
+	Color::DarkGray, Color::LightRed, Color::LightGreen, Color::Yellow,
+	Color::LightBlue, Color::Pink, Color::LightCyan, Color::White,
+];
+
+/// Escape character that starts an ANSI control sequence
+const ESC: u8 = 0x1b;
+/// Most numeric parameters kept from one control sequence
+const MAX_PARAMS: usize = 8;
+
+/// Current text colors while interpreting SGR escape sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Style {
+	foreground: Option<usize>,
+	background: Option<usize>,
+	bold: bool,
+}
+
+impl Style {
+	const DEFAULT: Style = Style { foreground: None, background: None, bold: false };
+
+	/// Apply the parameters of one `ESC [ ... m` sequence
+	fn apply(&mut self, params: &[u16]) {
+		for &param in params {
+			match param as usize {
+				0 => *self = Style::DEFAULT,
+				1 => self.bold = true,
+				22 => self.bold = false,
+				n @ 30..=37 => self.foreground = Some(n - 30),
+				39 => self.foreground = None,
+				n @ 40..=47 => self.background = Some(n - 40),
+				49 => self.background = None,
+				n @ 90..=97 => self.foreground = Some(n - 90 + 8),
+				n @ 100..=107 => self.background = Some(n - 100 + 8),
+				_ => {}
+			}
+		}
+	}
+
+	/// The VGA color code for this style, given the console's default colors
+	fn color_code(&self, (default_foreground, default_background): (Color, Color)) -> ColorCode {
+		let foreground = match self.foreground {
+			Some(n) if n >= 8 || self.bold => ANSI_BRIGHT_COLORS[n % 8],
+			Some(n) => ANSI_COLORS[n],
+			None => default_foreground,
+		};
+		let background = match self.background {
+			Some(n) if n >= 8 => ANSI_BRIGHT_COLORS[n % 8],
+			Some(n) => ANSI_COLORS[n],
+			None => default_background,
+		};
+		ColorCode::new(foreground, background)
+	}
+}
+
+/// How far the writer is through an escape sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+	/// Ordinary text
+	Ground,
+	/// After `ESC`
+	Escape,
+	/// Inside `ESC [`, collecting parameters until the final byte
+	Csi,
+}
+
 /// VGA writer for managing text output
+///
+/// Understands the common VT100/ANSI control sequences: cursor movement
+/// (`ESC[nA`-`D`, `E`, `F`, `G`, `d`, `H`), erasing (`ESC[nJ`, `ESC[nK`),
+/// colors (`ESC[...m`), saving/restoring the cursor (`ESC[s`/`ESC[u`,
+/// `ESC 7`/`ESC 8`), and showing/hiding it (`ESC[?25h`/`ESC[?25l`).
 pub struct Writer {
 	column_position: usize,
+	row_position: usize,
 	color_code: ColorCode,
-	buffer: &'static mut Buffer,
+	style: Style,
+	/// Colors of text with no SGR colors set
+	default_colors: (Color, Color),
+	saved_position: (usize, usize),
+	escape: EscapeState,
+	params: [u16; MAX_PARAMS],
+	/// Index of the parameter being collected
+	param_index: usize,
+	/// Whether the sequence has a `?` marker (DEC private mode)
+	private: bool,
+	/// Screen size in character cells
+	rows: usize,
+	cols: usize,
+	/// Display row the screen starts at, below the status line
+	origin: usize,
+	/// Lines scrolled off the top, oldest first; `None` until the heap is ready
+	history: Option<VecDeque<Line>>,
+	/// How many lines back from the live screen the view is scrolled
+	view_offset: usize,
+	/// Whether this console wants the cursor shown
+	cursor_visible: bool,
+	/// The live screen contents
+	grid: &'static mut Grid,
+	/// Where the screen is drawn while this console is the one displayed
+	display: Option<Box<dyn Console>>,
 }
 
 impl Writer {
-	/// Write a single byte to the VGA buffer
+	/// A writer at the bottom line of `grid` in the default colors, drawing
+	/// on `display` if it is the console on screen
+	fn new(grid: &'static mut Grid, display: Option<Box<dyn Console>>) -> Writer {
+		let (rows, cols) = screen_size();
+		Writer {
+			column_position: 0,
+			row_position: rows - 1,
+			color_code: Style::DEFAULT.color_code((DEFAULT_FOREGROUND, DEFAULT_BACKGROUND)),
+			style: Style::DEFAULT,
+			default_colors: (DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+			saved_position: (rows - 1, 0),
+			escape: EscapeState::Ground,
+			params: [0; MAX_PARAMS],
+			param_index: 0,
+			private: false,
+			rows,
+			cols,
+			origin: STATUS_ROWS.load(Ordering::Relaxed),
+			history: None,
+			view_offset: 0,
+			cursor_visible: true,
+			grid,
+			display,
+		}
+	}
+
+	/// Write a single byte to the VGA buffer, interpreting control characters
+	/// and escape sequences
 	pub fn write_byte(&mut self, byte: u8) {
+		self.reset_view();
+		match self.escape {
+			EscapeState::Ground => {}
+			EscapeState::Escape => return self.escape_byte(byte),
+			EscapeState::Csi => return self.csi_byte(byte),
+		}
 		match byte {
+			ESC => self.escape = EscapeState::Escape,
 			b'\n' => self.new_line(),
+			b'\r' => self.column_position = 0,
+			// Backspace moves left without erasing
+			0x08 => self.column_position = self.column_position.saturating_sub(1),
 			byte => {
-				if self.column_position >= BUFFER_WIDTH {
+				if self.column_position >= self.cols {
 					self.new_line();
 				}
 
-				let row = BUFFER_HEIGHT - 1;
+				let row = self.row_position;
 				let col = self.column_position;
 
 				let color_code = self.color_code;
-				self.buffer.chars[row][col].write(ScreenChar {
+				self.set_cell(row, col, ScreenChar {
 					ascii_character: byte,
 					color_code,
 				});
@@ -86,38 +413,368 @@ impl Writer {
 		}
 	}
 
-	/// Write a string to the VGA buffer
+	/// Write a string to the VGA buffer, drawing other characters than ASCII
+	/// with their code page 437 glyphs
 	pub fn write_string(&mut self, s: &str) {
-		for byte in s.bytes() {
-			match byte {
-				// Printable ASCII byte or newline
-				0x20..=0x7e | b'\n' => self.write_byte(byte),
-				// Not part of printable ASCII range
-				_ => self.write_byte(0xfe),
+		for c in s.chars() {
+			match cp437::encode(c) {
+				// Printable character or a control character the writer handles
+				byte @ (0x20..=0x7e | 0x80..=0xff | b'\n' | b'\r' | 0x08 | ESC) => self.write_byte(byte),
+				// Other control characters
+				_ => self.write_byte(cp437::UNKNOWN),
 			}
 		}
 	}
 
-	/// Create a new line by scrolling and moving cursor
-	fn new_line(&mut self) {
-		for row in 1..BUFFER_HEIGHT {
-			for col in 0..BUFFER_WIDTH {
-				let character = self.buffer.chars[row][col].read();
-				self.buffer.chars[row - 1][col].write(character);
+	/// Handle the byte after `ESC`
+	fn escape_byte(&mut self, byte: u8) {
+		self.escape = EscapeState::Ground;
+		match byte {
+			b'[' => {
+				self.params = [0; MAX_PARAMS];
+				self.param_index = 0;
+				self.private = false;
+				self.escape = EscapeState::Csi;
 			}
+			b'7' => self.saved_position = (self.row_position, self.column_position),
+			b'8' => (self.row_position, self.column_position) = self.saved_position,
+			b'c' => {
+				self.style = Style::DEFAULT;
+				self.color_code = self.style.color_code(self.default_colors);
+				self.clear_screen();
+			}
+			_ => {}
+		}
+	}
+
+	/// Collect a parameter byte of a control sequence, or carry it out on
+	/// its final byte
+	fn csi_byte(&mut self, byte: u8) {
+		match byte {
+			b'0'..=b'9' => {
+				if let Some(param) = self.params.get_mut(self.param_index) {
+					*param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+				}
+			}
+			b';' => self.param_index += 1,
+			b'?' => self.private = true,
+			// Other private markers and intermediate bytes
+			b'<'..=b'>' | 0x20..=0x2f => {}
+			0x40..=0x7e => {
+				self.escape = EscapeState::Ground;
+				self.execute_csi(byte);
+			}
+			// Anything else cancels the sequence
+			_ => self.escape = EscapeState::Ground,
+		}
+	}
+
+	/// Parameter `index` of the current sequence, with 0 or missing meaning `default`
+	fn param(&self, index: usize, default: usize) -> usize {
+		match self.params.get(index).copied().unwrap_or(0) {
+			0 => default,
+			n => n as usize,
 		}
-		self.clear_row(BUFFER_HEIGHT - 1);
+	}
+
+	/// Carry out a complete `ESC [ params final` sequence
+	fn execute_csi(&mut self, final_byte: u8) {
+		let n = self.param(0, 1);
+		let (rows, cols) = (self.rows, self.cols);
+		let last_row = rows - 1;
+		let last_col = cols - 1;
+		if self.private {
+			// `ESC[?25h` shows the cursor and `ESC[?25l` hides it
+			match (self.params[0], final_byte) {
+				(25, b'h') => self.set_cursor_visible(true),
+				(25, b'l') => self.set_cursor_visible(false),
+				_ => {}
+			}
+			return;
+		}
+		match final_byte {
+			b'A' => self.row_position = self.row_position.saturating_sub(n),
+			b'B' => self.row_position = (self.row_position + n).min(last_row),
+			b'C' => self.column_position = (self.column_position + n).min(last_col),
+			b'D' => self.column_position = self.column_position.min(last_col).saturating_sub(n),
+			b'E' => {
+				self.row_position = (self.row_position + n).min(last_row);
+				self.column_position = 0;
+			}
+			b'F' => {
+				self.row_position = self.row_position.saturating_sub(n);
+				self.column_position = 0;
+			}
+			b'G' => self.column_position = (n - 1).min(last_col),
+			b'd' => self.row_position = (n - 1).min(last_row),
+			b'H' | b'f' => {
+				self.row_position = (n - 1).min(last_row);
+				self.column_position = (self.param(1, 1) - 1).min(last_col);
+			}
+			b'J' => {
+				let (row, col) = (self.row_position, self.column_position.min(cols));
+				match self.params[0] {
+					0 => {
+						self.clear_cells(row, col..cols);
+						(row + 1..rows).for_each(|row| self.clear_row(row));
+					}
+					1 => {
+						(0..row).for_each(|row| self.clear_row(row));
+						self.clear_cells(row, 0..(col + 1).min(cols));
+					}
+					_ => (0..rows).for_each(|row| self.clear_row(row)),
+				}
+			}
+			b'K' => {
+				let (row, col) = (self.row_position, self.column_position.min(cols));
+				match self.params[0] {
+					0 => self.clear_cells(row, col..cols),
+					1 => self.clear_cells(row, 0..(col + 1).min(cols)),
+					_ => self.clear_row(row),
+				}
+			}
+			b'm' => {
+				let count = (self.param_index + 1).min(MAX_PARAMS);
+				let params = self.params;
+				self.style.apply(&params[..count]);
+				self.color_code = self.style.color_code(self.default_colors);
+			}
+			b's' => self.saved_position = (self.row_position, self.column_position),
+			b'u' => (self.row_position, self.column_position) = self.saved_position,
+			_ => {}
+		}
+	}
+
+	/// Create a new line, scrolling once the bottom row is reached
+	fn new_line(&mut self) {
 		self.column_position = 0;
+		if self.row_position < self.rows - 1 {
+			self.row_position += 1;
+			return;
+		}
+		if let Some(history) = self.history.as_mut() {
+			while history.len() >= SCROLLBACK_SCREENS * self.rows {
+				history.pop_front();
+			}
+			history.push_back(self.grid.cells[0][..self.cols].into());
+		}
+		self.grid.cells.copy_within(1..self.rows, 0);
+		if let Some(display) = self.display.as_mut() {
+			display.scroll_up(self.origin..self.origin + self.rows);
+		}
+		self.clear_row(self.rows - 1);
+	}
+
+	/// Store a cell of the live screen, drawing it if this console is displayed
+	fn set_cell(&mut self, row: usize, col: usize, cell: ScreenChar) {
+		self.grid.cells[row][col] = cell;
+		if let Some(display) = self.display.as_mut() {
+			cell.draw(&mut **display, self.origin + row, col);
+		}
+	}
+
+	/// Draw the whole live screen, if this console is displayed
+	fn redraw(&mut self) {
+		let Some(display) = self.display.as_mut() else {
+			return;
+		};
+		for (row, line) in self.grid.cells[..self.rows].iter().enumerate() {
+			for (col, &cell) in line[..self.cols].iter().enumerate() {
+				cell.draw(&mut **display, self.origin + row, col);
+			}
+		}
+	}
+
+	/// Start keeping lines that scroll off the top; needs the heap
+	pub fn enable_scrollback(&mut self) {
+		if self.history.is_none() {
+			self.history = Some(VecDeque::with_capacity(SCROLLBACK_SCREENS * self.rows));
+		}
+	}
+
+	/// Scroll the view back through history by `lines` (forward if negative),
+	/// without disturbing the live screen underneath
+	pub fn scroll_view(&mut self, lines: isize) {
+		let Some(history) = self.history.as_ref() else {
+			return;
+		};
+		let offset = self.view_offset.saturating_add_signed(lines).min(history.len());
+		if offset == 0 {
+			self.reset_view();
+			return;
+		}
+		self.view_offset = offset;
+		self.render_view();
+	}
+
+	/// Show the window `view_offset` lines back from the live screen
+	fn render_view(&mut self) {
+		let (Some(history), Some(display)) = (self.history.as_ref(), self.display.as_mut()) else {
+			return;
+		};
+		let first = history.len() - self.view_offset;
+		for row in 0..self.rows {
+			for col in 0..self.cols {
+				// Lines kept before a resize may be narrower than the screen
+				let cell = match history.get(first + row) {
+					Some(line) => line.get(col).copied().unwrap_or(BLANK),
+					None => self.grid.cells[first + row - history.len()][col],
+				};
+				cell.draw(&mut **display, self.origin + row, col);
+			}
+		}
+	}
+
+	/// Return to the live screen if the view is scrolled back
+	fn reset_view(&mut self) {
+		if self.view_offset == 0 {
+			return;
+		}
+		self.view_offset = 0;
+		self.redraw();
+	}
+
+	/// Set the colors used for subsequent text
+	pub fn set_color(&mut self, foreground: Color, background: Color) {
+		self.color_code = ColorCode::new(foreground, background);
+	}
+
+	/// Change the colors of text with no SGR colors set, repainting the
+	/// cells already drawn in the old ones
+	pub fn set_default_colors(&mut self, foreground: Color, background: Color) {
+		self.reset_view();
+		let old = ColorCode::new(self.default_colors.0, self.default_colors.1);
+		let new = ColorCode::new(foreground, background);
+		for row in 0..self.rows {
+			for col in 0..self.cols {
+				let cell = self.grid.cells[row][col];
+				if cell.color_code == old {
+					self.set_cell(row, col, ScreenChar { color_code: new, ..cell });
+				}
+			}
+		}
+		self.default_colors = (foreground, background);
+		self.color_code = self.style.color_code(self.default_colors);
+	}
+
+	/// The colors of text with no SGR colors set
+	pub fn default_colors(&self) -> (Color, Color) {
+		self.default_colors
+	}
+
+	/// Put a character at a fixed screen position without moving the text cursor
+	pub fn put_char(&mut self, row: usize, col: usize, byte: u8, foreground: Color, background: Color) {
+		self.reset_view();
+		if row < self.rows && col < self.cols {
+			let byte = if matches!(byte, 0x20..=0x7e | 0x80..=0xff) { byte } else { cp437::UNKNOWN };
+			self.set_cell(row, col, ScreenChar {
+				ascii_character: byte,
+				color_code: ColorCode::new(foreground, background),
+			});
+		}
+	}
+
+	/// Write text at a fixed screen position without moving the text cursor,
+	/// cutting it off at the end of the row
+	pub fn write_at(&mut self, row: usize, col: usize, s: &str, foreground: Color, background: Color) {
+		for (col, c) in (col..self.cols).zip(s.chars()) {
+			self.put_char(row, col, cp437::encode(c), foreground, background);
+		}
+	}
+
+	/// Move the text cursor, clamped to the screen
+	pub fn set_position(&mut self, row: usize, col: usize) {
+		self.row_position = row.min(self.rows - 1);
+		self.column_position = col.min(self.cols - 1);
+		self.sync_cursor();
+	}
+
+	/// The row and column where the next character will be written
+	pub fn position(&self) -> (usize, usize) {
+		(self.row_position, self.column_position)
+	}
+
+	/// Rows and columns of this console's screen
+	pub fn size(&self) -> (usize, usize) {
+		(self.rows, self.cols)
+	}
+
+	/// Blank the whole screen and start writing at the top left again
+	pub fn clear_screen(&mut self) {
+		self.reset_view();
+		for row in 0..self.rows {
+			self.clear_row(row);
+		}
+		self.set_position(0, 0);
+	}
+
+	/// Show or hide the cursor, for full-screen programs that draw their own
+	pub fn set_cursor_visible(&mut self, visible: bool) {
+		self.cursor_visible = visible;
+		self.sync_cursor();
+	}
+
+	/// Move the display's cursor to where the next character will be written
+	///
+	/// Does nothing while this writer's console is not the one on screen.
+	pub fn sync_cursor(&mut self) {
+		let cursor = (self.origin + self.row_position, self.column_position.min(self.cols - 1));
+		let visible = self.cursor_visible;
+		if let Some(display) = self.display.as_mut() {
+			display.set_cursor(visible.then_some(cursor));
+		}
+	}
+
+	/// Trade screens with another console's writer, each drawing its own
+	/// text on the display it ends up with
+	fn swap_screens(&mut self, other: &mut Writer) {
+		self.reset_view();
+		other.reset_view();
+		core::mem::swap(&mut self.display, &mut other.display);
+		self.redraw();
+		other.redraw();
+	}
+
+	/// Move the screen to start at display row `origin` and change its
+	/// size, keeping the text that still fits and blanking the area that is new
+	fn layout(&mut self, origin: usize, rows: usize, cols: usize) {
+		self.reset_view();
+		let blank = ScreenChar {
+			ascii_character: b' ',
+			color_code: ColorCode::new(self.default_colors.0, self.default_colors.1),
+		};
+		// Scroll the text up rather than lose the cursor's line
+		let shift = (self.row_position + 1).saturating_sub(rows);
+		self.grid.cells.copy_within(shift..self.rows, 0);
+		self.row_position -= shift;
+		let kept_rows = self.rows - shift;
+		for (row, line) in self.grid.cells[..rows].iter_mut().enumerate() {
+			let kept = if row < kept_rows { self.cols.min(cols) } else { 0 };
+			line[kept..cols].fill(blank);
+		}
+		self.origin = origin;
+		self.rows = rows;
+		self.cols = cols;
+		self.row_position = self.row_position.min(rows - 1);
+		self.column_position = self.column_position.min(cols);
+		self.saved_position = (self.saved_position.0.min(rows - 1), self.saved_position.1.min(cols - 1));
+		self.redraw();
+		self.sync_cursor();
 	}
 
 	/// Clear a specific row
 	fn clear_row(&mut self, row: usize) {
+		self.clear_cells(row, 0..self.cols);
+	}
+
+	/// Blank some columns of a row in the current colors
+	fn clear_cells(&mut self, row: usize, cols: core::ops::Range<usize>) {
 		let blank = ScreenChar {
 			ascii_character: b' ',
 			color_code: self.color_code,
 		};
-		for col in 0..BUFFER_WIDTH {
-			self.buffer.chars[row][col].write(blank);
+		for col in cols {
+			self.set_cell(row, col, blank);
 		}
 	}
 }
@@ -129,15 +786,331 @@ impl fmt::Write for Writer {
 	}
 }
 
+/// CRT controller index and data ports
+const CRTC_INDEX: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+/// CRT controller registers for the cursor shape and location
+const CRTC_CURSOR_START: u8 = 0x0a;
+const CRTC_CURSOR_END: u8 = 0x0b;
+const CRTC_CURSOR_HIGH: u8 = 0x0e;
+const CRTC_CURSOR_LOW: u8 = 0x0f;
+/// Cursor start register bit that turns the cursor off
+const CURSOR_DISABLE: u8 = 0x20;
+/// Scanlines of the default underline cursor in a 16-line character cell
+const CURSOR_SCANLINES: (u8, u8) = (14, 15);
+
+/// Read a CRT controller register
+fn crtc_read(register: u8) -> u8 {
+	use x86_64::instructions::port::Port;
+
+	unsafe {
+		Port::<u8>::new(CRTC_INDEX).write(register);
+		Port::<u8>::new(CRTC_DATA).read()
+	}
+}
+
+/// Write a CRT controller register
+fn crtc_write(register: u8, value: u8) {
+	use x86_64::instructions::port::Port;
+
+	unsafe {
+		Port::<u8>::new(CRTC_INDEX).write(register);
+		Port::<u8>::new(CRTC_DATA).write(value);
+	}
+}
+
+/// Move the blinking hardware cursor to a screen position
+pub fn set_cursor(row: usize, col: usize) {
+	let position = (row * BUFFER_WIDTH + col) as u16;
+	crtc_write(CRTC_CURSOR_LOW, position as u8);
+	crtc_write(CRTC_CURSOR_HIGH, (position >> 8) as u8);
+}
+
+/// Turn the hardware cursor on, covering scanlines `start..=end` of each cell
+pub fn enable_cursor(start: u8, end: u8) {
+	crtc_write(CRTC_CURSOR_START, (crtc_read(CRTC_CURSOR_START) & 0xc0) | (start & 0x1f));
+	crtc_write(CRTC_CURSOR_END, (crtc_read(CRTC_CURSOR_END) & 0xe0) | (end & 0x1f));
+}
+
+/// Show the hardware cursor as an underline
+pub fn show_cursor() {
+	enable_cursor(CURSOR_SCANLINES.0, CURSOR_SCANLINES.1);
+}
+
+/// Hide the hardware cursor, for full-screen programs that draw their own
+pub fn hide_cursor() {
+	crtc_write(CRTC_CURSOR_START, CURSOR_DISABLE);
+}
+
 lazy_static! {
-	/// Global VGA writer instance
-	pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-		column_position: 0,
-		color_code: ColorCode::new(Color::Yellow, Color::Black),
-		buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+	/// Writer of the console that output currently goes to
+	pub static ref WRITER: SpinLockIrqSave<Writer> = SpinLockIrqSave::new(Writer::new(unsafe { &mut *core::ptr::addr_of_mut!(BOOT_GRID) }, Some(Box::new(VgaText))));
+}
+
+/// Writers of the consoles output is not going to; the selected one is in `WRITER`
+static CONSOLES: Mutex<[Option<Writer>; CONSOLE_COUNT]> = Mutex::new([const { None }; CONSOLE_COUNT]);
+/// Console whose writer is in `WRITER`
+static OUTPUT_CONSOLE: AtomicUsize = AtomicUsize::new(0);
+/// Console shown on the screen
+static DISPLAYED_CONSOLE: AtomicUsize = AtomicUsize::new(0);
+
+/// Give every other console an off-screen screen with scrollback; needs the heap
+pub fn init_consoles() {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut consoles = CONSOLES.lock();
+		let output = OUTPUT_CONSOLE.load(Ordering::Relaxed);
+		for (console, slot) in consoles.iter_mut().enumerate() {
+			if console != output && slot.is_none() {
+				let mut writer = Writer::new(Box::leak(Box::new(Grid::BLANK)), None);
+				writer.set_default_colors(theme().foreground, theme().background);
+				writer.enable_scrollback();
+				*slot = Some(writer);
+			}
+		}
+	});
+}
+
+/// Send subsequent output to another console, whether or not it is on screen
+pub fn select_output(console: usize) {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		let mut consoles = CONSOLES.lock();
+		let current = OUTPUT_CONSOLE.load(Ordering::Relaxed);
+		if console == current {
+			return;
+		}
+		let Some(next) = consoles.get_mut(console).and_then(Option::take) else {
+			return;
+		};
+		consoles[current] = Some(core::mem::replace(&mut *writer, next));
+		OUTPUT_CONSOLE.store(console, Ordering::Relaxed);
+	});
+}
+
+/// Console output currently goes to
+pub fn output_console() -> usize {
+	OUTPUT_CONSOLE.load(Ordering::Relaxed)
+}
+
+/// Put another console on the screen, returning whether it exists
+pub fn show_console(console: usize) -> bool {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		let mut consoles = CONSOLES.lock();
+		let output = OUTPUT_CONSOLE.load(Ordering::Relaxed);
+		let shown = DISPLAYED_CONSOLE.load(Ordering::Relaxed);
+		if console == shown {
+			return true;
+		}
+		let (old, new) = if shown == output {
+			(&mut *writer, consoles.get_mut(console).and_then(Option::as_mut))
+		} else if console == output {
+			let Some(old) = consoles[shown].as_mut() else {
+				return false;
+			};
+			(old, Some(&mut *writer))
+		} else {
+			let Ok([old, new]) = consoles.get_disjoint_mut([shown, console]) else {
+				return false;
+			};
+			let Some(old) = old.as_mut() else {
+				return false;
+			};
+			(old, new.as_mut())
+		};
+		let Some(new) = new else {
+			return false;
+		};
+		old.swap_screens(new);
+		new.sync_cursor();
+		DISPLAYED_CONSOLE.store(console, Ordering::Relaxed);
+		true
+	})
+}
+
+/// Console shown on the screen
+pub fn displayed_console() -> usize {
+	DISPLAYED_CONSOLE.load(Ordering::Relaxed)
+}
+
+/// The writer of the console on screen, given the locked output writer and
+/// the other consoles
+fn displayed_writer<'a>(writer: &'a mut Writer, consoles: &'a mut [Option<Writer>; CONSOLE_COUNT]) -> Option<&'a mut Writer> {
+	let shown = DISPLAYED_CONSOLE.load(Ordering::Relaxed);
+	if shown == OUTPUT_CONSOLE.load(Ordering::Relaxed) {
+		Some(writer)
+	} else {
+		consoles[shown].as_mut()
+	}
+}
+
+/// Fit every console's screen onto a display of `(rows, cols)`, below the
+/// status line if there is one
+fn layout(writer: &mut Writer, consoles: &mut [Option<Writer>; CONSOLE_COUNT], (rows, cols): (usize, usize)) {
+	let origin = STATUS_ROWS.load(Ordering::Relaxed);
+	let rows = rows.saturating_sub(origin).clamp(1, MAX_ROWS);
+	let cols = cols.clamp(1, MAX_COLUMNS);
+	SCREEN_ROWS.store(rows, Ordering::Relaxed);
+	SCREEN_COLUMNS.store(cols, Ordering::Relaxed);
+	writer.layout(origin, rows, cols);
+	for console in consoles.iter_mut().flatten() {
+		console.layout(origin, rows, cols);
+	}
+}
+
+/// Draw the console on screen on another display, such as a framebuffer,
+/// resizing every console to fit it
+pub fn set_display(display: Box<dyn Console>) {
+	// Mirror it to the serial port if the console goes there too
+	let display = console::outputs(display);
+	let size = display.size();
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		let mut consoles = CONSOLES.lock();
+		if let Some(target) = displayed_writer(&mut writer, &mut consoles) {
+			target.display = Some(display);
+		}
+		layout(&mut writer, &mut consoles, size);
+		if let Some(display) = displayed_writer(&mut writer, &mut consoles).and_then(|target| target.display.as_mut()) {
+			draw_status(&mut **display);
+		}
+	});
+}
+
+/// Draw the console on screen again, after a full-screen program has drawn
+/// over the display
+pub fn redraw() {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		let mut consoles = CONSOLES.lock();
+		if let Some(target) = displayed_writer(&mut writer, &mut consoles) {
+			target.reset_view();
+			target.redraw();
+			target.sync_cursor();
+			if let Some(display) = target.display.as_mut() {
+				draw_status(&mut **display);
+			}
+		}
+	});
+}
+
+/// Clear the displayed console in `foreground` on `background` for the
+/// panic screen, and send all output there
+///
+/// # Safety
+///
+/// This breaks the console locks, so nothing else may run afterwards.
+pub unsafe fn take_over(foreground: Color, background: Color) {
+	WRITER.force_unlock();
+	CONSOLES.force_unlock();
+	STATUS_TEXT.force_unlock();
+	select_output(displayed_console());
+	set_status_bar(false);
+	let mut writer = WRITER.lock();
+	writer.escape = EscapeState::Ground;
+	writer.style = Style::DEFAULT;
+	writer.set_default_colors(foreground, background);
+	writer.clear_screen();
+	writer.set_cursor_visible(false);
+}
+
+/// Rows at the top of the display kept for the status line
+static STATUS_ROWS: AtomicUsize = AtomicUsize::new(0);
+/// Text of the status line
+static STATUS_TEXT: Mutex<String> = Mutex::new(String::new());
+
+/// Draw the status line across the top row of a display, if it is shown
+fn draw_status(display: &mut dyn Console) {
+	if STATUS_ROWS.load(Ordering::Relaxed) == 0 {
+		return;
+	}
+	let (_, cols) = display.size();
+	let text = STATUS_TEXT.lock();
+	let mut chars = text.chars();
+	for col in 0..cols {
+		let byte = chars.next().map_or(b' ', cp437::encode);
+		display.draw(0, col, byte, Color::Black, Color::LightGray);
+	}
+}
+
+/// Keep the top row of the display for a status line, or give it back to
+/// the consoles
+pub fn set_status_bar(enabled: bool) {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		let mut consoles = CONSOLES.lock();
+		STATUS_ROWS.store(enabled as usize, Ordering::Relaxed);
+		let size = displayed_writer(&mut writer, &mut consoles)
+			.and_then(|target| target.display.as_ref())
+			.map(|display| display.size());
+		let Some(size) = size else {
+			return;
+		};
+		layout(&mut writer, &mut consoles, size);
+		if let Some(display) = displayed_writer(&mut writer, &mut consoles).and_then(|target| target.display.as_mut()) {
+			draw_status(&mut **display);
+		}
 	});
 }
 
+/// Change the text of the status line, drawing it if it is shown
+pub fn set_status_line(text: &str) {
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		let mut consoles = CONSOLES.lock();
+		{
+			let mut status = STATUS_TEXT.lock();
+			status.clear();
+			status.push_str(text);
+		}
+		if let Some(display) = displayed_writer(&mut writer, &mut consoles).and_then(|target| target.display.as_mut()) {
+			draw_status(&mut **display);
+		}
+	});
+}
+
+/// Set the default text colors of the console output goes to
+pub fn set_color(foreground: Color, background: Color) {
+	x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().set_default_colors(foreground, background));
+}
+
+/// Switch every console to a theme by name, returning whether it exists
+pub fn set_theme(name: &str) -> bool {
+	let Some(index) = THEMES.iter().position(|theme| theme.name == name) else {
+		return false;
+	};
+	THEME.store(index, Ordering::Relaxed);
+	let theme = &THEMES[index];
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		WRITER.lock().set_default_colors(theme.foreground, theme.background);
+		for writer in CONSOLES.lock().iter_mut().flatten() {
+			writer.set_default_colors(theme.foreground, theme.background);
+		}
+	});
+	true
+}
+
+/// Print in another foreground color, such as a theme's error color
+pub fn print_in_color(foreground: Color, args: fmt::Arguments) {
+	use core::fmt::Write;
+
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		let saved = writer.color_code;
+		let background = writer.default_colors.1;
+		writer.color_code = ColorCode::new(foreground, background);
+		writer.write_fmt(args).unwrap();
+		writer.color_code = saved;
+		writer.sync_cursor();
+	});
+}
+
+/// Scroll the view of the console on screen back by `lines` (forward if negative)
+pub fn scroll_view(lines: isize) {
+	select_output(displayed_console());
+	x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().scroll_view(lines));
+}
+
 /// Print macro implementation
 #[macro_export]
 macro_rules! print {
@@ -158,7 +1131,9 @@ pub fn _print(args: fmt::Arguments) {
 	use x86_64::instructions::interrupts;
 
 	interrupts::without_interrupts(|| {
-		WRITER.lock().write_fmt(args).unwrap();
+		let mut writer = WRITER.lock();
+		writer.write_fmt(args).unwrap();
+		writer.sync_cursor();
 	});
 }
 
@@ -187,8 +1162,181 @@ fn test_println_output() {
 		let mut writer = WRITER.lock();
 		writeln!(writer, "\n{}", s).expect("writeln failed");
 		for (i, c) in s.chars().enumerate() {
-			let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+			let screen_char = writer.grid.cells[BUFFER_HEIGHT - 2][i];
 			assert_eq!(char::from(screen_char.ascii_character), c);
 		}
 	});
-} 
\ No newline at end of file
+} 
+
+/// Test cursor movement, erasing, colors, and cursor save/restore
+#[test_case]
+fn test_escape_sequences() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		write!(writer, "\x1b[2J\x1b[3;5HAB\x1b[1;31mC\x1b[0m").expect("write failed");
+		let row = &writer.grid.cells[2];
+		assert_eq!(row[4].ascii_character, b'A');
+		assert_eq!(row[6], ScreenChar { ascii_character: b'C', color_code: ColorCode::new(Color::LightRed, DEFAULT_BACKGROUND) });
+		assert_eq!((writer.row_position, writer.column_position), (2, 7));
+
+		write!(writer, "\x1b[s\x1b[HX\x1b[2B\x1b[3DY\x1b[u").expect("write failed");
+		assert_eq!(writer.grid.cells[0][0].ascii_character, b'X');
+		assert_eq!(writer.grid.cells[2][0].ascii_character, b'Y');
+		assert_eq!((writer.row_position, writer.column_position), (2, 7));
+
+		write!(writer, "\x1b[1K\rZ").expect("write failed");
+		assert_eq!(writer.grid.cells[2][0].ascii_character, b'Z');
+		assert_eq!(writer.grid.cells[2][6].ascii_character, b' ');
+		assert_eq!(writer.color_code, ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND));
+		write!(writer, "\x1b[25;1H").expect("write failed");
+	});
+}
+
+/// Test hiding and showing the hardware cursor, directly and by escape sequence
+#[test_case]
+fn test_cursor_visibility() {
+	use core::fmt::Write;
+
+	hide_cursor();
+	assert_ne!(crtc_read(CRTC_CURSOR_START) & CURSOR_DISABLE, 0);
+	write!(WRITER.lock(), "\x1b[?25h").expect("write failed");
+	assert_eq!(crtc_read(CRTC_CURSOR_START) & CURSOR_DISABLE, 0);
+	assert_eq!(crtc_read(CRTC_CURSOR_START) & 0x1f, CURSOR_SCANLINES.0);
+	write!(WRITER.lock(), "\x1b[?25l").expect("write failed");
+	assert_ne!(crtc_read(CRTC_CURSOR_START) & CURSOR_DISABLE, 0);
+	show_cursor();
+}
+
+/// Test direct addressing and clearing
+#[test_case]
+fn test_screen_addressing() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		writer.clear_screen();
+		assert_eq!(writer.position(), (0, 0));
+		writer.write_at(3, BUFFER_WIDTH - 2, "status", Color::Black, Color::LightGray);
+		assert_eq!(writer.grid.cells[3][BUFFER_WIDTH - 1].ascii_character, b't');
+		assert_eq!(writer.grid.cells[4][0].ascii_character, b' ');
+		assert_eq!(writer.position(), (0, 0));
+
+		writer.set_position(5, 200);
+		write!(writer, "x\ny").expect("write failed");
+		assert_eq!(writer.grid.cells[5][BUFFER_WIDTH - 1].ascii_character, b'x');
+		assert_eq!(writer.grid.cells[6][0].ascii_character, b'y');
+		writer.set_position(BUFFER_HEIGHT - 1, 0);
+	});
+}
+
+/// Test scrolling the view back through history and returning to the live screen
+#[test_case]
+fn test_scrollback() {
+	use core::fmt::Write;
+	use x86_64::instructions::interrupts;
+
+	interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		writer.enable_scrollback();
+		for i in 0..BUFFER_HEIGHT + 5 {
+			writeln!(writer, "scrollback line {}", i).expect("writeln failed");
+		}
+		let screen = unsafe { &*(VGA_BUFFER as *const Buffer) };
+		let shown = |row: usize| screen.chars[row].each_ref().map(|cell| cell.read());
+		let top = shown(0);
+		let bottom = shown(BUFFER_HEIGHT - 1);
+		let scrolled_off = writer.history.as_ref().unwrap().back().unwrap().clone();
+
+		writer.scroll_view(1);
+		assert_eq!(shown(0)[..], scrolled_off[..]);
+		assert_eq!(shown(1), top);
+		assert_eq!(writer.grid.cells[0][..BUFFER_WIDTH], top);
+		writer.scroll_view(-1);
+		assert_eq!(shown(0), top);
+
+		writer.scroll_view(isize::MAX);
+		assert_eq!(writer.view_offset, writer.history.as_ref().unwrap().len());
+		writer.write_byte(b'x');
+		assert_eq!(writer.view_offset, 0);
+		assert_eq!(shown(0), top);
+		assert_eq!(shown(BUFFER_HEIGHT - 1)[1], bottom[1]);
+	});
+}
+
+/// Test that output to a console in the background appears when it is shown
+#[test_case]
+fn test_virtual_consoles() {
+	use core::fmt::Write;
+
+	init_consoles();
+	let bottom = |row: &[ScreenChar]| row.iter().take(7).map(|cell| cell.ascii_character as char).collect::<alloc::string::String>();
+	select_output(1);
+	x86_64::instructions::interrupts::without_interrupts(|| write!(WRITER.lock(), "\nconsole").expect("write failed"));
+	select_output(0);
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let writer = WRITER.lock();
+		assert!(writer.display.is_some());
+		assert_ne!(bottom(&writer.grid.cells[BUFFER_HEIGHT - 1]), "console");
+	});
+
+	assert!(show_console(1));
+	assert_eq!(displayed_console(), 1);
+	let screen = unsafe { &*(VGA_BUFFER as *const Buffer) };
+	assert_eq!(bottom(&screen.chars[BUFFER_HEIGHT - 1].each_ref().map(|cell| cell.read())), "console");
+	assert!(!show_console(CONSOLE_COUNT));
+	assert!(show_console(0));
+	assert_ne!(bottom(&screen.chars[BUFFER_HEIGHT - 1].each_ref().map(|cell| cell.read())), "console");
+	assert_eq!(output_console(), 0);
+}
+
+/// Test color names and repainting for a new default color
+#[test_case]
+fn test_default_colors() {
+	use core::fmt::Write;
+
+	assert_eq!(Color::from_name("light-red"), Some(Color::LightRed));
+	assert_eq!(Color::from_name("lightred"), Some(Color::LightRed));
+	assert_eq!(Color::from_name("9"), Some(Color::LightBlue));
+	assert_eq!(Color::from_name("mauve"), None);
+
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		let saved = writer.default_colors();
+		write!(writer, "\rA\x1b[31mB\x1b[0m").expect("write failed");
+		writer.set_default_colors(Color::White, Color::Blue);
+		let row = &writer.grid.cells[writer.row_position];
+		assert_eq!(row[0].color_code, ColorCode::new(Color::White, Color::Blue));
+		assert_eq!(row[1].color_code, ColorCode::new(Color::Red, saved.1));
+		assert_eq!(writer.color_code, ColorCode::new(Color::White, Color::Blue));
+		writer.set_default_colors(saved.0, saved.1);
+		writeln!(writer).expect("writeln failed");
+	});
+}
+
+/// Test that the status line takes the top row and moves the screen below it
+#[test_case]
+fn test_status_line() {
+	let screen = unsafe { &*(VGA_BUFFER as *const Buffer) };
+	let shown = |row: usize| screen.chars[row].each_ref().map(|cell| cell.read().ascii_character);
+
+	set_status_line("status");
+	assert_ne!(shown(0)[..6], *b"status");
+	set_status_bar(true);
+	assert_eq!(screen_size(), (BUFFER_HEIGHT - 1, BUFFER_WIDTH));
+	assert_eq!(shown(0)[..7], *b"status ");
+	assert_eq!(screen.chars[0][0].read().color_code, ColorCode::new(Color::Black, Color::LightGray));
+	x86_64::instructions::interrupts::without_interrupts(|| {
+		let mut writer = WRITER.lock();
+		writer.write_at(0, 0, "below", Color::White, Color::Black);
+	});
+	assert_eq!(shown(1)[..5], *b"below");
+
+	set_status_line("");
+	set_status_bar(false);
+	assert_eq!(screen_size(), (BUFFER_HEIGHT, BUFFER_WIDTH));
+	assert_eq!(shown(0)[..5], *b"below");
+}
@@ -0,0 +1,347 @@
+use crate::memory;
+use crate::pci::{self, Bar};
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+use x86_64::instructions::port::Port;
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Vendor ID of every virtio device
+pub const VENDOR_ID: u16 = 0x1af4;
+
+/// Legacy interface registers, as offsets into the I/O BAR
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_ADDRESS: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0c;
+const REG_QUEUE_SELECT: u16 = 0x0e;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_STATUS: u16 = 0x12;
+const REG_ISR: u16 = 0x13;
+/// With MSI-X on, the vectors of configuration changes and of the
+/// selected queue come next, moving the device's own configuration along
+const REG_CONFIG_VECTOR: u16 = 0x14;
+const REG_QUEUE_VECTOR: u16 = 0x16;
+const CONFIG: u16 = 0x14;
+const CONFIG_MSIX: u16 = 0x18;
+/// A vector register value for no interrupt at all
+const NO_VECTOR: u16 = 0xffff;
+
+/// Device status bits, set in this order as the driver brings it up
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 2;
+pub const STATUS_DRIVER_OK: u8 = 4;
+pub const STATUS_FAILED: u8 = 128;
+
+/// ISR status bit set when a queue has used buffers
+const ISR_QUEUE: u8 = 1;
+
+/// Legacy queues are laid out in, and addressed by, pages of this size
+const QUEUE_ALIGN: u64 = 4096;
+
+/// Descriptor flags: the chain goes on, and the device writes the buffer
+const DESC_NEXT: u16 = 1;
+const DESC_WRITE: u16 = 2;
+/// Available ring flag asking the device not to interrupt for the queue
+const AVAIL_NO_INTERRUPT: u16 = 1;
+
+/// A device's legacy PCI interface, reached through I/O ports
+pub struct Transport {
+	port: u16,
+	/// Where the device's own configuration starts
+	config: u16,
+}
+
+impl Transport {
+	/// The interface of a legacy or transitional device, which has its
+	/// registers in BAR 0
+	pub fn new(device: &pci::Device) -> Option<Transport> {
+		match device.bars.first() {
+			Some(&Bar::Io { port, .. }) => Some(Transport { port: port as u16, config: CONFIG }),
+			_ => None,
+		}
+	}
+
+	fn read_u8(&self, offset: u16) -> u8 {
+		unsafe { Port::new(self.port + offset).read() }
+	}
+
+	fn write_u8(&self, offset: u16, value: u8) {
+		unsafe { Port::new(self.port + offset).write(value) }
+	}
+
+	fn read_u16(&self, offset: u16) -> u16 {
+		unsafe { Port::new(self.port + offset).read() }
+	}
+
+	fn write_u16(&self, offset: u16, value: u16) {
+		unsafe { Port::new(self.port + offset).write(value) }
+	}
+
+	fn read_u32(&self, offset: u16) -> u32 {
+		unsafe { Port::new(self.port + offset).read() }
+	}
+
+	fn write_u32(&self, offset: u16, value: u32) {
+		unsafe { Port::new(self.port + offset).write(value) }
+	}
+
+	/// Stop the device and forget everything the driver set up
+	pub fn reset(&self) {
+		self.write_u8(REG_STATUS, 0);
+	}
+
+	/// Add status bits to those already set
+	pub fn add_status(&self, status: u8) {
+		self.write_u8(REG_STATUS, self.read_u8(REG_STATUS) | status);
+	}
+
+	/// Accept the features in `wanted` that the device offers, returning
+	/// those accepted
+	pub fn negotiate(&self, wanted: u32) -> u32 {
+		let features = self.read_u32(REG_DEVICE_FEATURES) & wanted;
+		self.write_u32(REG_GUEST_FEATURES, features);
+		features
+	}
+
+	/// Read a byte of the device's own configuration
+	pub fn config_u8(&self, offset: u16) -> u8 {
+		self.read_u8(self.config + offset)
+	}
+
+	/// Read a word of the device's own configuration
+	pub fn config_u16(&self, offset: u16) -> u16 {
+		self.read_u16(self.config + offset)
+	}
+
+	/// Send every queue's interrupts to the first MSI-X vector, once it is
+	/// enabled, and none for configuration changes
+	pub fn use_msix(&mut self, queues: u16) {
+		self.config = CONFIG_MSIX;
+		self.write_u16(REG_CONFIG_VECTOR, NO_VECTOR);
+		for queue in 0..queues {
+			self.write_u16(REG_QUEUE_SELECT, queue);
+			self.write_u16(REG_QUEUE_VECTOR, 0);
+		}
+	}
+
+	/// The first of the device's I/O ports
+	pub fn port(&self) -> u16 {
+		self.port
+	}
+
+	/// Allocate and register queue number `index`, or `None` if the device
+	/// does not have it
+	pub fn setup_queue(&self, index: u16) -> Option<Virtqueue> {
+		self.write_u16(REG_QUEUE_SELECT, index);
+		let size = self.read_u16(REG_QUEUE_SIZE);
+		if size == 0 {
+			return None;
+		}
+		let layout = QueueLayout::new(size);
+		let (physical, virtual_address) = memory::allocate_dma(layout.total)?;
+		self.write_u32(REG_QUEUE_ADDRESS, (physical.as_u64() / QUEUE_ALIGN) as u32);
+		Some(Virtqueue::new(index, size, virtual_address, layout))
+	}
+
+	/// Tell the device a queue has new available buffers
+	pub fn notify(&self, queue: &Virtqueue) {
+		// The ring must be visible before the device looks at it
+		fence(Ordering::SeqCst);
+		self.write_u16(REG_QUEUE_NOTIFY, queue.index);
+	}
+}
+
+/// Read and clear the interrupt status of the device at `port`, returning
+/// whether a queue interrupted; with MSI-X this is not needed
+///
+/// Takes the port rather than a `Transport` so interrupt handlers can call
+/// it; does not block or allocate.
+pub fn queue_interrupt(port: u16) -> bool {
+	unsafe { Port::<u8>::new(port + REG_ISR).read() & ISR_QUEUE != 0 }
+}
+
+/// Where the parts of a legacy queue of a given size go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct QueueLayout {
+	available: u64,
+	used: u64,
+	total: u64,
+}
+
+impl QueueLayout {
+	/// Descriptors, then the available ring, then the used ring on a page
+	/// of its own
+	fn new(size: u16) -> Self {
+		let size = u64::from(size);
+		let available = 16 * size;
+		let used = (available + 6 + 2 * size).next_multiple_of(QUEUE_ALIGN);
+		let total = used + (6 + 8 * size).next_multiple_of(QUEUE_ALIGN);
+		QueueLayout { available, used, total }
+	}
+}
+
+/// A buffer in a descriptor chain
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+	pub address: PhysAddr,
+	pub length: u32,
+	/// Whether the device writes it rather than reads it
+	pub writable: bool,
+}
+
+/// A descriptor, as the device reads it
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+	address: u64,
+	length: u32,
+	flags: u16,
+	next: u16,
+}
+
+/// A ring of buffers shared with the device: the driver makes chains
+/// available and the device hands them back used
+pub struct Virtqueue {
+	index: u16,
+	size: u16,
+	memory: VirtAddr,
+	layout: QueueLayout,
+	/// Descriptors not in a chain, each linked to the next
+	free_head: u16,
+	free: u16,
+	/// The used ring entry to look at next
+	last_used: u16,
+}
+
+impl Virtqueue {
+	fn new(index: u16, size: u16, memory: VirtAddr, layout: QueueLayout) -> Self {
+		let queue = Virtqueue { index, size, memory, layout, free_head: 0, free: size, last_used: 0 };
+		for descriptor in 0..size {
+			queue.set_descriptor(descriptor, Descriptor { address: 0, length: 0, flags: 0, next: descriptor + 1 });
+		}
+		queue
+	}
+
+	/// Descriptors the queue has
+	pub fn size(&self) -> u16 {
+		self.size
+	}
+
+	fn descriptor(&self, index: u16) -> Descriptor {
+		unsafe { self.memory.as_ptr::<Descriptor>().add(usize::from(index)).read_volatile() }
+	}
+
+	fn set_descriptor(&self, index: u16, descriptor: Descriptor) {
+		unsafe { self.memory.as_mut_ptr::<Descriptor>().add(usize::from(index)).write_volatile(descriptor) }
+	}
+
+	/// A word of the available or used ring: flags, index, then entries
+	fn ring_u16(&self, ring: u64, word: usize) -> *mut u16 {
+		unsafe { (self.memory + ring).as_mut_ptr::<u16>().add(word) }
+	}
+
+	/// Ask the device not to interrupt when it uses buffers, for queues
+	/// the driver checks by itself
+	pub fn disable_interrupts(&mut self) {
+		unsafe { self.ring_u16(self.layout.available, 0).write_volatile(AVAIL_NO_INTERRUPT) };
+	}
+
+	/// Make a chain of buffers available to the device, returning the ID
+	/// it comes back as, or `None` if there are not enough free descriptors
+	///
+	/// The device is not told until `Transport::notify`.
+	pub fn add(&mut self, chain: &[Buffer]) -> Option<u16> {
+		if chain.is_empty() || usize::from(self.free) < chain.len() {
+			return None;
+		}
+		let head = self.free_head;
+		let mut index = head;
+		for (position, buffer) in chain.iter().enumerate() {
+			let next = self.descriptor(index).next;
+			let mut flags = if buffer.writable { DESC_WRITE } else { 0 };
+			if position + 1 < chain.len() {
+				flags |= DESC_NEXT;
+			}
+			self.set_descriptor(index, Descriptor { address: buffer.address.as_u64(), length: buffer.length, flags, next });
+			if position + 1 < chain.len() {
+				index = next;
+			} else {
+				self.free_head = next;
+			}
+		}
+		self.free -= chain.len() as u16;
+
+		let available = self.layout.available;
+		let ring_index = unsafe { self.ring_u16(available, 1).read_volatile() };
+		unsafe { self.ring_u16(available, 2 + usize::from(ring_index % self.size)).write_volatile(head) };
+		// The entry must be in place before the device sees the new index
+		fence(Ordering::SeqCst);
+		unsafe { self.ring_u16(available, 1).write_volatile(ring_index.wrapping_add(1)) };
+		Some(head)
+	}
+
+	/// Take the next chain the device has used, freeing its descriptors;
+	/// returns its ID and the bytes the device wrote to it
+	pub fn take_used(&mut self) -> Option<(u16, u32)> {
+		let used = self.layout.used;
+		let device_index = unsafe { self.ring_u16(used, 1).read_volatile() };
+		if device_index == self.last_used {
+			return None;
+		}
+		fence(Ordering::SeqCst);
+		// Used entries are an ID and a length, 32 bits each
+		let entry = unsafe { (self.memory + used + 4u64).as_ptr::<u32>().add(2 * usize::from(self.last_used % self.size)) };
+		let (id, length) = unsafe { (entry.read_volatile() as u16, entry.add(1).read_volatile()) };
+		self.last_used = self.last_used.wrapping_add(1);
+
+		let mut index = id;
+		loop {
+			let descriptor = self.descriptor(index);
+			self.free += 1;
+			if descriptor.flags & DESC_NEXT == 0 {
+				self.set_descriptor(index, Descriptor { next: self.free_head, ..descriptor });
+				break;
+			}
+			index = descriptor.next;
+		}
+		self.free_head = id;
+		Some((id, length))
+	}
+}
+
+/// Allocate DMA memory for `count` buffers of `size` bytes, which must
+/// divide a page, returning each buffer's physical and virtual address
+pub fn allocate_buffers(count: usize, size: usize) -> Option<Vec<(PhysAddr, VirtAddr)>> {
+	let (physical, virtual_address) = memory::allocate_dma((count * size) as u64)?;
+	Some((0..count).map(|index| ((physical + (index * size) as u64), virtual_address + (index * size) as u64)).collect())
+}
+
+/// Test the legacy queue layout and handing chains to the device and back
+#[test_case]
+fn test_virtqueue() {
+	assert_eq!(QueueLayout::new(256), QueueLayout { available: 4096, used: 8192, total: 12288 });
+	assert_eq!(QueueLayout::new(16), QueueLayout { available: 256, used: 4096, total: 8192 });
+
+	let layout = QueueLayout::new(4);
+	let memory = alloc::vec![0u64; layout.total as usize / 8];
+	let mut queue = Virtqueue::new(0, 4, VirtAddr::from_ptr(memory.as_ptr()), layout);
+	let buffer = |writable| Buffer { address: PhysAddr::new(0x1000), length: 64, writable };
+	let first = queue.add(&[buffer(false), buffer(true), buffer(true)]).unwrap();
+	assert_eq!(queue.add(&[buffer(false), buffer(false)]), None);
+	let second = queue.add(&[buffer(true)]).unwrap();
+	assert_eq!((first, second, queue.free), (0, 3, 0));
+	assert_eq!(queue.descriptor(1).flags, DESC_WRITE | DESC_NEXT);
+	assert_eq!(unsafe { queue.ring_u16(layout.available, 1).read() }, 2);
+
+	// Play the device using the first chain
+	unsafe {
+		let entry = (queue.memory + layout.used + 4u64).as_mut_ptr::<u32>();
+		entry.write(u32::from(first));
+		entry.add(1).write(42);
+		queue.ring_u16(layout.used, 1).write(1);
+	}
+	assert_eq!(queue.take_used(), Some((first, 42)));
+	assert_eq!(queue.take_used(), None);
+	assert_eq!(queue.free, 3);
+	assert!(queue.add(&[buffer(true), buffer(true), buffer(true)]).is_some());
+}
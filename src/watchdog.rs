@@ -0,0 +1,132 @@
+use crate::task::{executor, timer};
+use crate::{cmdline, klog, panic, smp, time, trace};
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::structures::idt::InterruptStackFrame;
+
+/// How often the feeder task pets the watchdog
+const FEED_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the watchdog goes unfed before it panics, unless `watchdog=SECS`
+/// says otherwise
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+/// Tracepoint events shown when it panics
+const REPORT_EVENTS: usize = 8;
+
+/// Uptime in milliseconds when the watchdog was last fed; 0 until the feeder
+/// first runs, which arms it
+static LAST_FED_MS: AtomicU64 = AtomicU64::new(0);
+static TIMEOUT_MS: AtomicU64 = AtomicU64::new(DEFAULT_TIMEOUT_SECS * 1000);
+/// Set once it has panicked, so a panic screen that leaves the timer
+/// running is not interrupted by another
+static TRIPPED: AtomicBool = AtomicBool::new(false);
+
+/// Pet the watchdog
+pub fn feed() {
+	LAST_FED_MS.store(time::uptime_ms().max(1), Ordering::Relaxed);
+}
+
+/// Pet the watchdog if it is armed, after the kernel was stopped on purpose,
+/// as in the debugger
+pub(crate) fn resume() {
+	if LAST_FED_MS.load(Ordering::Relaxed) != 0 {
+		feed();
+	}
+}
+
+/// Pet the watchdog forever, as a background task on the boot processor:
+/// it only gets a turn once every task ahead of it has yielded, so missing
+/// turns means something there has stopped yielding
+///
+/// `watchdog=off` leaves the watchdog unarmed; `watchdog=SECS` sets how long
+/// it waits.
+pub async fn run_feeder() {
+	match cmdline::param("watchdog") {
+		Some("off") => return,
+		Some(value) => match value.parse::<u64>() {
+			Ok(secs) if secs > 0 => TIMEOUT_MS.store(secs * 1000, Ordering::Relaxed),
+			_ => klog!(Warn, "watchdog: watchdog={} is not a number of seconds", value),
+		},
+		None => {}
+	}
+	loop {
+		feed();
+		timer::sleep(FEED_INTERVAL).await;
+	}
+}
+
+/// Whether a watchdog last fed at `last_fed_ms` is overdue at `now_ms`
+fn overdue(last_fed_ms: u64, now_ms: u64, timeout_ms: u64) -> bool {
+	last_fed_ms != 0 && now_ms.saturating_sub(last_fed_ms) >= timeout_ms
+}
+
+/// Whether the timer interrupted a wait: user code, or a `hlt`, which
+/// every loop in the kernel that waits for input or the network sits in
+fn waiting(frame: &InterruptStackFrame) -> bool {
+	const HLT: u8 = 0xf4;
+	let after = frame.instruction_pointer.as_u64();
+	frame.code_segment & 3 != 0 || (panic::is_mapped(after - 1) && unsafe { *((after - 1) as *const u8) } == HLT)
+}
+
+/// Panic if the watchdog has gone unfed too long; called from the timer
+/// interrupt's entry, and only checked on the boot processor, which runs the
+/// feeder
+pub(crate) fn check(frame: &InterruptStackFrame) {
+	if smp::current_cpu() != 0 {
+		return;
+	}
+	let last_fed_ms = LAST_FED_MS.load(Ordering::Relaxed);
+	let now_ms = time::uptime_ms();
+	if !overdue(last_fed_ms, now_ms, TIMEOUT_MS.load(Ordering::Relaxed)) {
+		return;
+	}
+	// A command waiting for a key for longer has not hung; one spinning has
+	if waiting(frame) {
+		feed();
+		return;
+	}
+	if TRIPPED.swap(true, Ordering::Relaxed) {
+		return;
+	}
+	panic!("{}", Report { starved_ms: now_ms - last_fed_ms });
+}
+
+/// What a tripped watchdog says: what was running and what happened last
+struct Report {
+	starved_ms: u64,
+}
+
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "watchdog: not fed for {} ms; ", self.starved_ms)?;
+		let polling = executor::with_current_poll(|id, name, polled| {
+			write!(f, "CPU 0 has been polling task {} ({}) for {} ms", id, name, polled.as_millis())
+		});
+		match polling {
+			Some(result) => result?,
+			None => write!(f, "CPU 0 is not polling a task, so an interrupt handler or the executor is stuck")?,
+		}
+		writeln!(f, "\nLast tracepoints:")?;
+		let mut result = Ok(());
+		let mut shown = 0;
+		let read = trace::try_recent(REPORT_EVENTS, |event| {
+			shown += 1;
+			result = result.and_then(|()| writeln!(f, "  cpu{} {:<7} {}", event.cpu, event.subsystem.name(), event.message()));
+		});
+		result?;
+		match (read, shown) {
+			(false, _) => writeln!(f, "  (the trace buffer is busy)"),
+			(true, 0) => writeln!(f, "  (none; trace on SUBSYSTEM records them)"),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Test when the watchdog counts as overdue
+#[test_case]
+fn test_overdue() {
+	assert!(!overdue(0, 1_000_000, 10_000));
+	assert!(!overdue(5_000, 14_999, 10_000));
+	assert!(overdue(5_000, 15_000, 10_000));
+	assert!(!overdue(5_000, 4_000, 10_000));
+}
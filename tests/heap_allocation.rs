@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(scottos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::panic::PanicInfo;
+use scottos::allocator::{self, HEAP_SIZE};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+	scottos::init();
+	allocator::init_heap().expect("heap initialization failed");
+	test_main();
+	scottos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+	scottos::test_panic_handler(info)
+}
+
+/// Test a couple of small allocations
+#[test_case]
+fn simple_allocation() {
+	let heap_value_1 = Box::new(41);
+	let heap_value_2 = Box::new(13);
+	assert_eq!(*heap_value_1, 41);
+	assert_eq!(*heap_value_2, 13);
+}
+
+/// Test a vector grown past several reallocations
+#[test_case]
+fn large_vec() {
+	let n = 1000;
+	let mut vec = Vec::new();
+	for i in 0..n {
+		vec.push(i);
+	}
+	assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+/// Test that freed memory is reused: more boxes than the heap could hold at once
+#[test_case]
+fn many_boxes() {
+	for i in 0..HEAP_SIZE {
+		let x = Box::new(i);
+		assert_eq!(*x, i);
+	}
+}
+
+/// Test that a long-lived allocation does not fragment the heap for the rest
+#[test_case]
+fn many_boxes_long_lived() {
+	let long_lived = Box::new(1);
+	for i in 0..HEAP_SIZE {
+		let x = Box::new(i);
+		assert_eq!(*x, i);
+	}
+	assert_eq!(*long_lived, 1);
+}
+
+/// Test that every byte freed is given back, after a map's many small nodes
+#[test_case]
+fn usage_returns_after_free() {
+	let (free_before, size) = allocator::heap_usage();
+	assert_eq!(size, HEAP_SIZE);
+	{
+		let map: BTreeMap<u32, [u8; 32]> = (0..500).map(|key| (key, [key as u8; 32])).collect();
+		assert_eq!(map[&499], [243; 32]);
+		assert!(allocator::heap_usage().0 < free_before);
+	}
+	assert_eq!(allocator::heap_usage().0, free_before);
+}
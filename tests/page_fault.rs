@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::ToString;
+use core::panic::PanicInfo;
+use scottos::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+/// Far above anything the bootloader maps
+const UNMAPPED: u64 = 0xdead_beef_0000;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+	serial_print!("page_fault::unmapped_read...\t");
+
+	scottos::init();
+	scottos::allocator::init_heap().expect("heap initialization failed");
+
+	let value = unsafe { core::ptr::read_volatile(UNMAPPED as *const u8) };
+
+	serial_println!("[failed]\n");
+	serial_println!("Error: read {:#x} from {:#x} without a page fault", value, UNMAPPED);
+	exit_qemu(QemuExitCode::Failed);
+	scottos::hlt_loop();
+}
+
+/// The kernel's page fault handler panics; pass if it named the fault and
+/// the address that caused it
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+	let message = info.message().to_string();
+	if message.contains("PAGE FAULT") && message.contains("0xdeadbeef0000") {
+		serial_println!("[ok]");
+		exit_qemu(QemuExitCode::Success);
+	} else {
+		scottos::test_panic_handler(info);
+	}
+	scottos::hlt_loop();
+}
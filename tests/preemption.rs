@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(scottos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::ToString;
+use core::panic::PanicInfo;
+use scottos::process::{Process, ProcessState, Scheduler};
+use scottos::time;
+
+/// Timer ticks a process runs for before the next gets a turn
+const TIME_SLICE: usize = 10;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+	scottos::init();
+	scottos::allocator::init_heap().expect("heap initialization failed");
+	test_main();
+	scottos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+	scottos::test_panic_handler(info)
+}
+
+/// Test that the timer interrupts a loop that never yields
+#[test_case]
+fn timer_interrupts_busy_loop() {
+	let start = time::ticks();
+	let mut spins = 0u64;
+	while time::ticks() < start + 3 {
+		spins += 1;
+		core::hint::spin_loop();
+		assert!(spins < 1 << 40, "no timer interrupt arrived");
+	}
+}
+
+/// Test that a process is switched out once its time slice runs out, and
+/// not before
+#[test_case]
+fn time_slice_expiry() {
+	let mut scheduler = Scheduler::new();
+	let first = Process::new("first".to_string(), None);
+	let second = Process::new("second".to_string(), None);
+	let (first_pid, second_pid) = (first.pid, second.pid);
+	scheduler.add_process(first);
+	scheduler.add_process(second);
+
+	let running = scheduler.schedule().unwrap();
+	let waiting = if running == first_pid { second_pid } else { first_pid };
+	for _ in 0..TIME_SLICE - 1 {
+		scheduler.timer_tick();
+		assert_eq!(scheduler.current_process().map(|p| p.pid), Some(running));
+	}
+	scheduler.timer_tick();
+	assert_eq!(scheduler.current_process().map(|p| p.pid), Some(waiting));
+	assert_eq!(scheduler.get_process(running).unwrap().state, ProcessState::Ready);
+	assert_eq!(scheduler.get_process(waiting).unwrap().state, ProcessState::Running);
+}
+
+/// Test that a process that stops is switched out at once
+#[test_case]
+fn terminated_process_is_replaced() {
+	let mut scheduler = Scheduler::new();
+	let first = Process::new("first".to_string(), None);
+	let second = Process::new("second".to_string(), None);
+	scheduler.add_process(first);
+	scheduler.add_process(second);
+
+	let running = scheduler.schedule().unwrap();
+	scheduler.remove_process(running);
+	let next = scheduler.current_process().map(|p| p.pid);
+	assert!(next.is_some() && next != Some(running));
+	scheduler.timer_tick();
+	assert!(scheduler.get_process(running).is_none());
+}
@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use scottos::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+lazy_static! {
+	/// An IDT whose double fault handler reports success, on the same
+	/// stack the kernel's uses
+	static ref TEST_IDT: InterruptDescriptorTable = {
+		let mut idt = InterruptDescriptorTable::new();
+		unsafe {
+			idt.double_fault
+				.set_handler_fn(test_double_fault_handler)
+				.set_stack_index(scottos::gdt::DOUBLE_FAULT_IST_INDEX);
+		}
+		idt
+	};
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+	serial_print!("stack_overflow::stack_overflow...\t");
+
+	scottos::gdt::init();
+	TEST_IDT.load();
+
+	stack_overflow();
+
+	panic!("Execution continued after stack overflow");
+}
+
+/// Recurse until the guard page below the stack is hit; the page fault
+/// cannot be delivered on the overflowed stack, so it becomes a double fault
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+	stack_overflow();
+	// Keep the call from being turned into a loop
+	volatile::Volatile::new(0).read();
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(_stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
+	serial_println!("[ok]");
+	exit_qemu(QemuExitCode::Success);
+	scottos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+	scottos::test_panic_handler(info)
+}
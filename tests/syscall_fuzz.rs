@@ -0,0 +1,244 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(scottos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use scottos::serial_println;
+use scottos::syscall::syscall_handler;
+
+/// System calls made per run
+const ITERATIONS: usize = 2000;
+/// Pointers go this far at most into the scratch buffer, leaving at least
+/// `ROOM` bytes after them for whatever the call writes there
+const SCRATCH_SIZE: usize = 8192;
+const ROOM: usize = 4096;
+/// Longest buffer passed with a pointer
+const MAX_LEN: usize = 1024;
+
+/// What kind of value a system call takes in an argument
+#[derive(Clone, Copy)]
+enum Arg {
+	/// Any integer, boundary values included
+	Int,
+	/// A descriptor, usually a low one that may be open
+	Fd,
+	/// A NUL-terminated path, null, or a wild pointer
+	Str,
+	/// A pointer into the scratch buffer whose length is the next argument,
+	/// or a wild one
+	Buf,
+	/// The length of the buffer before it, sometimes one running off the
+	/// end of the address space
+	Len,
+	/// A pointer into the scratch buffer for a structure, null, or a wild one
+	Out,
+}
+
+use Arg::*;
+
+/// The system calls fuzzed, with what their arguments are
+///
+/// Left out: `exit` and `reboot`, which stop the kernel, and `socket`, since
+/// a blocking `accept` or `recv` on one would wait forever for a network,
+/// `getrandom`, which waits for entropy from a timer tick this kernel
+/// never starts, and `prctl`, whose filters would deny every call after.
+const SYSCALLS: &[(usize, &[Arg])] = &[
+	(0, &[Fd, Buf, Len]),
+	(1, &[Fd, Buf, Len]),
+	(2, &[Str, Int, Int]),
+	(3, &[Fd]),
+	(16, &[Fd, Int, Out]),
+	(21, &[Str, Int]),
+	(22, &[Out]),
+	(32, &[Fd]),
+	(33, &[Fd, Fd]),
+	(39, &[]),
+	(42, &[Fd, Buf, Len]),
+	(43, &[Fd, Out, Out]),
+	(44, &[Fd, Buf, Len, Int, Buf, Len]),
+	(45, &[Fd, Buf, Len, Int, Out, Out]),
+	(46, &[Fd, Out]),
+	(47, &[Fd, Out, Int]),
+	(48, &[Fd, Int]),
+	(49, &[Fd, Buf, Len]),
+	(50, &[Fd, Int]),
+	(51, &[Fd, Out, Out]),
+	(52, &[Fd, Out, Out]),
+	(53, &[Int, Int, Int, Out]),
+	(54, &[Fd, Int, Int, Out, Int]),
+	(55, &[Fd, Int, Int, Out, Out]),
+	(59, &[Str]),
+	(62, &[Int, Int]),
+	(63, &[Out]),
+	(79, &[Buf, Len]),
+	(80, &[Str]),
+	(82, &[Str, Str]),
+	(83, &[Str, Int]),
+	(84, &[Str]),
+	(87, &[Str]),
+	(90, &[Str, Int]),
+	(92, &[Str, Int, Int]),
+	(96, &[Out]),
+	(102, &[]),
+	(104, &[]),
+	(105, &[Int]),
+	(106, &[Int]),
+	(115, &[Int, Out]),
+	(109, &[Int, Int]),
+	(121, &[Int]),
+	(164, &[Out]),
+	(201, &[Out]),
+];
+
+/// Numbers no system call has, which should all be refused alike
+const UNKNOWN: [usize; 4] = [4, 200, 999, usize::MAX];
+
+/// Paths the string arguments point at
+const PATHS: [&[u8]; 8] = [
+	b"/\0", b"\0", b"/tmp\0", b"/tmp/fuzz\0", b"fuzz/../../x\0", b"/dev/null\0", b"//tmp///fuzz/\0", b"/\xff\xfe\0",
+];
+
+/// Pointers `syscall_handler` has to refuse: unmapped, running into
+/// the non-canonical hole, in it, and at the very top of memory
+const WILD: [usize; 5] = [0x7000_0000_0000, 0x7fff_ffff_fffc, 0x8000_0000_0000, 0xffff_7fff_ffff_f000, usize::MAX];
+
+/// Lengths that take any pointer into the scratch buffer past the end of
+/// the lower half, or around the end of the address space
+const OVERFLOWING: [usize; 3] = [isize::MAX as usize, usize::MAX - 8, usize::MAX];
+
+/// Integers close to where bounds checks go wrong
+const BOUNDARIES: [usize; 8] = [0, 1, 2, 0x7f, 0xffff, u32::MAX as usize, isize::MAX as usize, usize::MAX];
+
+/// Where buffers and structures passed by pointer live
+static mut SCRATCH: [u8; SCRATCH_SIZE] = [0; SCRATCH_SIZE];
+
+/// The seed and the call being made, for the panic handler to report
+static SEED: AtomicU64 = AtomicU64::new(0);
+static ITERATION: AtomicUsize = AtomicUsize::new(0);
+static CALL: [AtomicUsize; 7] = [const { AtomicUsize::new(0) }; 7];
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+	scottos::init();
+	scottos::allocator::init_heap().expect("heap initialization failed");
+	// Wild pointers are only refused if the page tables can be looked at
+	scottos::memory::init(boot_info);
+	// Descriptors belong to the current process, and paths to the filesystem
+	scottos::process::init();
+	scottos::fs::init_filesystem();
+	test_main();
+	scottos::hlt_loop();
+}
+
+/// Say which call panicked and how to make it again before failing
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+	let call: [usize; 7] = core::array::from_fn(|i| CALL[i].load(Ordering::Relaxed));
+	serial_println!(
+		"\nsyscall_fuzz: iteration {} panicked in syscall {} {:#x?}; rerun with FUZZ_SEED={:#x}",
+		ITERATION.load(Ordering::Relaxed), call[0], &call[1..], SEED.load(Ordering::Relaxed)
+	);
+	scottos::test_panic_handler(info)
+}
+
+/// xorshift64*, so a seed always gives the same calls
+struct Rng(u64);
+
+impl Rng {
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 >> 12;
+		self.0 ^= self.0 << 25;
+		self.0 ^= self.0 >> 27;
+		self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+	}
+
+	/// A number below `bound`
+	fn below(&mut self, bound: usize) -> usize {
+		(self.next() % bound as u64) as usize
+	}
+
+	fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+		items[self.below(items.len())]
+	}
+}
+
+/// The seed from `FUZZ_SEED` when built, or else from the time stamp counter
+fn seed() -> u64 {
+	let seed = option_env!("FUZZ_SEED")
+		.and_then(|seed| u64::from_str_radix(seed.trim_start_matches("0x"), 16).ok())
+		.unwrap_or_else(|| unsafe { core::arch::x86_64::_rdtsc() });
+	// xorshift never leaves zero
+	seed.max(1)
+}
+
+/// A value for an argument of kind `arg`
+fn value(rng: &mut Rng, arg: Arg, scratch: usize) -> usize {
+	match arg {
+		Int => match rng.below(3) {
+			0 => rng.pick(&BOUNDARIES),
+			1 => rng.below(64),
+			_ => rng.next() as usize,
+		},
+		Fd => match rng.below(8) {
+			0 => rng.pick(&BOUNDARIES),
+			_ => rng.below(8),
+		},
+		Str => match rng.below(10) {
+			0 => 0,
+			1 => rng.pick(&WILD),
+			_ => rng.pick(&PATHS).as_ptr() as usize,
+		},
+		Buf => match rng.below(10) {
+			0 => rng.pick(&WILD),
+			_ => scratch + rng.below(ROOM),
+		},
+		Len => match rng.below(8) {
+			0 | 1 => 0,
+			2 => rng.pick(&OVERFLOWING),
+			_ => rng.below(MAX_LEN + 1),
+		},
+		Out => match rng.below(10) {
+			0 => 0,
+			1 => rng.pick(&WILD),
+			_ => scratch + rng.below(ROOM),
+		},
+	}
+}
+
+/// Test that randomized system calls only ever return, with a result or an error
+#[test_case]
+fn fuzz_syscalls() {
+	let seed = seed();
+	SEED.store(seed, Ordering::Relaxed);
+	serial_println!("syscall_fuzz: seed {:#x}, {} calls", seed, ITERATIONS);
+	let mut rng = Rng(seed);
+	let scratch = &raw mut SCRATCH;
+	let (mut ok, mut failed) = (0, 0);
+	for iteration in 0..ITERATIONS {
+		ITERATION.store(iteration, Ordering::Relaxed);
+		// Start from zeroed memory, so a structure holds no stale pointers
+		unsafe { scratch.write_bytes(0, 1) };
+		let (number, kinds) = match rng.below(10) {
+			0 => (rng.pick(&UNKNOWN), &[Int, Int, Int, Int, Int, Int][..]),
+			_ => rng.pick(SYSCALLS),
+		};
+		let mut args = [0usize; 6];
+		for (arg, &kind) in args.iter_mut().zip(kinds) {
+			*arg = value(&mut rng, kind, scratch as usize);
+		}
+		CALL[0].store(number, Ordering::Relaxed);
+		for (slot, &arg) in CALL[1..].iter().zip(&args) {
+			slot.store(arg, Ordering::Relaxed);
+		}
+		match syscall_handler(number, args[0], args[1], args[2], args[3], args[4], args[5]) {
+			Ok(_) => ok += 1,
+			Err(_) => failed += 1,
+		}
+	}
+	serial_println!("syscall_fuzz: {} calls returned, {} with an error", ok, failed);
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(scottos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use scottos::syscall::{syscall_handler, SyscallError};
+
+/// System call numbers, as user programs pass them
+const SYS_READ: usize = 0;
+const SYS_WRITE: usize = 1;
+const SYS_CLOSE: usize = 3;
+const SYS_PIPE: usize = 22;
+const SYS_GETPID: usize = 39;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+	scottos::init();
+	scottos::allocator::init_heap().expect("heap initialization failed");
+	// Pointers passed in are looked up in the page tables before being used
+	scottos::memory::init(boot_info);
+	// Descriptors belong to the current process, so there has to be one
+	scottos::process::init();
+	test_main();
+	scottos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+	scottos::test_panic_handler(info)
+}
+
+/// Make a system call the way the dispatcher receives one
+fn syscall(number: usize, args: [usize; 3]) -> Result<usize, SyscallError> {
+	syscall_handler(number, args[0], args[1], args[2], 0, 0, 0)
+}
+
+/// Test that the init process is the one asking
+#[test_case]
+fn getpid() {
+	assert_eq!(syscall(SYS_GETPID, [0; 3]), Ok(1));
+}
+
+/// Test bytes written to a pipe coming back out of it, and its descriptors closing
+#[test_case]
+fn pipe_round_trip() {
+	let mut fds = [0i32; 2];
+	assert_eq!(syscall(SYS_PIPE, [fds.as_mut_ptr() as usize, 0, 0]), Ok(0));
+	let (read_fd, write_fd) = (fds[0] as usize, fds[1] as usize);
+	assert_ne!(read_fd, write_fd);
+
+	let message = b"round trip";
+	assert_eq!(syscall(SYS_WRITE, [write_fd, message.as_ptr() as usize, message.len()]), Ok(message.len()));
+	let mut buffer = [0u8; 32];
+	let count = syscall(SYS_READ, [read_fd, buffer.as_mut_ptr() as usize, buffer.len()]).unwrap();
+	assert_eq!(&buffer[..count], message);
+
+	// Each end only goes one way
+	assert_eq!(syscall(SYS_READ, [write_fd, buffer.as_mut_ptr() as usize, buffer.len()]), Err(SyscallError::BadFileNumber));
+	assert_eq!(syscall(SYS_CLOSE, [read_fd, 0, 0]), Ok(0));
+	assert_eq!(syscall(SYS_CLOSE, [write_fd, 0, 0]), Ok(0));
+	assert_eq!(syscall(SYS_CLOSE, [write_fd, 0, 0]), Err(SyscallError::BadFileNumber));
+}
+
+/// Test the console descriptors writing, and bad arguments being refused
+#[test_case]
+fn errors() {
+	let message = b"syscalls: console write\n";
+	assert_eq!(syscall(SYS_WRITE, [1, message.as_ptr() as usize, message.len()]), Ok(message.len()));
+	assert_eq!(syscall(SYS_WRITE, [99, message.as_ptr() as usize, message.len()]), Err(SyscallError::BadFileNumber));
+	assert_eq!(syscall(SYS_PIPE, [0; 3]), Err(SyscallError::BadAddress));
+	assert_eq!(syscall(9999, [0; 3]), Err(SyscallError::InvalidArgument));
+}
+
+/// Test pointers that are not canonical, not mapped, or run past the end of
+/// the address space being refused before they are touched
+#[test_case]
+fn bad_addresses() {
+	let message = b"unsent";
+	assert_eq!(syscall(SYS_WRITE, [1, 0x8000_0000_0000, 4]), Err(SyscallError::BadAddress));
+	assert_eq!(syscall(SYS_WRITE, [1, message.as_ptr() as usize, usize::MAX]), Err(SyscallError::BadAddress));
+	assert_eq!(syscall(SYS_READ, [0, 0x7000_0000_0000, 16]), Err(SyscallError::BadAddress));
+	assert_eq!(syscall(SYS_PIPE, [0x7fff_ffff_fffc, 0, 0]), Err(SyscallError::BadAddress));
+}
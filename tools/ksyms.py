@@ -0,0 +1,89 @@
+#!/usr/bin/env python3
+# Write the kernel's symbol map into its .ksyms section, so panics can name
+# the functions in a backtrace
+#
+# Usage: tools/ksyms.py [KERNEL]   (default target/x86_64-scottos/debug/scottos)
+#
+# The map, little-endian, laid out as src/ksyms.rs reads it:
+#   header  "KSYM", entry count u32, offset of the names u32, reserved u32
+#   entries start address u64, name offset u32, name length u32; by address
+#   names   the demangled function names, each stored once
+
+import os
+import struct
+import subprocess
+import sys
+
+SECTION = b".ksyms"
+MAGIC = b"KSYM"
+HEADER_SIZE = 16
+ENTRY_SIZE = 16
+# Longer names are cut short; generic types make some run to hundreds of bytes
+MAX_NAME = 128
+
+
+def find_section(image, name):
+	"""File offset and size of the ELF64 section called `name`"""
+	if image[:4] != b"\x7fELF" or image[4] != 2:
+		sys.exit("ksyms: not an ELF64 file")
+	shoff, = struct.unpack_from("<Q", image, 0x28)
+	shentsize, shnum, shstrndx = struct.unpack_from("<HHH", image, 0x3a)
+	def header(i):
+		return struct.unpack_from("<IIQQQQIIQQ", image, shoff + i * shentsize)
+	strtab = header(shstrndx)[4]
+	for i in range(shnum):
+		fields = header(i)
+		start = strtab + fields[0]
+		if image[start:image.index(b"\0", start)] == name:
+			return fields[4], fields[5]
+	sys.exit("ksyms: no %s section; was the kernel built with src/ksyms.rs?" % name.decode())
+
+
+def functions(kernel):
+	"""(address, name) of every function, demangled, in address order"""
+	nm = os.environ.get("NM", "nm")
+	output = subprocess.run([nm, "-n", "-C", "--defined-only", kernel],
+		check=True, capture_output=True, text=True).stdout
+	symbols = []
+	for line in output.splitlines():
+		fields = line.split(" ", 2)
+		if len(fields) == 3 and fields[1] in "tTwW":
+			address = int(fields[0], 16)
+			# Aliases at one address keep the first name
+			if not symbols or symbols[-1][0] != address:
+				symbols.append((address, fields[2][:MAX_NAME]))
+	return symbols
+
+
+def build(symbols, capacity):
+	"""The map, with as many symbols as fit in `capacity` bytes"""
+	names, offsets, entries = bytearray(), {}, []
+	for address, name in symbols:
+		encoded = name.encode()
+		added = 0 if encoded in offsets else len(encoded)
+		if HEADER_SIZE + (len(entries) + 1) * ENTRY_SIZE + len(names) + added > capacity:
+			print("ksyms: map full, %d of %d symbols left out" % (len(symbols) - len(entries), len(symbols)))
+			break
+		if encoded not in offsets:
+			offsets[encoded] = len(names)
+			names += encoded
+		entries.append(struct.pack("<QII", address, offsets[encoded], len(encoded)))
+	header = MAGIC + struct.pack("<III", len(entries), HEADER_SIZE + len(entries) * ENTRY_SIZE, 0)
+	return header + b"".join(entries) + names
+
+
+def main():
+	kernel = sys.argv[1] if len(sys.argv) > 1 else "target/x86_64-scottos/debug/scottos"
+	with open(kernel, "rb") as f:
+		image = bytearray(f.read())
+	offset, size = find_section(image, SECTION)
+	symbols = functions(kernel)
+	table = build(symbols, size)
+	image[offset:offset + size] = table.ljust(size, b"\0")
+	with open(kernel, "wb") as f:
+		f.write(image)
+	print("ksyms: %d symbols, %d of %d bytes" % (struct.unpack_from("<I", table, 4)[0], len(table), size))
+
+
+if __name__ == "__main__":
+	main()